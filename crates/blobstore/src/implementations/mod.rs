@@ -1,7 +1,7 @@
 mod on_blocks;
 pub use on_blocks::{
     BlobOnBlocks, BlobStoreOnBlocks, DataInnerNode, DataLeafNode, DataNode, DataNodeStore,
-    DataTree, DataTreeStore, LoadNodeError,
+    DataTree, DataTreeStore, HOLE_BLOCK_ID, LoadNodeError,
 };
 mod shared;
 
@@ -68,6 +68,30 @@ impl<B: BlockStore<Block: Send + Sync> + AsyncDrop + Debug + Send + Sync> Blob f
         self._tree_mut().write_bytes(source, offset).await
     }
 
+    async fn punch_hole(&mut self, offset: u64, len: u64) -> Result<()> {
+        self._tree_mut().punch_hole(offset, len).await
+    }
+
+    async fn seek_data(&mut self, offset: u64) -> Result<Option<u64>> {
+        self._tree_mut().seek_data(offset).await
+    }
+
+    async fn seek_hole(&mut self, offset: u64) -> Result<Option<u64>> {
+        self._tree_mut().seek_hole(offset).await
+    }
+
+    async fn clone_range_from(
+        &mut self,
+        source: &mut Self,
+        source_offset: u64,
+        offset: u64,
+        len: u64,
+    ) -> Result<u64> {
+        self.tree
+            .clone_range_from(&mut source.tree, source_offset, offset, len)
+            .await
+    }
+
     async fn flush(&mut self) -> Result<()> {
         self._tree_mut().flush().await
     }
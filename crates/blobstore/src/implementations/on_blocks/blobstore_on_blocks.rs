@@ -79,6 +79,10 @@ impl<B: BlockStore<Block: Send + Sync> + AsyncDrop + Debug + Send + Sync> BlobSt
         self.tree_store.flush_tree_if_cached(blob_id.root).await
     }
 
+    async fn sync(&self) -> Result<()> {
+        self.tree_store.sync().await
+    }
+
     #[cfg(any(test, feature = "testutils"))]
     async fn clear_cache_slow(&self) -> Result<()> {
         self.tree_store.clear_cache_slow().await
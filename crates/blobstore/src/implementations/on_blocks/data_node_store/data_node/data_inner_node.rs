@@ -10,6 +10,11 @@ use cryfs_utils::data::{Data, ZeroedData};
 
 pub(super) const MAX_DEPTH: u8 = 10;
 
+/// Inner nodes can reference this id instead of an actual child node. It stands for a full subtree in which all leaves
+/// are completely zero, i.e. a hole in a sparse blob. Reading from a hole returns zeroes without loading anything,
+/// writing to a hole creates the nodes needed for the write. The nodes on the rightmost path of a tree are never holes.
+pub const HOLE_BLOCK_ID: BlockId = BlockId::zero();
+
 pub struct DataInnerNode<B: BlockStore> {
     block: B::Block,
 }
@@ -85,6 +90,16 @@ impl<B: BlockStore> DataInnerNode<B> {
         blockstore.flush_block(&mut self.block).await
     }
 
+    pub(super) fn shared(&self) -> u8 {
+        let view = node::View::new(self.block.data());
+        view.shared().read()
+    }
+
+    pub(super) fn set_shared(&mut self, value: u8) {
+        let mut view = node::View::new(self.block.data_mut());
+        view.shared_mut().write(value);
+    }
+
     pub fn num_children(&self) -> NonZeroU32 {
         let view = node::View::new(self.block.data().as_ref());
         NonZeroU32::new(view.size().read())
@@ -114,7 +129,6 @@ impl<B: BlockStore> DataInnerNode<B> {
             .map(|id_bytes| BlockId::from_slice(id_bytes).unwrap())
     }
 
-    #[cfg(any(test, feature = "testutils"))]
     pub fn update_child(&mut self, index: usize, new_child: &BlockId) {
         assert!(index < usize::try_from(self.num_children().get()).unwrap());
         self._children_mut_raw()
@@ -149,6 +163,20 @@ impl<B: BlockStore> DataInnerNode<B> {
         Ok(())
     }
 
+    /// Add a child that is a hole, see [HOLE_BLOCK_ID]
+    pub fn add_hole_child(&mut self) -> Result<()> {
+        let view = node::View::new(self.block.data_mut());
+        let prev_num_children = view.size().read();
+        let new_child_entry: &mut [u8] = self
+            ._children_mut_raw()
+            .nth(usize::try_from(prev_num_children).unwrap())
+            .ok_or_else(|| anyhow!("Adding more children than we can store"))?;
+        new_child_entry.copy_from_slice(HOLE_BLOCK_ID.data());
+        let mut view = node::View::new(self.block.data_mut());
+        view.size_mut().write(prev_num_children + 1);
+        Ok(())
+    }
+
     pub fn shrink_num_children(&mut self, new_num_children: NonZeroU32) -> Result<()> {
         let mut view = node::View::new(self.block.data_mut().as_mut());
         let old_num_children = view.size().read();
@@ -208,7 +236,7 @@ where
     let mut view = node::View::new(dest.into_inner());
     view.format_version_header_mut()
         .write(FORMAT_VERSION_HEADER);
-    view.unused_mut().write(0);
+    view.shared_mut().write(0);
     view.depth_mut().write(depth);
     view.size_mut()
         .write(u32::try_from(children.len()).unwrap());
@@ -531,7 +559,7 @@ mod tests {
             let serialized = serialize_inner_node(1, &children, &layout);
             let view = node::View::new(serialized.as_ref());
             assert_eq!(view.format_version_header().read(), FORMAT_VERSION_HEADER);
-            assert_eq!(view.unused().read(), 0);
+            assert_eq!(view.shared().read(), 0);
             assert_eq!(view.depth().read(), 1);
             assert_eq!(view.size().read(), 2);
             assert_eq!(&view.data()[0..BLOCKID_LEN], blockid1.data());
@@ -67,6 +67,16 @@ impl<B: BlockStore> DataLeafNode<B> {
         blockstore.flush_block(&mut self.block).await
     }
 
+    pub(super) fn shared(&self) -> u8 {
+        let view = node::View::new(self.block.data());
+        view.shared().read()
+    }
+
+    pub(super) fn set_shared(&mut self, value: u8) {
+        let mut view = node::View::new(self.block.data_mut());
+        view.shared_mut().write(value);
+    }
+
     pub fn num_bytes(&self) -> u32 {
         let view = node::View::new(self.block.data());
         view.size().read()
@@ -139,7 +149,7 @@ pub fn serialize_leaf_node_optimized(mut data: Data, num_bytes: u32, layout: &No
     let mut view = node::View::new(&mut data);
     view.format_version_header_mut()
         .write(FORMAT_VERSION_HEADER);
-    view.unused_mut().write(0);
+    view.shared_mut().write(0);
     view.depth_mut().write(0);
     view.size_mut().write(num_bytes);
     // view.data is already set correctly because we grew this view from the data input
@@ -395,7 +405,7 @@ mod tests {
             let serialized = serialize_leaf_node_optimized(data.clone(), SIZE as u32, &layout);
             let view = node::View::new(serialized.as_ref());
             assert_eq!(view.format_version_header().read(), FORMAT_VERSION_HEADER);
-            assert_eq!(view.unused().read(), 0);
+            assert_eq!(view.shared().read(), 0);
             assert_eq!(view.depth().read(), 0);
             assert_eq!(view.size().read(), SIZE as u32);
             assert_eq!(data.as_ref(), view.data());
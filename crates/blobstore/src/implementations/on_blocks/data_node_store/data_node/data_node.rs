@@ -53,6 +53,39 @@ impl<B: BlockStore> DataNode<B> {
         }
     }
 
+    fn shared(&self) -> u8 {
+        match self {
+            Self::Leaf(leaf) => leaf.shared(),
+            Self::Inner(inner) => inner.shared(),
+        }
+    }
+
+    fn set_shared(&mut self, value: u8) {
+        match self {
+            Self::Leaf(leaf) => leaf.set_shared(value),
+            Self::Inner(inner) => inner.set_shared(value),
+        }
+    }
+
+    /// Number of parents referencing this node in addition to the first one. Nodes with extra references are shared
+    /// between trees and must not be modified. This is only meaningful for non-root nodes.
+    pub fn num_extra_references(&self) -> u8 {
+        self.shared()
+    }
+
+    pub fn set_num_extra_references(&mut self, num_extra_references: u8) {
+        self.set_shared(num_extra_references);
+    }
+
+    /// Whether the tree may contain nodes that are shared with other trees. This is only meaningful for root nodes.
+    pub fn tree_may_have_shared_nodes(&self) -> bool {
+        self.shared() != 0
+    }
+
+    pub fn set_tree_may_have_shared_nodes(&mut self) {
+        self.set_shared(1);
+    }
+
     // TODO No pub(crate) but rather pub(super::super)?
     pub(crate) fn raw_blockdata(&self) -> &Data {
         match self {
@@ -80,6 +113,8 @@ impl<B: BlockStore> DataNode<B> {
         first_child: DataNode<B>,
         layout: &NodeLayout,
     ) -> DataInnerNode<B> {
+        // This is only used to grow the root of a tree, so it keeps its sharing information
+        let shared = self.shared();
         let mut block = self._into_block();
         let block_data: ZeroedData<&mut Data> = ZeroedData::fill_with_zeroes(block.data_mut());
         data_inner_node::initialize_inner_node(
@@ -88,8 +123,10 @@ impl<B: BlockStore> DataNode<B> {
             layout,
             block_data,
         );
-        DataInnerNode::new(block, layout)
-            .expect("Newly created inner node shouldn't violate any invariants")
+        let mut node = DataInnerNode::new(block, layout)
+            .expect("Newly created inner node shouldn't violate any invariants");
+        node.set_shared(shared);
+        node
     }
 
     pub fn overwrite_node_with(
@@ -97,6 +134,8 @@ impl<B: BlockStore> DataNode<B> {
         source: &DataNode<B>,
         layout: &NodeLayout,
     ) -> Result<DataNode<B>> {
+        // This is only used to shrink the root of a tree, so it keeps its sharing information
+        let shared = self.shared();
         let mut block = self._into_block();
         let dest_data = block.data_mut();
         let source_data = source.raw_blockdata();
@@ -116,7 +155,9 @@ impl<B: BlockStore> DataNode<B> {
         );
         dest_data.copy_from_slice(source_data);
         // TODO DataNode::parse() is checking invariants again but we don't need to do that - violating invariants wouldn't have been able to create the source object.
-        DataNode::parse(block, layout)
+        let mut node = DataNode::parse(block, layout)?;
+        node.set_shared(shared);
+        Ok(node)
     }
 
     pub fn into_inner_node(self) -> Option<DataInnerNode<B>> {
@@ -1,5 +1,5 @@
 mod data_inner_node;
-pub use data_inner_node::{DataInnerNode, serialize_inner_node};
+pub use data_inner_node::{DataInnerNode, HOLE_BLOCK_ID, serialize_inner_node};
 
 mod data_leaf_node;
 pub use data_leaf_node::{DataLeafNode, serialize_leaf_node_optimized};
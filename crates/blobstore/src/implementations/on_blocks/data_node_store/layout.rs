@@ -10,8 +10,11 @@ pub const FORMAT_VERSION_HEADER: u16 = 0;
 binary_layout!(node, LittleEndian, {
     format_version_header: u16,
 
-    // Not currently used, only used for alignment.
-    unused: u8,
+    // Copy-on-write sharing of subtrees between trees, see `DataTree::clone_range_from`.
+    // Non-root nodes store how many parents reference them in addition to the first one, i.e. 0 if they aren't shared.
+    // Root nodes can't be shared. They store 1 here if their tree may contain shared nodes and 0 otherwise.
+    // Nodes written before sharing existed always have 0 here, which is correct for them.
+    shared: u8,
 
     // Leaf nodes have a depth of 0. Each layer above has a depth of one higher than the level directly below.
     depth: u8,
@@ -19,7 +19,7 @@ pub use layout::NodeLayout;
 use layout::node;
 
 mod data_node;
-pub use data_node::{DataInnerNode, DataLeafNode, DataNode};
+pub use data_node::{DataInnerNode, DataLeafNode, DataNode, HOLE_BLOCK_ID};
 
 #[cfg(test)]
 mod testutils;
@@ -172,8 +172,11 @@ impl<B: BlockStore + AsyncDrop + Debug + Send> DataNodeStore<B> {
             source_data.len(),
             self.layout.block_size
         );
+        // The copy is a new node that isn't referenced from anywhere yet, so it isn't shared even if the source is
+        let mut block_data = source_data.clone();
+        node::View::new(block_data.as_mut()).shared_mut().write(0);
         // TODO Use create_optimized instead of create?
-        let blockid = self.block_store.create(source_data).await?;
+        let blockid = self.block_store.create(&block_data).await?;
         // TODO Avoid extra load here. Do our callers actually need this object? If no, just return the block id. If yes, maybe change block store API to return the block?
         self.load(blockid)
             .await?
@@ -225,6 +228,14 @@ impl<B: BlockStore + AsyncDrop + Debug + Send> DataNodeStore<B> {
         node.flush(&self.block_store).await
     }
 
+    pub async fn sync(&self) -> Result<()> {
+        self.block_store.sync().await
+    }
+
+    pub fn into_inner_block_store(this: AsyncDropGuard<Self>) -> AsyncDropGuard<B> {
+        this.unsafe_into_inner_dont_drop().block_store
+    }
+
     #[cfg(any(test, feature = "testutils"))]
     pub async fn clear_cache_slow(&self) -> Result<()> {
         self.block_store.clear_cache_slow().await
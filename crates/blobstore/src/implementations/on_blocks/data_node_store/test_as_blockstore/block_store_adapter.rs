@@ -125,7 +125,12 @@ impl AsyncDrop for BlockStoreAdapter {
     }
 }
 
-impl LLBlockStore for BlockStoreAdapter {}
+#[async_trait]
+impl LLBlockStore for BlockStoreAdapter {
+    async fn sync(&self) -> Result<()> {
+        self.0.sync().await
+    }
+}
 
 /// TestFixtureAdapter takes a [Fixture] for a [BlockStore] and makes it into
 /// a [Fixture] that creates a [DataNodeStore] based on that [BlockStore].
@@ -11,12 +11,6 @@ use cryfs_utils::async_drop::AsyncDrop;
 #[derive(Clone, Copy)]
 pub enum SizeCache {
     SizeUnknown,
-    RootIsInnerNodeAndNumLeavesIsKnown {
-        num_leaves: NonZeroU64,
-        // It's important to remember whether root is an inner node because if it was a leaf, then it would be the rightmost_leaf_id, and trying
-        // to load it to calculate the size would cause a deadlock.
-        rightmost_leaf_id: BlockId,
-    },
     NumBytesIsKnown {
         num_leaves: NonZeroU64,
         rightmost_leaf_num_bytes: u32,
@@ -24,36 +18,6 @@ pub enum SizeCache {
 }
 
 impl SizeCache {
-    pub async fn get_or_calculate_num_leaves<B: BlockStore + AsyncDrop + Debug + Send>(
-        &mut self,
-        node_store: &DataNodeStore<B>,
-        root_node: &DataNode<B>,
-    ) -> Result<NonZeroU64> {
-        match (*self, root_node) {
-            (Self::SizeUnknown, DataNode::Inner(root_node)) => {
-                let NumLeavesAndRightmostLeafId {
-                    num_leaves,
-                    rightmost_leaf_id,
-                } = calculate_num_leaves_and_rightmost_leaf_id(node_store, root_node).await?;
-                *self = SizeCache::RootIsInnerNodeAndNumLeavesIsKnown {
-                    num_leaves,
-                    rightmost_leaf_id,
-                };
-                Ok(num_leaves)
-            }
-            (Self::SizeUnknown, DataNode::Leaf(root_node)) => {
-                let num_leaves = NonZeroU64::new(1).unwrap();
-                *self = SizeCache::NumBytesIsKnown {
-                    num_leaves,
-                    rightmost_leaf_num_bytes: root_node.num_bytes(),
-                };
-                Ok(num_leaves)
-            }
-            (Self::RootIsInnerNodeAndNumLeavesIsKnown { num_leaves, .. }, _) => Ok(num_leaves),
-            (Self::NumBytesIsKnown { num_leaves, .. }, _) => Ok(num_leaves),
-        }
-    }
-
     pub async fn get_or_calculate_num_bytes<B: BlockStore + AsyncDrop + Debug + Send + Sync>(
         &mut self,
         node_store: &DataNodeStore<B>,
@@ -99,21 +63,6 @@ impl SizeCache {
                 };
                 calculate_num_bytes(num_leaves, rightmost_leaf_num_bytes)
             }
-            (
-                Self::RootIsInnerNodeAndNumLeavesIsKnown {
-                    num_leaves,
-                    rightmost_leaf_id,
-                },
-                _,
-            ) => {
-                let rightmost_leaf_num_bytes =
-                    Self::_calculate_leaf_size(node_store, rightmost_leaf_id).await?;
-                *self = Self::NumBytesIsKnown {
-                    num_leaves,
-                    rightmost_leaf_num_bytes,
-                };
-                calculate_num_bytes(num_leaves, rightmost_leaf_num_bytes)
-            }
             (
                 Self::NumBytesIsKnown {
                     num_leaves,
@@ -129,6 +129,10 @@ impl<B: BlockStore<Block: Send + Sync> + AsyncDrop + Debug + Send + Sync> DataTr
         Ok(())
     }
 
+    pub async fn sync(&self) -> Result<()> {
+        self.node_store.sync().await
+    }
+
     #[cfg(test)]
     // This needs to load all blocks, so it's not very efficient. Only use it for tests.
     pub async fn all_tree_roots(&self) -> Result<Vec<BlockId>> {
@@ -113,7 +113,9 @@ pub async fn create_multi_leaf_tree<
     num_leaves: u64,
 ) -> AsyncDropGuard<DataTree<B>> {
     let mut tree = store.create_tree().await.unwrap();
-    tree.resize_num_bytes(num_leaves * store.logical_block_size_bytes().as_u64())
+    // Write data instead of resizing, because resizing would only create a sparse tree
+    let num_bytes = num_leaves * store.logical_block_size_bytes().as_u64();
+    tree.write_bytes(data(num_bytes as usize, 0).as_ref(), 0)
         .await
         .unwrap();
     tree
@@ -272,6 +274,41 @@ pub const fn expected_num_nodes_for_num_leaves(num_leaves: u64, layout: NodeLayo
     num_nodes
 }
 
+/// Number of nodes in a tree with `old_num_leaves` leaves after writing to the leaves `begin_leaf..end_leaf`.
+/// Writing beyond the end of the tree doesn't create the leaves between the old end and `begin_leaf`,
+/// those become holes. Only the nodes on the paths to the written leaves are created.
+pub const fn expected_num_nodes_after_writing_leaves(
+    old_num_leaves: u64,
+    begin_leaf: u64,
+    end_leaf: u64,
+    layout: NodeLayout,
+) -> u64 {
+    assert!(old_num_leaves > 0);
+    assert!(begin_leaf < end_leaf);
+    let max_children = layout.max_children_per_inner_node() as u64;
+    let mut num_nodes = 0;
+    let mut existing_num_nodes_current_level = old_num_leaves;
+    let mut begin_current_level = begin_leaf;
+    let mut end_current_level = end_leaf;
+    while existing_num_nodes_current_level > 1 || end_current_level > 1 {
+        num_nodes += existing_num_nodes_current_level;
+        if end_current_level > existing_num_nodes_current_level {
+            let first_new_node = if begin_current_level > existing_num_nodes_current_level {
+                begin_current_level
+            } else {
+                existing_num_nodes_current_level
+            };
+            num_nodes += end_current_level - first_new_node;
+        }
+        existing_num_nodes_current_level = div_ceil(existing_num_nodes_current_level, max_children);
+        begin_current_level /= max_children;
+        end_current_level = div_ceil(end_current_level, max_children);
+    }
+    // root node
+    num_nodes += 1;
+    num_nodes
+}
+
 pub const fn expected_depth_for_num_leaves(num_leaves: u64, layout: NodeLayout) -> u8 {
     assert!(num_leaves > 0);
     let mut depth = 0;
@@ -10,7 +10,7 @@ use std::fmt::Debug;
 use thiserror::Error;
 
 use crate::implementations::on_blocks::data_node_store::{
-    DataInnerNode, DataLeafNode, DataNode, DataNodeStore, NodeLayout,
+    DataInnerNode, DataLeafNode, DataNode, DataNodeStore, HOLE_BLOCK_ID, NodeLayout,
 };
 use cryfs_blockstore::{BlockId, BlockStore};
 use cryfs_utils::{async_drop::AsyncDrop, data::Data};
@@ -36,6 +36,9 @@ pub enum LeafHandle<'a, B: BlockStore + AsyncDrop + Debug + Send> {
         store: &'a DataNodeStore<B>,
         leaf_block_id: BlockId,
     },
+    /// The leaf is in a hole, see [HOLE_BLOCK_ID]. It doesn't exist and its data is all zeroes.
+    /// Only read-only traversals see holes, traversals that allow writes create the leaf instead.
+    Hole,
 }
 
 impl<'a, B: BlockStore + AsyncDrop + Debug + Send> LeafHandle<'a, B> {
@@ -50,8 +53,13 @@ impl<'a, B: BlockStore + AsyncDrop + Debug + Send> LeafHandle<'a, B> {
         Self::Borrowed { leaf }
     }
 
+    pub fn is_hole(&self) -> bool {
+        matches!(self, Self::Hole)
+    }
+
     pub async fn node(&mut self) -> Result<&mut DataLeafNode<B>> {
         match self {
+            Self::Hole => bail!("Tried to load a leaf in a hole"),
             Self::Borrowed { leaf } => Ok(leaf),
             Self::Owned { leaf } => Ok(leaf),
             Self::NotLoadedYet {
@@ -95,6 +103,7 @@ impl<'a, B: BlockStore + AsyncDrop + Debug + Send> LeafHandle<'a, B> {
                     .overwrite_with_leaf_node(leaf_block_id, source)
                     .await?;
             }
+            Self::Hole => bail!("Tried to write to a leaf in a hole"),
         }
         Ok(())
     }
@@ -269,6 +278,22 @@ async fn _traverse_existing_subtree<
     grow_last_leaf: bool,
     callbacks: &C,
 ) -> Result<()> {
+    if block_id == HOLE_BLOCK_ID {
+        assert!(
+            !ALLOW_WRITES,
+            "Traversals that allow writes should have created the nodes for this hole before descending into it"
+        );
+        ensure!(
+            !is_right_border_node && !grow_last_leaf,
+            "Found a hole on the rightmost path of the tree"
+        );
+        for leaf_index in begin_index..end_index {
+            callbacks
+                .on_existing_leaf(leaf_offset + leaf_index, false, LeafHandle::Hole)
+                .await?;
+        }
+        return Ok(());
+    }
     if depth == 0 {
         assert!(
             begin_index <= 1 && end_index <= 1,
@@ -355,7 +380,7 @@ async fn _traverse_existing_subtree_of_inner_node<
         end_child <= usize::try_from(node_store.layout().max_children_per_inner_node()).unwrap(),
         "Traversal region would need increasing the tree depth. This should have happened before calling this function."
     );
-    let children = root.children();
+    let children: Vec<BlockId> = root.children().collect();
     let num_children = children.len();
     assert!(
         !grow_last_leaf || end_child >= num_children,
@@ -420,10 +445,17 @@ async fn _traverse_existing_subtree_of_inner_node<
 
     // Traverse existing children
     let existing_children = children
+        .into_iter()
         .enumerate()
         .skip(begin_child)
         .take(end_child - begin_child);
-    for (child_index, child_block_id) in existing_children {
+    for (child_index, mut child_block_id) in existing_children {
+        if ALLOW_WRITES && child_block_id == HOLE_BLOCK_ID {
+            // Create the child so we can write to it. This only creates one level, holes further down stay holes
+            // unless the traversal descends into them.
+            child_block_id = create_node_for_hole(node_store, root.depth().get() - 1).await?;
+            root.update_child(child_index, &child_block_id);
+        }
         let child_offset = u64::try_from(child_index)
             .unwrap()
             .checked_mul(leaves_per_child)
@@ -477,6 +509,11 @@ async fn _traverse_existing_subtree_of_inner_node<
             ALLOW_WRITES,
             "Can't create new children in a read-only traversal"
         );
+        if child_index < begin_child {
+            // Gap children don't need any nodes, they're holes
+            root.add_hole_child()?;
+            continue;
+        }
         let child_offset = u64::try_from(child_index)
             .unwrap()
             .checked_mul(leaves_per_child)
@@ -497,9 +534,6 @@ async fn _traverse_existing_subtree_of_inner_node<
                 )
             })?);
         struct Callbacks<'a, C> {
-            child_index: usize,
-            begin_child: usize,
-            layout: NodeLayout,
             callbacks: &'a C,
         }
         #[async_trait]
@@ -510,11 +544,7 @@ async fn _traverse_existing_subtree_of_inner_node<
         > CreateNewSubtreeCallbacks<B> for Callbacks<'a, C>
         {
             fn on_create_leaf(&self, index: u64) -> Data {
-                if self.child_index >= self.begin_child {
-                    self.callbacks.on_create_leaf(index)
-                } else {
-                    _create_max_size_leaf(&self.layout)
-                }
+                self.callbacks.on_create_leaf(index)
             }
             async fn on_backtrack_from_subtree(&self, node: &mut DataInnerNode<B>) -> Result<()> {
                 self.callbacks.on_backtrack_from_subtree(node).await
@@ -532,12 +562,7 @@ async fn _traverse_existing_subtree_of_inner_node<
                 )
             })?,
             root.depth().get() - 1,
-            &Callbacks {
-                child_index,
-                begin_child,
-                layout: *node_store.layout(),
-                callbacks,
-            },
+            &Callbacks { callbacks },
         )
         .await?;
         root.add_child(&child)?;
@@ -556,6 +581,26 @@ fn _create_max_size_leaf(layout: &NodeLayout) -> Data {
     Data::from(vec![0; max_bytes_per_leaf])
 }
 
+/// Create a node that can replace a hole of the given depth. Since holes are never on the rightmost path of a tree,
+/// they always stand for a full subtree, so this is either a max size leaf or an inner node with the maximal number of
+/// children, all of them holes.
+pub(super) async fn create_node_for_hole<B: BlockStore + AsyncDrop + Debug + Send>(
+    node_store: &DataNodeStore<B>,
+    depth: u8,
+) -> Result<BlockId> {
+    if depth == 0 {
+        let leaf = node_store
+            .create_new_leaf_node(&_create_max_size_leaf(node_store.layout()))
+            .await?;
+        Ok(*leaf.block_id())
+    } else {
+        let max_children = node_store.layout().max_children_per_inner_node();
+        let children = vec![HOLE_BLOCK_ID; usize::try_from(max_children).unwrap()];
+        let node = node_store.create_new_inner_node(depth, &children).await?;
+        Ok(*node.block_id())
+    }
+}
+
 async fn _increase_tree_depth<B: BlockStore + AsyncDrop + Debug + Send>(
     node_store: &DataNodeStore<B>,
     root: DataNode<B>,
@@ -595,14 +640,10 @@ async fn _create_new_subtree<
 
     if 0 == depth {
         assert!(
-            begin_index <= 1 && end_index == 1,
-            "With depth 0, we can only traverse one or zero leaves (i.e. traverse one leaf or traverse a gap leaf)."
+            begin_index == 0 && end_index == 1,
+            "With depth 0, we can only traverse one leaf. Gap leaves are holes and shouldn't be created."
         );
-        let leaf_data = if begin_index == 0 {
-            callbacks.on_create_leaf(leaf_offset)
-        } else {
-            _create_max_size_leaf(node_store.layout())
-        };
+        let leaf_data = callbacks.on_create_leaf(leaf_offset);
         let leaf = node_store.create_new_leaf_node(&leaf_data).await?;
         Ok(DataNode::Leaf(leaf))
     } else {
@@ -630,45 +671,8 @@ async fn _create_new_subtree<
         let end_child = DivCeil::div_ceil(end_index, leaves_per_child);
 
         let mut children = Vec::with_capacity(usize::try_from(end_child).unwrap());
-        // TODO Remove redundancy of following two for loops by using min/max for calculating the parameters of the recursive call.
-        // Create gap children (i.e. children before the traversal but after the current size)
-        for child_index in 0..begin_child {
-            let child_offset = child_index.checked_mul(leaves_per_child).ok_or_else(|| {
-                anyhow!(
-                    "Overflow in child_index*leaves_per_child: {}*{}",
-                    child_index,
-                    leaves_per_child
-                )
-            })?;
-            struct Callbacks;
-            #[async_trait]
-            impl<B: BlockStore + AsyncDrop + Debug + Send + Sync> CreateNewSubtreeCallbacks<B> for Callbacks {
-                fn on_create_leaf(&self, _index: u64) -> Data {
-                    panic!("We're only creating gap leaves here, not traversing any");
-                }
-                async fn on_backtrack_from_subtree(
-                    &self,
-                    _node: &mut DataInnerNode<B>,
-                ) -> Result<()> {
-                    Ok(())
-                }
-            }
-            let child = Box::pin(_create_new_subtree(
-                node_store,
-                leaves_per_child,
-                leaves_per_child,
-                leaf_offset + child_offset,
-                depth - 1,
-                &Callbacks,
-            ))
-            .await?;
-            assert_eq!(
-                child.depth(),
-                depth - 1,
-                "Created child node has wrong depth"
-            );
-            children.push(*child.block_id());
-        }
+        // Gap children (i.e. children before the traversal but after the current size) are holes
+        children.extend((0..begin_child).map(|_| HOLE_BLOCK_ID));
         // Create new children that are traversed
         for child_index in begin_child..end_child {
             let child_offset = child_index * leaves_per_child;
@@ -858,11 +862,14 @@ where
     B: BlockStore<Block: Send> + AsyncDrop + Debug + Send + Sync,
 {
     // iter<stream<result<block_id>>>
-    let subtree_stream = subtree_root.children().map(|child_id| {
-        let child_stream = load_all_nodes_in_subtree_of_id(node_store, child_id);
-        // Transform Future<Stream<Result<BlockId>>> into Stream<Result<BlockId>>
-        child_stream.flatten_stream().boxed()
-    });
+    let subtree_stream = subtree_root
+        .children()
+        .filter(|child_id| *child_id != HOLE_BLOCK_ID)
+        .map(|child_id| {
+            let child_stream = load_all_nodes_in_subtree_of_id(node_store, child_id);
+            // Transform Future<Stream<Result<BlockId>>> into Stream<Result<BlockId>>
+            child_stream.flatten_stream().boxed()
+        });
 
     stream::select_all(subtree_stream).boxed()
 }
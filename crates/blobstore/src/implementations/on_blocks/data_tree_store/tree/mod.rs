@@ -16,7 +16,7 @@ use super::traversal::{self, LeafHandle};
 use crate::{
     RemoveResult,
     implementations::on_blocks::data_node_store::{
-        DataInnerNode, DataNode, DataNodeStore, NodeLayout,
+        DataInnerNode, DataNode, DataNodeStore, HOLE_BLOCK_ID, NodeLayout,
     },
 };
 use cryfs_blockstore::{BlockId, BlockStore};
@@ -29,6 +29,9 @@ use cryfs_utils::{
 // TODO Exception safety. If an operation fails, it shouldn't do any modifications (or undo any that it did do).
 //      Also look at traversal.rs for this.
 
+/// If data can't be shared in [DataTree::clone_range_from], it is copied in chunks of at most this size.
+const MAX_BYTES_PER_COPY_CHUNK: u64 = 1024 * 1024;
+
 pub struct DataTree<B: BlockStore<Block: Send + Sync> + AsyncDrop + Debug + Send + Sync> {
     // The lock on the root node also ensures that there never are two [DataTree] instances for the same tree
     // &mut self in all the methods makes sure we don't run into race conditions where
@@ -66,29 +69,60 @@ impl<B: BlockStore<Block: Send + Sync> + AsyncDrop + Debug + Send + Sync> DataTr
             .await
     }
 
+    /// Number of nodes actually stored for this tree. Holes don't count, so we can't calculate this from the number
+    /// of leaves and have to go through the inner nodes instead. Leaves don't need to be loaded for this.
     pub async fn num_nodes(&mut self) -> Result<u64> {
-        let root_node = self.root_node.as_ref().expect("DataTree.root_node is None");
-        let mut num_nodes_current_level = self
-            .num_bytes_cache
-            .get_or_calculate_num_leaves(&*self.node_store, root_node)
-            .await?
-            .get();
-        let mut total_num_nodes = num_nodes_current_level;
-        for _level in 0..root_node.depth() {
-            num_nodes_current_level = DivCeil::div_ceil(
-                num_nodes_current_level,
-                u64::from(self.node_store.layout().max_children_per_inner_node()),
-            );
-            total_num_nodes += num_nodes_current_level;
+        match self.root_node.as_ref().expect("DataTree.root_node is None") {
+            DataNode::Leaf(_) => Ok(1),
+            DataNode::Inner(root) => {
+                Self::_num_nodes_in_subtree_of_inner_node(&self.node_store, root).await
+            }
+        }
+    }
+
+    async fn _num_nodes_in_subtree_of_inner_node(
+        node_store: &DataNodeStore<B>,
+        root: &DataInnerNode<B>,
+    ) -> Result<u64> {
+        let children = root
+            .children()
+            .filter(|child_id| *child_id != HOLE_BLOCK_ID);
+        let depth = root.depth().get();
+        if depth == 1 {
+            return Ok(1 + u64::try_from(children.count()).unwrap());
+        }
+        let mut num_nodes = 1;
+        for child_id in children {
+            match node_store.load(child_id).await? {
+                None => bail!("Couldn't find child node {:?}", child_id),
+                Some(DataNode::Leaf(_)) => bail!(
+                    "Loaded a leaf node {:?} but expected an inner node at depth {}",
+                    child_id,
+                    depth - 1
+                ),
+                Some(DataNode::Inner(child)) => {
+                    num_nodes += Box::pin(Self::_num_nodes_in_subtree_of_inner_node(
+                        node_store, &child,
+                    ))
+                    .await?;
+                }
+            }
         }
-        Ok(total_num_nodes)
+        Ok(num_nodes)
     }
 
     pub fn root_node_id(&self) -> &BlockId {
-        self.root_node
-            .as_ref()
-            .expect("DataTree.root_node is None")
-            .block_id()
+        self._root_node().block_id()
+    }
+
+    fn _root_node(&self) -> &DataNode<B> {
+        self.root_node.as_ref().expect("DataTree.root_node is None")
+    }
+
+    async fn _num_leaves(&mut self) -> Result<u64> {
+        let num_bytes = self.num_bytes().await?;
+        let max_bytes_per_leaf = u64::from(self.node_store.layout().max_bytes_per_leaf());
+        Ok(DivCeil::div_ceil(num_bytes, max_bytes_per_leaf).max(1))
     }
 
     // TODO Can we make read_bytes and try_read_bytes take &self instead of &mut self?
@@ -141,7 +175,11 @@ impl<B: BlockStore<Block: Send + Sync> + AsyncDrop + Debug + Send + Sync> DataTr
                 leaf_data_offset: u32,
                 leaf_data_size: u32,
             ) -> Result<()> {
-                let leaf = leaf.node().await?;
+                let leaf = if leaf.is_hole() {
+                    None
+                } else {
+                    Some(leaf.node().await?)
+                };
                 let mut target = self.target.lock().unwrap();
                 assert!(
                     index_of_first_leaf_byte + u64::from(leaf_data_offset) >= self.offset
@@ -165,9 +203,14 @@ impl<B: BlockStore<Block: Send + Sync> + AsyncDrop + Debug + Send + Sync> DataTr
                 let target_end = target_begin + u64::from(leaf_data_size);
                 let actual_target = &mut target
                     [usize::try_from(target_begin).unwrap()..usize::try_from(target_end).unwrap()];
-                let actual_source = &leaf.data()[usize::try_from(leaf_data_offset).unwrap()
-                    ..usize::try_from(leaf_data_offset + leaf_data_size).unwrap()];
-                actual_target.copy_from_slice(actual_source);
+                if let Some(leaf) = leaf {
+                    let actual_source = &leaf.data()[usize::try_from(leaf_data_offset).unwrap()
+                        ..usize::try_from(leaf_data_offset + leaf_data_size).unwrap()];
+                    actual_target.copy_from_slice(actual_source);
+                } else {
+                    // Holes are all zeroes and don't need any I/O
+                    actual_target.fill(0);
+                }
                 Ok(())
             }
             fn on_create_leaf(&self, _begin_byte: u64, _num_bytes: u32) -> Data {
@@ -256,6 +299,17 @@ impl<B: BlockStore<Block: Send + Sync> + AsyncDrop + Debug + Send + Sync> DataTr
             }
         }
 
+        if source.is_empty() {
+            return Ok(());
+        }
+        let max_bytes_per_leaf = u64::from(self.node_store.layout().max_bytes_per_leaf());
+        let end_byte = offset + u64::try_from(source.len()).unwrap();
+        self._unshare_nodes_for_writing_traversal(
+            offset / max_bytes_per_leaf,
+            DivCeil::div_ceil(end_byte, max_bytes_per_leaf),
+        )
+        .await?;
+
         self._traverse_leaves_by_byte_indices::<Callbacks, true>(
             offset,
             u64::try_from(source.len()).unwrap(),
@@ -278,6 +332,7 @@ impl<B: BlockStore<Block: Send + Sync> + AsyncDrop + Debug + Send + Sync> DataTr
     pub async fn resize_num_bytes(&mut self, new_num_bytes: u64) -> Result<()> {
         struct Callbacks<'a, B: BlockStore + AsyncDrop + Debug + Send + Sync> {
             node_store: &'a DataNodeStore<B>,
+            may_have_shared_nodes: bool,
             new_num_leaves: NonZeroU64,
             new_last_leaf_size: u32,
         }
@@ -346,8 +401,13 @@ impl<B: BlockStore<Block: Send + Sync> + AsyncDrop + Debug + Send + Sync> DataTr
                     NonZeroU32::new(needed_children_for_right_border_node).unwrap(),
                 )?;
                 for_each_unordered(children_to_delete.into_iter(), async |block_id| {
-                    DataTree::_remove_subtree_by_root_id(self.node_store, depth.get() - 1, block_id)
-                        .await
+                    DataTree::_remove_subtree_by_root_id(
+                        self.node_store,
+                        depth.get() - 1,
+                        block_id,
+                        self.may_have_shared_nodes,
+                    )
+                    .await
                 })
                 .await?;
 
@@ -361,6 +421,10 @@ impl<B: BlockStore<Block: Send + Sync> + AsyncDrop + Debug + Send + Sync> DataTr
         let new_last_leaf_size =
             u32::try_from(new_num_bytes - (new_num_leaves.get() - 1) * max_bytes_per_leaf).unwrap();
 
+        self._unshare_nodes_for_writing_traversal(new_num_leaves.get() - 1, new_num_leaves.get())
+            .await?;
+        let may_have_shared_nodes = self._root_node().tree_may_have_shared_nodes();
+
         let root_node = self.root_node.take().expect("DataTree.root_node is None");
         let new_root = self
             ._traverse_leaves_by_leaf_indices_return_new_root::<Callbacks<'_, B>, true>(
@@ -369,6 +433,7 @@ impl<B: BlockStore<Block: Send + Sync> + AsyncDrop + Debug + Send + Sync> DataTr
                 new_num_leaves.get(),
                 &Callbacks {
                     node_store: &*self.node_store,
+                    may_have_shared_nodes,
                     new_last_leaf_size,
                     new_num_leaves,
                 },
@@ -389,6 +454,702 @@ impl<B: BlockStore<Block: Send + Sync> + AsyncDrop + Debug + Send + Sync> DataTr
         Ok(())
     }
 
+    /// Deallocate the leaves fully covered by `[offset, offset + len)` by replacing them with holes, and zero out
+    /// the parts of partially covered leaves. The size of the tree doesn't change and the range reads as zeroes afterwards.
+    pub async fn punch_hole(&mut self, offset: u64, len: u64) -> Result<()> {
+        let num_bytes = self.num_bytes().await?;
+        let end = offset.saturating_add(len).min(num_bytes);
+        if offset >= end {
+            return Ok(());
+        }
+        let max_bytes_per_leaf = u64::from(self.node_store.layout().max_bytes_per_leaf());
+        let num_leaves = DivCeil::div_ceil(num_bytes, max_bytes_per_leaf).max(1);
+        let begin_full_leaf = DivCeil::div_ceil(offset, max_bytes_per_leaf);
+        // The last leaf is never a hole, so if the range covers it, we zero it out instead
+        let end_full_leaf = (end / max_bytes_per_leaf).min(num_leaves - 1);
+        if begin_full_leaf >= end_full_leaf {
+            self._unshare_leaves(
+                offset / max_bytes_per_leaf,
+                DivCeil::div_ceil(end, max_bytes_per_leaf),
+            )
+            .await?;
+            return self._zero_out_existing_leaves(offset, end - offset).await;
+        }
+
+        // The leaves that are only partially covered get zeroed out below. Shared nodes on the paths to the fully covered
+        // leaves are unshared while punching the holes.
+        self._unshare_leaves(offset / max_bytes_per_leaf, begin_full_leaf)
+            .await?;
+        self._unshare_leaves(end_full_leaf, DivCeil::div_ceil(end, max_bytes_per_leaf))
+            .await?;
+        let may_have_shared_nodes = self._root_node().tree_may_have_shared_nodes();
+        match self.root_node.as_mut().expect("DataTree.root_node is None") {
+            DataNode::Leaf(_) => {
+                unreachable!("A tree with a leaf as root node only has one leaf, the last one")
+            }
+            DataNode::Inner(root) => {
+                let root_is_all_holes = Self::_punch_holes_in_subtree_of_inner_node(
+                    &self.node_store,
+                    root,
+                    0,
+                    begin_full_leaf,
+                    end_full_leaf,
+                    may_have_shared_nodes,
+                )
+                .await?;
+                assert!(
+                    !root_is_all_holes,
+                    "The last leaf is never a hole, so the root can't be all holes"
+                );
+            }
+        }
+
+        let begin_full_leaf_byte = begin_full_leaf * max_bytes_per_leaf;
+        let end_full_leaf_byte = end_full_leaf * max_bytes_per_leaf;
+        self._zero_out_existing_leaves(offset, begin_full_leaf_byte - offset)
+            .await?;
+        self._zero_out_existing_leaves(end_full_leaf_byte, end - end_full_leaf_byte)
+            .await?;
+        Ok(())
+    }
+
+    /// Replaces the children of `node` that are fully within `[begin_leaf, end_leaf)` with holes and removes their subtrees.
+    /// Returns true if all children of `node` are holes afterwards, i.e. the caller can replace `node` itself with a hole.
+    async fn _punch_holes_in_subtree_of_inner_node(
+        node_store: &DataNodeStore<B>,
+        node: &mut DataInnerNode<B>,
+        first_leaf_index_of_node: u64,
+        begin_leaf: u64,
+        end_leaf: u64,
+        may_have_shared_nodes: bool,
+    ) -> Result<bool> {
+        let child_depth = node.depth().get() - 1;
+        let leaves_per_child = node_store
+            .layout()
+            .num_leaves_per_full_subtree(child_depth)?
+            .get();
+        let children: Vec<BlockId> = node.children().collect();
+        let mut subtrees_to_remove = Vec::new();
+        for (index, child_id) in children.into_iter().enumerate() {
+            if child_id == HOLE_BLOCK_ID {
+                continue;
+            }
+            let child_begin_leaf = first_leaf_index_of_node + index as u64 * leaves_per_child;
+            let child_end_leaf = child_begin_leaf + leaves_per_child;
+            if child_end_leaf <= begin_leaf || end_leaf <= child_begin_leaf {
+                continue;
+            }
+            let child_is_all_holes = if begin_leaf <= child_begin_leaf && child_end_leaf <= end_leaf
+            {
+                true
+            } else {
+                assert!(
+                    child_depth > 0,
+                    "A leaf is either fully inside or fully outside of the range"
+                );
+                match Self::_load_child_for_writing(node_store, node, index).await? {
+                    DataNode::Leaf(child) => bail!(
+                        "Loaded a leaf node {:?} but expected an inner node at depth {}",
+                        child.block_id(),
+                        child_depth
+                    ),
+                    DataNode::Inner(mut child) => {
+                        Box::pin(Self::_punch_holes_in_subtree_of_inner_node(
+                            node_store,
+                            &mut child,
+                            child_begin_leaf,
+                            begin_leaf,
+                            end_leaf,
+                            may_have_shared_nodes,
+                        ))
+                        .await?
+                    }
+                }
+            };
+            if child_is_all_holes {
+                // The child might have been replaced with an unshared copy above, so we have to look up its id again
+                let child_id = node.children().nth(index).unwrap();
+                node.update_child(index, &HOLE_BLOCK_ID);
+                subtrees_to_remove.push(child_id);
+            }
+        }
+
+        // Ordering: First remove the child block ids from the node, then remove the actual blocks.
+        // This has a higher chance of keeping the file system in a consistent state if there's a power loss in the middle.
+        for_each_unordered(subtrees_to_remove.into_iter(), async |block_id| {
+            Self::_remove_subtree_by_root_id(
+                node_store,
+                child_depth,
+                block_id,
+                may_have_shared_nodes,
+            )
+            .await
+        })
+        .await?;
+
+        Ok(node.children().all(|child_id| child_id == HOLE_BLOCK_ID))
+    }
+
+    /// Overwrite `[offset, offset + len)` with zeroes, but leave holes in the range as they are.
+    /// The range must be within the current size of the tree.
+    async fn _zero_out_existing_leaves(&mut self, offset: u64, len: u64) -> Result<()> {
+        struct Callbacks;
+        #[async_trait]
+        impl<B: BlockStore<Block: Send + Sync> + AsyncDrop + Debug + Send + Sync>
+            TraversalByByteIndicesCallbacks<B> for Callbacks
+        {
+            async fn on_existing_leaf(
+                &self,
+                _index_of_first_leaf_byte: u64,
+                mut leaf: LeafHandle<'_, B>,
+                leaf_data_offset: u32,
+                leaf_data_size: u32,
+            ) -> Result<()> {
+                if leaf.is_hole() {
+                    // Holes already read as zeroes
+                    return Ok(());
+                }
+                leaf.node().await?.data_mut()[usize::try_from(leaf_data_offset).unwrap()
+                    ..usize::try_from(leaf_data_offset + leaf_data_size).unwrap()]
+                    .fill(0);
+                Ok(())
+            }
+            fn on_create_leaf(&self, _begin_byte: u64, _num_bytes: u32) -> Data {
+                panic!("Zeroing out existing data shouldn't create new leaves");
+            }
+        }
+        self._traverse_leaves_by_byte_indices::<Callbacks, false>(offset, len, &Callbacks)
+            .await
+    }
+
+    /// Returns the first offset at or after `offset` that isn't in a hole, or `None` if `offset` is at or beyond the end of the tree.
+    pub async fn seek_data(&mut self, offset: u64) -> Result<Option<u64>> {
+        self._seek(offset, false).await
+    }
+
+    /// Returns the first offset at or after `offset` that is in a hole, or `None` if `offset` is at or beyond the end of the tree.
+    /// The end of the tree counts as a hole, so this returns the size of the tree if there are no holes after `offset`.
+    pub async fn seek_hole(&mut self, offset: u64) -> Result<Option<u64>> {
+        self._seek(offset, true).await
+    }
+
+    async fn _seek(&mut self, offset: u64, find_hole: bool) -> Result<Option<u64>> {
+        let num_bytes = self.num_bytes().await?;
+        if offset >= num_bytes {
+            return Ok(None);
+        }
+        let max_bytes_per_leaf = u64::from(self.node_store.layout().max_bytes_per_leaf());
+        let from_leaf = offset / max_bytes_per_leaf;
+        let found_leaf = match self.root_node.as_ref().expect("DataTree.root_node is None") {
+            // A single leaf is never a hole
+            DataNode::Leaf(_) => (!find_hole).then_some(from_leaf),
+            DataNode::Inner(root) => {
+                Self::_find_leaf_in_subtree_of_inner_node(
+                    &self.node_store,
+                    root,
+                    0,
+                    from_leaf,
+                    find_hole,
+                )
+                .await?
+            }
+        };
+        match found_leaf {
+            Some(leaf_index) => Ok(Some((leaf_index * max_bytes_per_leaf).max(offset))),
+            None if find_hole => Ok(Some(num_bytes)),
+            None => bail!(
+                "Didn't find any data up to the end of the tree but the last leaf is never a hole"
+            ),
+        }
+    }
+
+    /// Returns the index of the first leaf at or after `from_leaf` that is a hole (if `find_hole`) or that isn't a hole (if `!find_hole`).
+    async fn _find_leaf_in_subtree_of_inner_node(
+        node_store: &DataNodeStore<B>,
+        node: &DataInnerNode<B>,
+        first_leaf_index_of_node: u64,
+        from_leaf: u64,
+        find_hole: bool,
+    ) -> Result<Option<u64>> {
+        let child_depth = node.depth().get() - 1;
+        let leaves_per_child = node_store
+            .layout()
+            .num_leaves_per_full_subtree(child_depth)?
+            .get();
+        for (index, child_id) in node.children().enumerate() {
+            let child_begin_leaf = first_leaf_index_of_node + index as u64 * leaves_per_child;
+            let child_end_leaf = child_begin_leaf + leaves_per_child;
+            if child_end_leaf <= from_leaf {
+                continue;
+            }
+            let first_relevant_leaf = child_begin_leaf.max(from_leaf);
+            if child_id == HOLE_BLOCK_ID {
+                if find_hole {
+                    return Ok(Some(first_relevant_leaf));
+                }
+                continue;
+            }
+            if child_depth == 0 {
+                if !find_hole {
+                    return Ok(Some(first_relevant_leaf));
+                }
+                continue;
+            }
+            match node_store.load(child_id).await? {
+                None => bail!("Couldn't find child node {:?}", child_id),
+                Some(DataNode::Leaf(_)) => bail!(
+                    "Loaded a leaf node {:?} but expected an inner node at depth {}",
+                    child_id,
+                    child_depth
+                ),
+                Some(DataNode::Inner(child)) => {
+                    let found = Box::pin(Self::_find_leaf_in_subtree_of_inner_node(
+                        node_store,
+                        &child,
+                        child_begin_leaf,
+                        from_leaf,
+                        find_hole,
+                    ))
+                    .await?;
+                    if found.is_some() {
+                        return Ok(found);
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Copy `len` bytes starting at `source_offset` in `source` to `dest_offset` in this tree, growing this tree if necessary.
+    /// Returns the number of bytes copied, which is less than `len` if `source` ends before.
+    ///
+    /// If both offsets are at the same position within their leaves, the leaves fully covered by the range aren't copied.
+    /// Instead, the largest aligned subtrees are shared between both trees and only get copied once one of the trees modifies
+    /// them (copy-on-write). Shared nodes count how many parents reference them, see [DataNode::num_extra_references].
+    pub async fn clone_range_from(
+        &mut self,
+        source: &mut DataTree<B>,
+        source_offset: u64,
+        dest_offset: u64,
+        len: u64,
+    ) -> Result<u64> {
+        ensure!(
+            AsyncDropArc::ptr_eq(&self.node_store, &source.node_store),
+            "Can only share nodes between trees from the same DataTreeStore"
+        );
+        let source_num_bytes = source.num_bytes().await?;
+        let len = len.min(source_num_bytes.saturating_sub(source_offset));
+        if len == 0 {
+            return Ok(0);
+        }
+        let dest_end = dest_offset
+            .checked_add(len)
+            .ok_or_else(|| anyhow!("Overflow in dest_offset+len: {}+{}", dest_offset, len))?;
+        let max_bytes_per_leaf = u64::from(self.node_store.layout().max_bytes_per_leaf());
+        if source_offset % max_bytes_per_leaf != dest_offset % max_bytes_per_leaf {
+            // The leaves of the two ranges don't line up, so there is nothing to share
+            self._copy_bytes_from(source, source_offset, dest_offset, len)
+                .await?;
+            return Ok(len);
+        }
+
+        if self.num_bytes().await? < dest_end {
+            // Growing is sparse, so the shared subtrees mostly replace holes
+            self.resize_num_bytes(dest_end).await?;
+        }
+
+        // Leaf `i` of `source` is copied to leaf `i - source_first_leaf + dest_first_leaf` of this tree.
+        let source_first_leaf = source_offset / max_bytes_per_leaf;
+        let dest_first_leaf = dest_offset / max_bytes_per_leaf;
+        let begin_leaf = DivCeil::div_ceil(source_offset, max_bytes_per_leaf);
+        // The last leaf of a tree can be partial and the nodes on the right border of a tree can have fewer children,
+        // so we only share subtrees that don't contain the last leaf of either tree.
+        let end_leaf = ((source_offset + len) / max_bytes_per_leaf)
+            .min(source._num_leaves().await? - 1)
+            .min(self._num_leaves().await? - 1 - dest_first_leaf + source_first_leaf);
+        if begin_leaf >= end_leaf {
+            self._copy_bytes_from(source, source_offset, dest_offset, len)
+                .await?;
+            return Ok(len);
+        }
+
+        // Ordering: Mark both trees before sharing anything, so that they never have shared nodes without knowing about it.
+        for tree in [&mut *source, &mut *self] {
+            let root = tree.root_node.as_mut().expect("DataTree.root_node is None");
+            if !root.tree_may_have_shared_nodes() {
+                root.set_tree_may_have_shared_nodes();
+            }
+        }
+
+        // Subtrees have to be below the root of both trees since roots can't be shared
+        let max_depth = self._root_node().depth().min(source._root_node().depth()) - 1;
+        let layout = *self.node_store.layout();
+        let mut leaf = begin_leaf;
+        while leaf < end_leaf {
+            let dest_leaf = leaf - source_first_leaf + dest_first_leaf;
+            let mut depth = max_depth;
+            let mut leaves_per_subtree = layout.num_leaves_per_full_subtree(depth)?.get();
+            while depth > 0
+                && (!leaf.is_multiple_of(leaves_per_subtree)
+                    || !dest_leaf.is_multiple_of(leaves_per_subtree)
+                    || leaf + leaves_per_subtree > end_leaf)
+            {
+                depth -= 1;
+                leaves_per_subtree = layout.num_leaves_per_full_subtree(depth)?.get();
+            }
+            self._share_subtree_from(source, leaf, dest_leaf, depth)
+                .await?;
+            leaf += leaves_per_subtree;
+        }
+
+        // Copy the partial leaves at the borders of the range
+        let begin_byte = begin_leaf * max_bytes_per_leaf;
+        let end_byte = end_leaf * max_bytes_per_leaf;
+        self._copy_bytes_from(
+            source,
+            source_offset,
+            dest_offset,
+            begin_byte - source_offset,
+        )
+        .await?;
+        self._copy_bytes_from(
+            source,
+            end_byte,
+            dest_offset + (end_byte - source_offset),
+            source_offset + len - end_byte,
+        )
+        .await?;
+
+        Ok(len)
+    }
+
+    async fn _copy_bytes_from(
+        &mut self,
+        source: &mut DataTree<B>,
+        source_offset: u64,
+        dest_offset: u64,
+        len: u64,
+    ) -> Result<()> {
+        let mut buffer = vec![0; usize::try_from(len.min(MAX_BYTES_PER_COPY_CHUNK)).unwrap()];
+        let mut num_copied = 0;
+        while num_copied < len {
+            let chunk = &mut buffer
+                [..usize::try_from((len - num_copied).min(MAX_BYTES_PER_COPY_CHUNK)).unwrap()];
+            source.read_bytes(source_offset + num_copied, chunk).await?;
+            self.write_bytes(chunk, dest_offset + num_copied).await?;
+            num_copied += u64::try_from(chunk.len()).unwrap();
+        }
+        Ok(())
+    }
+
+    /// Replace the subtree of the given depth that starts at `dest_leaf` in this tree with a reference to the subtree
+    /// that starts at `source_leaf` in `source`.
+    async fn _share_subtree_from(
+        &mut self,
+        source: &DataTree<B>,
+        source_leaf: u64,
+        dest_leaf: u64,
+        depth: u8,
+    ) -> Result<()> {
+        // Ordering: First take the new reference, then give up the old one. Otherwise, a subtree that is already
+        // shared between both trees at this position could get removed.
+        let source_subtree_id =
+            Self::_find_subtree_id(&self.node_store, source._root_node(), source_leaf, depth)
+                .await?;
+        let subtree_id = if source_subtree_id == HOLE_BLOCK_ID {
+            HOLE_BLOCK_ID
+        } else {
+            Self::_share_subtree(&self.node_store, source_subtree_id).await?
+        };
+        let replaced_subtree_id = match self.root_node.as_mut().expect("DataTree.root_node is None")
+        {
+            DataNode::Leaf(_) => {
+                bail!("Tried to replace a subtree in a tree that only has one leaf")
+            }
+            DataNode::Inner(root) => {
+                Self::_replace_subtree(&self.node_store, root, 0, dest_leaf, depth, &subtree_id)
+                    .await?
+            }
+        };
+        Self::_remove_subtree_by_root_id(&self.node_store, depth, replaced_subtree_id, true).await
+    }
+
+    /// Returns the id of the subtree of the given depth that contains `leaf_index`. If that subtree is in a hole, this returns [HOLE_BLOCK_ID].
+    async fn _find_subtree_id(
+        node_store: &DataNodeStore<B>,
+        root: &DataNode<B>,
+        leaf_index: u64,
+        depth: u8,
+    ) -> Result<BlockId> {
+        let DataNode::Inner(root) = root else {
+            bail!("Tried to find a subtree in a tree that only has one leaf");
+        };
+        let (_, mut subtree_id, mut subtree_first_leaf) =
+            Self::_child_containing_leaf(node_store.layout(), root, 0, leaf_index)?;
+        let mut subtree_depth = root.depth().get() - 1;
+        while subtree_depth > depth {
+            if subtree_id == HOLE_BLOCK_ID {
+                return Ok(HOLE_BLOCK_ID);
+            }
+            let DataNode::Inner(subtree) =
+                Self::_load_node(node_store, subtree_id, subtree_depth).await?
+            else {
+                unreachable!("_load_node checked that the node has depth {subtree_depth} > 0");
+            };
+            (_, subtree_id, subtree_first_leaf) = Self::_child_containing_leaf(
+                node_store.layout(),
+                &subtree,
+                subtree_first_leaf,
+                leaf_index,
+            )?;
+            subtree_depth -= 1;
+        }
+        Ok(subtree_id)
+    }
+
+    /// Replace the subtree of the given depth that contains `leaf_index` with `new_subtree_id` and return the id of the replaced subtree.
+    /// Shared nodes and holes on the path to it get replaced with nodes that can be modified.
+    async fn _replace_subtree(
+        node_store: &DataNodeStore<B>,
+        node: &mut DataInnerNode<B>,
+        first_leaf_index_of_node: u64,
+        leaf_index: u64,
+        depth: u8,
+        new_subtree_id: &BlockId,
+    ) -> Result<BlockId> {
+        let (index, child_id, child_first_leaf) = Self::_child_containing_leaf(
+            node_store.layout(),
+            node,
+            first_leaf_index_of_node,
+            leaf_index,
+        )?;
+        if node.depth().get() - 1 == depth {
+            node.update_child(index, new_subtree_id);
+            return Ok(child_id);
+        }
+        match Self::_load_child_for_writing(node_store, node, index).await? {
+            DataNode::Leaf(_) => {
+                unreachable!("_load_child_for_writing checked that the node has depth > {depth}")
+            }
+            DataNode::Inner(mut child) => {
+                Box::pin(Self::_replace_subtree(
+                    node_store,
+                    &mut child,
+                    child_first_leaf,
+                    leaf_index,
+                    depth,
+                    new_subtree_id,
+                ))
+                .await
+            }
+        }
+    }
+
+    /// Returns the index and id of the child of `node` that contains `leaf_index`, and the index of the first leaf of that child.
+    fn _child_containing_leaf(
+        layout: &NodeLayout,
+        node: &DataInnerNode<B>,
+        first_leaf_index_of_node: u64,
+        leaf_index: u64,
+    ) -> Result<(usize, BlockId, u64)> {
+        let leaves_per_child = layout
+            .num_leaves_per_full_subtree(node.depth().get() - 1)?
+            .get();
+        let index = (leaf_index - first_leaf_index_of_node) / leaves_per_child;
+        let child_id = node
+            .children()
+            .nth(usize::try_from(index).unwrap())
+            .ok_or_else(|| {
+                anyhow!(
+                    "Leaf {} isn't in the subtree of node {:?}",
+                    leaf_index,
+                    node.block_id()
+                )
+            })?;
+        Ok((
+            usize::try_from(index).unwrap(),
+            child_id,
+            first_leaf_index_of_node + index * leaves_per_child,
+        ))
+    }
+
+    async fn _load_node(
+        node_store: &DataNodeStore<B>,
+        block_id: BlockId,
+        depth: u8,
+    ) -> Result<DataNode<B>> {
+        let node = node_store
+            .load(block_id)
+            .await?
+            .ok_or_else(|| anyhow!("Couldn't find node {:?}", block_id))?;
+        ensure!(
+            node.depth() == depth,
+            "Loaded node {:?} with depth {} but expected depth {}",
+            block_id,
+            node.depth(),
+            depth
+        );
+        Ok(node)
+    }
+
+    /// Load the child at `index` of `node` so that it can be modified. A hole gets replaced with a new node and
+    /// a child that is shared with other trees gets replaced with an unshared copy.
+    async fn _load_child_for_writing(
+        node_store: &DataNodeStore<B>,
+        node: &mut DataInnerNode<B>,
+        index: usize,
+    ) -> Result<DataNode<B>> {
+        let child_id = node
+            .children()
+            .nth(index)
+            .expect("Child index out of bounds");
+        let child_depth = node.depth().get() - 1;
+        if child_id == HOLE_BLOCK_ID {
+            let new_child_id = traversal::create_node_for_hole(node_store, child_depth).await?;
+            node.update_child(index, &new_child_id);
+            return Self::_load_node(node_store, new_child_id, child_depth).await;
+        }
+        let child = Self::_load_node(node_store, child_id, child_depth).await?;
+        if child.num_extra_references() == 0 {
+            return Ok(child);
+        }
+        let copy = Self::_unshare_node(node_store, child).await?;
+        node.update_child(index, copy.block_id());
+        Ok(copy)
+    }
+
+    /// Create an unshared copy of `node` and give up one reference to `node`. The caller has to replace
+    /// its reference to `node` with the copy.
+    async fn _unshare_node(
+        node_store: &DataNodeStore<B>,
+        mut node: DataNode<B>,
+    ) -> Result<DataNode<B>> {
+        let num_extra_references = node.num_extra_references();
+        assert!(
+            num_extra_references > 0,
+            "Tried to unshare node {:?} but it isn't shared",
+            node.block_id()
+        );
+        // Ordering: First let the copy take its references to the children, then give up the reference to `node`.
+        let mut copy = node_store.create_new_node_as_copy_from(&node).await?;
+        Self::_share_children(node_store, &mut copy).await?;
+        node.set_num_extra_references(num_extra_references - 1);
+        Ok(copy)
+    }
+
+    /// Take another reference to the node with the given id and return the id to use for it. That's usually the
+    /// same id, but if the node already has the maximal number of references, this creates and returns a copy.
+    async fn _share_subtree(node_store: &DataNodeStore<B>, block_id: BlockId) -> Result<BlockId> {
+        let mut node = node_store
+            .load(block_id)
+            .await?
+            .ok_or_else(|| anyhow!("Couldn't find node {:?} to share", block_id))?;
+        let num_extra_references = node.num_extra_references();
+        if num_extra_references < u8::MAX {
+            node.set_num_extra_references(num_extra_references + 1);
+            return Ok(block_id);
+        }
+        let mut copy = node_store.create_new_node_as_copy_from(&node).await?;
+        std::mem::drop(node);
+        Self::_share_children(node_store, &mut copy).await?;
+        Ok(*copy.block_id())
+    }
+
+    /// Take another reference to each child of `node`. This is needed when `node` is a new copy of another node.
+    async fn _share_children(node_store: &DataNodeStore<B>, node: &mut DataNode<B>) -> Result<()> {
+        let DataNode::Inner(node) = node else {
+            return Ok(());
+        };
+        let children: Vec<BlockId> = node.children().collect();
+        for (index, child_id) in children.into_iter().enumerate() {
+            if child_id == HOLE_BLOCK_ID {
+                continue;
+            }
+            let shared_child_id = Box::pin(Self::_share_subtree(node_store, child_id)).await?;
+            if shared_child_id != child_id {
+                node.update_child(index, &shared_child_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Before a writing traversal of the leaves `[begin_leaf, end_leaf)`, replace the shared nodes it could modify with
+    /// unshared copies. Besides the traversed leaves, growing the tree also modifies its old last leaf and right border.
+    async fn _unshare_nodes_for_writing_traversal(
+        &mut self,
+        begin_leaf: u64,
+        end_leaf: u64,
+    ) -> Result<()> {
+        if !self._root_node().tree_may_have_shared_nodes() {
+            return Ok(());
+        }
+        let num_leaves = self._num_leaves().await?;
+        let begin_leaf = if end_leaf > num_leaves {
+            begin_leaf.min(num_leaves - 1)
+        } else {
+            begin_leaf
+        };
+        self._unshare_leaves(begin_leaf, end_leaf.min(num_leaves))
+            .await
+    }
+
+    /// Replace all shared nodes on the paths to the leaves `[begin_leaf, end_leaf)` with unshared copies so that they can be modified.
+    /// Holes stay as they are.
+    async fn _unshare_leaves(&mut self, begin_leaf: u64, end_leaf: u64) -> Result<()> {
+        if begin_leaf >= end_leaf || !self._root_node().tree_may_have_shared_nodes() {
+            return Ok(());
+        }
+        match self.root_node.as_mut().expect("DataTree.root_node is None") {
+            // Roots are never shared
+            DataNode::Leaf(_) => Ok(()),
+            DataNode::Inner(root) => {
+                Self::_unshare_leaves_in_subtree_of_inner_node(
+                    &self.node_store,
+                    root,
+                    0,
+                    begin_leaf,
+                    end_leaf,
+                )
+                .await
+            }
+        }
+    }
+
+    async fn _unshare_leaves_in_subtree_of_inner_node(
+        node_store: &DataNodeStore<B>,
+        node: &mut DataInnerNode<B>,
+        first_leaf_index_of_node: u64,
+        begin_leaf: u64,
+        end_leaf: u64,
+    ) -> Result<()> {
+        let leaves_per_child = node_store
+            .layout()
+            .num_leaves_per_full_subtree(node.depth().get() - 1)?
+            .get();
+        let begin_child = begin_leaf.saturating_sub(first_leaf_index_of_node) / leaves_per_child;
+        let end_child = DivCeil::div_ceil(end_leaf - first_leaf_index_of_node, leaves_per_child)
+            .min(u64::from(node.num_children().get()));
+        for index in begin_child..end_child {
+            let index = usize::try_from(index).unwrap();
+            if node.children().nth(index).unwrap() == HOLE_BLOCK_ID {
+                continue;
+            }
+            if let DataNode::Inner(mut child) =
+                Self::_load_child_for_writing(node_store, node, index).await?
+            {
+                Box::pin(Self::_unshare_leaves_in_subtree_of_inner_node(
+                    node_store,
+                    &mut child,
+                    first_leaf_index_of_node + index as u64 * leaves_per_child,
+                    begin_leaf,
+                    end_leaf,
+                ))
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
     pub async fn remove(mut this: AsyncDropGuard<Self>) -> Result<()> {
         let root_node = this.root_node.take().expect("DataTree.root_node is None");
         if let Err(e) = Self::_remove_subtree(&*this.node_store, root_node).await {
@@ -610,13 +1371,15 @@ impl<B: BlockStore<Block: Send + Sync> + AsyncDrop + Debug + Send + Sync> DataTr
     }
 
     async fn _remove_subtree(node_store: &DataNodeStore<B>, root: DataNode<B>) -> Result<()> {
+        let may_have_shared_nodes = root.tree_may_have_shared_nodes();
         match root {
             DataNode::Leaf(_) => {
                 node_store.remove(root).await?;
                 Ok(())
             }
             DataNode::Inner(root) => {
-                Self::_remove_subtree_of_inner_node(node_store, root).await?;
+                Self::_remove_subtree_of_inner_node(node_store, root, may_have_shared_nodes)
+                    .await?;
                 Ok(())
             }
         }
@@ -625,6 +1388,7 @@ impl<B: BlockStore<Block: Send + Sync> + AsyncDrop + Debug + Send + Sync> DataTr
     async fn _remove_subtree_of_inner_node(
         node_store: &DataNodeStore<B>,
         root: DataInnerNode<B>,
+        may_have_shared_nodes: bool,
     ) -> Result<()> {
         // Ordering: First remove the node itself, then remove the children.
         // This has a higher chance of keeping the file system in a consistent state if there's a power loss in the middle.
@@ -632,18 +1396,30 @@ impl<B: BlockStore<Block: Send + Sync> + AsyncDrop + Debug + Send + Sync> DataTr
         let depth = root.depth().get();
         node_store.remove(root.upcast()).await?;
         for_each_unordered(children.into_iter(), |child_block_id| {
-            Self::_remove_subtree_by_root_id(node_store, depth - 1, child_block_id)
+            Self::_remove_subtree_by_root_id(
+                node_store,
+                depth - 1,
+                child_block_id,
+                may_have_shared_nodes,
+            )
         })
         .await?;
         Ok(())
     }
 
+    /// Remove the subtree with the given root, or only give up our reference to it if it is shared with other trees.
+    /// If `may_have_shared_nodes` is false, leaves are removed without loading them.
     async fn _remove_subtree_by_root_id(
         node_store: &DataNodeStore<B>,
         depth: u8,
         block_id: BlockId,
+        may_have_shared_nodes: bool,
     ) -> Result<()> {
-        if depth == 0 {
+        if block_id == HOLE_BLOCK_ID {
+            // Holes don't have any nodes to remove
+            return Ok(());
+        }
+        if depth == 0 && !may_have_shared_nodes {
             // Here, we can remove the leaf node without even loading it
             let remove_result = node_store.remove_by_id(&block_id).await?;
             ensure!(
@@ -651,26 +1427,31 @@ impl<B: BlockStore<Block: Send + Sync> + AsyncDrop + Debug + Send + Sync> DataTr
                 "Tried to remove {:?} but didn't find it",
                 block_id
             );
-        } else {
-            match node_store.load(block_id).await? {
-                None => bail!(
-                    "Tried to load inner node {:?} for removal but didn't find it",
-                    block_id
-                ),
-                Some(DataNode::Leaf(_)) => bail!(
-                    "Tried to load inner node {:?} for removal but it was a leaf",
-                    block_id
-                ),
-                Some(DataNode::Inner(node)) => {
-                    ensure!(
-                        node.depth().get() == depth,
-                        "Tried to load inner node {:?} at depth {} for removal but it had depth {}",
-                        block_id,
-                        depth,
-                        node.depth()
-                    );
-                    Self::_remove_subtree_of_inner_node(node_store, node).await?;
-                }
+            return Ok(());
+        }
+        let Some(mut node) = node_store.load(block_id).await? else {
+            bail!(
+                "Tried to load node {:?} for removal but didn't find it",
+                block_id
+            );
+        };
+        ensure!(
+            node.depth() == depth,
+            "Tried to load node {:?} at depth {} for removal but it had depth {}",
+            block_id,
+            depth,
+            node.depth()
+        );
+        let num_extra_references = node.num_extra_references();
+        if num_extra_references > 0 {
+            // Other trees still reference this node
+            node.set_num_extra_references(num_extra_references - 1);
+            return Ok(());
+        }
+        match node {
+            DataNode::Leaf(_) => node_store.remove(node).await?,
+            DataNode::Inner(node) => {
+                Self::_remove_subtree_of_inner_node(node_store, node, may_have_shared_nodes).await?
             }
         }
         Ok(())
@@ -701,16 +1482,19 @@ impl<B: BlockStore<Block: Send + Sync> + AsyncDrop + Debug + Send + Sync> DataTr
         subtree_root: &DataInnerNode<B>,
     ) -> Result<BoxStream<'_, Result<BlockId>>> {
         // iter<stream<result<block_id>>>
-        let subtree_stream = subtree_root.children().map(|child_id| {
-            let child_stream = async move {
-                self._all_blocks_in_subtree_of_id(child_id)
-                    .await
-                    // Transform Result<Stream<Result<BlockId>>> into Stream<Result<BlockId>>
-                    .unwrap_or_else(|err| stream::once(future::ready(Err(err))).boxed())
-            };
-            // Transform Future<Stream<Result<BlockId>>> into Stream<Result<BlockId>>
-            child_stream.flatten_stream().boxed()
-        });
+        let subtree_stream = subtree_root
+            .children()
+            .filter(|child_id| *child_id != HOLE_BLOCK_ID)
+            .map(|child_id| {
+                let child_stream = async move {
+                    self._all_blocks_in_subtree_of_id(child_id)
+                        .await
+                        // Transform Result<Stream<Result<BlockId>>> into Stream<Result<BlockId>>
+                        .unwrap_or_else(|err| stream::once(future::ready(Err(err))).boxed())
+                };
+                // Transform Future<Stream<Result<BlockId>>> into Stream<Result<BlockId>>
+                child_stream.flatten_stream().boxed()
+            });
         let subtree_stream = stream::select_all(subtree_stream).boxed();
         Ok(subtree_stream)
     }
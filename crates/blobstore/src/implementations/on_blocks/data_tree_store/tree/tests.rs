@@ -1,9 +1,4 @@
 use super::super::super::data_node_store::NodeLayout;
-#[cfg(any(
-    feature = "slow-tests-1",
-    feature = "slow-tests-2",
-    feature = "slow-tests-6"
-))]
 use super::super::super::data_tree_store::DataTree;
 use super::super::testutils::*;
 #[cfg(feature = "slow-tests-any")]
@@ -38,6 +33,8 @@ mod testutils {
     use super::super::super::super::data_node_store::DataNode;
     use super::super::super::super::data_node_store::DataNodeStore;
     #[cfg(any(feature = "slow-tests-4", feature = "slow-tests-5"))]
+    use super::super::super::super::data_node_store::HOLE_BLOCK_ID;
+    #[cfg(any(feature = "slow-tests-4", feature = "slow-tests-5"))]
     use super::super::super::{DataTree, DataTreeStore};
     use super::*;
 
@@ -116,6 +113,7 @@ mod testutils {
             feature = "slow-tests-1",
             feature = "slow-tests-2",
             feature = "slow-tests-4",
+            feature = "slow-tests-5",
         ))]
         pub fn expected_num_leaves(&self, layout: NodeLayout) -> u64 {
             self.num_full_leaves.eval(layout) + 1
@@ -250,6 +248,10 @@ mod testutils {
         expected_depth: u8,
         nodestore: &DataNodeStore<B>,
     ) {
+        if root_id == HOLE_BLOCK_ID {
+            // A hole stands for a max data tree full of zeroes
+            return;
+        }
         let root = nodestore
             .load(root_id)
             .await
@@ -417,13 +419,23 @@ mod testutils {
                     as u64)
                     .pow(inner.depth().get() as u32 - 1);
                 let children = inner.children();
-                future::join_all(children.enumerate().map(|(child_index, child_id)| {
-                    Box::pin(for_each_leaf(
-                        child_id,
-                        first_leaf_index + child_index as u64 * num_leaves_per_child,
-                        nodestore,
-                        leaf_callback,
-                    ))
+                future::join_all(children.enumerate().map(async |(child_index, child_id)| {
+                    let first_leaf_index_of_child =
+                        first_leaf_index + child_index as u64 * num_leaves_per_child;
+                    if child_id == HOLE_BLOCK_ID {
+                        let zeroes = vec![0; nodestore.layout().max_bytes_per_leaf() as usize];
+                        for leaf_index in 0..num_leaves_per_child {
+                            leaf_callback(first_leaf_index_of_child + leaf_index, &zeroes);
+                        }
+                    } else {
+                        Box::pin(for_each_leaf(
+                            child_id,
+                            first_leaf_index_of_child,
+                            nodestore,
+                            leaf_callback,
+                        ))
+                        .await;
+                    }
                 }))
                 .await;
             }
@@ -508,19 +520,21 @@ mod num_bytes_and_num_nodes {
                         * param.num_full_leaves.eval(layout)
                         + param.last_leaf_num_bytes.eval(layout);
                     tree.resize_num_bytes(num_bytes).await.unwrap();
-                    assert_eq!(num_bytes, tree.num_bytes().await.unwrap());
-                    assert_eq!(
-                        param.expected_num_nodes(layout),
-                        tree.num_nodes().await.unwrap()
+                    // Growing via resize creates a sparse tree, only the first and the last leaf actually exist
+                    let num_leaves = param.expected_num_leaves(layout);
+                    let expected_num_nodes = expected_num_nodes_after_writing_leaves(
+                        1,
+                        num_leaves - 1,
+                        num_leaves,
+                        layout,
                     );
+                    assert_eq!(num_bytes, tree.num_bytes().await.unwrap());
+                    assert_eq!(expected_num_nodes, tree.num_nodes().await.unwrap());
 
                     // Check the values are still the same when queried again
                     // (they should now be returned from the cache instead of calculated)
                     assert_eq!(num_bytes, tree.num_bytes().await.unwrap());
-                    assert_eq!(
-                        param.expected_num_nodes(layout),
-                        tree.num_nodes().await.unwrap()
-                    );
+                    assert_eq!(expected_num_nodes, tree.num_nodes().await.unwrap());
                     tree.async_drop().await.unwrap();
                 })
             })
@@ -656,6 +670,191 @@ mod root_node_id {
     }
 }
 
+mod sparse {
+    use super::*;
+    use futures::stream::TryStreamExt;
+    use std::collections::HashSet;
+
+    const NUM_LEAVES: u64 = 100;
+
+    #[test]
+    fn growing_via_resize_only_creates_first_and_last_leaf() {
+        run_tokio_test!({
+            with_treestore_and_nodestore(|treestore, nodestore| {
+                Box::pin(async move {
+                    let layout = *nodestore.layout();
+                    let mut tree = treestore.create_tree().await.unwrap();
+                    tree.resize_num_bytes(NUM_LEAVES * layout.max_bytes_per_leaf() as u64)
+                        .await
+                        .unwrap();
+                    let expected_num_nodes = expected_num_nodes_after_writing_leaves(
+                        1,
+                        NUM_LEAVES - 1,
+                        NUM_LEAVES,
+                        layout,
+                    );
+                    assert!(
+                        expected_num_nodes < expected_num_nodes_for_num_leaves(NUM_LEAVES, layout)
+                    );
+                    assert_eq!(expected_num_nodes, tree.num_nodes().await.unwrap());
+                    tree.async_drop().await.unwrap();
+
+                    treestore.clear_cache_slow().await.unwrap();
+                    assert_eq!(expected_num_nodes, nodestore.num_nodes().await.unwrap());
+                })
+            })
+            .await
+        });
+    }
+
+    #[test]
+    fn reading_a_hole_returns_zeroes_and_doesnt_create_nodes() {
+        run_tokio_test!({
+            with_treestore_and_nodestore(|treestore, nodestore| {
+                Box::pin(async move {
+                    let layout = *nodestore.layout();
+                    let num_bytes = NUM_LEAVES * layout.max_bytes_per_leaf() as u64;
+                    let mut tree = treestore.create_tree().await.unwrap();
+                    tree.write_bytes(&[1; 10], 0).await.unwrap();
+                    tree.resize_num_bytes(num_bytes).await.unwrap();
+                    let num_nodes = tree.num_nodes().await.unwrap();
+
+                    let mut target = vec![1; 3 * layout.max_bytes_per_leaf() as usize];
+                    tree.read_bytes(num_bytes / 2, &mut target).await.unwrap();
+                    assert_eq!(vec![0; target.len()], target);
+
+                    let data = tree.read_all().await.unwrap();
+                    let mut expected_data = vec![0; num_bytes as usize];
+                    expected_data[..10].copy_from_slice(&[1; 10]);
+                    assert_eq!(expected_data, data.as_ref());
+
+                    assert_eq!(num_nodes, tree.num_nodes().await.unwrap());
+                    tree.async_drop().await.unwrap();
+
+                    treestore.clear_cache_slow().await.unwrap();
+                    assert_eq!(num_nodes, nodestore.num_nodes().await.unwrap());
+                })
+            })
+            .await
+        });
+    }
+
+    #[test]
+    fn writing_into_a_hole_only_creates_nodes_on_the_path() {
+        run_tokio_test!({
+            with_treestore_and_nodestore(|treestore, nodestore| {
+                Box::pin(async move {
+                    let layout = *nodestore.layout();
+                    let num_bytes = NUM_LEAVES * layout.max_bytes_per_leaf() as u64;
+                    let mut tree = treestore.create_tree().await.unwrap();
+                    tree.resize_num_bytes(num_bytes).await.unwrap();
+                    let num_nodes_before = tree.num_nodes().await.unwrap();
+
+                    const WRITTEN_LEAF: u64 = NUM_LEAVES / 2;
+                    let offset = WRITTEN_LEAF * layout.max_bytes_per_leaf() as u64 + 3;
+                    tree.write_bytes(&[5; 4], offset).await.unwrap();
+                    assert_eq!(num_bytes, tree.num_bytes().await.unwrap());
+
+                    // The written leaf and its ancestors that were holes got created, nothing else
+                    let depth = expected_depth_for_num_leaves(NUM_LEAVES, layout) as u64;
+                    let num_nodes_after = tree.num_nodes().await.unwrap();
+                    assert!(num_nodes_after > num_nodes_before);
+                    assert!(num_nodes_after <= num_nodes_before + depth);
+
+                    let data = tree.read_all().await.unwrap();
+                    let mut expected_data = vec![0; num_bytes as usize];
+                    expected_data[offset as usize..offset as usize + 4].copy_from_slice(&[5; 4]);
+                    assert_eq!(expected_data, data.as_ref());
+                    tree.async_drop().await.unwrap();
+
+                    treestore.clear_cache_slow().await.unwrap();
+                    assert_eq!(num_nodes_after, nodestore.num_nodes().await.unwrap());
+                })
+            })
+            .await
+        });
+    }
+
+    #[test]
+    fn shrinking_into_a_hole() {
+        run_tokio_test!({
+            with_treestore_and_nodestore(|treestore, nodestore| {
+                Box::pin(async move {
+                    let layout = *nodestore.layout();
+                    let mut tree = treestore.create_tree().await.unwrap();
+                    tree.resize_num_bytes(NUM_LEAVES * layout.max_bytes_per_leaf() as u64)
+                        .await
+                        .unwrap();
+
+                    let new_num_bytes = NUM_LEAVES / 2 * layout.max_bytes_per_leaf() as u64 + 7;
+                    tree.resize_num_bytes(new_num_bytes).await.unwrap();
+                    assert_eq!(new_num_bytes, tree.num_bytes().await.unwrap());
+                    let data = tree.read_all().await.unwrap();
+                    assert_eq!(vec![0; new_num_bytes as usize], data.as_ref());
+                    let num_nodes = tree.num_nodes().await.unwrap();
+                    tree.async_drop().await.unwrap();
+
+                    treestore.clear_cache_slow().await.unwrap();
+                    assert_eq!(num_nodes, nodestore.num_nodes().await.unwrap());
+                })
+            })
+            .await
+        });
+    }
+
+    #[test]
+    fn all_blocks_doesnt_contain_holes() {
+        run_tokio_test!({
+            with_treestore_and_nodestore(|treestore, nodestore| {
+                Box::pin(async move {
+                    let layout = *nodestore.layout();
+                    let mut tree = treestore.create_tree().await.unwrap();
+                    tree.resize_num_bytes(NUM_LEAVES * layout.max_bytes_per_leaf() as u64)
+                        .await
+                        .unwrap();
+                    let blocks: HashSet<BlockId> =
+                        tree.all_blocks().unwrap().try_collect().await.unwrap();
+                    tree.async_drop().await.unwrap();
+
+                    treestore.clear_cache_slow().await.unwrap();
+                    let expected_blocks: HashSet<BlockId> = nodestore
+                        .all_nodes()
+                        .await
+                        .unwrap()
+                        .try_collect()
+                        .await
+                        .unwrap();
+                    assert_eq!(expected_blocks, blocks);
+                })
+            })
+            .await
+        });
+    }
+
+    #[test]
+    fn remove_deletes_all_nodes() {
+        run_tokio_test!({
+            with_treestore_and_nodestore(|treestore, nodestore| {
+                Box::pin(async move {
+                    let layout = *nodestore.layout();
+                    let mut tree = treestore.create_tree().await.unwrap();
+                    tree.resize_num_bytes(NUM_LEAVES * layout.max_bytes_per_leaf() as u64)
+                        .await
+                        .unwrap();
+                    tree.write_bytes(&[1; 10], layout.max_bytes_per_leaf() as u64 * 10)
+                        .await
+                        .unwrap();
+                    DataTree::remove(tree).await.unwrap();
+
+                    treestore.clear_cache_slow().await.unwrap();
+                    assert_eq!(0, nodestore.num_nodes().await.unwrap());
+                })
+            })
+            .await
+        });
+    }
+}
+
 #[cfg(any(
     feature = "slow-tests-1",
     feature = "slow-tests-2",
@@ -1119,19 +1318,25 @@ mod write_bytes {
                 flush_caches(tree, nodestore, treestore).await;
                 assert_tree_structure(tree_id, expected_depth, nodestore).await;
 
-                // Check it hasn't created any orphan nodes
-                let expected_new_num_leaves = if writing_grew_data {
-                    DivCeil::div_ceil(
+                // Check it hasn't created any orphan nodes and didn't create nodes for holes
+                let old_num_leaves = params.num_full_leaves.eval(layout) + 1;
+                let expected_num_nodes = if writing_grew_data {
+                    let begin_leaf = offset / layout.max_bytes_per_leaf() as u64;
+                    let end_leaf = DivCeil::div_ceil(
                         expected_new_data.len() as u64,
                         layout.max_bytes_per_leaf() as u64,
+                    );
+                    expected_num_nodes_after_writing_leaves(
+                        old_num_leaves,
+                        begin_leaf,
+                        end_leaf,
+                        layout,
                     )
                 } else {
                     // We don't use `div_ceil` here because it would be inaccurate
                     // for the corner case where we created a tree with last_leaf_size == 0.
-                    params.num_full_leaves.eval(layout) + 1
+                    expected_num_nodes_for_num_leaves(old_num_leaves, layout)
                 };
-                let expected_num_nodes =
-                    expected_num_nodes_for_num_leaves(expected_new_num_leaves, layout);
                 assert_eq!(expected_num_nodes, nodestore.num_nodes().await.unwrap());
 
                 // Read whole tree back and check its leaves have the correct data
@@ -1196,26 +1401,33 @@ mod resize_num_bytes {
                     // Resize tree with `resize_num_bytes`
                     let new_num_bytes = param_after_resize.expected_num_bytes(layout);
                     tree.resize_num_bytes(new_num_bytes).await.unwrap();
+                    // Growing the tree doesn't create nodes for the new leaves except for the last one, they're holes
+                    let old_num_leaves = param_before_resize.expected_num_leaves(layout);
+                    let new_num_leaves = param_after_resize.expected_num_leaves(layout);
+                    let expected_num_nodes = if new_num_leaves > old_num_leaves {
+                        expected_num_nodes_after_writing_leaves(
+                            old_num_leaves,
+                            new_num_leaves - 1,
+                            new_num_leaves,
+                            layout,
+                        )
+                    } else {
+                        param_after_resize.expected_num_nodes(layout)
+                    };
 
                     // Check key didn't change
                     assert_eq!(tree_id, *tree.root_node_id());
 
                     // Check tree has correct size (looked up from the size cache of the `tree` instance)
                     assert_eq!(new_num_bytes, tree.num_bytes().await.unwrap());
-                    assert_eq!(
-                        param_after_resize.expected_num_nodes(layout),
-                        tree.num_nodes().await.unwrap()
-                    );
+                    assert_eq!(expected_num_nodes, tree.num_nodes().await.unwrap());
 
                     // Check tree has correct size (looked up after clearing the size cache of the `tree` instance)
                     tree.async_drop().await.unwrap();
                     std::mem::drop(tree);
                     let mut tree = treestore.load_tree(tree_id).await.unwrap().unwrap();
                     assert_eq!(new_num_bytes, tree.num_bytes().await.unwrap());
-                    assert_eq!(
-                        param_after_resize.expected_num_nodes(layout),
-                        tree.num_nodes().await.unwrap()
-                    );
+                    assert_eq!(expected_num_nodes, tree.num_nodes().await.unwrap());
 
                     // Check tree data using `read_all`
                     let read_data = tree.read_all().await.unwrap();
@@ -1242,10 +1454,7 @@ mod resize_num_bytes {
                     assert_leaf_data_is_correct(tree_id, &expected_new_data, nodestore).await;
 
                     // Check there weren't too many nodes created or left behind
-                    assert_eq!(
-                        param_after_resize.expected_num_nodes(layout),
-                        nodestore.num_nodes().await.unwrap()
-                    );
+                    assert_eq!(expected_num_nodes, nodestore.num_nodes().await.unwrap());
                 })
             })
             .await;
@@ -1429,3 +1638,600 @@ mod all_blocks {
         });
     }
 }
+
+mod punch_hole_and_seek {
+    use super::*;
+
+    const NUM_LEAVES: u64 = 100;
+
+    #[test]
+    fn punching_a_hole_zeroes_the_range_and_keeps_the_size() {
+        run_tokio_test!({
+            with_treestore_and_nodestore(|treestore, nodestore| {
+                Box::pin(async move {
+                    let layout = *nodestore.layout();
+                    let leaf_size = layout.max_bytes_per_leaf() as u64;
+                    let num_bytes = NUM_LEAVES * leaf_size;
+                    let mut tree = treestore.create_tree().await.unwrap();
+                    tree.write_bytes(&vec![1; num_bytes as usize], 0)
+                        .await
+                        .unwrap();
+
+                    let offset = 10 * leaf_size + 5;
+                    let len = 20 * leaf_size;
+                    tree.punch_hole(offset, len).await.unwrap();
+
+                    assert_eq!(num_bytes, tree.num_bytes().await.unwrap());
+                    let data = tree.read_all().await.unwrap();
+                    let mut expected_data = vec![1; num_bytes as usize];
+                    expected_data[offset as usize..(offset + len) as usize].fill(0);
+                    assert_eq!(expected_data, data.as_ref());
+                    tree.async_drop().await.unwrap();
+                })
+            })
+            .await
+        });
+    }
+
+    #[test]
+    fn punching_a_hole_removes_fully_covered_leaves() {
+        run_tokio_test!({
+            with_treestore_and_nodestore(|treestore, nodestore| {
+                Box::pin(async move {
+                    let layout = *nodestore.layout();
+                    let leaf_size = layout.max_bytes_per_leaf() as u64;
+                    let num_bytes = NUM_LEAVES * leaf_size;
+                    let mut tree = treestore.create_tree().await.unwrap();
+                    tree.write_bytes(&vec![1; num_bytes as usize], 0)
+                        .await
+                        .unwrap();
+                    let num_nodes_before = tree.num_nodes().await.unwrap();
+
+                    // Covers leaves 10..30 partially at the borders, so leaves 11..30 become holes
+                    tree.punch_hole(10 * leaf_size + 5, 20 * leaf_size)
+                        .await
+                        .unwrap();
+
+                    let num_nodes_after = tree.num_nodes().await.unwrap();
+                    assert!(num_nodes_after <= num_nodes_before - 19);
+                    tree.async_drop().await.unwrap();
+
+                    treestore.clear_cache_slow().await.unwrap();
+                    assert_eq!(num_nodes_after, nodestore.num_nodes().await.unwrap());
+                })
+            })
+            .await
+        });
+    }
+
+    #[test]
+    fn punching_a_hole_until_the_end_keeps_the_last_leaf() {
+        run_tokio_test!({
+            with_treestore_and_nodestore(|treestore, nodestore| {
+                Box::pin(async move {
+                    let layout = *nodestore.layout();
+                    let leaf_size = layout.max_bytes_per_leaf() as u64;
+                    let num_bytes = NUM_LEAVES * leaf_size;
+                    let mut tree = treestore.create_tree().await.unwrap();
+                    tree.write_bytes(&vec![1; num_bytes as usize], 0)
+                        .await
+                        .unwrap();
+
+                    tree.punch_hole(0, 2 * num_bytes).await.unwrap();
+
+                    assert_eq!(num_bytes, tree.num_bytes().await.unwrap());
+                    let data = tree.read_all().await.unwrap();
+                    assert_eq!(vec![0; num_bytes as usize], data.as_ref());
+                    // Only the path to the last leaf is left
+                    assert_eq!(
+                        expected_depth_for_num_leaves(NUM_LEAVES, layout) as u64 + 1,
+                        tree.num_nodes().await.unwrap(),
+                    );
+                    assert_eq!(
+                        Some(num_bytes - leaf_size),
+                        tree.seek_data(0).await.unwrap()
+                    );
+                    tree.async_drop().await.unwrap();
+                })
+            })
+            .await
+        });
+    }
+
+    #[test]
+    fn punching_a_hole_beyond_the_end_does_nothing() {
+        run_tokio_test!({
+            with_treestore_and_nodestore(|treestore, nodestore| {
+                Box::pin(async move {
+                    let layout = *nodestore.layout();
+                    let num_bytes = NUM_LEAVES * layout.max_bytes_per_leaf() as u64;
+                    let mut tree = treestore.create_tree().await.unwrap();
+                    tree.write_bytes(&vec![1; num_bytes as usize], 0)
+                        .await
+                        .unwrap();
+                    let num_nodes = tree.num_nodes().await.unwrap();
+
+                    tree.punch_hole(num_bytes, 1000).await.unwrap();
+
+                    assert_eq!(num_bytes, tree.num_bytes().await.unwrap());
+                    assert_eq!(num_nodes, tree.num_nodes().await.unwrap());
+                    let data = tree.read_all().await.unwrap();
+                    assert_eq!(vec![1; num_bytes as usize], data.as_ref());
+                    tree.async_drop().await.unwrap();
+                })
+            })
+            .await
+        });
+    }
+
+    #[test]
+    fn writing_into_a_punched_hole() {
+        run_tokio_test!({
+            with_treestore_and_nodestore(|treestore, nodestore| {
+                Box::pin(async move {
+                    let layout = *nodestore.layout();
+                    let leaf_size = layout.max_bytes_per_leaf() as u64;
+                    let num_bytes = NUM_LEAVES * leaf_size;
+                    let mut tree = treestore.create_tree().await.unwrap();
+                    tree.write_bytes(&vec![1; num_bytes as usize], 0)
+                        .await
+                        .unwrap();
+
+                    tree.punch_hole(0, num_bytes / 2).await.unwrap();
+                    tree.write_bytes(&[2; 3], 20 * leaf_size + 1).await.unwrap();
+
+                    let data = tree.read_all().await.unwrap();
+                    let mut expected_data = vec![1; num_bytes as usize];
+                    expected_data[..(num_bytes / 2) as usize].fill(0);
+                    expected_data[(20 * leaf_size + 1) as usize..(20 * leaf_size + 4) as usize]
+                        .fill(2);
+                    assert_eq!(expected_data, data.as_ref());
+                    tree.async_drop().await.unwrap();
+                })
+            })
+            .await
+        });
+    }
+
+    #[test]
+    fn seek_in_tree_without_holes() {
+        run_tokio_test!({
+            with_treestore_and_nodestore(|treestore, nodestore| {
+                Box::pin(async move {
+                    let layout = *nodestore.layout();
+                    let num_bytes = NUM_LEAVES * layout.max_bytes_per_leaf() as u64;
+                    let mut tree = treestore.create_tree().await.unwrap();
+                    tree.write_bytes(&vec![1; num_bytes as usize], 0)
+                        .await
+                        .unwrap();
+
+                    assert_eq!(Some(0), tree.seek_data(0).await.unwrap());
+                    assert_eq!(Some(123), tree.seek_data(123).await.unwrap());
+                    assert_eq!(Some(num_bytes), tree.seek_hole(0).await.unwrap());
+                    assert_eq!(Some(num_bytes), tree.seek_hole(123).await.unwrap());
+                    assert_eq!(None, tree.seek_data(num_bytes).await.unwrap());
+                    assert_eq!(None, tree.seek_hole(num_bytes).await.unwrap());
+                    tree.async_drop().await.unwrap();
+                })
+            })
+            .await
+        });
+    }
+
+    #[test]
+    fn seek_in_sparse_tree() {
+        run_tokio_test!({
+            with_treestore_and_nodestore(|treestore, nodestore| {
+                Box::pin(async move {
+                    let layout = *nodestore.layout();
+                    let leaf_size = layout.max_bytes_per_leaf() as u64;
+                    let num_bytes = NUM_LEAVES * leaf_size;
+                    let mut tree = treestore.create_tree().await.unwrap();
+                    tree.write_bytes(&vec![1; num_bytes as usize], 0)
+                        .await
+                        .unwrap();
+                    tree.punch_hole(10 * leaf_size, 20 * leaf_size)
+                        .await
+                        .unwrap();
+                    tree.punch_hole(50 * leaf_size, 10 * leaf_size)
+                        .await
+                        .unwrap();
+
+                    assert_eq!(Some(0), tree.seek_data(0).await.unwrap());
+                    assert_eq!(Some(10 * leaf_size), tree.seek_hole(0).await.unwrap());
+                    assert_eq!(
+                        Some(10 * leaf_size + 7),
+                        tree.seek_hole(10 * leaf_size + 7).await.unwrap()
+                    );
+                    assert_eq!(
+                        Some(30 * leaf_size),
+                        tree.seek_data(10 * leaf_size + 7).await.unwrap()
+                    );
+                    assert_eq!(
+                        Some(50 * leaf_size),
+                        tree.seek_hole(30 * leaf_size).await.unwrap()
+                    );
+                    assert_eq!(
+                        Some(60 * leaf_size),
+                        tree.seek_data(50 * leaf_size).await.unwrap()
+                    );
+                    assert_eq!(
+                        Some(num_bytes),
+                        tree.seek_hole(60 * leaf_size).await.unwrap()
+                    );
+                    tree.async_drop().await.unwrap();
+                })
+            })
+            .await
+        });
+    }
+
+    #[test]
+    fn seek_in_tree_grown_via_resize() {
+        run_tokio_test!({
+            with_treestore_and_nodestore(|treestore, nodestore| {
+                Box::pin(async move {
+                    let layout = *nodestore.layout();
+                    let leaf_size = layout.max_bytes_per_leaf() as u64;
+                    let num_bytes = NUM_LEAVES * leaf_size;
+                    let mut tree = treestore.create_tree().await.unwrap();
+                    tree.write_bytes(&[1; 10], 0).await.unwrap();
+                    tree.resize_num_bytes(num_bytes).await.unwrap();
+
+                    assert_eq!(Some(leaf_size), tree.seek_hole(0).await.unwrap());
+                    assert_eq!(
+                        Some((NUM_LEAVES - 1) * leaf_size),
+                        tree.seek_data(leaf_size).await.unwrap()
+                    );
+                    tree.async_drop().await.unwrap();
+                })
+            })
+            .await
+        });
+    }
+}
+
+mod clone_range_from {
+    use super::*;
+
+    const NUM_LEAVES: u64 = 100;
+
+    fn data(num_bytes: u64, seed: u8) -> Vec<u8> {
+        (0..num_bytes)
+            .map(|i| (i % 251) as u8 ^ seed)
+            .collect::<Vec<u8>>()
+    }
+
+    #[test]
+    fn cloning_an_aligned_range_shares_the_nodes() {
+        run_tokio_test!({
+            with_treestore_and_nodestore(|treestore, nodestore| {
+                Box::pin(async move {
+                    let layout = *nodestore.layout();
+                    let num_bytes = NUM_LEAVES * layout.max_bytes_per_leaf() as u64;
+                    let source_data = data(num_bytes, 1);
+                    let mut source = treestore.create_tree().await.unwrap();
+                    source.write_bytes(&source_data, 0).await.unwrap();
+                    let num_source_nodes = source.num_nodes().await.unwrap();
+                    let mut dest = treestore.create_tree().await.unwrap();
+
+                    assert_eq!(
+                        num_bytes,
+                        dest.clone_range_from(&mut source, 0, 0, num_bytes)
+                            .await
+                            .unwrap()
+                    );
+
+                    assert_eq!(source_data, dest.read_all().await.unwrap().as_ref());
+                    assert_eq!(source_data, source.read_all().await.unwrap().as_ref());
+                    source.async_drop().await.unwrap();
+                    dest.async_drop().await.unwrap();
+                    treestore.clear_cache_slow().await.unwrap();
+                    // Only the right border of the tree is copied
+                    assert!(
+                        nodestore.num_nodes().await.unwrap()
+                            <= num_source_nodes
+                                + 1
+                                + expected_depth_for_num_leaves(NUM_LEAVES, layout) as u64
+                    );
+                })
+            })
+            .await
+        });
+    }
+
+    #[test]
+    fn modifying_a_clone_doesnt_modify_the_source() {
+        run_tokio_test!({
+            with_treestore_and_nodestore(|treestore, nodestore| {
+                Box::pin(async move {
+                    let layout = *nodestore.layout();
+                    let leaf_size = layout.max_bytes_per_leaf() as u64;
+                    let num_bytes = NUM_LEAVES * leaf_size;
+                    let source_data = data(num_bytes, 1);
+                    let mut source = treestore.create_tree().await.unwrap();
+                    source.write_bytes(&source_data, 0).await.unwrap();
+                    let mut dest = treestore.create_tree().await.unwrap();
+                    dest.clone_range_from(&mut source, 0, 0, num_bytes)
+                        .await
+                        .unwrap();
+
+                    dest.write_bytes(&[0; 10], 10 * leaf_size).await.unwrap();
+                    dest.write_bytes(&vec![0; 3 * leaf_size as usize], 50 * leaf_size)
+                        .await
+                        .unwrap();
+                    dest.punch_hole(70 * leaf_size + 3, 5 * leaf_size)
+                        .await
+                        .unwrap();
+                    dest.resize_num_bytes(90 * leaf_size + 7).await.unwrap();
+                    dest.resize_num_bytes(num_bytes).await.unwrap();
+
+                    let mut expected_dest_data = source_data.clone();
+                    expected_dest_data[(10 * leaf_size) as usize..(10 * leaf_size + 10) as usize]
+                        .fill(0);
+                    expected_dest_data[(50 * leaf_size) as usize..(53 * leaf_size) as usize]
+                        .fill(0);
+                    expected_dest_data
+                        [(70 * leaf_size + 3) as usize..(75 * leaf_size + 3) as usize]
+                        .fill(0);
+                    expected_dest_data[(90 * leaf_size + 7) as usize..].fill(0);
+                    assert_eq!(expected_dest_data, dest.read_all().await.unwrap().as_ref());
+                    assert_eq!(source_data, source.read_all().await.unwrap().as_ref());
+
+                    source.write_bytes(&[5; 10], 20 * leaf_size).await.unwrap();
+                    assert_eq!(expected_dest_data, dest.read_all().await.unwrap().as_ref());
+
+                    source.async_drop().await.unwrap();
+                    dest.async_drop().await.unwrap();
+                })
+            })
+            .await
+        });
+    }
+
+    #[test]
+    fn removing_both_trees_removes_all_nodes() {
+        run_tokio_test!({
+            with_treestore_and_nodestore(|treestore, nodestore| {
+                Box::pin(async move {
+                    let layout = *nodestore.layout();
+                    let leaf_size = layout.max_bytes_per_leaf() as u64;
+                    let num_bytes = NUM_LEAVES * leaf_size;
+                    let source_data = data(num_bytes, 1);
+                    let mut source = treestore.create_tree().await.unwrap();
+                    source.write_bytes(&source_data, 0).await.unwrap();
+                    let mut dest = treestore.create_tree().await.unwrap();
+                    dest.clone_range_from(&mut source, 0, 0, num_bytes)
+                        .await
+                        .unwrap();
+                    dest.write_bytes(&[0; 10], 10 * leaf_size).await.unwrap();
+
+                    DataTree::remove(source).await.unwrap();
+                    assert_eq!(
+                        &source_data[(20 * leaf_size) as usize..],
+                        &dest.read_all().await.unwrap()[(20 * leaf_size) as usize..]
+                    );
+                    DataTree::remove(dest).await.unwrap();
+
+                    treestore.clear_cache_slow().await.unwrap();
+                    assert_eq!(0, nodestore.num_nodes().await.unwrap());
+                })
+            })
+            .await
+        });
+    }
+
+    #[test]
+    fn cloning_into_the_middle_of_existing_data() {
+        run_tokio_test!({
+            with_treestore_and_nodestore(|treestore, nodestore| {
+                Box::pin(async move {
+                    let layout = *nodestore.layout();
+                    let leaf_size = layout.max_bytes_per_leaf() as u64;
+                    let num_bytes = NUM_LEAVES * leaf_size;
+                    let source_data = data(num_bytes, 1);
+                    let dest_data = data(num_bytes, 2);
+                    let mut source = treestore.create_tree().await.unwrap();
+                    source.write_bytes(&source_data, 0).await.unwrap();
+                    let mut dest = treestore.create_tree().await.unwrap();
+                    dest.write_bytes(&dest_data, 0).await.unwrap();
+
+                    // Same offset within the leaves, but at different leaves
+                    let source_offset = 3 * leaf_size + 5;
+                    let dest_offset = 40 * leaf_size + 5;
+                    let len = 50 * leaf_size;
+                    assert_eq!(
+                        len,
+                        dest.clone_range_from(&mut source, source_offset, dest_offset, len)
+                            .await
+                            .unwrap()
+                    );
+
+                    let mut expected_dest_data = dest_data.clone();
+                    expected_dest_data[dest_offset as usize..(dest_offset + len) as usize]
+                        .copy_from_slice(
+                            &source_data[source_offset as usize..(source_offset + len) as usize],
+                        );
+                    assert_eq!(expected_dest_data, dest.read_all().await.unwrap().as_ref());
+                    assert_eq!(source_data, source.read_all().await.unwrap().as_ref());
+
+                    DataTree::remove(dest).await.unwrap();
+                    assert_eq!(source_data, source.read_all().await.unwrap().as_ref());
+                    DataTree::remove(source).await.unwrap();
+                    treestore.clear_cache_slow().await.unwrap();
+                    assert_eq!(0, nodestore.num_nodes().await.unwrap());
+                })
+            })
+            .await
+        });
+    }
+
+    #[test]
+    fn cloning_an_unaligned_range_copies_the_data() {
+        run_tokio_test!({
+            with_treestore_and_nodestore(|treestore, nodestore| {
+                Box::pin(async move {
+                    let layout = *nodestore.layout();
+                    let leaf_size = layout.max_bytes_per_leaf() as u64;
+                    let num_bytes = NUM_LEAVES * leaf_size;
+                    let source_data = data(num_bytes, 1);
+                    let mut source = treestore.create_tree().await.unwrap();
+                    source.write_bytes(&source_data, 0).await.unwrap();
+                    let mut dest = treestore.create_tree().await.unwrap();
+
+                    assert_eq!(
+                        num_bytes - 3,
+                        dest.clone_range_from(&mut source, 3, 1, num_bytes)
+                            .await
+                            .unwrap()
+                    );
+
+                    let mut expected_dest_data = vec![0; 1];
+                    expected_dest_data.extend_from_slice(&source_data[3..]);
+                    assert_eq!(expected_dest_data, dest.read_all().await.unwrap().as_ref());
+                    source.async_drop().await.unwrap();
+                    dest.async_drop().await.unwrap();
+                })
+            })
+            .await
+        });
+    }
+
+    #[test]
+    fn cloning_a_sparse_range() {
+        run_tokio_test!({
+            with_treestore_and_nodestore(|treestore, nodestore| {
+                Box::pin(async move {
+                    let layout = *nodestore.layout();
+                    let leaf_size = layout.max_bytes_per_leaf() as u64;
+                    let num_bytes = NUM_LEAVES * leaf_size;
+                    let mut source = treestore.create_tree().await.unwrap();
+                    source.resize_num_bytes(num_bytes).await.unwrap();
+                    source.write_bytes(&[1; 10], 50 * leaf_size).await.unwrap();
+                    let mut dest = treestore.create_tree().await.unwrap();
+                    dest.write_bytes(&data(num_bytes, 2), 0).await.unwrap();
+
+                    dest.clone_range_from(&mut source, 0, 0, num_bytes)
+                        .await
+                        .unwrap();
+
+                    assert_eq!(
+                        source.read_all().await.unwrap(),
+                        dest.read_all().await.unwrap()
+                    );
+                    // The first leaf isn't a hole because the tree started out with it
+                    assert_eq!(
+                        Some(50 * leaf_size),
+                        dest.seek_data(leaf_size).await.unwrap()
+                    );
+                    source.async_drop().await.unwrap();
+                    dest.async_drop().await.unwrap();
+                })
+            })
+            .await
+        });
+    }
+
+    #[test]
+    fn cloning_beyond_the_end_of_the_source() {
+        run_tokio_test!({
+            with_treestore_and_nodestore(|treestore, nodestore| {
+                Box::pin(async move {
+                    let layout = *nodestore.layout();
+                    let num_bytes = NUM_LEAVES * layout.max_bytes_per_leaf() as u64;
+                    let mut source = treestore.create_tree().await.unwrap();
+                    source.write_bytes(&data(num_bytes, 1), 0).await.unwrap();
+                    let mut dest = treestore.create_tree().await.unwrap();
+
+                    assert_eq!(
+                        0,
+                        dest.clone_range_from(&mut source, num_bytes, 0, 100)
+                            .await
+                            .unwrap()
+                    );
+                    assert_eq!(0, dest.num_bytes().await.unwrap());
+                    source.async_drop().await.unwrap();
+                    dest.async_drop().await.unwrap();
+                })
+            })
+            .await
+        });
+    }
+
+    #[test]
+    fn cloning_from_a_tree_multiple_times() {
+        run_tokio_test!({
+            with_treestore_and_nodestore(|treestore, nodestore| {
+                Box::pin(async move {
+                    let layout = *nodestore.layout();
+                    let num_bytes = NUM_LEAVES * layout.max_bytes_per_leaf() as u64;
+                    let source_data = data(num_bytes, 1);
+                    let mut source = treestore.create_tree().await.unwrap();
+                    source.write_bytes(&source_data, 0).await.unwrap();
+                    let mut clones = Vec::new();
+                    for _ in 0..3 {
+                        let mut clone = treestore.create_tree().await.unwrap();
+                        clone
+                            .clone_range_from(&mut source, 0, 0, num_bytes)
+                            .await
+                            .unwrap();
+                        clones.push(clone);
+                    }
+                    // A clone of a clone
+                    let mut clone = treestore.create_tree().await.unwrap();
+                    clone
+                        .clone_range_from(&mut clones[0], 0, 0, num_bytes)
+                        .await
+                        .unwrap();
+                    clones.push(clone);
+
+                    DataTree::remove(source).await.unwrap();
+                    for clone in &mut clones {
+                        assert_eq!(source_data, clone.read_all().await.unwrap().as_ref());
+                    }
+                    for clone in clones {
+                        DataTree::remove(clone).await.unwrap();
+                    }
+                    treestore.clear_cache_slow().await.unwrap();
+                    assert_eq!(0, nodestore.num_nodes().await.unwrap());
+                })
+            })
+            .await
+        });
+    }
+
+    #[test]
+    fn cloning_more_often_than_the_reference_count_can_hold() {
+        run_tokio_test!({
+            with_treestore_and_nodestore(|treestore, nodestore| {
+                Box::pin(async move {
+                    let layout = *nodestore.layout();
+                    let num_bytes = 20 * layout.max_bytes_per_leaf() as u64;
+                    let source_data = data(num_bytes, 1);
+                    let mut source = treestore.create_tree().await.unwrap();
+                    source.write_bytes(&source_data, 0).await.unwrap();
+                    let mut clones = Vec::new();
+                    for _ in 0..300 {
+                        let mut clone = treestore.create_tree().await.unwrap();
+                        clone
+                            .clone_range_from(&mut source, 0, 0, num_bytes)
+                            .await
+                            .unwrap();
+                        clones.push(clone);
+                    }
+
+                    for clone in clones.iter_mut().step_by(50) {
+                        assert_eq!(source_data, clone.read_all().await.unwrap().as_ref());
+                    }
+                    DataTree::remove(source).await.unwrap();
+                    for clone in clones {
+                        DataTree::remove(clone).await.unwrap();
+                    }
+                    treestore.clear_cache_slow().await.unwrap();
+                    assert_eq!(0, nodestore.num_nodes().await.unwrap());
+                })
+            })
+            .await
+        });
+    }
+}
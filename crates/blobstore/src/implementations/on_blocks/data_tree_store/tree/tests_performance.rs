@@ -71,7 +71,8 @@ mod testutils {
         blockstore: &LLSharedBlockStore<LLTrackingBlockStore<InMemoryBlockStore>>,
     ) -> BlockId {
         let mut tree = treestore.create_tree().await.unwrap();
-        tree.resize_num_bytes(NUM_LEAVES * LAYOUT.max_bytes_per_leaf() as u64)
+        // Write data instead of resizing, because resizing would only create a sparse tree
+        tree.write_bytes(&vec![1; NUM_BYTES as usize], 0)
             .await
             .unwrap();
         let id = *tree.root_node_id();
@@ -137,49 +138,38 @@ mod testutils {
         num_inner_nodes
     }
 
-    pub fn num_nodes_written_when_growing_tree(old_num_leaves: u64, new_num_leaves: u64) -> u64 {
-        assert!(new_num_leaves >= old_num_leaves);
-        let mut num_nodes_written = 0;
-        let mut old_num_nodes_current_level = old_num_leaves;
-        let mut new_num_nodes_current_level = new_num_leaves;
-        for _ in 0..DEPTH {
-            assert!(new_num_nodes_current_level >= old_num_nodes_current_level);
-            num_nodes_written += new_num_nodes_current_level - old_num_nodes_current_level;
-            old_num_nodes_current_level = DivCeil::div_ceil(
-                old_num_nodes_current_level,
+    /// Number of nodes that need to be created when writing leaves `begin_leaf..end_leaf` beyond the end of a tree
+    /// with `old_num_leaves` leaves. Leaves between the old end and `begin_leaf` become holes and don't create any nodes.
+    pub fn num_nodes_created_when_writing_beyond_end(
+        old_num_leaves: u64,
+        begin_leaf: u64,
+        end_leaf: u64,
+    ) -> u64 {
+        assert!(old_num_leaves <= begin_leaf && begin_leaf < end_leaf);
+        let mut num_nodes_created = 0;
+        let mut existing_num_nodes_current_level = old_num_leaves;
+        let mut begin_current_level = begin_leaf;
+        let mut end_current_level = end_leaf;
+        // Go up level by level until we reach the level of the new root, which is written in place of the old root
+        while end_current_level > 1 {
+            num_nodes_created +=
+                end_current_level - begin_current_level.max(existing_num_nodes_current_level);
+            if existing_num_nodes_current_level == 1 {
+                // The tree grows by one level. The first node on this level is newly created
+                // and takes over the data of the old root (or is a new parent of it).
+                num_nodes_created += 1;
+            }
+            existing_num_nodes_current_level = DivCeil::div_ceil(
+                existing_num_nodes_current_level,
                 LAYOUT.max_children_per_inner_node() as u64,
             );
-            new_num_nodes_current_level = DivCeil::div_ceil(
-                new_num_nodes_current_level,
+            begin_current_level /= LAYOUT.max_children_per_inner_node() as u64;
+            end_current_level = DivCeil::div_ceil(
+                end_current_level,
                 LAYOUT.max_children_per_inner_node() as u64,
             );
         }
-        assert_eq!(
-            1, old_num_nodes_current_level,
-            "Root level should only have one node"
-        );
-        while new_num_nodes_current_level > 1 {
-            // We need to grow the tree size by one level.
-            // Precondition: we already created one node on the current level for the old tree 9or there was one preexisting in the old tree)
-            let old_num_nodes_current_level = 1;
-            num_nodes_written += new_num_nodes_current_level - old_num_nodes_current_level;
-            // We also need to add a node one level above ourselves to create the new root
-            // and because the next loop iteration assumes that the "old" tree already has a node there
-            // Because the blob it has to remain the same, this new root actually is written to the
-            // current root, and a new node is created with the previous data of the current root.
-            // This means we have to write 2 additional nodes.
-            num_nodes_written += 2;
-            new_num_nodes_current_level = DivCeil::div_ceil(
-                new_num_nodes_current_level,
-                LAYOUT.max_children_per_inner_node() as u64,
-            );
-            // Postcondition: All nodes on the current level were created plus a new root node on the level one above
-        }
-        assert_eq!(
-            1, new_num_nodes_current_level,
-            "Root level should only have one node"
-        );
-        num_nodes_written
+        num_nodes_created
     }
 }
 
@@ -236,18 +226,18 @@ mod num_nodes {
                     blockstore.get_and_reset_counts(),
                 );
 
-                // The tree has `DEPTH+1` right border nodes. A call to num_nodes() should look up `DEPTH-1` nodes,
-                // because the root node is already loaded and, as opposed to num_bytes, the leaf doesn't need to be loaded.
+                // Because of holes, num_nodes() can't compute the number of nodes from the right border anymore.
+                // It should look up all inner nodes except for the already loaded root node, but it shouldn't load any leaves.
                 assert_eq!(NUM_NODES, tree.num_nodes().await.unwrap());
                 assert_eq!(
                     LLActionCounts {
-                        load: (DEPTH - 1) as u32,
+                        load: (NUM_NODES - NUM_LEAVES - 1) as u32,
                         ..LLActionCounts::ZERO
                     },
                     blockstore.get_and_reset_counts(),
                 );
 
-                // Calling num_nodes() again shouldn't load any more nodes, it's now cached.
+                // Calling num_nodes() again shouldn't load any more nodes, they're now cached.
                 assert_eq!(NUM_NODES, tree.num_nodes().await.unwrap());
                 assert_eq!(
                     LLActionCounts {
@@ -353,12 +343,12 @@ mod num_bytes {
                     blockstore.get_and_reset_counts(),
                 );
 
-                // The tree has `DEPTH+1` right border nodes. A call to num_nodes() should look up `DEPTH-1` nodes,
-                // because the root node is already loaded and, as opposed to num_bytes, the leaf doesn't need to be loaded.
+                // Because of holes, num_nodes() can't compute the number of nodes from the right border anymore.
+                // It should look up all inner nodes except for the already loaded root node, but it shouldn't load any leaves.
                 assert_eq!(NUM_NODES, tree.num_nodes().await.unwrap());
                 assert_eq!(
                     LLActionCounts {
-                        load: (DEPTH - 1) as u32,
+                        load: (NUM_NODES - NUM_LEAVES - 1) as u32,
                         ..LLActionCounts::ZERO
                     },
                     blockstore.get_and_reset_counts(),
@@ -1066,7 +1056,11 @@ mod write_bytes {
                 // That means reading the leaf should load `DEPTH` nodes.
                 assert_eq!(
                     LLActionCounts {
-                        exists: 84, // TODO Why do we need these exist and can we calculate this number based on the tree structure?
+                        exists: num_nodes_created_when_writing_beyond_end(
+                            NUM_LEAVES,
+                            WRITTEN_LEAF_INDEX as u64,
+                            WRITTEN_LEAF_INDEX as u64 + 1,
+                        ) as u32,
                         load: DEPTH as u32,
                         ..LLActionCounts::ZERO
                     },
@@ -1077,12 +1071,14 @@ mod write_bytes {
                 tree.async_drop().await.unwrap();
                 std::mem::drop(tree);
                 treestore.clear_cache_slow().await.unwrap();
-                let expected_stored =
-                    num_nodes_written_when_growing_tree(NUM_LEAVES, WRITTEN_LEAF_INDEX as u64 + 1)
-                        as u32;
+                let expected_stored = num_nodes_created_when_writing_beyond_end(
+                    NUM_LEAVES,
+                    WRITTEN_LEAF_INDEX as u64,
+                    WRITTEN_LEAF_INDEX as u64 + 1,
+                ) as u32;
                 assert_eq!(
                     LLActionCounts {
-                        store: expected_stored + 3, // TODO Why + 3 ?
+                        store: expected_stored + 1 + 3, // +1 for the root, which is overwritten in place. TODO Why + 3 ?
                         ..LLActionCounts::ZERO
                     },
                     blockstore.get_and_reset_counts(),
@@ -1113,7 +1109,11 @@ mod write_bytes {
                 let expected_loaded = DEPTH as u32;
                 assert_eq!(
                     LLActionCounts {
-                        exists: 84, // TODO Why do we need these exist and can we calculate this number based on the tree structure?
+                        exists: num_nodes_created_when_writing_beyond_end(
+                            NUM_LEAVES,
+                            WRITTEN_LEAF_INDEX as u64,
+                            WRITTEN_LEAF_INDEX as u64 + 1,
+                        ) as u32,
                         load: expected_loaded,
                         ..LLActionCounts::ZERO
                     },
@@ -1124,12 +1124,14 @@ mod write_bytes {
                 tree.async_drop().await.unwrap();
                 std::mem::drop(tree);
                 treestore.clear_cache_slow().await.unwrap();
-                let expected_stored =
-                    num_nodes_written_when_growing_tree(NUM_LEAVES, WRITTEN_LEAF_INDEX as u64 + 1)
-                        as u32;
+                let expected_stored = num_nodes_created_when_writing_beyond_end(
+                    NUM_LEAVES,
+                    WRITTEN_LEAF_INDEX as u64,
+                    WRITTEN_LEAF_INDEX as u64 + 1,
+                ) as u32;
                 assert_eq!(
                     LLActionCounts {
-                        store: expected_stored + 3, // TODO Why + 3 ?
+                        store: expected_stored + 1 + 3, // +1 for the root, which is overwritten in place. TODO Why + 3 ?
                         ..LLActionCounts::ZERO
                     },
                     blockstore.get_and_reset_counts(),
@@ -1167,7 +1169,11 @@ mod write_bytes {
                 // TODO For some reason, we actually need to load `DEPTH` nodes. Maybe we do load the leaf. Fix this.
                 assert_eq!(
                     LLActionCounts {
-                        exists: 84, // TODO Why do we need these exist and can we calculate this number based on the tree structure?
+                        exists: num_nodes_created_when_writing_beyond_end(
+                            NUM_LEAVES,
+                            WRITTEN_LEAF_INDEX as u64,
+                            WRITTEN_LEAF_INDEX as u64 + 1,
+                        ) as u32,
                         load: DEPTH as u32,
                         ..LLActionCounts::ZERO
                     },
@@ -1178,12 +1184,14 @@ mod write_bytes {
                 tree.async_drop().await.unwrap();
                 std::mem::drop(tree);
                 treestore.clear_cache_slow().await.unwrap();
-                let expected_stored =
-                    num_nodes_written_when_growing_tree(NUM_LEAVES, WRITTEN_LEAF_INDEX as u64 + 1)
-                        as u32;
+                let expected_stored = num_nodes_created_when_writing_beyond_end(
+                    NUM_LEAVES,
+                    WRITTEN_LEAF_INDEX as u64,
+                    WRITTEN_LEAF_INDEX as u64 + 1,
+                ) as u32;
                 assert_eq!(
                     LLActionCounts {
-                        store: expected_stored + 3, // TODO Why + 3 ?
+                        store: expected_stored + 1 + 3, // +1 for the root, which is overwritten in place. TODO Why + 3 ?
                         ..LLActionCounts::ZERO
                     },
                     blockstore.get_and_reset_counts(),
@@ -1219,7 +1227,11 @@ mod write_bytes {
                 let expected_loaded = DEPTH as u32;
                 assert_eq!(
                     LLActionCounts {
-                        exists: 84, // TODO Why do we need these exist and can we calculate this number based on the tree structure?
+                        exists: num_nodes_created_when_writing_beyond_end(
+                            NUM_LEAVES,
+                            WRITTEN_LEAF_INDEX as u64,
+                            WRITTEN_LEAF_INDEX as u64 + 1,
+                        ) as u32,
                         load: expected_loaded,
                         ..LLActionCounts::ZERO
                     },
@@ -1230,12 +1242,14 @@ mod write_bytes {
                 tree.async_drop().await.unwrap();
                 std::mem::drop(tree);
                 treestore.clear_cache_slow().await.unwrap();
-                let expected_stored =
-                    num_nodes_written_when_growing_tree(NUM_LEAVES, WRITTEN_LEAF_INDEX as u64 + 1)
-                        as u32;
+                let expected_stored = num_nodes_created_when_writing_beyond_end(
+                    NUM_LEAVES,
+                    WRITTEN_LEAF_INDEX as u64,
+                    WRITTEN_LEAF_INDEX as u64 + 1,
+                ) as u32;
                 assert_eq!(
                     LLActionCounts {
-                        store: expected_stored + 3, // TODO Why + 3 ?
+                        store: expected_stored + 1 + 3, // +1 for the root, which is overwritten in place. TODO Why + 3 ?
                         ..LLActionCounts::ZERO
                     },
                     blockstore.get_and_reset_counts(),
@@ -1276,7 +1290,11 @@ mod write_bytes {
                 let expected_loaded = DEPTH as u32;
                 assert_eq!(
                     LLActionCounts {
-                        exists: 103, // TODO Why do we need these exist and can we calculate this number based on the tree structure?
+                        exists: num_nodes_created_when_writing_beyond_end(
+                            NUM_LEAVES,
+                            FIRST_ACCESSED_LEAF,
+                            FIRST_ACCESSED_LEAF + NUM_ACCESSED_LEAVES,
+                        ) as u32,
                         load: expected_loaded,
                         ..LLActionCounts::ZERO
                     },
@@ -1287,13 +1305,14 @@ mod write_bytes {
                 tree.async_drop().await.unwrap();
                 std::mem::drop(tree);
                 treestore.clear_cache_slow().await.unwrap();
-                let expected_stored = num_nodes_written_when_growing_tree(
+                let expected_stored = num_nodes_created_when_writing_beyond_end(
                     NUM_LEAVES,
+                    FIRST_ACCESSED_LEAF,
                     FIRST_ACCESSED_LEAF + NUM_ACCESSED_LEAVES,
                 ) as u32;
                 assert_eq!(
                     LLActionCounts {
-                        store: expected_stored + 3, // TODO Why +3?
+                        store: expected_stored + 1 + 3, // +1 for the root, which is overwritten in place. TODO Why +3?
                         ..LLActionCounts::ZERO
                     },
                     blockstore.get_and_reset_counts(),
@@ -1331,7 +1350,11 @@ mod write_bytes {
                 let expected_loaded = DEPTH as u32;
                 assert_eq!(
                     LLActionCounts {
-                        exists: 103, // TODO Why do we need these exist and can we calculate this number based on the tree structure?
+                        exists: num_nodes_created_when_writing_beyond_end(
+                            NUM_LEAVES,
+                            FIRST_ACCESSED_LEAF,
+                            FIRST_ACCESSED_LEAF + NUM_ACCESSED_LEAVES,
+                        ) as u32,
                         load: expected_loaded,
                         ..LLActionCounts::ZERO
                     },
@@ -1342,13 +1365,14 @@ mod write_bytes {
                 tree.async_drop().await.unwrap();
                 std::mem::drop(tree);
                 treestore.clear_cache_slow().await.unwrap();
-                let expected_stored = num_nodes_written_when_growing_tree(
+                let expected_stored = num_nodes_created_when_writing_beyond_end(
                     NUM_LEAVES,
+                    FIRST_ACCESSED_LEAF,
                     FIRST_ACCESSED_LEAF + NUM_ACCESSED_LEAVES,
                 ) as u32;
                 assert_eq!(
                     LLActionCounts {
-                        store: expected_stored + 3, // TODO Why +3?
+                        store: expected_stored + 1 + 3, // +1 for the root, which is overwritten in place. TODO Why +3?
                         ..LLActionCounts::ZERO
                     },
                     blockstore.get_and_reset_counts(),
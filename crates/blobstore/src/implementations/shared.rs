@@ -43,6 +43,10 @@ impl<B: BlobStore + Send + Sync + Debug + AsyncDrop> BlobStore for AsyncDropArc<
         self.deref().flush_if_cached(blob_id).await
     }
 
+    async fn sync(&self) -> Result<()> {
+        self.deref().sync().await
+    }
+
     #[cfg(any(test, feature = "testutils"))]
     async fn clear_cache_slow(&self) -> Result<()> {
         self.deref().clear_cache_slow().await
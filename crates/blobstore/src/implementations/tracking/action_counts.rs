@@ -9,6 +9,10 @@ pub struct BlobStoreActionCounts {
     pub blob_read: u32,
     pub blob_try_read: u32,
     pub blob_write: u32,
+    pub blob_punch_hole: u32,
+    pub blob_seek_data: u32,
+    pub blob_seek_hole: u32,
+    pub blob_clone_range_from: u32,
     pub blob_flush: u32,
     pub blob_num_nodes: u32,
     pub blob_remove: u32,
@@ -37,6 +41,10 @@ impl Debug for BlobStoreActionCounts {
         print_field("blob_read", self.blob_read);
         print_field("blob_try_read", self.blob_try_read);
         print_field("blob_write", self.blob_write);
+        print_field("blob_punch_hole", self.blob_punch_hole);
+        print_field("blob_seek_data", self.blob_seek_data);
+        print_field("blob_seek_hole", self.blob_seek_hole);
+        print_field("blob_clone_range_from", self.blob_clone_range_from);
         print_field("blob_flush", self.blob_flush);
         print_field("blob_num_nodes", self.blob_num_nodes);
         print_field("blob_remove", self.blob_remove);
@@ -67,6 +75,10 @@ impl BlobStoreActionCounts {
         blob_read: 0,
         blob_try_read: 0,
         blob_write: 0,
+        blob_punch_hole: 0,
+        blob_seek_data: 0,
+        blob_seek_hole: 0,
+        blob_clone_range_from: 0,
         blob_flush: 0,
         blob_num_nodes: 0,
         blob_remove: 0,
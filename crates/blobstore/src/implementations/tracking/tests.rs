@@ -58,6 +58,10 @@ mod counter_tests {
                 blob_read: 0,
                 blob_try_read: 0,
                 blob_write: 0,
+                blob_punch_hole: 0,
+                blob_seek_data: 0,
+                blob_seek_hole: 0,
+                blob_clone_range_from: 0,
                 blob_flush: 0,
                 blob_num_nodes: 0,
                 blob_remove: 0,
@@ -423,6 +427,112 @@ mod counter_tests {
         store.async_drop().await.unwrap();
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn blob_punch_hole_increases_counter() {
+        let mut fixture = super::TestFixture::new();
+        let mut store = fixture.store().await;
+
+        let mut blob = store.create().await.unwrap();
+        blob.write(&[1, 2, 3], 0).await.unwrap();
+        blob.punch_hole(0, 1).await.unwrap();
+        blob.punch_hole(1, 1).await.unwrap();
+
+        let counts = store.counts();
+        assert_eq!(
+            BlobStoreActionCounts {
+                store_create: 1,
+                blob_write: 1,
+                blob_punch_hole: 2,
+                ..BlobStoreActionCounts::ZERO
+            },
+            counts
+        );
+
+        blob.async_drop().await.unwrap();
+        drop(blob);
+        store.async_drop().await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn blob_seek_data_increases_counter() {
+        let mut fixture = super::TestFixture::new();
+        let mut store = fixture.store().await;
+
+        let mut blob = store.create().await.unwrap();
+        blob.write(&[1, 2, 3], 0).await.unwrap();
+        blob.seek_data(0).await.unwrap();
+        blob.seek_data(1).await.unwrap();
+
+        let counts = store.counts();
+        assert_eq!(
+            BlobStoreActionCounts {
+                store_create: 1,
+                blob_write: 1,
+                blob_seek_data: 2,
+                ..BlobStoreActionCounts::ZERO
+            },
+            counts
+        );
+
+        blob.async_drop().await.unwrap();
+        drop(blob);
+        store.async_drop().await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn blob_seek_hole_increases_counter() {
+        let mut fixture = super::TestFixture::new();
+        let mut store = fixture.store().await;
+
+        let mut blob = store.create().await.unwrap();
+        blob.write(&[1, 2, 3], 0).await.unwrap();
+        blob.seek_hole(0).await.unwrap();
+        blob.seek_hole(1).await.unwrap();
+
+        let counts = store.counts();
+        assert_eq!(
+            BlobStoreActionCounts {
+                store_create: 1,
+                blob_write: 1,
+                blob_seek_hole: 2,
+                ..BlobStoreActionCounts::ZERO
+            },
+            counts
+        );
+
+        blob.async_drop().await.unwrap();
+        drop(blob);
+        store.async_drop().await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn blob_clone_range_from_increases_counter() {
+        let mut fixture = super::TestFixture::new();
+        let mut store = fixture.store().await;
+
+        let mut source = store.create().await.unwrap();
+        let mut blob = store.create().await.unwrap();
+        source.write(&[1, 2, 3], 0).await.unwrap();
+        blob.clone_range_from(&mut source, 0, 0, 3).await.unwrap();
+
+        let counts = store.counts();
+        assert_eq!(
+            BlobStoreActionCounts {
+                store_create: 2,
+                blob_write: 1,
+                blob_clone_range_from: 1,
+                ..BlobStoreActionCounts::ZERO
+            },
+            counts
+        );
+
+        source.async_drop().await.unwrap();
+        drop(source);
+        blob.async_drop().await.unwrap();
+        drop(blob);
+        store.async_drop().await.unwrap();
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn blob_flush_increases_counter() {
         let mut fixture = super::TestFixture::new();
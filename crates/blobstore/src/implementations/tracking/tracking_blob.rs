@@ -74,6 +74,34 @@ where
         self.blob.write(source, offset).await
     }
 
+    async fn punch_hole(&mut self, offset: u64, len: u64) -> Result<()> {
+        self.counts.lock().unwrap().blob_punch_hole += 1;
+        self.blob.punch_hole(offset, len).await
+    }
+
+    async fn seek_data(&mut self, offset: u64) -> Result<Option<u64>> {
+        self.counts.lock().unwrap().blob_seek_data += 1;
+        self.blob.seek_data(offset).await
+    }
+
+    async fn seek_hole(&mut self, offset: u64) -> Result<Option<u64>> {
+        self.counts.lock().unwrap().blob_seek_hole += 1;
+        self.blob.seek_hole(offset).await
+    }
+
+    async fn clone_range_from(
+        &mut self,
+        source: &mut Self,
+        source_offset: u64,
+        offset: u64,
+        len: u64,
+    ) -> Result<u64> {
+        self.counts.lock().unwrap().blob_clone_range_from += 1;
+        self.blob
+            .clone_range_from(&mut source.blob, source_offset, offset, len)
+            .await
+    }
+
     async fn flush(&mut self) -> Result<()> {
         self.counts.lock().unwrap().blob_flush += 1;
         self.blob.flush().await
@@ -26,6 +26,24 @@ pub trait Blob: Sized + Debug + AsyncDrop {
     async fn try_read(&mut self, target: &mut [u8], offset: u64) -> Result<usize>;
     async fn write(&mut self, source: &[u8], offset: u64) -> Result<()>;
 
+    /// Deallocate `[offset, offset + len)` without changing the size of the blob. The range reads as zeroes afterwards.
+    async fn punch_hole(&mut self, offset: u64, len: u64) -> Result<()>;
+    /// First offset at or after `offset` that contains data, or `None` if `offset` is at or beyond the end of the blob.
+    async fn seek_data(&mut self, offset: u64) -> Result<Option<u64>>;
+    /// First offset at or after `offset` that is in a hole, or `None` if `offset` is at or beyond the end of the blob.
+    /// The end of the blob counts as a hole.
+    async fn seek_hole(&mut self, offset: u64) -> Result<Option<u64>>;
+    /// Copy `len` bytes from `source` at `source_offset` into this blob at `offset`, growing this blob if necessary.
+    /// Where possible, the data is shared with `source` instead of being copied. Later writes to either blob don't affect the other one.
+    /// Returns the number of bytes copied, which is less than `len` if `source` ends before `source_offset + len`.
+    async fn clone_range_from(
+        &mut self,
+        source: &mut Self,
+        source_offset: u64,
+        offset: u64,
+        len: u64,
+    ) -> Result<u64>;
+
     async fn flush(&mut self) -> Result<()>;
 
     // TODO `num_nodes` and `all_blocks` is a leaky abstraction because it gives away that we use blocks. Remove these.
@@ -52,6 +70,10 @@ pub trait BlobStore {
 
     async fn flush_if_cached(&self, blob_id: BlobId) -> Result<()>;
 
+    /// Write barrier, making sure that everything written to the underlying storage so far survives a crash.
+    /// This doesn't flush caches, use [BlobStore::flush_if_cached] or [Blob::flush] first.
+    async fn sync(&self) -> Result<()>;
+
     #[cfg(any(test, feature = "testutils"))]
     async fn clear_cache_slow(&self) -> Result<()>;
     #[cfg(any(test, feature = "testutils"))]
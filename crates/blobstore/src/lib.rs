@@ -12,7 +12,7 @@ pub use interface::{BLOBID_LEN, Blob, BlobStore};
 mod implementations;
 pub use implementations::{
     BlobOnBlocks, BlobStoreOnBlocks, DataInnerNode, DataLeafNode, DataNode, DataNodeStore,
-    DataTree, DataTreeStore, LoadNodeError,
+    DataTree, DataTreeStore, HOLE_BLOCK_ID, LoadNodeError,
 };
 #[cfg(any(test, feature = "testutils"))]
 pub use implementations::{BlobStoreActionCounts, TrackingBlobStore};
@@ -168,7 +168,12 @@ where
     }
 }
 
-impl<B> LLBlockStore for BlockStoreAdapter<B> where
-    B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static
+#[async_trait]
+impl<B> LLBlockStore for BlockStoreAdapter<B>
+where
+    B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
 {
+    async fn sync(&self) -> Result<()> {
+        self.underlying_store.sync().await
+    }
 }
@@ -0,0 +1,24 @@
+use clap::Parser;
+use cryfs_blockstore::RemoteAddress;
+use cryfs_cli_utils::parse_path;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+pub struct BlockServerArgs {
+    /// Directory containing the blocks of the vault.
+    #[arg(value_parser=parse_path)]
+    pub basedir: PathBuf,
+
+    /// Where to listen for clients, e.g. '0.0.0.0:4000', '[::]:4000' or 'unix:/run/cryfs-blockserver.sock'.
+    #[arg(long, value_name = "ADDR", value_parser=parse_address)]
+    pub listen: RemoteAddress,
+
+    /// File containing the secret clients need to know to connect. It must have at least 16 bytes.
+    /// The connection isn't encrypted, but clients only send encrypted blocks.
+    #[arg(long, value_parser=parse_path)]
+    pub secret_file: PathBuf,
+}
+
+fn parse_address(address: &str) -> Result<RemoteAddress, String> {
+    address.parse().map_err(|err| format!("{err:#}"))
+}
@@ -0,0 +1,7 @@
+use std::process::ExitCode;
+
+use cryfs_blockserver::BlockServerCli;
+
+fn main() -> ExitCode {
+    cryfs_cli_utils::run::<BlockServerCli>()
+}
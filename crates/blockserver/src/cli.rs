@@ -0,0 +1,92 @@
+use anyhow::{Result, anyhow};
+
+use clap_logflag::{LogDestination, LogDestinationConfig, LoggingConfig};
+use cryfs_blockstore::{
+    BlockServerListener, BlockServerSecret, FsyncPolicy, OnDiskBlockStore, serve_blocks,
+};
+use cryfs_cli_utils::{Application, CliError, CliErrorKind, CliResultExt, Environment};
+use cryfs_config::CRYFS_VERSION;
+use cryfs_utils::async_drop::AsyncDropGuard;
+use cryfs_version::VersionInfo;
+
+use crate::args::BlockServerArgs;
+
+pub struct BlockServerCli {
+    args: BlockServerArgs,
+}
+
+impl Application for BlockServerCli {
+    type ConcreteArgs = BlockServerArgs;
+    const NAME: &'static str = "cryfs-blockserver";
+    const VERSION: VersionInfo<'static, 'static, &'static str> = CRYFS_VERSION;
+
+    fn new(args: BlockServerArgs, _env: Environment) -> Result<Self, CliError> {
+        Ok(Self { args })
+    }
+
+    fn default_log_config(&self) -> LoggingConfig {
+        LoggingConfig::new(vec![LogDestinationConfig {
+            destination: LogDestination::Stderr,
+            level: None,
+        }])
+    }
+
+    fn main(self) -> Result<(), CliError> {
+        // TODO Runtime settings
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .thread_name(Self::NAME)
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(self.async_main())
+    }
+}
+
+impl BlockServerCli {
+    async fn async_main(self) -> Result<(), CliError> {
+        if !self.args.basedir.is_dir() {
+            return Err(anyhow!(
+                "Vault directory {} doesn't exist or isn't a directory",
+                self.args.basedir.display()
+            ))
+            .map_cli_error(CliErrorKind::InaccessibleVaultDir);
+        }
+        let secret = BlockServerSecret::from_file(&self.args.secret_file)
+            .map_cli_error(CliErrorKind::InvalidArguments)?;
+        let listener = BlockServerListener::bind(&self.args.listen)
+            .await
+            .map_cli_error(CliErrorKind::UnspecifiedError)?;
+        let address = listener
+            .local_address()
+            .map_cli_error(CliErrorKind::UnspecifiedError)?;
+        println!(
+            "Serving blocks from {} on {address}. Press Ctrl+C to stop.",
+            self.args.basedir.display()
+        );
+
+        // Clients call sync when an application calls fsync, so they decide when data has to be on the disk
+        let blockstore = OnDiskBlockStore::new(self.args.basedir.clone(), FsyncPolicy::OnFsync);
+        let result = serve(&blockstore, listener, &secret).await;
+        let drop_result = drop_blockstore(blockstore).await;
+        result
+            .and(drop_result)
+            .map_cli_error(CliErrorKind::UnspecifiedError)
+    }
+}
+
+async fn serve(
+    blockstore: &OnDiskBlockStore,
+    listener: BlockServerListener,
+    secret: &BlockServerSecret,
+) -> Result<()> {
+    serve_blocks(blockstore, listener, secret, async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            log::error!("Failed to wait for Ctrl+C, shutting down: {err:?}");
+        }
+    })
+    .await
+}
+
+async fn drop_blockstore(mut blockstore: AsyncDropGuard<OnDiskBlockStore>) -> Result<()> {
+    blockstore.async_drop().await
+}
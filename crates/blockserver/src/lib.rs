@@ -0,0 +1,12 @@
+//! The `cryfs-blockserver` binary serves the blocks of a vault over the network so that
+//! clients can access them with a `RemoteBlockStore`.
+
+#![forbid(unsafe_code)]
+// TODO #![deny(missing_docs)]
+
+mod args;
+
+mod cli;
+pub use cli::BlockServerCli;
+
+cryfs_version::assert_cargo_version_equals_git_version!();
@@ -249,6 +249,11 @@ impl<B: crate::low_level::LLBlockStore + Send + Sync + Debug + 'static> BlockSto
         self.cache.flush_block(entry, &block_id).await
     }
 
+    async fn sync(&self) -> Result<()> {
+        let base_store = self.base_store.as_ref().expect("Already destructed");
+        base_store.sync().await
+    }
+
     #[cfg(any(test, feature = "testutils"))]
     async fn clear_cache_slow(&self) -> Result<()> {
         self.cache.prune_all_blocks().await
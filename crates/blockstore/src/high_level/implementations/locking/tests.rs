@@ -232,4 +232,33 @@ async fn test_overhead() {
     store.async_drop().await.unwrap();
 }
 
+#[tokio::test]
+async fn test_whenCallingSync_thenPassesThroughToBaseStore() {
+    let mut underlying_store = make_mock_block_store();
+    underlying_store
+        .expect_sync()
+        .once()
+        .returning(|| Box::pin(async { Ok(()) }));
+    let mut store = LockingBlockStore::new(underlying_store);
+
+    store.sync().await.unwrap();
+
+    store.async_drop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_whenCallingSync_butBaseStoreReturnsError_thenReturnsError() {
+    let mut underlying_store = make_mock_block_store();
+    underlying_store
+        .expect_sync()
+        .once()
+        .returning(|| Box::pin(async { Err(anyhow!("Some error")) }));
+    let mut store = LockingBlockStore::new(underlying_store);
+
+    let err = store.sync().await.unwrap_err();
+    assert_eq!("Some error", err.to_string());
+
+    store.async_drop().await.unwrap();
+}
+
 // TODO Test flush_block
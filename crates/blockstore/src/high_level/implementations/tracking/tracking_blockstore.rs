@@ -108,6 +108,10 @@ where
         self.underlying_store.flush_block(block.inner_mut()).await
     }
 
+    async fn sync(&self) -> Result<()> {
+        self.underlying_store.sync().await
+    }
+
     #[cfg(any(test, feature = "testutils"))]
     async fn clear_cache_slow(&self) -> Result<()> {
         self.underlying_store.clear_cache_slow().await
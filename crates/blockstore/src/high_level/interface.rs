@@ -38,6 +38,10 @@ pub trait BlockStore {
     async fn create(&self, data: &Data) -> Result<BlockId>;
     async fn flush_block(&self, block: &mut Self::Block) -> Result<()>;
 
+    /// Write barrier for the underlying low level block store, see [crate::low_level::LLBlockStore::sync].
+    /// This doesn't write dirty blocks from the cache, use [BlockStore::flush_block] on the blocks that should be covered first.
+    async fn sync(&self) -> Result<()>;
+
     /// clear_cache_slow is only used in test cases. Without test cases calling it, they would only
     /// ever test cached blocks and never have to store/reload them to the base store.
     /// This is implemented in a very slow way and shouldn't be used in non-test code.
@@ -13,11 +13,19 @@ pub use high_level::{Block, BlockStore, LockingBlockStore};
 mod low_level;
 pub use low_level::{
     AllowIntegrityViolations, BlockStoreDeleter, BlockStoreReader, BlockStoreWriter, ClientId,
-    CompressingBlockStore, DynBlockStore, EncryptedBlockStore, InMemoryBlockStore,
-    IntegrityBlockStore, IntegrityBlockStoreInitError, IntegrityConfig, IntegrityViolationError,
-    LLBlockStore, MissingBlockIsIntegrityViolation, OnDiskBlockStore, OptimizedBlockStoreWriter,
-    ReadOnlyBlockStore,
+    CompressingBlockStore, CompressionAlgorithm, DynBlockStore, EncryptedBlockStore, FsyncPolicy,
+    InMemoryBlockStore, IntegrityBlockStore, IntegrityBlockStoreInitError, IntegrityConfig,
+    IntegrityViolationError, LLBlockStore, MirroringBlockStore, MissingBlockIsIntegrityViolation,
+    OnDiskBlockStore, OptimizedBlockStoreWriter, PackedBlockStore, PackedBlockStoreConfig,
+    ReadOnlyBlockStore, RebalanceSummary, ResyncSummary, StripedBlockStore,
+    UnknownCompressionAlgorithmError,
 };
+#[cfg(feature = "remote")]
+pub use low_level::{
+    BlockServerListener, BlockServerSecret, RemoteAddress, RemoteBlockStore, serve_blocks,
+};
+#[cfg(feature = "s3")]
+pub use low_level::{S3AddressingStyle, S3BlockStore, S3Config, S3Credentials};
 
 mod overhead;
 pub use overhead::{InvalidBlockSizeError, Overhead};
@@ -5,7 +5,10 @@ use futures::stream::BoxStream;
 
 use crate::{
     BlockId, Overhead, RemoveResult, TryCreateResult,
-    low_level::interface::{BlockStoreDeleter, BlockStoreReader, BlockStoreWriter, LLBlockStore},
+    low_level::interface::{
+        BlockStoreDeleter, BlockStoreReader, LLBlockStore, OptimizedBlockStoreWriter,
+        block_data::{IBlockData, create_block_data_wrapper},
+    },
 };
 use cryfs_utils::async_drop::AsyncDrop;
 use cryfs_utils::data::Data;
@@ -44,15 +47,26 @@ impl BlockStoreReader for DynBlockStore {
     }
 }
 
+create_block_data_wrapper!(BlockData);
+
 #[async_trait]
-impl BlockStoreWriter for DynBlockStore {
-    async fn try_create(&self, id: &BlockId, data: &[u8]) -> Result<TryCreateResult> {
-        let r = (*self.0).try_create(id, data);
+impl OptimizedBlockStoreWriter for DynBlockStore {
+    type BlockData = BlockData;
+
+    fn allocate(size: usize) -> BlockData {
+        // We don't know the underlying store's type here, so it'll have to copy the data if it needs space for headers
+        BlockData::new(Data::from(vec![0; size]))
+    }
+
+    async fn try_create_optimized(&self, id: &BlockId, data: BlockData) -> Result<TryCreateResult> {
+        let data = data.extract();
+        let r = (*self.0).try_create(id, data.as_ref());
         r.await
     }
 
-    async fn store(&self, id: &BlockId, data: &[u8]) -> Result<()> {
-        let r = (*self.0).store(id, data);
+    async fn store_optimized(&self, id: &BlockId, data: BlockData) -> Result<()> {
+        let data = data.extract();
+        let r = (*self.0).store(id, data.as_ref());
         r.await
     }
 }
@@ -76,4 +90,10 @@ impl AsyncDrop for DynBlockStore {
     }
 }
 
-impl LLBlockStore for DynBlockStore {}
+#[async_trait]
+impl LLBlockStore for DynBlockStore {
+    async fn sync(&self) -> Result<()> {
+        let r = (*self.0).sync();
+        r.await
+    }
+}
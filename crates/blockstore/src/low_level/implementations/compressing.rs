@@ -1,14 +1,20 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, anyhow, bail, ensure};
 use async_trait::async_trait;
 use byte_unit::Byte;
+use cryfs_utils::lazy_reclaim::LazyReclaim;
+use cryfs_utils::threadpool::ThreadPool;
 use futures::stream::BoxStream;
-use std::fmt::{self, Debug};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Debug, Display};
+use std::str::FromStr;
+use std::sync::Arc;
+use thiserror::Error;
 
 use crate::{
     BlockId, Overhead,
     low_level::{
-        BlockStoreDeleter, BlockStoreReader, BlockStoreWriter, LLBlockStore,
-        OptimizedBlockStoreWriter, interface::block_data::IBlockData,
+        BlockStoreDeleter, BlockStoreReader, LLBlockStore, OptimizedBlockStoreWriter,
+        interface::block_data::{IBlockData, create_block_data_wrapper},
     },
     utils::{RemoveResult, TryCreateResult},
 };
@@ -18,23 +24,98 @@ use cryfs_utils::{
     data::Data,
 };
 
-/// This block store compresses blocks before storing them. The implementation isn't really
-/// optimized or meant for production code, it's just being used in test cases currently.
+/// A global thread pool for compression in CompressingBlockStore, see the comment on CRYPTO_THREAD_POOL in EncryptedBlockStore.
+static COMPRESSION_THREAD_POOL: LazyReclaim<ThreadPool> = LazyReclaim::new(|| {
+    ThreadPool::new("CompressingBlockStore").expect("Failed to create compression pool")
+});
+
+/// Each block starts with a one byte tag saying how the rest of the block is stored. The tag is per block,
+/// so blocks stored with different algorithms can be mixed and changing the algorithm doesn't require
+/// rewriting existing blocks.
+const TAG_LEN: usize = 1;
+const TAG_UNCOMPRESSED: u8 = 0;
+/// Followed by the uncompressed size as little endian u32, and then the lz4 compressed data
+const TAG_LZ4: u8 = 1;
+/// Followed by a zstd frame
+const TAG_ZSTD: u8 = 2;
+
+const LZ4_SIZE_HEADER_LEN: usize = std::mem::size_of::<u32>();
+
+/// Compression algorithms supported by [CompressingBlockStore]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionAlgorithm {
+    /// Very fast, but doesn't compress as well as zstd
+    Lz4,
+    /// Compresses better than lz4, but is slower
+    Zstd,
+}
+
+impl CompressionAlgorithm {
+    pub const ALL: &[Self] = &[Self::Lz4, Self::Zstd];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Lz4 => "lz4",
+            Self::Zstd => "zstd",
+        }
+    }
+}
+
+impl Display for CompressionAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("Unknown compression algorithm '{name}'")]
+pub struct UnknownCompressionAlgorithmError {
+    pub name: String,
+}
+
+impl FromStr for CompressionAlgorithm {
+    type Err = UnknownCompressionAlgorithmError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .find(|algorithm| algorithm.name() == name)
+            .copied()
+            .ok_or_else(|| UnknownCompressionAlgorithmError {
+                name: name.to_owned(),
+            })
+    }
+}
+
+/// This block store compresses blocks before storing them in the underlying block store.
+///
+/// Compression only helps if it happens before encryption, so this needs to wrap the [EncryptedBlockStore](super::EncryptedBlockStore).
+/// Blocks that don't get smaller when compressed (e.g. because they contain already compressed data) are stored uncompressed,
+/// so the overhead is at most one byte per block.
 pub struct CompressingBlockStore<B: Send + Debug + AsyncDrop<Error = anyhow::Error>> {
     underlying_block_store: AsyncDropGuard<B>,
+    algorithm: CompressionAlgorithm,
+    threadpool: Arc<ThreadPool>,
 }
 
 impl<B: Send + Sync + Debug + AsyncDrop<Error = anyhow::Error>> CompressingBlockStore<B> {
-    pub fn new(underlying_block_store: AsyncDropGuard<B>) -> AsyncDropGuard<Self> {
+    /// `algorithm` is used for newly stored blocks. Blocks stored with a different algorithm can still be loaded.
+    pub fn new(
+        underlying_block_store: AsyncDropGuard<B>,
+        algorithm: CompressionAlgorithm,
+    ) -> AsyncDropGuard<Self> {
         AsyncDropGuard::new(Self {
             underlying_block_store,
+            algorithm,
+            threadpool: COMPRESSION_THREAD_POOL.get_or_init(),
         })
     }
 }
 
 impl<B: Send + Debug + AsyncDrop<Error = anyhow::Error>> Debug for CompressingBlockStore<B> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "CompressingBlockStore")
+        write!(f, "CompressingBlockStore({})", self.algorithm)
     }
 }
 
@@ -50,11 +131,23 @@ impl<B: BlockStoreReader + Sync + Send + Debug + AsyncDrop<Error = anyhow::Error
         let loaded = self.underlying_block_store.load(block_id).await.context(
             "CompressingBlockStore failed to load the block from the underlying block store",
         )?;
-        if let Some(loaded) = loaded {
-            Ok(Some(_decompress(loaded).await?))
-        } else {
-            Ok(None)
+        let Some(mut loaded) = loaded else {
+            return Ok(None);
+        };
+        let Some(&tag) = loaded.first() else {
+            bail!("Couldn't parse compressed block {block_id:?}: Block is empty");
+        };
+        loaded.shrink_to_subregion(TAG_LEN..);
+        if tag == TAG_UNCOMPRESSED {
+            // Avoid the thread pool roundtrip, there's nothing to do
+            return Ok(Some(loaded));
         }
+        let decompressed = self
+            .threadpool
+            .execute_job(move || _decompress(tag, &loaded))
+            .await
+            .with_context(|| format!("Couldn't decompress block {block_id:?}"))?;
+        Ok(Some(decompressed))
     }
 
     async fn num_blocks(&self) -> Result<u64> {
@@ -66,9 +159,9 @@ impl<B: BlockStoreReader + Sync + Send + Debug + AsyncDrop<Error = anyhow::Error
     }
 
     fn overhead(&self) -> Overhead {
-        //We probably have negative overhead since we're compressing, but we don't know exactly how much.
-        //The best we can do is ignore the compression step here.
-        self.underlying_block_store.overhead()
+        // Compression usually makes blocks smaller, but we don't know by how much. The only thing we can guarantee
+        // is the worst case, where a block doesn't compress and gets stored uncompressed with a tag.
+        self.underlying_block_store.overhead() + Overhead::new(Byte::from_u64(TAG_LEN as u64))
     }
 
     async fn all_blocks(&self) -> Result<BoxStream<'static, Result<BlockId>>> {
@@ -85,37 +178,53 @@ impl<B: BlockStoreDeleter + Sync + Send + Debug + AsyncDrop<Error = anyhow::Erro
     }
 }
 
+create_block_data_wrapper!(BlockData);
+
 #[async_trait]
 impl<B: OptimizedBlockStoreWriter + Sync + Send + Debug + AsyncDrop<Error = anyhow::Error>>
     OptimizedBlockStoreWriter for CompressingBlockStore<B>
 {
-    type BlockData = B::BlockData;
+    type BlockData = BlockData;
 
     fn allocate(size: usize) -> Self::BlockData {
-        B::allocate(size)
+        // Reserve space for the tag so that we can store incompressible blocks without copying them
+        let mut data = B::allocate(TAG_LEN + size).extract();
+        data.shrink_to_subregion(TAG_LEN..);
+        BlockData::new(data)
     }
 
     async fn try_create_optimized(
         &self,
         id: &BlockId,
-        data: B::BlockData,
+        data: Self::BlockData,
     ) -> Result<TryCreateResult> {
-        let compressed = _compress(data.extract()).await?;
+        let compressed = self._compress(data.extract()).await?;
         self.underlying_block_store
-            // We cannot use try_create_optimized because we may not have enough prefix bytes available
-            .try_create(id, &compressed)
+            .try_create_optimized(id, compressed)
             .await
     }
 
-    async fn store_optimized(&self, id: &BlockId, data: B::BlockData) -> Result<()> {
-        let compressed = _compress(data.extract()).await?;
+    async fn store_optimized(&self, id: &BlockId, data: Self::BlockData) -> Result<()> {
+        let compressed = self._compress(data.extract()).await?;
         self.underlying_block_store
-            // We cannot use store_optimized because we may not have enough prefix bytes available
-            .store(id, &compressed)
+            .store_optimized(id, compressed)
             .await
     }
 }
 
+impl<B: OptimizedBlockStoreWriter + Sync + Send + Debug + AsyncDrop<Error = anyhow::Error>>
+    CompressingBlockStore<B>
+{
+    async fn _compress(&self, data: Data) -> Result<B::BlockData> {
+        let algorithm = self.algorithm;
+        let compressed = self
+            .threadpool
+            .execute_job(move || _compress::<B>(algorithm, data))
+            .await?;
+        Ok(B::BlockData::new(compressed))
+    }
+}
+
 #[async_trait]
 impl<B: Sync + Send + Debug + AsyncDrop<Error = anyhow::Error>> AsyncDrop
     for CompressingBlockStore<B>
@@ -127,62 +236,140 @@ impl<B: Sync + Send + Debug + AsyncDrop<Error = anyhow::Error>> AsyncDrop
     }
 }
 
+#[async_trait]
 impl<B: LLBlockStore + OptimizedBlockStoreWriter + Sync + Send + Debug> LLBlockStore
     for CompressingBlockStore<B>
 {
+    async fn sync(&self) -> Result<()> {
+        self.underlying_block_store.sync().await
+    }
 }
 
-async fn _decompress(data: Data) -> Result<Data> {
-    // TODO Is a dedicated thread pool better than spawn_blocking? Similar for EncryptedBlockStore.
-    let decompressed = tokio::task::spawn_blocking(move || -> Result<Vec<u8>> {
-        let mut decompressed = Vec::new();
-        lzzzz::lz4f::decompress_to_vec(&data, &mut decompressed)?;
-        Ok(decompressed)
-    })
-    .await??;
-    Ok(decompressed.into())
+/// Returns the data to store, with enough prefix bytes available for `B`
+fn _compress<B: OptimizedBlockStoreWriter>(
+    algorithm: CompressionAlgorithm,
+    mut data: Data,
+) -> Result<Data> {
+    let (tag, header_len) = match algorithm {
+        CompressionAlgorithm::Lz4 => (TAG_LZ4, TAG_LEN + LZ4_SIZE_HEADER_LEN),
+        CompressionAlgorithm::Zstd => (TAG_ZSTD, TAG_LEN),
+    };
+    // Only give the compressor as much space as the uncompressed block would need. If the compressed data
+    // doesn't fit, compression fails and we store the block uncompressed. If it does fit, it's smaller than the
+    // uncompressed block because the uncompressed block has a tag as well.
+    if let Some(available) = data.len().checked_sub(header_len) {
+        let mut compressed = B::allocate(header_len + available).extract();
+        let compressed_len = match algorithm {
+            CompressionAlgorithm::Lz4 => lzzzz::lz4::compress(
+                &data,
+                &mut compressed[header_len..],
+                lzzzz::lz4::ACC_LEVEL_DEFAULT,
+            )
+            .ok(),
+            CompressionAlgorithm::Zstd => zstd::bulk::compress_to_buffer(
+                &data,
+                &mut compressed[header_len..],
+                zstd::DEFAULT_COMPRESSION_LEVEL,
+            )
+            .ok(),
+        };
+        if let Some(compressed_len) = compressed_len {
+            compressed[0] = tag;
+            if tag == TAG_LZ4 {
+                let uncompressed_len = u32::try_from(data.len())
+                    .context("Block is too large to be compressed with lz4")?;
+                compressed[TAG_LEN..header_len].copy_from_slice(&uncompressed_len.to_le_bytes());
+            }
+            compressed.shrink_to_subregion(..header_len + compressed_len);
+            return Ok(compressed);
+        }
+    }
+
+    data.grow_region_fail_if_reallocation_necessary(TAG_LEN, 0)
+        .expect("Tried to grow the data to contain the tag in CompressingBlockStore::_compress");
+    data[0] = TAG_UNCOMPRESSED;
+    Ok(data)
 }
 
-async fn _compress(data: Data) -> Result<Data> {
-    // TODO Is a dedicated thread pool better than spawn_blocking? Similar for EncryptedBlockStore.
-    let compressed = tokio::task::spawn_blocking(move || -> Result<Vec<u8>> {
-        let prefs = lzzzz::lz4f::Preferences::default();
-        let mut compressed = Vec::new();
-        lzzzz::lz4f::compress_to_vec(&data, &mut compressed, &prefs)?;
-        Ok(compressed)
-    })
-    .await??;
-    Ok(compressed.into())
+fn _decompress(tag: u8, data: &[u8]) -> Result<Data> {
+    match tag {
+        TAG_LZ4 => {
+            ensure!(
+                data.len() >= LZ4_SIZE_HEADER_LEN,
+                "lz4 compressed block is missing its size header"
+            );
+            let (size_header, compressed) = data.split_at(LZ4_SIZE_HEADER_LEN);
+            let uncompressed_len =
+                u32::from_le_bytes(size_header.try_into().expect("Wrong header size")) as usize;
+            let mut decompressed = vec![0; uncompressed_len];
+            let actual_len = lzzzz::lz4::decompress(compressed, &mut decompressed)?;
+            ensure!(
+                actual_len == uncompressed_len,
+                "lz4 compressed block decompressed to {actual_len} bytes but the header says {uncompressed_len} bytes"
+            );
+            Ok(decompressed.into())
+        }
+        TAG_ZSTD => Ok(zstd::stream::decode_all(data)?.into()),
+        _ => Err(anyhow!("Unknown compression tag {tag}")),
+    }
 }
 
 #[cfg(test)]
-mod generic_tests {
+mod tests {
     use super::*;
-    use crate::low_level::InMemoryBlockStore;
+    use crate::low_level::{BlockStoreWriter, InMemoryBlockStore, SharedBlockStore};
     use crate::tests::low_level::LLFixture;
+    use crate::tests::utils::{blockid, data};
+    use std::marker::PhantomData;
 
-    use crate::instantiate_blockstore_tests_for_lowlevel_blockstore;
+    trait Algorithm: Send + Sync {
+        const ALGORITHM: CompressionAlgorithm;
+    }
+    struct Lz4;
+    impl Algorithm for Lz4 {
+        const ALGORITHM: CompressionAlgorithm = CompressionAlgorithm::Lz4;
+    }
+    struct Zstd;
+    impl Algorithm for Zstd {
+        const ALGORITHM: CompressionAlgorithm = CompressionAlgorithm::Zstd;
+    }
 
-    struct TestFixture {}
+    struct TestFixture<A: Algorithm> {
+        _a: PhantomData<A>,
+    }
     #[async_trait]
-    impl LLFixture for TestFixture {
+    impl<A: Algorithm> LLFixture for TestFixture<A> {
         type ConcreteBlockStore = CompressingBlockStore<InMemoryBlockStore>;
         fn new() -> Self {
-            Self {}
+            Self { _a: PhantomData }
         }
         async fn store(&mut self) -> AsyncDropGuard<Self::ConcreteBlockStore> {
-            CompressingBlockStore::new(InMemoryBlockStore::new())
+            CompressingBlockStore::new(InMemoryBlockStore::new(), A::ALGORITHM)
         }
         async fn yield_fixture(&self, _store: &Self::ConcreteBlockStore) {}
     }
 
-    instantiate_blockstore_tests_for_lowlevel_blockstore!(TestFixture, (flavor = "multi_thread"));
+    mod lz4 {
+        use super::*;
+        crate::instantiate_blockstore_tests_for_lowlevel_blockstore!(
+            super::TestFixture<Lz4>,
+            (flavor = "multi_thread")
+        );
+    }
+
+    mod zstd {
+        use super::*;
+        crate::instantiate_blockstore_tests_for_lowlevel_blockstore!(
+            super::TestFixture<Zstd>,
+            (flavor = "multi_thread")
+        );
+    }
 
     #[tokio::test]
     async fn test_usable_block_size_from_physical_block_size() {
-        let mut fixture = TestFixture::new();
+        let mut fixture = TestFixture::<Zstd>::new();
         let mut store = fixture.store().await;
-        let expected_overhead = Byte::from_u64(0);
+        let expected_overhead = Byte::from_u64(TAG_LEN as u64);
 
         assert_eq!(
             0u64,
@@ -203,4 +390,141 @@ mod generic_tests {
 
         store.async_drop().await.unwrap();
     }
+
+    fn compressible_data(size: usize) -> Data {
+        (0..size)
+            .map(|i| b"some compressible text "[i % 23])
+            .collect::<Vec<u8>>()
+            .into()
+    }
+
+    async fn _store(
+        underlying: &AsyncDropGuard<SharedBlockStore<InMemoryBlockStore>>,
+        algorithm: CompressionAlgorithm,
+        block_id: &BlockId,
+        data: &Data,
+    ) {
+        let mut store = CompressingBlockStore::new(SharedBlockStore::clone(underlying), algorithm);
+        store.store(block_id, data).await.unwrap();
+        store.async_drop().await.unwrap();
+    }
+
+    async fn _load(
+        underlying: &AsyncDropGuard<SharedBlockStore<InMemoryBlockStore>>,
+        algorithm: CompressionAlgorithm,
+        block_id: &BlockId,
+    ) -> Result<Option<Data>> {
+        let mut store = CompressingBlockStore::new(SharedBlockStore::clone(underlying), algorithm);
+        let result = store.load(block_id).await;
+        store.async_drop().await.unwrap();
+        result
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_compressible_block_gets_compressed() {
+        for &algorithm in CompressionAlgorithm::ALL {
+            let mut underlying = SharedBlockStore::new(InMemoryBlockStore::new());
+            _store(
+                &underlying,
+                algorithm,
+                &blockid(0),
+                &compressible_data(16 * 1024),
+            )
+            .await;
+
+            let stored = underlying.load(&blockid(0)).await.unwrap().unwrap();
+            assert!(stored.len() < 1024, "{algorithm}: {} bytes", stored.len());
+            assert_ne!(TAG_UNCOMPRESSED, stored[0]);
+            assert_eq!(
+                Some(compressible_data(16 * 1024)),
+                _load(&underlying, algorithm, &blockid(0)).await.unwrap()
+            );
+
+            underlying.async_drop().await.unwrap();
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_incompressible_block_gets_stored_uncompressed() {
+        for &algorithm in CompressionAlgorithm::ALL {
+            for size in [0, 1, 5, 1024] {
+                let mut underlying = SharedBlockStore::new(InMemoryBlockStore::new());
+                // Random data doesn't compress
+                _store(&underlying, algorithm, &blockid(0), &data(size, 0)).await;
+
+                let stored = underlying.load(&blockid(0)).await.unwrap().unwrap();
+                assert_eq!(TAG_LEN + size, stored.len());
+                assert_eq!(TAG_UNCOMPRESSED, stored[0]);
+                assert_eq!(&data(size, 0)[..], &stored[TAG_LEN..]);
+                assert_eq!(
+                    Some(data(size, 0)),
+                    _load(&underlying, algorithm, &blockid(0)).await.unwrap()
+                );
+
+                underlying.async_drop().await.unwrap();
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_loading_blocks_stored_with_a_different_algorithm() {
+        let mut underlying = SharedBlockStore::new(InMemoryBlockStore::new());
+        _store(
+            &underlying,
+            CompressionAlgorithm::Lz4,
+            &blockid(0),
+            &compressible_data(4096),
+        )
+        .await;
+        _store(
+            &underlying,
+            CompressionAlgorithm::Zstd,
+            &blockid(1),
+            &compressible_data(8192),
+        )
+        .await;
+
+        for &algorithm in CompressionAlgorithm::ALL {
+            assert_eq!(
+                Some(compressible_data(4096)),
+                _load(&underlying, algorithm, &blockid(0)).await.unwrap()
+            );
+            assert_eq!(
+                Some(compressible_data(8192)),
+                _load(&underlying, algorithm, &blockid(1)).await.unwrap()
+            );
+        }
+
+        underlying.async_drop().await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_loading_block_with_unknown_tag_fails() {
+        let mut underlying = SharedBlockStore::new(InMemoryBlockStore::new());
+        underlying.store(&blockid(0), &[7, 1, 2, 3]).await.unwrap();
+        underlying.store(&blockid(1), &[]).await.unwrap();
+
+        _load(&underlying, CompressionAlgorithm::Zstd, &blockid(0))
+            .await
+            .unwrap_err();
+        _load(&underlying, CompressionAlgorithm::Zstd, &blockid(1))
+            .await
+            .unwrap_err();
+
+        underlying.async_drop().await.unwrap();
+    }
+
+    #[test]
+    fn test_algorithm_names() {
+        for &algorithm in CompressionAlgorithm::ALL {
+            assert_eq!(
+                algorithm,
+                algorithm
+                    .to_string()
+                    .parse::<CompressionAlgorithm>()
+                    .unwrap()
+            );
+        }
+        assert!("gzip".parse::<CompressionAlgorithm>().is_err());
+    }
 }
@@ -218,12 +218,16 @@ impl<
     }
 }
 
+#[async_trait]
 impl<
     C: 'static + CipherDef + Send + Sync,
     _B: LLBlockStore + OptimizedBlockStoreWriter + Send + Sync + Debug,
     B: 'static + Debug + AsyncDrop<Error = anyhow::Error> + Borrow<_B> + Send + Sync,
 > LLBlockStore for EncryptedBlockStore<C, _B, B>
 {
+    async fn sync(&self) -> Result<()> {
+        self.underlying_block_store.deref().borrow().sync().await
+    }
 }
 
 impl<
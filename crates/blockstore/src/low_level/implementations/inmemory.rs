@@ -154,7 +154,12 @@ impl AsyncDrop for InMemoryBlockStore {
     }
 }
 
-impl LLBlockStore for InMemoryBlockStore {}
+#[async_trait]
+impl LLBlockStore for InMemoryBlockStore {
+    async fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+}
 
 #[cfg(test)]
 mod tests {
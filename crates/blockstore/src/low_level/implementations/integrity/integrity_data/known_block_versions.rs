@@ -14,7 +14,7 @@ use std::sync::atomic::{AtomicBool, Ordering};
 
 use crate::BlockId;
 use cryfs_utils::{
-    binary::{BinaryReadExt, BinaryWriteExt, read_nonzerou32, write_nonzerou32},
+    binary::{BinaryReadExt, read_nonzerou32, write_nonzerou32},
     safe_panic,
 };
 
@@ -148,7 +148,7 @@ impl std::fmt::Debug for BlockVersion {
 ///    The client_id we consider to have created the current version of the block.
 ///
 /// The invariant we uphold is that within a `(client_id, block_id)` pair, version numbers are always increasing.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BlockInfo {
     pub last_update_client_id: MaybeClientId,
 
@@ -340,11 +340,26 @@ impl<'a> Drop for BlockVersionTransaction<'a> {
     }
 }
 
+/// A copy of the state of a [KnownBlockVersions] object, see [KnownBlockVersions::snapshot].
+#[derive(Debug)]
+pub struct KnownBlockVersionsSnapshot(KnownBlockVersionsSerialized);
+
+impl KnownBlockVersionsSnapshot {
+    pub async fn save(self, file_path: &Path) -> Result<()> {
+        self.0
+            .serialize_to_file_atomically_in_background(file_path.to_owned())
+            .await
+            .context("Error saving KnownBlockVersions")
+    }
+}
+
 #[derive(Debug)]
 pub struct KnownBlockVersions {
     // TODO Remove pub, it's only for serialization
     pub(super) integrity_violation_in_previous_run: AtomicBool,
     pub(super) block_infos: Arc<LockableHashMap<BlockId, BlockInfo>>,
+    /// Set whenever the state may have changed since the last snapshot, see [KnownBlockVersions::snapshot_if_modified].
+    pub(super) modified_since_snapshot: AtomicBool,
 }
 
 impl Default for KnownBlockVersions {
@@ -352,6 +367,7 @@ impl Default for KnownBlockVersions {
         Self {
             integrity_violation_in_previous_run: false.into(),
             block_infos: Arc::new(LockableHashMap::new()),
+            modified_since_snapshot: false.into(),
         }
     }
 }
@@ -372,14 +388,36 @@ impl KnownBlockVersions {
     pub async fn save(self, file_path: &Path) -> Result<()> {
         KnownBlockVersionsSerialized::async_from(self)
             .await
-            .serialize_to_file(file_path)
+            .serialize_to_file_atomically_in_background(file_path.to_owned())
+            .await
             .context("Error saving KnownBlockVersions")
     }
 
+    /// Take a copy of the current state that can be saved while the object is still in use.
+    /// Changes that are made concurrently may or may not end up in the snapshot.
+    /// Returns `None` if nothing could have changed since the last snapshot was taken.
+    pub async fn snapshot_if_modified(&self) -> Option<KnownBlockVersionsSnapshot> {
+        // Reset the flag before taking the snapshot so that concurrent changes that miss the snapshot set it again
+        if !self.modified_since_snapshot.swap(false, Ordering::SeqCst) {
+            return None;
+        }
+        Some(KnownBlockVersionsSnapshot(
+            KnownBlockVersionsSerialized::snapshot(self).await,
+        ))
+    }
+
+    /// Make sure the next call to [KnownBlockVersions::snapshot_if_modified] returns a snapshot,
+    /// e.g. because saving the previous one failed.
+    pub fn mark_as_modified(&self) {
+        self.modified_since_snapshot.store(true, Ordering::SeqCst);
+    }
+
     pub async fn lock_block_info(
         &self,
         block_id: BlockId,
     ) -> <LockableHashMap<BlockId, BlockInfo> as Lockable<BlockId, BlockInfo>>::OwnedGuard {
+        // We don't know whether the caller will modify the block info, so we have to assume it does
+        self.mark_as_modified();
         self.block_infos
             .async_lock_owned(block_id, AsyncLimit::no_limit())
             .await
@@ -399,6 +437,7 @@ impl KnownBlockVersions {
     pub fn set_integrity_violation_in_previous_run(&self) {
         self.integrity_violation_in_previous_run
             .store(true, Ordering::SeqCst);
+        self.mark_as_modified();
     }
 }
 
@@ -12,7 +12,8 @@ mod serialization;
 
 pub use integrity_violation_error::IntegrityViolationError;
 pub use known_block_versions::{
-    BlockInfo, BlockVersion, BlockVersionTransaction, ClientId, KnownBlockVersions, MaybeClientId,
+    BlockInfo, BlockVersion, BlockVersionTransaction, ClientId, KnownBlockVersions,
+    KnownBlockVersionsSnapshot, MaybeClientId,
 };
 
 // TODO Rethink serialization. It's weird to have a KnownBlockVersions object wrapped in an IntegrityData object just because parts are serialized to different files. Merge them.
@@ -81,6 +82,28 @@ impl IntegrityData {
         self._known_block_versions().existing_blocks()
     }
 
+    /// Take a copy of the current state, to be written with [IntegrityData::save_snapshot].
+    /// This is split into two steps so that callers can make sure that all blocks covered by the
+    /// snapshot are persisted before the snapshot is.
+    /// Returns `None` if nothing changed since the last snapshot, so there's nothing to save.
+    pub async fn snapshot_if_modified(&self) -> Option<KnownBlockVersionsSnapshot> {
+        self._known_block_versions().snapshot_if_modified().await
+    }
+
+    pub async fn save_snapshot(&self, snapshot: KnownBlockVersionsSnapshot) -> Result<()> {
+        let result = snapshot.save(&self.state_file_path).await;
+        if result.is_err() {
+            // Make sure the next sync tries again
+            self.mark_as_modified();
+        }
+        result
+    }
+
+    /// Make sure the next call to [IntegrityData::snapshot_if_modified] returns a snapshot.
+    pub fn mark_as_modified(&self) {
+        self._known_block_versions().mark_as_modified();
+    }
+
     fn _known_block_versions(&self) -> &KnownBlockVersions {
         self.known_block_versions
             .as_ref()
@@ -1,13 +1,18 @@
+use anyhow::{Context, Result};
 use binrw::{BinRead, BinWrite};
 use core::num::NonZeroU8;
-use lockable::{InfallibleUnwrap, LockableHashMap, SyncLimit};
+use lockable::{AsyncLimit, InfallibleUnwrap, LockableHashMap, SyncLimit};
 use std::collections::hash_map::HashMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use crate::BlockId;
 use cryfs_utils::{
     binary::{
-        read_bool, read_hashmap, read_null_string, write_bool, write_hashmap, write_null_string,
+        BinaryWriteExt, read_bool, read_hashmap, read_null_string, write_bool, write_hashmap,
+        write_null_string,
     },
     containers::HashMapExt,
 };
@@ -85,18 +90,33 @@ impl From<KnownBlockVersionsSerialized> for KnownBlockVersions {
         KnownBlockVersions {
             integrity_violation_in_previous_run: data.integrity_violation_in_previous_run.into(),
             block_infos: Arc::new(block_infos),
+            // This is exactly what's stored on disk
+            modified_since_snapshot: false.into(),
         }
     }
 }
 
 impl KnownBlockVersionsSerialized {
-    pub async fn async_from(data: KnownBlockVersions) -> Self {
-        let mut known_block_versions = HashMap::new();
-        let mut last_update_client_id = HashMap::new();
-        let num_blocks = data.block_infos.num_entries_or_locked();
-        known_block_versions.reserve(num_blocks);
-        last_update_client_id.reserve(num_blocks);
+    /// Copy the state of `data` while it is still in use. Block infos are locked one at a time,
+    /// so this waits for operations that currently hold a lock on a block, but doesn't block
+    /// operations on other blocks.
+    pub async fn snapshot(data: &KnownBlockVersions) -> Self {
+        let integrity_violation_in_previous_run = data.integrity_violation_in_previous_run();
+        let mut block_infos = Vec::new();
+        for block_id in data.block_infos.keys_with_entries_or_locked() {
+            let guard = data
+                .block_infos
+                .async_lock(block_id, AsyncLimit::no_limit())
+                .await
+                .infallible_unwrap();
+            if let Some(block_info) = guard.value() {
+                block_infos.push((block_id, block_info.clone()));
+            }
+        }
+        Self::_from_block_infos(integrity_violation_in_previous_run, block_infos)
+    }
 
+    pub async fn async_from(data: KnownBlockVersions) -> Self {
         let integrity_violation_in_previous_run = data.integrity_violation_in_previous_run();
 
         // At this point, we have exclusive access to KnownBlockVersions, but there may still be other threads/tasks having access to
@@ -113,7 +133,20 @@ impl KnownBlockVersionsSerialized {
         }
         let block_infos = Arc::into_inner(data.block_infos).expect("We just waited until we're the only one with a clone of the Arc. It can't have gone back up.");
 
-        for (block_id, block_info) in block_infos.into_entries_unordered() {
+        Self::_from_block_infos(
+            integrity_violation_in_previous_run,
+            block_infos.into_entries_unordered(),
+        )
+    }
+
+    fn _from_block_infos(
+        integrity_violation_in_previous_run: bool,
+        block_infos: impl IntoIterator<Item = (BlockId, BlockInfo)>,
+    ) -> Self {
+        let mut known_block_versions = HashMap::new();
+        let mut last_update_client_id = HashMap::new();
+
+        for (block_id, block_info) in block_infos {
             for (client_id, block_version) in block_info.known_block_versions {
                 HashMapExt::try_insert(
                     &mut known_block_versions,
@@ -141,6 +174,55 @@ impl KnownBlockVersionsSerialized {
     }
 }
 
+impl KnownBlockVersionsSerialized {
+    /// Write to a temporary file and rename it over `file_path`, so that a crash
+    /// leaves either the old or the new state but never a partially written one.
+    pub fn serialize_to_file_atomically(&self, file_path: &Path) -> Result<()> {
+        let mut temp_path = file_path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        let temp_path = Path::new(&temp_path);
+        let file = File::create(temp_path)
+            .with_context(|| format!("Failed to create {}", temp_path.display()))?;
+        let mut writer = BufWriter::new(file);
+        self.serialize_to_stream(&mut writer)?;
+        let file = writer
+            .into_inner()
+            .context("Failed to flush the integrity state")?;
+        file.sync_all()
+            .with_context(|| format!("Failed to fsync {}", temp_path.display()))?;
+        std::fs::rename(temp_path, file_path).with_context(|| {
+            format!(
+                "Failed to rename {} to {}",
+                temp_path.display(),
+                file_path.display()
+            )
+        })?;
+        #[cfg(unix)]
+        if let Some(parent_dir) = file_path.parent() {
+            let parent_dir = if parent_dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                parent_dir
+            };
+            File::open(parent_dir)
+                .and_then(|dir| dir.sync_all())
+                .with_context(|| format!("Failed to fsync {}", parent_dir.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Like [KnownBlockVersionsSerialized::serialize_to_file_atomically], but runs the blocking file system
+    /// operations on a thread where they don't block the async runtime.
+    pub async fn serialize_to_file_atomically_in_background(
+        self,
+        file_path: PathBuf,
+    ) -> Result<()> {
+        tokio::task::spawn_blocking(move || self.serialize_to_file_atomically(&file_path))
+            .await
+            .context("Task saving the integrity state panicked")?
+    }
+}
+
 fn format_potential_utf8(data: &[u8]) -> String {
     match std::str::from_utf8(data) {
         Ok(str) => String::from(str),
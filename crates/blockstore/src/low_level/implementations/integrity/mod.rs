@@ -63,6 +63,9 @@ pub struct IntegrityConfig {
     pub allow_integrity_violations: AllowIntegrityViolations,
     pub missing_block_is_integrity_violation: MissingBlockIsIntegrityViolation,
     pub on_integrity_violation: Box<dyn Sync + Send + Fn(&IntegrityViolationError)>,
+    /// Called when we load a block and see that a different client modified it since we've last seen it,
+    /// e.g. because the storage is synchronized between devices.
+    pub on_block_changed_by_other_client: Box<dyn Sync + Send + Fn(&BlockId)>,
 }
 
 pub struct IntegrityBlockStore<B: Send + Debug + AsyncDrop<Error = anyhow::Error>> {
@@ -469,10 +472,25 @@ impl<B: Send + Debug + AsyncDrop<Error = anyhow::Error>> IntegrityBlockStore<B>
         block_id: BlockId,
         block_info: &mut BlockInfo,
     ) -> Result<()> {
+        let previously_seen = (
+            block_info.last_update_client_id(),
+            block_info.current_version(),
+        );
         let update =
             block_info.check_and_update_version(last_update_client_id, block_id, block_version);
         match update {
-            Ok(()) => Ok(()),
+            Ok(()) => {
+                if let (MaybeClientId::ClientId(_), Some(_)) = previously_seen
+                    && previously_seen
+                        != (
+                            MaybeClientId::ClientId(last_update_client_id),
+                            Some(block_version),
+                        )
+                {
+                    (*self.config.on_block_changed_by_other_client)(&block_id);
+                }
+                Ok(())
+            }
             Err(err) if err.is::<IntegrityViolationError>() => {
                 // IntegrityViolationErrors are channeled through _integrity_violation_detected
                 // so that we can silence them if integrity checking is disabled.
@@ -502,9 +520,26 @@ impl<B: Sync + Send + Debug + AsyncDrop<Error = anyhow::Error>> AsyncDrop
     }
 }
 
+#[async_trait]
 impl<B: LLBlockStore + OptimizedBlockStoreWriter + Sync + Send + Debug> LLBlockStore
     for IntegrityBlockStore<B>
 {
+    async fn sync(&self) -> Result<()> {
+        // The snapshot is taken before and saved after syncing the blocks. This way, the saved state covers at least all
+        // blocks that were written before this call, and it never gets ahead of blocks that weren't synced yet.
+        let snapshot = self.integrity_data.snapshot_if_modified().await;
+        if let Err(err) = self.underlying_block_store.sync().await {
+            if snapshot.is_some() {
+                // The snapshot won't be saved, so the next sync has to try again
+                self.integrity_data.mark_as_modified();
+            }
+            return Err(err);
+        }
+        match snapshot {
+            Some(snapshot) => self.integrity_data.save_snapshot(snapshot).await,
+            None => Ok(()),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -560,6 +595,7 @@ mod generic_tests {
                     on_integrity_violation: Box::new(|err| {
                         panic!("Integrity violation: {:?}", err)
                     }),
+                    on_block_changed_by_other_client: Box::new(|_| {}),
                 },
             )
             .await
@@ -624,6 +660,7 @@ mod generic_tests {
 mod specialized_tests {
     #![allow(non_snake_case)]
 
+    use super::integrity_data::KnownBlockVersions;
     use super::integrity_data::testutils::{clientid, version};
     use super::*;
     use crate::low_level::{BlockStoreWriter, InMemoryBlockStore, SharedBlockStore};
@@ -638,6 +675,7 @@ mod specialized_tests {
         underlying: SyncDrop<SharedBlockStore<InMemoryBlockStore>>,
         integrity_file_dir: TempDir,
         integrity_violation_triggered: Arc<Mutex<Option<IntegrityViolationError>>>,
+        changed_by_other_client: Arc<Mutex<Vec<BlockId>>>,
     }
 
     impl Fixture {
@@ -646,6 +684,7 @@ mod specialized_tests {
                 underlying: SyncDrop::new(SharedBlockStore::new(InMemoryBlockStore::new())),
                 integrity_file_dir: tempfile::Builder::new().prefix("test").tempdir().unwrap(),
                 integrity_violation_triggered: Arc::new(Mutex::new(None)),
+                changed_by_other_client: Arc::new(Mutex::new(Vec::new())),
             }
         }
 
@@ -655,6 +694,7 @@ mod specialized_tests {
             missing_block_is_integrity_violation: MissingBlockIsIntegrityViolation,
         ) -> SyncDrop<IntegrityBlockStore<SharedBlockStore<InMemoryBlockStore>>> {
             let integrity_violation_triggered = Arc::clone(&self.integrity_violation_triggered);
+            let changed_by_other_client = Arc::clone(&self.changed_by_other_client);
             SyncDrop::new(
                 IntegrityBlockStore::new(
                     SharedBlockStore::clone(self.underlying.inner()),
@@ -669,6 +709,9 @@ mod specialized_tests {
                         on_integrity_violation: Box::new(move |err| {
                             *integrity_violation_triggered.lock().unwrap() = Some(err.clone());
                         }),
+                        on_block_changed_by_other_client: Box::new(move |block_id| {
+                            changed_by_other_client.lock().unwrap().push(*block_id);
+                        }),
                     },
                 )
                 .await
@@ -1271,4 +1314,88 @@ mod specialized_tests {
         )
         .await;
     }
+
+    #[tokio::test]
+    async fn load_block_changed_by_other_client() {
+        let fixture = Fixture::new();
+        let store = fixture
+            .store(
+                AllowIntegrityViolations::DontAllowViolations,
+                MissingBlockIsIntegrityViolation::IsAViolation,
+            )
+            .await;
+        let blockid = create_block_return_key(&store, &data(1024, 1)).await;
+        fixture.modify_block(&store, &blockid).await;
+        store.load(&blockid).await.unwrap().unwrap();
+        assert_eq!(
+            Vec::<BlockId>::new(),
+            *fixture.changed_by_other_client.lock().unwrap()
+        );
+
+        fixture.change_client_id(&blockid).await.unwrap();
+        store.load(&blockid).await.unwrap().unwrap();
+        assert_eq!(
+            vec![blockid],
+            *fixture.changed_by_other_client.lock().unwrap()
+        );
+
+        // Loading it again doesn't report it again
+        store.load(&blockid).await.unwrap().unwrap();
+        assert_eq!(
+            vec![blockid],
+            *fixture.changed_by_other_client.lock().unwrap()
+        );
+        fixture.assert_integrity_violation_didnt_trigger();
+    }
+
+    #[tokio::test]
+    async fn sync_writes_integrity_state() {
+        let fixture = Fixture::new();
+        let store = fixture
+            .store(
+                AllowIntegrityViolations::DontAllowViolations,
+                MissingBlockIsIntegrityViolation::IsAViolation,
+            )
+            .await;
+        let blockid = create_block_return_key(&store, &data(1024, 1)).await;
+        fixture.modify_block(&store, &blockid).await;
+
+        store.sync().await.unwrap();
+
+        // The state file is up to date even though the store is still open
+        let state =
+            KnownBlockVersions::load(&fixture.integrity_file_dir.path().join("integrity_file"))
+                .unwrap()
+                .unwrap();
+        let block_info = state.lock_block_info(blockid).await;
+        assert_eq!(
+            Some(version(2)),
+            block_info.value().unwrap().current_version()
+        );
+    }
+
+    #[tokio::test]
+    async fn sync_without_changes_doesnt_write_integrity_state() {
+        let fixture = Fixture::new();
+        let store = fixture
+            .store(
+                AllowIntegrityViolations::DontAllowViolations,
+                MissingBlockIsIntegrityViolation::IsAViolation,
+            )
+            .await;
+        let state_file = fixture.integrity_file_dir.path().join("integrity_file");
+        let blockid = create_block_return_key(&store, &data(1024, 1)).await;
+        store.sync().await.unwrap();
+        assert!(state_file.exists());
+
+        // Nothing changed since the last sync, so the state file isn't written again
+        std::fs::remove_file(&state_file).unwrap();
+        store.sync().await.unwrap();
+        assert!(!state_file.exists());
+
+        // After a change, it is
+        fixture.modify_block(&store, &blockid).await;
+        store.sync().await.unwrap();
+        assert!(state_file.exists());
+    }
 }
@@ -0,0 +1,677 @@
+use anyhow::{Result, anyhow, bail, ensure};
+use async_trait::async_trait;
+use byte_unit::Byte;
+use futures::future;
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use std::collections::HashSet;
+use std::fmt::{self, Debug};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Instant;
+
+use crate::low_level::interface::block_data::create_block_data_wrapper;
+use crate::{
+    BlockId, Overhead,
+    low_level::{
+        BlockStoreDeleter, BlockStoreReader, LLBlockStore, OptimizedBlockStoreWriter,
+        interface::block_data::IBlockData,
+    },
+    utils::{RemoveResult, TryCreateResult},
+};
+use cryfs_utils::{
+    async_drop::{AsyncDrop, AsyncDropGuard},
+    data::Data,
+};
+
+/// A block store that keeps a full copy of all blocks in each of several replicas, e.g. the same vault on two disks.
+///
+/// Writes go to all replicas. Reads go to the replica that answered fastest so far and fall back to the other replicas
+/// if a replica doesn't have the block or fails to load it. Those replicas then get repaired with the first valid copy found. To also detect corrupted blocks, each replica should have its own [super::EncryptedBlockStore]
+/// so that blocks that fail to decrypt show up as load errors here.
+///
+/// If a write fails on some but not all replicas, the write still succeeds and the failed replicas are considered lagging.
+/// Lagging replicas still get writes, but aren't used for reads until they were brought up to date with [MirroringBlockStore::resync].
+pub struct MirroringBlockStore<B: LLBlockStore + Send + Sync> {
+    replicas: Vec<Replica<B>>,
+}
+
+struct Replica<B: LLBlockStore + Send + Sync> {
+    store: AsyncDropGuard<B>,
+    /// Exponential moving average of the time it took to load a block, 0 if we didn't load anything yet
+    load_latency_micros: AtomicU64,
+    /// A write to this replica failed, so it may have outdated or removed blocks
+    lagging: AtomicBool,
+}
+
+impl<B: LLBlockStore + Send + Sync> Replica<B> {
+    fn record_load_latency(&self, started: Instant) {
+        let sample = u64::try_from(started.elapsed().as_micros())
+            .unwrap_or(u64::MAX)
+            .max(1);
+        // Not atomic as a whole, but a lost update only makes the average slightly less accurate
+        let previous = self.load_latency_micros.load(Ordering::Relaxed);
+        let new = if previous == 0 {
+            sample
+        } else {
+            previous - previous / 8 + sample / 8
+        };
+        self.load_latency_micros.store(new, Ordering::Relaxed);
+    }
+
+    fn is_lagging(&self) -> bool {
+        self.lagging.load(Ordering::Relaxed)
+    }
+}
+
+/// What [MirroringBlockStore::resync] did to bring the target replica up to date
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ResyncSummary {
+    /// Blocks that were missing in the target replica
+    pub num_copied: u64,
+    /// Blocks that only existed in the target replica
+    pub num_removed: u64,
+    /// Blocks that existed in both replicas, but were different or couldn't be loaded from the target replica
+    pub num_repaired: u64,
+}
+
+impl<B: LLBlockStore + Send + Sync> MirroringBlockStore<B> {
+    pub fn new(replicas: Vec<AsyncDropGuard<B>>) -> AsyncDropGuard<Self> {
+        assert!(
+            !replicas.is_empty(),
+            "MirroringBlockStore needs at least one replica"
+        );
+        AsyncDropGuard::new(Self {
+            replicas: replicas
+                .into_iter()
+                .map(|store| Replica {
+                    store,
+                    load_latency_micros: AtomicU64::new(0),
+                    lagging: AtomicBool::new(false),
+                })
+                .collect(),
+        })
+    }
+
+    pub fn num_replicas(&self) -> usize {
+        self.replicas.len()
+    }
+
+    /// Replicas that missed writes and need a [MirroringBlockStore::resync]
+    pub fn lagging_replicas(&self) -> Vec<usize> {
+        (0..self.replicas.len())
+            .filter(|&index| self.replicas[index].is_lagging())
+            .collect()
+    }
+
+    /// Brings the `target` replica up to date with the `source` replica by comparing the block ids both of them have.
+    /// Blocks missing in the target get copied, blocks that only exist in the target get removed.
+    ///
+    /// Blocks that exist in both replicas are only compared if `compare_contents` is set. This reads all blocks,
+    /// but is necessary to catch blocks that were overwritten while the target replica was lagging.
+    pub async fn resync(
+        &self,
+        source: usize,
+        target: usize,
+        compare_contents: bool,
+    ) -> Result<ResyncSummary> {
+        ensure!(
+            source < self.replicas.len() && target < self.replicas.len(),
+            "Replica index out of range, there are only {} replicas",
+            self.replicas.len()
+        );
+        ensure!(source != target, "Can't resync a replica with itself");
+        let source_store = &self.replicas[source].store;
+        let target_store = &self.replicas[target].store;
+
+        let mut target_only: HashSet<BlockId> =
+            target_store.all_blocks().await?.try_collect().await?;
+        let mut summary = ResyncSummary::default();
+        let mut source_blocks = source_store.all_blocks().await?;
+        while let Some(block_id) = source_blocks.try_next().await? {
+            let exists_in_target = target_only.remove(&block_id);
+            if exists_in_target && !compare_contents {
+                continue;
+            }
+            let Some(data) = source_store.load(&block_id).await? else {
+                // The block was removed since we listed it
+                continue;
+            };
+            if exists_in_target {
+                match target_store.load(&block_id).await {
+                    Ok(Some(target_data)) if target_data.as_ref() == data.as_ref() => continue,
+                    Ok(_) => {}
+                    Err(err) => {
+                        log::warn!(
+                            "Failed to load block {block_id:?} from replica {target}, repairing it: {err:?}"
+                        );
+                    }
+                }
+                summary.num_repaired += 1;
+            } else {
+                summary.num_copied += 1;
+            }
+            target_store.store(&block_id, data.as_ref()).await?;
+        }
+        for block_id in target_only {
+            // Don't remove blocks that were created since we listed the source blocks
+            if !source_store.exists(&block_id).await?
+                && target_store.remove(&block_id).await? == RemoveResult::SuccessfullyRemoved
+            {
+                summary.num_removed += 1;
+            }
+        }
+
+        self.replicas[target]
+            .lagging
+            .store(false, Ordering::Relaxed);
+        Ok(summary)
+    }
+
+    /// Replicas to read from, fastest first. Lagging replicas are only used if there are no others.
+    fn _read_order(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.replicas.len())
+            .filter(|&index| !self.replicas[index].is_lagging())
+            .collect();
+        if order.is_empty() {
+            order = (0..self.replicas.len()).collect();
+        }
+        order.sort_by_key(|&index| {
+            self.replicas[index]
+                .load_latency_micros
+                .load(Ordering::Relaxed)
+        });
+        order
+    }
+
+    /// Stores `data` in replicas that were found to be missing the block or to have a broken copy of it
+    async fn _repair(&self, id: &BlockId, data: &[u8], replicas: &[usize]) {
+        for &index in replicas {
+            log::warn!("Repairing block {id:?} in replica {index}");
+            if let Err(err) = self.replicas[index].store.store(id, data).await {
+                log::error!("Failed to repair block {id:?} in replica {index}: {err:?}");
+                self.replicas[index].lagging.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Succeeds if the write succeeded on at least one replica. Replicas where it failed are marked as lagging.
+    fn _handle_write_results<T>(&self, id: &BlockId, results: Vec<Result<T>>) -> Result<Vec<T>> {
+        let mut successes = Vec::with_capacity(results.len());
+        let mut first_error = None;
+        for (index, result) in results.into_iter().enumerate() {
+            match result {
+                Ok(value) => successes.push(value),
+                Err(err) => {
+                    log::error!(
+                        "Failed to write block {id:?} to replica {index}, marking it as lagging: {err:?}"
+                    );
+                    self.replicas[index].lagging.store(true, Ordering::Relaxed);
+                    first_error.get_or_insert(err);
+                }
+            }
+        }
+        if successes.is_empty() {
+            Err(first_error
+                .expect("There is at least one replica")
+                .context("Write failed on all replicas"))
+        } else {
+            Ok(successes)
+        }
+    }
+
+    async fn _store(&self, id: &BlockId, data: &[u8]) -> Result<()> {
+        let results = future::join_all(
+            self.replicas
+                .iter()
+                .map(|replica| replica.store.store(id, data)),
+        )
+        .await;
+        self._handle_write_results(id, results)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<B: LLBlockStore + Send + Sync> BlockStoreReader for MirroringBlockStore<B> {
+    async fn exists(&self, id: &BlockId) -> Result<bool> {
+        let mut first_error = None;
+        for index in self._read_order() {
+            match self.replicas[index].store.exists(id).await {
+                Ok(true) => return Ok(true),
+                Ok(false) => {}
+                Err(err) => {
+                    log::warn!("Failed to check block {id:?} in replica {index}: {err:?}");
+                    first_error.get_or_insert(err);
+                }
+            }
+        }
+        match first_error {
+            Some(err) => Err(err),
+            None => Ok(false),
+        }
+    }
+
+    async fn load(&self, id: &BlockId) -> Result<Option<Data>> {
+        let mut needs_repair = Vec::new();
+        let mut first_error = None;
+        for index in self._read_order() {
+            let replica = &self.replicas[index];
+            let started = Instant::now();
+            match replica.store.load(id).await {
+                Ok(Some(data)) => {
+                    replica.record_load_latency(started);
+                    self._repair(id, data.as_ref(), &needs_repair).await;
+                    return Ok(Some(data));
+                }
+                Ok(None) => {
+                    replica.record_load_latency(started);
+                    needs_repair.push(index);
+                }
+                Err(err) => {
+                    log::warn!("Failed to load block {id:?} from replica {index}: {err:?}");
+                    needs_repair.push(index);
+                    first_error.get_or_insert(err);
+                }
+            }
+        }
+        // No replica has a valid copy. If any replica failed, the block may have existed there, so report the error.
+        match first_error {
+            Some(err) => Err(err),
+            None => Ok(None),
+        }
+    }
+
+    async fn num_blocks(&self) -> Result<u64> {
+        let results = future::join_all(
+            self._read_order()
+                .into_iter()
+                .map(|index| self.replicas[index].store.num_blocks()),
+        )
+        .await;
+        let mut max = None;
+        for result in results {
+            let num_blocks = result?;
+            max = Some(max.unwrap_or(0).max(num_blocks));
+        }
+        max.ok_or_else(|| anyhow!("No replicas"))
+    }
+
+    fn estimate_num_free_bytes(&self) -> Result<Byte> {
+        // Every block gets written to every replica, so the fullest replica limits what we can store
+        let mut min = None;
+        for replica in &self.replicas {
+            let free_bytes = replica.store.estimate_num_free_bytes()?;
+            min = Some(min.map_or(free_bytes, |min: Byte| min.min(free_bytes)));
+        }
+        min.ok_or_else(|| anyhow!("No replicas"))
+    }
+
+    fn overhead(&self) -> Overhead {
+        self.replicas
+            .iter()
+            .map(|replica| replica.store.overhead())
+            .max_by_key(|overhead| {
+                overhead.physical_block_size_from_usable_block_size(Byte::from_u64(0))
+            })
+            .expect("There is at least one replica")
+    }
+
+    async fn all_blocks(&self) -> Result<BoxStream<'static, Result<BlockId>>> {
+        // Replicas can miss blocks that get repaired when loading them, so list the blocks of all replicas
+        let mut block_ids = HashSet::new();
+        for index in self._read_order() {
+            let mut replica_blocks = self.replicas[index].store.all_blocks().await?;
+            while let Some(block_id) = replica_blocks.try_next().await? {
+                block_ids.insert(block_id);
+            }
+        }
+        Ok(stream::iter(block_ids.into_iter().map(Ok)).boxed())
+    }
+}
+
+#[async_trait]
+impl<B: LLBlockStore + Send + Sync> BlockStoreDeleter for MirroringBlockStore<B> {
+    async fn remove(&self, id: &BlockId) -> Result<RemoveResult> {
+        let results =
+            future::join_all(self.replicas.iter().map(|replica| replica.store.remove(id))).await;
+        let results = self._handle_write_results(id, results)?;
+        if results.contains(&RemoveResult::SuccessfullyRemoved) {
+            Ok(RemoveResult::SuccessfullyRemoved)
+        } else {
+            Ok(RemoveResult::NotRemovedBecauseItDoesntExist)
+        }
+    }
+}
+
+create_block_data_wrapper!(BlockData);
+
+#[async_trait]
+impl<B: LLBlockStore + Send + Sync> OptimizedBlockStoreWriter for MirroringBlockStore<B> {
+    type BlockData = BlockData;
+
+    fn allocate(size: usize) -> BlockData {
+        // Each replica needs its own copy of the data anyway, so there is no point in reserving space for headers
+        BlockData::new(Data::from(vec![0; size]))
+    }
+
+    async fn try_create_optimized(&self, id: &BlockId, data: BlockData) -> Result<TryCreateResult> {
+        // Asking each replica to try_create could create the block in some replicas and not in others
+        if self.exists(id).await? {
+            return Ok(TryCreateResult::NotCreatedBecauseBlockIdAlreadyExists);
+        }
+        self._store(id, data.extract().as_ref()).await?;
+        Ok(TryCreateResult::SuccessfullyCreated)
+    }
+
+    async fn store_optimized(&self, id: &BlockId, data: BlockData) -> Result<()> {
+        self._store(id, data.extract().as_ref()).await
+    }
+}
+
+impl<B: LLBlockStore + Send + Sync> Debug for MirroringBlockStore<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.replicas.iter().map(|replica| &replica.store))
+            .finish()?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<B: LLBlockStore + Send + Sync> AsyncDrop for MirroringBlockStore<B> {
+    type Error = anyhow::Error;
+    async fn async_drop_impl(&mut self) -> Result<()> {
+        let mut first_error = None;
+        for replica in &mut self.replicas {
+            if let Err(err) = replica.store.async_drop().await {
+                first_error.get_or_insert(err);
+            }
+        }
+        match first_error {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl<B: LLBlockStore + Send + Sync> LLBlockStore for MirroringBlockStore<B> {
+    async fn sync(&self) -> Result<()> {
+        let results =
+            future::join_all(self.replicas.iter().map(|replica| replica.store.sync())).await;
+        for (index, result) in results.into_iter().enumerate() {
+            if let Err(err) = result {
+                bail!("Failed to sync replica {index}: {err:?}");
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::low_level::{
+        BlockStoreWriter, EncryptedBlockStore, InMemoryBlockStore, SharedBlockStore,
+    };
+    use crate::tests::low_level::LLFixture;
+    use crate::tests::utils::{blockid, data};
+    use cryfs_crypto::symmetric::{CipherDef, EncryptionKey, XChaCha20Poly1305};
+
+    struct TestFixture {}
+    #[async_trait]
+    impl LLFixture for TestFixture {
+        type ConcreteBlockStore = MirroringBlockStore<InMemoryBlockStore>;
+        fn new() -> Self {
+            Self {}
+        }
+        async fn store(&mut self) -> AsyncDropGuard<Self::ConcreteBlockStore> {
+            MirroringBlockStore::new(vec![InMemoryBlockStore::new(), InMemoryBlockStore::new()])
+        }
+        async fn yield_fixture(&self, _store: &Self::ConcreteBlockStore) {}
+    }
+
+    crate::instantiate_blockstore_tests_for_lowlevel_blockstore!(
+        TestFixture,
+        (flavor = "multi_thread")
+    );
+
+    type Replica = EncryptedBlockStore<
+        XChaCha20Poly1305,
+        SharedBlockStore<InMemoryBlockStore>,
+        SharedBlockStore<InMemoryBlockStore>,
+    >;
+
+    fn cipher() -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(
+            EncryptionKey::new(XChaCha20Poly1305::KEY_SIZE, |key| {
+                key.fill(5);
+                Ok::<_, std::convert::Infallible>(())
+            })
+            .unwrap(),
+        )
+        .unwrap()
+    }
+
+    /// Creates a mirror with two encrypted replicas and returns the unencrypted stores below them,
+    /// so that tests can remove or corrupt blocks in one of the replicas.
+    fn mirror() -> (
+        AsyncDropGuard<MirroringBlockStore<Replica>>,
+        [AsyncDropGuard<SharedBlockStore<InMemoryBlockStore>>; 2],
+    ) {
+        let underlying = [
+            SharedBlockStore::new(InMemoryBlockStore::new()),
+            SharedBlockStore::new(InMemoryBlockStore::new()),
+        ];
+        let replicas = underlying
+            .iter()
+            .map(|underlying| Replica::new(SharedBlockStore::clone(underlying), cipher()))
+            .collect();
+        (MirroringBlockStore::new(replicas), underlying)
+    }
+
+    /// Makes `replica` look like the fastest replica so that loads try it first
+    fn prefer(store: &MirroringBlockStore<Replica>, replica: usize) {
+        for (index, r) in store.replicas.iter().enumerate() {
+            let latency = if index == replica { 1 } else { 1000 };
+            r.load_latency_micros.store(latency, Ordering::Relaxed);
+        }
+    }
+
+    async fn drop_all(
+        mut store: AsyncDropGuard<MirroringBlockStore<Replica>>,
+        underlying: [AsyncDropGuard<SharedBlockStore<InMemoryBlockStore>>; 2],
+    ) {
+        store.async_drop().await.unwrap();
+        for mut underlying in underlying {
+            underlying.async_drop().await.unwrap();
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_store_writes_to_all_replicas() {
+        let (store, underlying) = mirror();
+        store.store(&blockid(0), &data(100, 0)).await.unwrap();
+
+        assert!(underlying[0].exists(&blockid(0)).await.unwrap());
+        assert!(underlying[1].exists(&blockid(0)).await.unwrap());
+
+        drop_all(store, underlying).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_load_repairs_missing_block() {
+        for broken in 0..2 {
+            let (store, underlying) = mirror();
+            store.store(&blockid(0), &data(100, 0)).await.unwrap();
+            assert_eq!(
+                RemoveResult::SuccessfullyRemoved,
+                underlying[broken].remove(&blockid(0)).await.unwrap()
+            );
+
+            prefer(&store, broken);
+            assert_eq!(Some(data(100, 0)), store.load(&blockid(0)).await.unwrap());
+            assert!(underlying[broken].exists(&blockid(0)).await.unwrap());
+            assert_eq!(
+                Some(data(100, 0)),
+                store.replicas[broken]
+                    .store
+                    .load(&blockid(0))
+                    .await
+                    .unwrap()
+            );
+
+            drop_all(store, underlying).await;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_load_repairs_undecryptable_block() {
+        for broken in 0..2 {
+            let (store, underlying) = mirror();
+            store.store(&blockid(0), &data(100, 0)).await.unwrap();
+            let mut corrupted = underlying[broken].load(&blockid(0)).await.unwrap().unwrap();
+            corrupted[20] ^= 1;
+            underlying[broken]
+                .store(&blockid(0), &corrupted)
+                .await
+                .unwrap();
+            assert!(
+                store.replicas[broken]
+                    .store
+                    .load(&blockid(0))
+                    .await
+                    .is_err()
+            );
+
+            prefer(&store, broken);
+            assert_eq!(Some(data(100, 0)), store.load(&blockid(0)).await.unwrap());
+            assert_eq!(
+                Some(data(100, 0)),
+                store.replicas[broken]
+                    .store
+                    .load(&blockid(0))
+                    .await
+                    .unwrap()
+            );
+
+            drop_all(store, underlying).await;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_load_fails_if_no_replica_can_load_the_block() {
+        let (store, underlying) = mirror();
+        store.store(&blockid(0), &data(100, 0)).await.unwrap();
+        for underlying in &underlying {
+            underlying.store(&blockid(0), &data(100, 1)).await.unwrap();
+        }
+
+        assert!(store.load(&blockid(0)).await.is_err());
+
+        drop_all(store, underlying).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_all_blocks_includes_blocks_missing_in_some_replicas() {
+        let (store, underlying) = mirror();
+        store.store(&blockid(0), &data(100, 0)).await.unwrap();
+        store.store(&blockid(1), &data(100, 1)).await.unwrap();
+        assert_eq!(
+            RemoveResult::SuccessfullyRemoved,
+            underlying[0].remove(&blockid(0)).await.unwrap()
+        );
+        assert_eq!(
+            RemoveResult::SuccessfullyRemoved,
+            underlying[1].remove(&blockid(1)).await.unwrap()
+        );
+
+        let mut all_blocks: Vec<BlockId> = store
+            .all_blocks()
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        all_blocks.sort();
+        let mut expected = vec![blockid(0), blockid(1)];
+        expected.sort();
+        assert_eq!(expected, all_blocks);
+        assert!(store.exists(&blockid(0)).await.unwrap());
+        assert!(store.exists(&blockid(1)).await.unwrap());
+
+        drop_all(store, underlying).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_resync() {
+        let (store, underlying) = mirror();
+        store.store(&blockid(0), &data(100, 0)).await.unwrap();
+        store.store(&blockid(1), &data(100, 1)).await.unwrap();
+        store.store(&blockid(2), &data(100, 2)).await.unwrap();
+        // Replica 1 is missing a block, has a block that was removed, and has an outdated block
+        assert_eq!(
+            RemoveResult::SuccessfullyRemoved,
+            underlying[1].remove(&blockid(0)).await.unwrap()
+        );
+        assert_eq!(
+            RemoveResult::SuccessfullyRemoved,
+            underlying[0].remove(&blockid(1)).await.unwrap()
+        );
+        store.replicas[1]
+            .store
+            .store(&blockid(2), &data(100, 3))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            ResyncSummary {
+                num_copied: 1,
+                num_removed: 1,
+                num_repaired: 0,
+            },
+            store.resync(0, 1, false).await.unwrap()
+        );
+        assert!(underlying[1].exists(&blockid(0)).await.unwrap());
+        assert!(!underlying[1].exists(&blockid(1)).await.unwrap());
+        assert_eq!(
+            Some(data(100, 3)),
+            store.replicas[1].store.load(&blockid(2)).await.unwrap()
+        );
+
+        assert_eq!(
+            ResyncSummary {
+                num_copied: 0,
+                num_removed: 0,
+                num_repaired: 1,
+            },
+            store.resync(0, 1, true).await.unwrap()
+        );
+        assert_eq!(
+            Some(data(100, 2)),
+            store.replicas[1].store.load(&blockid(2)).await.unwrap()
+        );
+
+        drop_all(store, underlying).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_resync_clears_lagging_flag() {
+        let (store, underlying) = mirror();
+        store.replicas[1].lagging.store(true, Ordering::Relaxed);
+        assert_eq!(vec![1], store.lagging_replicas());
+
+        store.resync(0, 1, false).await.unwrap();
+        assert!(store.lagging_replicas().is_empty());
+
+        drop_all(store, underlying).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_resync_rejects_invalid_replicas() {
+        let (store, underlying) = mirror();
+        assert!(store.resync(0, 0, false).await.is_err());
+        assert!(store.resync(0, 2, false).await.is_err());
+
+        drop_all(store, underlying).await;
+    }
+}
@@ -34,7 +34,9 @@ mock! {
         type Error = anyhow::Error;
         fn async_drop_impl<'a, 'r>(&'a mut self) -> BoxFuture<'r, Result<()>> where 'a: 'r;
     }
-    impl LLBlockStore for BlockStore {}
+    impl LLBlockStore for BlockStore {
+        fn sync<'a, 'r>(&'a self) -> BoxFuture<'r, Result<()>> where 'a: 'r;
+    }
 }
 impl Debug for MockBlockStore {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -1,5 +1,7 @@
 mod compressing;
-pub use compressing::CompressingBlockStore;
+pub use compressing::{
+    CompressingBlockStore, CompressionAlgorithm, UnknownCompressionAlgorithmError,
+};
 
 mod encrypted;
 pub use encrypted::EncryptedBlockStore;
@@ -13,12 +15,33 @@ pub use integrity::{
     IntegrityConfig, IntegrityViolationError, MissingBlockIsIntegrityViolation,
 };
 
+mod mirroring;
+pub use mirroring::{MirroringBlockStore, ResyncSummary};
+
 mod ondisk;
-pub use ondisk::OnDiskBlockStore;
+pub use ondisk::{FsyncPolicy, OnDiskBlockStore};
+
+mod packed;
+pub use packed::{PackedBlockStore, PackedBlockStoreConfig};
+
+#[cfg(feature = "remote")]
+mod remote;
+#[cfg(feature = "remote")]
+pub use remote::{
+    BlockServerListener, BlockServerSecret, RemoteAddress, RemoteBlockStore, serve_blocks,
+};
 
 mod readonly;
 pub use readonly::ReadOnlyBlockStore;
 
+#[cfg(feature = "s3")]
+mod s3;
+#[cfg(feature = "s3")]
+pub use s3::{S3AddressingStyle, S3BlockStore, S3Config, S3Credentials};
+
+mod striped;
+pub use striped::{RebalanceSummary, StripedBlockStore};
+
 mod box_dyn;
 pub use box_dyn::DynBlockStore;
 
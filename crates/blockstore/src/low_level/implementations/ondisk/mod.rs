@@ -2,10 +2,15 @@ use anyhow::{Context, Error, Result, anyhow, bail};
 use async_trait::async_trait;
 use base64::engine::{Engine as _, general_purpose::STANDARD as base64_STANDARD};
 use byte_unit::Byte;
+use futures::future;
 use futures::stream::{BoxStream, Stream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::fmt::{self, Debug};
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use tokio::fs::DirEntry;
+use tokio::io::AsyncWriteExt;
 use tokio_stream::wrappers::ReadDirStream;
 
 use crate::low_level::interface::block_data::create_block_data_wrapper;
@@ -23,25 +28,73 @@ use cryfs_utils::{
     path::path_join,
 };
 
-mod sysinfo;
+pub(super) mod sysinfo;
 
 // TODO Check if tokio-uring o uring-fs is faster than tokio::fs
 
-const FORMAT_VERSION_HEADER_PREFIX: &[u8] = b"cryfs;block;";
-const FORMAT_VERSION_HEADER: &[u8] = b"cryfs;block;0\0";
-
-const PREFIX_LEN: usize = 3;
-const NONPREFIX_LEN: usize = 2 * BLOCKID_LEN - PREFIX_LEN;
+pub(super) const FORMAT_VERSION_HEADER_PREFIX: &[u8] = b"cryfs;block;";
+pub(super) const FORMAT_VERSION_HEADER: &[u8] = b"cryfs;block;0\0";
+
+const TEMP_FILE_SUFFIX: &str = ".tmp";
+
+pub(super) const PREFIX_LEN: usize = 3;
+pub(super) const NONPREFIX_LEN: usize = 2 * BLOCKID_LEN - PREFIX_LEN;
+
+/// Defines when [OnDiskBlockStore] forces written blocks to the physical disk.
+///
+/// Independent of the policy, blocks are always written to a temporary file first and then atomically
+/// renamed to their final path, so readers never see a partially written block. Whether a crash can leave
+/// one behind depends on the policy: Only [FsyncPolicy::Always] syncs the temporary file before renaming it.
+/// With the other policies, a block written shortly before a crash can end up empty or partially written,
+/// depending on the underlying file system, unless it was synced before the crash.
+/// Temporary files left behind by a crash are deleted when the block store is opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum FsyncPolicy {
+    /// Never fsync, leave it to the operating system when to write data to the disk.
+    /// This is the fastest option, but blocks written shortly before a power loss can be lost
+    /// even if the file system got an fsync for them.
+    Never,
+
+    /// Remember which blocks were written or removed and fsync them when [LLBlockStore::sync] is called,
+    /// i.e. when an application calls fsync on a file or directory in the file system.
+    /// Blocks that weren't synced yet can be lost or corrupted by a crash.
+    #[default]
+    OnFsync,
+
+    /// Fsync every block before it is renamed to its final path and its directory before the write or remove
+    /// operation returns. This is the slowest option, but a crash leaves every block either in its old or its new version.
+    Always,
+}
 
 pub struct OnDiskBlockStore {
     basedir: PathBuf,
+    fsync_policy: FsyncPolicy,
+
+    // Only used with [FsyncPolicy::OnFsync]
+    unsynced: Mutex<UnsyncedPaths>,
+}
+
+/// Block files and directories that were modified since the last call to [LLBlockStore::sync].
+#[derive(Default)]
+struct UnsyncedPaths {
+    files: HashSet<PathBuf>,
+    dirs: HashSet<PathBuf>,
 }
 
 impl OnDiskBlockStore {
-    pub fn new(basedir: PathBuf) -> AsyncDropGuard<Self> {
+    pub fn new(basedir: PathBuf, fsync_policy: FsyncPolicy) -> AsyncDropGuard<Self> {
         // TODO When we're creating a new file system, we should make sure that all the subfolders exist.
         //      Because we don't remove the folders when blocks get removed, otherwise a used file system looks different from a new one.
-        AsyncDropGuard::new(Self { basedir })
+        _remove_stale_temp_files(&basedir);
+        AsyncDropGuard::new(Self {
+            basedir,
+            fsync_policy,
+            unsynced: Mutex::new(UnsyncedPaths::default()),
+        })
+    }
+
+    pub fn fsync_policy(&self) -> FsyncPolicy {
+        self.fsync_policy
     }
 }
 
@@ -104,12 +157,15 @@ impl BlockStoreReader for OnDiskBlockStore {
 impl BlockStoreDeleter for OnDiskBlockStore {
     async fn remove(&self, id: &BlockId) -> Result<RemoveResult> {
         let path = self._block_path(id);
-        match tokio::fs::remove_file(path).await {
+        match tokio::fs::remove_file(&path).await {
             Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
                 // File doesn't exist. Return false. This is not an error.
                 Ok(RemoveResult::NotRemovedBecauseItDoesntExist)
             }
-            Ok(()) => Ok(RemoveResult::SuccessfullyRemoved),
+            Ok(()) => {
+                self._dir_modified(_parent_dir(&path)).await?;
+                Ok(RemoveResult::SuccessfullyRemoved)
+            }
             Err(err) => Err(err.into()),
         }
     }
@@ -132,14 +188,14 @@ impl OptimizedBlockStoreWriter for OnDiskBlockStore {
         if path_exists(&path).await? {
             Ok(TryCreateResult::NotCreatedBecauseBlockIdAlreadyExists)
         } else {
-            _store(&path, data).await?;
+            self._store(&path, data).await?;
             Ok(TryCreateResult::SuccessfullyCreated)
         }
     }
 
     async fn store_optimized(&self, id: &BlockId, data: BlockData) -> Result<()> {
         let path = self._block_path(id);
-        _store(&path, data).await
+        self._store(&path, data).await
     }
 }
 
@@ -162,13 +218,109 @@ impl AsyncDrop for OnDiskBlockStore {
     }
 }
 
-impl LLBlockStore for OnDiskBlockStore {}
+#[async_trait]
+impl LLBlockStore for OnDiskBlockStore {
+    async fn sync(&self) -> Result<()> {
+        match self.fsync_policy {
+            FsyncPolicy::Never | FsyncPolicy::Always => Ok(()),
+            FsyncPolicy::OnFsync => {
+                let unsynced = std::mem::take(&mut *self.unsynced.lock().unwrap());
+                let result = async {
+                    future::try_join_all(
+                        unsynced
+                            .files
+                            .iter()
+                            .map(|file| _fsync_file_if_exists(file)),
+                    )
+                    .await?;
+                    future::try_join_all(unsynced.dirs.iter().map(|dir| _fsync_dir(dir))).await?;
+                    Ok(())
+                }
+                .await;
+                if result.is_err() {
+                    // We don't know which of the paths got synced, so we need to sync all of them again next time.
+                    let mut still_unsynced = self.unsynced.lock().unwrap();
+                    still_unsynced.files.extend(unsynced.files);
+                    still_unsynced.dirs.extend(unsynced.dirs);
+                }
+                result
+            }
+        }
+    }
+}
 
 impl OnDiskBlockStore {
     fn _block_path(&self, block_id: &BlockId) -> PathBuf {
         _block_path(self.basedir.as_path(), block_id)
     }
 
+    async fn _store(&self, path: &Path, data: BlockData) -> Result<()> {
+        let parent_dir = _parent_dir(path);
+        let created_parent_dir =
+            _create_dir_if_doesnt_exist(parent_dir)
+                .await
+                .with_context(|| {
+                    format!(
+                        "Failed to create parent directory for block file at {}",
+                        path.display()
+                    )
+                })?;
+        if created_parent_dir {
+            self._dir_modified(&self.basedir).await?;
+        }
+
+        let mut data = data.extract();
+        data.grow_region_fail_if_reallocation_necessary(FORMAT_VERSION_HEADER.len(), 0)
+            .expect("Tried to grow data region to store in OnDiskBlockStore::_store");
+        // TODO Use binary-layout here?
+        data.as_mut()[..FORMAT_VERSION_HEADER.len()].copy_from_slice(FORMAT_VERSION_HEADER);
+
+        // Write to a temporary file and atomically rename it so that a crash can't leave a partially written block behind
+        let temp_path = _temp_path(path);
+        let write_result =
+            _write_file(&temp_path, &data, self.fsync_policy == FsyncPolicy::Always).await;
+        let write_result = match write_result {
+            Ok(()) => tokio::fs::rename(&temp_path, path).await.with_context(|| {
+                format!(
+                    "Failed to rename {} to {}",
+                    temp_path.display(),
+                    path.display()
+                )
+            }),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = write_result {
+            if let Err(remove_err) = tokio::fs::remove_file(&temp_path).await
+                && remove_err.kind() != std::io::ErrorKind::NotFound
+            {
+                log::warn!(
+                    "Failed to remove temporary block file at {}: {remove_err:?}",
+                    temp_path.display()
+                );
+            }
+            return Err(err)
+                .with_context(|| format!("Failed to write to block file at {}", path.display()));
+        }
+
+        if self.fsync_policy == FsyncPolicy::OnFsync {
+            self.unsynced.lock().unwrap().files.insert(path.to_owned());
+        }
+        self._dir_modified(parent_dir).await
+    }
+
+    /// Called after an entry in `dir` was added, replaced or removed.
+    /// Makes sure the change gets synced according to the [FsyncPolicy].
+    async fn _dir_modified(&self, dir: &Path) -> Result<()> {
+        match self.fsync_policy {
+            FsyncPolicy::Never => Ok(()),
+            FsyncPolicy::OnFsync => {
+                self.unsynced.lock().unwrap().dirs.insert(dir.to_owned());
+                Ok(())
+            }
+            FsyncPolicy::Always => _fsync_dir(dir).await,
+        }
+    }
+
     async fn _all_block_files(&self) -> Result<impl Stream<Item = Result<DirEntry>> + use<>> {
         Ok(
             ReadDirStream::new(tokio::fs::read_dir(&self.basedir).await?)
@@ -276,7 +428,7 @@ fn _blockid_from_filepath(path: &Path) -> BlockId {
         .unwrap()
 }
 
-fn _check_and_remove_header(mut data: Data) -> Result<Data> {
+pub(super) fn _check_and_remove_header(mut data: Data) -> Result<Data> {
     if !data.starts_with(FORMAT_VERSION_HEADER) {
         if data.starts_with(FORMAT_VERSION_HEADER_PREFIX) {
             bail!(
@@ -294,42 +446,134 @@ fn _check_and_remove_header(mut data: Data) -> Result<Data> {
     Ok(data)
 }
 
+/// Returns true if the directory was created and false if it already existed
 // TODO Test
-async fn _create_dir_if_doesnt_exist(dir: &Path) -> Result<()> {
+async fn _create_dir_if_doesnt_exist(dir: &Path) -> Result<bool> {
     match tokio::fs::create_dir(dir).await {
-        Ok(()) => Ok(()),
+        Ok(()) => Ok(true),
         Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
             // This is ok, we only want to create the directory if it doesn't exist yet
-            Ok(())
+            Ok(false)
         }
         Err(err) => Err(err.into()),
     }
 }
 
-fn _is_allowed_blockid_character(c: char) -> bool {
+pub(super) fn _is_allowed_blockid_character(c: char) -> bool {
     c.is_ascii_digit() || ('A'..='F').contains(&c)
 }
 
-async fn _store(path: &Path, data: BlockData) -> Result<()> {
-    _create_dir_if_doesnt_exist(
-        path.parent()
-            .expect("Block file path should have a parent directory"),
-    )
-    .await
-    .with_context(|| {
-        format!(
-            "Failed to create parent directory for block file at {}",
-            path.display()
-        )
-    })?;
-    let mut data = data.extract();
-    data.grow_region_fail_if_reallocation_necessary(FORMAT_VERSION_HEADER.len(), 0)
-        .expect("Tried to grow data region to store in OnDiskBlockStore::_store");
-    // TODO Use binary-layout here?
-    data.as_mut()[..FORMAT_VERSION_HEADER.len()].copy_from_slice(FORMAT_VERSION_HEADER);
-    tokio::fs::write(&path, data)
+async fn _write_file(path: &Path, data: &[u8], fsync: bool) -> Result<()> {
+    let mut file = tokio::fs::File::create(path).await?;
+    file.write_all(data).await?;
+    if fsync {
+        file.sync_data().await?;
+    } else {
+        file.flush().await?;
+    }
+    Ok(())
+}
+
+async fn _fsync_file_if_exists(path: &Path) -> Result<()> {
+    match tokio::fs::File::open(path).await {
+        Ok(file) => file
+            .sync_data()
+            .await
+            .with_context(|| format!("Failed to fsync block file at {}", path.display())),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            // The block was removed after it was written. Removing it marked its directory as modified,
+            // so the removal gets synced when we sync the directory.
+            Ok(())
+        }
+        Err(err) => {
+            Err(err).with_context(|| format!("Failed to open block file at {}", path.display()))
+        }
+    }
+}
+
+#[cfg(unix)]
+pub(super) async fn _fsync_dir(path: &Path) -> Result<()> {
+    let dir = tokio::fs::File::open(path)
         .await
-        .with_context(|| format!("Failed to write to block file at {}", path.display()))
+        .with_context(|| format!("Failed to open directory {}", path.display()))?;
+    dir.sync_all()
+        .await
+        .with_context(|| format!("Failed to fsync directory {}", path.display()))
+}
+
+#[cfg(not(unix))]
+pub(super) async fn _fsync_dir(_path: &Path) -> Result<()> {
+    // Directories can't be opened for syncing on Windows, renames are synced together with the file there.
+    Ok(())
+}
+
+fn _parent_dir(block_path: &Path) -> &Path {
+    block_path
+        .parent()
+        .expect("Block file path should have a parent directory")
+}
+
+/// Delete temporary files that a crash left behind in the block directories.
+/// This assumes that no other process is writing to the block store at the same time.
+/// Failures are only logged, they don't keep the block store from being opened.
+fn _remove_stale_temp_files(basedir: &Path) {
+    let subdirs = match std::fs::read_dir(basedir) {
+        Ok(subdirs) => subdirs,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return,
+        Err(err) => {
+            log::warn!(
+                "Failed to list {} to remove stale temporary files: {err:?}",
+                basedir.display()
+            );
+            return;
+        }
+    };
+    for subdir in subdirs.flatten() {
+        let is_block_dir = subdir.file_name().to_str().is_some_and(|name| {
+            name.len() == PREFIX_LEN && name.chars().all(_is_allowed_blockid_character)
+        });
+        if !is_block_dir || !subdir.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+            continue;
+        }
+        let entries = match std::fs::read_dir(subdir.path()) {
+            Ok(entries) => entries,
+            Err(err) => {
+                log::warn!(
+                    "Failed to list {} to remove stale temporary files: {err:?}",
+                    subdir.path().display()
+                );
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            let is_temp_file = entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.ends_with(TEMP_FILE_SUFFIX));
+            if is_temp_file {
+                log::info!("Removing stale temporary file {}", entry.path().display());
+                if let Err(err) = std::fs::remove_file(entry.path()) {
+                    log::warn!(
+                        "Failed to remove stale temporary file {}: {err:?}",
+                        entry.path().display()
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Temporary files have a different name length than block files, so [OnDiskBlockStore::all_blocks] ignores them.
+fn _temp_path(block_path: &Path) -> PathBuf {
+    let file_name = block_path
+        .file_name()
+        .expect("Block file path should have a file name")
+        .to_str()
+        .expect("Block file path should be valid UTF-8");
+    block_path.with_file_name(format!(
+        "{file_name}.{:016x}{TEMP_FILE_SUFFIX}",
+        rand::random::<u64>()
+    ))
 }
 
 fn _block_path(basedir: &Path, block_id: &BlockId) -> PathBuf {
@@ -373,7 +617,7 @@ mod tests {
             Self { basedir }
         }
         async fn store(&mut self) -> AsyncDropGuard<OnDiskBlockStore> {
-            OnDiskBlockStore::new(self.basedir.path().to_path_buf())
+            OnDiskBlockStore::new(self.basedir.path().to_path_buf(), FsyncPolicy::OnFsync)
         }
         async fn yield_fixture(&self, _store: &Self::ConcreteBlockStore) {}
     }
@@ -382,7 +626,8 @@ mod tests {
 
     #[tokio::test]
     async fn test_block_path() {
-        let mut block_store = OnDiskBlockStore::new(PathBuf::from("/base/path"));
+        let mut block_store =
+            OnDiskBlockStore::new(PathBuf::from("/base/path"), FsyncPolicy::OnFsync);
         assert_eq!(
             Path::new("/base/path/2AC/9C78D80937AD50852C50BD3F1F982"),
             block_store._block_path(
@@ -514,4 +759,212 @@ mod tests {
 
         store.async_drop().await.unwrap();
     }
+
+    fn _files_in_prefix_dir(basedir: &Path, block_id: &BlockId) -> Vec<String> {
+        let block_path = _block_path(basedir, block_id);
+        let mut files: Vec<String> = std::fs::read_dir(_parent_dir(&block_path))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[tokio::test]
+    async fn test_whenStoringBlock_thenDoesntLeaveTempFileBehind() {
+        let mut fixture = TestFixture::new();
+        let mut store = fixture.store().await;
+
+        store.store(&blockid(0), &data(500, 0)).await.unwrap();
+        store.store(&blockid(0), &data(500, 1)).await.unwrap();
+
+        let block_path = _block_path(fixture.basedir.path(), &blockid(0));
+        assert_eq!(
+            vec![
+                block_path
+                    .file_name()
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .to_string()
+            ],
+            _files_in_prefix_dir(fixture.basedir.path(), &blockid(0)),
+        );
+
+        store.async_drop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_givenLeftoverTempFile_whenListingBlocks_thenIgnoresIt() {
+        let mut fixture = TestFixture::new();
+        let mut store = fixture.store().await;
+
+        store.store(&blockid(0), &data(500, 0)).await.unwrap();
+        let temp_path = _temp_path(&_block_path(fixture.basedir.path(), &blockid(1)));
+        std::fs::create_dir_all(temp_path.parent().unwrap()).unwrap();
+        std::fs::write(&temp_path, b"partially written").unwrap();
+
+        let blocks: Vec<BlockId> = store
+            .all_blocks()
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(vec![blockid(0)], blocks);
+        assert_eq!(1, store.num_blocks().await.unwrap());
+        assert_eq!(None, store.load(&blockid(1)).await.unwrap());
+
+        store.async_drop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_givenLeftoverTempFile_whenOpening_thenRemovesIt() {
+        let mut fixture = TestFixture::new();
+        let mut store = fixture.store().await;
+        store.store(&blockid(0), &data(500, 0)).await.unwrap();
+        store.async_drop().await.unwrap();
+
+        let block_path = _block_path(fixture.basedir.path(), &blockid(0));
+        std::fs::write(_temp_path(&block_path), b"partially written").unwrap();
+        assert_eq!(
+            2,
+            _files_in_prefix_dir(fixture.basedir.path(), &blockid(0)).len()
+        );
+
+        let mut store = fixture.store().await;
+        assert_eq!(
+            vec![
+                block_path
+                    .file_name()
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .to_string()
+            ],
+            _files_in_prefix_dir(fixture.basedir.path(), &blockid(0)),
+        );
+        assert_eq!(Some(data(500, 0)), store.load(&blockid(0)).await.unwrap());
+
+        store.async_drop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_givenOnFsyncPolicy_whenSyncing_thenForgetsUnsyncedPaths() {
+        let mut fixture = TestFixture::new();
+        let mut store = fixture.store().await;
+
+        store.store(&blockid(0), &data(500, 0)).await.unwrap();
+        store.store(&blockid(1), &data(500, 1)).await.unwrap();
+        {
+            let unsynced = store.unsynced.lock().unwrap();
+            assert_eq!(
+                HashSet::from([
+                    _block_path(fixture.basedir.path(), &blockid(0)),
+                    _block_path(fixture.basedir.path(), &blockid(1)),
+                ]),
+                unsynced.files,
+            );
+            assert!(unsynced.dirs.contains(fixture.basedir.path()));
+        }
+
+        store.sync().await.unwrap();
+        {
+            let unsynced = store.unsynced.lock().unwrap();
+            assert!(unsynced.files.is_empty());
+            assert!(unsynced.dirs.is_empty());
+        }
+
+        store.async_drop().await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_givenOnFsyncPolicy_whenSyncFails_thenKeepsUnsyncedPaths() {
+        let mut fixture = TestFixture::new();
+        let mut store = fixture.store().await;
+
+        store.store(&blockid(0), &data(500, 0)).await.unwrap();
+        let block_dir = _parent_dir(&_block_path(fixture.basedir.path(), &blockid(0))).to_owned();
+        // Syncing a directory that doesn't exist anymore fails
+        std::fs::remove_dir_all(&block_dir).unwrap();
+
+        store.sync().await.unwrap_err();
+        {
+            let unsynced = store.unsynced.lock().unwrap();
+            assert!(unsynced.dirs.contains(&block_dir));
+        }
+        // Retrying doesn't pretend that the failed paths got synced
+        store.sync().await.unwrap_err();
+
+        std::fs::create_dir_all(&block_dir).unwrap();
+        store.sync().await.unwrap();
+
+        store.async_drop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_givenOnFsyncPolicy_whenRemovingBlockBeforeSync_thenSyncSucceeds() {
+        let mut fixture = TestFixture::new();
+        let mut store = fixture.store().await;
+
+        store.store(&blockid(0), &data(500, 0)).await.unwrap();
+        assert_eq!(
+            RemoveResult::SuccessfullyRemoved,
+            store.remove(&blockid(0)).await.unwrap()
+        );
+        store.sync().await.unwrap();
+
+        store.async_drop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_givenNeverOrAlwaysPolicy_whenStoring_thenDoesntRememberUnsyncedPaths() {
+        for policy in [FsyncPolicy::Never, FsyncPolicy::Always] {
+            let basedir = tempfile::tempdir().unwrap();
+            let mut store = OnDiskBlockStore::new(basedir.path().to_path_buf(), policy);
+
+            store.store(&blockid(0), &data(500, 0)).await.unwrap();
+            assert_eq!(
+                RemoveResult::SuccessfullyRemoved,
+                store.remove(&blockid(0)).await.unwrap()
+            );
+            {
+                let unsynced = store.unsynced.lock().unwrap();
+                assert!(unsynced.files.is_empty());
+                assert!(unsynced.dirs.is_empty());
+            }
+            store.sync().await.unwrap();
+
+            store.async_drop().await.unwrap();
+        }
+    }
+
+    mod fsync_always {
+        use super::*;
+
+        struct TestFixture {
+            basedir: TempDir,
+        }
+        #[async_trait]
+        impl LLFixture for TestFixture {
+            type ConcreteBlockStore = OnDiskBlockStore;
+            fn new() -> Self {
+                let basedir = tempfile::Builder::new()
+                    .prefix("OnDiskBlockStoreTest")
+                    .tempdir()
+                    .unwrap();
+                Self { basedir }
+            }
+            async fn store(&mut self) -> AsyncDropGuard<OnDiskBlockStore> {
+                OnDiskBlockStore::new(self.basedir.path().to_path_buf(), FsyncPolicy::Always)
+            }
+            async fn yield_fixture(&self, _store: &Self::ConcreteBlockStore) {}
+        }
+
+        instantiate_blockstore_tests_for_lowlevel_blockstore!(
+            TestFixture,
+            (flavor = "multi_thread")
+        );
+    }
 }
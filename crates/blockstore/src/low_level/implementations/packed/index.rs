@@ -0,0 +1,517 @@
+use anyhow::{Context, Result, bail, ensure};
+use binrw::{BinRead, BinWrite};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::io::Cursor;
+use std::num::NonZeroU8;
+
+use super::pack_file::{PACK_HEADER, PackId, RecordHeader, RecordKind};
+use crate::BlockId;
+use cryfs_crypto::symmetric::CipherDef;
+use cryfs_utils::{
+    binary::{
+        BinaryReadExt, BinaryWriteExt, read_hashmap, read_null_string, write_hashmap,
+        write_null_string,
+    },
+    data::Data,
+};
+
+const INDEX_HEADER: &[u8] = b"cryfs;packindex;0";
+
+/// Where a record is stored
+#[derive(BinRead, BinWrite, Debug, Clone, Copy, PartialEq, Eq)]
+#[brw(little)]
+pub struct RecordLocation {
+    pub pack: PackId,
+    pub offset: u64,
+    pub data_len: u32,
+    pub sequence_number: u64,
+}
+
+#[derive(BinRead, BinWrite, Debug, Clone, Copy, PartialEq, Eq)]
+#[brw(little)]
+pub struct PackInfo {
+    /// Length of the valid part of the pack file. Records get appended here.
+    pub len: u64,
+    /// Number of bytes in the pack belonging to records that are still needed
+    pub live_bytes: u64,
+}
+
+impl PackInfo {
+    fn new() -> Self {
+        Self {
+            len: PACK_HEADER.len() as u64,
+            live_bytes: 0,
+        }
+    }
+
+    /// Fraction of the records in this pack that are still needed
+    pub fn live_ratio(&self) -> f64 {
+        let records_len = self.len - PACK_HEADER.len() as u64;
+        if records_len == 0 {
+            0.0
+        } else {
+            self.live_bytes as f64 / records_len as f64
+        }
+    }
+}
+
+/// In-memory index of the [super::PackedBlockStore], mapping block ids to the records in the pack files.
+///
+/// Besides the current record of each block, the index counts the superseded store records that are still
+/// in some pack file for each block. As long as there are any, the removal record for that block has to be kept
+/// in the packs, otherwise rebuilding the index from the pack contents would bring the removed block back.
+#[derive(Debug, PartialEq)]
+pub struct Index {
+    record_header_len: u64,
+    next_sequence_number: u64,
+    next_pack_id: u64,
+    packs: HashMap<PackId, PackInfo>,
+    blocks: HashMap<BlockId, RecordLocation>,
+    tombstones: HashMap<BlockId, RecordLocation>,
+    stale_stores: HashMap<BlockId, u32>,
+}
+
+impl Index {
+    pub fn new<C: CipherDef>() -> Self {
+        Self {
+            record_header_len: super::pack_file::record_header_len::<C>() as u64,
+            next_sequence_number: 0,
+            next_pack_id: 0,
+            packs: HashMap::new(),
+            blocks: HashMap::new(),
+            tombstones: HashMap::new(),
+            stale_stores: HashMap::new(),
+        }
+    }
+
+    pub fn block(&self, block_id: &BlockId) -> Option<&RecordLocation> {
+        self.blocks.get(block_id)
+    }
+
+    pub fn num_blocks(&self) -> u64 {
+        self.blocks.len() as u64
+    }
+
+    pub fn block_ids(&self) -> impl Iterator<Item = &BlockId> {
+        self.blocks.keys()
+    }
+
+    pub fn pack(&self, pack: PackId) -> Option<&PackInfo> {
+        self.packs.get(&pack)
+    }
+
+    pub fn pack_ids(&self) -> impl Iterator<Item = PackId> + '_ {
+        self.packs.keys().copied()
+    }
+
+    pub fn next_sequence_number(&mut self) -> u64 {
+        let sequence_number = self.next_sequence_number;
+        self.next_sequence_number += 1;
+        sequence_number
+    }
+
+    pub fn add_pack(&mut self) -> PackId {
+        let pack = PackId(self.next_pack_id);
+        self.next_pack_id += 1;
+        let previous = self.packs.insert(pack, PackInfo::new());
+        assert!(previous.is_none(), "Pack {pack:?} already exists");
+        pack
+    }
+
+    /// Adds a pack found on disk that the index doesn't know about yet
+    pub fn add_existing_pack(&mut self, pack: PackId) {
+        self.next_pack_id = self.next_pack_id.max(pack.0 + 1);
+        self.packs.entry(pack).or_insert_with(PackInfo::new);
+    }
+
+    fn record_len(&self, data_len: u32) -> u64 {
+        self.record_header_len + u64::from(data_len)
+    }
+
+    /// Updates the index after a record was written to or found in a pack.
+    /// Records can be applied in any order, the one with the highest sequence number wins.
+    pub fn apply(&mut self, pack: PackId, offset: u64, header: &RecordHeader) {
+        let location = RecordLocation {
+            pack,
+            offset,
+            data_len: header.data_len,
+            sequence_number: header.sequence_number,
+        };
+        let record_len = self.record_len(header.data_len);
+        let pack_info = self
+            .packs
+            .get_mut(&pack)
+            .expect("Record is in a pack we don't know");
+        pack_info.len = pack_info.len.max(offset + record_len);
+        self.next_sequence_number = self.next_sequence_number.max(header.sequence_number + 1);
+
+        let current = self
+            .blocks
+            .get(&header.block_id)
+            .map(|location| (RecordKind::Store, *location))
+            .or_else(|| {
+                self.tombstones
+                    .get(&header.block_id)
+                    .map(|location| (RecordKind::Remove, *location))
+            });
+        if let Some((current_kind, current_location)) = current {
+            if current_location.sequence_number > header.sequence_number {
+                // The record we're applying is already superseded
+                if header.kind == RecordKind::Store {
+                    *self.stale_stores.entry(header.block_id).or_insert(0) += 1;
+                }
+                return;
+            }
+            // The current record gets superseded by the one we're applying
+            let current_record_len = self.record_len(current_location.data_len);
+            self.packs
+                .get_mut(&current_location.pack)
+                .expect("Record is in a pack we don't know")
+                .live_bytes -= current_record_len;
+            match current_kind {
+                RecordKind::Store => {
+                    self.blocks.remove(&header.block_id);
+                    *self.stale_stores.entry(header.block_id).or_insert(0) += 1;
+                }
+                RecordKind::Remove => {
+                    self.tombstones.remove(&header.block_id);
+                }
+            }
+        }
+
+        self.packs
+            .get_mut(&pack)
+            .expect("Record is in a pack we don't know")
+            .live_bytes += record_len;
+        match header.kind {
+            RecordKind::Store => {
+                self.blocks.insert(header.block_id, location);
+            }
+            RecordKind::Remove => {
+                self.tombstones.insert(header.block_id, location);
+            }
+        }
+    }
+
+    /// Returns true if the record at the given location is the current record of its block
+    pub fn is_live(&self, pack: PackId, offset: u64, header: &RecordHeader) -> bool {
+        let current = match header.kind {
+            RecordKind::Store => self.blocks.get(&header.block_id),
+            RecordKind::Remove => self.tombstones.get(&header.block_id),
+        };
+        current.is_some_and(|current| current.pack == pack && current.offset == offset)
+    }
+
+    /// Number of superseded store records for this block that are still in some pack
+    pub fn num_stale_stores(&self, block_id: &BlockId) -> u32 {
+        self.stale_stores.get(block_id).copied().unwrap_or(0)
+    }
+
+    /// Updates the index after a live record was copied to a different place during compaction
+    pub fn relocate(&mut self, pack: PackId, offset: u64, header: &RecordHeader) {
+        let record_len = self.record_len(header.data_len);
+        let current = match header.kind {
+            RecordKind::Store => self.blocks.get_mut(&header.block_id),
+            RecordKind::Remove => self.tombstones.get_mut(&header.block_id),
+        }
+        .expect("Relocated record must be live");
+        assert_eq!(header.sequence_number, current.sequence_number);
+        let old_pack = current.pack;
+        current.pack = pack;
+        current.offset = offset;
+        self.packs
+            .get_mut(&old_pack)
+            .expect("Record is in a pack we don't know")
+            .live_bytes -= record_len;
+        let pack_info = self
+            .packs
+            .get_mut(&pack)
+            .expect("Record is in a pack we don't know");
+        pack_info.live_bytes += record_len;
+        pack_info.len = pack_info.len.max(offset + record_len);
+    }
+
+    /// Forgets about a pack after compaction copied all its live records somewhere else.
+    /// `stale_stores` are the blocks of the superseded store records in that pack,
+    /// one entry per record.
+    pub fn remove_pack(&mut self, pack: PackId, stale_stores: impl IntoIterator<Item = BlockId>) {
+        debug_assert!(
+            !self.blocks.values().any(|location| location.pack == pack),
+            "Pack {pack:?} still has live blocks"
+        );
+        // Removal records that are still in the pack weren't needed anymore and were dropped
+        self.tombstones.retain(|_, location| location.pack != pack);
+        for block_id in stale_stores {
+            match self.stale_stores.entry(block_id) {
+                Entry::Occupied(mut entry) => {
+                    *entry.get_mut() -= 1;
+                    if *entry.get() == 0 {
+                        entry.remove();
+                    }
+                }
+                Entry::Vacant(_) => panic!("Stale store count for {block_id:?} underflowed"),
+            }
+        }
+        self.packs.remove(&pack);
+    }
+
+    /// Returns the pack with the smallest fraction of live data if that fraction is below `threshold`
+    pub fn compaction_candidate(
+        &self,
+        threshold: f64,
+        exclude: impl Fn(PackId) -> bool,
+    ) -> Option<PackId> {
+        self.packs
+            .iter()
+            .filter(|(pack, _)| !exclude(**pack))
+            .map(|(pack, info)| (*pack, info.live_ratio()))
+            .filter(|(_, live_ratio)| *live_ratio < threshold)
+            .min_by(|(lhs_pack, lhs_ratio), (rhs_pack, rhs_ratio)| {
+                lhs_ratio.total_cmp(rhs_ratio).then(lhs_pack.cmp(rhs_pack))
+            })
+            .map(|(pack, _)| pack)
+    }
+
+    pub fn serialize<C: CipherDef>(&self, cipher: &C) -> Result<Vec<u8>> {
+        let serialized = IndexSerialized {
+            header: INDEX_HEADER
+                .iter()
+                .map(|c| NonZeroU8::new(*c).expect("Header can't contain null bytes"))
+                .collect(),
+            next_sequence_number: self.next_sequence_number,
+            next_pack_id: self.next_pack_id,
+            packs: self.packs.clone(),
+            blocks: self.blocks.clone(),
+            tombstones: self.tombstones.clone(),
+            stale_stores: self.stale_stores.clone(),
+        };
+        let mut plaintext = Cursor::new(Vec::new());
+        serialized.serialize_to_stream(&mut plaintext)?;
+        let plaintext = plaintext.into_inner();
+        let mut data = Data::allocate(
+            C::CIPHERTEXT_OVERHEAD_PREFIX,
+            plaintext.len(),
+            C::CIPHERTEXT_OVERHEAD_SUFFIX,
+        );
+        data.as_mut().copy_from_slice(&plaintext);
+        let ciphertext = cipher.encrypt(data)?;
+        Ok(ciphertext.into_vec())
+    }
+
+    pub fn deserialize<C: CipherDef>(cipher: &C, ciphertext: Vec<u8>) -> Result<Self> {
+        let plaintext = cipher
+            .decrypt(Data::from(ciphertext))
+            .context("Failed to decrypt index")?;
+        let serialized =
+            IndexSerialized::deserialize_from_complete_stream(&mut Cursor::new(plaintext.as_ref()))
+                .context("Failed to parse index")?;
+        if serialized
+            .header
+            .iter()
+            .map(|c| c.get())
+            .ne(INDEX_HEADER.iter().copied())
+        {
+            bail!(
+                "Index has an unsupported format version. Maybe it was created with a newer version of CryFS?"
+            );
+        }
+        let index = Self {
+            record_header_len: super::pack_file::record_header_len::<C>() as u64,
+            next_sequence_number: serialized.next_sequence_number,
+            next_pack_id: serialized.next_pack_id,
+            packs: serialized.packs,
+            blocks: serialized.blocks,
+            tombstones: serialized.tombstones,
+            stale_stores: serialized.stale_stores,
+        };
+        index.check_consistency()?;
+        Ok(index)
+    }
+
+    fn check_consistency(&self) -> Result<()> {
+        for location in self.blocks.values().chain(self.tombstones.values()) {
+            let pack = self.packs.get(&location.pack);
+            ensure!(
+                pack.is_some_and(
+                    |pack| location.offset + self.record_len(location.data_len) <= pack.len
+                ),
+                "Index points to a record outside of the known packs"
+            );
+            ensure!(
+                location.sequence_number < self.next_sequence_number,
+                "Index has records with sequence numbers it didn't assign yet"
+            );
+        }
+        ensure!(
+            self.packs.keys().all(|pack| pack.0 < self.next_pack_id),
+            "Index has packs with ids it didn't assign yet"
+        );
+        Ok(())
+    }
+}
+
+#[derive(BinRead, BinWrite)]
+#[brw(little)]
+struct IndexSerialized {
+    #[br(parse_with = read_null_string)]
+    #[bw(write_with = write_null_string)]
+    header: Vec<NonZeroU8>,
+
+    next_sequence_number: u64,
+    next_pack_id: u64,
+
+    #[br(parse_with = read_hashmap)]
+    #[bw(write_with = write_hashmap)]
+    packs: HashMap<PackId, PackInfo>,
+
+    #[br(parse_with = read_hashmap)]
+    #[bw(write_with = write_hashmap)]
+    blocks: HashMap<BlockId, RecordLocation>,
+
+    #[br(parse_with = read_hashmap)]
+    #[bw(write_with = write_hashmap)]
+    tombstones: HashMap<BlockId, RecordLocation>,
+
+    #[br(parse_with = read_hashmap)]
+    #[bw(write_with = write_hashmap)]
+    stale_stores: HashMap<BlockId, u32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::utils::blockid;
+    use cryfs_crypto::symmetric::{EncryptionKey, XChaCha20Poly1305};
+
+    type C = XChaCha20Poly1305;
+
+    fn cipher(seed: u8) -> C {
+        C::new(
+            EncryptionKey::new(C::KEY_SIZE, |key| {
+                key.fill(seed);
+                Ok::<_, std::convert::Infallible>(())
+            })
+            .unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn header(kind: RecordKind, block: u64, sequence_number: u64, data_len: u32) -> RecordHeader {
+        RecordHeader {
+            kind,
+            block_id: blockid(block),
+            sequence_number,
+            data_len,
+        }
+    }
+
+    fn record_len(data_len: u32) -> u64 {
+        super::super::pack_file::record_header_len::<C>() as u64 + u64::from(data_len)
+    }
+
+    #[test]
+    fn test_store_and_overwrite() {
+        let mut index = Index::new::<C>();
+        let pack = index.add_pack();
+        let offset0 = PACK_HEADER.len() as u64;
+        index.apply(pack, offset0, &header(RecordKind::Store, 1, 0, 100));
+        let offset1 = offset0 + record_len(100);
+        index.apply(pack, offset1, &header(RecordKind::Store, 1, 1, 50));
+
+        assert_eq!(1, index.num_blocks());
+        assert_eq!(offset1, index.block(&blockid(1)).unwrap().offset);
+        assert_eq!(1, index.num_stale_stores(&blockid(1)));
+        assert_eq!(record_len(50), index.pack(pack).unwrap().live_bytes);
+        assert_eq!(offset1 + record_len(50), index.pack(pack).unwrap().len);
+        assert_eq!(2, index.next_sequence_number());
+    }
+
+    #[test]
+    fn test_records_applied_out_of_order() {
+        let mut index = Index::new::<C>();
+        let pack0 = index.add_pack();
+        let pack1 = index.add_pack();
+        let offset = PACK_HEADER.len() as u64;
+        // The removal was copied to a newer pack by compaction, the store it superseded is older
+        index.apply(pack1, offset, &header(RecordKind::Remove, 1, 5, 0));
+        index.apply(pack0, offset, &header(RecordKind::Store, 1, 3, 100));
+
+        assert_eq!(None, index.block(&blockid(1)));
+        assert_eq!(1, index.num_stale_stores(&blockid(1)));
+        assert_eq!(0, index.pack(pack0).unwrap().live_bytes);
+        assert_eq!(record_len(0), index.pack(pack1).unwrap().live_bytes);
+        assert!(index.is_live(pack1, offset, &header(RecordKind::Remove, 1, 5, 0)));
+        assert!(!index.is_live(pack0, offset, &header(RecordKind::Store, 1, 3, 100)));
+    }
+
+    #[test]
+    fn test_compaction_candidate() {
+        let mut index = Index::new::<C>();
+        let pack0 = index.add_pack();
+        let pack1 = index.add_pack();
+        let pack2 = index.add_pack();
+        let offset = PACK_HEADER.len() as u64;
+        index.apply(pack0, offset, &header(RecordKind::Store, 1, 0, 100));
+        index.apply(pack1, offset, &header(RecordKind::Store, 2, 1, 100));
+        index.apply(
+            pack1,
+            offset + record_len(100),
+            &header(RecordKind::Store, 3, 2, 100),
+        );
+        index.apply(pack2, offset, &header(RecordKind::Store, 2, 3, 100));
+
+        // pack0 is fully live, pack1 is half dead
+        assert_eq!(
+            Some(pack1),
+            index.compaction_candidate(0.6, |pack| pack == pack2)
+        );
+        assert_eq!(None, index.compaction_candidate(0.4, |pack| pack == pack2));
+        assert_eq!(None, index.compaction_candidate(0.6, |pack| pack == pack1));
+    }
+
+    #[test]
+    fn test_remove_pack() {
+        let mut index = Index::new::<C>();
+        let pack0 = index.add_pack();
+        let pack1 = index.add_pack();
+        let offset = PACK_HEADER.len() as u64;
+        index.apply(pack0, offset, &header(RecordKind::Store, 1, 0, 100));
+        index.apply(pack1, offset, &header(RecordKind::Remove, 1, 1, 0));
+        assert_eq!(1, index.num_stale_stores(&blockid(1)));
+
+        index.remove_pack(pack0, [blockid(1)]);
+        assert_eq!(0, index.num_stale_stores(&blockid(1)));
+        assert_eq!(None, index.pack(pack0));
+        // The removal record is still live until pack1 gets compacted
+        assert!(index.is_live(pack1, offset, &header(RecordKind::Remove, 1, 1, 0)));
+    }
+
+    #[test]
+    fn test_serialization_roundtrip() {
+        let mut index = Index::new::<C>();
+        let pack = index.add_pack();
+        let offset = PACK_HEADER.len() as u64;
+        index.apply(pack, offset, &header(RecordKind::Store, 1, 0, 100));
+        index.apply(
+            pack,
+            offset + record_len(100),
+            &header(RecordKind::Store, 2, 1, 10),
+        );
+        index.apply(
+            pack,
+            offset + record_len(100) + record_len(10),
+            &header(RecordKind::Remove, 1, 2, 0),
+        );
+
+        let serialized = index.serialize(&cipher(1)).unwrap();
+        assert_eq!(index, Index::deserialize(&cipher(1), serialized).unwrap());
+    }
+
+    #[test]
+    fn test_deserialize_with_wrong_key_fails() {
+        let index = Index::new::<C>();
+        let serialized = index.serialize(&cipher(1)).unwrap();
+        assert!(Index::deserialize(&cipher(2), serialized).is_err());
+    }
+}
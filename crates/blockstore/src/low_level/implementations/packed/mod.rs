@@ -0,0 +1,739 @@
+use anyhow::{Context, Result, bail};
+use async_trait::async_trait;
+use byte_unit::Byte;
+use futures::stream::{self, BoxStream, StreamExt};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{self, Debug};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::{Mutex, RwLock};
+
+use crate::low_level::interface::block_data::create_block_data_wrapper;
+use crate::{
+    BlockId, Overhead,
+    low_level::{
+        BlockStoreDeleter, BlockStoreReader, LLBlockStore, OptimizedBlockStoreWriter,
+        interface::block_data::IBlockData,
+    },
+    utils::{RemoveResult, TryCreateResult},
+};
+use cryfs_crypto::symmetric::CipherDef;
+use cryfs_utils::{
+    async_drop::{AsyncDrop, AsyncDropGuard},
+    data::Data,
+    periodic_task::PeriodicTask,
+};
+
+use super::ondisk::{_fsync_dir, FsyncPolicy, sysinfo};
+
+mod index;
+mod pack_file;
+
+use index::Index;
+use pack_file::{
+    PACK_HEADER, PackId, RecordHeader, RecordKind, ScanResult, read_pack, record_header_len,
+    scan_records,
+};
+
+const INDEX_FILE_NAME: &str = "index";
+const INDEX_TEMP_FILE_NAME: &str = "index.tmp";
+const INDEX_FILE_HEADER: &[u8] = b"cryfs;packindex\0";
+
+/// Configuration for a [PackedBlockStore]
+#[derive(Debug, Clone)]
+pub struct PackedBlockStoreConfig {
+    /// Once a pack file reaches this size, new blocks get appended to a new pack file.
+    pub max_pack_size: u64,
+
+    /// Packs whose records are less than this fraction still needed get compacted,
+    /// i.e. their live blocks get copied to the newest pack and the pack gets deleted.
+    pub compaction_threshold: f64,
+
+    /// How often to look for packs to compact in the background. `None` disables background compaction,
+    /// [PackedBlockStore::compact] can still be called manually.
+    pub compaction_interval: Option<Duration>,
+
+    /// When to force written packs to the physical disk. See [FsyncPolicy].
+    pub fsync_policy: FsyncPolicy,
+}
+
+impl Default for PackedBlockStoreConfig {
+    fn default() -> Self {
+        Self {
+            max_pack_size: 16 * 1024 * 1024,
+            compaction_threshold: 0.5,
+            compaction_interval: Some(Duration::from_secs(60)),
+            fsync_policy: FsyncPolicy::default(),
+        }
+    }
+}
+
+/// A block store that appends blocks to a small number of large pack files instead of storing each block in its own file.
+/// This keeps the number of files low, which matters for cloud sync clients and file systems with limited inodes.
+///
+/// Each record in a pack file is a block (or the removal of a block) with an encrypted header containing the block id
+/// and a sequence number. Overwriting or removing a block appends a new record and leaves the old one behind as dead data.
+/// Background compaction rewrites packs that are mostly dead.
+///
+/// Which record is the current one for each block is tracked in an in-memory index. The index gets written to an encrypted
+/// index file when the block store is dropped and after compaction. When opening the block store, records that were appended
+/// after the index file was written get replayed from the pack files. If the index file is missing or doesn't match the packs,
+/// e.g. after a crash, it gets rebuilt from the pack contents.
+pub struct PackedBlockStore<C: CipherDef + Send + Sync + 'static> {
+    inner: Arc<Inner<C>>,
+    compaction_task: Option<AsyncDropGuard<PeriodicTask>>,
+}
+
+struct Inner<C> {
+    basedir: PathBuf,
+    cipher: C,
+    config: PackedBlockStoreConfig,
+    state: RwLock<State>,
+    // Serializes writing the index file. Always locked before `state` if both are locked.
+    index_file_lock: Mutex<()>,
+}
+
+struct State {
+    index: Index,
+
+    /// The pack new records get appended to. `None` if the next record should start a new pack.
+    active_pack: Option<ActivePack>,
+
+    /// Packs that were written to since they were last synced
+    unsynced_packs: HashSet<PackId>,
+
+    /// Packs with data we couldn't read when opening the block store. They could be the result of a crash
+    /// while writing, but also of corruption that hides records we still need, so they never get compacted away.
+    damaged_packs: HashSet<PackId>,
+
+    /// Whether pack files were created or deleted since the base directory was last synced
+    basedir_modified: bool,
+
+    /// Whether the index changed since the index file was last written
+    index_modified: bool,
+}
+
+struct ActivePack {
+    id: PackId,
+    file: tokio::fs::File,
+}
+
+impl<C: CipherDef + Send + Sync + 'static> PackedBlockStore<C> {
+    /// Opens the block store in `basedir`, which must already exist. If there are no pack files yet, this creates an empty block store.
+    pub async fn new(
+        basedir: PathBuf,
+        cipher: C,
+        config: PackedBlockStoreConfig,
+    ) -> Result<AsyncDropGuard<Self>> {
+        let state = _open(&basedir, &cipher, &config).await?;
+        let inner = Arc::new(Inner {
+            basedir,
+            cipher,
+            config,
+            state: RwLock::new(state),
+            index_file_lock: Mutex::new(()),
+        });
+        let compaction_task = inner.config.compaction_interval.map(|interval| {
+            let inner = Arc::clone(&inner);
+            PeriodicTask::spawn("PackedBlockStore compaction", interval, move || {
+                let inner = Arc::clone(&inner);
+                async move { inner.compact().await }
+            })
+        });
+        Ok(AsyncDropGuard::new(Self {
+            inner,
+            compaction_task,
+        }))
+    }
+
+    /// Compacts all packs that are mostly dead and writes the index file if it changed.
+    /// This is also regularly called in the background, see [PackedBlockStoreConfig::compaction_interval].
+    pub async fn compact(&self) -> Result<()> {
+        self.inner.compact().await
+    }
+}
+
+#[async_trait]
+impl<C: CipherDef + Send + Sync + 'static> BlockStoreReader for PackedBlockStore<C> {
+    async fn exists(&self, id: &BlockId) -> Result<bool> {
+        Ok(self.inner.state.read().await.index.block(id).is_some())
+    }
+
+    async fn load(&self, id: &BlockId) -> Result<Option<Data>> {
+        // Keep the read lock while reading so compaction can't delete the pack in the meantime
+        let state = self.inner.state.read().await;
+        let Some(location) = state.index.block(id) else {
+            return Ok(None);
+        };
+        let path = location.pack.path(&self.inner.basedir);
+        let mut file = tokio::fs::File::open(&path)
+            .await
+            .with_context(|| format!("Failed to open pack file at {}", path.display()))?;
+        file.seek(std::io::SeekFrom::Start(
+            location.offset + record_header_len::<C>() as u64,
+        ))
+        .await?;
+        let mut data = vec![0; location.data_len as usize];
+        file.read_exact(&mut data).await.with_context(|| {
+            format!(
+                "Failed to read block {id:?} at offset {} of pack file at {}",
+                location.offset,
+                path.display()
+            )
+        })?;
+        Ok(Some(data.into()))
+    }
+
+    async fn num_blocks(&self) -> Result<u64> {
+        Ok(self.inner.state.read().await.index.num_blocks())
+    }
+
+    fn estimate_num_free_bytes(&self) -> Result<Byte> {
+        sysinfo::get_available_disk_space(&self.inner.basedir).map(Byte::from_u64)
+    }
+
+    fn overhead(&self) -> Overhead {
+        Overhead::new(Byte::from_u64(record_header_len::<C>() as u64))
+    }
+
+    async fn all_blocks(&self) -> Result<BoxStream<'static, Result<BlockId>>> {
+        let block_ids: Vec<BlockId> = self
+            .inner
+            .state
+            .read()
+            .await
+            .index
+            .block_ids()
+            .copied()
+            .collect();
+        Ok(stream::iter(block_ids.into_iter().map(Ok)).boxed())
+    }
+}
+
+#[async_trait]
+impl<C: CipherDef + Send + Sync + 'static> BlockStoreDeleter for PackedBlockStore<C> {
+    async fn remove(&self, id: &BlockId) -> Result<RemoveResult> {
+        let mut state = self.inner.state.write().await;
+        if state.index.block(id).is_none() {
+            return Ok(RemoveResult::NotRemovedBecauseItDoesntExist);
+        }
+        let header = RecordHeader {
+            kind: RecordKind::Remove,
+            block_id: *id,
+            sequence_number: state.index.next_sequence_number(),
+            data_len: 0,
+        };
+        let record = header.encrypt(&self.inner.cipher)?;
+        let (pack, offset) = self.inner._append(&mut state, record.as_ref()).await?;
+        state.index.apply(pack, offset, &header);
+        state.index_modified = true;
+        Ok(RemoveResult::SuccessfullyRemoved)
+    }
+}
+
+create_block_data_wrapper!(BlockData);
+
+#[async_trait]
+impl<C: CipherDef + Send + Sync + 'static> OptimizedBlockStoreWriter for PackedBlockStore<C> {
+    type BlockData = BlockData;
+
+    fn allocate(size: usize) -> BlockData {
+        let mut data = Data::from(vec![0; record_header_len::<C>() + size]);
+        data.shrink_to_subregion(record_header_len::<C>()..);
+        BlockData::new(data)
+    }
+
+    async fn try_create_optimized(&self, id: &BlockId, data: BlockData) -> Result<TryCreateResult> {
+        let mut state = self.inner.state.write().await;
+        if state.index.block(id).is_some() {
+            Ok(TryCreateResult::NotCreatedBecauseBlockIdAlreadyExists)
+        } else {
+            self.inner._store(&mut state, id, data).await?;
+            Ok(TryCreateResult::SuccessfullyCreated)
+        }
+    }
+
+    async fn store_optimized(&self, id: &BlockId, data: BlockData) -> Result<()> {
+        let mut state = self.inner.state.write().await;
+        self.inner._store(&mut state, id, data).await
+    }
+}
+
+impl<C: CipherDef + Send + Sync + 'static> Debug for PackedBlockStore<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PackedBlockStore")
+    }
+}
+
+#[async_trait]
+impl<C: CipherDef + Send + Sync + 'static> AsyncDrop for PackedBlockStore<C> {
+    type Error = anyhow::Error;
+    async fn async_drop_impl(&mut self) -> Result<()> {
+        if let Some(mut compaction_task) = self.compaction_task.take() {
+            compaction_task.async_drop().await?;
+        }
+        self.inner._write_index_if_modified().await
+    }
+}
+
+#[async_trait]
+impl<C: CipherDef + Send + Sync + 'static> LLBlockStore for PackedBlockStore<C> {
+    async fn sync(&self) -> Result<()> {
+        match self.inner.config.fsync_policy {
+            FsyncPolicy::Never | FsyncPolicy::Always => Ok(()),
+            FsyncPolicy::OnFsync => {
+                let (unsynced_packs, basedir_modified) = {
+                    let mut state = self.inner.state.write().await;
+                    (
+                        std::mem::take(&mut state.unsynced_packs),
+                        std::mem::take(&mut state.basedir_modified),
+                    )
+                };
+                for pack in unsynced_packs {
+                    _fsync_pack_if_exists(&pack.path(&self.inner.basedir)).await?;
+                }
+                if basedir_modified {
+                    _fsync_dir(&self.inner.basedir).await?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<C: CipherDef + Send + Sync> Inner<C> {
+    async fn _store(&self, state: &mut State, id: &BlockId, data: BlockData) -> Result<()> {
+        let mut data = data.extract();
+        let Ok(data_len) = u32::try_from(data.len()) else {
+            bail!(
+                "Block {id:?} has {} bytes, which is too large for a pack",
+                data.len()
+            );
+        };
+        let header = RecordHeader {
+            kind: RecordKind::Store,
+            block_id: *id,
+            sequence_number: state.index.next_sequence_number(),
+            data_len,
+        };
+        let encrypted_header = header.encrypt(&self.cipher)?;
+        data.grow_region_fail_if_reallocation_necessary(encrypted_header.len(), 0)
+            .expect("Tried to grow data region to store in PackedBlockStore::_store");
+        data.as_mut()[..encrypted_header.len()].copy_from_slice(encrypted_header.as_ref());
+
+        let (pack, offset) = self._append(state, data.as_ref()).await?;
+        state.index.apply(pack, offset, &header);
+        state.index_modified = true;
+        Ok(())
+    }
+
+    /// Appends a record to the active pack and returns where it was written.
+    /// The caller is responsible for updating the index.
+    async fn _append(&self, state: &mut State, record: &[u8]) -> Result<(PackId, u64)> {
+        let record_len = record.len() as u64;
+        let active_pack_is_full = match &state.active_pack {
+            None => true,
+            Some(active_pack) => {
+                let pack_len = self._pack_len(state, active_pack.id);
+                pack_len > PACK_HEADER.len() as u64
+                    && pack_len + record_len > self.config.max_pack_size
+            }
+        };
+        if active_pack_is_full {
+            self._start_new_pack(state).await?;
+        }
+
+        let active_pack = state
+            .active_pack
+            .as_mut()
+            .expect("We just made sure there is an active pack");
+        let pack = active_pack.id;
+        let offset = state
+            .index
+            .pack(pack)
+            .expect("Active pack must be in the index")
+            .len;
+        let write_result = _write_and_flush(
+            &mut active_pack.file,
+            record,
+            self.config.fsync_policy == FsyncPolicy::Always,
+        )
+        .await;
+        if let Err(err) = write_result {
+            // The pack may now end with a partially written record. Don't append anything after it,
+            // otherwise we couldn't recover those records when rebuilding the index.
+            state.active_pack = None;
+            return Err(err).with_context(|| {
+                format!(
+                    "Failed to write to pack file at {}",
+                    pack.path(&self.basedir).display()
+                )
+            });
+        }
+        if self.config.fsync_policy != FsyncPolicy::Always {
+            state.unsynced_packs.insert(pack);
+        }
+        Ok((pack, offset))
+    }
+
+    fn _pack_len(&self, state: &State, pack: PackId) -> u64 {
+        state
+            .index
+            .pack(pack)
+            .expect("Pack must be in the index")
+            .len
+    }
+
+    async fn _start_new_pack(&self, state: &mut State) -> Result<()> {
+        state.active_pack = None;
+        let pack = state.index.add_pack();
+        let path = pack.path(&self.basedir);
+        let file = async {
+            let mut file = tokio::fs::OpenOptions::new()
+                .create_new(true)
+                .write(true)
+                .open(&path)
+                .await?;
+            _write_and_flush(
+                &mut file,
+                PACK_HEADER,
+                self.config.fsync_policy == FsyncPolicy::Always,
+            )
+            .await?;
+            anyhow::Ok(file)
+        }
+        .await
+        .with_context(|| format!("Failed to create pack file at {}", path.display()))?;
+        self._basedir_modified(state).await?;
+        state.active_pack = Some(ActivePack { id: pack, file });
+        state.index_modified = true;
+        Ok(())
+    }
+
+    async fn _basedir_modified(&self, state: &mut State) -> Result<()> {
+        if self.config.fsync_policy == FsyncPolicy::Always {
+            _fsync_dir(&self.basedir).await
+        } else {
+            state.basedir_modified = true;
+            Ok(())
+        }
+    }
+
+    /// Syncs all packs written since they were last synced, independent of the [FsyncPolicy]
+    async fn _sync_all(&self, state: &mut State) -> Result<()> {
+        for pack in std::mem::take(&mut state.unsynced_packs) {
+            _fsync_pack_if_exists(&pack.path(&self.basedir)).await?;
+        }
+        if std::mem::take(&mut state.basedir_modified) {
+            _fsync_dir(&self.basedir).await?;
+        }
+        Ok(())
+    }
+
+    async fn compact(&self) -> Result<()> {
+        loop {
+            let mut state = self.state.write().await;
+            let active_pack = state.active_pack.as_ref().map(|active_pack| active_pack.id);
+            let Some(pack) = state
+                .index
+                .compaction_candidate(self.config.compaction_threshold, |pack| {
+                    Some(pack) == active_pack || state.damaged_packs.contains(&pack)
+                })
+            else {
+                break;
+            };
+            self._compact_pack(&mut state, pack).await?;
+        }
+        self._write_index_if_modified().await
+    }
+
+    /// Copies the records of the given pack that are still needed to the active pack and deletes the pack.
+    async fn _compact_pack(&self, state: &mut State, pack: PackId) -> Result<()> {
+        let path = pack.path(&self.basedir);
+        let content = read_pack(&path).await?;
+        let pack_len = self._pack_len(state, pack) as usize;
+        let ScanResult { records, valid_len } = scan_records(
+            &self.cipher,
+            &content[PACK_HEADER.len()..pack_len.min(content.len())],
+            PACK_HEADER.len() as u64,
+        );
+        if valid_len as usize != pack_len {
+            bail!(
+                "Pack file at {} is shorter than the index expects or has unreadable records",
+                path.display()
+            );
+        }
+
+        let mut stale_stores_in_pack: HashMap<BlockId, u32> = HashMap::new();
+        for record in &records {
+            if record.header.kind == RecordKind::Store
+                && !state.index.is_live(pack, record.offset, &record.header)
+            {
+                *stale_stores_in_pack
+                    .entry(record.header.block_id)
+                    .or_insert(0) += 1;
+            }
+        }
+
+        for record in &records {
+            if !state.index.is_live(pack, record.offset, &record.header) {
+                continue;
+            }
+            if record.header.kind == RecordKind::Remove {
+                // A removal record is only needed as long as there are older store records for the same block in other packs
+                let stale_stores_elsewhere = state.index.num_stale_stores(&record.header.block_id)
+                    - stale_stores_in_pack
+                        .get(&record.header.block_id)
+                        .copied()
+                        .unwrap_or(0);
+                if stale_stores_elsewhere == 0 {
+                    continue;
+                }
+            }
+            let record_len = record.header.record_len::<C>() as usize;
+            let record_bytes =
+                &content[record.offset as usize..record.offset as usize + record_len];
+            let (new_pack, new_offset) = self._append(state, record_bytes).await?;
+            state.index.relocate(new_pack, new_offset, &record.header);
+        }
+
+        // The copied records must be on disk before we delete the original ones
+        self._sync_all(state).await?;
+        tokio::fs::remove_file(&path)
+            .await
+            .with_context(|| format!("Failed to remove pack file at {}", path.display()))?;
+        // The deletion must be on disk before a later compaction drops removal records that were only needed because of this pack
+        _fsync_dir(&self.basedir).await?;
+        state.unsynced_packs.remove(&pack);
+        state.index.remove_pack(
+            pack,
+            stale_stores_in_pack
+                .into_iter()
+                .flat_map(|(block_id, count)| std::iter::repeat_n(block_id, count as usize)),
+        );
+        state.index_modified = true;
+        Ok(())
+    }
+
+    async fn _write_index_if_modified(&self) -> Result<()> {
+        let _index_file_lock = self.index_file_lock.lock().await;
+        let serialized = {
+            let mut state = self.state.write().await;
+            if !state.index_modified {
+                return Ok(());
+            }
+            // The index must not reference records that could still get lost
+            self._sync_all(&mut state).await?;
+            state.index_modified = false;
+            state.index.serialize(&self.cipher)?
+        };
+        let temp_path = self.basedir.join(INDEX_TEMP_FILE_NAME);
+        let path = self.basedir.join(INDEX_FILE_NAME);
+        let mut content = Vec::with_capacity(INDEX_FILE_HEADER.len() + serialized.len());
+        content.extend_from_slice(INDEX_FILE_HEADER);
+        content.extend_from_slice(&serialized);
+        let write_result = async {
+            let mut file = tokio::fs::File::create(&temp_path).await?;
+            _write_and_flush(&mut file, &content, true).await?;
+            tokio::fs::rename(&temp_path, &path).await?;
+            _fsync_dir(&self.basedir).await
+        }
+        .await;
+        if let Err(err) = write_result {
+            self.state.write().await.index_modified = true;
+            return Err(err)
+                .with_context(|| format!("Failed to write index file at {}", path.display()));
+        }
+        Ok(())
+    }
+}
+
+/// Loads the index and replays records that were written after the index file, or rebuilds the index from scratch
+/// if the index file is missing or doesn't match the packs.
+async fn _open<C: CipherDef>(
+    basedir: &Path,
+    cipher: &C,
+    config: &PackedBlockStoreConfig,
+) -> Result<State> {
+    let pack_files = _list_pack_files(basedir).await?;
+    let loaded_index = _load_index(basedir, cipher).await;
+    let mut index_modified = false;
+    let mut index = match loaded_index {
+        Some(index) if _index_matches_packs(&index, &pack_files) => index,
+        Some(_) => {
+            log::warn!(
+                "Index file doesn't match the pack files, rebuilding it from the pack files"
+            );
+            index_modified = true;
+            Index::new::<C>()
+        }
+        None => {
+            index_modified = true;
+            Index::new::<C>()
+        }
+    };
+
+    let mut damaged_packs = HashSet::new();
+    let mut newest_pack_is_appendable = false;
+    for (&pack, &file_len) in &pack_files {
+        let start_offset = match index.pack(pack) {
+            Some(pack_info) => pack_info.len,
+            None => {
+                index.add_existing_pack(pack);
+                index_modified = true;
+                PACK_HEADER.len() as u64
+            }
+        };
+        let mut has_invalid_tail = false;
+        if file_len > start_offset {
+            let path = pack.path(basedir);
+            let content = read_pack(&path).await?;
+            let scan_result = scan_records(cipher, &content[start_offset as usize..], start_offset);
+            for record in &scan_result.records {
+                index.apply(pack, record.offset, &record.header);
+            }
+            index_modified |= !scan_result.records.is_empty();
+            has_invalid_tail = scan_result.has_invalid_tail(content.len() as u64);
+            if has_invalid_tail {
+                log::warn!(
+                    "Pack file at {} has unreadable data after offset {}, probably from a crash while writing. Ignoring it.",
+                    path.display(),
+                    scan_result.valid_len
+                );
+                damaged_packs.insert(pack);
+            }
+        }
+        newest_pack_is_appendable = !has_invalid_tail;
+    }
+
+    // Continue appending to the newest pack unless it is full or we crashed while writing to it
+    let active_pack = match pack_files.keys().next_back() {
+        Some(&pack) if newest_pack_is_appendable => {
+            let pack_len = index.pack(pack).expect("We just added all packs").len;
+            if pack_len < config.max_pack_size {
+                let file = tokio::fs::OpenOptions::new()
+                    .append(true)
+                    .open(pack.path(basedir))
+                    .await
+                    .with_context(|| {
+                        format!(
+                            "Failed to open pack file at {}",
+                            pack.path(basedir).display()
+                        )
+                    })?;
+                Some(ActivePack { id: pack, file })
+            } else {
+                None
+            }
+        }
+        _ => None,
+    };
+
+    Ok(State {
+        index,
+        active_pack,
+        unsynced_packs: HashSet::new(),
+        damaged_packs,
+        basedir_modified: false,
+        index_modified,
+    })
+}
+
+/// Returns the pack files in the base directory with their file sizes.
+/// Pack files that are shorter than the pack header are left over from a crash while creating them, they get deleted.
+async fn _list_pack_files(basedir: &Path) -> Result<BTreeMap<PackId, u64>> {
+    let mut pack_files = BTreeMap::new();
+    let mut entries = tokio::fs::read_dir(basedir)
+        .await
+        .with_context(|| format!("Failed to read directory {}", basedir.display()))?;
+    while let Some(entry) = entries.next_entry().await? {
+        let Some(pack) = entry.file_name().to_str().and_then(PackId::from_file_name) else {
+            continue;
+        };
+        let file_len = entry.metadata().await?.len();
+        if file_len < PACK_HEADER.len() as u64 {
+            log::warn!(
+                "Removing incomplete pack file at {}",
+                entry.path().display()
+            );
+            tokio::fs::remove_file(entry.path()).await?;
+            continue;
+        }
+        pack_files.insert(pack, file_len);
+    }
+    Ok(pack_files)
+}
+
+/// Returns `None` if there is no index file or it can't be read. The index then gets rebuilt from the pack files.
+async fn _load_index<C: CipherDef>(basedir: &Path, cipher: &C) -> Option<Index> {
+    let path = basedir.join(INDEX_FILE_NAME);
+    let content = match tokio::fs::read(&path).await {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(err) => {
+            log::warn!(
+                "Failed to read index file at {}, rebuilding it from the pack files: {err:?}",
+                path.display()
+            );
+            return None;
+        }
+    };
+    let Some(ciphertext) = content.strip_prefix(INDEX_FILE_HEADER) else {
+        log::warn!(
+            "Index file at {} has an invalid header, rebuilding it from the pack files",
+            path.display()
+        );
+        return None;
+    };
+    match Index::deserialize(cipher, ciphertext.to_vec()) {
+        Ok(index) => Some(index),
+        Err(err) => {
+            log::warn!(
+                "Failed to load index file at {}, rebuilding it from the pack files: {err:?}",
+                path.display()
+            );
+            None
+        }
+    }
+}
+
+/// Each pack the index knows must still exist and be at least as long as the index expects.
+/// Packs can be longer if records were appended after the index was written.
+fn _index_matches_packs(index: &Index, pack_files: &BTreeMap<PackId, u64>) -> bool {
+    index.pack_ids().all(|pack| {
+        pack_files.get(&pack).is_some_and(|&file_len| {
+            file_len >= index.pack(pack).expect("Pack is in the index").len
+        })
+    })
+}
+
+async fn _write_and_flush(file: &mut tokio::fs::File, data: &[u8], fsync: bool) -> Result<()> {
+    file.write_all(data).await?;
+    if fsync {
+        file.sync_data().await?;
+    } else {
+        file.flush().await?;
+    }
+    Ok(())
+}
+
+async fn _fsync_pack_if_exists(path: &Path) -> Result<()> {
+    match tokio::fs::File::open(path).await {
+        Ok(file) => file
+            .sync_data()
+            .await
+            .with_context(|| format!("Failed to fsync pack file at {}", path.display())),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            // The pack was compacted away in the meantime
+            Ok(())
+        }
+        Err(err) => {
+            Err(err).with_context(|| format!("Failed to open pack file at {}", path.display()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,291 @@
+use anyhow::{Context, Result, bail};
+use binrw::{BinRead, BinWrite};
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use crate::{BLOCKID_LEN, BlockId};
+use cryfs_crypto::symmetric::CipherDef;
+use cryfs_utils::{
+    binary::{BinaryReadExt, BinaryWriteExt},
+    data::Data,
+};
+
+pub const PACK_HEADER_PREFIX: &[u8] = b"cryfs;pack;";
+pub const PACK_HEADER: &[u8] = b"cryfs;pack;0\0";
+
+const PACK_FILE_SUFFIX: &str = ".pack";
+const PACK_ID_HEX_LEN: usize = 16;
+
+/// Packs are numbered in the order they were created
+#[derive(BinRead, BinWrite, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[brw(little)]
+pub struct PackId(pub u64);
+
+impl PackId {
+    pub fn path(self, basedir: &Path) -> PathBuf {
+        basedir.join(format!("{:016x}{PACK_FILE_SUFFIX}", self.0))
+    }
+
+    /// Returns `None` if the file name doesn't belong to a pack file
+    pub fn from_file_name(file_name: &str) -> Option<Self> {
+        let hex = file_name.strip_suffix(PACK_FILE_SUFFIX)?;
+        if hex.len() != PACK_ID_HEX_LEN || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        u64::from_str_radix(hex, 16).ok().map(PackId)
+    }
+}
+
+#[derive(BinRead, BinWrite, Debug, Clone, Copy, PartialEq, Eq)]
+#[brw(little, repr = u8)]
+pub enum RecordKind {
+    /// The record stores the data of a block
+    Store = 1,
+    /// The record marks a block as removed. It doesn't have any data.
+    Remove = 2,
+}
+
+/// Each record in a pack file starts with this header. It is encrypted so that the pack files
+/// don't reveal which blocks they contain and which blocks were overwritten or removed.
+#[derive(BinRead, BinWrite, Debug, Clone, Copy, PartialEq, Eq)]
+#[brw(little)]
+pub struct RecordHeader {
+    pub kind: RecordKind,
+    pub block_id: BlockId,
+    /// Increases with every record written. If there are multiple records for the same block,
+    /// the one with the highest sequence number is the current one.
+    pub sequence_number: u64,
+    pub data_len: u32,
+}
+
+const RECORD_HEADER_PLAINTEXT_LEN: usize = 1 + BLOCKID_LEN + 8 + 4;
+
+pub const fn record_header_len<C: CipherDef>() -> usize {
+    C::CIPHERTEXT_OVERHEAD_PREFIX + RECORD_HEADER_PLAINTEXT_LEN + C::CIPHERTEXT_OVERHEAD_SUFFIX
+}
+
+impl RecordHeader {
+    pub fn encrypt<C: CipherDef>(&self, cipher: &C) -> Result<Data> {
+        let mut serialized = Cursor::new(Vec::with_capacity(RECORD_HEADER_PLAINTEXT_LEN));
+        self.serialize_to_stream(&mut serialized)?;
+        let serialized = serialized.into_inner();
+        assert_eq!(RECORD_HEADER_PLAINTEXT_LEN, serialized.len());
+        let mut plaintext = Data::allocate(
+            C::CIPHERTEXT_OVERHEAD_PREFIX,
+            RECORD_HEADER_PLAINTEXT_LEN,
+            C::CIPHERTEXT_OVERHEAD_SUFFIX,
+        );
+        plaintext.as_mut().copy_from_slice(&serialized);
+        let ciphertext = cipher.encrypt(plaintext)?;
+        assert_eq!(record_header_len::<C>(), ciphertext.len());
+        Ok(ciphertext)
+    }
+
+    pub fn decrypt<C: CipherDef>(cipher: &C, ciphertext: &[u8]) -> Result<Self> {
+        let plaintext = cipher.decrypt(Data::from(ciphertext.to_vec()))?;
+        RecordHeader::deserialize_from_complete_stream(&mut Cursor::new(plaintext.as_ref()))
+    }
+
+    pub fn record_len<C: CipherDef>(&self) -> u64 {
+        record_header_len::<C>() as u64 + u64::from(self.data_len)
+    }
+}
+
+/// A record found when scanning a pack file
+#[derive(Debug, Clone, Copy)]
+pub struct ScannedRecord {
+    pub offset: u64,
+    pub header: RecordHeader,
+}
+
+pub struct ScanResult {
+    pub records: Vec<ScannedRecord>,
+    /// Offset after the last valid record. Everything behind it is a partially written
+    /// record or otherwise unreadable.
+    pub valid_len: u64,
+}
+
+impl ScanResult {
+    pub fn has_invalid_tail(&self, file_len: u64) -> bool {
+        self.valid_len < file_len
+    }
+}
+
+/// Parses the records in `content`, which is the content of a pack file starting at `start_offset`.
+/// Stops at the first record that is incomplete or whose header can't be decrypted,
+/// e.g. because we crashed while writing it.
+pub fn scan_records<C: CipherDef>(cipher: &C, content: &[u8], start_offset: u64) -> ScanResult {
+    let header_len = record_header_len::<C>();
+    let mut records = Vec::new();
+    let mut pos = 0;
+    while content.len() - pos >= header_len {
+        let header = match RecordHeader::decrypt(cipher, &content[pos..pos + header_len]) {
+            Ok(header) => header,
+            Err(err) => {
+                log::warn!(
+                    "Found unreadable record at offset {}: {err:?}",
+                    start_offset + pos as u64
+                );
+                break;
+            }
+        };
+        let record_len = header_len + header.data_len as usize;
+        if content.len() - pos < record_len {
+            log::warn!(
+                "Found incomplete record at offset {}",
+                start_offset + pos as u64
+            );
+            break;
+        }
+        records.push(ScannedRecord {
+            offset: start_offset + pos as u64,
+            header,
+        });
+        pos += record_len;
+    }
+    ScanResult {
+        records,
+        valid_len: start_offset + pos as u64,
+    }
+}
+
+pub fn check_pack_header(content: &[u8]) -> Result<()> {
+    if !content.starts_with(PACK_HEADER) {
+        if content.starts_with(PACK_HEADER_PREFIX) {
+            bail!(
+                "This pack is not supported yet. Maybe it was created with a newer version of CryFS?"
+            );
+        } else {
+            bail!("This is not a valid pack file");
+        }
+    }
+    Ok(())
+}
+
+/// Reads the whole pack file and returns its content, with the pack header already checked.
+pub async fn read_pack(path: &Path) -> Result<Vec<u8>> {
+    let content = tokio::fs::read(path)
+        .await
+        .with_context(|| format!("Failed to read pack file at {}", path.display()))?;
+    check_pack_header(&content)
+        .with_context(|| format!("Failed to parse pack file at {}", path.display()))?;
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::utils::blockid;
+    use cryfs_crypto::symmetric::{EncryptionKey, XChaCha20Poly1305};
+
+    fn cipher() -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(
+            EncryptionKey::new(XChaCha20Poly1305::KEY_SIZE, |key| {
+                key.fill(5);
+                Ok::<_, std::convert::Infallible>(())
+            })
+            .unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn header(kind: RecordKind, data_len: u32) -> RecordHeader {
+        RecordHeader {
+            kind,
+            block_id: blockid(3),
+            sequence_number: 10,
+            data_len,
+        }
+    }
+
+    fn record(cipher: &XChaCha20Poly1305, header: RecordHeader) -> Vec<u8> {
+        let mut record = header.encrypt(cipher).unwrap().into_vec();
+        record.extend(std::iter::repeat_n(1u8, header.data_len as usize));
+        record
+    }
+
+    #[test]
+    fn test_pack_id_file_name() {
+        let path = PackId(0x2a).path(Path::new("/base"));
+        assert_eq!(Path::new("/base/000000000000002a.pack"), path);
+        assert_eq!(
+            Some(PackId(0x2a)),
+            PackId::from_file_name(path.file_name().unwrap().to_str().unwrap())
+        );
+        assert_eq!(None, PackId::from_file_name("000000000000002a.pack.tmp"));
+        assert_eq!(None, PackId::from_file_name("2a.pack"));
+        assert_eq!(None, PackId::from_file_name("index"));
+    }
+
+    #[test]
+    fn test_header_roundtrip() {
+        let cipher = cipher();
+        for header in [
+            header(RecordKind::Store, 500),
+            header(RecordKind::Remove, 0),
+        ] {
+            let encrypted = header.encrypt(&cipher).unwrap();
+            assert_eq!(
+                header,
+                RecordHeader::decrypt(&cipher, encrypted.as_ref()).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_scan_records() {
+        let cipher = cipher();
+        let mut content = record(&cipher, header(RecordKind::Store, 100));
+        content.extend(record(&cipher, header(RecordKind::Remove, 0)));
+
+        let result = scan_records(&cipher, &content, 20);
+        assert_eq!(2, result.records.len());
+        assert_eq!(20, result.records[0].offset);
+        assert_eq!(
+            20 + header(RecordKind::Store, 100).record_len::<XChaCha20Poly1305>(),
+            result.records[1].offset
+        );
+        assert_eq!(20 + content.len() as u64, result.valid_len);
+        assert!(!result.has_invalid_tail(20 + content.len() as u64));
+    }
+
+    #[test]
+    fn test_scan_records_stops_at_incomplete_record() {
+        let cipher = cipher();
+        let first = record(&cipher, header(RecordKind::Store, 100));
+        let mut content = first.clone();
+        let second = record(&cipher, header(RecordKind::Store, 100));
+        content.extend(&second[..second.len() - 1]);
+
+        let result = scan_records(&cipher, &content, 0);
+        assert_eq!(1, result.records.len());
+        assert_eq!(first.len() as u64, result.valid_len);
+        assert!(result.has_invalid_tail(content.len() as u64));
+    }
+
+    #[test]
+    fn test_scan_records_stops_at_corrupted_header() {
+        let cipher = cipher();
+        let first = record(&cipher, header(RecordKind::Store, 100));
+        let mut content = first.clone();
+        let mut second = record(&cipher, header(RecordKind::Store, 100));
+        second[5] ^= 1;
+        content.extend(second);
+
+        let result = scan_records(&cipher, &content, 0);
+        assert_eq!(1, result.records.len());
+        assert_eq!(first.len() as u64, result.valid_len);
+    }
+
+    #[test]
+    fn test_check_pack_header() {
+        check_pack_header(PACK_HEADER).unwrap();
+        assert!(
+            check_pack_header(b"cryfs;pack;1\0")
+                .unwrap_err()
+                .to_string()
+                .contains("newer version")
+        );
+        assert!(check_pack_header(b"something else").is_err());
+    }
+}
@@ -0,0 +1,433 @@
+#![allow(non_snake_case)]
+
+use futures::TryStreamExt;
+use std::io::Write;
+use tempfile::TempDir;
+
+use super::*;
+use crate::instantiate_blockstore_tests_for_lowlevel_blockstore;
+use crate::low_level::BlockStoreWriter;
+use crate::tests::{
+    low_level::LLFixture,
+    utils::{blockid, data},
+};
+use cryfs_crypto::symmetric::{EncryptionKey, XChaCha20Poly1305};
+
+type C = XChaCha20Poly1305;
+
+fn cipher() -> C {
+    C::new(
+        EncryptionKey::new(C::KEY_SIZE, |key| {
+            key.fill(7);
+            Ok::<_, std::convert::Infallible>(())
+        })
+        .unwrap(),
+    )
+    .unwrap()
+}
+
+fn config(max_pack_size: u64) -> PackedBlockStoreConfig {
+    PackedBlockStoreConfig {
+        max_pack_size,
+        compaction_threshold: 0.5,
+        compaction_interval: None,
+        fsync_policy: FsyncPolicy::OnFsync,
+    }
+}
+
+async fn open(basedir: &Path, max_pack_size: u64) -> AsyncDropGuard<PackedBlockStore<C>> {
+    PackedBlockStore::new(basedir.to_path_buf(), cipher(), config(max_pack_size))
+        .await
+        .unwrap()
+}
+
+struct TestFixture {
+    basedir: TempDir,
+}
+#[async_trait]
+impl LLFixture for TestFixture {
+    type ConcreteBlockStore = PackedBlockStore<C>;
+    fn new() -> Self {
+        let basedir = tempfile::Builder::new()
+            .prefix("PackedBlockStoreTest")
+            .tempdir()
+            .unwrap();
+        Self { basedir }
+    }
+    async fn store(&mut self) -> AsyncDropGuard<PackedBlockStore<C>> {
+        open(self.basedir.path(), 16 * 1024 * 1024).await
+    }
+    async fn yield_fixture(&self, _store: &Self::ConcreteBlockStore) {}
+}
+
+instantiate_blockstore_tests_for_lowlevel_blockstore!(TestFixture, (flavor = "multi_thread"));
+
+mod with_small_packs_and_background_compaction {
+    use super::*;
+
+    struct TestFixture {
+        basedir: TempDir,
+    }
+    #[async_trait]
+    impl LLFixture for TestFixture {
+        type ConcreteBlockStore = PackedBlockStore<C>;
+        fn new() -> Self {
+            let basedir = tempfile::Builder::new()
+                .prefix("PackedBlockStoreTest")
+                .tempdir()
+                .unwrap();
+            Self { basedir }
+        }
+        async fn store(&mut self) -> AsyncDropGuard<PackedBlockStore<C>> {
+            PackedBlockStore::new(
+                self.basedir.path().to_path_buf(),
+                cipher(),
+                PackedBlockStoreConfig {
+                    max_pack_size: 2048,
+                    compaction_threshold: 0.9,
+                    compaction_interval: Some(Duration::from_millis(1)),
+                    fsync_policy: FsyncPolicy::Never,
+                },
+            )
+            .await
+            .unwrap()
+        }
+        async fn yield_fixture(&self, store: &Self::ConcreteBlockStore) {
+            store.compact().await.unwrap();
+        }
+    }
+
+    instantiate_blockstore_tests_for_lowlevel_blockstore!(TestFixture, (flavor = "multi_thread"));
+}
+
+fn pack_files(basedir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(basedir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "pack"))
+        .collect();
+    files.sort();
+    files
+}
+
+async fn assert_blocks(store: &PackedBlockStore<C>, expected: &[(u64, Option<Data>)]) {
+    for (block, expected_data) in expected {
+        assert_eq!(
+            expected_data.as_ref().map(|data| data.as_ref()),
+            store
+                .load(&blockid(*block))
+                .await
+                .unwrap()
+                .as_ref()
+                .map(|data| data.as_ref()),
+            "Block {block}"
+        );
+    }
+    let num_existing = expected.iter().filter(|(_, data)| data.is_some()).count();
+    assert_eq!(num_existing as u64, store.num_blocks().await.unwrap());
+    let all_blocks: Vec<BlockId> = store
+        .all_blocks()
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(num_existing, all_blocks.len());
+}
+
+/// Stores some blocks, overwrites one of them and removes another one
+async fn make_changes(store: &PackedBlockStore<C>) -> Vec<(u64, Option<Data>)> {
+    for block in 0..10 {
+        store
+            .store(&blockid(block), &data(500, block))
+            .await
+            .unwrap();
+    }
+    store.store(&blockid(3), &data(300, 100)).await.unwrap();
+    assert_eq!(
+        RemoveResult::SuccessfullyRemoved,
+        store.remove(&blockid(5)).await.unwrap()
+    );
+    (0..10)
+        .map(|block| match block {
+            3 => (block, Some(data(300, 100))),
+            5 => (block, None),
+            _ => (block, Some(data(500, block))),
+        })
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_whenReopening_thenBlocksAreStillThere() {
+    let basedir = tempfile::tempdir().unwrap();
+    let mut store = open(basedir.path(), 16 * 1024 * 1024).await;
+    let expected = make_changes(&store).await;
+    store.async_drop().await.unwrap();
+    assert!(basedir.path().join(INDEX_FILE_NAME).exists());
+
+    let mut store = open(basedir.path(), 16 * 1024 * 1024).await;
+    assert_blocks(&store, &expected).await;
+    store.async_drop().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_givenMissingIndex_whenReopening_thenRebuildsIndexFromPacks() {
+    let basedir = tempfile::tempdir().unwrap();
+    let mut store = open(basedir.path(), 2048).await;
+    let expected = make_changes(&store).await;
+    store.async_drop().await.unwrap();
+    std::fs::remove_file(basedir.path().join(INDEX_FILE_NAME)).unwrap();
+
+    let mut store = open(basedir.path(), 2048).await;
+    assert_blocks(&store, &expected).await;
+    store.async_drop().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_givenCorruptedIndex_whenReopening_thenRebuildsIndexFromPacks() {
+    let basedir = tempfile::tempdir().unwrap();
+    let mut store = open(basedir.path(), 2048).await;
+    let expected = make_changes(&store).await;
+    store.async_drop().await.unwrap();
+    let index_path = basedir.path().join(INDEX_FILE_NAME);
+    let mut index_content = std::fs::read(&index_path).unwrap();
+    let last = index_content.len() - 1;
+    index_content[last] ^= 1;
+    std::fs::write(&index_path, index_content).unwrap();
+
+    let mut store = open(basedir.path(), 2048).await;
+    assert_blocks(&store, &expected).await;
+    store.async_drop().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_givenOutdatedIndex_whenReopening_thenReplaysNewerRecords() {
+    let basedir = tempfile::tempdir().unwrap();
+    let mut store = open(basedir.path(), 16 * 1024 * 1024).await;
+    let mut expected = make_changes(&store).await;
+    store.async_drop().await.unwrap();
+    let outdated_index = std::fs::read(basedir.path().join(INDEX_FILE_NAME)).unwrap();
+
+    let mut store = open(basedir.path(), 16 * 1024 * 1024).await;
+    store.store(&blockid(20), &data(100, 20)).await.unwrap();
+    assert_eq!(
+        RemoveResult::SuccessfullyRemoved,
+        store.remove(&blockid(0)).await.unwrap()
+    );
+    expected.push((20, Some(data(100, 20))));
+    expected[0] = (0, None);
+    store.async_drop().await.unwrap();
+    // Simulate a crash after writing the records but before writing the index
+    std::fs::write(basedir.path().join(INDEX_FILE_NAME), outdated_index).unwrap();
+
+    let mut store = open(basedir.path(), 16 * 1024 * 1024).await;
+    assert_blocks(&store, &expected).await;
+    store.async_drop().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_givenPartiallyWrittenRecord_whenReopening_thenIgnoresItAndAppendsToNewPack() {
+    let basedir = tempfile::tempdir().unwrap();
+    let mut store = open(basedir.path(), 16 * 1024 * 1024).await;
+    let mut expected = make_changes(&store).await;
+    store.async_drop().await.unwrap();
+    std::fs::remove_file(basedir.path().join(INDEX_FILE_NAME)).unwrap();
+    let packs = pack_files(basedir.path());
+    assert_eq!(1, packs.len());
+    std::fs::OpenOptions::new()
+        .append(true)
+        .open(&packs[0])
+        .unwrap()
+        .write_all(&[1; 100])
+        .unwrap();
+
+    let mut store = open(basedir.path(), 16 * 1024 * 1024).await;
+    assert_blocks(&store, &expected).await;
+    store.store(&blockid(20), &data(100, 20)).await.unwrap();
+    expected.push((20, Some(data(100, 20))));
+    store.async_drop().await.unwrap();
+    assert_eq!(2, pack_files(basedir.path()).len());
+
+    // Records written after the partially written one can be recovered too
+    std::fs::remove_file(basedir.path().join(INDEX_FILE_NAME)).unwrap();
+    let mut store = open(basedir.path(), 16 * 1024 * 1024).await;
+    assert_blocks(&store, &expected).await;
+    store.async_drop().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_givenIncompletePackHeader_whenReopening_thenRemovesPack() {
+    let basedir = tempfile::tempdir().unwrap();
+    let mut store = open(basedir.path(), 16 * 1024 * 1024).await;
+    let expected = make_changes(&store).await;
+    store.async_drop().await.unwrap();
+    let incomplete_pack = PackId(100).path(basedir.path());
+    std::fs::write(&incomplete_pack, &PACK_HEADER[..3]).unwrap();
+
+    let mut store = open(basedir.path(), 16 * 1024 * 1024).await;
+    assert_blocks(&store, &expected).await;
+    assert!(!incomplete_pack.exists());
+    store.async_drop().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_packsAreBoundedByMaxPackSize() {
+    let basedir = tempfile::tempdir().unwrap();
+    let mut store = open(basedir.path(), 2048).await;
+    for block in 0..20 {
+        store
+            .store(&blockid(block), &data(500, block))
+            .await
+            .unwrap();
+    }
+    store.async_drop().await.unwrap();
+
+    let packs = pack_files(basedir.path());
+    assert!(packs.len() >= 20 / 3, "Only {} packs", packs.len());
+    for pack in packs {
+        assert!(pack.metadata().unwrap().len() <= 2048);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_whenCompacting_thenRemovesMostlyDeadPacksAndKeepsBlocks() {
+    let basedir = tempfile::tempdir().unwrap();
+    let mut store = open(basedir.path(), 2048).await;
+    let mut expected = make_changes(&store).await;
+    for block in 0..8 {
+        if block != 5 {
+            assert_eq!(
+                RemoveResult::SuccessfullyRemoved,
+                store.remove(&blockid(block)).await.unwrap()
+            );
+            expected[block as usize] = (block, None);
+        }
+    }
+    let num_packs_before = pack_files(basedir.path()).len();
+
+    store.compact().await.unwrap();
+    let num_packs_after = pack_files(basedir.path()).len();
+    assert!(
+        num_packs_after < num_packs_before,
+        "{num_packs_after} packs after compaction, {num_packs_before} before"
+    );
+    assert_blocks(&store, &expected).await;
+    store.async_drop().await.unwrap();
+
+    // Removed blocks must not come back when rebuilding the index from the remaining packs
+    std::fs::remove_file(basedir.path().join(INDEX_FILE_NAME)).unwrap();
+    let mut store = open(basedir.path(), 2048).await;
+    assert_blocks(&store, &expected).await;
+    store.async_drop().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_givenAllBlocksRemoved_whenCompacting_thenNoRecordsAreLeft() {
+    let basedir = tempfile::tempdir().unwrap();
+    let mut store = open(basedir.path(), 2048).await;
+    let expected = make_changes(&store).await;
+    for (block, data) in &expected {
+        if data.is_some() {
+            assert_eq!(
+                RemoveResult::SuccessfullyRemoved,
+                store.remove(&blockid(*block)).await.unwrap()
+            );
+        }
+    }
+    // Start a new pack so that all the packs with records can be compacted
+    store.store(&blockid(50), &data(1500, 50)).await.unwrap();
+    assert_eq!(
+        RemoveResult::SuccessfullyRemoved,
+        store.remove(&blockid(50)).await.unwrap()
+    );
+    store.store(&blockid(51), &data(1500, 51)).await.unwrap();
+
+    store.compact().await.unwrap();
+    store.compact().await.unwrap();
+    let state = store.inner.state.read().await;
+    let active_pack = state.active_pack.as_ref().unwrap().id;
+    assert_eq!(
+        vec![active_pack],
+        state.index.pack_ids().collect::<Vec<_>>()
+    );
+    assert_eq!(
+        vec![active_pack.path(basedir.path())],
+        pack_files(basedir.path())
+    );
+    drop(state);
+    store.async_drop().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_packsAndIndexDontContainBlockIdsInPlaintext() {
+    let basedir = tempfile::tempdir().unwrap();
+    let mut store = open(basedir.path(), 16 * 1024 * 1024).await;
+    // Use block data that can't contain the block ids by chance
+    for block in 0..10 {
+        store.store(&blockid(block), &[0; 500]).await.unwrap();
+    }
+    assert_eq!(
+        RemoveResult::SuccessfullyRemoved,
+        store.remove(&blockid(5)).await.unwrap()
+    );
+    store.async_drop().await.unwrap();
+
+    let mut files = pack_files(basedir.path());
+    files.push(basedir.path().join(INDEX_FILE_NAME));
+    for file in files {
+        let content = std::fs::read(&file).unwrap();
+        for block in 0..10 {
+            let block_id = blockid(block);
+            assert!(
+                !content
+                    .windows(block_id.data().len())
+                    .any(|window| window == block_id.data()),
+                "{} contains block id {block_id:?}",
+                file.display()
+            );
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_givenWrongKey_whenReopening_thenDoesntFindBlocks() {
+    let basedir = tempfile::tempdir().unwrap();
+    let mut store = open(basedir.path(), 16 * 1024 * 1024).await;
+    make_changes(&store).await;
+    store.async_drop().await.unwrap();
+
+    let wrong_cipher = C::new(
+        EncryptionKey::new(C::KEY_SIZE, |key| {
+            key.fill(8);
+            Ok::<_, std::convert::Infallible>(())
+        })
+        .unwrap(),
+    )
+    .unwrap();
+    let mut store = PackedBlockStore::new(
+        basedir.path().to_path_buf(),
+        wrong_cipher,
+        config(16 * 1024 * 1024),
+    )
+    .await
+    .unwrap();
+    assert_eq!(0, store.num_blocks().await.unwrap());
+    store.async_drop().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_usable_block_size_from_physical_block_size() {
+    let basedir = tempfile::tempdir().unwrap();
+    let mut store = open(basedir.path(), 16 * 1024 * 1024).await;
+    let expected_overhead = Byte::from_u64(record_header_len::<C>() as u64);
+    assert_eq!(
+        Byte::from_u64(20),
+        store
+            .overhead()
+            .usable_block_size_from_physical_block_size(
+                expected_overhead.add(Byte::from_u64(20)).unwrap()
+            )
+            .unwrap()
+    );
+    store.async_drop().await.unwrap();
+}
@@ -100,7 +100,12 @@ impl<B: Sync + Send + Debug + AsyncDrop<Error = anyhow::Error>> AsyncDrop
     }
 }
 
+#[async_trait]
 impl<B: LLBlockStore + OptimizedBlockStoreWriter + Sync + Send + Debug> LLBlockStore
     for ReadOnlyBlockStore<B>
 {
+    async fn sync(&self) -> Result<()> {
+        // Nothing was written through this block store, so there is nothing to sync
+        Ok(())
+    }
 }
@@ -0,0 +1,469 @@
+use anyhow::{Context, Result, anyhow, bail};
+use async_trait::async_trait;
+use byte_unit::Byte;
+use futures::stream::{self, BoxStream, StreamExt};
+use rand::{RngExt, rng};
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, BufWriter, ReadHalf, WriteHalf};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+use super::protocol::{
+    ClientHello, ClientProof, HandshakeResult, MAX_FRAME_LEN, MAX_HANDSHAKE_FRAME_LEN,
+    PROTOCOL_HEADER, Request, RequestFrame, Response, ResponseFrame, Role, ServerHello,
+    compute_proof, decode_frame, encode_frame, read_frame, read_message, verify_proof,
+    write_message,
+};
+use super::{BlockServerSecret, RemoteAddress, Transport, connect};
+use crate::low_level::interface::block_data::create_block_data_wrapper;
+use crate::{
+    BlockId, Overhead,
+    low_level::{
+        BlockStoreDeleter, BlockStoreReader, LLBlockStore, OptimizedBlockStoreWriter,
+        interface::block_data::IBlockData,
+    },
+    utils::{RemoveResult, TryCreateResult},
+};
+use cryfs_utils::{
+    async_drop::{AsyncDrop, AsyncDropGuard},
+    data::Data,
+};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Requests that were queued but not sent yet. Sending blocks when this is full, which keeps a fast
+/// client from queuing up unbounded amounts of block data in memory.
+const MAX_QUEUED_REQUESTS: usize = 64;
+
+/// A block store that accesses the blocks of a block server run with [super::serve_blocks], e.g. the `cryfs-blockserver` binary.
+///
+/// Requests are pipelined, i.e. concurrent operations share one connection and don't wait for each other's responses.
+/// If the connection breaks, operations fail and the next operation tries to reconnect.
+///
+/// This store should be used below an [super::EncryptedBlockStore] so that the server only ever sees ciphertext.
+pub struct RemoteBlockStore {
+    address: RemoteAddress,
+    secret: BlockServerSecret,
+    connection: tokio::sync::Mutex<Option<Arc<Connection>>>,
+    overhead: Overhead,
+    /// [BlockStoreReader::estimate_num_free_bytes] can't wait for the server, so it returns the last value we got
+    /// and asks the server for a new one in the background
+    num_free_bytes: Arc<AtomicU64>,
+    refreshing_num_free_bytes: Arc<AtomicBool>,
+}
+
+impl RemoteBlockStore {
+    pub async fn connect(
+        address: RemoteAddress,
+        secret: BlockServerSecret,
+    ) -> Result<AsyncDropGuard<Self>> {
+        let (connection, server_info) = Connection::establish(&address, &secret).await?;
+        Ok(AsyncDropGuard::new(Self {
+            address,
+            secret,
+            connection: tokio::sync::Mutex::new(Some(Arc::new(connection))),
+            overhead: Overhead::new(Byte::from_u64(server_info.overhead)),
+            num_free_bytes: Arc::new(AtomicU64::new(server_info.num_free_bytes)),
+            refreshing_num_free_bytes: Arc::new(AtomicBool::new(false)),
+        }))
+    }
+
+    /// Returns the current connection, or connects again if it broke
+    async fn _connection(&self) -> Result<Arc<Connection>> {
+        let mut connection = self.connection.lock().await;
+        if let Some(connection) = &*connection
+            && !connection.is_closed()
+        {
+            return Ok(Arc::clone(connection));
+        }
+        log::info!("Reconnecting to block server at {}", self.address);
+        let (new_connection, server_info) =
+            Connection::establish(&self.address, &self.secret).await?;
+        if Overhead::new(Byte::from_u64(server_info.overhead)) != self.overhead {
+            bail!(
+                "The block server at {} changed its block overhead",
+                self.address
+            );
+        }
+        self.num_free_bytes
+            .store(server_info.num_free_bytes, Ordering::Relaxed);
+        let new_connection = Arc::new(new_connection);
+        *connection = Some(Arc::clone(&new_connection));
+        Ok(new_connection)
+    }
+
+    async fn _request(&self, request: Request) -> Result<Response> {
+        let response = self._connection().await?.request(request).await?;
+        if let Response::Error { message } = response {
+            bail!(
+                "The block server at {} failed: {}",
+                self.address,
+                String::from_utf8_lossy(&message)
+            );
+        }
+        Ok(response)
+    }
+}
+
+fn _unexpected_response(response: Response) -> anyhow::Error {
+    anyhow!("Unexpected response from block server: {response:?}")
+}
+
+#[async_trait]
+impl BlockStoreReader for RemoteBlockStore {
+    async fn exists(&self, id: &BlockId) -> Result<bool> {
+        match self._request(Request::Exists { block_id: *id }).await? {
+            Response::Bool { value } => Ok(value),
+            response => Err(_unexpected_response(response)),
+        }
+    }
+
+    async fn load(&self, id: &BlockId) -> Result<Option<Data>> {
+        match self._request(Request::Load { block_id: *id }).await? {
+            Response::Data { data } => Ok(Some(Data::from(data))),
+            Response::NotFound => Ok(None),
+            response => Err(_unexpected_response(response)),
+        }
+    }
+
+    async fn num_blocks(&self) -> Result<u64> {
+        match self._request(Request::NumBlocks).await? {
+            Response::Number { value } => Ok(value),
+            response => Err(_unexpected_response(response)),
+        }
+    }
+
+    fn estimate_num_free_bytes(&self) -> Result<Byte> {
+        let cached = Byte::from_u64(self.num_free_bytes.load(Ordering::Relaxed));
+        let connection = match self.connection.try_lock() {
+            Ok(connection) => connection.as_ref().map(Arc::clone),
+            Err(_) => None,
+        };
+        if let (Some(connection), Ok(runtime)) = (connection, tokio::runtime::Handle::try_current())
+            && !self.refreshing_num_free_bytes.swap(true, Ordering::Relaxed)
+        {
+            let num_free_bytes = Arc::clone(&self.num_free_bytes);
+            let refreshing = Arc::clone(&self.refreshing_num_free_bytes);
+            runtime.spawn(async move {
+                match connection.request(Request::EstimateNumFreeBytes).await {
+                    Ok(Response::Number { value }) => {
+                        num_free_bytes.store(value, Ordering::Relaxed)
+                    }
+                    Ok(response) => log::warn!(
+                        "Failed to refresh free space of block server: {:?}",
+                        _unexpected_response(response)
+                    ),
+                    Err(err) => {
+                        log::warn!("Failed to refresh free space of block server: {err:?}")
+                    }
+                }
+                refreshing.store(false, Ordering::Relaxed);
+            });
+        }
+        Ok(cached)
+    }
+
+    fn overhead(&self) -> Overhead {
+        self.overhead
+    }
+
+    async fn all_blocks(&self) -> Result<BoxStream<'static, Result<BlockId>>> {
+        match self._request(Request::AllBlocks).await? {
+            Response::BlockIds { block_ids } => {
+                Ok(stream::iter(block_ids.into_iter().map(Ok)).boxed())
+            }
+            response => Err(_unexpected_response(response)),
+        }
+    }
+}
+
+#[async_trait]
+impl BlockStoreDeleter for RemoteBlockStore {
+    async fn remove(&self, id: &BlockId) -> Result<RemoveResult> {
+        match self._request(Request::Remove { block_id: *id }).await? {
+            Response::Bool { value: true } => Ok(RemoveResult::SuccessfullyRemoved),
+            Response::Bool { value: false } => Ok(RemoveResult::NotRemovedBecauseItDoesntExist),
+            response => Err(_unexpected_response(response)),
+        }
+    }
+}
+
+create_block_data_wrapper!(BlockData);
+
+#[async_trait]
+impl OptimizedBlockStoreWriter for RemoteBlockStore {
+    type BlockData = BlockData;
+
+    fn allocate(size: usize) -> BlockData {
+        BlockData::new(Data::from(vec![0; size]))
+    }
+
+    async fn try_create_optimized(&self, id: &BlockId, data: BlockData) -> Result<TryCreateResult> {
+        let request = Request::TryCreate {
+            block_id: *id,
+            data: data.extract().into_vec(),
+        };
+        match self._request(request).await? {
+            Response::Bool { value: true } => Ok(TryCreateResult::SuccessfullyCreated),
+            Response::Bool { value: false } => {
+                Ok(TryCreateResult::NotCreatedBecauseBlockIdAlreadyExists)
+            }
+            response => Err(_unexpected_response(response)),
+        }
+    }
+
+    async fn store_optimized(&self, id: &BlockId, data: BlockData) -> Result<()> {
+        let request = Request::Store {
+            block_id: *id,
+            data: data.extract().into_vec(),
+        };
+        match self._request(request).await? {
+            Response::Done => Ok(()),
+            response => Err(_unexpected_response(response)),
+        }
+    }
+}
+
+impl Debug for RemoteBlockStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RemoteBlockStore")
+            .field("address", &self.address)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl AsyncDrop for RemoteBlockStore {
+    type Error = anyhow::Error;
+    async fn async_drop_impl(&mut self) -> Result<()> {
+        // All requests are done, otherwise we wouldn't be dropped. Dropping the connection stops its tasks.
+        self.connection.get_mut().take();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl LLBlockStore for RemoteBlockStore {
+    async fn sync(&self) -> Result<()> {
+        match self._request(Request::Sync).await? {
+            Response::Done => Ok(()),
+            response => Err(_unexpected_response(response)),
+        }
+    }
+}
+
+struct ServerInfo {
+    overhead: u64,
+    num_free_bytes: u64,
+}
+
+/// An authenticated connection to the block server. A writer task sends queued requests and a reader task
+/// passes responses to whoever is waiting for them, so any number of requests can be in flight at the same time.
+struct Connection {
+    next_request_id: AtomicU64,
+    pending: Arc<Mutex<PendingRequests>>,
+    send_queue: mpsc::Sender<Vec<u8>>,
+    reader_task: JoinHandle<()>,
+    writer_task: JoinHandle<()>,
+}
+
+struct PendingRequests {
+    /// Set when the connection broke. Contains the reason.
+    closed: Option<String>,
+    waiting: HashMap<u64, oneshot::Sender<Result<Response, String>>>,
+}
+
+impl PendingRequests {
+    fn close(&mut self, reason: String) {
+        for (_, waiting) in self.waiting.drain() {
+            let _ = waiting.send(Err(reason.clone()));
+        }
+        self.closed.get_or_insert(reason);
+    }
+}
+
+impl Connection {
+    async fn establish(
+        address: &RemoteAddress,
+        secret: &BlockServerSecret,
+    ) -> Result<(Self, ServerInfo)> {
+        tokio::time::timeout(CONNECT_TIMEOUT, Self::_establish(address, secret))
+            .await
+            .with_context(|| format!("Timeout connecting to block server at {address}"))?
+            .with_context(|| format!("Failed to connect to block server at {address}"))
+    }
+
+    async fn _establish(
+        address: &RemoteAddress,
+        secret: &BlockServerSecret,
+    ) -> Result<(Self, ServerInfo)> {
+        let (reader, writer) = tokio::io::split(connect(address).await?);
+        let mut reader = BufReader::new(reader);
+        let mut writer = BufWriter::new(writer);
+        let server_info = _handshake(&mut reader, &mut writer, secret).await?;
+
+        let pending = Arc::new(Mutex::new(PendingRequests {
+            closed: None,
+            waiting: HashMap::new(),
+        }));
+        let (send_queue, queued) = mpsc::channel(MAX_QUEUED_REQUESTS);
+        let reader_task = tokio::spawn(_receive_responses(reader, Arc::clone(&pending)));
+        let writer_task = tokio::spawn(_send_requests(writer, queued, Arc::clone(&pending)));
+        Ok((
+            Self {
+                next_request_id: AtomicU64::new(0),
+                pending,
+                send_queue,
+                reader_task,
+                writer_task,
+            },
+            server_info,
+        ))
+    }
+
+    fn is_closed(&self) -> bool {
+        self.pending.lock().unwrap().closed.is_some()
+    }
+
+    async fn request(&self, request: Request) -> Result<Response> {
+        let request_id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let frame = encode_frame(&RequestFrame {
+            request_id,
+            request,
+        })?;
+        let (sender, receiver) = oneshot::channel();
+        {
+            let mut pending = self.pending.lock().unwrap();
+            if let Some(reason) = &pending.closed {
+                bail!("Connection to block server broke: {reason}");
+            }
+            pending.waiting.insert(request_id, sender);
+        }
+        if self.send_queue.send(frame).await.is_err() {
+            self.pending.lock().unwrap().waiting.remove(&request_id);
+            bail!("Connection to block server was closed");
+        }
+        match receiver.await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(reason)) => Err(anyhow!("Connection to block server broke: {reason}")),
+            Err(_) => Err(anyhow!("Connection to block server was closed")),
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.reader_task.abort();
+        self.writer_task.abort();
+    }
+}
+
+async fn _handshake(
+    reader: &mut (impl AsyncRead + Unpin),
+    writer: &mut (impl AsyncWrite + Unpin),
+    secret: &BlockServerSecret,
+) -> Result<ServerInfo> {
+    let client_nonce = rng().random();
+    write_message(
+        writer,
+        &ClientHello {
+            header: *PROTOCOL_HEADER,
+            client_nonce,
+        },
+    )
+    .await?;
+
+    let hello: ServerHello = read_message(reader, MAX_HANDSHAKE_FRAME_LEN).await?;
+    // Check the server before proving ourselves so that we don't talk to a server that doesn't know the secret
+    if !verify_proof(
+        secret.as_bytes(),
+        Role::Server,
+        &client_nonce,
+        &hello.server_nonce,
+        &hello.server_proof,
+    ) {
+        bail!("The block server doesn't know the secret");
+    }
+    write_message(
+        writer,
+        &ClientProof {
+            client_proof: compute_proof(
+                secret.as_bytes(),
+                Role::Client,
+                &client_nonce,
+                &hello.server_nonce,
+            ),
+        },
+    )
+    .await?;
+
+    match read_message(reader, MAX_HANDSHAKE_FRAME_LEN).await? {
+        HandshakeResult::Accepted {
+            overhead,
+            num_free_bytes,
+        } => Ok(ServerInfo {
+            overhead,
+            num_free_bytes,
+        }),
+        HandshakeResult::Rejected => bail!("The block server rejected our secret"),
+    }
+}
+
+async fn _send_requests(
+    mut writer: BufWriter<WriteHalf<Box<dyn Transport>>>,
+    mut queued: mpsc::Receiver<Vec<u8>>,
+    pending: Arc<Mutex<PendingRequests>>,
+) {
+    let result: Result<()> = async {
+        while let Some(frame) = queued.recv().await {
+            writer.write_all(&frame).await?;
+            // Send requests that were queued at the same time together, but don't hold them back for later ones
+            if queued.is_empty() {
+                writer.flush().await?;
+            }
+        }
+        writer.shutdown().await?;
+        Ok(())
+    }
+    .await;
+    if let Err(err) = result {
+        pending
+            .lock()
+            .unwrap()
+            .close(format!("Failed to send request: {err:#}"));
+    }
+}
+
+async fn _receive_responses(
+    mut reader: BufReader<ReadHalf<Box<dyn Transport>>>,
+    pending: Arc<Mutex<PendingRequests>>,
+) {
+    let reason = loop {
+        let frame = match read_frame(&mut reader, MAX_FRAME_LEN).await {
+            Ok(Some(frame)) => frame,
+            Ok(None) => break "Connection closed by block server".to_string(),
+            Err(err) => break format!("Failed to receive response: {err:#}"),
+        };
+        let response = match decode_frame::<ResponseFrame>(&frame) {
+            Ok(response) => response,
+            Err(err) => break format!("Failed to parse response: {err:#}"),
+        };
+        let waiting = pending.lock().unwrap().waiting.remove(&response.request_id);
+        match waiting {
+            // If nobody waits anymore, the request future was dropped and the response isn't needed
+            Some(waiting) => {
+                let _ = waiting.send(Ok(response.response));
+            }
+            None => log::debug!(
+                "Got response for request {} that nobody waits for",
+                response.request_id
+            ),
+        }
+    };
+    log::warn!("{reason}");
+    pending.lock().unwrap().close(reason);
+}
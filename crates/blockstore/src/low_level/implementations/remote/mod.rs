@@ -0,0 +1,184 @@
+use anyhow::{Context, Result, ensure};
+use std::fmt::{self, Debug, Display};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+mod client;
+mod protocol;
+mod server;
+
+#[cfg(test)]
+mod tests;
+
+pub use client::RemoteBlockStore;
+pub use server::serve_blocks;
+
+/// Where a block server listens, e.g. `192.168.1.5:4000`, `tcp:[::1]:4000` or `unix:/run/cryfs-blockserver.sock`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RemoteAddress {
+    /// A `host:port` pair
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl FromStr for RemoteAddress {
+    type Err = anyhow::Error;
+
+    fn from_str(address: &str) -> Result<Self> {
+        #[cfg(unix)]
+        if let Some(path) = address.strip_prefix("unix:") {
+            ensure!(!path.is_empty(), "Missing socket path in {address:?}");
+            return Ok(Self::Unix(PathBuf::from(path)));
+        }
+        let host_and_port = address.strip_prefix("tcp:").unwrap_or(address);
+        ensure!(
+            host_and_port
+                .rsplit_once(':')
+                .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok()),
+            "Invalid address {address:?}, expected host:port or unix:/path/to/socket"
+        );
+        Ok(Self::Tcp(host_and_port.to_string()))
+    }
+}
+
+impl Display for RemoteAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(host_and_port) => write!(f, "tcp:{host_and_port}"),
+            #[cfg(unix)]
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// The secret shared between a block server and its clients. Clients that don't know it can't access the blocks.
+///
+/// The secret only authenticates the connection. The traffic itself isn't encrypted, but the blocks are already
+/// encrypted by the client, so the server and anyone listening on the network only see ciphertext and block ids.
+#[derive(Clone)]
+pub struct BlockServerSecret(Vec<u8>);
+
+impl BlockServerSecret {
+    /// Short secrets could be guessed by trying them against the server
+    pub const MIN_LEN: usize = 16;
+
+    pub fn new(secret: Vec<u8>) -> Result<Self> {
+        ensure!(
+            secret.len() >= Self::MIN_LEN,
+            "The block server secret must have at least {} bytes",
+            Self::MIN_LEN
+        );
+        Ok(Self(secret))
+    }
+
+    /// Reads the secret from a file. Leading and trailing whitespace is ignored so that the file can end with a newline.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read(path).with_context(|| {
+            format!("Failed to read block server secret from {}", path.display())
+        })?;
+        Self::new(content.trim_ascii().to_vec())
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Debug for BlockServerSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "BlockServerSecret(..)")
+    }
+}
+
+trait Transport: AsyncRead + AsyncWrite + Unpin + Send + 'static {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Transport for T {}
+
+async fn connect(address: &RemoteAddress) -> Result<Box<dyn Transport>> {
+    match address {
+        RemoteAddress::Tcp(host_and_port) => {
+            let stream = TcpStream::connect(host_and_port).await?;
+            // Requests are small and latency matters more than packet count
+            stream.set_nodelay(true)?;
+            Ok(Box::new(stream))
+        }
+        #[cfg(unix)]
+        RemoteAddress::Unix(path) => Ok(Box::new(UnixStream::connect(path).await?)),
+    }
+}
+
+/// A socket accepting connections for [serve_blocks]
+pub struct BlockServerListener {
+    inner: ListenerImpl,
+}
+
+enum ListenerImpl {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix {
+        listener: UnixListener,
+        path: PathBuf,
+    },
+}
+
+impl BlockServerListener {
+    pub async fn bind(address: &RemoteAddress) -> Result<Self> {
+        let inner = match address {
+            RemoteAddress::Tcp(host_and_port) => ListenerImpl::Tcp(
+                TcpListener::bind(host_and_port)
+                    .await
+                    .with_context(|| format!("Failed to listen on {address}"))?,
+            ),
+            #[cfg(unix)]
+            RemoteAddress::Unix(path) => ListenerImpl::Unix {
+                listener: UnixListener::bind(path)
+                    .with_context(|| format!("Failed to listen on {address}"))?,
+                path: path.clone(),
+            },
+        };
+        Ok(Self { inner })
+    }
+
+    /// The address clients can connect to. Useful to find out the port if the listener was bound to port 0.
+    pub fn local_address(&self) -> Result<RemoteAddress> {
+        match &self.inner {
+            ListenerImpl::Tcp(listener) => {
+                Ok(RemoteAddress::Tcp(listener.local_addr()?.to_string()))
+            }
+            #[cfg(unix)]
+            ListenerImpl::Unix { path, .. } => Ok(RemoteAddress::Unix(path.clone())),
+        }
+    }
+
+    /// Returns the connection and a description of the peer for log messages
+    async fn accept(&self) -> Result<(Box<dyn Transport>, String)> {
+        match &self.inner {
+            ListenerImpl::Tcp(listener) => {
+                let (stream, peer) = listener.accept().await?;
+                stream.set_nodelay(true)?;
+                Ok((Box::new(stream), peer.to_string()))
+            }
+            #[cfg(unix)]
+            ListenerImpl::Unix { listener, .. } => {
+                let (stream, _) = listener.accept().await?;
+                Ok((Box::new(stream), "unix socket peer".to_string()))
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for BlockServerListener {
+    fn drop(&mut self) {
+        // Otherwise, binding to the same path again would fail with "address in use"
+        if let ListenerImpl::Unix { path, .. } = &self.inner
+            && let Err(err) = std::fs::remove_file(path)
+        {
+            log::warn!("Failed to remove socket file {}: {err}", path.display());
+        }
+    }
+}
@@ -0,0 +1,478 @@
+//! Wire format of the block server protocol.
+//!
+//! Every message is a frame consisting of a little endian `u32` length followed by that many bytes of a
+//! binrw serialized message. A connection starts with a handshake in which client and server prove to each
+//! other that they know the shared secret:
+//!
+//! 1. Client -> server: [ClientHello] with the protocol header and a random client nonce
+//! 2. Server -> client: [ServerHello] with a random server nonce and the server's proof
+//! 3. Client -> server: [ClientProof]
+//! 4. Server -> client: [HandshakeResult]
+//!
+//! After that, the client sends [RequestFrame]s and the server answers each of them with a [ResponseFrame]
+//! carrying the same request id. The client doesn't have to wait for a response before sending the next
+//! request, and the server may answer requests in a different order than it received them.
+
+use anyhow::{Context, Result, bail, ensure};
+use binrw::{BinRead, BinWrite, binrw};
+use hmac::{Hmac, KeyInit, Mac};
+use sha2::Sha256;
+use std::io::Cursor;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::BlockId;
+use cryfs_utils::binary::{BinaryReadExt, BinaryWriteExt};
+
+pub const PROTOCOL_HEADER_PREFIX: &[u8] = b"cryfs;blockserver;";
+pub const PROTOCOL_HEADER: &[u8; 20] = b"cryfs;blockserver;0\0";
+
+pub const NONCE_LEN: usize = 32;
+pub const PROOF_LEN: usize = 32;
+
+/// Frames during the handshake are tiny. Limiting them means unauthenticated peers can't make us allocate much memory.
+pub const MAX_HANDSHAKE_FRAME_LEN: u32 = 1024;
+/// Limit for frames after the handshake. The largest ones are responses listing all block ids.
+pub const MAX_FRAME_LEN: u32 = 1 << 30;
+
+#[binrw]
+#[brw(little)]
+#[derive(Debug)]
+pub struct ClientHello {
+    pub header: [u8; 20],
+    pub client_nonce: [u8; NONCE_LEN],
+}
+
+impl ClientHello {
+    pub fn check_header(&self) -> Result<()> {
+        if &self.header != PROTOCOL_HEADER {
+            if self.header.starts_with(PROTOCOL_HEADER_PREFIX) {
+                bail!(
+                    "The client uses a protocol version that isn't supported yet. Maybe it runs a newer version of CryFS?"
+                );
+            } else {
+                bail!("The client doesn't speak the CryFS block server protocol");
+            }
+        }
+        Ok(())
+    }
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Debug)]
+pub struct ServerHello {
+    pub server_nonce: [u8; NONCE_LEN],
+    pub server_proof: [u8; PROOF_LEN],
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Debug)]
+pub struct ClientProof {
+    pub client_proof: [u8; PROOF_LEN],
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Debug)]
+pub enum HandshakeResult {
+    /// The client is authenticated. Also tells the client what it needs to know about the block store.
+    #[brw(magic = 1u8)]
+    Accepted { overhead: u64, num_free_bytes: u64 },
+    #[brw(magic = 2u8)]
+    Rejected,
+}
+
+/// Which side of the connection a proof comes from. Including it in the proof means the server's
+/// proof can't be replayed as the client's proof.
+#[derive(Clone, Copy)]
+pub enum Role {
+    Client,
+    Server,
+}
+
+/// Proof that the sender knows the shared secret, bound to the nonces of this connection so it can't be replayed
+pub fn compute_proof(
+    secret: &[u8],
+    role: Role,
+    client_nonce: &[u8; NONCE_LEN],
+    server_nonce: &[u8; NONCE_LEN],
+) -> [u8; PROOF_LEN] {
+    _proof_mac(secret, role, client_nonce, server_nonce)
+        .finalize()
+        .into_bytes()
+        .into()
+}
+
+/// Compares in constant time so that the comparison doesn't leak how much of the proof was correct
+pub fn verify_proof(
+    secret: &[u8],
+    role: Role,
+    client_nonce: &[u8; NONCE_LEN],
+    server_nonce: &[u8; NONCE_LEN],
+    proof: &[u8; PROOF_LEN],
+) -> bool {
+    _proof_mac(secret, role, client_nonce, server_nonce)
+        .verify_slice(proof)
+        .is_ok()
+}
+
+fn _proof_mac(
+    secret: &[u8],
+    role: Role,
+    client_nonce: &[u8; NONCE_LEN],
+    server_nonce: &[u8; NONCE_LEN],
+) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret).expect("HMAC-SHA256 can take keys of any size");
+    mac.update(match role {
+        Role::Client => b"client",
+        Role::Server => b"server",
+    });
+    mac.update(client_nonce);
+    mac.update(server_nonce);
+    mac
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Debug)]
+pub struct RequestFrame {
+    pub request_id: u64,
+    pub request: Request,
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Debug)]
+pub enum Request {
+    #[brw(magic = 1u8)]
+    Exists { block_id: BlockId },
+    #[brw(magic = 2u8)]
+    Load { block_id: BlockId },
+    #[brw(magic = 3u8)]
+    NumBlocks,
+    #[brw(magic = 4u8)]
+    EstimateNumFreeBytes,
+    #[brw(magic = 5u8)]
+    AllBlocks,
+    #[brw(magic = 6u8)]
+    Remove { block_id: BlockId },
+    #[brw(magic = 7u8)]
+    TryCreate {
+        block_id: BlockId,
+        #[br(temp)]
+        #[bw(calc = u32::try_from(data.len()).expect("Block too large"))]
+        data_len: u32,
+        #[br(count = data_len)]
+        data: Vec<u8>,
+    },
+    #[brw(magic = 8u8)]
+    Store {
+        block_id: BlockId,
+        #[br(temp)]
+        #[bw(calc = u32::try_from(data.len()).expect("Block too large"))]
+        data_len: u32,
+        #[br(count = data_len)]
+        data: Vec<u8>,
+    },
+    #[brw(magic = 9u8)]
+    Sync,
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Debug)]
+pub struct ResponseFrame {
+    pub request_id: u64,
+    pub response: Response,
+}
+
+#[binrw]
+#[brw(little)]
+pub enum Response {
+    /// Answer to requests that don't return anything
+    #[brw(magic = 1u8)]
+    Done,
+    /// Answer to [Request::Exists], and to [Request::Remove] and [Request::TryCreate] telling whether they did something
+    #[brw(magic = 2u8)]
+    Bool {
+        #[br(map = |value: u8| value != 0)]
+        #[bw(map = |value: &bool| u8::from(*value))]
+        value: bool,
+    },
+    #[brw(magic = 3u8)]
+    Data {
+        #[br(temp)]
+        #[bw(calc = u32::try_from(data.len()).expect("Block too large"))]
+        data_len: u32,
+        #[br(count = data_len)]
+        data: Vec<u8>,
+    },
+    #[brw(magic = 4u8)]
+    NotFound,
+    #[brw(magic = 5u8)]
+    Number { value: u64 },
+    #[brw(magic = 6u8)]
+    BlockIds {
+        #[br(temp)]
+        #[bw(calc = u64::try_from(block_ids.len()).expect("Too many blocks"))]
+        num_block_ids: u64,
+        #[br(count = num_block_ids)]
+        block_ids: Vec<BlockId>,
+    },
+    /// The block store on the server side returned an error
+    #[brw(magic = 7u8)]
+    Error {
+        #[br(temp)]
+        #[bw(calc = u32::try_from(message.len()).expect("Error message too long"))]
+        message_len: u32,
+        #[br(count = message_len)]
+        message: Vec<u8>,
+    },
+}
+
+impl std::fmt::Debug for Response {
+    // Don't print block data or long block id lists
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Done => write!(f, "Done"),
+            Self::Bool { value } => write!(f, "Bool({value})"),
+            Self::Data { data } => write!(f, "Data({} bytes)", data.len()),
+            Self::NotFound => write!(f, "NotFound"),
+            Self::Number { value } => write!(f, "Number({value})"),
+            Self::BlockIds { block_ids } => write!(f, "BlockIds({} ids)", block_ids.len()),
+            Self::Error { message } => write!(f, "Error({})", String::from_utf8_lossy(message)),
+        }
+    }
+}
+
+/// Serializes a message into a frame, including the length prefix
+pub fn encode_frame<M>(message: &M) -> Result<Vec<u8>>
+where
+    for<'a> M: BinWrite<Args<'a> = ()>,
+{
+    let mut frame = Cursor::new(vec![0; 4]);
+    frame.set_position(4);
+    message.serialize_to_stream(&mut frame)?;
+    let mut frame = frame.into_inner();
+    let len = u32::try_from(frame.len() - 4).context("Message too large")?;
+    frame[..4].copy_from_slice(&len.to_le_bytes());
+    Ok(frame)
+}
+
+pub fn decode_frame<M>(frame: &[u8]) -> Result<M>
+where
+    for<'a> M: BinRead<Args<'a> = ()>,
+{
+    M::deserialize_from_complete_stream(&mut Cursor::new(frame))
+}
+
+/// Reads the next frame and returns its content without the length prefix.
+/// Returns `None` if the peer closed the connection between two frames.
+pub async fn read_frame(
+    reader: &mut (impl AsyncRead + Unpin),
+    max_len: u32,
+) -> Result<Option<Vec<u8>>> {
+    let mut len = [0; 4];
+    let mut num_read = 0;
+    while num_read < len.len() {
+        let n = reader.read(&mut len[num_read..]).await?;
+        if n == 0 {
+            ensure!(num_read == 0, "Connection closed in the middle of a frame");
+            return Ok(None);
+        }
+        num_read += n;
+    }
+    let len = u32::from_le_bytes(len);
+    ensure!(
+        len <= max_len,
+        "Frame has {len} bytes, which is more than the allowed {max_len} bytes"
+    );
+    let mut frame = vec![0; len as usize];
+    reader
+        .read_exact(&mut frame)
+        .await
+        .context("Connection closed in the middle of a frame")?;
+    Ok(Some(frame))
+}
+
+/// Reads the next frame and fails if the connection was closed
+pub async fn read_message<M>(reader: &mut (impl AsyncRead + Unpin), max_len: u32) -> Result<M>
+where
+    for<'a> M: BinRead<Args<'a> = ()>,
+{
+    let frame = read_frame(reader, max_len)
+        .await?
+        .context("Connection closed by peer")?;
+    decode_frame(&frame)
+}
+
+pub async fn write_message<M>(writer: &mut (impl AsyncWrite + Unpin), message: &M) -> Result<()>
+where
+    for<'a> M: BinWrite<Args<'a> = ()>,
+{
+    writer.write_all(&encode_frame(message)?).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::utils::blockid;
+
+    fn roundtrip_request(request: Request) -> Request {
+        let frame = encode_frame(&RequestFrame {
+            request_id: 5,
+            request,
+        })
+        .unwrap();
+        assert_eq!(
+            frame.len() - 4,
+            u32::from_le_bytes(frame[..4].try_into().unwrap()) as usize
+        );
+        let decoded: RequestFrame = decode_frame(&frame[4..]).unwrap();
+        assert_eq!(5, decoded.request_id);
+        decoded.request
+    }
+
+    #[test]
+    fn test_request_roundtrip() {
+        let Request::Store { block_id, data } = roundtrip_request(Request::Store {
+            block_id: blockid(1),
+            data: vec![1, 2, 3],
+        }) else {
+            panic!("Wrong request type");
+        };
+        assert_eq!(blockid(1), block_id);
+        assert_eq!(vec![1, 2, 3], data);
+
+        assert!(matches!(roundtrip_request(Request::Sync), Request::Sync));
+        assert!(matches!(
+            roundtrip_request(Request::Load { block_id: blockid(2) }),
+            Request::Load { block_id } if block_id == blockid(2)
+        ));
+    }
+
+    #[test]
+    fn test_response_roundtrip() {
+        let frame = encode_frame(&ResponseFrame {
+            request_id: 7,
+            response: Response::BlockIds {
+                block_ids: vec![blockid(1), blockid(2)],
+            },
+        })
+        .unwrap();
+        let decoded: ResponseFrame = decode_frame(&frame[4..]).unwrap();
+        assert_eq!(7, decoded.request_id);
+        let Response::BlockIds { block_ids } = decoded.response else {
+            panic!("Wrong response type");
+        };
+        assert_eq!(vec![blockid(1), blockid(2)], block_ids);
+    }
+
+    #[test]
+    fn test_proofs() {
+        let client_nonce = [1; NONCE_LEN];
+        let server_nonce = [2; NONCE_LEN];
+        let proof = compute_proof(b"secret", Role::Client, &client_nonce, &server_nonce);
+        assert!(verify_proof(
+            b"secret",
+            Role::Client,
+            &client_nonce,
+            &server_nonce,
+            &proof
+        ));
+        assert!(!verify_proof(
+            b"other secret",
+            Role::Client,
+            &client_nonce,
+            &server_nonce,
+            &proof
+        ));
+        assert!(!verify_proof(
+            b"secret",
+            Role::Server,
+            &client_nonce,
+            &server_nonce,
+            &proof
+        ));
+        assert!(!verify_proof(
+            b"secret",
+            Role::Client,
+            &server_nonce,
+            &client_nonce,
+            &proof
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_read_frame() {
+        let mut content = encode_frame(&ClientProof {
+            client_proof: [3; PROOF_LEN],
+        })
+        .unwrap();
+        content.extend(encode_frame(&HandshakeResult::Rejected).unwrap());
+        let mut reader = Cursor::new(content);
+
+        let proof: ClientProof = read_message(&mut reader, MAX_HANDSHAKE_FRAME_LEN)
+            .await
+            .unwrap();
+        assert_eq!([3; PROOF_LEN], proof.client_proof);
+        let result: HandshakeResult = read_message(&mut reader, MAX_HANDSHAKE_FRAME_LEN)
+            .await
+            .unwrap();
+        assert!(matches!(result, HandshakeResult::Rejected));
+        assert!(
+            read_frame(&mut reader, MAX_HANDSHAKE_FRAME_LEN)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_rejects_too_large_frames() {
+        let mut reader = Cursor::new(2000u32.to_le_bytes().to_vec());
+        assert!(
+            read_frame(&mut reader, MAX_HANDSHAKE_FRAME_LEN)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_rejects_incomplete_frames() {
+        let frame = encode_frame(&HandshakeResult::Rejected).unwrap();
+        let mut reader = Cursor::new(frame[..frame.len() - 1].to_vec());
+        assert!(
+            read_frame(&mut reader, MAX_HANDSHAKE_FRAME_LEN)
+                .await
+                .is_err()
+        );
+        let mut reader = Cursor::new(frame[..2].to_vec());
+        assert!(
+            read_frame(&mut reader, MAX_HANDSHAKE_FRAME_LEN)
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_check_header() {
+        let hello = |header: &[u8; 20]| ClientHello {
+            header: *header,
+            client_nonce: [0; NONCE_LEN],
+        };
+        hello(PROTOCOL_HEADER).check_header().unwrap();
+        assert!(
+            hello(b"cryfs;blockserver;1\0")
+                .check_header()
+                .unwrap_err()
+                .to_string()
+                .contains("newer version")
+        );
+        assert!(hello(b"GET / HTTP/1.1\r\n\r\n\0\0").check_header().is_err());
+    }
+}
@@ -0,0 +1,230 @@
+use anyhow::{Context, Result, bail};
+use byte_unit::Byte;
+use futures::stream::{self, FuturesUnordered, StreamExt, TryStreamExt};
+use rand::{RngExt, rng};
+use std::future::Future;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, BufReader, BufWriter};
+use tokio::sync::watch;
+
+use super::protocol::{
+    ClientHello, ClientProof, HandshakeResult, MAX_FRAME_LEN, MAX_HANDSHAKE_FRAME_LEN, Request,
+    RequestFrame, Response, ResponseFrame, Role, ServerHello, compute_proof, decode_frame,
+    read_frame, read_message, verify_proof, write_message,
+};
+use super::{BlockServerListener, BlockServerSecret, Transport};
+use crate::{
+    low_level::LLBlockStore,
+    utils::{RemoveResult, TryCreateResult},
+};
+
+/// Clients that don't finish the handshake in time get disconnected
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Requests of a single connection that are processed concurrently. If a client sends more, we stop
+/// reading from its connection until some of them are done.
+const MAX_REQUESTS_IN_FLIGHT: usize = 64;
+
+/// Serves `store` to clients connecting to `listener` until `shutdown` completes.
+///
+/// On shutdown, we stop accepting connections and requests, but requests that are already being processed
+/// are finished and answered. The store is synced before this returns.
+pub async fn serve_blocks<B: LLBlockStore + Sync>(
+    store: &B,
+    listener: BlockServerListener,
+    secret: &BlockServerSecret,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+    let (shutdown_sender, shutdown_receiver) = watch::channel(false);
+    let mut connections = FuturesUnordered::new();
+    let mut shutdown = std::pin::pin!(shutdown);
+    loop {
+        tokio::select! {
+            () = &mut shutdown => break,
+            accepted = listener.accept() => match accepted {
+                Ok((connection, peer)) => {
+                    log::info!("Accepted connection from {peer}");
+                    connections.push(_serve_connection(
+                        store,
+                        connection,
+                        peer,
+                        secret,
+                        shutdown_receiver.clone(),
+                    ));
+                }
+                Err(err) => log::warn!("Failed to accept connection: {err:?}"),
+            },
+            Some(()) = connections.next(), if !connections.is_empty() => {}
+        }
+    }
+
+    log::info!("Shutting down block server");
+    shutdown_sender
+        .send(true)
+        .expect("The receiver in this function is still alive");
+    while connections.next().await.is_some() {}
+    store.sync().await
+}
+
+async fn _serve_connection<B: LLBlockStore + Sync>(
+    store: &B,
+    connection: Box<dyn Transport>,
+    peer: String,
+    secret: &BlockServerSecret,
+    shutdown: watch::Receiver<bool>,
+) {
+    match __serve_connection(store, connection, secret, shutdown).await {
+        Ok(()) => log::info!("Connection from {peer} closed"),
+        Err(err) => log::warn!("Connection from {peer} failed: {err:#}"),
+    }
+}
+
+async fn __serve_connection<B: LLBlockStore + Sync>(
+    store: &B,
+    connection: Box<dyn Transport>,
+    secret: &BlockServerSecret,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let (reader, writer) = tokio::io::split(connection);
+    let mut reader = BufReader::new(reader);
+    let mut writer = BufWriter::new(writer);
+    tokio::time::timeout(
+        HANDSHAKE_TIMEOUT,
+        _handshake(store, &mut reader, &mut writer, secret),
+    )
+    .await
+    .context("Handshake timed out")??;
+
+    // Reading a frame isn't cancel safe, so keep the read future alive across loop iterations by wrapping it in a stream
+    let mut frames = std::pin::pin!(stream::unfold(reader, |mut reader| async move {
+        let frame = read_frame(&mut reader, MAX_FRAME_LEN).await.transpose()?;
+        Some((frame, reader))
+    }));
+    let mut in_flight = FuturesUnordered::new();
+    let mut reading = !*shutdown.borrow();
+    let mut error = None;
+    while reading || !in_flight.is_empty() {
+        tokio::select! {
+            frame = frames.next(), if reading && in_flight.len() < MAX_REQUESTS_IN_FLIGHT => {
+                match frame.map(|frame| frame.and_then(|frame| decode_frame::<RequestFrame>(&frame))) {
+                    None => reading = false,
+                    Some(Ok(request)) => in_flight.push(_handle_request(store, request)),
+                    Some(Err(err)) => {
+                        // Still answer the requests we already got, the client may be waiting for them
+                        reading = false;
+                        error = Some(err);
+                    }
+                }
+            }
+            Some(response) = in_flight.next(), if !in_flight.is_empty() => {
+                write_message(&mut writer, &response).await?;
+            }
+            _ = shutdown.changed(), if reading => reading = false,
+        }
+    }
+    match error {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+async fn _handshake<B: LLBlockStore + Sync>(
+    store: &B,
+    reader: &mut (impl AsyncRead + Unpin),
+    writer: &mut (impl AsyncWrite + Unpin),
+    secret: &BlockServerSecret,
+) -> Result<()> {
+    let hello: ClientHello = read_message(reader, MAX_HANDSHAKE_FRAME_LEN).await?;
+    hello.check_header()?;
+    let server_nonce = rng().random();
+    write_message(
+        writer,
+        &ServerHello {
+            server_nonce,
+            server_proof: compute_proof(
+                secret.as_bytes(),
+                Role::Server,
+                &hello.client_nonce,
+                &server_nonce,
+            ),
+        },
+    )
+    .await?;
+
+    let proof: ClientProof = read_message(reader, MAX_HANDSHAKE_FRAME_LEN).await?;
+    if !verify_proof(
+        secret.as_bytes(),
+        Role::Client,
+        &hello.client_nonce,
+        &server_nonce,
+        &proof.client_proof,
+    ) {
+        write_message(writer, &HandshakeResult::Rejected).await?;
+        bail!("Client doesn't know the secret");
+    }
+    write_message(
+        writer,
+        &HandshakeResult::Accepted {
+            overhead: store
+                .overhead()
+                .physical_block_size_from_usable_block_size(Byte::from_u64(0))
+                .as_u64(),
+            num_free_bytes: store.estimate_num_free_bytes()?.as_u64(),
+        },
+    )
+    .await
+}
+
+async fn _handle_request<B: LLBlockStore + Sync>(store: &B, frame: RequestFrame) -> ResponseFrame {
+    let response = match __handle_request(store, frame.request).await {
+        Ok(response) => response,
+        Err(err) => {
+            log::error!("Request {} failed: {err:?}", frame.request_id);
+            Response::Error {
+                message: format!("{err:#}").into_bytes(),
+            }
+        }
+    };
+    ResponseFrame {
+        request_id: frame.request_id,
+        response,
+    }
+}
+
+async fn __handle_request<B: LLBlockStore + Sync>(store: &B, request: Request) -> Result<Response> {
+    Ok(match request {
+        Request::Exists { block_id } => Response::Bool {
+            value: store.exists(&block_id).await?,
+        },
+        Request::Load { block_id } => match store.load(&block_id).await? {
+            Some(data) => Response::Data {
+                data: data.into_vec(),
+            },
+            None => Response::NotFound,
+        },
+        Request::NumBlocks => Response::Number {
+            value: store.num_blocks().await?,
+        },
+        Request::EstimateNumFreeBytes => Response::Number {
+            value: store.estimate_num_free_bytes()?.as_u64(),
+        },
+        Request::AllBlocks => Response::BlockIds {
+            block_ids: store.all_blocks().await?.try_collect().await?,
+        },
+        Request::Remove { block_id } => Response::Bool {
+            value: store.remove(&block_id).await? == RemoveResult::SuccessfullyRemoved,
+        },
+        Request::TryCreate { block_id, data } => Response::Bool {
+            value: store.try_create(&block_id, &data).await?
+                == TryCreateResult::SuccessfullyCreated,
+        },
+        Request::Store { block_id, data } => {
+            store.store(&block_id, &data).await?;
+            Response::Done
+        }
+        Request::Sync => {
+            store.sync().await?;
+            Response::Done
+        }
+    })
+}
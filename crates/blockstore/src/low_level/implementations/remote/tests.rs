@@ -0,0 +1,377 @@
+use async_trait::async_trait;
+use futures::future;
+use std::sync::Arc;
+use tempfile::TempDir;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+use super::*;
+use crate::instantiate_blockstore_tests_for_lowlevel_blockstore;
+use crate::low_level::{
+    BlockStoreDeleter, BlockStoreReader, BlockStoreWriter, EncryptedBlockStore, InMemoryBlockStore,
+    MockBlockStore,
+};
+use crate::tests::{
+    low_level::LLFixture,
+    utils::{blockid, data},
+};
+use crate::utils::RemoveResult;
+use crate::{BlockId, Overhead};
+use byte_unit::Byte;
+use cryfs_crypto::symmetric::{CipherDef, EncryptionKey, XChaCha20Poly1305};
+use cryfs_utils::{async_drop::AsyncDropGuard, data::Data};
+
+fn secret() -> BlockServerSecret {
+    BlockServerSecret::new(b"0123456789abcdef-test-secret".to_vec()).unwrap()
+}
+
+/// A block server on loopback serving an in-memory block store
+struct TestServer {
+    address: RemoteAddress,
+    store: Arc<InMemoryBlockStore>,
+    shutdown: Option<oneshot::Sender<()>>,
+    task: JoinHandle<Result<()>>,
+    // Keep the directory for unix sockets alive
+    _tempdir: Option<TempDir>,
+}
+
+impl TestServer {
+    async fn start_tcp() -> Self {
+        Self::_start(RemoteAddress::Tcp("127.0.0.1:0".to_string()), None).await
+    }
+
+    async fn start_unix() -> Self {
+        let tempdir = TempDir::new().unwrap();
+        let address = RemoteAddress::Unix(tempdir.path().join("blockserver.sock"));
+        Self::_start(address, Some(tempdir)).await
+    }
+
+    async fn _start(address: RemoteAddress, tempdir: Option<TempDir>) -> Self {
+        // The in-memory store doesn't hold any resources that need an async drop,
+        // which lets us share it with the server task and abort that task at any time
+        let store = Arc::new(InMemoryBlockStore::new().unsafe_into_inner_dont_drop());
+        Self::_start_with_store(address, tempdir, store).await
+    }
+
+    async fn _start_with_store(
+        address: RemoteAddress,
+        tempdir: Option<TempDir>,
+        store: Arc<InMemoryBlockStore>,
+    ) -> Self {
+        let listener = BlockServerListener::bind(&address).await.unwrap();
+        let address = listener.local_address().unwrap();
+        let (shutdown, shutdown_receiver) = oneshot::channel();
+        let server_store = Arc::clone(&store);
+        let task = tokio::spawn(async move {
+            serve_blocks(&*server_store, listener, &secret(), async {
+                let _ = shutdown_receiver.await;
+            })
+            .await
+        });
+        Self {
+            address,
+            store,
+            shutdown: Some(shutdown),
+            task,
+            _tempdir: tempdir,
+        }
+    }
+
+    async fn connect(&self) -> AsyncDropGuard<RemoteBlockStore> {
+        RemoteBlockStore::connect(self.address.clone(), secret())
+            .await
+            .unwrap()
+    }
+
+    async fn shutdown(mut self) {
+        self.shutdown.take().unwrap().send(()).unwrap();
+        (&mut self.task).await.unwrap().unwrap();
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+struct TcpFixture {
+    server: Option<TestServer>,
+}
+
+#[async_trait]
+impl LLFixture for TcpFixture {
+    type ConcreteBlockStore = RemoteBlockStore;
+    fn new() -> Self {
+        Self { server: None }
+    }
+    async fn store(&mut self) -> AsyncDropGuard<Self::ConcreteBlockStore> {
+        let server = TestServer::start_tcp().await;
+        let store = server.connect().await;
+        self.server = Some(server);
+        store
+    }
+    async fn yield_fixture(&self, _store: &Self::ConcreteBlockStore) {}
+}
+
+instantiate_blockstore_tests_for_lowlevel_blockstore!(TcpFixture, (flavor = "multi_thread"));
+
+mod unix_socket {
+    use super::*;
+
+    struct UnixFixture {
+        server: Option<TestServer>,
+    }
+
+    #[async_trait]
+    impl LLFixture for UnixFixture {
+        type ConcreteBlockStore = RemoteBlockStore;
+        fn new() -> Self {
+            Self { server: None }
+        }
+        async fn store(&mut self) -> AsyncDropGuard<Self::ConcreteBlockStore> {
+            let server = TestServer::start_unix().await;
+            let store = server.connect().await;
+            self.server = Some(server);
+            store
+        }
+        async fn yield_fixture(&self, _store: &Self::ConcreteBlockStore) {}
+    }
+
+    instantiate_blockstore_tests_for_lowlevel_blockstore!(UnixFixture, (flavor = "multi_thread"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_wrong_secret_is_rejected() {
+    let server = TestServer::start_tcp().await;
+
+    let wrong_secret = BlockServerSecret::new(b"fedcba9876543210-test-secret".to_vec()).unwrap();
+    let error = RemoteBlockStore::connect(server.address.clone(), wrong_secret)
+        .await
+        .unwrap_err();
+    assert!(
+        format!("{error:#}").contains("doesn't know the secret"),
+        "{error:#}"
+    );
+
+    // The server is still usable for clients with the right secret
+    let mut store = server.connect().await;
+    store.store(&blockid(0), &data(100, 0)).await.unwrap();
+    store.async_drop().await.unwrap();
+    server.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_client_that_doesnt_speak_the_protocol_is_disconnected() {
+    let server = TestServer::start_tcp().await;
+    let RemoteAddress::Tcp(host_and_port) = &server.address else {
+        unreachable!()
+    };
+
+    let mut connection = tokio::net::TcpStream::connect(host_and_port).await.unwrap();
+    tokio::io::AsyncWriteExt::write_all(&mut connection, b"GET / HTTP/1.1\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = Vec::new();
+    tokio::io::AsyncReadExt::read_to_end(&mut connection, &mut response)
+        .await
+        .unwrap();
+    assert!(response.is_empty());
+
+    server.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_server_only_sees_ciphertext() {
+    let server = TestServer::start_tcp().await;
+    let cipher = XChaCha20Poly1305::new(
+        EncryptionKey::new(XChaCha20Poly1305::KEY_SIZE, |key| {
+            key.fill(7);
+            Ok::<_, std::convert::Infallible>(())
+        })
+        .unwrap(),
+    )
+    .unwrap();
+    let mut store = EncryptedBlockStore::<_, RemoteBlockStore, RemoteBlockStore>::new(
+        server.connect().await,
+        cipher,
+    );
+
+    let plaintext = vec![b'x'; 1000];
+    store.store(&blockid(0), &plaintext).await.unwrap();
+    assert_eq!(
+        Some(plaintext.as_slice()),
+        store.load(&blockid(0)).await.unwrap().as_deref()
+    );
+
+    let on_server = server.store.load(&blockid(0)).await.unwrap().unwrap();
+    assert!(
+        !on_server
+            .windows(16)
+            .any(|window| window == &plaintext[..16])
+    );
+
+    store.async_drop().await.unwrap();
+    server.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_pipelined_requests() {
+    let server = TestServer::start_tcp().await;
+    let mut store = server.connect().await;
+
+    let block_ids: Vec<BlockId> = (0..200).map(blockid).collect();
+    let blocks: Vec<Data> = (0..200).map(|i| data(1000, i)).collect();
+    future::try_join_all(
+        block_ids
+            .iter()
+            .zip(&blocks)
+            .map(|(block_id, block)| store.store(block_id, block)),
+    )
+    .await
+    .unwrap();
+    let loaded = future::try_join_all(block_ids.iter().map(|block_id| store.load(block_id)))
+        .await
+        .unwrap();
+    for (i, loaded) in loaded.into_iter().enumerate() {
+        assert_eq!(Some(data(1000, i as u64)), loaded);
+    }
+    assert_eq!(200, store.num_blocks().await.unwrap());
+
+    store.async_drop().await.unwrap();
+    server.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_server_errors_are_returned_to_the_client() {
+    let listener = BlockServerListener::bind(&RemoteAddress::Tcp("127.0.0.1:0".to_string()))
+        .await
+        .unwrap();
+    let address = listener.local_address().unwrap();
+    let mut server_store = MockBlockStore::new();
+    server_store
+        .expect_overhead()
+        .returning(|| Overhead::new(Byte::from_u64(0)));
+    server_store
+        .expect_estimate_num_free_bytes()
+        .returning(|| Ok(Byte::from_u64(1000)));
+    server_store
+        .expect_store()
+        .returning(|_, _| Box::pin(async { Err(anyhow::anyhow!("Disk full")) }));
+    server_store
+        .expect_load()
+        .returning(|_| Box::pin(async { Ok(None) }));
+    server_store
+        .expect_sync()
+        .returning(|| Box::pin(async { Ok(()) }));
+    server_store
+        .expect_async_drop_impl()
+        .returning(|| Box::pin(async { Ok(()) }));
+    let mut server_store = AsyncDropGuard::new(server_store);
+    let (shutdown, shutdown_receiver) = oneshot::channel::<()>();
+    let client = async {
+        let mut store = RemoteBlockStore::connect(address, secret()).await.unwrap();
+        let error = store.store(&blockid(0), &data(100, 0)).await.unwrap_err();
+        assert!(format!("{error:#}").contains("Disk full"), "{error:#}");
+        // The connection is still usable after an error
+        assert_eq!(None, store.load(&blockid(0)).await.unwrap());
+        store.async_drop().await.unwrap();
+        shutdown.send(()).unwrap();
+    };
+    let secret = secret();
+    let server = serve_blocks(&*server_store, listener, &secret, async {
+        let _ = shutdown_receiver.await;
+    });
+    let ((), server_result) = tokio::join!(client, server);
+    server_result.unwrap();
+    server_store.async_drop().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_reconnects_after_server_restart() {
+    let mut server = TestServer::start_unix().await;
+    let mut store = server.connect().await;
+    store.store(&blockid(0), &data(100, 0)).await.unwrap();
+
+    // Stop the server and start a new one on the same socket with the same blocks
+    let address = server.address.clone();
+    let server_store = Arc::clone(&server.store);
+    let tempdir = server._tempdir.take();
+    server.shutdown().await;
+    assert!(store.load(&blockid(0)).await.is_err());
+
+    let RemoteAddress::Unix(socket_path) = &address else {
+        unreachable!()
+    };
+    assert!(
+        !socket_path.exists(),
+        "The socket file should be removed when the server stops"
+    );
+    let restarted = TestServer::_start_with_store(address, tempdir, server_store).await;
+
+    assert_eq!(Some(data(100, 0)), store.load(&blockid(0)).await.unwrap());
+    assert_eq!(
+        RemoveResult::SuccessfullyRemoved,
+        store.remove(&blockid(0)).await.unwrap()
+    );
+
+    store.async_drop().await.unwrap();
+    restarted.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_estimate_num_free_bytes() {
+    let server = TestServer::start_tcp().await;
+    let mut store = server.connect().await;
+
+    // The first call returns the value from the handshake, later calls return refreshed values
+    assert!(store.estimate_num_free_bytes().unwrap().as_u64() > 0);
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    assert!(store.estimate_num_free_bytes().unwrap().as_u64() > 0);
+
+    store.async_drop().await.unwrap();
+    server.shutdown().await;
+}
+
+#[test]
+fn test_parse_address() {
+    assert_eq!(
+        RemoteAddress::Tcp("192.168.1.5:4000".to_string()),
+        "192.168.1.5:4000".parse().unwrap()
+    );
+    assert_eq!(
+        RemoteAddress::Tcp("[::1]:4000".to_string()),
+        "tcp:[::1]:4000".parse().unwrap()
+    );
+    assert_eq!(
+        RemoteAddress::Unix("/run/blockserver.sock".into()),
+        "unix:/run/blockserver.sock".parse().unwrap()
+    );
+    assert!("localhost".parse::<RemoteAddress>().is_err());
+    assert!("localhost:notaport".parse::<RemoteAddress>().is_err());
+    assert!("unix:".parse::<RemoteAddress>().is_err());
+}
+
+#[test]
+fn test_address_display_roundtrip() {
+    for address in ["tcp:localhost:4000", "unix:/run/blockserver.sock"] {
+        assert_eq!(
+            address,
+            address.parse::<RemoteAddress>().unwrap().to_string()
+        );
+    }
+}
+
+#[test]
+fn test_secret() {
+    assert!(BlockServerSecret::new(b"too short".to_vec()).is_err());
+
+    let tempdir = TempDir::new().unwrap();
+    let path = tempdir.path().join("secret");
+    std::fs::write(&path, b"0123456789abcdef-test-secret\n").unwrap();
+    assert_eq!(
+        b"0123456789abcdef-test-secret",
+        BlockServerSecret::from_file(&path).unwrap().as_bytes()
+    );
+    assert!(!format!("{:?}", secret()).contains("test-secret"));
+}
@@ -0,0 +1,311 @@
+use anyhow::{Context, Result, anyhow, bail};
+use reqwest::{Method, Response, StatusCode, Url};
+use std::sync::Arc;
+
+use super::list_objects::ListObjectsPage;
+use super::signing::{
+    AmzTimestamp, RequestToSign, authorization_header, canonical_query_string, sha256_hex,
+    uri_encode,
+};
+use super::{S3AddressingStyle, S3Config};
+
+/// Result of a conditional `PutObject` request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum PutResult {
+    Stored,
+    NotStoredBecauseObjectAlreadyExists,
+}
+
+/// A minimal client for the subset of the S3 API we need. Requests are signed with AWS Signature Version 4.
+#[derive(Clone)]
+pub(super) struct S3Client {
+    http: reqwest::Client,
+    endpoint: Url,
+    config: Arc<S3Config>,
+}
+
+impl S3Client {
+    pub fn new(config: S3Config) -> Result<Self> {
+        let endpoint = Url::parse(&config.endpoint)
+            .with_context(|| format!("Invalid S3 endpoint URL: {}", config.endpoint))?;
+        if endpoint.host_str().is_none() {
+            bail!("S3 endpoint URL {} doesn't have a host", config.endpoint);
+        }
+        if endpoint.query().is_some() {
+            bail!("S3 endpoint URL {} must not have a query", config.endpoint);
+        }
+        let http = reqwest::Client::builder()
+            .build()
+            .context("Failed to initialize HTTP client")?;
+        Ok(Self {
+            http,
+            endpoint,
+            config: Arc::new(config),
+        })
+    }
+
+    /// Returns `None` if the object doesn't exist
+    pub async fn get_object(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let response = self.send(Method::GET, Some(key), &[], &[], vec![]).await?;
+        match response.status() {
+            StatusCode::OK => {
+                let body = response
+                    .bytes()
+                    .await
+                    .with_context(|| format!("Failed to read body of S3 object {key}"))?;
+                Ok(Some(body.to_vec()))
+            }
+            StatusCode::NOT_FOUND => Ok(None),
+            _ => Err(error_from_response("GetObject", key, response).await),
+        }
+    }
+
+    pub async fn head_object(&self, key: &str) -> Result<bool> {
+        let response = self.send(Method::HEAD, Some(key), &[], &[], vec![]).await?;
+        match response.status() {
+            StatusCode::OK => Ok(true),
+            StatusCode::NOT_FOUND => Ok(false),
+            _ => Err(error_from_response("HeadObject", key, response).await),
+        }
+    }
+
+    /// If `only_if_not_exists` is set, this is a conditional write that doesn't overwrite existing objects.
+    /// This requires the S3 service to support the `If-None-Match` header, which AWS S3 and MinIO do.
+    pub async fn put_object(
+        &self,
+        key: &str,
+        body: Vec<u8>,
+        only_if_not_exists: bool,
+    ) -> Result<PutResult> {
+        let headers: &[(&str, &str)] = if only_if_not_exists {
+            &[("if-none-match", "*")]
+        } else {
+            &[]
+        };
+        let response = self
+            .send(Method::PUT, Some(key), &[], headers, body)
+            .await?;
+        match response.status() {
+            StatusCode::OK => Ok(PutResult::Stored),
+            StatusCode::PRECONDITION_FAILED if only_if_not_exists => {
+                Ok(PutResult::NotStoredBecauseObjectAlreadyExists)
+            }
+            _ => Err(error_from_response("PutObject", key, response).await),
+        }
+    }
+
+    /// Note that S3 doesn't tell us whether the object existed. Deleting a nonexisting object succeeds.
+    pub async fn delete_object(&self, key: &str) -> Result<()> {
+        let response = self
+            .send(Method::DELETE, Some(key), &[], &[], vec![])
+            .await?;
+        match response.status() {
+            StatusCode::OK | StatusCode::NO_CONTENT => Ok(()),
+            _ => Err(error_from_response("DeleteObject", key, response).await),
+        }
+    }
+
+    pub async fn list_objects_page(
+        &self,
+        prefix: &str,
+        continuation_token: Option<&str>,
+    ) -> Result<ListObjectsPage> {
+        let mut query = vec![("list-type", "2"), ("prefix", prefix)];
+        if let Some(continuation_token) = continuation_token {
+            query.push(("continuation-token", continuation_token));
+        }
+        let response = self.send(Method::GET, None, &query, &[], vec![]).await?;
+        match response.status() {
+            StatusCode::OK => {
+                let body = response
+                    .text()
+                    .await
+                    .context("Failed to read body of ListObjectsV2 response")?;
+                ListObjectsPage::parse(&body)
+            }
+            _ => Err(error_from_response("ListObjectsV2", prefix, response).await),
+        }
+    }
+
+    /// Send a signed request. If `key` is `None`, the request goes to the bucket itself.
+    async fn send(
+        &self,
+        method: Method,
+        key: Option<&str>,
+        query: &[(&str, &str)],
+        extra_headers: &[(&str, &str)],
+        body: Vec<u8>,
+    ) -> Result<Response> {
+        let url = self.url(key, query)?;
+        let host = match url.port() {
+            Some(port) => format!("{}:{port}", url.host_str().expect("Checked in constructor")),
+            None => url.host_str().expect("Checked in constructor").to_string(),
+        };
+        let payload_sha256 = sha256_hex(&body);
+        let timestamp = AmzTimestamp::now();
+
+        let mut headers: Vec<(&str, &str)> = vec![
+            ("host", &host),
+            ("x-amz-content-sha256", &payload_sha256),
+            ("x-amz-date", &timestamp.datetime),
+        ];
+        if let Some(session_token) = &self.config.credentials.session_token {
+            headers.push(("x-amz-security-token", session_token));
+        }
+        headers.extend_from_slice(extra_headers);
+
+        let authorization = authorization_header(
+            &RequestToSign {
+                method: method.as_str(),
+                canonical_uri: url.path(),
+                query,
+                headers: &headers,
+                payload_sha256: &payload_sha256,
+            },
+            &self.config.credentials,
+            &self.config.region,
+            &timestamp,
+        );
+
+        let mut request = self.http.request(method.clone(), url);
+        for (name, value) in headers {
+            // reqwest sets the host header itself, based on the URL
+            if name != "host" {
+                request = request.header(name, value);
+            }
+        }
+        request
+            .header("authorization", authorization)
+            .body(body)
+            .send()
+            .await
+            .with_context(|| {
+                format!(
+                    "Failed to send {method} request for {} to S3",
+                    key.unwrap_or("bucket")
+                )
+            })
+    }
+
+    fn url(&self, key: Option<&str>, query: &[(&str, &str)]) -> Result<Url> {
+        let mut url = self.endpoint.clone();
+        let base_path = self.endpoint.path().trim_end_matches('/');
+        let key_path = key.map(|key| uri_encode(key, false));
+        let path = match self.config.addressing_style {
+            S3AddressingStyle::Path => match &key_path {
+                Some(key_path) => format!("{base_path}/{}/{key_path}", self.config.bucket),
+                None => format!("{base_path}/{}", self.config.bucket),
+            },
+            S3AddressingStyle::VirtualHosted => {
+                let host = format!(
+                    "{}.{}",
+                    self.config.bucket,
+                    self.endpoint.host_str().expect("Checked in constructor"),
+                );
+                url.set_host(Some(&host))
+                    .with_context(|| format!("Invalid bucket name {}", self.config.bucket))?;
+                match &key_path {
+                    Some(key_path) => format!("{base_path}/{key_path}"),
+                    None => format!("{base_path}/"),
+                }
+            }
+        };
+        url.set_path(&path);
+        if query.is_empty() {
+            url.set_query(None);
+        } else {
+            url.set_query(Some(&canonical_query_string(query)));
+        }
+        Ok(url)
+    }
+}
+
+async fn error_from_response(operation: &str, key: &str, response: Response) -> anyhow::Error {
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    anyhow!("S3 {operation} request for {key} failed with status {status}: {body}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::low_level::implementations::s3::S3Credentials;
+
+    fn client(endpoint: &str, addressing_style: S3AddressingStyle) -> S3Client {
+        S3Client::new(S3Config {
+            endpoint: endpoint.to_string(),
+            region: "us-east-1".to_string(),
+            bucket: "mybucket".to_string(),
+            prefix: "".to_string(),
+            addressing_style,
+            credentials: S3Credentials {
+                access_key_id: "access".to_string(),
+                secret_access_key: "secret".to_string(),
+                session_token: None,
+            },
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_url_pathstyle() {
+        let client = client("http://localhost:9000", S3AddressingStyle::Path);
+        assert_eq!(
+            "http://localhost:9000/mybucket/vault/2AC/9C78D8",
+            client.url(Some("vault/2AC/9C78D8"), &[]).unwrap().as_str()
+        );
+        assert_eq!(
+            "http://localhost:9000/mybucket?list-type=2&prefix=vault%2F",
+            client
+                .url(None, &[("prefix", "vault/"), ("list-type", "2")])
+                .unwrap()
+                .as_str()
+        );
+    }
+
+    #[test]
+    fn test_url_pathstyle_with_base_path() {
+        let client = client("https://example.com/s3/", S3AddressingStyle::Path);
+        assert_eq!(
+            "https://example.com/s3/mybucket/some%20key",
+            client.url(Some("some key"), &[]).unwrap().as_str()
+        );
+    }
+
+    #[test]
+    fn test_url_virtualhosted() {
+        let client = client(
+            "https://s3.eu-central-1.amazonaws.com",
+            S3AddressingStyle::VirtualHosted,
+        );
+        assert_eq!(
+            "https://mybucket.s3.eu-central-1.amazonaws.com/vault/2AC/9C78D8",
+            client.url(Some("vault/2AC/9C78D8"), &[]).unwrap().as_str()
+        );
+        assert_eq!(
+            "https://mybucket.s3.eu-central-1.amazonaws.com/?list-type=2&prefix=",
+            client
+                .url(None, &[("list-type", "2"), ("prefix", "")])
+                .unwrap()
+                .as_str()
+        );
+    }
+
+    #[test]
+    fn test_invalid_endpoint() {
+        let config = |endpoint: &str| S3Config {
+            endpoint: endpoint.to_string(),
+            region: "us-east-1".to_string(),
+            bucket: "mybucket".to_string(),
+            prefix: "".to_string(),
+            addressing_style: S3AddressingStyle::Path,
+            credentials: S3Credentials {
+                access_key_id: "access".to_string(),
+                secret_access_key: "secret".to_string(),
+                session_token: None,
+            },
+        };
+        assert!(S3Client::new(config("not a url")).is_err());
+        assert!(S3Client::new(config("http://localhost:9000/?a=b")).is_err());
+    }
+}
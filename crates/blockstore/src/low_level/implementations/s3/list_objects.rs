@@ -0,0 +1,130 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+/// One page of results of a `ListObjectsV2` request,
+/// see <https://docs.aws.amazon.com/AmazonS3/latest/API/API_ListObjectsV2.html>
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub(super) struct ListObjectsPage {
+    #[serde(default)]
+    pub contents: Vec<ListedObject>,
+    #[serde(default)]
+    pub is_truncated: bool,
+    pub next_continuation_token: Option<String>,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub(super) struct ListedObject {
+    pub key: String,
+}
+
+impl ListObjectsPage {
+    pub fn parse(xml: &str) -> Result<Self> {
+        quick_xml::de::from_str(xml).context("Failed to parse ListObjectsV2 response")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_empty() {
+        let page = ListObjectsPage::parse(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+                <Name>bucket</Name>
+                <Prefix>vault/</Prefix>
+                <KeyCount>0</KeyCount>
+                <MaxKeys>1000</MaxKeys>
+                <IsTruncated>false</IsTruncated>
+            </ListBucketResult>"#,
+        )
+        .unwrap();
+        assert_eq!(
+            ListObjectsPage {
+                contents: vec![],
+                is_truncated: false,
+                next_continuation_token: None,
+            },
+            page
+        );
+    }
+
+    #[test]
+    fn test_parse_last_page() {
+        let page = ListObjectsPage::parse(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+                <Name>bucket</Name>
+                <Prefix></Prefix>
+                <KeyCount>2</KeyCount>
+                <MaxKeys>1000</MaxKeys>
+                <IsTruncated>false</IsTruncated>
+                <Contents>
+                    <Key>2AC/9C78D80937AD50852C50BD3F1F982</Key>
+                    <LastModified>2009-10-12T17:50:30.000Z</LastModified>
+                    <ETag>"fba9dede5f27731c9771645a39863328"</ETag>
+                    <Size>16398</Size>
+                    <StorageClass>STANDARD</StorageClass>
+                </Contents>
+                <Contents>
+                    <Key>cryfs.config</Key>
+                    <LastModified>2009-10-12T17:50:30.000Z</LastModified>
+                    <ETag>"fba9dede5f27731c9771645a39863328"</ETag>
+                    <Size>1024</Size>
+                    <StorageClass>STANDARD</StorageClass>
+                </Contents>
+            </ListBucketResult>"#,
+        )
+        .unwrap();
+        assert_eq!(
+            ListObjectsPage {
+                contents: vec![
+                    ListedObject {
+                        key: "2AC/9C78D80937AD50852C50BD3F1F982".to_string(),
+                    },
+                    ListedObject {
+                        key: "cryfs.config".to_string(),
+                    },
+                ],
+                is_truncated: false,
+                next_continuation_token: None,
+            },
+            page
+        );
+    }
+
+    #[test]
+    fn test_parse_truncated_page() {
+        let page = ListObjectsPage::parse(
+            r#"<ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+                <Name>bucket</Name>
+                <KeyCount>1</KeyCount>
+                <MaxKeys>1</MaxKeys>
+                <IsTruncated>true</IsTruncated>
+                <Contents><Key>2AC/9C78D80937AD50852C50BD3F1F982</Key></Contents>
+                <NextContinuationToken>1ueGcxLPRx1Tr/XYExHnhbYLgveDs2J/wm36Hy4vbOwM=</NextContinuationToken>
+            </ListBucketResult>"#,
+        )
+        .unwrap();
+        assert_eq!(
+            ListObjectsPage {
+                contents: vec![ListedObject {
+                    key: "2AC/9C78D80937AD50852C50BD3F1F982".to_string(),
+                }],
+                is_truncated: true,
+                next_continuation_token: Some(
+                    "1ueGcxLPRx1Tr/XYExHnhbYLgveDs2J/wm36Hy4vbOwM=".to_string()
+                ),
+            },
+            page
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(ListObjectsPage::parse("not xml").is_err());
+    }
+}
@@ -0,0 +1,211 @@
+//! A minimal in-process S3 server for tests. It only supports the operations [super::S3BlockStore] uses
+//! and keeps all objects in memory.
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use reqwest::Url;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+use super::signing::sha256_hex;
+
+/// Return at most this many keys per ListObjectsV2 page so that tests exercise pagination
+const MAX_KEYS_PER_PAGE: usize = 3;
+
+struct State {
+    bucket: String,
+    access_key_id: String,
+    objects: Mutex<BTreeMap<String, Vec<u8>>>,
+}
+
+pub struct MockS3Server {
+    addr: SocketAddr,
+    state: Arc<State>,
+    task: JoinHandle<()>,
+}
+
+impl MockS3Server {
+    pub async fn start(bucket: &str, access_key_id: &str) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(State {
+            bucket: bucket.to_string(),
+            access_key_id: access_key_id.to_string(),
+            objects: Mutex::new(BTreeMap::new()),
+        });
+        let state_clone = Arc::clone(&state);
+        let task = tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let state = Arc::clone(&state_clone);
+                tokio::spawn(async move {
+                    let service =
+                        service_fn(move |request| handle_request(Arc::clone(&state), request));
+                    // Errors here are usually just the client closing the connection
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+        Self { addr, state, task }
+    }
+
+    pub fn endpoint(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn object_keys(&self) -> Vec<String> {
+        self.state.objects.lock().unwrap().keys().cloned().collect()
+    }
+
+    pub fn object(&self, key: &str) -> Option<Vec<u8>> {
+        self.state.objects.lock().unwrap().get(key).cloned()
+    }
+
+    pub fn put_object(&self, key: &str, content: Vec<u8>) {
+        self.state
+            .objects
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), content);
+    }
+}
+
+impl Drop for MockS3Server {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn handle_request(
+    state: Arc<State>,
+    request: Request<Incoming>,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    let url = Url::parse(&format!("http://localhost{}", request.uri())).unwrap();
+    let method = request.method().clone();
+    let headers = request.headers().clone();
+    let body = request.into_body().collect().await.unwrap().to_bytes();
+
+    let expected_auth_prefix = format!("AWS4-HMAC-SHA256 Credential={}/", state.access_key_id);
+    let authorized = headers
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with(&expected_auth_prefix));
+    if !authorized {
+        return Ok(response(StatusCode::FORBIDDEN, "AccessDenied"));
+    }
+    let payload_hash_correct = headers
+        .get("x-amz-content-sha256")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value == sha256_hex(&body));
+    if !payload_hash_correct {
+        return Ok(response(
+            StatusCode::BAD_REQUEST,
+            "XAmzContentSHA256Mismatch",
+        ));
+    }
+
+    let path = percent_decode(url.path());
+    let Some(path) = path.strip_prefix(&format!("/{}", state.bucket)) else {
+        return Ok(response(StatusCode::NOT_FOUND, "NoSuchBucket"));
+    };
+    let key = path.strip_prefix('/').unwrap_or("");
+
+    let mut objects = state.objects.lock().unwrap();
+    let response = match (method, key) {
+        (Method::GET, "") => {
+            let query: BTreeMap<String, String> = url.query_pairs().into_owned().collect();
+            list_objects(&objects, &query)
+        }
+        (Method::GET, key) => match objects.get(key) {
+            Some(content) => Response::new(Full::new(Bytes::from(content.clone()))),
+            None => response(StatusCode::NOT_FOUND, "NoSuchKey"),
+        },
+        (Method::HEAD, key) => match objects.get(key) {
+            Some(_) => Response::new(Full::new(Bytes::new())),
+            None => response(StatusCode::NOT_FOUND, ""),
+        },
+        (Method::PUT, key) => {
+            let only_if_not_exists = headers
+                .get("if-none-match")
+                .is_some_and(|value| value == "*");
+            if only_if_not_exists && objects.contains_key(key) {
+                response(StatusCode::PRECONDITION_FAILED, "PreconditionFailed")
+            } else {
+                objects.insert(key.to_string(), body.to_vec());
+                Response::new(Full::new(Bytes::new()))
+            }
+        }
+        (Method::DELETE, key) => {
+            objects.remove(key);
+            response(StatusCode::NO_CONTENT, "")
+        }
+        _ => response(StatusCode::METHOD_NOT_ALLOWED, "MethodNotAllowed"),
+    };
+    Ok(response)
+}
+
+fn list_objects(
+    objects: &BTreeMap<String, Vec<u8>>,
+    query: &BTreeMap<String, String>,
+) -> Response<Full<Bytes>> {
+    assert_eq!(Some("2"), query.get("list-type").map(String::as_str));
+    let prefix = query.get("prefix").map(String::as_str).unwrap_or("");
+    // Our continuation tokens are just the last key of the previous page
+    let start_after = query.get("continuation-token");
+    let mut matching = objects
+        .keys()
+        .filter(|key| key.starts_with(prefix))
+        .filter(|key| start_after.is_none_or(|start_after| *key > start_after));
+    let page: Vec<&String> = matching.by_ref().take(MAX_KEYS_PER_PAGE).collect();
+    let is_truncated = matching.next().is_some();
+
+    let mut xml = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8"?><ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">"#,
+    );
+    xml.push_str(&format!(
+        "<KeyCount>{}</KeyCount><IsTruncated>{is_truncated}</IsTruncated>",
+        page.len()
+    ));
+    for key in &page {
+        xml.push_str(&format!("<Contents><Key>{key}</Key></Contents>"));
+    }
+    if is_truncated {
+        xml.push_str(&format!(
+            "<NextContinuationToken>{}</NextContinuationToken>",
+            page.last().expect("Truncated pages can't be empty")
+        ));
+    }
+    xml.push_str("</ListBucketResult>");
+    Response::new(Full::new(Bytes::from(xml)))
+}
+
+fn response(status: StatusCode, body: &str) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::from(body.to_string())));
+    *response.status_mut() = status;
+    response
+}
+
+fn percent_decode(input: &str) -> String {
+    let mut result = Vec::with_capacity(input.len());
+    let mut bytes = input.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte == b'%' {
+            let hex = [bytes.next().unwrap(), bytes.next().unwrap()];
+            result.push(u8::from_str_radix(std::str::from_utf8(&hex).unwrap(), 16).unwrap());
+        } else {
+            result.push(byte);
+        }
+    }
+    String::from_utf8(result).unwrap()
+}
@@ -0,0 +1,302 @@
+use anyhow::{Context, Result, anyhow};
+use async_trait::async_trait;
+use byte_unit::Byte;
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use std::fmt::{self, Debug};
+
+use super::ondisk::{
+    _check_and_remove_header, _is_allowed_blockid_character, FORMAT_VERSION_HEADER, NONPREFIX_LEN,
+    PREFIX_LEN,
+};
+use crate::low_level::interface::block_data::create_block_data_wrapper;
+use crate::{
+    BLOCKID_LEN, BlockId, Overhead,
+    low_level::{
+        BlockStoreDeleter, BlockStoreReader, LLBlockStore, OptimizedBlockStoreWriter,
+        interface::block_data::IBlockData,
+    },
+    utils::{RemoveResult, TryCreateResult},
+};
+use cryfs_utils::{
+    async_drop::{AsyncDrop, AsyncDropGuard},
+    data::Data,
+};
+
+mod client;
+mod list_objects;
+mod signing;
+
+#[cfg(test)]
+mod mock_server;
+#[cfg(test)]
+mod tests;
+
+use client::{PutResult, S3Client};
+
+/// Configuration for connecting an [S3BlockStore] to a bucket on an S3 compatible object storage service.
+#[derive(Debug, Clone)]
+pub struct S3Config {
+    /// URL of the S3 service, e.g. `https://s3.eu-central-1.amazonaws.com` or `http://localhost:9000` for a local MinIO server.
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: String,
+    /// Prefix for all object keys, e.g. `myvault/`. This allows storing multiple vaults in the same bucket. Can be empty.
+    pub prefix: String,
+    pub addressing_style: S3AddressingStyle,
+    pub credentials: S3Credentials,
+}
+
+/// Whether the bucket name is part of the URL path (`https://endpoint/bucket/key`), as needed by most self-hosted
+/// S3 compatible services, or part of the host name (`https://bucket.endpoint/key`), as preferred by AWS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum S3AddressingStyle {
+    Path,
+    VirtualHosted,
+}
+
+#[derive(Clone)]
+pub struct S3Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+impl S3Credentials {
+    /// Read credentials from the `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and (optionally) `AWS_SESSION_TOKEN`
+    /// environment variables, like the AWS command line tools do.
+    pub fn from_env() -> Result<Self> {
+        let var = |name: &str| {
+            std::env::var(name)
+                .with_context(|| format!("Failed to read environment variable {name}"))
+        };
+        Ok(Self {
+            access_key_id: var("AWS_ACCESS_KEY_ID")?,
+            secret_access_key: var("AWS_SECRET_ACCESS_KEY")?,
+            session_token: std::env::var("AWS_SESSION_TOKEN").ok(),
+        })
+    }
+}
+
+impl Debug for S3Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("S3Credentials")
+            .field("access_key_id", &self.access_key_id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A block store storing each block as an object in a bucket of an S3 compatible object storage service.
+///
+/// Objects use the same key layout and file format as the block files of [super::OnDiskBlockStore],
+/// i.e. `{prefix}{first 3 hex digits of the block id}/{remaining hex digits}`. This means a vault directory
+/// can be uploaded to a bucket (or downloaded from it) with any S3 sync tool and used with the other block store.
+///
+/// [BlockStoreWriter::try_create](crate::BlockStoreWriter::try_create) uses conditional writes (`If-None-Match: *`),
+/// so the object storage service needs to support those. AWS S3 and MinIO do.
+pub struct S3BlockStore {
+    client: S3Client,
+    prefix: String,
+}
+
+impl S3BlockStore {
+    pub fn new(config: S3Config) -> Result<AsyncDropGuard<Self>> {
+        let prefix = config.prefix.clone();
+        let client = S3Client::new(config)?;
+        Ok(AsyncDropGuard::new(Self { client, prefix }))
+    }
+
+    fn _object_key(&self, block_id: &BlockId) -> String {
+        _object_key(&self.prefix, block_id)
+    }
+}
+
+#[async_trait]
+impl BlockStoreReader for S3BlockStore {
+    async fn exists(&self, id: &BlockId) -> Result<bool> {
+        self.client.head_object(&self._object_key(id)).await
+    }
+
+    async fn load(&self, id: &BlockId) -> Result<Option<Data>> {
+        let key = self._object_key(id);
+        let Some(object) = self.client.get_object(&key).await? else {
+            return Ok(None);
+        };
+        let block_content = _check_and_remove_header(object.into())
+            .with_context(|| format!("Failed to parse contents of S3 object {key}"))?;
+        Ok(Some(block_content))
+    }
+
+    async fn num_blocks(&self) -> Result<u64> {
+        self.all_blocks()
+            .await?
+            .try_fold(0, |acc, _block_id| futures::future::ready(Ok(acc + 1)))
+            .await
+    }
+
+    fn estimate_num_free_bytes(&self) -> Result<Byte> {
+        // Object storage doesn't have a capacity we could query
+        Ok(Byte::MAX)
+    }
+
+    fn overhead(&self) -> Overhead {
+        Overhead::new(Byte::from_u64(FORMAT_VERSION_HEADER.len() as u64))
+    }
+
+    async fn all_blocks(&self) -> Result<BoxStream<'static, Result<BlockId>>> {
+        enum ListState {
+            NextPage { continuation_token: Option<String> },
+            Done,
+        }
+
+        let client = self.client.clone();
+        let prefix = self.prefix.clone();
+        let pages = stream::try_unfold(
+            ListState::NextPage {
+                continuation_token: None,
+            },
+            move |state| {
+                let client = client.clone();
+                let prefix = prefix.clone();
+                async move {
+                    let ListState::NextPage { continuation_token } = state else {
+                        return Ok(None);
+                    };
+                    let page = client
+                        .list_objects_page(&prefix, continuation_token.as_deref())
+                        .await?;
+                    let next_state = match (page.is_truncated, page.next_continuation_token) {
+                        (true, Some(continuation_token)) => ListState::NextPage {
+                            continuation_token: Some(continuation_token),
+                        },
+                        (true, None) => {
+                            return Err(anyhow!(
+                                "S3 returned a truncated listing without a continuation token"
+                            ));
+                        }
+                        (false, _) => ListState::Done,
+                    };
+                    let block_ids: Vec<Result<BlockId>> = page
+                        .contents
+                        .into_iter()
+                        .filter_map(|object| _blockid_from_object_key(&prefix, &object.key))
+                        .collect();
+                    Ok(Some((stream::iter(block_ids), next_state)))
+                }
+            },
+        );
+        Ok(pages.try_flatten().boxed())
+    }
+}
+
+#[async_trait]
+impl BlockStoreDeleter for S3BlockStore {
+    async fn remove(&self, id: &BlockId) -> Result<RemoveResult> {
+        let key = self._object_key(id);
+        // S3 doesn't tell us whether a deleted object existed, so we have to check first.
+        if !self.client.head_object(&key).await? {
+            return Ok(RemoveResult::NotRemovedBecauseItDoesntExist);
+        }
+        self.client.delete_object(&key).await?;
+        Ok(RemoveResult::SuccessfullyRemoved)
+    }
+}
+
+create_block_data_wrapper!(BlockData);
+
+#[async_trait]
+impl OptimizedBlockStoreWriter for S3BlockStore {
+    type BlockData = BlockData;
+
+    fn allocate(size: usize) -> BlockData {
+        let mut data = Data::from(vec![0; FORMAT_VERSION_HEADER.len() + size]);
+        data.shrink_to_subregion(FORMAT_VERSION_HEADER.len()..);
+        BlockData::new(data)
+    }
+
+    async fn try_create_optimized(&self, id: &BlockId, data: BlockData) -> Result<TryCreateResult> {
+        let put_result = self
+            .client
+            .put_object(&self._object_key(id), _add_header(data), true)
+            .await?;
+        match put_result {
+            PutResult::Stored => Ok(TryCreateResult::SuccessfullyCreated),
+            PutResult::NotStoredBecauseObjectAlreadyExists => {
+                Ok(TryCreateResult::NotCreatedBecauseBlockIdAlreadyExists)
+            }
+        }
+    }
+
+    async fn store_optimized(&self, id: &BlockId, data: BlockData) -> Result<()> {
+        let key = self._object_key(id);
+        let put_result = self
+            .client
+            .put_object(&key, _add_header(data), false)
+            .await?;
+        match put_result {
+            PutResult::Stored => Ok(()),
+            PutResult::NotStoredBecauseObjectAlreadyExists => Err(anyhow!(
+                "S3 service didn't store object {key} even though the write wasn't conditional"
+            ))
+            .with_context(|| format!("Failed to store block {id:?}")),
+        }
+    }
+}
+
+impl Debug for S3BlockStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "S3BlockStore")
+    }
+}
+
+#[async_trait]
+impl AsyncDrop for S3BlockStore {
+    type Error = anyhow::Error;
+    async fn async_drop_impl(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl LLBlockStore for S3BlockStore {
+    async fn sync(&self) -> Result<()> {
+        // S3 only acknowledges a write once the object is stored durably
+        Ok(())
+    }
+}
+
+fn _add_header(data: BlockData) -> Vec<u8> {
+    let mut data = data.extract();
+    data.grow_region_fail_if_reallocation_necessary(FORMAT_VERSION_HEADER.len(), 0)
+        .expect("Tried to grow data region to store in S3BlockStore");
+    data.as_mut()[..FORMAT_VERSION_HEADER.len()].copy_from_slice(FORMAT_VERSION_HEADER);
+    data.into_vec()
+}
+
+fn _object_key(prefix: &str, block_id: &BlockId) -> String {
+    let block_id_str = block_id.to_hex_upper();
+    format!(
+        "{prefix}{}/{}",
+        &block_id_str[..PREFIX_LEN],
+        &block_id_str[PREFIX_LEN..]
+    )
+}
+
+/// Returns `None` if the key doesn't belong to a block, e.g. because it is a `cryfs.config` file stored in the same bucket.
+fn _blockid_from_object_key(prefix: &str, key: &str) -> Option<Result<BlockId>> {
+    let key = key.strip_prefix(prefix)?;
+    let (blockid_prefix, blockid_nonprefix) = key.split_once('/')?;
+    if blockid_prefix.len() != PREFIX_LEN
+        || blockid_nonprefix.len() != NONPREFIX_LEN
+        || !blockid_prefix.chars().all(_is_allowed_blockid_character)
+        || !blockid_nonprefix.chars().all(_is_allowed_blockid_character)
+    {
+        return None;
+    }
+    let mut blockid = String::with_capacity(2 * BLOCKID_LEN);
+    blockid.push_str(blockid_prefix);
+    blockid.push_str(blockid_nonprefix);
+    Some(
+        BlockId::from_hex(&blockid)
+            .with_context(|| format!("S3 object key `{key}` cannot be parsed as block id")),
+    )
+}
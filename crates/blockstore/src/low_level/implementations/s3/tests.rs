@@ -0,0 +1,251 @@
+#![allow(non_snake_case)]
+
+//! By default, these tests run against an in-process mock server. To run them against a real
+//! S3 compatible service, e.g. a local MinIO server, set the following environment variables:
+//! - `CRYFS_TEST_S3_ENDPOINT`, e.g. `http://localhost:9000`
+//! - `CRYFS_TEST_S3_BUCKET`, the bucket must already exist
+//! - `CRYFS_TEST_S3_REGION` (optional, defaults to `us-east-1`)
+//! - `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`
+//!
+//! Each test uses a random key prefix, so tests don't interfere with each other.
+
+use async_trait::async_trait;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use super::mock_server::MockS3Server;
+use super::*;
+use crate::instantiate_blockstore_tests_for_lowlevel_blockstore;
+use crate::low_level::{BlockStoreWriter, EncryptedBlockStore};
+use crate::tests::{
+    low_level::LLFixture,
+    utils::{blockid, data},
+};
+use cryfs_crypto::symmetric::{CipherDef, EncryptionKey, XChaCha20Poly1305};
+use lockable::InfallibleUnwrap;
+
+const MOCK_BUCKET: &str = "cryfs-test-bucket";
+const MOCK_ACCESS_KEY_ID: &str = "test-access-key";
+
+fn random_prefix() -> String {
+    format!("cryfs-test-{:016x}/", rand::random::<u64>())
+}
+
+/// Returns `None` if the tests should run against the mock server
+fn external_config() -> Option<S3Config> {
+    let endpoint = std::env::var("CRYFS_TEST_S3_ENDPOINT").ok()?;
+    Some(S3Config {
+        endpoint,
+        region: std::env::var("CRYFS_TEST_S3_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
+        bucket: std::env::var("CRYFS_TEST_S3_BUCKET")
+            .expect("CRYFS_TEST_S3_BUCKET must be set if CRYFS_TEST_S3_ENDPOINT is set"),
+        prefix: random_prefix(),
+        addressing_style: S3AddressingStyle::Path,
+        credentials: S3Credentials::from_env().unwrap(),
+    })
+}
+
+fn mock_config(server: &MockS3Server, prefix: &str) -> S3Config {
+    S3Config {
+        endpoint: server.endpoint(),
+        region: "us-east-1".to_string(),
+        bucket: MOCK_BUCKET.to_string(),
+        prefix: prefix.to_string(),
+        addressing_style: S3AddressingStyle::Path,
+        credentials: S3Credentials {
+            access_key_id: MOCK_ACCESS_KEY_ID.to_string(),
+            secret_access_key: "test-secret-key".to_string(),
+            session_token: None,
+        },
+    }
+}
+
+struct TestFixture {
+    // Keep the server alive for as long as the test runs
+    server: Option<MockS3Server>,
+}
+
+impl TestFixture {
+    async fn config(&mut self) -> S3Config {
+        if let Some(config) = external_config() {
+            return config;
+        }
+        let server = MockS3Server::start(MOCK_BUCKET, MOCK_ACCESS_KEY_ID).await;
+        let config = mock_config(&server, &random_prefix());
+        self.server = Some(server);
+        config
+    }
+}
+
+#[async_trait]
+impl LLFixture for TestFixture {
+    type ConcreteBlockStore = S3BlockStore;
+    fn new() -> Self {
+        Self { server: None }
+    }
+    async fn store(&mut self) -> AsyncDropGuard<Self::ConcreteBlockStore> {
+        S3BlockStore::new(self.config().await).unwrap()
+    }
+    async fn yield_fixture(&self, _store: &Self::ConcreteBlockStore) {}
+}
+
+instantiate_blockstore_tests_for_lowlevel_blockstore!(TestFixture, (flavor = "multi_thread"));
+
+mod encrypted {
+    //! Make sure [S3BlockStore] works as the base of the block store stack
+
+    use super::*;
+
+    struct EncryptedTestFixture {
+        inner: TestFixture,
+    }
+
+    #[async_trait]
+    impl LLFixture for EncryptedTestFixture {
+        type ConcreteBlockStore =
+            EncryptedBlockStore<XChaCha20Poly1305, S3BlockStore, S3BlockStore>;
+        fn new() -> Self {
+            Self {
+                inner: TestFixture::new(),
+            }
+        }
+        async fn store(&mut self) -> AsyncDropGuard<Self::ConcreteBlockStore> {
+            let key = EncryptionKey::new(32, |key_data| {
+                StdRng::seed_from_u64(0).fill_bytes(key_data);
+                Ok(())
+            })
+            .infallible_unwrap();
+            EncryptedBlockStore::new(
+                self.inner.store().await,
+                XChaCha20Poly1305::new(key).unwrap(),
+            )
+        }
+        async fn yield_fixture(&self, _store: &Self::ConcreteBlockStore) {}
+    }
+
+    instantiate_blockstore_tests_for_lowlevel_blockstore!(
+        EncryptedTestFixture,
+        (flavor = "multi_thread")
+    );
+}
+
+mod mock_only {
+    //! Tests that need to look at the objects stored on the server and therefore only run against the mock server
+
+    use super::*;
+
+    async fn setup(prefix: &str) -> (MockS3Server, AsyncDropGuard<S3BlockStore>) {
+        let server = MockS3Server::start(MOCK_BUCKET, MOCK_ACCESS_KEY_ID).await;
+        let store = S3BlockStore::new(mock_config(&server, prefix)).unwrap();
+        (server, store)
+    }
+
+    #[tokio::test]
+    async fn test_objectKey() {
+        assert_eq!(
+            "myprefix/2AC/9C78D80937AD50852C50BD3F1F982",
+            _object_key(
+                "myprefix/",
+                &BlockId::from_hex("2AC9C78D80937AD50852C50BD3F1F982").unwrap()
+            )
+        );
+        assert_eq!(
+            "2AC/9C78D80937AD50852C50BD3F1F982",
+            _object_key(
+                "",
+                &BlockId::from_hex("2AC9C78D80937AD50852C50BD3F1F982").unwrap()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_whenStoringBlock_thenObjectHasOnDiskFormat() {
+        let (server, mut store) = setup("vault/").await;
+
+        store.store(&blockid(1), &data(100, 0)).await.unwrap();
+
+        let key = _object_key("vault/", &blockid(1));
+        assert_eq!(vec![key.clone()], server.object_keys());
+        let mut expected = FORMAT_VERSION_HEADER.to_vec();
+        expected.extend_from_slice(&data(100, 0));
+        assert_eq!(Some(expected), server.object(&key));
+
+        store.async_drop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_whenRemovingBlock_thenObjectIsDeleted() {
+        let (server, mut store) = setup("vault/").await;
+
+        store.store(&blockid(1), &data(100, 0)).await.unwrap();
+        assert_eq!(
+            RemoveResult::SuccessfullyRemoved,
+            store.remove(&blockid(1)).await.unwrap()
+        );
+        assert!(server.object_keys().is_empty());
+
+        store.async_drop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_givenNonBlockObjects_whenListingBlocks_thenIgnoresThem() {
+        let (server, mut store) = setup("vault/").await;
+
+        store.store(&blockid(1), &data(100, 0)).await.unwrap();
+        store.store(&blockid(2), &data(100, 1)).await.unwrap();
+        server.put_object("vault/cryfs.config", vec![1, 2, 3]);
+        server.put_object("vault/2AC/tooshort", vec![1, 2, 3]);
+        server.put_object("vault/somedir/9C78D80937AD50852C50BD3F1F982", vec![1]);
+        server.put_object(&_object_key("othervault/", &blockid(3)), vec![1]);
+        server.put_object(&_object_key("", &blockid(4)), vec![1]);
+
+        let mut blocks: Vec<BlockId> = store
+            .all_blocks()
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        blocks.sort();
+        let mut expected = vec![blockid(1), blockid(2)];
+        expected.sort();
+        assert_eq!(expected, blocks);
+        assert_eq!(2, store.num_blocks().await.unwrap());
+
+        store.async_drop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_givenManyBlocks_whenListingBlocks_thenReturnsAllPages() {
+        let (_server, mut store) = setup("").await;
+
+        for i in 0..20 {
+            store.store(&blockid(i), &data(10, i)).await.unwrap();
+        }
+        assert_eq!(20, store.num_blocks().await.unwrap());
+
+        store.async_drop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_givenObjectWithInvalidHeader_whenLoading_thenFails() {
+        let (server, mut store) = setup("vault/").await;
+
+        server.put_object(&_object_key("vault/", &blockid(1)), b"invalid".to_vec());
+        assert!(store.load(&blockid(1)).await.is_err());
+
+        store.async_drop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_givenWrongCredentials_whenLoading_thenFails() {
+        let server = MockS3Server::start(MOCK_BUCKET, MOCK_ACCESS_KEY_ID).await;
+        let mut config = mock_config(&server, "vault/");
+        config.credentials.access_key_id = "wrong-access-key".to_string();
+        let mut store = S3BlockStore::new(config).unwrap();
+
+        let error = store.load(&blockid(1)).await.unwrap_err();
+        assert!(format!("{error:?}").contains("403"), "{error:?}");
+
+        store.async_drop().await.unwrap();
+    }
+}
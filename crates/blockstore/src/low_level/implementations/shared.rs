@@ -109,9 +109,13 @@ impl<B: Sync + Send + Debug + AsyncDrop<Error = anyhow::Error>> AsyncDrop for Sh
     }
 }
 
+#[async_trait]
 impl<B: LLBlockStore + OptimizedBlockStoreWriter + Sync + Send + Debug> LLBlockStore
     for SharedBlockStore<B>
 {
+    async fn sync(&self) -> Result<()> {
+        self.underlying_store.sync().await
+    }
 }
 
 impl<B: Debug + Sync + Send + AsyncDrop<Error = anyhow::Error>> Deref for SharedBlockStore<B> {
@@ -0,0 +1,824 @@
+use anyhow::{Result, anyhow, bail, ensure};
+use async_trait::async_trait;
+use byte_unit::Byte;
+use futures::future;
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use lockable::{AsyncLimit, InfallibleUnwrap, LockableHashMap};
+use std::collections::HashSet;
+use std::fmt::{self, Debug};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::{Mutex, RwLock};
+
+use crate::low_level::interface::block_data::create_block_data_wrapper;
+use crate::{
+    BlockId, Overhead,
+    low_level::{
+        BlockStoreDeleter, BlockStoreReader, LLBlockStore, OptimizedBlockStoreWriter,
+        interface::block_data::IBlockData,
+    },
+    utils::{RemoveResult, TryCreateResult},
+};
+use cryfs_utils::{
+    async_drop::{AsyncDrop, AsyncDropGuard},
+    data::Data,
+};
+
+/// Number of points each target gets on the hash ring. More points spread the blocks more evenly.
+const POINTS_PER_TARGET: u32 = 128;
+
+/// A block store that spreads blocks over several underlying stores, e.g. to combine the quota of several cloud accounts.
+///
+/// Each block is stored in exactly one target, chosen by consistent hashing of its block id. The placement only depends
+/// on the target names, not on their order, so the same targets must be passed in with the same names when the store
+/// is opened again. Adding or removing a target only moves the blocks that are placed differently afterwards.
+///
+/// While a rebalance is running, blocks may still be in their old target. Reads then fall back to the other targets
+/// and removals go to all targets. If a rebalance gets interrupted, e.g. by a crash, open the store with the new
+/// set of targets and call [StripedBlockStore::rebalance] to finish it.
+pub struct StripedBlockStore<B: LLBlockStore + Send + Sync> {
+    state: RwLock<State<B>>,
+    /// While rebalancing, operations on a block are serialized with moving that block
+    block_locks: LockableHashMap<BlockId, ()>,
+    rebalance_lock: Mutex<()>,
+    /// The block size of the file system depends on this, so it can't change when targets are added
+    overhead: Overhead,
+    /// Returned by [BlockStoreReader::estimate_num_free_bytes] while the targets are being changed
+    last_free_bytes_estimate: AtomicU64,
+}
+
+struct State<B: LLBlockStore + Send + Sync> {
+    targets: Vec<StripeTarget<B>>,
+    ring: HashRing,
+    rebalancing: bool,
+}
+
+struct StripeTarget<B: LLBlockStore + Send + Sync> {
+    name: String,
+    store: AsyncDropGuard<B>,
+    /// The target is being removed. It isn't on the hash ring anymore and its blocks get moved to the other targets.
+    draining: bool,
+}
+
+/// What a rebalance of a [StripedBlockStore] did
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RebalanceSummary {
+    /// Blocks that were moved to the target they belong to
+    pub num_moved: u64,
+    /// Outdated copies that were removed because the target they belong to already had a newer version
+    pub num_discarded: u64,
+}
+
+impl<B: LLBlockStore + Send + Sync> StripedBlockStore<B> {
+    /// Creates a striped block store over the given targets. Target names must be unique.
+    pub fn new(targets: Vec<(String, AsyncDropGuard<B>)>) -> AsyncDropGuard<Self> {
+        assert!(
+            !targets.is_empty(),
+            "StripedBlockStore needs at least one target"
+        );
+        let targets: Vec<StripeTarget<B>> = targets
+            .into_iter()
+            .map(|(name, store)| StripeTarget {
+                name,
+                store,
+                draining: false,
+            })
+            .collect();
+        let names: HashSet<&str> = targets.iter().map(|target| target.name.as_str()).collect();
+        assert_eq!(
+            targets.len(),
+            names.len(),
+            "StripedBlockStore target names must be unique"
+        );
+        let ring = HashRing::new(&targets);
+        let overhead = targets
+            .iter()
+            .map(|target| target.store.overhead())
+            .max_by_key(|overhead| {
+                overhead.physical_block_size_from_usable_block_size(Byte::from_u64(0))
+            })
+            .expect("There is at least one target");
+        AsyncDropGuard::new(Self {
+            state: RwLock::new(State {
+                targets,
+                ring,
+                rebalancing: false,
+            }),
+            block_locks: LockableHashMap::new(),
+            rebalance_lock: Mutex::new(()),
+            overhead,
+            last_free_bytes_estimate: AtomicU64::new(0),
+        })
+    }
+
+    pub async fn target_names(&self) -> Vec<String> {
+        self.state
+            .read()
+            .await
+            .targets
+            .iter()
+            .filter(|target| !target.draining)
+            .map(|target| target.name.clone())
+            .collect()
+    }
+
+    /// Adds a target and moves the blocks that belong to it there. The store stays usable while this is running.
+    pub async fn add_target(
+        &self,
+        name: String,
+        mut store: AsyncDropGuard<B>,
+    ) -> Result<RebalanceSummary> {
+        let _rebalance_lock = self.rebalance_lock.lock().await;
+        {
+            let mut state = self.state.write().await;
+            let error = if state.targets.iter().any(|target| target.name == name) {
+                Some(anyhow!(
+                    "StripedBlockStore already has a target named {name}"
+                ))
+            } else if store.overhead() != self.overhead {
+                Some(anyhow!(
+                    "Target {name} has a different overhead than the existing targets, which would change the block size"
+                ))
+            } else {
+                None
+            };
+            if let Some(error) = error {
+                drop(state);
+                store.async_drop().await?;
+                return Err(error);
+            }
+            state.targets.push(StripeTarget {
+                name,
+                store,
+                draining: false,
+            });
+            state.ring = HashRing::new(&state.targets);
+            state.rebalancing = true;
+        }
+        self._rebalance().await
+    }
+
+    /// Moves all blocks of a target to the other targets and then removes it. The store stays usable while this is running.
+    pub async fn remove_target(&self, name: &str) -> Result<RebalanceSummary> {
+        let _rebalance_lock = self.rebalance_lock.lock().await;
+        {
+            let mut state = self.state.write().await;
+            let num_remaining = state
+                .targets
+                .iter()
+                .filter(|target| !target.draining && target.name != name)
+                .count();
+            let Some(target) = state.targets.iter_mut().find(|target| target.name == name) else {
+                bail!("StripedBlockStore doesn't have a target named {name}");
+            };
+            ensure!(
+                num_remaining > 0,
+                "Can't remove the last target of a StripedBlockStore"
+            );
+            target.draining = true;
+            state.ring = HashRing::new(&state.targets);
+            state.rebalancing = true;
+        }
+        self._rebalance().await
+    }
+
+    /// Moves all blocks that aren't in the target they belong to. This is only needed to finish a rebalance
+    /// that got interrupted, [StripedBlockStore::add_target] and [StripedBlockStore::remove_target] already rebalance.
+    pub async fn rebalance(&self) -> Result<RebalanceSummary> {
+        let _rebalance_lock = self.rebalance_lock.lock().await;
+        self.state.write().await.rebalancing = true;
+        self._rebalance().await
+    }
+
+    /// Must be called with `rebalance_lock` held and `rebalancing` set. If this fails, `rebalancing` stays set
+    /// so that reads still find blocks that weren't moved yet.
+    async fn _rebalance(&self) -> Result<RebalanceSummary> {
+        let mut summary = RebalanceSummary::default();
+        let num_targets = self.state.read().await.targets.len();
+        for source in 0..num_targets {
+            let mut block_ids = self.state.read().await.targets[source]
+                .store
+                .all_blocks()
+                .await?;
+            while let Some(block_id) = block_ids.try_next().await? {
+                self._move_if_misplaced(source, block_id, &mut summary)
+                    .await?;
+            }
+        }
+
+        let mut state = self.state.write().await;
+        let mut drained = Vec::new();
+        let mut index = 0;
+        while index < state.targets.len() {
+            if state.targets[index].draining {
+                drained.push(state.targets.remove(index));
+            } else {
+                index += 1;
+            }
+        }
+        state.ring = HashRing::new(&state.targets);
+        state.rebalancing = false;
+        drop(state);
+
+        for mut target in drained {
+            log::info!("Removed target {} from StripedBlockStore", target.name);
+            target.store.async_drop().await?;
+        }
+        Ok(summary)
+    }
+
+    async fn _move_if_misplaced(
+        &self,
+        source: usize,
+        block_id: BlockId,
+        summary: &mut RebalanceSummary,
+    ) -> Result<()> {
+        let state = self.state.read().await;
+        let owner = state.ring.owner(&block_id);
+        if owner == source {
+            return Ok(());
+        }
+        let _block_lock = self._lock_block(block_id).await;
+        let source_store = &state.targets[source].store;
+        let Some(data) = source_store.load(&block_id).await? else {
+            // The block was removed since we listed it
+            return Ok(());
+        };
+        // If the owner already has the block, it was written during the rebalance and our copy is outdated
+        match state.targets[owner]
+            .store
+            .try_create(&block_id, data.as_ref())
+            .await?
+        {
+            TryCreateResult::SuccessfullyCreated => summary.num_moved += 1,
+            TryCreateResult::NotCreatedBecauseBlockIdAlreadyExists => summary.num_discarded += 1,
+        }
+        // We hold the block lock, so the block can't have been removed since we loaded it
+        let _ = source_store.remove(&block_id).await?;
+        Ok(())
+    }
+
+    async fn _lock_block(
+        &self,
+        block_id: BlockId,
+    ) -> <LockableHashMap<BlockId, ()> as lockable::Lockable<BlockId, ()>>::Guard<'_> {
+        self.block_locks
+            .async_lock(block_id, AsyncLimit::no_limit())
+            .await
+            .infallible_unwrap()
+    }
+
+    /// Targets that may have the block, the one it belongs to first
+    fn _locations(state: &State<B>, id: &BlockId) -> Vec<usize> {
+        let owner = state.ring.owner(id);
+        let mut locations = vec![owner];
+        if state.rebalancing {
+            locations.extend((0..state.targets.len()).filter(|&index| index != owner));
+        }
+        locations
+    }
+}
+
+#[async_trait]
+impl<B: LLBlockStore + Send + Sync> BlockStoreReader for StripedBlockStore<B> {
+    async fn exists(&self, id: &BlockId) -> Result<bool> {
+        let state = self.state.read().await;
+        let _block_lock = if state.rebalancing {
+            Some(self._lock_block(*id).await)
+        } else {
+            None
+        };
+        for index in Self::_locations(&state, id) {
+            if state.targets[index].store.exists(id).await? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    async fn load(&self, id: &BlockId) -> Result<Option<Data>> {
+        let state = self.state.read().await;
+        let _block_lock = if state.rebalancing {
+            Some(self._lock_block(*id).await)
+        } else {
+            None
+        };
+        for index in Self::_locations(&state, id) {
+            if let Some(data) = state.targets[index].store.load(id).await? {
+                return Ok(Some(data));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn num_blocks(&self) -> Result<u64> {
+        let state = self.state.read().await;
+        let results =
+            future::join_all(state.targets.iter().map(|target| target.store.num_blocks())).await;
+        let mut sum = 0;
+        for result in results {
+            sum += result?;
+        }
+        Ok(sum)
+    }
+
+    fn estimate_num_free_bytes(&self) -> Result<Byte> {
+        let Ok(state) = self.state.try_read() else {
+            return Ok(Byte::from_u64(
+                self.last_free_bytes_estimate.load(Ordering::Relaxed),
+            ));
+        };
+        let mut sum: u64 = 0;
+        for target in state.targets.iter().filter(|target| !target.draining) {
+            sum = sum.saturating_add(target.store.estimate_num_free_bytes()?.as_u64());
+        }
+        self.last_free_bytes_estimate.store(sum, Ordering::Relaxed);
+        Ok(Byte::from_u64(sum))
+    }
+
+    fn overhead(&self) -> Overhead {
+        self.overhead
+    }
+
+    async fn all_blocks(&self) -> Result<BoxStream<'static, Result<BlockId>>> {
+        let state = self.state.read().await;
+        let mut streams = Vec::with_capacity(state.targets.len());
+        for target in &state.targets {
+            streams.push(target.store.all_blocks().await?);
+        }
+        let all_blocks = stream::iter(streams).flatten();
+        if state.rebalancing {
+            // A block may have been listed in its old target and again after it was moved
+            let block_ids: HashSet<BlockId> = all_blocks.try_collect().await?;
+            Ok(stream::iter(block_ids.into_iter().map(Ok)).boxed())
+        } else {
+            Ok(all_blocks.boxed())
+        }
+    }
+}
+
+#[async_trait]
+impl<B: LLBlockStore + Send + Sync> BlockStoreDeleter for StripedBlockStore<B> {
+    async fn remove(&self, id: &BlockId) -> Result<RemoveResult> {
+        let state = self.state.read().await;
+        let _block_lock = if state.rebalancing {
+            Some(self._lock_block(*id).await)
+        } else {
+            None
+        };
+        // Remove outdated copies in other targets too, otherwise a rebalance would bring the block back
+        let mut result = RemoveResult::NotRemovedBecauseItDoesntExist;
+        for index in Self::_locations(&state, id) {
+            if state.targets[index].store.remove(id).await? == RemoveResult::SuccessfullyRemoved {
+                result = RemoveResult::SuccessfullyRemoved;
+            }
+        }
+        Ok(result)
+    }
+}
+
+create_block_data_wrapper!(BlockData);
+
+#[async_trait]
+impl<B: LLBlockStore + Send + Sync> OptimizedBlockStoreWriter for StripedBlockStore<B> {
+    type BlockData = BlockData;
+
+    fn allocate(size: usize) -> BlockData {
+        BlockData::new(Data::from(vec![0; size]))
+    }
+
+    async fn try_create_optimized(&self, id: &BlockId, data: BlockData) -> Result<TryCreateResult> {
+        let state = self.state.read().await;
+        let _block_lock = if state.rebalancing {
+            Some(self._lock_block(*id).await)
+        } else {
+            None
+        };
+        let locations = Self::_locations(&state, id);
+        for &index in &locations[1..] {
+            if state.targets[index].store.exists(id).await? {
+                return Ok(TryCreateResult::NotCreatedBecauseBlockIdAlreadyExists);
+            }
+        }
+        state.targets[locations[0]]
+            .store
+            .try_create(id, data.extract().as_ref())
+            .await
+    }
+
+    async fn store_optimized(&self, id: &BlockId, data: BlockData) -> Result<()> {
+        let state = self.state.read().await;
+        let _block_lock = if state.rebalancing {
+            Some(self._lock_block(*id).await)
+        } else {
+            None
+        };
+        // An outdated copy in another target doesn't hurt, reads look at the owner first and a rebalance discards it
+        state.targets[state.ring.owner(id)]
+            .store
+            .store(id, data.extract().as_ref())
+            .await
+    }
+}
+
+impl<B: LLBlockStore + Send + Sync> Debug for StripedBlockStore<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.state.try_read() {
+            Ok(state) => f
+                .debug_map()
+                .entries(
+                    state
+                        .targets
+                        .iter()
+                        .map(|target| (&target.name, &target.store)),
+                )
+                .finish(),
+            Err(_) => write!(f, "StripedBlockStore(locked)"),
+        }
+    }
+}
+
+#[async_trait]
+impl<B: LLBlockStore + Send + Sync> AsyncDrop for StripedBlockStore<B> {
+    type Error = anyhow::Error;
+    async fn async_drop_impl(&mut self) -> Result<()> {
+        let mut first_error = None;
+        for target in &mut self.state.get_mut().targets {
+            if let Err(err) = target.store.async_drop().await {
+                first_error.get_or_insert(err);
+            }
+        }
+        match first_error {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl<B: LLBlockStore + Send + Sync> LLBlockStore for StripedBlockStore<B> {
+    async fn sync(&self) -> Result<()> {
+        let state = self.state.read().await;
+        let results =
+            future::join_all(state.targets.iter().map(|target| target.store.sync())).await;
+        for (target, result) in state.targets.iter().zip(results) {
+            if let Err(err) = result {
+                bail!("Failed to sync target {}: {err:?}", target.name);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Consistent hash ring mapping block ids to the index of the target they belong to
+struct HashRing {
+    /// Sorted by position
+    points: Vec<(u64, usize)>,
+}
+
+impl HashRing {
+    fn new<B: LLBlockStore + Send + Sync>(targets: &[StripeTarget<B>]) -> Self {
+        let mut points: Vec<(u64, usize)> = targets
+            .iter()
+            .enumerate()
+            .filter(|(_, target)| !target.draining)
+            .flat_map(|(index, target)| {
+                (0..POINTS_PER_TARGET).map(move |point| {
+                    let mut key = target.name.as_bytes().to_vec();
+                    key.extend_from_slice(&point.to_le_bytes());
+                    (stable_hash(&key), index)
+                })
+            })
+            .collect();
+        assert!(!points.is_empty(), "There is at least one target");
+        points.sort_unstable();
+        Self { points }
+    }
+
+    fn owner(&self, id: &BlockId) -> usize {
+        let position = stable_hash(id.data());
+        let index = self.points.partition_point(|&(point, _)| point < position);
+        self.points[index % self.points.len()].1
+    }
+}
+
+/// Block placement is persisted implicitly in where the blocks are stored, so this hash must never change.
+/// That's why we don't use [std::hash::DefaultHasher], whose algorithm isn't guaranteed to stay the same.
+fn stable_hash(data: &[u8]) -> u64 {
+    // FNV-1a, followed by the murmur3 finalizer to spread similar inputs over the whole ring
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in data {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^= hash >> 33;
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::low_level::{BlockStoreWriter, InMemoryBlockStore, SharedBlockStore};
+    use crate::tests::low_level::LLFixture;
+    use crate::tests::utils::{blockid, data};
+
+    struct TestFixture {}
+    #[async_trait]
+    impl LLFixture for TestFixture {
+        type ConcreteBlockStore = StripedBlockStore<InMemoryBlockStore>;
+        fn new() -> Self {
+            Self {}
+        }
+        async fn store(&mut self) -> AsyncDropGuard<Self::ConcreteBlockStore> {
+            StripedBlockStore::new(vec![
+                ("a".to_string(), InMemoryBlockStore::new()),
+                ("b".to_string(), InMemoryBlockStore::new()),
+                ("c".to_string(), InMemoryBlockStore::new()),
+            ])
+        }
+        async fn yield_fixture(&self, _store: &Self::ConcreteBlockStore) {}
+    }
+
+    crate::instantiate_blockstore_tests_for_lowlevel_blockstore!(
+        TestFixture,
+        (flavor = "multi_thread")
+    );
+
+    type Target = SharedBlockStore<InMemoryBlockStore>;
+
+    const NUM_BLOCKS: u64 = 200;
+
+    fn targets(names: &[&str]) -> Vec<(String, AsyncDropGuard<Target>)> {
+        names
+            .iter()
+            .map(|name| {
+                (
+                    name.to_string(),
+                    SharedBlockStore::new(InMemoryBlockStore::new()),
+                )
+            })
+            .collect()
+    }
+
+    /// Creates a striped store and keeps a handle to each target so that tests can look into them
+    fn striped(
+        targets: Vec<(String, AsyncDropGuard<Target>)>,
+    ) -> (
+        AsyncDropGuard<StripedBlockStore<Target>>,
+        Vec<AsyncDropGuard<Target>>,
+    ) {
+        let handles = targets
+            .iter()
+            .map(|(_, target)| SharedBlockStore::clone(target))
+            .collect();
+        (StripedBlockStore::new(targets), handles)
+    }
+
+    async fn drop_all(
+        mut store: AsyncDropGuard<StripedBlockStore<Target>>,
+        handles: Vec<AsyncDropGuard<Target>>,
+    ) {
+        store.async_drop().await.unwrap();
+        for mut handle in handles {
+            handle.async_drop().await.unwrap();
+        }
+    }
+
+    async fn store_blocks(store: &StripedBlockStore<Target>) {
+        for i in 0..NUM_BLOCKS {
+            store.store(&blockid(i), &data(100, i)).await.unwrap();
+        }
+    }
+
+    async fn assert_blocks_readable(store: &StripedBlockStore<Target>) {
+        for i in 0..NUM_BLOCKS {
+            assert_eq!(Some(data(100, i)), store.load(&blockid(i)).await.unwrap());
+        }
+        assert_eq!(NUM_BLOCKS, store.num_blocks().await.unwrap());
+    }
+
+    async fn num_blocks_per_target(handles: &[AsyncDropGuard<Target>]) -> Vec<u64> {
+        let mut result = Vec::new();
+        for handle in handles {
+            result.push(handle.num_blocks().await.unwrap());
+        }
+        result
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_blocks_are_spread_over_targets() {
+        let (store, handles) = striped(targets(&["a", "b", "c"]));
+        store_blocks(&store).await;
+
+        let per_target = num_blocks_per_target(&handles).await;
+        assert_eq!(NUM_BLOCKS, per_target.iter().sum::<u64>());
+        for num_blocks in per_target {
+            assert!(num_blocks > NUM_BLOCKS / 6, "{num_blocks}");
+        }
+
+        drop_all(store, handles).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_placement_only_depends_on_target_names() {
+        let (store1, handles1) = striped(targets(&["a", "b", "c"]));
+        let (store2, handles2) = striped(targets(&["c", "a", "b"]));
+        store_blocks(&store1).await;
+        store_blocks(&store2).await;
+
+        let all_blocks = |handle: &AsyncDropGuard<Target>| {
+            let handle = SharedBlockStore::clone(handle);
+            async move {
+                let mut handle = handle;
+                let mut blocks: Vec<BlockId> = handle
+                    .all_blocks()
+                    .await
+                    .unwrap()
+                    .try_collect()
+                    .await
+                    .unwrap();
+                handle.async_drop().await.unwrap();
+                blocks.sort();
+                blocks
+            }
+        };
+        assert_eq!(
+            all_blocks(&handles1[0]).await,
+            all_blocks(&handles2[1]).await
+        );
+        assert_eq!(
+            all_blocks(&handles1[1]).await,
+            all_blocks(&handles2[2]).await
+        );
+        assert_eq!(
+            all_blocks(&handles1[2]).await,
+            all_blocks(&handles2[0]).await
+        );
+
+        drop_all(store1, handles1).await;
+        drop_all(store2, handles2).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_num_blocks_and_free_bytes_are_aggregated() {
+        let (store, handles) = striped(targets(&["a", "b"]));
+        store_blocks(&store).await;
+
+        assert_eq!(NUM_BLOCKS, store.num_blocks().await.unwrap());
+        let free_bytes_per_target = handles[0].estimate_num_free_bytes().unwrap().as_u64();
+        let free_bytes = store.estimate_num_free_bytes().unwrap().as_u64();
+        // Both in-memory targets report the available system memory, which can change slightly between the calls
+        assert!(free_bytes > free_bytes_per_target * 3 / 2, "{free_bytes}");
+
+        drop_all(store, handles).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_add_target() {
+        let (store, mut handles) = striped(targets(&["a", "b"]));
+        store_blocks(&store).await;
+
+        let new_target = SharedBlockStore::new(InMemoryBlockStore::new());
+        handles.push(SharedBlockStore::clone(&new_target));
+        let summary = store.add_target("c".to_string(), new_target).await.unwrap();
+
+        let per_target = num_blocks_per_target(&handles).await;
+        assert_eq!(NUM_BLOCKS, per_target.iter().sum::<u64>());
+        assert_eq!(per_target[2], summary.num_moved);
+        assert!(summary.num_moved > 0);
+        assert_eq!(0, summary.num_discarded);
+        assert_eq!(vec!["a", "b", "c"], store.target_names().await);
+        assert_blocks_readable(&store).await;
+
+        drop_all(store, handles).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_add_target_with_existing_name() {
+        let (store, handles) = striped(targets(&["a", "b"]));
+
+        assert!(
+            store
+                .add_target(
+                    "a".to_string(),
+                    SharedBlockStore::new(InMemoryBlockStore::new())
+                )
+                .await
+                .is_err()
+        );
+        assert_eq!(vec!["a", "b"], store.target_names().await);
+
+        drop_all(store, handles).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_remove_target() {
+        let (store, handles) = striped(targets(&["a", "b", "c"]));
+        store_blocks(&store).await;
+        let num_blocks_in_b = handles[1].num_blocks().await.unwrap();
+
+        let summary = store.remove_target("b").await.unwrap();
+
+        assert_eq!(num_blocks_in_b, summary.num_moved);
+        let per_target = num_blocks_per_target(&handles).await;
+        assert_eq!(vec![per_target[0], 0, per_target[2]], per_target);
+        assert_eq!(vec!["a", "c"], store.target_names().await);
+        assert_blocks_readable(&store).await;
+
+        drop_all(store, handles).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_remove_last_target() {
+        let (store, handles) = striped(targets(&["a"]));
+        assert!(store.remove_target("a").await.is_err());
+        assert!(store.remove_target("b").await.is_err());
+        assert_eq!(vec!["a"], store.target_names().await);
+        drop_all(store, handles).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_rebalance_finishes_interrupted_rebalance() {
+        // Blocks were written with two targets and the store was reopened with a third one
+        let (store, handles) = striped(targets(&["a", "b"]));
+        store_blocks(&store).await;
+        let (old_store, handles) = (store, handles);
+        let mut new_targets = vec![
+            ("a".to_string(), SharedBlockStore::clone(&handles[0])),
+            ("b".to_string(), SharedBlockStore::clone(&handles[1])),
+        ];
+        new_targets.extend(targets(&["c"]));
+        let (store, new_handles) = striped(new_targets);
+        drop_all(old_store, handles).await;
+
+        let summary = store.rebalance().await.unwrap();
+
+        assert!(summary.num_moved > 0);
+        assert_eq!(
+            summary.num_moved,
+            new_handles[2].num_blocks().await.unwrap()
+        );
+        assert_blocks_readable(&store).await;
+
+        drop_all(store, new_handles).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_rebalance_discards_outdated_copies() {
+        let (store, handles) = striped(targets(&["a", "b"]));
+        store.store(&blockid(0), &data(100, 0)).await.unwrap();
+        let owner = usize::from(!handles[0].exists(&blockid(0)).await.unwrap());
+        // An outdated copy in the wrong target, e.g. from an interrupted rebalance
+        handles[1 - owner]
+            .store(&blockid(0), &data(100, 1))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            RebalanceSummary {
+                num_moved: 0,
+                num_discarded: 1,
+            },
+            store.rebalance().await.unwrap()
+        );
+        assert!(!handles[1 - owner].exists(&blockid(0)).await.unwrap());
+        assert_eq!(Some(data(100, 0)), store.load(&blockid(0)).await.unwrap());
+
+        drop_all(store, handles).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_store_is_usable_during_rebalance() {
+        let (store, mut handles) = striped(targets(&["a", "b"]));
+        store_blocks(&store).await;
+
+        let new_target = SharedBlockStore::new(InMemoryBlockStore::new());
+        handles.push(SharedBlockStore::clone(&new_target));
+        let rebalance = store.add_target("c".to_string(), new_target);
+        let use_store = async {
+            for i in 0..NUM_BLOCKS {
+                assert_eq!(Some(data(100, i)), store.load(&blockid(i)).await.unwrap());
+                store
+                    .store(&blockid(i), &data(100, i + 1000))
+                    .await
+                    .unwrap();
+            }
+        };
+        let (summary, ()) = tokio::join!(rebalance, use_store);
+        summary.unwrap();
+
+        for i in 0..NUM_BLOCKS {
+            assert_eq!(
+                Some(data(100, i + 1000)),
+                store.load(&blockid(i)).await.unwrap()
+            );
+        }
+        let per_target = num_blocks_per_target(&handles).await;
+        assert_eq!(NUM_BLOCKS, per_target.iter().sum::<u64>());
+
+        drop_all(store, handles).await;
+    }
+}
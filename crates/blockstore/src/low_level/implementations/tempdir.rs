@@ -14,7 +14,7 @@ use cryfs_utils::{
     data::Data,
 };
 
-use super::OnDiskBlockStore;
+use super::{FsyncPolicy, OnDiskBlockStore};
 
 /// Runs a [OnDiskBlockStore] in a temporary directory. Mostly useful because it binds the
 /// lifetime of the temporary directory to the lifetime of the block store and automatically
@@ -35,7 +35,8 @@ impl TempDirBlockStore {
         let path = tempdir.path().to_owned();
         AsyncDropGuard::new(Self {
             _tempdir: tempdir,
-            underlying_store: OnDiskBlockStore::new(path),
+            // Durability doesn't matter for a temporary directory
+            underlying_store: OnDiskBlockStore::new(path, FsyncPolicy::Never),
         })
     }
 }
@@ -104,7 +105,12 @@ impl AsyncDrop for TempDirBlockStore {
     }
 }
 
-impl LLBlockStore for TempDirBlockStore {}
+#[async_trait]
+impl LLBlockStore for TempDirBlockStore {
+    async fn sync(&self) -> Result<()> {
+        self.underlying_store.sync().await
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -170,9 +170,13 @@ impl<B: Sync + Send + Debug + AsyncDrop<Error = anyhow::Error>> AsyncDrop
     }
 }
 
+#[async_trait]
 impl<B: LLBlockStore + OptimizedBlockStoreWriter + Sync + Send + Debug> LLBlockStore
     for TrackingBlockStore<B>
 {
+    async fn sync(&self) -> Result<()> {
+        self.underlying_store.sync().await
+    }
 }
 
 #[cfg(test)]
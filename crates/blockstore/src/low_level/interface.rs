@@ -74,6 +74,7 @@ impl<B: OptimizedBlockStoreWriter + Sync> BlockStoreWriter for B {
     }
 }
 
+#[async_trait]
 pub trait LLBlockStore:
     BlockStoreReader
     + BlockStoreWriter
@@ -82,6 +83,10 @@ pub trait LLBlockStore:
     + Debug
     + Any
 {
+    /// Write barrier. After this returns, all writes and removals that completed before it was called
+    /// are guaranteed to survive a crash or power loss, as far as the underlying storage allows.
+    /// Block stores wrapping another block store must forward this call.
+    async fn sync(&self) -> Result<()>;
 }
 
 /// BlockData instances wrap a [Data] instance and guarantee the upholding of an
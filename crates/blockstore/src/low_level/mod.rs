@@ -10,8 +10,16 @@ pub use implementations::{
     ActionCounts, MockBlockStore, SharedBlockStore, TempDirBlockStore, TrackingBlockStore,
 };
 pub use implementations::{
-    AllowIntegrityViolations, ClientId, CompressingBlockStore, DynBlockStore, EncryptedBlockStore,
-    InMemoryBlockStore, IntegrityBlockStore, IntegrityBlockStoreInitError, IntegrityConfig,
-    IntegrityViolationError, MissingBlockIsIntegrityViolation, OnDiskBlockStore,
-    ReadOnlyBlockStore,
+    AllowIntegrityViolations, ClientId, CompressingBlockStore, CompressionAlgorithm, DynBlockStore,
+    EncryptedBlockStore, FsyncPolicy, InMemoryBlockStore, IntegrityBlockStore,
+    IntegrityBlockStoreInitError, IntegrityConfig, IntegrityViolationError, MirroringBlockStore,
+    MissingBlockIsIntegrityViolation, OnDiskBlockStore, PackedBlockStore, PackedBlockStoreConfig,
+    ReadOnlyBlockStore, RebalanceSummary, ResyncSummary, StripedBlockStore,
+    UnknownCompressionAlgorithmError,
 };
+#[cfg(feature = "remote")]
+pub use implementations::{
+    BlockServerListener, BlockServerSecret, RemoteAddress, RemoteBlockStore, serve_blocks,
+};
+#[cfg(feature = "s3")]
+pub use implementations::{S3AddressingStyle, S3BlockStore, S3Config, S3Credentials};
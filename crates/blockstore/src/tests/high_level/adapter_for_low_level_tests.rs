@@ -106,9 +106,13 @@ impl<B: BlockStore + AsyncDrop<Error = anyhow::Error> + Send + Sync + Debug + 's
     }
 }
 
+#[async_trait]
 impl<B: BlockStore + AsyncDrop<Error = anyhow::Error> + Send + Sync + Debug + 'static> LLBlockStore
     for BlockStoreToLLBlockStoreAdapter<B>
 {
+    async fn sync(&self) -> Result<()> {
+        self.0.sync().await
+    }
 }
 
 /// [FixtureAdapterForLLTests] takes a [HLFixture] for a [BlockStore] and makes it into
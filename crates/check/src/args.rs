@@ -1,4 +1,4 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use cryfs_cli_utils::parse_path;
 use std::path::PathBuf;
 
@@ -6,4 +6,35 @@ use std::path::PathBuf;
 pub struct CryfsRecoverArgs {
     #[arg(value_parser=parse_path)]
     pub vaultdir: PathBuf,
+
+    /// After checking the file system, print a plan of fixes for the errors that can be safely fixed.
+    /// This is a dry run and doesn't modify the file system unless `--apply` is given.
+    #[arg(long)]
+    pub repair: bool,
+
+    /// Apply the fixes planned by `--repair` to the file system.
+    /// Refuses to run while the file system is mounted. Consider backing it up first.
+    #[arg(long, requires = "repair")]
+    pub apply: bool,
+
+    /// Format of the check results printed to stdout.
+    #[arg(long, value_enum, default_value_t, conflicts_with = "repair")]
+    pub output: OutputFormat,
+
+    /// Ask for the password only once. If stdin isn't a terminal, read the password from stdin
+    /// instead of asking for it. This is meant for scripts, e.g. together with `--output json`.
+    /// Setting the environment variable CRYFS_FRONTEND=noninteractive has the same effect.
+    #[arg(long)]
+    pub noninteractive: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human readable error descriptions
+    #[default]
+    Human,
+
+    /// A single JSON document with all errors, summary counts and the exit status.
+    /// Everything else, e.g. progress bars, is printed to stderr.
+    Json,
 }
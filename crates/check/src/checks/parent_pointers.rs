@@ -14,7 +14,7 @@ use crate::{
     assertion::Assertion,
     error::{
         BlobReferencedMultipleTimesError, BlobUnreadableError, CorruptedError, NodeMissingError,
-        WrongParentPointerError,
+        WrongLinkCountError, WrongParentPointerError,
     },
     node_info::{
         BlobInfoAsSeenByLookingAtBlob, BlobReference, MaybeBlobInfoAsSeenByLookingAtBlob,
@@ -29,6 +29,8 @@ enum SeenBlobInfo {
     Readable {
         blob_type: BlobType,
         parent_pointer: BlobId,
+        /// Only set for blobs that were hard linked at some point
+        num_links: Option<u32>,
     },
     Unreadable {
         referenced_as: BlobReference,
@@ -42,6 +44,7 @@ impl SeenBlobInfo {
             Self::Readable {
                 blob_type,
                 parent_pointer,
+                num_links: _,
             } => BlobInfoAsSeenByLookingAtBlob::Readable {
                 blob_type: *blob_type,
                 parent_pointer: *parent_pointer,
@@ -83,20 +86,39 @@ impl FilesystemCheck for CheckParentPointers {
                 let seen_blob_info = SeenBlobInfo::Readable {
                     parent_pointer: blob.parent(),
                     blob_type: blob.blob_type(),
+                    num_links: blob.is_hard_linked().then(|| blob.num_links()),
                 };
                 self.reference_checker
                     .mark_as_seen(blob.blob_id(), seen_blob_info);
 
                 match blob {
                     FsBlob::File(_) | FsBlob::Symlink(_) => {
-                        // Files and Symlinks don't have children
+                        // Files and Symlinks don't have children, but hard linked ones reference their link metadata blob
+                        if let Some(link_metadata_blob_id) = blob.link_metadata_blob_id() {
+                            self.reference_checker.mark_as_referenced(
+                                link_metadata_blob_id,
+                                BlobReference {
+                                    blob_type: BlobType::File,
+                                    parent_id: blob.blob_id(),
+                                    path: referenced_as.path.clone(),
+                                },
+                            );
+                        }
                     }
                     FsBlob::Directory(blob) => {
-                        for entry in blob.entries() {
+                        for entry in blob
+                            .loaded_entries()
+                            .expect("The check runner loads all entries of directory blobs")
+                        {
+                            let Some(blob_type) = entry_type_to_blob_type(entry.entry_type())
+                            else {
+                                // This entry doesn't reference a blob
+                                continue;
+                            };
                             self.reference_checker.mark_as_referenced(
                                 *entry.blob_id(),
                                 BlobReference {
-                                    blob_type: entry_type_to_blob_type(entry.entry_type()),
+                                    blob_type,
                                     parent_id: blob.blob_id(),
                                     path: referenced_as.path.join(entry.name()),
                                 },
@@ -170,6 +192,26 @@ impl FilesystemCheck for CheckParentPointers {
         let mut errors = CheckResult::new();
 
         for (blob_id, seen_blob_info, referenced_as) in self.reference_checker.finalize() {
+            if let Some(SeenBlobInfo::Readable {
+                blob_type,
+                parent_pointer,
+                num_links: Some(num_links),
+            }) = seen_blob_info
+            {
+                // This blob is hard linked. It is expected to be referenced once per link, and since the parent pointer
+                // can only point to one of the referencing directories, it isn't kept up to date for such blobs.
+                if referenced_as.len() != num_links as usize {
+                    errors.add_error(WrongLinkCountError {
+                        blob_id,
+                        blob_type,
+                        parent_pointer,
+                        num_links,
+                        referenced_as: referenced_as.into_iter().collect(),
+                    });
+                }
+                continue;
+            }
+
             if referenced_as.len() > 1 {
                 let blob_info = seen_blob_info
                     .as_ref()
@@ -191,6 +233,7 @@ impl FilesystemCheck for CheckParentPointers {
                 Some(SeenBlobInfo::Readable {
                     parent_pointer,
                     blob_type,
+                    num_links: _,
                 }) => {
                     let mut matching_parents = referenced_as
                         .iter()
@@ -258,10 +301,14 @@ impl FilesystemCheck for CheckParentPointers {
 }
 
 // TODO This exists in multiple places. Deduplicate.
-fn entry_type_to_blob_type(entry_type: EntryType) -> BlobType {
+/// Returns `None` for fifos, sockets and devices because they aren't backed by a blob
+fn entry_type_to_blob_type(entry_type: EntryType) -> Option<BlobType> {
     match entry_type {
-        EntryType::File => BlobType::File,
-        EntryType::Dir => BlobType::Dir,
-        EntryType::Symlink => BlobType::Symlink,
+        EntryType::File => Some(BlobType::File),
+        EntryType::Dir => Some(BlobType::Dir),
+        EntryType::Symlink => Some(BlobType::Symlink),
+        EntryType::Fifo | EntryType::Socket | EntryType::CharDevice | EntryType::BlockDevice => {
+            None
+        }
     }
 }
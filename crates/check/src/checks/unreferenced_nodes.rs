@@ -1,7 +1,8 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::num::NonZeroU8;
 
-use cryfs_blobstore::{BlobId, BlobStoreOnBlocks, DataNode};
+use cryfs_blobstore::{BlobId, BlobStoreOnBlocks, DataNode, HOLE_BLOCK_ID};
 use cryfs_blockstore::{BlockId, BlockStore};
 use cryfs_fsblobstore::fsblobstore::{BlobType, EntryType, FsBlob};
 use cryfs_utils::async_drop::AsyncDrop;
@@ -34,12 +35,16 @@ struct SeenInfo {
 /// - each existing node is referenced
 /// - each referenced node exists (i.e. no dangling node exists and no node is missing)
 /// - each node is readable
-/// - no node is referenced multiple times
+/// - no node is referenced multiple times, unless it is shared between blobs and has a matching reference count
 ///
 /// Algorithm: While passing through each node, we mark the current node as **seen** and all referenced nodes as **referenced**.
 /// We make sure that each node id is both **seen** and **referenced** and that there are no nodes that are only one of the two.
 struct UnreferencedNodesReferenceChecker {
     reference_checker: ReferenceChecker<BlockId, SeenInfo, ReferencedAs>,
+    // Root nodes of hard linked blobs. Those are expected to be referenced once per link.
+    hard_linked_root_nodes: HashSet<BlockId>,
+    // Non-root nodes shared between blobs, see [DataNode::num_extra_references]. Those are expected to be referenced once per reference count.
+    shared_nodes: HashMap<BlockId, u8>,
     errors: CheckResult,
 }
 
@@ -47,6 +52,8 @@ impl UnreferencedNodesReferenceChecker {
     pub fn new() -> Self {
         Self {
             reference_checker: ReferenceChecker::new(),
+            hard_linked_root_nodes: HashSet::new(),
+            shared_nodes: HashMap::new(),
             errors: CheckResult::new(),
         }
     }
@@ -91,6 +98,10 @@ impl UnreferencedNodesReferenceChecker {
         };
         self.reference_checker
             .mark_as_seen(*node.block_id(), SeenInfo { node_info });
+        if node.num_extra_references() > 0 {
+            self.shared_nodes
+                .insert(*node.block_id(), node.num_extra_references());
+        }
 
         // Mark all referenced nodes within the same blob as referenced
         match node {
@@ -98,7 +109,11 @@ impl UnreferencedNodesReferenceChecker {
                 // Leaf nodes don't have children
             }
             DataNode::Inner(node) => {
-                for child in node.children() {
+                // Holes in sparse blobs don't have nodes
+                for child in node
+                    .children()
+                    .filter(|child_id| *child_id != HOLE_BLOCK_ID)
+                {
                     let parent_id = *node.block_id();
                     let depth = node.depth().get() - 1;
                     let referenced_as = if depth == 0 {
@@ -181,16 +196,44 @@ impl UnreferencedNodesReferenceChecker {
         >,
         path: &AbsolutePath,
     ) -> Result<(), CheckError> {
+        if blob.is_hard_linked() {
+            self.hard_linked_root_nodes
+                .insert(*blob.blob_id().to_root_block_id());
+        }
         match blob {
             FsBlob::File(_) | FsBlob::Symlink(_) => {
-                // Files and symlinks don't reference other blobs
+                // Files and symlinks don't reference other blobs, except for the link metadata blob of hard linked ones
+                if let Some(link_metadata_blob_id) = blob.link_metadata_blob_id() {
+                    self.reference_checker.mark_as_referenced(
+                        *link_metadata_blob_id.to_root_block_id(),
+                        ReferencedAs {
+                            referenced_as: NodeAndBlobReference::RootNode {
+                                belongs_to_blob: BlobReferenceWithId {
+                                    blob_id: link_metadata_blob_id,
+                                    referenced_as: BlobReference {
+                                        blob_type: BlobType::File,
+                                        parent_id: blob.blob_id(),
+                                        path: path.to_owned(),
+                                    },
+                                },
+                            },
+                        },
+                    );
+                }
             }
             FsBlob::Directory(blob) => {
-                for child in blob.entries() {
+                for child in blob
+                    .loaded_entries()
+                    .expect("The check runner loads all entries of directory blobs")
+                {
+                    let Some(blob_type) = entry_type_to_blob_type(child.entry_type()) else {
+                        // This entry doesn't reference a blob
+                        continue;
+                    };
                     let child_blob_info = BlobReferenceWithId {
                         blob_id: *child.blob_id(),
                         referenced_as: BlobReference {
-                            blob_type: entry_type_to_blob_type(child.entry_type()),
+                            blob_type,
                             parent_id: blob.blob_id(),
                             path: path.join(child.name()),
                         },
@@ -236,7 +279,29 @@ impl UnreferencedNodesReferenceChecker {
                         .collect(),
                 ));
             }
-            if referenced_as.len() > 1 {
+            let is_only_referenced_by_hard_links = self.hard_linked_root_nodes.contains(&node_id)
+                && referenced_as.iter().all(|referenced_as| {
+                    matches!(
+                        referenced_as.referenced_as,
+                        NodeAndBlobReference::RootNode { .. }
+                    )
+                });
+            let is_shared_as_expected =
+                self.shared_nodes
+                    .get(&node_id)
+                    .is_some_and(|num_extra_references| {
+                        referenced_as.len() == usize::from(*num_extra_references) + 1
+                            && referenced_as.iter().all(|referenced_as| {
+                                !matches!(
+                                    referenced_as.referenced_as,
+                                    NodeAndBlobReference::RootNode { .. }
+                                )
+                            })
+                    });
+            if referenced_as.len() > 1
+                && !is_only_referenced_by_hard_links
+                && !is_shared_as_expected
+            {
                 let node_info = seen
                     .as_ref()
                     .map(|seen| seen.node_info.clone().into())
@@ -404,10 +469,14 @@ impl FilesystemCheck for CheckUnreferencedNodes {
 }
 
 // TODO This exists in multiple places. Deduplicate.
-fn entry_type_to_blob_type(entry_type: EntryType) -> BlobType {
+/// Returns `None` for fifos, sockets and devices because they aren't backed by a blob
+fn entry_type_to_blob_type(entry_type: EntryType) -> Option<BlobType> {
     match entry_type {
-        EntryType::File => BlobType::File,
-        EntryType::Dir => BlobType::Dir,
-        EntryType::Symlink => BlobType::Symlink,
+        EntryType::File => Some(BlobType::File),
+        EntryType::Dir => Some(BlobType::Dir),
+        EntryType::Symlink => Some(BlobType::Symlink),
+        EntryType::Fifo | EntryType::Socket | EntryType::CharDevice | EntryType::BlockDevice => {
+            None
+        }
     }
 }
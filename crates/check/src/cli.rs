@@ -1,34 +1,45 @@
-use anyhow::Result;
+use anyhow::{Result, anyhow};
+use std::os::unix::fs::MetadataExt;
 use std::{path::Path, sync::Arc};
 
 use clap_logflag::{LogDestination, LogDestinationConfig, LoggingConfig};
 use cryfs_blockstore::{
-    AllowIntegrityViolations, IntegrityConfig, LLBlockStore, MissingBlockIsIntegrityViolation,
-    OnDiskBlockStore, OptimizedBlockStoreWriter, ReadOnlyBlockStore,
+    AllowIntegrityViolations, DynBlockStore, FsyncPolicy, IntegrityConfig, LLBlockStore,
+    MissingBlockIsIntegrityViolation, OptimizedBlockStoreWriter, ReadOnlyBlockStore,
 };
 use cryfs_cli_utils::{
-    Application, CliError, CliErrorKind, CliResultExt, Environment,
-    password_provider::InteractivePasswordProvider, print_config, setup_blockstore_stack,
+    Application, BackendConfig, CliError, CliErrorKind, CliResultExt, CliResultExtFn, Environment,
+    FilesystemLock, is_mounted,
+    password_provider::{InteractivePasswordProvider, NoninteractivePasswordProvider},
+    print_config, setup_blockstore_stack,
 };
 use cryfs_config::{
     CRYFS_VERSION,
     config::{CommandLineFlags, ConfigLoadError, ConfigLoadResult, PasswordProvider},
     localstate::LocalStateDir,
 };
+use cryfs_fsblobstore::{Gid, Uid};
 use cryfs_utils::{
     async_drop::AsyncDropGuard,
     progress::{ConsoleProgressBarManager, ProgressBarManager},
 };
 use cryfs_version::VersionInfo;
 
-use super::{console::RecoverConsole, error::CorruptedError, runner::RecoverRunner};
-use crate::args::CryfsRecoverArgs;
+use super::{
+    console::RecoverConsole,
+    error::CorruptedError,
+    repair::{ApplyRepairRunner, PlanRepairRunner, RepairPlan},
+    runner::RecoverRunner,
+};
+use crate::args::{CryfsRecoverArgs, OutputFormat};
+use crate::report::json_report;
 
 // TODO Make sure we don't write to local state or integrity data either. Read-only blockstore isn't enough.
 
 pub struct RecoverCli {
     args: CryfsRecoverArgs,
     local_state_dir: LocalStateDir,
+    is_noninteractive: bool,
 }
 
 impl Application for RecoverCli {
@@ -39,9 +50,11 @@ impl Application for RecoverCli {
     fn new(args: CryfsRecoverArgs, env: Environment) -> Result<Self, CliError> {
         // TODO Make sure we have tests for the local_state_dir location
         let local_state_dir = LocalStateDir::new(env.local_state_dir);
+        let is_noninteractive = args.noninteractive || env.is_noninteractive;
         Ok(Self {
             args,
             local_state_dir,
+            is_noninteractive,
         })
     }
 
@@ -65,37 +78,158 @@ impl Application for RecoverCli {
 
 impl RecoverCli {
     async fn async_main(self) -> Result<(), CliError> {
-        println!(
-            "Checking filesystem at {}",
-            self.args
-                .vaultdir
-                .to_str()
-                .expect("Invalid utf-8 in filesystem path")
-        );
+        let vaultdir = self
+            .args
+            .vaultdir
+            .to_str()
+            .expect("Invalid utf-8 in filesystem path");
+        match self.args.output {
+            OutputFormat::Human => println!("Checking filesystem at {vaultdir}"),
+            // Keep stdout clean for the JSON report
+            OutputFormat::Json => eprintln!("Checking filesystem at {vaultdir}"),
+        }
 
         let config_file_path = self.args.vaultdir.join("cryfs.config");
-        let blockstore = OnDiskBlockStore::new(self.args.vaultdir);
+        let backend = BackendConfig::load(&self.args.vaultdir)
+            .map_cli_error(CliErrorKind::InvalidFilesystem)?;
+        // Checking only reads from the block store, so there is nothing to fsync
+        let blockstore = self.open_blockstore(&backend, FsyncPolicy::Never).await?;
 
-        // TODO Allow NonInteractivePasswordProvider like cryfs-cli does?
-        let password_provider = InteractivePasswordProvider;
+        let config = load_config(
+            &config_file_path,
+            &self.local_state_dir,
+            self.password_provider(),
+            ConsoleProgressBarManager,
+        )
+        .map_cli_error(|_| CliErrorKind::UnspecifiedError)?;
+        if self.args.output == OutputFormat::Human {
+            print_config(&config);
+        }
 
-        let errors = check_filesystem(
+        let errors = check_loaded_filesystem(
             blockstore,
-            &config_file_path,
+            &config,
             &self.local_state_dir,
-            &password_provider,
             ConsoleProgressBarManager,
         )
         .await
         // TODO Should we add specific exit codes for the check tool?
         .map_cli_error(CliErrorKind::UnspecifiedError)?;
 
-        for error in &errors {
-            println!("{error}\n");
+        let all_errors_fixed = match self.args.output {
+            OutputFormat::Human => {
+                for error in &errors {
+                    println!("{error}\n");
+                }
+                println!("Found {} errors", errors.len());
+
+                if self.args.repair {
+                    self.repair(&backend, &config, &errors).await?
+                } else {
+                    false
+                }
+            }
+            OutputFormat::Json => {
+                println!("{}", json_report(&errors, exit_status(&errors)));
+                false
+            }
+        };
+
+        if errors.is_empty() || all_errors_fixed {
+            Ok(())
+        } else {
+            Err(CliError {
+                kind: exit_status(&errors),
+                error: Arc::new(anyhow!("Found {} errors in the file system", errors.len())),
+            })
+        }
+    }
+
+    fn password_provider(&self) -> &'static dyn PasswordProvider {
+        if self.is_noninteractive {
+            &NoninteractivePasswordProvider
+        } else {
+            &InteractivePasswordProvider
+        }
+    }
+
+    /// Returns true if the repair was applied and fixed all errors
+    async fn repair(
+        &self,
+        backend: &BackendConfig,
+        config: &ConfigLoadResult,
+        errors: &[CorruptedError],
+    ) -> Result<bool, CliError> {
+        let blockstore = self.open_blockstore(backend, FsyncPolicy::Never).await?;
+        let plan = plan_repair(blockstore, config, &self.local_state_dir, errors)
+            .await
+            .map_cli_error(CliErrorKind::UnspecifiedError)?;
+        print!("\n{plan}");
+
+        if !self.args.apply {
+            if !plan.is_empty() {
+                println!("This was a dry run. Run with --apply to apply the fixes.");
+            }
+            return Ok(false);
+        }
+        if plan.is_empty() {
+            return Ok(false);
+        }
+
+        // Repairing a file system that is in use would race with the changes the other process makes
+        if is_mounted(&self.args.vaultdir).map_cli_error(CliErrorKind::UnspecifiedError)? {
+            return Err(anyhow!(
+                "The file system at {} is mounted. Please unmount it before repairing it.",
+                self.args.vaultdir.display()
+            ))
+            .map_cli_error(CliErrorKind::FilesystemInUse);
         }
-        println!("Found {} errors", errors.len());
+        let _lock = FilesystemLock::try_acquire(
+            &self.local_state_dir,
+            &config.config.config().filesystem_id,
+        )?;
+
+        // Entries in lost+found get the owner of the vault directory
+        let metadata = std::fs::metadata(&self.args.vaultdir)
+            .map_cli_error(|_| CliErrorKind::InaccessibleVaultDir)?;
+        let blockstore = self
+            .open_blockstore(backend, FsyncPolicy::default())
+            .await?;
+        apply_repair(
+            blockstore,
+            config,
+            &self.local_state_dir,
+            &plan,
+            Uid::from(metadata.uid()),
+            Gid::from(metadata.gid()),
+            ConsoleProgressBarManager,
+        )
+        .await
+        .map_cli_error(CliErrorKind::UnspecifiedError)?;
+        println!("Applied {} fixes", plan.actions.len());
 
-        Ok(())
+        Ok(plan.unfixable.is_empty())
+    }
+
+    /// Opens the blocks of the vault wherever its [BackendConfig] says they are
+    async fn open_blockstore(
+        &self,
+        backend: &BackendConfig,
+        fsync_policy: FsyncPolicy,
+    ) -> Result<AsyncDropGuard<DynBlockStore>, CliError> {
+        Ok(backend
+            .open(&self.args.vaultdir, fsync_policy)
+            .await
+            .map_cli_error(CliErrorKind::InaccessibleVaultDir)?
+            .into_dyn())
+    }
+}
+
+fn exit_status(errors: &[CorruptedError]) -> CliErrorKind {
+    if errors.is_empty() {
+        CliErrorKind::Success
+    } else {
+        CliErrorKind::InvalidFilesystem
     }
 }
 
@@ -103,11 +237,9 @@ pub async fn check_filesystem(
     blockstore: AsyncDropGuard<impl LLBlockStore + OptimizedBlockStoreWriter + Sync + Send>,
     config_file_path: &Path,
     local_state_dir: &LocalStateDir,
-    password_provider: &impl PasswordProvider,
+    password_provider: &(impl PasswordProvider + ?Sized),
     progress_bar_manager: impl ProgressBarManager,
 ) -> Result<Vec<CorruptedError>, Arc<anyhow::Error>> {
-    let blockstore = ReadOnlyBlockStore::new(blockstore);
-
     let config = load_config(
         config_file_path,
         local_state_dir,
@@ -117,6 +249,18 @@ pub async fn check_filesystem(
     .map_err(|err| Arc::new(err.into()))?;
     print_config(&config);
 
+    check_loaded_filesystem(blockstore, &config, local_state_dir, progress_bar_manager).await
+}
+
+/// Like [check_filesystem], but for a config that was already loaded
+pub async fn check_loaded_filesystem(
+    blockstore: AsyncDropGuard<impl LLBlockStore + OptimizedBlockStoreWriter + Sync + Send>,
+    config: &ConfigLoadResult,
+    local_state_dir: &LocalStateDir,
+    progress_bar_manager: impl ProgressBarManager,
+) -> Result<Vec<CorruptedError>, Arc<anyhow::Error>> {
+    let blockstore = ReadOnlyBlockStore::new(blockstore);
+
     // TODO It currently seems to spend some seconds before getting from here in to `RecoveryRunner`. Probably to load local state or something like that. Let's add a spinner.
     //      Or parallelize that with scrypt.
 
@@ -125,18 +269,9 @@ pub async fn check_filesystem(
         &config.config.config(),
         config.my_client_id,
         local_state_dir,
-        // TODO Setup IntegrityConfig correctly
-        IntegrityConfig {
-                allow_integrity_violations: AllowIntegrityViolations::AllowViolations,
-                missing_block_is_integrity_violation:
-                    // TODO Since we say AllowViolations above, should this be IsAViolation so we log it?
-                    MissingBlockIsIntegrityViolation::IsNotAViolation,
-                on_integrity_violation: Box::new(|err| {
-                    // TODO What to do here? Maybe we should at least log it
-                }),
-            },
+        integrity_config(),
         RecoverRunner {
-            config: &config,
+            config,
             progress_bar_manager,
         },
     )
@@ -145,10 +280,78 @@ pub async fn check_filesystem(
     .map_err(Arc::new)
 }
 
+/// Decide which of the `errors` found by [check_filesystem] can be fixed and how.
+/// This doesn't modify the file system.
+pub async fn plan_repair(
+    blockstore: AsyncDropGuard<impl LLBlockStore + OptimizedBlockStoreWriter + Sync + Send>,
+    config: &ConfigLoadResult,
+    local_state_dir: &LocalStateDir,
+    errors: &[CorruptedError],
+) -> Result<RepairPlan, Arc<anyhow::Error>> {
+    let blockstore = ReadOnlyBlockStore::new(blockstore);
+
+    setup_blockstore_stack(
+        blockstore,
+        config.config.config(),
+        config.my_client_id,
+        local_state_dir,
+        integrity_config(),
+        PlanRepairRunner { config, errors },
+    )
+    .await
+    .map_err(|err: CliError| err.error)?
+    .map_err(Arc::new)
+}
+
+/// Apply a [RepairPlan] created by [plan_repair] to the file system.
+/// `uid` and `gid` are the owner of any entries added to the lost+found directory.
+pub async fn apply_repair(
+    blockstore: AsyncDropGuard<impl LLBlockStore + OptimizedBlockStoreWriter + Sync + Send>,
+    config: &ConfigLoadResult,
+    local_state_dir: &LocalStateDir,
+    plan: &RepairPlan,
+    uid: Uid,
+    gid: Gid,
+    progress_bar_manager: impl ProgressBarManager,
+) -> Result<(), Arc<anyhow::Error>> {
+    setup_blockstore_stack(
+        blockstore,
+        config.config.config(),
+        config.my_client_id,
+        local_state_dir,
+        integrity_config(),
+        ApplyRepairRunner {
+            config,
+            plan,
+            uid,
+            gid,
+            progress_bar_manager,
+        },
+    )
+    .await
+    .map_err(|err: CliError| err.error)?
+    .map_err(Arc::new)
+}
+
+fn integrity_config() -> IntegrityConfig {
+    // TODO Setup IntegrityConfig correctly
+    IntegrityConfig {
+        allow_integrity_violations: AllowIntegrityViolations::AllowViolations,
+        missing_block_is_integrity_violation:
+            // TODO Since we say AllowViolations above, should this be IsAViolation so we log it?
+            MissingBlockIsIntegrityViolation::IsNotAViolation,
+        on_integrity_violation: Box::new(|err| {
+            // TODO What to do here? Maybe we should at least log it
+        }),
+        // We don't cache anything that could get stale
+        on_block_changed_by_other_client: Box::new(|_| {}),
+    }
+}
+
 fn load_config(
     config_file_path: &Path,
     local_state_dir: &LocalStateDir,
-    password_provider: &impl PasswordProvider,
+    password_provider: &(impl PasswordProvider + ?Sized),
     progress_bars: impl ProgressBarManager,
 ) -> Result<ConfigLoadResult, ConfigLoadError> {
     // TODO Allow changing config file using args as C++ did
@@ -160,6 +363,9 @@ fn load_config(
             missing_block_is_integrity_violation: Some(false),
             expected_cipher: None,
             blocksize: None,
+            kdf: None,
+            compression: None,
+            extended_format: false,
         },
         local_state_dir,
         progress_bars,
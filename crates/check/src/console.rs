@@ -3,7 +3,7 @@ use byte_unit::Byte;
 use std::path::Path;
 
 use cryfs_config::config::Console;
-use cryfs_crypto::kdf::scrypt::ScryptSettings;
+use cryfs_crypto::kdf::{argon2id::Argon2idSettings, scrypt::ScryptSettings};
 use cryfs_version::{Version, VersionInfo};
 
 // TODO What to do in these cases?
@@ -41,6 +41,10 @@ impl Console for RecoverConsole {
         todo!()
     }
 
+    fn ask_argon2id_settings_for_new_filesystem(&self) -> Result<Argon2idSettings> {
+        todo!()
+    }
+
     fn ask_cipher_for_new_filesystem(&self) -> Result<String> {
         todo!()
     }
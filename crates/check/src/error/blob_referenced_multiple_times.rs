@@ -33,7 +33,7 @@ impl BlobReferencedMultipleTimesError {
     }
 }
 
-const ERROR_TITLE: ErrorTitle = ErrorTitle {
+pub(super) const ERROR_TITLE: ErrorTitle = ErrorTitle {
     error_type: "BlobReferencedMultipleTimes",
     error_message: "Blob is referenced multiple times.",
 };
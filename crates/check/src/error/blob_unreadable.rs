@@ -24,7 +24,7 @@ impl BlobUnreadableError {
     }
 }
 
-const ERROR_TITLE: ErrorTitle = ErrorTitle {
+pub(super) const ERROR_TITLE: ErrorTitle = ErrorTitle {
     error_type: "BlobUnreadable",
     error_message: "Blob is unreadable and likely corrupted.",
 };
@@ -0,0 +1,360 @@
+//! Machine readable representation of [CorruptedError]s, used by `cryfs-check --output json`.
+//! This mirrors the information shown by the human readable [std::fmt::Display] implementations.
+
+use serde_json::{Value, json};
+use std::collections::BTreeSet;
+
+use cryfs_fsblobstore::fsblobstore::BlobType;
+
+use super::display::ErrorTitle;
+use super::{
+    BlobReferencedMultipleTimesError, BlobUnreadableError, CorruptedError, NodeMissingError,
+    NodeReferencedMultipleTimesError, NodeUnreadableError, NodeUnreferencedError,
+    WrongLinkCountError, WrongParentPointerError,
+};
+use crate::node_info::{
+    BlobReference, MaybeBlobInfoAsSeenByLookingAtBlob, MaybeBlobReferenceWithId,
+    MaybeNodeInfoAsSeenByLookingAtNode, NodeAndBlobReference, NodeInfoAsSeenByLookingAtNode,
+};
+
+impl CorruptedError {
+    fn error_title(&self) -> &'static ErrorTitle {
+        match self {
+            Self::NodeUnreadable(_) => &super::node_unreadable::ERROR_TITLE,
+            Self::NodeMissing(_) => &super::node_missing::ERROR_TITLE,
+            Self::NodeUnreferenced(_) => &super::node_unreferenced::ERROR_TITLE,
+            Self::NodeReferencedMultipleTimes(_) => {
+                &super::node_referenced_multiple_times::ERROR_TITLE
+            }
+            Self::BlobReferencedMultipleTimes(_) => {
+                &super::blob_referenced_multiple_times::ERROR_TITLE
+            }
+            Self::BlobUnreadable(_) => &super::blob_unreadable::ERROR_TITLE,
+            Self::WrongParentPointer(_) => &super::wrong_parent_pointer::ERROR_TITLE,
+            Self::WrongLinkCount(_) => &super::wrong_link_count::ERROR_TITLE,
+        }
+    }
+
+    /// The name of the error type, e.g. `NodeMissing`. This is the same name shown in the human readable output.
+    pub fn kind(&self) -> &'static str {
+        self.error_title().error_type
+    }
+
+    /// A JSON object with the `kind` and `message` of the error and all the block ids, blob ids and
+    /// paths that the human readable output shows.
+    pub fn to_json(&self) -> Value {
+        let mut details = match self {
+            Self::NodeUnreadable(NodeUnreadableError {
+                node_id,
+                referenced_as,
+            }) => json!({
+                "node_id": node_id.to_hex(),
+                "node_referenced_as": node_references_to_json(referenced_as),
+            }),
+            Self::NodeMissing(NodeMissingError {
+                node_id,
+                referenced_as,
+            }) => json!({
+                "node_id": node_id.to_hex(),
+                "node_referenced_as": node_references_to_json(referenced_as),
+            }),
+            Self::NodeUnreferenced(NodeUnreferencedError { node_id, node_info }) => json!({
+                "node_id": node_id.to_hex(),
+                "node_info": node_info_to_json(*node_info),
+            }),
+            Self::NodeReferencedMultipleTimes(NodeReferencedMultipleTimesError {
+                node_id,
+                node_info,
+                referenced_as,
+            }) => json!({
+                "node_id": node_id.to_hex(),
+                "node_info": maybe_node_info_to_json(*node_info),
+                "node_referenced_as": node_references_to_json(referenced_as),
+            }),
+            Self::BlobReferencedMultipleTimes(BlobReferencedMultipleTimesError {
+                blob_id,
+                blob_info,
+                referenced_as,
+            }) => json!({
+                "blob_id": blob_id.to_hex(),
+                "blob_info": maybe_blob_info_to_json(*blob_info),
+                "blob_referenced_as": blob_references_to_json(referenced_as),
+            }),
+            Self::BlobUnreadable(BlobUnreadableError {
+                blob_id,
+                referenced_as,
+            }) => json!({
+                "blob_id": blob_id.to_hex(),
+                "blob_referenced_as": blob_references_to_json(referenced_as),
+            }),
+            Self::WrongParentPointer(WrongParentPointerError {
+                blob_id,
+                blob_type,
+                parent_pointer,
+                referenced_as,
+            }) => json!({
+                "blob_id": blob_id.to_hex(),
+                "blob_type": blob_type_to_json(*blob_type),
+                "parent_pointer": parent_pointer.to_hex(),
+                "blob_referenced_as": blob_references_to_json(referenced_as),
+            }),
+            Self::WrongLinkCount(WrongLinkCountError {
+                blob_id,
+                blob_type,
+                parent_pointer,
+                num_links,
+                referenced_as,
+            }) => json!({
+                "blob_id": blob_id.to_hex(),
+                "blob_type": blob_type_to_json(*blob_type),
+                "parent_pointer": parent_pointer.to_hex(),
+                "num_links": num_links,
+                "blob_referenced_as": blob_references_to_json(referenced_as),
+            }),
+        };
+        let title = self.error_title();
+        let fields = details
+            .as_object_mut()
+            .expect("All error details are JSON objects");
+        fields.insert("kind".to_string(), title.error_type.into());
+        fields.insert("message".to_string(), title.error_message.into());
+        details
+    }
+}
+
+fn blob_type_to_json(blob_type: BlobType) -> &'static str {
+    match blob_type {
+        BlobType::File => "file",
+        BlobType::Dir => "dir",
+        BlobType::Symlink => "symlink",
+    }
+}
+
+fn blob_reference_to_json(reference: &BlobReference) -> Value {
+    json!({
+        "blob_type": blob_type_to_json(reference.blob_type),
+        "parent_id": reference.parent_id.to_hex(),
+        "path": reference.path.as_str(),
+    })
+}
+
+fn blob_references_to_json(references: &BTreeSet<BlobReference>) -> Vec<Value> {
+    references.iter().map(blob_reference_to_json).collect()
+}
+
+fn maybe_blob_reference_to_json(reference: &MaybeBlobReferenceWithId) -> Value {
+    match reference {
+        MaybeBlobReferenceWithId::UnreachableFromFilesystemRoot => json!({
+            "reachable": false,
+        }),
+        MaybeBlobReferenceWithId::ReachableFromFilesystemRoot {
+            blob_id,
+            referenced_as,
+        } => json!({
+            "reachable": true,
+            "blob_id": blob_id.to_hex(),
+            "blob_referenced_as": blob_reference_to_json(referenced_as),
+        }),
+    }
+}
+
+fn node_reference_to_json(reference: &NodeAndBlobReference) -> Value {
+    match reference {
+        NodeAndBlobReference::RootNode { belongs_to_blob } => json!({
+            "node_type": "root",
+            "belongs_to_blob": {
+                "reachable": true,
+                "blob_id": belongs_to_blob.blob_id.to_hex(),
+                "blob_referenced_as": blob_reference_to_json(&belongs_to_blob.referenced_as),
+            },
+        }),
+        NodeAndBlobReference::NonRootInnerNode {
+            belongs_to_blob,
+            depth,
+            parent_id,
+        } => json!({
+            "node_type": "inner",
+            "depth": depth.get(),
+            "parent_id": parent_id.to_hex(),
+            "belongs_to_blob": maybe_blob_reference_to_json(belongs_to_blob),
+        }),
+        NodeAndBlobReference::NonRootLeafNode {
+            belongs_to_blob,
+            parent_id,
+        } => json!({
+            "node_type": "leaf",
+            "parent_id": parent_id.to_hex(),
+            "belongs_to_blob": maybe_blob_reference_to_json(belongs_to_blob),
+        }),
+    }
+}
+
+fn node_references_to_json(references: &BTreeSet<NodeAndBlobReference>) -> Vec<Value> {
+    references.iter().map(node_reference_to_json).collect()
+}
+
+fn node_info_to_json(node_info: NodeInfoAsSeenByLookingAtNode) -> Value {
+    match node_info {
+        NodeInfoAsSeenByLookingAtNode::Unreadable => json!({ "node_type": "unreadable" }),
+        NodeInfoAsSeenByLookingAtNode::InnerNode { depth } => json!({
+            "node_type": "inner",
+            "depth": depth.get(),
+        }),
+        NodeInfoAsSeenByLookingAtNode::LeafNode => json!({ "node_type": "leaf" }),
+    }
+}
+
+fn maybe_node_info_to_json(node_info: MaybeNodeInfoAsSeenByLookingAtNode) -> Value {
+    match node_info {
+        MaybeNodeInfoAsSeenByLookingAtNode::Missing => json!({ "node_type": "missing" }),
+        MaybeNodeInfoAsSeenByLookingAtNode::Unreadable => json!({ "node_type": "unreadable" }),
+        MaybeNodeInfoAsSeenByLookingAtNode::InnerNode { depth } => json!({
+            "node_type": "inner",
+            "depth": depth.get(),
+        }),
+        MaybeNodeInfoAsSeenByLookingAtNode::LeafNode => json!({ "node_type": "leaf" }),
+    }
+}
+
+fn maybe_blob_info_to_json(blob_info: MaybeBlobInfoAsSeenByLookingAtBlob) -> Value {
+    match blob_info {
+        MaybeBlobInfoAsSeenByLookingAtBlob::Missing => json!({ "state": "missing" }),
+        MaybeBlobInfoAsSeenByLookingAtBlob::Unreadable => json!({ "state": "unreadable" }),
+        MaybeBlobInfoAsSeenByLookingAtBlob::Readable {
+            blob_type,
+            parent_pointer,
+        } => json!({
+            "state": "readable",
+            "blob_type": blob_type_to_json(blob_type),
+            "parent_pointer": parent_pointer.to_hex(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU8;
+
+    use cryfs_blobstore::BlobId;
+    use cryfs_blockstore::BlockId;
+    use cryfs_utils::path::AbsolutePathBuf;
+
+    use super::*;
+
+    #[test]
+    fn test_node_missing() {
+        let error: CorruptedError = NodeMissingError {
+            node_id: BlockId::from_hex("918ca6ac525c700c275615c3de0cea1b").unwrap(),
+            referenced_as: [NodeAndBlobReference::NonRootInnerNode {
+                depth: NonZeroU8::new(4).unwrap(),
+                parent_id: BlockId::from_hex("6935f4693039c90da370e99ada93ef70").unwrap(),
+                belongs_to_blob: MaybeBlobReferenceWithId::ReachableFromFilesystemRoot {
+                    blob_id: BlobId::from_hex("525c7918ca6ade0cea1bc00c275615c3").unwrap(),
+                    referenced_as: BlobReference {
+                        blob_type: BlobType::File,
+                        parent_id: BlobId::from_hex("3ef706935f4693039c90da370e99ada9").unwrap(),
+                        path: AbsolutePathBuf::try_from_string("/path/to/blob".to_string())
+                            .unwrap(),
+                    },
+                },
+            }]
+            .into_iter()
+            .collect(),
+        }
+        .into();
+        assert_eq!("NodeMissing", error.kind());
+        assert_eq!(
+            json!({
+                "kind": "NodeMissing",
+                "message": "Node is missing.",
+                "node_id": "918ca6ac525c700c275615c3de0cea1b",
+                "node_referenced_as": [{
+                    "node_type": "inner",
+                    "depth": 4,
+                    "parent_id": "6935f4693039c90da370e99ada93ef70",
+                    "belongs_to_blob": {
+                        "reachable": true,
+                        "blob_id": "525c7918ca6ade0cea1bc00c275615c3",
+                        "blob_referenced_as": {
+                            "blob_type": "file",
+                            "parent_id": "3ef706935f4693039c90da370e99ada9",
+                            "path": "/path/to/blob",
+                        },
+                    },
+                }],
+            }),
+            error.to_json(),
+        );
+    }
+
+    #[test]
+    fn test_node_unreferenced() {
+        let error: CorruptedError = NodeUnreferencedError {
+            node_id: BlockId::from_hex("918ca6ac525c700c275615c3de0cea1b").unwrap(),
+            node_info: NodeInfoAsSeenByLookingAtNode::LeafNode,
+        }
+        .into();
+        assert_eq!("NodeUnreferenced", error.kind());
+        assert_eq!(
+            json!({
+                "kind": "NodeUnreferenced",
+                "message": "Node is not referenced by any other nodes.",
+                "node_id": "918ca6ac525c700c275615c3de0cea1b",
+                "node_info": { "node_type": "leaf" },
+            }),
+            error.to_json(),
+        );
+    }
+
+    #[test]
+    fn test_blob_referenced_multiple_times() {
+        let error: CorruptedError = BlobReferencedMultipleTimesError {
+            blob_id: BlobId::from_hex("525c7918ca6ade0cea1bc00c275615c3").unwrap(),
+            blob_info: MaybeBlobInfoAsSeenByLookingAtBlob::Readable {
+                blob_type: BlobType::Dir,
+                parent_pointer: BlobId::from_hex("3ef706935f4693039c90da370e99ada9").unwrap(),
+            },
+            referenced_as: [
+                BlobReference {
+                    blob_type: BlobType::Dir,
+                    parent_id: BlobId::from_hex("3ef706935f4693039c90da370e99ada9").unwrap(),
+                    path: AbsolutePathBuf::try_from_string("/path/one".to_string()).unwrap(),
+                },
+                BlobReference {
+                    blob_type: BlobType::Dir,
+                    parent_id: BlobId::from_hex("0da370e99ada93ef706935f4693039c9").unwrap(),
+                    path: AbsolutePathBuf::try_from_string("/path/two".to_string()).unwrap(),
+                },
+            ]
+            .into_iter()
+            .collect(),
+        }
+        .into();
+        assert_eq!("BlobReferencedMultipleTimes", error.kind());
+        assert_eq!(
+            json!({
+                "kind": "BlobReferencedMultipleTimes",
+                "message": "Blob is referenced multiple times.",
+                "blob_id": "525c7918ca6ade0cea1bc00c275615c3",
+                "blob_info": {
+                    "state": "readable",
+                    "blob_type": "dir",
+                    "parent_pointer": "3ef706935f4693039c90da370e99ada9",
+                },
+                "blob_referenced_as": [
+                    {
+                        "blob_type": "dir",
+                        "parent_id": "0da370e99ada93ef706935f4693039c9",
+                        "path": "/path/two",
+                    },
+                    {
+                        "blob_type": "dir",
+                        "parent_id": "3ef706935f4693039c90da370e99ada9",
+                        "path": "/path/one",
+                    },
+                ],
+            }),
+            error.to_json(),
+        );
+    }
+}
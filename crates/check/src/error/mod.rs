@@ -21,7 +21,11 @@ pub use blob_unreadable::BlobUnreadableError;
 mod wrong_parent_pointer;
 pub use wrong_parent_pointer::WrongParentPointerError;
 
+mod wrong_link_count;
+pub use wrong_link_count::WrongLinkCountError;
+
 mod display;
+mod json;
 
 /// A [CorruptedError] is an error we found in the file system when analyzing it
 #[derive(Debug, Error, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
@@ -47,6 +51,9 @@ pub enum CorruptedError {
 
     #[error(transparent)]
     WrongParentPointer(#[from] WrongParentPointerError),
+
+    #[error(transparent)]
+    WrongLinkCount(#[from] WrongLinkCountError),
 }
 
 /// A CheckError is an error found in the analysis itself. This doesn't necessarily mean that the file system is corrupted
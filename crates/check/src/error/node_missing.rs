@@ -27,7 +27,7 @@ impl NodeMissingError {
     }
 }
 
-const ERROR_TITLE: ErrorTitle = ErrorTitle {
+pub(super) const ERROR_TITLE: ErrorTitle = ErrorTitle {
     error_type: "NodeMissing",
     error_message: "Node is missing.",
 };
@@ -33,7 +33,7 @@ impl NodeReferencedMultipleTimesError {
     }
 }
 
-const ERROR_TITLE: ErrorTitle = ErrorTitle {
+pub(super) const ERROR_TITLE: ErrorTitle = ErrorTitle {
     error_type: "NodeReferencedMultipleTimes",
     error_message: "Node is referenced multiple times.",
 };
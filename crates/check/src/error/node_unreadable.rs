@@ -26,7 +26,7 @@ impl NodeUnreadableError {
     }
 }
 
-const ERROR_TITLE: ErrorTitle = ErrorTitle {
+pub(super) const ERROR_TITLE: ErrorTitle = ErrorTitle {
     error_type: "NodeUnreadable",
     error_message: "Node is unreadable and likely corrupted.",
 };
@@ -18,7 +18,7 @@ impl NodeUnreferencedError {
     }
 }
 
-const ERROR_TITLE: ErrorTitle = ErrorTitle {
+pub(super) const ERROR_TITLE: ErrorTitle = ErrorTitle {
     error_type: "NodeUnreferenced",
     error_message: "Node is not referenced by any other nodes.",
 };
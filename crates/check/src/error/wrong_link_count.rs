@@ -0,0 +1,130 @@
+use console::style;
+use cryfs_fsblobstore::fsblobstore::BlobType;
+use std::collections::BTreeSet;
+use std::fmt::{Debug, Display, Formatter};
+use thiserror::Error;
+
+use cryfs_blobstore::BlobId;
+
+use super::display::{BlobErrorDisplayMessage, ErrorDisplayBlobInfo, ErrorTitle};
+use crate::node_info::{BlobReference, MaybeBlobInfoAsSeenByLookingAtBlob};
+
+#[derive(Error, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+pub struct WrongLinkCountError {
+    pub blob_id: BlobId,
+    pub blob_type: BlobType,
+    pub parent_pointer: BlobId,
+    pub num_links: u32,
+    pub referenced_as: BTreeSet<BlobReference>,
+}
+
+pub(super) const ERROR_TITLE: ErrorTitle = ErrorTitle {
+    error_type: "WrongLinkCount",
+    error_message: "The link count stored in the blob does not match the number of directory entries referencing it.",
+};
+
+impl Display for WrongLinkCountError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let error_display = BlobErrorDisplayMessage {
+            error_title: ERROR_TITLE,
+
+            blob_info: ErrorDisplayBlobInfo {
+                blob_id: self.blob_id,
+                blob_info: MaybeBlobInfoAsSeenByLookingAtBlob::Readable {
+                    blob_type: self.blob_type,
+                    parent_pointer: self.parent_pointer,
+                },
+                blob_referenced_as: self.referenced_as.iter(),
+            },
+        };
+        error_display.display(f)?;
+        writeln!(
+            f,
+            "  {num_links_title} {num_links}",
+            num_links_title = style("Link Count:").bold(),
+            num_links = self.num_links,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use console::strip_ansi_codes;
+    use cryfs_fsblobstore::fsblobstore::BlobType;
+    use cryfs_utils::path::AbsolutePathBuf;
+
+    use super::*;
+
+    #[test]
+    fn test_display_too_few_references() {
+        let error = WrongLinkCountError {
+            blob_id: BlobId::from_hex("918ca6ac525c700c275615c3de0cea1b").unwrap(),
+            blob_type: BlobType::File,
+            parent_pointer: BlobId::from_hex("f4693039c3ef70a370e99ada9693590d").unwrap(),
+            num_links: 3,
+            referenced_as: [
+                BlobReference {
+                    blob_type: BlobType::File,
+                    parent_id: BlobId::from_hex("f4693039c3ef70a370e99ada9693590d").unwrap(),
+                    path: AbsolutePathBuf::try_from_string("/path/to/blob".to_string()).unwrap(),
+                },
+                BlobReference {
+                    blob_type: BlobType::File,
+                    parent_id: BlobId::from_hex("3ef706935f4693039c90da370e99ada9").unwrap(),
+                    path: AbsolutePathBuf::try_from_string("/path/to/link".to_string()).unwrap(),
+                },
+            ]
+            .into_iter()
+            .collect(),
+        };
+        assert_eq!(
+            strip_ansi_codes(&format!("{}", error)).trim(),
+            "
+Error[WrongLinkCount]: The link count stored in the blob does not match the number of directory entries referencing it.
+  ---> File at /path/to/link [parent_blob=3ef706935f4693039c90da370e99ada9]
+  ---> File at /path/to/blob [parent_blob=f4693039c3ef70a370e99ada9693590d]
+  Blob Id: 918ca6ac525c700c275615c3de0cea1b
+  Blob Info: File [parent_pointer=f4693039c3ef70a370e99ada9693590d]
+  Link Count: 3
+    "
+            .trim(),
+        );
+    }
+
+    #[test]
+    fn test_display_too_many_references() {
+        let error = WrongLinkCountError {
+            blob_id: BlobId::from_hex("8ca6ac525c700c275615c3de0cea1b91").unwrap(),
+            blob_type: BlobType::Symlink,
+            parent_pointer: BlobId::from_hex("693039c3ef70a370e99ada9693590df4").unwrap(),
+            num_links: 1,
+            referenced_as: [
+                BlobReference {
+                    blob_type: BlobType::Symlink,
+                    parent_id: BlobId::from_hex("693039c3ef70a370e99ada9693590df4").unwrap(),
+                    path: AbsolutePathBuf::try_from_string("/path/to/symlink".to_string()).unwrap(),
+                },
+                BlobReference {
+                    blob_type: BlobType::Symlink,
+                    parent_id: BlobId::from_hex("693039c3ef70a370e99ada9693590df4").unwrap(),
+                    path: AbsolutePathBuf::try_from_string("/path/to/another/symlink".to_string())
+                        .unwrap(),
+                },
+            ]
+            .into_iter()
+            .collect(),
+        };
+        assert_eq!(
+            strip_ansi_codes(&format!("{}", error)).trim(),
+            "
+Error[WrongLinkCount]: The link count stored in the blob does not match the number of directory entries referencing it.
+  ---> Symlink at /path/to/another/symlink [parent_blob=693039c3ef70a370e99ada9693590df4]
+  ---> Symlink at /path/to/symlink [parent_blob=693039c3ef70a370e99ada9693590df4]
+  Blob Id: 8ca6ac525c700c275615c3de0cea1b91
+  Blob Info: Symlink [parent_pointer=693039c3ef70a370e99ada9693590df4]
+  Link Count: 1
+    "
+            .trim(),
+        );
+    }
+}
@@ -16,7 +16,7 @@ pub struct WrongParentPointerError {
     pub referenced_as: BTreeSet<BlobReference>,
 }
 
-const ERROR_TITLE: ErrorTitle = ErrorTitle {
+pub(super) const ERROR_TITLE: ErrorTitle = ErrorTitle {
     error_type: "WrongParentPointer",
     error_message: "The blobs parent pointer does not match any of the blobs referencing it.",
 };
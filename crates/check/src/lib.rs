@@ -4,7 +4,7 @@
 mod args;
 
 mod cli;
-pub use cli::{RecoverCli, check_filesystem};
+pub use cli::{RecoverCli, apply_repair, check_filesystem, check_loaded_filesystem, plan_repair};
 
 mod checks;
 mod console;
@@ -12,7 +12,7 @@ mod error;
 pub use error::{
     BlobReferencedMultipleTimesError, BlobUnreadableError, CorruptedError, NodeMissingError,
     NodeReferencedMultipleTimesError, NodeUnreadableError, NodeUnreferencedError,
-    WrongParentPointerError,
+    WrongLinkCountError, WrongParentPointerError,
 };
 mod node_info;
 pub use node_info::{
@@ -22,6 +22,9 @@ pub use node_info::{
     NodeAndBlobReferenceFromReachableBlob, NodeInfoAsSeenByLookingAtNode, NodeReference,
 };
 mod assertion;
+mod repair;
+pub use repair::{LOST_AND_FOUND_DIR_NAME, RepairAction, RepairPlan};
+mod report;
 mod runner;
 mod task_queue;
 
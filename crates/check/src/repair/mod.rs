@@ -0,0 +1,10 @@
+//! Safe fixes for some of the [crate::CorruptedError]s that cryfs-check finds.
+//!
+//! Repairing happens in two steps. [PlanRepairRunner] looks at the errors and the file system
+//! and decides on a [RepairPlan] without modifying anything. [ApplyRepairRunner] then executes it.
+
+mod plan;
+pub use plan::{LOST_AND_FOUND_DIR_NAME, RepairAction, RepairPlan};
+
+mod runner;
+pub use runner::{ApplyRepairRunner, PlanRepairRunner};
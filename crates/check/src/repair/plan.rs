@@ -0,0 +1,268 @@
+use std::collections::{BTreeSet, HashSet};
+use std::fmt::{self, Display, Formatter};
+
+use cryfs_blobstore::BlobId;
+use cryfs_blockstore::BlockId;
+use cryfs_fsblobstore::fsblobstore::BlobType;
+
+use crate::error::{
+    CorruptedError, NodeMissingError, NodeUnreadableError, WrongParentPointerError,
+};
+use crate::node_info::{MaybeBlobReferenceWithId, NodeAndBlobReference};
+
+/// Name of the directory in the root directory that orphaned blobs are moved into
+pub const LOST_AND_FOUND_DIR_NAME: &str = "lost+found";
+
+/// A fix that `cryfs-check --repair` can apply to the file system
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+pub enum RepairAction {
+    /// Replace a missing leaf node of a reachable blob with a zero-filled leaf node.
+    /// This must run before any other fix that loads the blob.
+    ReplaceMissingLeaf { node_id: BlockId },
+
+    /// Remove a node that isn't referenced from anywhere, together with all nodes below it.
+    /// `node_ids` contains `root_node_id` and all of its descendants that aren't referenced
+    /// from somewhere else as well.
+    RemoveUnreferencedNodes {
+        root_node_id: BlockId,
+        node_ids: BTreeSet<BlockId>,
+    },
+
+    /// Set the parent pointer of a blob to the directory blob that references it.
+    FixParentPointer {
+        blob_id: BlobId,
+        blob_type: BlobType,
+        old_parent: BlobId,
+        new_parent: BlobId,
+    },
+
+    /// Add an entry for a readable blob that isn't referenced from any directory to the
+    /// [LOST_AND_FOUND_DIR_NAME] directory, and point its parent pointer there.
+    /// If the root node of a blob went missing, the leftmost remaining subtree still starts with
+    /// the blob header and is salvaged this way as a truncated blob.
+    MoveToLostAndFound {
+        blob_id: BlobId,
+        blob_type: BlobType,
+    },
+}
+
+impl Display for RepairAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ReplaceMissingLeaf { node_id } => {
+                write!(
+                    f,
+                    "Replace missing leaf node {node_id} with a zero-filled leaf node"
+                )
+            }
+            Self::RemoveUnreferencedNodes {
+                root_node_id,
+                node_ids,
+            } => write!(
+                f,
+                "Remove unreferenced node {root_node_id} and the {num_descendants} nodes below it",
+                num_descendants = node_ids.len() - 1,
+            ),
+            Self::FixParentPointer {
+                blob_id,
+                blob_type,
+                old_parent,
+                new_parent,
+            } => write!(
+                f,
+                "Change parent pointer of {blob_type} blob {blob_id} from {old_parent} to {new_parent}",
+                blob_type = blob_type_name(*blob_type),
+            ),
+            Self::MoveToLostAndFound { blob_id, blob_type } => write!(
+                f,
+                "Move unreferenced {blob_type} blob {blob_id} to /{LOST_AND_FOUND_DIR_NAME}/{blob_id}",
+                blob_type = blob_type_name(*blob_type),
+            ),
+        }
+    }
+}
+
+fn blob_type_name(blob_type: BlobType) -> &'static str {
+    match blob_type {
+        BlobType::File => "file",
+        BlobType::Dir => "dir",
+        BlobType::Symlink => "symlink",
+    }
+}
+
+/// What we found out about the root of an unreferenced subtree by loading it
+#[derive(Debug, Clone)]
+pub enum UnreferencedNode {
+    /// The node is the root node of a readable blob. `children` are the blobs referenced
+    /// by it if it is a directory blob.
+    OrphanedBlob {
+        blob_type: BlobType,
+        children: Vec<BlobId>,
+    },
+    /// The node isn't the root node of a readable blob. `node_ids` are the nodes in its subtree.
+    Other { node_ids: BTreeSet<BlockId> },
+}
+
+/// The list of fixes `cryfs-check --repair` would apply for a list of [CorruptedError]s,
+/// and the errors it doesn't know how to fix safely.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct RepairPlan {
+    pub actions: Vec<RepairAction>,
+    pub unfixable: Vec<CorruptedError>,
+}
+
+impl RepairPlan {
+    /// Decide on the fixes for `errors`. `unreferenced_nodes` must contain an entry for
+    /// each node reported by a [CorruptedError::NodeUnreferenced] error.
+    pub fn new(
+        errors: &[CorruptedError],
+        unreferenced_nodes: &[(BlockId, UnreferencedNode)],
+    ) -> Self {
+        // Orphaned directories that we move to lost+found make their children reachable again,
+        // so those children must stay where they are.
+        let children_of_orphaned_blobs: HashSet<BlobId> = unreferenced_nodes
+            .iter()
+            .flat_map(|(_, node)| match node {
+                UnreferencedNode::OrphanedBlob { children, .. } => children.as_slice(),
+                UnreferencedNode::Other { .. } => &[],
+            })
+            .copied()
+            .collect();
+
+        let mut actions = BTreeSet::new();
+        let mut removed_nodes = HashSet::new();
+        for (node_id, node) in unreferenced_nodes {
+            let blob_id = BlobId::from_root_block_id(*node_id);
+            match node {
+                UnreferencedNode::OrphanedBlob { .. }
+                    if children_of_orphaned_blobs.contains(&blob_id) =>
+                {
+                    // It will be reachable via its parent in lost+found
+                }
+                UnreferencedNode::OrphanedBlob { blob_type, .. } => {
+                    actions.insert(RepairAction::MoveToLostAndFound {
+                        blob_id,
+                        blob_type: *blob_type,
+                    });
+                }
+                UnreferencedNode::Other { node_ids } => {
+                    removed_nodes.extend(node_ids.iter().copied());
+                    actions.insert(RepairAction::RemoveUnreferencedNodes {
+                        root_node_id: *node_id,
+                        node_ids: node_ids.clone(),
+                    });
+                }
+            }
+        }
+
+        let mut unfixable = Vec::new();
+        for error in errors {
+            match error {
+                CorruptedError::NodeUnreferenced(_) => {
+                    // Already handled above
+                }
+                CorruptedError::WrongParentPointer(WrongParentPointerError {
+                    blob_id,
+                    blob_type,
+                    parent_pointer,
+                    referenced_as,
+                }) if referenced_as.len() == 1 => {
+                    let new_parent = referenced_as.first().expect("len is 1").parent_id;
+                    actions.insert(RepairAction::FixParentPointer {
+                        blob_id: *blob_id,
+                        blob_type: *blob_type,
+                        old_parent: *parent_pointer,
+                        new_parent,
+                    });
+                }
+                CorruptedError::NodeMissing(NodeMissingError {
+                    node_id,
+                    referenced_as,
+                }) => {
+                    if referenced_as.iter().all(|referenced_as| {
+                        is_referenced_by_removed_node(referenced_as, &removed_nodes)
+                    }) {
+                        // The nodes referencing it get removed, so nothing references it anymore
+                    } else if is_single_leaf_reference_from_reachable_blob(referenced_as) {
+                        actions.insert(RepairAction::ReplaceMissingLeaf { node_id: *node_id });
+                    } else {
+                        unfixable.push(error.clone());
+                    }
+                }
+                CorruptedError::NodeUnreadable(NodeUnreadableError { node_id, .. })
+                    if removed_nodes.contains(node_id) =>
+                {
+                    // The node gets removed
+                }
+                CorruptedError::WrongParentPointer(_)
+                | CorruptedError::WrongLinkCount(_)
+                | CorruptedError::NodeUnreadable(_)
+                | CorruptedError::NodeReferencedMultipleTimes(_)
+                | CorruptedError::BlobReferencedMultipleTimes(_)
+                | CorruptedError::BlobUnreadable(_) => {
+                    unfixable.push(error.clone());
+                }
+            }
+        }
+
+        Self {
+            // [RepairAction]'s ordering makes sure missing leaves are replaced first
+            actions: actions.into_iter().collect(),
+            unfixable,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+}
+
+fn is_referenced_by_removed_node(
+    referenced_as: &NodeAndBlobReference,
+    removed_nodes: &HashSet<BlockId>,
+) -> bool {
+    match referenced_as {
+        NodeAndBlobReference::RootNode { .. } => false,
+        NodeAndBlobReference::NonRootInnerNode { parent_id, .. }
+        | NodeAndBlobReference::NonRootLeafNode { parent_id, .. } => {
+            removed_nodes.contains(parent_id)
+        }
+    }
+}
+
+fn is_single_leaf_reference_from_reachable_blob(
+    referenced_as: &BTreeSet<NodeAndBlobReference>,
+) -> bool {
+    let mut referenced_as = referenced_as.iter();
+    matches!(
+        (referenced_as.next(), referenced_as.next()),
+        (
+            Some(NodeAndBlobReference::NonRootLeafNode {
+                belongs_to_blob: MaybeBlobReferenceWithId::ReachableFromFilesystemRoot { .. },
+                ..
+            }),
+            None,
+        )
+    )
+}
+
+impl Display for RepairPlan {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.actions.is_empty() {
+            writeln!(f, "Nothing to repair.")?;
+        } else {
+            writeln!(f, "Planned fixes:")?;
+            for action in &self.actions {
+                writeln!(f, "  - {action}")?;
+            }
+        }
+        if !self.unfixable.is_empty() {
+            writeln!(
+                f,
+                "{} errors can't be fixed automatically and will be left as they are.",
+                self.unfixable.len(),
+            )?;
+        }
+        Ok(())
+    }
+}
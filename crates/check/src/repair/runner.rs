@@ -0,0 +1,471 @@
+use anyhow::{Result, anyhow, bail};
+use std::collections::{BTreeSet, HashSet};
+use std::fmt::Debug;
+use std::time::SystemTime;
+
+use super::plan::{LOST_AND_FOUND_DIR_NAME, RepairAction, RepairPlan, UnreferencedNode};
+use crate::error::{CorruptedError, NodeReferencedMultipleTimesError, NodeUnreferencedError};
+use crate::runner::fs_format;
+use cryfs_blobstore::{BlobId, BlobStoreOnBlocks, DataNode, DataNodeStore, HOLE_BLOCK_ID};
+use cryfs_blockstore::{BlockId, BlockStore, LLBlockStore, LockingBlockStore};
+use cryfs_cli_utils::BlockstoreCallback;
+use cryfs_config::config::ConfigLoadResult;
+use cryfs_fsblobstore::{
+    Gid, Mode, Uid,
+    fsblobstore::{BlobType, EntryType, FlushBehavior, FsBlob, FsBlobStore},
+};
+use cryfs_utils::{
+    async_drop::{AsyncDrop, AsyncDropGuard},
+    data::Data,
+    path::{PathComponent, PathComponentBuf},
+    progress::{Progress, ProgressBarManager},
+};
+
+/// Decides on a [RepairPlan] for the errors found by a previous check. This only reads from the blockstore.
+pub struct PlanRepairRunner<'c, 'e> {
+    pub config: &'c ConfigLoadResult,
+    pub errors: &'e [CorruptedError],
+}
+
+impl<'c, 'e> BlockstoreCallback for PlanRepairRunner<'c, 'e> {
+    type Result = Result<RepairPlan>;
+
+    async fn callback<B: LLBlockStore + AsyncDrop + Send + Sync + 'static>(
+        self,
+        blockstore: AsyncDropGuard<LockingBlockStore<B>>,
+    ) -> Self::Result {
+        let blocksize = self.config.config.config().blocksize;
+        let unreferenced_node_ids: Vec<BlockId> = self
+            .errors
+            .iter()
+            .filter_map(|error| match error {
+                CorruptedError::NodeUnreferenced(NodeUnreferencedError { node_id, .. }) => {
+                    Some(*node_id)
+                }
+                _ => None,
+            })
+            .collect();
+        // Nodes referenced multiple times are also referenced from somewhere else, so we must not remove them
+        // when removing an unreferenced subtree.
+        let referenced_multiple_times: HashSet<BlockId> =
+            self.errors
+                .iter()
+                .filter_map(|error| match error {
+                    CorruptedError::NodeReferencedMultipleTimes(
+                        NodeReferencedMultipleTimesError { node_id, .. },
+                    ) => Some(*node_id),
+                    _ => None,
+                })
+                .collect();
+
+        let mut nodestore = DataNodeStore::new(blockstore, blocksize).await?;
+        let mut subtrees = Vec::with_capacity(unreferenced_node_ids.len());
+        for node_id in &unreferenced_node_ids {
+            match nodes_in_subtree(&nodestore, *node_id, &referenced_multiple_times).await {
+                Ok(node_ids) => subtrees.push(node_ids),
+                Err(e) => {
+                    nodestore.async_drop().await?;
+                    return Err(e);
+                }
+            }
+        }
+
+        let blockstore = DataNodeStore::into_inner_block_store(nodestore);
+        let mut blobstore = FsBlobStore::new(
+            BlobStoreOnBlocks::new(blockstore, blocksize).await?,
+            fs_format(self.config.config.config()),
+        );
+        let mut unreferenced_nodes = Vec::with_capacity(unreferenced_node_ids.len());
+        for (node_id, node_ids) in unreferenced_node_ids.into_iter().zip(subtrees) {
+            let node = match load_orphaned_blob(&blobstore, node_id).await {
+                Ok(Some(node)) => node,
+                Ok(None) => UnreferencedNode::Other { node_ids },
+                Err(e) => {
+                    blobstore.async_drop().await?;
+                    return Err(e);
+                }
+            };
+            unreferenced_nodes.push((node_id, node));
+        }
+        blobstore.async_drop().await?;
+
+        Ok(RepairPlan::new(self.errors, &unreferenced_nodes))
+    }
+}
+
+/// Returns `root_node_id` and all nodes below it, except for the ones in `excluded` and the ones below those.
+async fn nodes_in_subtree<B>(
+    nodestore: &DataNodeStore<B>,
+    root_node_id: BlockId,
+    excluded: &HashSet<BlockId>,
+) -> Result<BTreeSet<BlockId>>
+where
+    B: BlockStore<Block: Send + Sync> + AsyncDrop + Debug + Send + Sync + 'static,
+{
+    let mut result = BTreeSet::new();
+    let mut to_visit = vec![root_node_id];
+    while let Some(node_id) = to_visit.pop() {
+        if excluded.contains(&node_id) || result.contains(&node_id) {
+            continue;
+        }
+        match nodestore.load(node_id).await {
+            Ok(Some(DataNode::Inner(node))) => {
+                // Holes in sparse blobs don't have nodes
+                to_visit.extend(
+                    node.children()
+                        .filter(|child_id| *child_id != HOLE_BLOCK_ID),
+                );
+                result.insert(node_id);
+            }
+            Ok(Some(DataNode::Leaf(_))) | Err(_) => {
+                // Unreadable nodes get removed as well, we don't know their children
+                result.insert(node_id);
+            }
+            Ok(None) => {
+                // Missing nodes don't need to be removed
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// If `node_id` is the root node of a readable blob, return it as [UnreferencedNode::OrphanedBlob]
+async fn load_orphaned_blob<B>(
+    blobstore: &FsBlobStore<BlobStoreOnBlocks<B>>,
+    node_id: BlockId,
+) -> Result<Option<UnreferencedNode>>
+where
+    B: BlockStore<Block: Send + Sync>
+        + AsyncDrop<Error = anyhow::Error>
+        + Debug
+        + Send
+        + Sync
+        + 'static,
+{
+    let mut blob = match blobstore.load(&BlobId::from_root_block_id(node_id)).await {
+        Ok(Some(blob)) => blob,
+        Ok(None) => bail!("Node {node_id} vanished while we were repairing the file system"),
+        // Either not the root node of a blob, or the blob is unreadable. Either way, we can't recover it.
+        Err(_) => return Ok(None),
+    };
+    let blob_type = blob.blob_type();
+    let children = match &mut *blob {
+        FsBlob::Directory(dir) => dir.entries().await.map(|entries| {
+            entries
+                // Fifos, sockets and devices aren't backed by a blob
+                .filter(|entry| entry.entry_type().has_blob())
+                .map(|entry| *entry.blob_id())
+                .collect()
+        }),
+        FsBlob::File(_) | FsBlob::Symlink(_) => Ok(vec![]),
+    };
+    blob.async_drop().await?;
+    // A directory blob whose entries are unreadable can't be recovered either
+    Ok(children
+        .ok()
+        .map(|children| UnreferencedNode::OrphanedBlob {
+            blob_type,
+            children,
+        }))
+}
+
+/// Applies a [RepairPlan] to the file system
+pub struct ApplyRepairRunner<'c, 'p, PBM: ProgressBarManager> {
+    pub config: &'c ConfigLoadResult,
+    pub plan: &'p RepairPlan,
+    /// Owner of the entries we add to the lost+found directory
+    pub uid: Uid,
+    pub gid: Gid,
+    pub progress_bar_manager: PBM,
+}
+
+impl<'c, 'p, PBM: ProgressBarManager> BlockstoreCallback for ApplyRepairRunner<'c, 'p, PBM> {
+    type Result = Result<()>;
+
+    async fn callback<B: LLBlockStore + AsyncDrop + Send + Sync + 'static>(
+        self,
+        blockstore: AsyncDropGuard<LockingBlockStore<B>>,
+    ) -> Self::Result {
+        let root_blob_id = BlobId::from_hex(&self.config.config.config().root_blob);
+        let root_blob_id = match root_blob_id {
+            Ok(root_blob_id) => root_blob_id,
+            Err(e) => {
+                let mut blockstore = blockstore;
+                blockstore.async_drop().await?;
+                return Err(e);
+            }
+        };
+        let blocksize = self.config.config.config().blocksize;
+        let pb = self.progress_bar_manager.new_progress_bar(
+            "Repairing file system",
+            u64::try_from(self.plan.actions.len()).unwrap(),
+        );
+
+        // Node fixes go first because blob fixes may need to load blobs with previously missing leaves
+        let mut nodestore = DataNodeStore::new(blockstore, blocksize).await?;
+        if let Err(e) = apply_node_actions(&nodestore, self.plan, &pb).await {
+            nodestore.async_drop().await?;
+            return Err(e);
+        }
+
+        let blockstore = DataNodeStore::into_inner_block_store(nodestore);
+        let mut blobstore = FsBlobStore::new(
+            BlobStoreOnBlocks::new(blockstore, blocksize).await?,
+            fs_format(self.config.config.config()),
+        );
+        let mut result =
+            apply_blob_actions(&blobstore, root_blob_id, self.plan, self.uid, self.gid, &pb).await;
+        if result.is_ok() {
+            // Make sure the fixes are on disk before we report success
+            result = blobstore.sync().await;
+        }
+        blobstore.async_drop().await?;
+        result?;
+
+        pb.finish();
+        Ok(())
+    }
+}
+
+async fn apply_node_actions<B>(
+    nodestore: &DataNodeStore<B>,
+    plan: &RepairPlan,
+    pb: &impl Progress,
+) -> Result<()>
+where
+    B: BlockStore<Block: Send + Sync> + AsyncDrop + Debug + Send + Sync + 'static,
+{
+    for action in &plan.actions {
+        match action {
+            RepairAction::ReplaceMissingLeaf { node_id } => {
+                let max_bytes_per_leaf = nodestore.layout().max_bytes_per_leaf();
+                let zeroes = Data::from(vec![0; usize::try_from(max_bytes_per_leaf).unwrap()]);
+                if nodestore
+                    .try_create_new_leaf_node(*node_id, &zeroes)
+                    .await?
+                    .is_none()
+                {
+                    bail!("Node {node_id} was created while we were repairing the file system");
+                }
+            }
+            RepairAction::RemoveUnreferencedNodes { node_ids, .. } => {
+                for node_id in node_ids {
+                    // Ignore whether it existed, a node being missing is fine here
+                    let _ = nodestore.remove_by_id(node_id).await?;
+                }
+            }
+            RepairAction::FixParentPointer { .. } | RepairAction::MoveToLostAndFound { .. } => {
+                continue;
+            }
+        }
+        pb.inc(1);
+    }
+    Ok(())
+}
+
+async fn apply_blob_actions<B>(
+    blobstore: &FsBlobStore<BlobStoreOnBlocks<B>>,
+    root_blob_id: BlobId,
+    plan: &RepairPlan,
+    uid: Uid,
+    gid: Gid,
+    pb: &impl Progress,
+) -> Result<()>
+where
+    B: BlockStore<Block: Send + Sync>
+        + AsyncDrop<Error = anyhow::Error>
+        + Debug
+        + Send
+        + Sync
+        + 'static,
+{
+    let mut lost_and_found_id = None;
+    for action in &plan.actions {
+        match action {
+            RepairAction::ReplaceMissingLeaf { .. }
+            | RepairAction::RemoveUnreferencedNodes { .. } => {
+                continue;
+            }
+            RepairAction::FixParentPointer {
+                blob_id,
+                new_parent,
+                ..
+            } => {
+                set_parent(blobstore, blob_id, new_parent).await?;
+            }
+            RepairAction::MoveToLostAndFound { blob_id, blob_type } => {
+                let lost_and_found_id = match lost_and_found_id {
+                    Some(id) => id,
+                    None => {
+                        let id =
+                            get_or_create_lost_and_found(blobstore, root_blob_id, uid, gid).await?;
+                        *lost_and_found_id.insert(id)
+                    }
+                };
+                move_to_lost_and_found(blobstore, lost_and_found_id, blob_id, *blob_type, uid, gid)
+                    .await?;
+            }
+        }
+        pb.inc(1);
+    }
+    Ok(())
+}
+
+async fn load_blob<B>(
+    blobstore: &FsBlobStore<BlobStoreOnBlocks<B>>,
+    blob_id: &BlobId,
+) -> Result<AsyncDropGuard<FsBlob<BlobStoreOnBlocks<B>>>>
+where
+    B: BlockStore<Block: Send + Sync>
+        + AsyncDrop<Error = anyhow::Error>
+        + Debug
+        + Send
+        + Sync
+        + 'static,
+{
+    blobstore
+        .load(blob_id)
+        .await?
+        .ok_or_else(|| anyhow!("Blob {blob_id} vanished while we were repairing the file system"))
+}
+
+async fn set_parent<B>(
+    blobstore: &FsBlobStore<BlobStoreOnBlocks<B>>,
+    blob_id: &BlobId,
+    new_parent: &BlobId,
+) -> Result<()>
+where
+    B: BlockStore<Block: Send + Sync>
+        + AsyncDrop<Error = anyhow::Error>
+        + Debug
+        + Send
+        + Sync
+        + 'static,
+{
+    let mut blob = load_blob(blobstore, blob_id).await?;
+    let result = blob.set_parent(new_parent).await;
+    blob.async_drop().await?;
+    result
+}
+
+async fn get_or_create_lost_and_found<B>(
+    blobstore: &FsBlobStore<BlobStoreOnBlocks<B>>,
+    root_blob_id: BlobId,
+    uid: Uid,
+    gid: Gid,
+) -> Result<BlobId>
+where
+    B: BlockStore<Block: Send + Sync>
+        + AsyncDrop<Error = anyhow::Error>
+        + Debug
+        + Send
+        + Sync
+        + 'static,
+{
+    let name = PathComponent::try_from_str(LOST_AND_FOUND_DIR_NAME).unwrap();
+    let mut root_blob = load_blob(blobstore, &root_blob_id).await?;
+    let result = async {
+        let root_dir = root_blob.as_dir_mut()?;
+        if let Some(entry) = root_dir.entry_by_name(name).await? {
+            if entry.entry_type() != EntryType::Dir {
+                bail!("/{LOST_AND_FOUND_DIR_NAME} exists but isn't a directory");
+            }
+            return Ok(*entry.blob_id());
+        }
+
+        let mut lost_and_found = blobstore
+            .create_dir_blob(
+                &BlobId::new_random(),
+                &root_blob_id,
+                FlushBehavior::FlushImmediately,
+            )
+            .await?;
+        let lost_and_found_id = lost_and_found.blob_id();
+        lost_and_found.async_drop().await?;
+        let now = SystemTime::now();
+        root_dir
+            .add_entry_dir(
+                name.to_owned(),
+                lost_and_found_id,
+                Mode::zero()
+                    .with_dir_flag()
+                    .with_user_read_flag()
+                    .with_user_write_flag()
+                    .with_user_exec_flag(),
+                uid,
+                gid,
+                now,
+                now,
+            )
+            .await?;
+        Ok(lost_and_found_id)
+    }
+    .await;
+    root_blob.async_drop().await?;
+    result
+}
+
+async fn move_to_lost_and_found<B>(
+    blobstore: &FsBlobStore<BlobStoreOnBlocks<B>>,
+    lost_and_found_id: BlobId,
+    blob_id: &BlobId,
+    blob_type: BlobType,
+    uid: Uid,
+    gid: Gid,
+) -> Result<()>
+where
+    B: BlockStore<Block: Send + Sync>
+        + AsyncDrop<Error = anyhow::Error>
+        + Debug
+        + Send
+        + Sync
+        + 'static,
+{
+    set_parent(blobstore, blob_id, &lost_and_found_id).await?;
+
+    let name = PathComponentBuf::try_from_string(blob_id.to_hex()).unwrap();
+    let now = SystemTime::now();
+    let mut lost_and_found = load_blob(blobstore, &lost_and_found_id).await?;
+    let result = match lost_and_found.as_dir_mut() {
+        Ok(lost_and_found) => match blob_type {
+            BlobType::Dir => lost_and_found
+                .add_entry_dir(
+                    name,
+                    *blob_id,
+                    Mode::zero()
+                        .with_dir_flag()
+                        .with_user_read_flag()
+                        .with_user_write_flag()
+                        .with_user_exec_flag(),
+                    uid,
+                    gid,
+                    now,
+                    now,
+                )
+                .await
+                .map_err(anyhow::Error::from),
+            BlobType::File => lost_and_found
+                .add_entry_file(
+                    name,
+                    *blob_id,
+                    Mode::zero()
+                        .with_file_flag()
+                        .with_user_read_flag()
+                        .with_user_write_flag(),
+                    uid,
+                    gid,
+                    now,
+                    now,
+                )
+                .await
+                .map_err(anyhow::Error::from),
+            BlobType::Symlink => lost_and_found
+                .add_entry_symlink(name, *blob_id, uid, gid, now, now)
+                .await
+                .map_err(anyhow::Error::from),
+        },
+        Err(e) => Err(e),
+    };
+    lost_and_found.async_drop().await?;
+    result
+}
@@ -0,0 +1,90 @@
+//! The machine readable report printed by `cryfs-check --output json`
+
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+
+use cryfs_cli_utils::CliErrorKind;
+
+use crate::error::CorruptedError;
+
+/// Build the JSON report for the `errors` found by a check that finishes with `exit_status`.
+pub fn json_report(errors: &[CorruptedError], exit_status: CliErrorKind) -> Value {
+    let mut num_errors_by_kind = BTreeMap::<&'static str, usize>::new();
+    for error in errors {
+        *num_errors_by_kind.entry(error.kind()).or_default() += 1;
+    }
+    json!({
+        "errors": errors.iter().map(CorruptedError::to_json).collect::<Vec<_>>(),
+        "summary": {
+            "num_errors": errors.len(),
+            "num_errors_by_kind": num_errors_by_kind,
+        },
+        "exit_status": {
+            "kind": exit_status,
+            "code": exit_status.exit_code_number(),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use cryfs_blockstore::BlockId;
+
+    use super::*;
+    use crate::{NodeInfoAsSeenByLookingAtNode, NodeUnreferencedError};
+
+    #[test]
+    fn test_no_errors() {
+        assert_eq!(
+            json!({
+                "errors": [],
+                "summary": {
+                    "num_errors": 0,
+                    "num_errors_by_kind": {},
+                },
+                "exit_status": {
+                    "kind": "Success",
+                    "code": 0,
+                },
+            }),
+            json_report(&[], CliErrorKind::Success),
+        );
+    }
+
+    #[test]
+    fn test_some_errors() {
+        let errors: Vec<CorruptedError> = vec![
+            NodeUnreferencedError {
+                node_id: BlockId::from_hex("918ca6ac525c700c275615c3de0cea1b").unwrap(),
+                node_info: NodeInfoAsSeenByLookingAtNode::LeafNode,
+            }
+            .into(),
+            NodeUnreferencedError {
+                node_id: BlockId::from_hex("6935f4693039c90da370e99ada93ef70").unwrap(),
+                node_info: NodeInfoAsSeenByLookingAtNode::Unreadable,
+            }
+            .into(),
+        ];
+        let report = json_report(&errors, CliErrorKind::InvalidFilesystem);
+        assert_eq!(
+            json!({
+                "num_errors": 2,
+                "num_errors_by_kind": {
+                    "NodeUnreferenced": 2,
+                },
+            }),
+            report["summary"],
+        );
+        assert_eq!(
+            json!({
+                "kind": "InvalidFilesystem",
+                "code": 19,
+            }),
+            report["exit_status"],
+        );
+        assert_eq!(
+            vec![errors[0].to_json(), errors[1].to_json()],
+            *report["errors"].as_array().unwrap(),
+        );
+    }
+}
@@ -20,13 +20,14 @@ use crate::{
     NodeAndBlobReferenceFromReachableBlob, NodeReference,
 };
 use cryfs_blobstore::{
-    BlobId, BlobStore, BlobStoreOnBlocks, DataNode, DataNodeStore, DataTreeStore,
+    Blob, BlobId, BlobStore, BlobStoreOnBlocks, DataNode, DataNodeStore, DataTreeStore,
+    HOLE_BLOCK_ID,
 };
 use cryfs_blockstore::{BlockId, BlockStore, LLBlockStore, LockingBlockStore};
 use cryfs_cli_utils::BlockstoreCallback;
-use cryfs_config::config::ConfigLoadResult;
+use cryfs_config::config::{ConfigLoadResult, CryConfig};
 use cryfs_fsblobstore::fsblobstore::EntryType;
-use cryfs_fsblobstore::fsblobstore::{BlobType, FsBlob, FsBlobStore};
+use cryfs_fsblobstore::fsblobstore::{BlobType, FsBlob, FsBlobStore, FsFormat, journal_blob_id};
 use cryfs_utils::{
     async_drop::{AsyncDrop, AsyncDropGuard},
     containers::{HashMapExt, OccupiedError},
@@ -73,12 +74,21 @@ impl<'l, PBM: ProgressBarManager> BlockstoreCallback for RecoverRunner<'l, PBM>
             }
         };
         pb.finish();
-        println!("Found {} nodes", all_nodes.len());
+        // This is progress information, stdout is reserved for the check results
+        eprintln!("Found {} nodes", all_nodes.len());
 
         let checks = AllChecks::new(root_blob_id);
 
         let blocksize = self.config.config.config().blocksize;
-        let mut blobstore = FsBlobStore::new(BlobStoreOnBlocks::new(blockstore, blocksize).await?);
+        let mut blobstore = BlobStoreOnBlocks::new(blockstore, blocksize).await?;
+        let all_nodes = match remove_journal_nodes(&blobstore, all_nodes, &root_blob_id).await {
+            Ok(all_nodes) => all_nodes,
+            Err(e) => {
+                blobstore.async_drop().await?;
+                return Err(e);
+            }
+        };
+        let mut blobstore = FsBlobStore::new(blobstore, fs_format(self.config.config.config()));
 
         let pb = self
             .progress_bar_manager
@@ -154,6 +164,15 @@ impl<'l, PBM: ProgressBarManager> BlockstoreCallback for RecoverRunner<'l, PBM>
     }
 }
 
+/// The format of the file system decides whether blobs we write (e.g. during repair) may use features CryFS 1.0 can't read.
+pub(crate) fn fs_format(config: &CryConfig) -> FsFormat {
+    if config.has_extended_format() {
+        FsFormat::Extended
+    } else {
+        FsFormat::Cryfs1Compatible
+    }
+}
+
 async fn get_all_node_ids<B>(
     blockstore: &AsyncDropGuard<LockingBlockStore<B>>,
 ) -> Result<HashSet<BlockId>>
@@ -163,6 +182,35 @@ where
     blockstore.all_blocks().await?.try_collect().await
 }
 
+/// The intent journal of the file system (see [IntentJournal](cryfs_fsblobstore::fsblobstore::IntentJournal)) isn't referenced
+/// from the directory structure, so we need to exclude its nodes or we'd report them as unreferenced.
+async fn remove_journal_nodes<B>(
+    blobstore: &BlobStoreOnBlocks<B>,
+    mut all_nodes: HashSet<BlockId>,
+    root_blob_id: &BlobId,
+) -> Result<HashSet<BlockId>>
+where
+    B: BlockStore<Block: Send + Sync>
+        + AsyncDrop<Error = anyhow::Error>
+        + Debug
+        + Send
+        + Sync
+        + 'static,
+{
+    let Some(mut journal) = blobstore.load(&journal_blob_id(root_blob_id)).await? else {
+        // File systems in the CryFS 1.0 compatible format and file systems that were never mounted with a version
+        // that has the journal don't have a journal blob
+        return Ok(all_nodes);
+    };
+    let journal_nodes: Result<Vec<BlockId>> =
+        async { journal.all_blocks()?.try_collect().await }.await;
+    journal.async_drop().await?;
+    for node in journal_nodes? {
+        all_nodes.remove(&node);
+    }
+    Ok(all_nodes)
+}
+
 async fn check_all_unreachable_nodes<B>(
     nodestore: &DataNodeStore<B>,
     unreachable_nodes: &HashSet<BlockId>,
@@ -265,7 +313,7 @@ where
     async move {
         log::debug!("Entering blob {blob_referenced_as}");
 
-        let loaded = blobstore.load(&blob_referenced_as.blob_id).await;
+        let loaded = load_blob_with_entries(blobstore, &blob_referenced_as.blob_id).await;
 
         let seen_blob_info = match &loaded {
             Ok(Some(blob)) => blob_content_summary(blob),
@@ -294,44 +342,51 @@ where
                 // But we do still want to process it so checks can notice this
                 // TODO Can we deduplicate this with the AlreadySeen::NotSeenYet branch below?
                 //      Also, do we want to add the same assertions here that we have below?
-                match loaded {
+                let is_hard_linked = match loaded {
                     Ok(Some(mut blob)) => {
+                        let is_hard_linked = blob.is_hard_linked();
                         let result = checks.process_reachable_blob_again(
                             BlobToProcess::Readable(&blob),
                             &blob_referenced_as.referenced_as,
                         );
                         blob.async_drop().await?;
                         result?;
+                        is_hard_linked
                     }
                     Ok(None) => {
                         // do nothing
+                        false
                     }
                     Err(error) => {
                         checks.process_reachable_blob_again(
                             BlobToProcess::<B>::Unreadable(blob_referenced_as.blob_id),
                             &blob_referenced_as.referenced_as,
                         )?;
+                        false
                     }
-                }
+                };
 
                 // The blob was already seen before. This can only happen if the blob is referenced multiple times.
-                // Let's assert that our checks find that.
-                checks.add_assertion(Assertion::error_matching_predicate_was_reported(
-                    move |error| match error {
-                        CorruptedError::BlobReferencedMultipleTimes(
-                            BlobReferencedMultipleTimesError {
-                                blob_id: reported_blob_id,
-                                referenced_as: reported_referenced_as,
-                                ..
-                            },
-                        ) => {
-                            *reported_blob_id == blob_referenced_as.blob_id
-                                && reported_referenced_as.contains(&prev_seen.0)
-                                && reported_referenced_as.contains(&now_seen.0)
-                        }
-                        _ => false,
-                    },
-                ));
+                // Let's assert that our checks find that. Hard linked blobs are expected to be referenced multiple times,
+                // the link count check takes care of them.
+                if !is_hard_linked {
+                    checks.add_assertion(Assertion::error_matching_predicate_was_reported(
+                        move |error| match error {
+                            CorruptedError::BlobReferencedMultipleTimes(
+                                BlobReferencedMultipleTimesError {
+                                    blob_id: reported_blob_id,
+                                    referenced_as: reported_referenced_as,
+                                    ..
+                                },
+                            ) => {
+                                *reported_blob_id == blob_referenced_as.blob_id
+                                    && reported_referenced_as.contains(&prev_seen.0)
+                                    && reported_referenced_as.contains(&now_seen.0)
+                            }
+                            _ => false,
+                        },
+                    ));
+                }
             }
             AlreadySeen::NotSeenYet => {
                 match loaded {
@@ -450,7 +505,28 @@ where
 {
     match blob.blob_type() {
         BlobType::File | BlobType::Symlink => {
-            // file and symlink blobs don't have child blobs. Nothing to do.
+            // file and symlink blobs don't have child blobs, but hard linked ones reference their link metadata blob
+            if let Some(link_metadata_blob_id) = blob.link_metadata_blob_id() {
+                pb.inc_length(1);
+                task_spawner.spawn(|task_spawner| {
+                    _check_all_reachable_blobs(
+                        blobstore,
+                        all_nodes,
+                        Arc::clone(&already_processed_blobs),
+                        BlobReferenceWithId {
+                            blob_id: link_metadata_blob_id,
+                            referenced_as: BlobReference {
+                                blob_type: BlobType::File,
+                                parent_id: blob.blob_id(),
+                                path: path_of_blob,
+                            },
+                        },
+                        checks,
+                        task_spawner,
+                        pb.clone(),
+                    )
+                });
+            }
         }
         BlobType::Dir => {
             // Get all directory entry and recurse into their blobs, concurrently.
@@ -468,9 +544,17 @@ where
 
             // TODO If we manage to prioritize directory blobs over file blobs in the processing, then the progress bar max would be correct much more quickly.
             //      We could for example do it with two task queues, one for directory blobs and one for file/symlink blobs, and then chain them together.
-            pb.inc_length(blob.entries().len() as u64);
+            let children: Vec<_> = blob
+                .loaded_entries()
+                .expect("Entries were loaded by load_blob_with_entries")
+                .filter_map(|entry| {
+                    // Fifos, sockets and devices aren't backed by a blob and don't need to be checked
+                    entry_type_to_blob_type(entry.entry_type()).map(|blob_type| (entry, blob_type))
+                })
+                .collect();
+            pb.inc_length(children.len() as u64);
 
-            for entry in blob.entries() {
+            for (entry, blob_type) in children {
                 task_spawner.spawn(|task_spawner| {
                     _check_all_reachable_blobs(
                         blobstore,
@@ -479,7 +563,7 @@ where
                         BlobReferenceWithId {
                             blob_id: *entry.blob_id(),
                             referenced_as: BlobReference {
-                                blob_type: entry_type_to_blob_type(entry.entry_type()),
+                                blob_type,
                                 parent_id: blob.blob_id(),
                                 path: path_of_blob.join(entry.name()),
                             },
@@ -495,11 +579,15 @@ where
     Ok(())
 }
 
-fn entry_type_to_blob_type(entry_type: EntryType) -> BlobType {
+/// Returns `None` for fifos, sockets and devices because they aren't backed by a blob
+fn entry_type_to_blob_type(entry_type: EntryType) -> Option<BlobType> {
     match entry_type {
-        EntryType::File => BlobType::File,
-        EntryType::Dir => BlobType::Dir,
-        EntryType::Symlink => BlobType::Symlink,
+        EntryType::File => Some(BlobType::File),
+        EntryType::Dir => Some(BlobType::Dir),
+        EntryType::Symlink => Some(BlobType::Symlink),
+        EntryType::Fifo | EntryType::Socket | EntryType::CharDevice | EntryType::BlockDevice => {
+            None
+        }
     }
 }
 
@@ -597,6 +685,13 @@ where
             }
 
             // The node was already seen before. This can only happen if the node is referenced multiple times.
+            // Nodes shared between blobs are expected to be referenced multiple times, the unreferenced nodes check
+            // decides whether that's an error by comparing the number of references with the node's reference count.
+            if let Ok(Some(node)) = &node
+                && node.num_extra_references() > 0
+            {
+                return Ok(());
+            }
             let current_node_referenced_as = current_node_referenced_as.into();
             checks.add_assertion(Assertion::error_matching_predicate_was_reported(
                 move |error| match error {
@@ -720,8 +815,11 @@ fn check_all_children_of_reachable_blob_node<'a, 'b, 'c, 'd, 'f, B>(
             // Leaf nodes don't have children. Nothing to do.
         }
         DataNode::Inner(node) => {
-            // Get all children and recurse into their nodes, concurrently.
-            for child_id in node.children() {
+            // Get all children and recurse into their nodes, concurrently. Holes in sparse blobs don't have nodes.
+            for child_id in node
+                .children()
+                .filter(|child_id| *child_id != HOLE_BLOCK_ID)
+            {
                 task_spawner.spawn(|task_spawner| {
                     let child_expected_node_info =
                         if let Some(child_depth) = NonZeroU8::new(node.depth().get() - 1) {
@@ -800,7 +898,10 @@ where
         DataNode::Leaf(_) => SeenNodeInfo::Leaf,
         DataNode::Inner(node) => SeenNodeInfo::Inner {
             depth: node.depth(),
-            children: node.children().collect(),
+            children: node
+                .children()
+                .filter(|child_id| *child_id != HOLE_BLOCK_ID)
+                .collect(),
         },
     }
 }
@@ -852,6 +953,36 @@ impl SeenBlobInfo {
     }
 }
 
+/// Load a blob and, if it is a directory blob, all of its entries. Checks look at directory entries without having
+/// access to the blob, so they need to be loaded upfront. A directory blob whose entries can't be loaded is unreadable.
+async fn load_blob_with_entries<B>(
+    blobstore: &FsBlobStore<BlobStoreOnBlocks<B>>,
+    blob_id: &BlobId,
+) -> Result<Option<AsyncDropGuard<FsBlob<BlobStoreOnBlocks<B>>>>>
+where
+    B: BlockStore<Block: Send + Sync>
+        + AsyncDrop<Error = anyhow::Error>
+        + Debug
+        + Send
+        + Sync
+        + 'static,
+{
+    let Some(mut blob) = blobstore.load(blob_id).await? else {
+        return Ok(None);
+    };
+    let result = match &mut *blob {
+        FsBlob::Directory(dir) => dir.load_all_entries().await,
+        FsBlob::File(_) | FsBlob::Symlink(_) => Ok(()),
+    };
+    match result {
+        Ok(()) => Ok(Some(blob)),
+        Err(error) => {
+            blob.async_drop().await?;
+            Err(error)
+        }
+    }
+}
+
 fn blob_content_summary<B>(blob: &FsBlob<B>) -> SeenBlobInfo
 where
     // TODO Do we really need B: 'static ?
@@ -867,7 +998,11 @@ where
         },
         FsBlob::Directory(blob) => SeenBlobInfo::Dir {
             parent_pointer: blob.parent(),
-            children: blob.entries().map(|entry| *entry.blob_id()).collect(),
+            children: blob
+                .loaded_entries()
+                .expect("Entries were loaded by load_blob_with_entries")
+                .map(|entry| *entry.blob_id())
+                .collect(),
         },
     }
 }
@@ -17,7 +17,10 @@ use cryfs_utils::testutils::asserts::assert_unordered_vec_eq;
 
 mod common;
 
-use common::{entry_helpers::SomeBlobs, fixture::FilesystemFixture};
+use common::{
+    entry_helpers::{SomeBlobs, expect_nodes_to_be_unreferenced},
+    fixture::{CorruptInnerNodeResult, FilesystemFixture},
+};
 
 fn make_file(
     fs_fixture: &FilesystemFixture,
@@ -200,9 +203,12 @@ async fn blob_referenced_multiple_times(
             (node_info, blob_info)
         }
         BlobStatus::Unreadable => {
-            fs_fixture
+            let CorruptInnerNodeResult { orphaned_nodes, .. } = fs_fixture
                 .corrupt_root_node_of_blob(blob_info.clone())
                 .await;
+            // Depending on the block size, even small blobs can have an inner root node
+            expected_errors
+                .extend(expect_nodes_to_be_unreferenced(&fs_fixture, orphaned_nodes).await);
             expected_errors.extend(
                 [
                     BlobUnreadableError {
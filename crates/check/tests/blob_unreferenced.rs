@@ -35,23 +35,30 @@ fn data(size: usize, seed: u64) -> Data {
     DataFixture::new(seed).get(size).into()
 }
 
-fn make_single_node_file_blob(
+fn make_small_file_blob(
     fs_fixture: &FilesystemFixture,
 ) -> BoxFuture<'_, (BlobId, NodeInfoAsSeenByLookingAtNode)> {
     Box::pin(async move {
-        fs_fixture
+        let blob_id = fs_fixture
             .update_fsblobstore(|fsblobstore| {
                 Box::pin(async move {
                     let mut blob = fsblobstore
-                        .create_file_blob(&parent_id(), FlushBehavior::DontFlush)
+                        .create_file_blob(
+                            &BlobId::new_random(),
+                            &parent_id(),
+                            FlushBehavior::DontFlush,
+                        )
                         .await
                         .unwrap();
                     let blob_id = blob.blob_id();
                     blob.async_drop().await.unwrap();
-                    (blob_id, NodeInfoAsSeenByLookingAtNode::LeafNode)
+                    blob_id
                 })
             })
-            .await
+            .await;
+        // With the link count header, even a small blob can need more than one node
+        let root_node_info = fs_fixture.load_node_info(*blob_id.to_root_block_id()).await;
+        (blob_id, root_node_info)
     })
 }
 
@@ -63,7 +70,11 @@ fn make_large_file_blob(
             .update_fsblobstore(|fsblobstore| {
                 Box::pin(async move {
                     let mut blob = fsblobstore
-                        .create_file_blob(&parent_id(), FlushBehavior::DontFlush)
+                        .create_file_blob(
+                            &BlobId::new_random(),
+                            &parent_id(),
+                            FlushBehavior::DontFlush,
+                        )
                         .await
                         .unwrap();
                     let file = blob.as_file_mut().unwrap();
@@ -100,7 +111,11 @@ fn make_single_node_dir_blob(
             .update_fsblobstore(|fsblobstore| {
                 Box::pin(async move {
                     let mut dir_blob = fsblobstore
-                        .create_dir_blob(&parent_id(), FlushBehavior::DontFlush)
+                        .create_dir_blob(
+                            &BlobId::new_random(),
+                            &parent_id(),
+                            FlushBehavior::DontFlush,
+                        )
                         .await
                         .unwrap();
                     let blob_id = dir_blob.blob_id();
@@ -120,11 +135,15 @@ fn make_large_dir_blob(
             .update_fsblobstore(|fsblobstore| {
                 Box::pin(async move {
                     let mut blob = fsblobstore
-                        .create_dir_blob(&parent_id(), FlushBehavior::DontFlush)
+                        .create_dir_blob(
+                            &BlobId::new_random(),
+                            &parent_id(),
+                            FlushBehavior::DontFlush,
+                        )
                         .await
                         .unwrap();
                     let dir_blob = blob.as_dir_mut().unwrap();
-                    for i in 0..400 {
+                    for i in 0..200 {
                         dir_blob
                             .add_entry_symlink(
                                 format!("symlink_{i}").try_into().unwrap(),
@@ -134,6 +153,7 @@ fn make_large_dir_blob(
                                 SystemTime::now(),
                                 SystemTime::now(),
                             )
+                            .await
                             .unwrap();
                     }
                     assert!(
@@ -166,7 +186,11 @@ fn make_dir_blob_with_children(
             .update_fsblobstore(|fsblobstore| {
                 Box::pin(async move {
                     let dir_blob = fsblobstore
-                        .create_dir_blob(&parent_id(), FlushBehavior::DontFlush)
+                        .create_dir_blob(
+                            &BlobId::new_random(),
+                            &parent_id(),
+                            FlushBehavior::DontFlush,
+                        )
                         .await
                         .unwrap();
                     let mut dir_blob = CreatedDirBlob::new(
@@ -202,23 +226,31 @@ fn make_dir_blob_with_children(
     })
 }
 
-fn make_single_node_symlink_blob(
+fn make_small_symlink_blob(
     fs_fixture: &FilesystemFixture,
 ) -> BoxFuture<'_, (BlobId, NodeInfoAsSeenByLookingAtNode)> {
     Box::pin(async move {
-        fs_fixture
+        let blob_id = fs_fixture
             .update_fsblobstore(|fsblobstore| {
                 Box::pin(async move {
                     let mut blob = fsblobstore
-                        .create_symlink_blob(&parent_id(), "target", FlushBehavior::DontFlush)
+                        .create_symlink_blob(
+                            &BlobId::new_random(),
+                            &parent_id(),
+                            "target",
+                            FlushBehavior::DontFlush,
+                        )
                         .await
                         .unwrap();
                     let blob_id = blob.blob_id();
                     blob.async_drop().await.unwrap();
-                    (blob_id, NodeInfoAsSeenByLookingAtNode::LeafNode)
+                    blob_id
                 })
             })
-            .await
+            .await;
+        // With the link count header, even a small blob can need more than one node
+        let root_node_info = fs_fixture.load_node_info(*blob_id.to_root_block_id()).await;
+        (blob_id, root_node_info)
     })
 }
 
@@ -231,6 +263,7 @@ fn make_large_symlink_blob(
                 Box::pin(async move {
                     let mut blob = fsblobstore
                         .create_symlink_blob(
+                            &BlobId::new_random(),
                             &parent_id(),
                             &common::entry_helpers::large_symlink_target(),
                             FlushBehavior::DontFlush,
@@ -264,11 +297,11 @@ fn make_large_symlink_blob(
 #[tokio::test(flavor = "multi_thread")]
 async fn blob_unreferenced(
     #[values(
-        make_single_node_file_blob,
+        make_small_file_blob,
         make_large_file_blob,
         make_single_node_dir_blob,
         make_large_dir_blob,
-        make_single_node_symlink_blob,
+        make_small_symlink_blob,
         make_large_symlink_blob
     )]
     make_blob: impl for<'a> FnOnce(
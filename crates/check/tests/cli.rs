@@ -0,0 +1,212 @@
+//! Tests running the cryfs-check binary
+
+use assert_cmd::Command;
+use byte_unit::Byte;
+use serde_json::Value;
+use std::process::Output;
+use tempfile::TempDir;
+
+use cryfs_blobstore::{BlobId, BlobStoreOnBlocks};
+use cryfs_blockstore::{
+    AllowIntegrityViolations, FsyncPolicy, IntegrityConfig, MissingBlockIsIntegrityViolation,
+    OnDiskBlockStore,
+};
+use cryfs_cli_utils::{CliErrorKind, FilesystemLock, setup_blockstore_stack_dyn};
+use cryfs_config::{
+    config::{CommandLineFlags, FilesystemId, FixedPasswordProvider},
+    localstate::LocalStateDir,
+};
+use cryfs_fsblobstore::fsblobstore::{FlushBehavior, FsBlobStore, FsFormat};
+use cryfs_utils::progress::SilentProgressBarManager;
+
+mod common;
+use common::console::FixtureCreationConsole;
+use common::fixture::PASSWORD;
+
+/// A file system stored on disk like a real vault, so that the cryfs-check binary can run on it.
+/// The [common::fixture::FilesystemFixture] keeps its blocks in memory and uses blocks that
+/// are too small to be stored on disk.
+struct Vault {
+    tempdir: TempDir,
+    filesystem_id: FilesystemId,
+}
+
+impl Vault {
+    /// Creates a file system with an empty root directory. If `with_unreferenced_blob` is set,
+    /// it also gets a blob that isn't referenced from any directory.
+    async fn create(with_unreferenced_blob: bool) -> Self {
+        let tempdir = tempfile::Builder::new()
+            .prefix("cryfs-check-cli")
+            .tempdir()
+            .unwrap();
+        let vaultdir = tempdir.path().join("vault");
+        std::fs::create_dir(&vaultdir).unwrap();
+        std::fs::create_dir(tempdir.path().join("local_state_dir")).unwrap();
+        let local_state_dir = LocalStateDir::new(tempdir.path().join("local_state_dir"));
+
+        let config = cryfs_config::config::create(
+            vaultdir.join("cryfs.config"),
+            &FixedPasswordProvider::new(PASSWORD.to_owned()),
+            &FixtureCreationConsole,
+            &CommandLineFlags {
+                missing_block_is_integrity_violation: Some(false),
+                expected_cipher: None,
+                blocksize: Some(Byte::from_u64(4096)),
+                kdf: None,
+                compression: None,
+                extended_format: true,
+            },
+            &local_state_dir,
+            true,
+            SilentProgressBarManager,
+        )
+        .unwrap();
+        let vault = Self {
+            tempdir,
+            filesystem_id: config.config.config().filesystem_id,
+        };
+        let blockstore = setup_blockstore_stack_dyn(
+            OnDiskBlockStore::new(vault.vaultdir(), FsyncPolicy::Never),
+            config.config.config(),
+            config.my_client_id,
+            &local_state_dir,
+            IntegrityConfig {
+                allow_integrity_violations: AllowIntegrityViolations::DontAllowViolations,
+                missing_block_is_integrity_violation:
+                    MissingBlockIsIntegrityViolation::IsAViolation,
+                on_integrity_violation: Box::new(|_err| {
+                    panic!("integrity violation");
+                }),
+                on_block_changed_by_other_client: Box::new(|_| {}),
+            },
+        )
+        .await
+        .unwrap();
+        let blobstore = BlobStoreOnBlocks::new(blockstore, config.config.config().blocksize)
+            .await
+            .unwrap();
+        let mut fsblobstore = FsBlobStore::new(blobstore, FsFormat::Extended);
+
+        let root_blob_id = BlobId::from_hex(&config.config.config().root_blob).unwrap();
+        let mut root = fsblobstore
+            .create_root_dir_blob(&root_blob_id)
+            .await
+            .unwrap();
+        root.async_drop().await.unwrap();
+        if with_unreferenced_blob {
+            let mut blob = fsblobstore
+                .create_file_blob(
+                    &BlobId::new_random(),
+                    &root_blob_id,
+                    FlushBehavior::DontFlush,
+                )
+                .await
+                .unwrap();
+            blob.async_drop().await.unwrap();
+        }
+        fsblobstore.async_drop().await.unwrap();
+
+        vault
+    }
+
+    fn vaultdir(&self) -> std::path::PathBuf {
+        self.tempdir.path().join("vault")
+    }
+
+    fn local_state_dir(&self) -> std::path::PathBuf {
+        self.tempdir.path().join("local_state_dir")
+    }
+
+    fn local_state(&self) -> LocalStateDir {
+        LocalStateDir::new(self.local_state_dir())
+    }
+
+    /// Runs cryfs-check with the given arguments, entering the password on stdin
+    fn run_cryfs_check(&self, args: &[&str]) -> Output {
+        Command::new(assert_cmd::cargo::cargo_bin!("cryfs-check"))
+            .arg(self.vaultdir())
+            .args(args)
+            .arg("--noninteractive")
+            .env("CRYFS_LOCAL_STATE_DIR", self.local_state_dir())
+            .env("CRYFS_NO_UPDATE_CHECK", "true")
+            .write_stdin(format!("{PASSWORD}\n"))
+            .output()
+            .unwrap()
+    }
+
+    /// Runs cryfs-check with `--output json` and returns the parsed JSON report and the exit code
+    fn run_cryfs_check_with_json_output(&self) -> (Value, i32) {
+        let output = self.run_cryfs_check(&["--output", "json"]);
+        let report: Value = serde_json::from_slice(&output.stdout).unwrap_or_else(|err| {
+            panic!(
+                "stdout isn't a JSON document: {err}\nstdout:\n{}\nstderr:\n{}",
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr),
+            )
+        });
+        (report, output.status.code().unwrap())
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn json_output_for_valid_filesystem() {
+    let vault = Vault::create(false).await;
+
+    let (report, exit_code) = vault.run_cryfs_check_with_json_output();
+
+    assert_eq!(0, exit_code);
+    assert_eq!(Some(&Vec::new()), report["errors"].as_array());
+    assert_eq!(0, report["summary"]["num_errors"]);
+    assert_eq!("Success", report["exit_status"]["kind"]);
+    assert_eq!(0, report["exit_status"]["code"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn json_output_for_filesystem_with_errors() {
+    let vault = Vault::create(true).await;
+
+    let (report, exit_code) = vault.run_cryfs_check_with_json_output();
+
+    let errors = report["errors"].as_array().unwrap();
+    assert!(!errors.is_empty());
+    assert_eq!(errors.len(), report["summary"]["num_errors"]);
+    assert_eq!("InvalidFilesystem", report["exit_status"]["kind"]);
+    assert_eq!(exit_code, report["exit_status"]["code"]);
+    assert_ne!(0, exit_code);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn check_after_repair_finds_no_errors() {
+    let vault = Vault::create(true).await;
+    let (report, _) = vault.run_cryfs_check_with_json_output();
+    assert!(!report["errors"].as_array().unwrap().is_empty());
+
+    let output = vault.run_cryfs_check(&["--repair", "--apply"]);
+    assert!(
+        output.status.success(),
+        "repair failed:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let (report, exit_code) = vault.run_cryfs_check_with_json_output();
+    assert_eq!(Some(&Vec::new()), report["errors"].as_array());
+    assert_eq!(0, exit_code);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn repair_refuses_to_run_while_filesystem_is_in_use() {
+    let vault = Vault::create(true).await;
+
+    // A mounted file system holds this lock
+    let lock = FilesystemLock::try_acquire(&vault.local_state(), &vault.filesystem_id).unwrap();
+    let output = vault.run_cryfs_check(&["--repair", "--apply"]);
+    assert_eq!(
+        i32::from(CliErrorKind::FilesystemInUse.exit_code_number()),
+        output.status.code().unwrap()
+    );
+    drop(lock);
+
+    // Nothing was repaired
+    let (report, _) = vault.run_cryfs_check_with_json_output();
+    assert!(!report["errors"].as_array().unwrap().is_empty());
+}
@@ -3,7 +3,7 @@ use byte_unit::Byte;
 use std::path::Path;
 
 use cryfs_config::config::Console;
-use cryfs_crypto::kdf::scrypt::ScryptSettings;
+use cryfs_crypto::kdf::{argon2id::Argon2idSettings, scrypt::ScryptSettings};
 use cryfs_version::{Version, VersionInfo};
 
 pub struct FixtureCreationConsole;
@@ -38,6 +38,10 @@ impl Console for FixtureCreationConsole {
         Ok(ScryptSettings::TEST)
     }
 
+    fn ask_argon2id_settings_for_new_filesystem(&self) -> Result<Argon2idSettings> {
+        Ok(Argon2idSettings::TEST)
+    }
+
     fn ask_cipher_for_new_filesystem(&self) -> Result<String> {
         Ok("aes-256-gcm".to_owned())
     }
@@ -9,7 +9,7 @@ use rand::{SeedableRng, rngs::SmallRng, seq::IteratorRandom};
 use std::fmt::Debug;
 use std::time::SystemTime;
 
-use crate::FilesystemFixture;
+use super::fixture::FilesystemFixture;
 use cryfs_blobstore::{
     BlobId, BlobStore, DataInnerNode, DataLeafNode, DataNode, DataNodeStore, DataTree,
 };
@@ -18,7 +18,7 @@ use cryfs_check::{
     BlobReference, BlobReferenceWithId, CorruptedError, NodeInfoAsSeenByLookingAtNode,
     NodeUnreferencedError,
 };
-use cryfs_fsblobstore::fsblobstore::{BlobType, FlushBehavior};
+use cryfs_fsblobstore::fsblobstore::{BlobType, EntryType, FlushBehavior};
 use cryfs_fsblobstore::{
     Gid, Mode, Uid,
     fsblobstore::{DirBlob, FileBlob, FsBlob, FsBlobStore, SymlinkBlob},
@@ -254,14 +254,18 @@ where
 {
     let mut parent_dir = parent.blob.as_dir_mut().unwrap();
     let new_entry = fsblobstore
-        .create_dir_blob(&parent_dir.blob_id(), FlushBehavior::DontFlush)
+        .create_dir_blob(
+            &BlobId::new_random(),
+            &parent_dir.blob_id(),
+            FlushBehavior::DontFlush,
+        )
         .await
         .unwrap();
-    add_dir_entry(&mut parent_dir, name, new_entry.blob_id());
+    add_dir_entry(&mut parent_dir, name, new_entry.blob_id()).await;
     CreatedDirBlob::new(new_entry, parent.path.join(name.try_into().unwrap()))
 }
 
-pub fn add_dir_entry<B>(parent: &mut DirBlob<B>, name: &str, blob_id: BlobId)
+pub async fn add_dir_entry<B>(parent: &mut DirBlob<B>, name: &str, blob_id: BlobId)
 where
     B: BlobStore + Debug + AsyncDrop<Error = anyhow::Error> + Send + 'static,
     <B as BlobStore>::ConcreteBlob: AsyncDrop<Error = anyhow::Error>,
@@ -276,6 +280,7 @@ where
             SystemTime::now(),
             SystemTime::now(),
         )
+        .await
         .unwrap();
 }
 
@@ -290,14 +295,18 @@ where
 {
     let mut parent_dir = parent.blob.as_dir_mut().unwrap();
     let new_entry = fsblobstore
-        .create_file_blob(&parent_dir.blob_id(), FlushBehavior::DontFlush)
+        .create_file_blob(
+            &BlobId::new_random(),
+            &parent_dir.blob_id(),
+            FlushBehavior::DontFlush,
+        )
         .await
         .unwrap();
-    add_file_entry(&mut parent_dir, name, new_entry.blob_id());
+    add_file_entry(&mut parent_dir, name, new_entry.blob_id()).await;
     CreatedFileBlob::new(new_entry, parent.path.join(name.try_into().unwrap()))
 }
 
-pub fn add_file_entry<B>(parent: &mut DirBlob<B>, name: &str, blob_id: BlobId)
+pub async fn add_file_entry<B>(parent: &mut DirBlob<B>, name: &str, blob_id: BlobId)
 where
     B: BlobStore + Debug + AsyncDrop<Error = anyhow::Error> + Send + 'static,
     <B as BlobStore>::ConcreteBlob: AsyncDrop<Error = anyhow::Error>,
@@ -312,6 +321,7 @@ where
             SystemTime::now(),
             SystemTime::now(),
         )
+        .await
         .unwrap();
 }
 
@@ -327,14 +337,19 @@ where
 {
     let mut parent_dir = parent.blob.as_dir_mut().unwrap();
     let new_entry = fsblobstore
-        .create_symlink_blob(&parent_dir.blob_id(), target, FlushBehavior::DontFlush)
+        .create_symlink_blob(
+            &BlobId::new_random(),
+            &parent_dir.blob_id(),
+            target,
+            FlushBehavior::DontFlush,
+        )
         .await
         .unwrap();
-    add_symlink_entry(&mut parent_dir, name, new_entry.blob_id());
+    add_symlink_entry(&mut parent_dir, name, new_entry.blob_id()).await;
     CreatedSymlinkBlob::new(new_entry, parent.path.join(name.try_into().unwrap()))
 }
 
-pub fn add_symlink_entry<B>(parent: &mut DirBlob<B>, name: &str, blob_id: BlobId)
+pub async fn add_symlink_entry<B>(parent: &mut DirBlob<B>, name: &str, blob_id: BlobId)
 where
     B: BlobStore + Debug + AsyncDrop<Error = anyhow::Error> + Send + 'static,
     <B as BlobStore>::ConcreteBlob: AsyncDrop<Error = anyhow::Error>,
@@ -348,6 +363,42 @@ where
             SystemTime::now(),
             SystemTime::now(),
         )
+        .await
+        .unwrap();
+}
+
+pub async fn add_special_entry<B>(
+    parent: &mut DirBlob<B>,
+    name: &str,
+    id: BlobId,
+    entry_type: EntryType,
+    rdev: u32,
+) where
+    B: BlobStore + Debug + AsyncDrop<Error = anyhow::Error> + Send + 'static,
+    <B as BlobStore>::ConcreteBlob: AsyncDrop<Error = anyhow::Error>,
+{
+    let mode = match entry_type {
+        EntryType::Fifo => Mode::zero().with_fifo_flag(),
+        EntryType::Socket => Mode::zero().with_socket_flag(),
+        EntryType::CharDevice => Mode::zero().with_char_device_flag(),
+        EntryType::BlockDevice => Mode::zero().with_block_device_flag(),
+        EntryType::File | EntryType::Dir | EntryType::Symlink => {
+            panic!("{entry_type:?} isn't a special entry type")
+        }
+    };
+    parent
+        .add_entry_special(
+            name.to_string().try_into().unwrap(),
+            id,
+            entry_type,
+            mode.with_user_read_flag().with_user_write_flag(),
+            Uid::from(1000),
+            Gid::from(1000),
+            rdev,
+            SystemTime::now(),
+            SystemTime::now(),
+        )
+        .await
         .unwrap();
 }
 
@@ -412,7 +463,7 @@ pub async fn add_entries_to_make_dir_large<B>(
     B: BlobStore + Debug + AsyncDrop<Error = anyhow::Error> + Send,
     <B as BlobStore>::ConcreteBlob: AsyncDrop<Error = anyhow::Error>,
 {
-    for i in 0..125 {
+    for i in 0..80 {
         create_empty_dir(fsblobstore, dir, &format!("dir{i}"))
             .await
             .async_drop()
@@ -944,15 +995,17 @@ where
 {
     Box::pin(
         async move {
-            let blob = fsblobstore.load(&dir_blob_id).await.unwrap().unwrap();
+            let mut blob = fsblobstore.load(&dir_blob_id).await.unwrap().unwrap();
             let (children, dir_children) = with_async_drop_2!(blob, {
-                let blob = blob.as_dir().expect("Expected a directory blob");
+                let blob = blob.as_dir_mut().expect("Expected a directory blob");
                 let children = blob
                     .entries()
+                    .await?
                     .map(|entry| *entry.blob_id())
                     .collect::<Vec<_>>();
                 let dir_children = blob
                     .entries()
+                    .await?
                     .filter(|entry| entry.mode().has_dir_flag())
                     .map(|entry| *entry.blob_id())
                     .collect::<Vec<_>>();
@@ -983,15 +1036,17 @@ where
 {
     Box::pin(
         async move {
-            let blob = fsblobstore.load(&maybe_dir_blob_id).await.unwrap().unwrap();
+            let mut blob = fsblobstore.load(&maybe_dir_blob_id).await.unwrap().unwrap();
             with_async_drop_2!(blob, {
-                if let Ok(blob) = blob.as_dir() {
+                if let Ok(blob) = blob.as_dir_mut() {
                     let children = blob
                         .entries()
+                        .await?
                         .map(|entry| *entry.blob_id())
                         .collect::<Vec<_>>();
                     let dir_children = blob
                         .entries()
+                        .await?
                         .filter(|entry| entry.mode().has_dir_flag())
                         .map(|entry| *entry.blob_id())
                         .collect::<Vec<_>>();
@@ -7,14 +7,17 @@ use cryfs_blockstore::{
 use cryfs_check::{
     BlobReference, BlobReferenceWithId, CorruptedError, MaybeBlobReferenceWithId,
     NodeAndBlobReference, NodeAndBlobReferenceFromReachableBlob, NodeInfoAsSeenByLookingAtNode,
-    NodeReference,
+    NodeReference, RepairPlan,
 };
 use cryfs_cli_utils::setup_blockstore_stack_dyn;
 use cryfs_config::{
     config::{CommandLineFlags, ConfigLoadResult, FixedPasswordProvider},
     localstate::LocalStateDir,
 };
-use cryfs_fsblobstore::fsblobstore::{BlobType, FsBlob, FsBlobStore};
+use cryfs_fsblobstore::{
+    Gid, Uid,
+    fsblobstore::{BlobType, EntryType, FlushBehavior, FsBlob, FsBlobStore, FsFormat},
+};
 use cryfs_utils::path::AbsolutePathBuf;
 use cryfs_utils::{
     async_drop::{AsyncDropGuard, SyncDrop},
@@ -36,7 +39,7 @@ use super::entry_helpers::{
     find_leaf_node_with_parent_id,
 };
 
-const PASSWORD: &str = "mypassword";
+pub const PASSWORD: &str = "mypassword";
 
 pub struct FilesystemFixture {
     root_blob_id: BlobId,
@@ -92,6 +95,7 @@ impl FilesystemFixture {
                 on_integrity_violation: Box::new(|_err| {
                     panic!("integrity violation");
                 }),
+                on_block_changed_by_other_client: Box::new(|_| {}),
             },
         )
         .await
@@ -123,7 +127,7 @@ impl FilesystemFixture {
     ) -> AsyncDropGuard<FsBlobStore<BlobStoreOnBlocks<LockingBlockStore<DynBlockStore>>>> {
         let blobstore = self.make_blobstore().await;
 
-        FsBlobStore::new(blobstore)
+        FsBlobStore::new(blobstore, FsFormat::Extended)
     }
 
     pub async fn update_blockstore<'s, 'b, 'f, F, R>(
@@ -186,6 +190,44 @@ impl FilesystemFixture {
         .expect("Failed to run cryfs-check")
     }
 
+    /// Run cryfs-check and plan repairs for the errors it found, without applying them
+    pub async fn plan_cryfs_repair(&self) -> RepairPlan {
+        let local_state_dir = self.tempdir.local_state_dir();
+        let errors = cryfs_check::check_loaded_filesystem(
+            LLSharedBlockStore::clone(&self.blockstore),
+            &self.config,
+            &local_state_dir,
+            SilentProgressBarManager,
+        )
+        .await
+        .expect("Failed to run cryfs-check");
+        cryfs_check::plan_repair(
+            LLSharedBlockStore::clone(&self.blockstore),
+            &self.config,
+            &local_state_dir,
+            &errors,
+        )
+        .await
+        .expect("Failed to plan repair")
+    }
+
+    /// Run cryfs-check, then plan and apply repairs for the errors it found
+    pub async fn run_cryfs_repair(&self) -> RepairPlan {
+        let plan = self.plan_cryfs_repair().await;
+        cryfs_check::apply_repair(
+            LLSharedBlockStore::clone(&self.blockstore),
+            &self.config,
+            &self.tempdir.local_state_dir(),
+            &plan,
+            Uid::from(1000),
+            Gid::from(1000),
+            SilentProgressBarManager,
+        )
+        .await
+        .expect("Failed to apply repair");
+        plan
+    }
+
     pub fn root_blob_id(&self) -> BlobId {
         self.root_blob_id
     }
@@ -313,7 +355,7 @@ impl FilesystemFixture {
                 let mut parent = blobstore.load(&parent).await.unwrap().unwrap();
                 with_async_drop_2!(parent, {
                     let mut parent = parent.as_dir_mut().unwrap();
-                    super::entry_helpers::add_file_entry(&mut parent, &name, blob_id);
+                    super::entry_helpers::add_file_entry(&mut parent, &name, blob_id).await;
                     Ok::<_, anyhow::Error>(())
                 })
                 .unwrap()
@@ -329,7 +371,7 @@ impl FilesystemFixture {
                 let mut parent = blobstore.load(&parent).await.unwrap().unwrap();
                 with_async_drop_2!(parent, {
                     let mut parent = parent.as_dir_mut().unwrap();
-                    super::entry_helpers::add_dir_entry(&mut parent, &name, blob_id);
+                    super::entry_helpers::add_dir_entry(&mut parent, &name, blob_id).await;
                     Ok::<_, anyhow::Error>(())
                 })
                 .unwrap()
@@ -345,7 +387,37 @@ impl FilesystemFixture {
                 let mut parent = blobstore.load(&parent).await.unwrap().unwrap();
                 with_async_drop_2!(parent, {
                     let mut parent = parent.as_dir_mut().unwrap();
-                    super::entry_helpers::add_symlink_entry(&mut parent, &name, blob_id);
+                    super::entry_helpers::add_symlink_entry(&mut parent, &name, blob_id).await;
+                    Ok::<_, anyhow::Error>(())
+                })
+                .unwrap()
+            })
+        })
+        .await;
+    }
+
+    /// Add a fifo, socket or device entry to the directory. Those aren't backed by a blob.
+    pub async fn add_special_entry_to_dir(
+        &self,
+        parent: BlobId,
+        name: &str,
+        entry_type: EntryType,
+        rdev: u32,
+    ) {
+        let name = name.to_owned();
+        self.update_fsblobstore(move |blobstore| {
+            Box::pin(async move {
+                let mut parent = blobstore.load(&parent).await.unwrap().unwrap();
+                with_async_drop_2!(parent, {
+                    let mut parent = parent.as_dir_mut().unwrap();
+                    super::entry_helpers::add_special_entry(
+                        &mut parent,
+                        &name,
+                        BlobId::new_random(),
+                        entry_type,
+                        rdev,
+                    )
+                    .await;
                     Ok::<_, anyhow::Error>(())
                 })
                 .unwrap()
@@ -354,14 +426,74 @@ impl FilesystemFixture {
         .await;
     }
 
+    /// If the blob wasn't hard linked before, this also creates its link metadata blob
+    pub async fn increment_num_links_of_blob(&self, blob_id: BlobId) {
+        self.update_fsblobstore(move |blobstore| {
+            Box::pin(async move {
+                let mut blob = blobstore.load(&blob_id).await.unwrap().unwrap();
+                with_async_drop_2!(blob, {
+                    if blob.is_hard_linked() {
+                        blob.increment_num_links().await?;
+                    } else {
+                        let link_metadata_blob_id = BlobId::new_random();
+                        let mut link_metadata_blob = blobstore
+                            .create_file_blob(
+                                &link_metadata_blob_id,
+                                &blob_id,
+                                FlushBehavior::FlushImmediately,
+                            )
+                            .await?;
+                        link_metadata_blob.async_drop().await?;
+                        blob.add_first_hard_link(&link_metadata_blob_id).await?;
+                    }
+                    Ok::<_, anyhow::Error>(())
+                })
+                .unwrap()
+            })
+        })
+        .await;
+    }
+
+    pub async fn remove_entry_from_dir(&self, parent: BlobId, blob_id: BlobId) {
+        self.update_fsblobstore(move |blobstore| {
+            Box::pin(async move {
+                let mut parent = blobstore.load(&parent).await.unwrap().unwrap();
+                with_async_drop_2!(parent, {
+                    parent
+                        .as_dir_mut()
+                        .unwrap()
+                        .remove_entry_by_id_if_exists(&blob_id)
+                        .await?;
+                    Ok::<_, anyhow::Error>(())
+                })
+                .unwrap()
+            })
+        })
+        .await;
+    }
+
+    /// Load the blob with the given id and return its parent pointer
+    pub async fn load_parent_pointer(&self, blob_id: BlobId) -> BlobId {
+        self.update_fsblobstore(move |blobstore| {
+            Box::pin(async move {
+                let mut blob = blobstore.load(&blob_id).await.unwrap().unwrap();
+                let parent = blob.parent();
+                blob.async_drop().await.unwrap();
+                parent
+            })
+        })
+        .await
+    }
+
     pub async fn get_children_of_dir_blob(&self, dir_blob: BlobId) -> Vec<BlobId> {
         self.update_fsblobstore(|fsblobstore| {
             Box::pin(async move {
-                let blob = fsblobstore.load(&dir_blob).await.unwrap().unwrap();
+                let mut blob = fsblobstore.load(&dir_blob).await.unwrap().unwrap();
                 with_async_drop_2!(blob, {
-                    let blob = blob.as_dir().unwrap();
+                    let blob = blob.as_dir_mut().unwrap();
                     Ok::<_, anyhow::Error>(
                         blob.entries()
+                            .await?
                             .map(|entry| *entry.blob_id())
                             .collect::<Vec<_>>(),
                     )
@@ -1147,6 +1279,9 @@ impl FixtureTempDir {
                 missing_block_is_integrity_violation: Some(false),
                 expected_cipher: None,
                 blocksize: None,
+                kdf: None,
+                compression: None,
+                extended_format: true,
             },
             &self.local_state_dir(),
             true,
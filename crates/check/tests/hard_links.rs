@@ -0,0 +1,217 @@
+//! Tests where files or symlinks are hard linked, i.e. referenced from multiple directory entries
+
+use futures::future::BoxFuture;
+use rstest::rstest;
+
+use cryfs_check::{BlobReference, BlobReferenceWithId, CorruptedError, WrongLinkCountError};
+use cryfs_fsblobstore::fsblobstore::BlobType;
+use cryfs_utils::testutils::asserts::assert_unordered_vec_eq;
+
+mod common;
+
+use common::{entry_helpers::SomeBlobs, fixture::FilesystemFixture};
+
+fn make_file(
+    fs_fixture: &FilesystemFixture,
+    parent: BlobReferenceWithId,
+) -> BoxFuture<'_, BlobReferenceWithId> {
+    Box::pin(async move {
+        fs_fixture
+            .create_empty_file_in_parent(parent, "my_filename")
+            .await
+    })
+}
+
+fn make_symlink(
+    fs_fixture: &FilesystemFixture,
+    parent: BlobReferenceWithId,
+) -> BoxFuture<'_, BlobReferenceWithId> {
+    Box::pin(async move {
+        fs_fixture
+            .create_symlink_in_parent(parent, "my_symlink", "target")
+            .await
+    })
+}
+
+fn same_dir(some_blobs: &SomeBlobs) -> (BlobReferenceWithId, BlobReferenceWithId) {
+    (
+        some_blobs.large_dir_1.clone(),
+        some_blobs.large_dir_1.clone(),
+    )
+}
+
+fn different_dirs(some_blobs: &SomeBlobs) -> (BlobReferenceWithId, BlobReferenceWithId) {
+    (
+        some_blobs.large_dir_1.clone(),
+        some_blobs.large_dir_2.clone(),
+    )
+}
+
+async fn add_link(
+    fs_fixture: &FilesystemFixture,
+    parent: &BlobReferenceWithId,
+    blob_info: &BlobReferenceWithId,
+    name: &str,
+) -> BlobReference {
+    match blob_info.referenced_as.blob_type {
+        BlobType::File => {
+            fs_fixture
+                .add_file_entry_to_dir(parent.blob_id, name, blob_info.blob_id)
+                .await
+        }
+        BlobType::Symlink => {
+            fs_fixture
+                .add_symlink_entry_to_dir(parent.blob_id, name, blob_info.blob_id)
+                .await
+        }
+        BlobType::Dir => panic!("Directories can't be hard linked"),
+    }
+    BlobReference {
+        blob_type: blob_info.referenced_as.blob_type,
+        parent_id: parent.blob_id,
+        path: parent.referenced_as.path.join(name.try_into().unwrap()),
+    }
+}
+
+#[rstest]
+#[tokio::test(flavor = "multi_thread")]
+async fn hard_link_with_correct_link_count(
+    #[values(same_dir, different_dirs)] parents: impl FnOnce(
+        &SomeBlobs,
+    ) -> (
+        BlobReferenceWithId,
+        BlobReferenceWithId,
+    ),
+    #[values(make_file, make_symlink)] make_blob: impl for<'a> FnOnce(
+        &'a FilesystemFixture,
+        BlobReferenceWithId,
+    ) -> BoxFuture<
+        'a,
+        BlobReferenceWithId,
+    >,
+) {
+    let (fs_fixture, some_blobs) = FilesystemFixture::new_with_some_blobs().await;
+    let (parent1_info, parent2_info) = parents(&some_blobs);
+
+    let blob_info = make_blob(&fs_fixture, parent1_info).await;
+    fs_fixture
+        .increment_num_links_of_blob(blob_info.blob_id)
+        .await;
+    add_link(&fs_fixture, &parent2_info, &blob_info, "my_link").await;
+
+    let errors = fs_fixture.run_cryfs_check().await;
+    assert_eq!(Vec::<CorruptedError>::new(), errors);
+}
+
+#[rstest]
+#[tokio::test(flavor = "multi_thread")]
+async fn link_count_higher_than_number_of_references(
+    #[values(same_dir, different_dirs)] parents: impl FnOnce(
+        &SomeBlobs,
+    ) -> (
+        BlobReferenceWithId,
+        BlobReferenceWithId,
+    ),
+    #[values(make_file, make_symlink)] make_blob: impl for<'a> FnOnce(
+        &'a FilesystemFixture,
+        BlobReferenceWithId,
+    ) -> BoxFuture<
+        'a,
+        BlobReferenceWithId,
+    >,
+) {
+    let (fs_fixture, some_blobs) = FilesystemFixture::new_with_some_blobs().await;
+    let (parent1_info, parent2_info) = parents(&some_blobs);
+
+    let blob_info = make_blob(&fs_fixture, parent1_info.clone()).await;
+    fs_fixture
+        .increment_num_links_of_blob(blob_info.blob_id)
+        .await;
+    fs_fixture
+        .increment_num_links_of_blob(blob_info.blob_id)
+        .await;
+    let link = add_link(&fs_fixture, &parent2_info, &blob_info, "my_link").await;
+
+    let expected_errors = vec![
+        WrongLinkCountError {
+            blob_id: blob_info.blob_id,
+            blob_type: blob_info.referenced_as.blob_type,
+            parent_pointer: parent1_info.blob_id,
+            num_links: 3,
+            referenced_as: [blob_info.referenced_as.clone(), link]
+                .into_iter()
+                .collect(),
+        }
+        .into(),
+    ];
+
+    let errors = fs_fixture.run_cryfs_check().await;
+    assert_unordered_vec_eq(expected_errors, errors);
+}
+
+#[rstest]
+#[tokio::test(flavor = "multi_thread")]
+async fn link_count_lower_than_number_of_references(
+    #[values(same_dir, different_dirs)] parents: impl FnOnce(
+        &SomeBlobs,
+    ) -> (
+        BlobReferenceWithId,
+        BlobReferenceWithId,
+    ),
+    #[values(make_file, make_symlink)] make_blob: impl for<'a> FnOnce(
+        &'a FilesystemFixture,
+        BlobReferenceWithId,
+    ) -> BoxFuture<
+        'a,
+        BlobReferenceWithId,
+    >,
+) {
+    let (fs_fixture, some_blobs) = FilesystemFixture::new_with_some_blobs().await;
+    let (parent1_info, parent2_info) = parents(&some_blobs);
+
+    let blob_info = make_blob(&fs_fixture, parent1_info.clone()).await;
+    fs_fixture
+        .increment_num_links_of_blob(blob_info.blob_id)
+        .await;
+    let link1 = add_link(&fs_fixture, &parent2_info, &blob_info, "my_link1").await;
+    let link2 = add_link(&fs_fixture, &parent2_info, &blob_info, "my_link2").await;
+
+    let expected_errors = vec![
+        WrongLinkCountError {
+            blob_id: blob_info.blob_id,
+            blob_type: blob_info.referenced_as.blob_type,
+            parent_pointer: parent1_info.blob_id,
+            num_links: 2,
+            referenced_as: [blob_info.referenced_as.clone(), link1, link2]
+                .into_iter()
+                .collect(),
+        }
+        .into(),
+    ];
+
+    let errors = fs_fixture.run_cryfs_check().await;
+    assert_unordered_vec_eq(expected_errors, errors);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn link_count_set_but_only_referenced_once() {
+    let (fs_fixture, some_blobs) = FilesystemFixture::new_with_some_blobs().await;
+
+    // A blob whose hard links were removed again still has a link count of 1, this is valid.
+    let blob_info = make_file(&fs_fixture, some_blobs.large_dir_1.clone()).await;
+    fs_fixture
+        .increment_num_links_of_blob(blob_info.blob_id)
+        .await;
+    fs_fixture
+        .update_fsblobstore(move |blobstore| {
+            Box::pin(async move {
+                let mut blob = blobstore.load(&blob_info.blob_id).await.unwrap().unwrap();
+                blob.decrement_num_links().await.unwrap();
+                blob.async_drop().await.unwrap();
+            })
+        })
+        .await;
+
+    let errors = fs_fixture.run_cryfs_check().await;
+    assert_eq!(Vec::<CorruptedError>::new(), errors);
+}
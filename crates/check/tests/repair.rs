@@ -0,0 +1,314 @@
+//! Tests for `cryfs-check --repair`
+
+use std::collections::BTreeSet;
+
+use cryfs_blobstore::BlobId;
+use cryfs_check::{
+    BlobReferenceWithId, CorruptedError, LOST_AND_FOUND_DIR_NAME, NodeAndBlobReference,
+    NodeMissingError, RepairAction, RepairPlan,
+};
+use cryfs_fsblobstore::fsblobstore::{BlobType, FlushBehavior};
+use cryfs_utils::path::PathComponent;
+
+mod common;
+use common::fixture::{FilesystemFixture, RemoveInnerNodeResult, RemoveLeafNodeResult};
+
+async fn set_parent(fs_fixture: &FilesystemFixture, blob_id: BlobId, new_parent: BlobId) {
+    fs_fixture
+        .update_fsblobstore(move |blobstore| {
+            Box::pin(async move {
+                let mut blob = blobstore.load(&blob_id).await.unwrap().unwrap();
+                blob.set_parent(&new_parent).await.unwrap();
+                blob.async_drop().await.unwrap();
+            })
+        })
+        .await;
+}
+
+/// Returns the blobs in the lost+found directory, or None if there is no lost+found directory
+async fn lost_and_found_entries(fs_fixture: &FilesystemFixture) -> Option<BTreeSet<BlobId>> {
+    let root_id = fs_fixture.root_blob_id();
+    let lost_and_found_id = fs_fixture
+        .update_fsblobstore(move |blobstore| {
+            Box::pin(async move {
+                let mut root = blobstore.load(&root_id).await.unwrap().unwrap();
+                let lost_and_found_id = root
+                    .as_dir_mut()
+                    .unwrap()
+                    .entry_by_name(PathComponent::try_from_str(LOST_AND_FOUND_DIR_NAME).unwrap())
+                    .await
+                    .unwrap()
+                    .map(|entry| *entry.blob_id());
+                root.async_drop().await.unwrap();
+                lost_and_found_id
+            })
+        })
+        .await?;
+    Some(
+        fs_fixture
+            .get_children_of_dir_blob(lost_and_found_id)
+            .await
+            .into_iter()
+            .collect(),
+    )
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn valid_filesystem() {
+    let (fs_fixture, _some_blobs) = FilesystemFixture::new_with_some_blobs().await;
+
+    let plan = fs_fixture.run_cryfs_repair().await;
+    assert_eq!(
+        RepairPlan {
+            actions: vec![],
+            unfixable: vec![],
+        },
+        plan
+    );
+    assert!(plan.is_empty());
+    assert_eq!(None, lost_and_found_entries(&fs_fixture).await);
+
+    assert_eq!(
+        Vec::<CorruptedError>::new(),
+        fs_fixture.run_cryfs_check().await
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unreferenced_file_is_moved_to_lost_and_found() {
+    let (fs_fixture, _some_blobs) = FilesystemFixture::new_with_some_blobs().await;
+    let file = fs_fixture.create_empty_file().await;
+    fs_fixture
+        .remove_entry_from_dir(fs_fixture.root_blob_id(), file.blob_id)
+        .await;
+
+    let plan = fs_fixture.run_cryfs_repair().await;
+    assert_eq!(
+        vec![RepairAction::MoveToLostAndFound {
+            blob_id: file.blob_id,
+            blob_type: BlobType::File,
+        }],
+        plan.actions
+    );
+    assert_eq!(Vec::<CorruptedError>::new(), plan.unfixable);
+    assert_eq!(
+        Some([file.blob_id].into_iter().collect()),
+        lost_and_found_entries(&fs_fixture).await
+    );
+
+    assert_eq!(
+        Vec::<CorruptedError>::new(),
+        fs_fixture.run_cryfs_check().await
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unreferenced_dir_is_moved_to_lost_and_found_with_its_children() {
+    let (fs_fixture, some_blobs) = FilesystemFixture::new_with_some_blobs().await;
+    fs_fixture
+        .remove_entry_from_dir(some_blobs.root.blob_id, some_blobs.dir2.blob_id)
+        .await;
+
+    let plan = fs_fixture.run_cryfs_repair().await;
+    assert_eq!(
+        vec![RepairAction::MoveToLostAndFound {
+            blob_id: some_blobs.dir2.blob_id,
+            blob_type: BlobType::Dir,
+        }],
+        plan.actions
+    );
+    assert_eq!(Vec::<CorruptedError>::new(), plan.unfixable);
+    assert_eq!(
+        Some([some_blobs.dir2.blob_id].into_iter().collect()),
+        lost_and_found_entries(&fs_fixture).await
+    );
+
+    assert_eq!(
+        Vec::<CorruptedError>::new(),
+        fs_fixture.run_cryfs_check().await
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unreferenced_blob_with_nonexisting_parent_is_moved_to_lost_and_found() {
+    let (fs_fixture, _some_blobs) = FilesystemFixture::new_with_some_blobs().await;
+    let orphaned_dir_id = fs_fixture
+        .update_fsblobstore(|blobstore| {
+            Box::pin(async move {
+                let mut blob = blobstore
+                    .create_dir_blob(
+                        &BlobId::new_random(),
+                        &BlobId::new_random(),
+                        FlushBehavior::DontFlush,
+                    )
+                    .await
+                    .unwrap();
+                let blob_id = blob.blob_id();
+                blob.async_drop().await.unwrap();
+                blob_id
+            })
+        })
+        .await;
+
+    let plan = fs_fixture.run_cryfs_repair().await;
+    assert_eq!(
+        vec![RepairAction::MoveToLostAndFound {
+            blob_id: orphaned_dir_id,
+            blob_type: BlobType::Dir,
+        }],
+        plan.actions
+    );
+    assert_eq!(
+        Some([orphaned_dir_id].into_iter().collect()),
+        lost_and_found_entries(&fs_fixture).await
+    );
+
+    assert_eq!(
+        Vec::<CorruptedError>::new(),
+        fs_fixture.run_cryfs_check().await
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn wrong_parent_pointer_is_fixed() {
+    let (fs_fixture, some_blobs) = FilesystemFixture::new_with_some_blobs().await;
+    set_parent(
+        &fs_fixture,
+        some_blobs.dir1_dir3.blob_id,
+        some_blobs.dir2.blob_id,
+    )
+    .await;
+
+    let plan = fs_fixture.run_cryfs_repair().await;
+    assert_eq!(
+        vec![RepairAction::FixParentPointer {
+            blob_id: some_blobs.dir1_dir3.blob_id,
+            blob_type: BlobType::Dir,
+            old_parent: some_blobs.dir2.blob_id,
+            new_parent: some_blobs.dir1.blob_id,
+        }],
+        plan.actions
+    );
+    assert_eq!(Vec::<CorruptedError>::new(), plan.unfixable);
+    assert_eq!(
+        some_blobs.dir1.blob_id,
+        fs_fixture
+            .load_parent_pointer(some_blobs.dir1_dir3.blob_id)
+            .await
+    );
+
+    assert_eq!(
+        Vec::<CorruptedError>::new(),
+        fs_fixture.run_cryfs_check().await
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn missing_leaf_node_is_replaced() {
+    let (fs_fixture, some_blobs) = FilesystemFixture::new_with_some_blobs().await;
+    let RemoveLeafNodeResult {
+        removed_node,
+        removed_node_info: _,
+    } = fs_fixture
+        .remove_a_leaf_node(some_blobs.large_file_1.clone())
+        .await;
+
+    let plan = fs_fixture.run_cryfs_repair().await;
+    assert_eq!(
+        vec![RepairAction::ReplaceMissingLeaf {
+            node_id: removed_node,
+        }],
+        plan.actions
+    );
+    assert_eq!(Vec::<CorruptedError>::new(), plan.unfixable);
+
+    assert_eq!(
+        Vec::<CorruptedError>::new(),
+        fs_fixture.run_cryfs_check().await
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dry_run_doesnt_modify_the_filesystem() {
+    let (fs_fixture, some_blobs) = FilesystemFixture::new_with_some_blobs().await;
+    let file = fs_fixture.create_empty_file().await;
+    fs_fixture
+        .remove_entry_from_dir(fs_fixture.root_blob_id(), file.blob_id)
+        .await;
+    set_parent(
+        &fs_fixture,
+        some_blobs.dir1_dir3.blob_id,
+        some_blobs.dir2.blob_id,
+    )
+    .await;
+
+    let plan = fs_fixture.plan_cryfs_repair().await;
+    assert_eq!(2, plan.actions.len());
+    assert_eq!(None, lost_and_found_entries(&fs_fixture).await);
+    assert_eq!(
+        some_blobs.dir2.blob_id,
+        fs_fixture
+            .load_parent_pointer(some_blobs.dir1_dir3.blob_id)
+            .await
+    );
+
+    assert_eq!(2, fs_fixture.run_cryfs_check().await.len());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unfixable_errors_are_kept_and_orphaned_nodes_are_salvaged_or_removed() {
+    let (fs_fixture, some_blobs) = FilesystemFixture::new_with_some_blobs().await;
+    let RemoveInnerNodeResult {
+        removed_node,
+        removed_node_info: _,
+        orphaned_nodes,
+    } = fs_fixture
+        .remove_root_node_of_blob(some_blobs.large_file_1.clone())
+        .await;
+
+    let plan = fs_fixture.run_cryfs_repair().await;
+    // The leftmost subtree starts with the blob header, so it looks like a (truncated) blob and is
+    // salvaged. The other subtrees are just data and get removed.
+    let (salvaged_subtree, removed_subtrees) = orphaned_nodes.split_first().unwrap();
+    let mut removed_subtree_roots = BTreeSet::new();
+    let mut moved_blobs = Vec::new();
+    for action in &plan.actions {
+        match action {
+            RepairAction::RemoveUnreferencedNodes { root_node_id, .. } => {
+                removed_subtree_roots.insert(*root_node_id);
+            }
+            RepairAction::MoveToLostAndFound { blob_id, blob_type } => {
+                moved_blobs.push((*blob_id, *blob_type));
+            }
+            action => panic!("Unexpected action {action:?}"),
+        }
+    }
+    assert_eq!(
+        removed_subtrees.iter().copied().collect::<BTreeSet<_>>(),
+        removed_subtree_roots
+    );
+    assert_eq!(
+        vec![(
+            BlobId::from_root_block_id(*salvaged_subtree),
+            BlobType::File
+        )],
+        moved_blobs
+    );
+    let expected_unfixable: Vec<CorruptedError> = vec![
+        NodeMissingError {
+            node_id: removed_node,
+            referenced_as: [NodeAndBlobReference::RootNode {
+                belongs_to_blob: BlobReferenceWithId {
+                    blob_id: some_blobs.large_file_1.blob_id,
+                    referenced_as: some_blobs.large_file_1.referenced_as.clone(),
+                },
+            }]
+            .into_iter()
+            .collect(),
+        }
+        .into(),
+    ];
+    assert_eq!(expected_unfixable, plan.unfixable);
+
+    assert_eq!(expected_unfixable, fs_fixture.run_cryfs_check().await);
+}
@@ -0,0 +1,85 @@
+//! Tests where files share nodes because a range was cloned from one file into another
+
+use cryfs_blobstore::BlobId;
+use cryfs_check::CorruptedError;
+
+mod common;
+
+use common::{
+    entry_helpers::{LARGE_FILE_SIZE, data},
+    fixture::FilesystemFixture,
+};
+
+async fn write_file(fs_fixture: &FilesystemFixture, blob_id: BlobId) {
+    fs_fixture
+        .update_fsblobstore(move |blobstore| {
+            Box::pin(async move {
+                let mut blob = blobstore.load(&blob_id).await.unwrap().unwrap();
+                blob.as_file_mut()
+                    .unwrap()
+                    .write(&data(LARGE_FILE_SIZE, 0), 0)
+                    .await
+                    .unwrap();
+                blob.async_drop().await.unwrap();
+            })
+        })
+        .await
+}
+
+async fn clone_file(fs_fixture: &FilesystemFixture, source_id: BlobId, dest_id: BlobId) {
+    fs_fixture
+        .update_fsblobstore(move |blobstore| {
+            Box::pin(async move {
+                let mut source = blobstore.load(&source_id).await.unwrap().unwrap();
+                let mut dest = blobstore.load(&dest_id).await.unwrap().unwrap();
+                let num_bytes = dest
+                    .as_file_mut()
+                    .unwrap()
+                    .clone_range_from(source.as_file_mut().unwrap(), 0, 0, LARGE_FILE_SIZE as u64)
+                    .await
+                    .unwrap();
+                assert_eq!(LARGE_FILE_SIZE as u64, num_bytes);
+                source.async_drop().await.unwrap();
+                dest.async_drop().await.unwrap();
+            })
+        })
+        .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn file_cloned_once() {
+    let (fs_fixture, some_blobs) = FilesystemFixture::new_with_some_blobs().await;
+    let source = fs_fixture
+        .create_empty_file_in_parent(some_blobs.large_dir_1.clone(), "source")
+        .await;
+    let dest = fs_fixture
+        .create_empty_file_in_parent(some_blobs.large_dir_2.clone(), "dest")
+        .await;
+    write_file(&fs_fixture, source.blob_id).await;
+
+    clone_file(&fs_fixture, source.blob_id, dest.blob_id).await;
+
+    let errors = fs_fixture.run_cryfs_check().await;
+    assert_eq!(Vec::<CorruptedError>::new(), errors);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn file_cloned_multiple_times() {
+    let (fs_fixture, some_blobs) = FilesystemFixture::new_with_some_blobs().await;
+    let source = fs_fixture
+        .create_empty_file_in_parent(some_blobs.large_dir_1.clone(), "source")
+        .await;
+    let dest1 = fs_fixture
+        .create_empty_file_in_parent(some_blobs.large_dir_1.clone(), "dest1")
+        .await;
+    let dest2 = fs_fixture
+        .create_empty_file_in_parent(some_blobs.large_dir_2.clone(), "dest2")
+        .await;
+    write_file(&fs_fixture, source.blob_id).await;
+
+    clone_file(&fs_fixture, source.blob_id, dest1.blob_id).await;
+    clone_file(&fs_fixture, dest1.blob_id, dest2.blob_id).await;
+
+    let errors = fs_fixture.run_cryfs_check().await;
+    assert_eq!(Vec::<CorruptedError>::new(), errors);
+}
@@ -0,0 +1,54 @@
+//! Tests where directories contain fifos, sockets or devices. Those entries aren't backed by a blob.
+
+use rstest::rstest;
+
+use cryfs_check::CorruptedError;
+use cryfs_fsblobstore::fsblobstore::EntryType;
+
+mod common;
+
+use common::fixture::FilesystemFixture;
+
+#[rstest]
+#[case::fifo(EntryType::Fifo, 0)]
+#[case::socket(EntryType::Socket, 0)]
+#[case::char_device(EntryType::CharDevice, 0x0103)]
+#[case::block_device(EntryType::BlockDevice, 0x0801)]
+#[tokio::test(flavor = "multi_thread")]
+async fn special_entry_in_root_dir(#[case] entry_type: EntryType, #[case] rdev: u32) {
+    let (fs_fixture, some_blobs) = FilesystemFixture::new_with_some_blobs().await;
+
+    fs_fixture
+        .add_special_entry_to_dir(some_blobs.root.blob_id, "my_special", entry_type, rdev)
+        .await;
+
+    let errors = fs_fixture.run_cryfs_check().await;
+    assert_eq!(Vec::<CorruptedError>::new(), errors);
+}
+
+#[rstest]
+#[case::fifo(EntryType::Fifo, 0)]
+#[case::socket(EntryType::Socket, 0)]
+#[case::char_device(EntryType::CharDevice, 0x0103)]
+#[case::block_device(EntryType::BlockDevice, 0x0801)]
+#[tokio::test(flavor = "multi_thread")]
+async fn special_entries_in_subdirs(#[case] entry_type: EntryType, #[case] rdev: u32) {
+    let (fs_fixture, some_blobs) = FilesystemFixture::new_with_some_blobs().await;
+
+    fs_fixture
+        .add_special_entry_to_dir(some_blobs.dir1_dir3.blob_id, "my_special", entry_type, rdev)
+        .await;
+    for i in 0..10 {
+        fs_fixture
+            .add_special_entry_to_dir(
+                some_blobs.large_dir_1.blob_id,
+                &format!("my_special_{i}"),
+                entry_type,
+                rdev,
+            )
+            .await;
+    }
+
+    let errors = fs_fixture.run_cryfs_check().await;
+    assert_eq!(Vec::<CorruptedError>::new(), errors);
+}
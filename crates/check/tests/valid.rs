@@ -1,6 +1,7 @@
 //! Tests where the filesystem doesn't have errors
 
 use cryfs_check::CorruptedError;
+use cryfs_fsblobstore::fsblobstore::journal_blob_id;
 
 mod common;
 use common::fixture::FilesystemFixture;
@@ -20,3 +21,22 @@ async fn fs_with_some_files_and_directories_and_symlinks() {
     let errors = fs_fixture.run_cryfs_check().await;
     assert_eq!(Vec::<CorruptedError>::new(), errors);
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_with_intent_journal() {
+    let (fs_fixture, _some_blobs) = FilesystemFixture::new_with_some_blobs().await;
+    let journal_blob_id = journal_blob_id(&fs_fixture.root_blob_id());
+    fs_fixture
+        .update_fsblobstore(|fsblobstore| {
+            Box::pin(async move {
+                fsblobstore
+                    .load_or_create_journal(&journal_blob_id)
+                    .await
+                    .unwrap();
+            })
+        })
+        .await;
+
+    let errors = fs_fixture.run_cryfs_check().await;
+    assert_eq!(Vec::<CorruptedError>::new(), errors);
+}
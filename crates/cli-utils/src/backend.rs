@@ -0,0 +1,220 @@
+use anyhow::{Context, Result, ensure};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use cryfs_blockstore::{
+    ClientId, DynBlockStore, FsyncPolicy, IntegrityConfig, LLBlockStore, MirroringBlockStore,
+    OnDiskBlockStore,
+};
+use cryfs_config::{config::CryConfig, localstate::LocalStateDir};
+use cryfs_utils::async_drop::AsyncDropGuard;
+
+use crate::{BlockstoreCallback, CliError, setup_blockstore_stack};
+
+/// Name of the file in the vault directory that describes where the blocks of the vault are stored.
+/// Vaults without this file store their blocks directly in the vault directory, like CryFS 1.x did.
+pub const BACKEND_CONFIG_FILE_NAME: &str = "cryfs.backend";
+
+/// Where a vault stores its blocks. The `cryfs.config` file always stays in the vault directory.
+///
+/// This is chosen when the file system is created and stored unencrypted in [BACKEND_CONFIG_FILE_NAME]
+/// so that the blocks can be found before the config file is decrypted.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BackendConfig {
+    /// One file per block in the vault directory
+    #[default]
+    OnDisk,
+
+    /// A full copy of all blocks in the vault directory and in each of the `replicas` directories, see [MirroringBlockStore]
+    Mirrored { replicas: Vec<PathBuf> },
+}
+
+impl BackendConfig {
+    /// Loads the backend config of the vault in `vaultdir`
+    pub fn load(vaultdir: &Path) -> Result<Self> {
+        let path = vaultdir.join(BACKEND_CONFIG_FILE_NAME);
+        if !path
+            .try_exists()
+            .with_context(|| format!("Failed to check whether {} exists", path.display()))?
+        {
+            return Ok(Self::OnDisk);
+        }
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    /// Stores the backend config in `vaultdir`. Vaults using [BackendConfig::OnDisk] don't get a backend config file
+    /// so that they stay readable by CryFS 1.x.
+    pub fn save(&self, vaultdir: &Path) -> Result<()> {
+        if *self == Self::OnDisk {
+            return Ok(());
+        }
+        let path = vaultdir.join(BACKEND_CONFIG_FILE_NAME);
+        let content = serde_json::to_string_pretty(self)?;
+        std::fs::write(&path, content)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// For [BackendConfig::Mirrored], returns all directories that keep a copy of the blocks, starting with the vault directory
+    pub fn mirror_replica_dirs(&self, vaultdir: &Path) -> Option<Vec<PathBuf>> {
+        match self {
+            Self::Mirrored { replicas } => Some(
+                std::iter::once(vaultdir.to_owned())
+                    .chain(replicas.iter().cloned())
+                    .collect(),
+            ),
+            Self::OnDisk => None,
+        }
+    }
+
+    /// Opens the block store that stores the blocks of the vault in `vaultdir`. This doesn't decrypt anything,
+    /// it's the block store the encryption layer of [setup_blockstore_stack_for_backend] writes to.
+    pub async fn open(
+        &self,
+        vaultdir: &Path,
+        fsync_policy: FsyncPolicy,
+    ) -> Result<BackendBlockStore> {
+        match self {
+            Self::OnDisk => Ok(BackendBlockStore::OnDisk(OnDiskBlockStore::new(
+                vaultdir.to_owned(),
+                fsync_policy,
+            ))),
+            Self::Mirrored { .. } => {
+                let replica_dirs = self
+                    .mirror_replica_dirs(vaultdir)
+                    .expect("We just checked that this is a mirrored backend");
+                let mirror = open_mirror(&replica_dirs, fsync_policy)?;
+                Ok(BackendBlockStore::Dyn(into_dyn(mirror)))
+            }
+        }
+    }
+}
+
+/// Opens a [MirroringBlockStore] that keeps a copy of all blocks in each of `replica_dirs`
+pub fn open_mirror(
+    replica_dirs: &[PathBuf],
+    fsync_policy: FsyncPolicy,
+) -> Result<AsyncDropGuard<MirroringBlockStore<OnDiskBlockStore>>> {
+    ensure!(
+        replica_dirs.len() >= 2,
+        "A mirrored vault needs at least one replica directory besides the vault directory"
+    );
+    for dir in replica_dirs {
+        ensure!(
+            dir.is_dir(),
+            "Replica directory {} doesn't exist or isn't a directory",
+            dir.display()
+        );
+    }
+    Ok(MirroringBlockStore::new(
+        replica_dirs
+            .iter()
+            .map(|dir| OnDiskBlockStore::new(dir.clone(), fsync_policy))
+            .collect(),
+    ))
+}
+
+/// The block store of a vault, as opened by [BackendConfig::open]
+#[derive(Debug)]
+pub enum BackendBlockStore {
+    OnDisk(AsyncDropGuard<OnDiskBlockStore>),
+
+    /// The other backends are type erased so that the block store stack doesn't get instantiated once for each of them
+    Dyn(AsyncDropGuard<DynBlockStore>),
+}
+
+impl BackendBlockStore {
+    pub fn into_dyn(self) -> AsyncDropGuard<DynBlockStore> {
+        match self {
+            Self::OnDisk(blockstore) => into_dyn(blockstore),
+            Self::Dyn(blockstore) => blockstore,
+        }
+    }
+}
+
+fn into_dyn(
+    blockstore: AsyncDropGuard<impl LLBlockStore + Send + Sync + 'static>,
+) -> AsyncDropGuard<DynBlockStore> {
+    let blockstore: Box<dyn LLBlockStore + Send + Sync> =
+        Box::new(blockstore.unsafe_into_inner_dont_drop());
+    AsyncDropGuard::new(DynBlockStore(blockstore))
+}
+
+/// Like [setup_blockstore_stack], but for a block store opened with [BackendConfig::open]
+pub async fn setup_blockstore_stack_for_backend<CB: BlockstoreCallback + Send + Sync>(
+    backend_blockstore: BackendBlockStore,
+    config: &CryConfig,
+    my_client_id: ClientId,
+    local_state_dir: &LocalStateDir,
+    integrity_config: IntegrityConfig,
+    callback: CB,
+) -> Result<CB::Result, CliError> {
+    // Boxing keeps the nesting depth of the future type low enough for the compiler
+    match backend_blockstore {
+        BackendBlockStore::OnDisk(blockstore) => {
+            Box::pin(setup_blockstore_stack(
+                blockstore,
+                config,
+                my_client_id,
+                local_state_dir,
+                integrity_config,
+                callback,
+            ))
+            .await
+        }
+        BackendBlockStore::Dyn(blockstore) => {
+            Box::pin(setup_blockstore_stack(
+                blockstore,
+                config,
+                my_client_id,
+                local_state_dir,
+                integrity_config,
+                callback,
+            ))
+            .await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn vault_without_backend_config_is_on_disk() {
+        let vaultdir = TempDir::new().unwrap();
+        assert_eq!(
+            BackendConfig::OnDisk,
+            BackendConfig::load(vaultdir.path()).unwrap()
+        );
+    }
+
+    #[test]
+    fn on_disk_backend_doesnt_write_a_file() {
+        let vaultdir = TempDir::new().unwrap();
+        BackendConfig::OnDisk.save(vaultdir.path()).unwrap();
+        assert!(!vaultdir.path().join(BACKEND_CONFIG_FILE_NAME).exists());
+    }
+
+    #[test]
+    fn mirrored_backend_roundtrips() {
+        let vaultdir = TempDir::new().unwrap();
+        let config = BackendConfig::Mirrored {
+            replicas: vec![PathBuf::from("/replica/one"), PathBuf::from("/replica/two")],
+        };
+        config.save(vaultdir.path()).unwrap();
+        assert_eq!(config, BackendConfig::load(vaultdir.path()).unwrap());
+        assert_eq!(
+            Some(vec![
+                vaultdir.path().to_owned(),
+                PathBuf::from("/replica/one"),
+                PathBuf::from("/replica/two"),
+            ]),
+            config.mirror_replica_dirs(vaultdir.path())
+        );
+    }
+}
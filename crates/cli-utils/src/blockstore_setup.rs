@@ -1,7 +1,7 @@
 use anyhow::Result;
 
 use cryfs_blockstore::{
-    ClientId, DynBlockStore, EncryptedBlockStore, IntegrityBlockStore,
+    ClientId, CompressingBlockStore, DynBlockStore, EncryptedBlockStore, IntegrityBlockStore,
     IntegrityBlockStoreInitError, IntegrityConfig, LLBlockStore, LockingBlockStore,
     OptimizedBlockStoreWriter,
 };
@@ -25,7 +25,7 @@ pub trait BlockstoreCallback {
     ) -> Self::Result;
 }
 
-/// Set up a blockstore stack (i.e. EncryptedBlockStore, IntegrityBlockStore) using the cipher specified in the config file.
+/// Set up a blockstore stack (i.e. EncryptedBlockStore, CompressingBlockStore, IntegrityBlockStore) using the cipher and compression specified in the config file.
 /// Give it the base blockstore (i.e. OnDiskBlockStore) and it will set up the blockstore stack as needed for a cryfs device.
 pub async fn setup_blockstore_stack<CB: BlockstoreCallback + Send + Sync>(
     base_blockstore: AsyncDropGuard<impl LLBlockStore + OptimizedBlockStoreWriter + Send + Sync>,
@@ -97,42 +97,78 @@ impl<B: LLBlockStore + OptimizedBlockStoreWriter + Send + Sync, CB: BlockstoreCa
                 return Err(err).map_cli_error(|_| CliErrorKind::InvalidFilesystem);
             }
         };
-        let mut encrypted_blockstore = EncryptedBlockStore::new(self.base_blockstore, cipher);
+        let encrypted_blockstore = EncryptedBlockStore::new(self.base_blockstore, cipher);
 
-        let integrity_file_path = self
-            .local_state_dir
-            .for_filesystem_id(&self.config.filesystem_id);
-        let integrity_file_path = match integrity_file_path {
-            Ok(integrity_file_path) => integrity_file_path.join("integritydata"),
-            Err(err) => {
-                encrypted_blockstore
-                    .async_drop()
-                    .await
-                    .map_cli_error(CliErrorKind::UnspecifiedError)?;
-                return Err(err).map_cli_error(CliErrorKind::InaccessibleLocalStateDir);
+        // Compression has to happen before encryption because encrypted data doesn't compress
+        match self.config.compression {
+            None => {
+                _setup_integrity_blockstore(
+                    encrypted_blockstore,
+                    self.config,
+                    self.my_client_id,
+                    self.local_state_dir,
+                    self.integrity_config,
+                    self.callback,
+                )
+                .await
             }
-        };
-        let integrity_blockstore = IntegrityBlockStore::new(
-            encrypted_blockstore,
-            integrity_file_path,
-            self.my_client_id,
-            self.integrity_config,
-        )
-        .await
-        .map_cli_error(|error| match error {
-            IntegrityBlockStoreInitError::IntegrityViolationInPreviousRun { .. } => {
-                CliErrorKind::IntegrityViolationOnPreviousRun
-            }
-            IntegrityBlockStoreInitError::InvalidLocalIntegrityState { .. } => {
-                CliErrorKind::InvalidLocalState
+            Some(algorithm) => {
+                _setup_integrity_blockstore(
+                    CompressingBlockStore::new(encrypted_blockstore, algorithm),
+                    self.config,
+                    self.my_client_id,
+                    self.local_state_dir,
+                    self.integrity_config,
+                    self.callback,
+                )
+                .await
             }
-        })?;
-        let blockstore = LockingBlockStore::new(integrity_blockstore);
-
-        Ok(self.callback.callback(blockstore).await)
+        }
     }
 }
 
+async fn _setup_integrity_blockstore<
+    B: LLBlockStore + OptimizedBlockStoreWriter + Send + Sync,
+    CB: BlockstoreCallback,
+>(
+    mut underlying_blockstore: AsyncDropGuard<B>,
+    config: &CryConfig,
+    my_client_id: ClientId,
+    local_state_dir: &LocalStateDir,
+    integrity_config: IntegrityConfig,
+    callback: CB,
+) -> Result<CB::Result, CliError> {
+    let integrity_file_path = local_state_dir.for_filesystem_id(&config.filesystem_id);
+    let integrity_file_path = match integrity_file_path {
+        Ok(integrity_file_path) => integrity_file_path.join("integritydata"),
+        Err(err) => {
+            underlying_blockstore
+                .async_drop()
+                .await
+                .map_cli_error(CliErrorKind::UnspecifiedError)?;
+            return Err(err).map_cli_error(CliErrorKind::InaccessibleLocalStateDir);
+        }
+    };
+    let integrity_blockstore = IntegrityBlockStore::new(
+        underlying_blockstore,
+        integrity_file_path,
+        my_client_id,
+        integrity_config,
+    )
+    .await
+    .map_cli_error(|error| match error {
+        IntegrityBlockStoreInitError::IntegrityViolationInPreviousRun { .. } => {
+            CliErrorKind::IntegrityViolationOnPreviousRun
+        }
+        IntegrityBlockStoreInitError::InvalidLocalIntegrityState { .. } => {
+            CliErrorKind::InvalidLocalState
+        }
+    })?;
+    let blockstore = LockingBlockStore::new(integrity_blockstore);
+
+    Ok(callback.callback(blockstore).await)
+}
+
 pub async fn setup_blockstore_stack_dyn(
     base_blockstore: AsyncDropGuard<impl LLBlockStore + OptimizedBlockStoreWriter + Send + Sync>,
     config: &CryConfig,
@@ -2,6 +2,7 @@ use byte_unit::{Byte, UnitType};
 use console::{StyledObject, style};
 use std::fmt::Display;
 
+use cryfs_blockstore::CompressionAlgorithm;
 use cryfs_config::config::ConfigLoadResult;
 
 // TODO Integration test the outputs of print_config
@@ -26,6 +27,10 @@ pub fn print_config(config: &ConfigLoadResult) {
         )
     }
 
+    fn format_compression(compression: Option<CompressionAlgorithm>) -> &'static str {
+        compression.map_or("none", |algorithm| algorithm.name())
+    }
+
     fn format_key(name: &str) -> StyledObject<&str> {
         style(name).bold().blue()
     }
@@ -66,6 +71,11 @@ pub fn print_config(config: &ConfigLoadResult) {
         format_bytes(config.old_config.blocksize),
         format_bytes(config.config.config().blocksize),
     );
+    print!("\n  • {} ", format_key("Compression:"));
+    print_value(
+        format_compression(config.old_config.compression),
+        format_compression(config.config.config().compression),
+    );
     print!("\n  • {} ", format_key("Filesystem Id:"));
     print_value(
         config.old_config.filesystem_id.to_hex(),
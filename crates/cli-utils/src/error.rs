@@ -120,12 +120,20 @@ pub enum CliErrorKind {
 
     /// An integrity violation was detected and the file system unmounted to make sure the user notices.
     IntegrityViolation,
+
+    /// The file system is mounted or being modified by another process.
+    FilesystemInUse,
 }
 
 impl CliErrorKind {
     /// Exit code to report to the shell
     pub fn exit_code(&self) -> ExitCode {
-        ExitCode::from(match self {
+        ExitCode::from(self.exit_code_number())
+    }
+
+    /// Exit code to report to the shell, as a number. Useful for machine readable output.
+    pub fn exit_code_number(&self) -> u8 {
+        match self {
             Self::Success => 0,
             Self::UnspecifiedError => 1,
             Self::InvalidArguments => 10,
@@ -147,6 +155,7 @@ impl CliErrorKind {
             Self::InaccessibleLocalStateDir => 26,
             Self::InvalidLocalState => 27,
             Self::WrongBlocksize => 28,
-        })
+            Self::FilesystemInUse => 29,
+        }
     }
 }
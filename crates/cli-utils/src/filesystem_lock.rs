@@ -0,0 +1,120 @@
+use anyhow::{Context, anyhow};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::Path;
+
+use cryfs_config::{config::FilesystemId, localstate::LocalStateDir};
+
+use crate::error::{CliError, CliErrorKind, CliResultExt};
+
+const LOCK_FILE_NAME: &str = "lock";
+
+/// An exclusive lock on a file system, held by processes that modify it, i.e. a mounted file system or `cryfs-check --apply`.
+/// The lock lives in the local state directory, so it only protects against other processes on the same machine.
+/// It is released when this object is dropped or the process exits.
+#[derive(Debug)]
+pub struct FilesystemLock {
+    _file: File,
+}
+
+impl FilesystemLock {
+    /// Acquire the lock or fail with [CliErrorKind::FilesystemInUse] if another process holds it.
+    pub fn try_acquire(
+        local_state_dir: &LocalStateDir,
+        filesystem_id: &FilesystemId,
+    ) -> Result<Self, CliError> {
+        let path = local_state_dir
+            .for_filesystem_id(filesystem_id)
+            .map_cli_error(CliErrorKind::InaccessibleLocalStateDir)?
+            .join(LOCK_FILE_NAME);
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open lock file at {}", path.display()))
+            .map_cli_error(CliErrorKind::InaccessibleLocalStateDir)?;
+        match file.try_lock() {
+            Ok(()) => Ok(Self { _file: file }),
+            Err(TryLockError::WouldBlock) => Err(anyhow!(
+                "The file system is in use by another process. Please unmount it first."
+            ))
+            .map_cli_error(CliErrorKind::FilesystemInUse),
+            Err(TryLockError::Error(err)) => Err(anyhow::Error::from(err))
+                .with_context(|| format!("Failed to lock {}", path.display()))
+                .map_cli_error(CliErrorKind::InaccessibleLocalStateDir),
+        }
+    }
+}
+
+/// Returns true if the file system in `vaultdir` is currently mounted, either by this CryFS version or by an older one.
+/// Unlike [FilesystemLock], this also finds mounts by CryFS versions that don't take the lock.
+/// Only implemented on Linux, on other platforms this always returns false.
+pub fn is_mounted(vaultdir: &Path) -> anyhow::Result<bool> {
+    #[cfg(target_os = "linux")]
+    {
+        let mounts = std::fs::read_to_string("/proc/self/mounts")
+            .context("Failed to read /proc/self/mounts")?;
+        let vaultdir = vaultdir
+            .canonicalize()
+            .unwrap_or_else(|_| vaultdir.to_owned());
+        let source = format!("cryfs@{}", vaultdir.display());
+        Ok(mounts.lines().any(|line| {
+            line.split(' ')
+                .next()
+                .is_some_and(|mount_source| unescape_mount_field(mount_source) == source)
+        }))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = vaultdir;
+        Ok(false)
+    }
+}
+
+/// /proc/self/mounts escapes space, tab, newline and backslash as octal sequences
+#[cfg(target_os = "linux")]
+fn unescape_mount_field(field: &str) -> String {
+    field
+        .replace("\\040", " ")
+        .replace("\\011", "\t")
+        .replace("\\012", "\n")
+        .replace("\\134", "\\")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn lock_is_exclusive() {
+        let local_state_dir = TempDir::new().unwrap();
+        let local_state_dir = LocalStateDir::new(local_state_dir.path().to_owned());
+        let filesystem_id = FilesystemId::new_random();
+
+        let lock = FilesystemLock::try_acquire(&local_state_dir, &filesystem_id).unwrap();
+        let err = FilesystemLock::try_acquire(&local_state_dir, &filesystem_id).unwrap_err();
+        assert_eq!(CliErrorKind::FilesystemInUse, err.kind);
+
+        // A different file system can still be locked
+        FilesystemLock::try_acquire(&local_state_dir, &FilesystemId::new_random()).unwrap();
+
+        drop(lock);
+        FilesystemLock::try_acquire(&local_state_dir, &filesystem_id).unwrap();
+    }
+
+    #[test]
+    fn unmounted_vaultdir_is_not_mounted() {
+        let vaultdir = TempDir::new().unwrap();
+        assert!(!is_mounted(vaultdir.path()).unwrap());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn unescapes_mount_fields() {
+        assert_eq!(
+            "cryfs@/path with space\\",
+            unescape_mount_field("cryfs@/path\\040with\\040space\\134"),
+        );
+    }
+}
@@ -23,11 +23,20 @@ pub use config::print_config;
 mod error;
 pub use error::{CliError, CliErrorKind, CliResultExt, CliResultExtFn};
 
+mod filesystem_lock;
+pub use filesystem_lock::{FilesystemLock, is_mounted};
+
 mod blockstore_setup;
 pub use blockstore_setup::{
     BlockstoreCallback, setup_blockstore_stack, setup_blockstore_stack_dyn,
 };
 
+mod backend;
+pub use backend::{
+    BACKEND_CONFIG_FILE_NAME, BackendBlockStore, BackendConfig, open_mirror,
+    setup_blockstore_stack_for_backend,
+};
+
 pub use clap_logflag;
 
 cryfs_version::assert_cargo_version_equals_git_version!();
@@ -1,24 +1,27 @@
 use anyhow::{Result, ensure};
 use console::style;
+use std::io::IsTerminal;
 
 use cryfs_config::config::PasswordProvider;
 
 // TODO Protect password similar to how we protect EncryptionKey (mprotect, zero on drop, ...). The rpassword crate actually has an internal class `SafeString` but then they extract it from that before returning :(
 
+/// Asks for the password on the terminal. Everything besides the prompt goes to stderr
+/// so that stdout stays free for the program's output.
 pub struct InteractivePasswordProvider;
 
 impl PasswordProvider for InteractivePasswordProvider {
     fn password_for_existing_filesystem(&self) -> Result<String> {
         // TODO Check how this flow looks like when actually running
         loop {
-            println!();
+            eprintln!();
             let password = ask_password_from_console("Password: ")?;
             match check_password(&password) {
                 Ok(()) => {
                     return Ok(password);
                 }
                 Err(err) => {
-                    println!("Error: {}", err);
+                    eprintln!("Error: {}", err);
                     continue;
                 }
             }
@@ -28,20 +31,20 @@ impl PasswordProvider for InteractivePasswordProvider {
     fn password_for_new_filesystem(&self) -> Result<String> {
         // TODO Check how this flow looks like when actually running
         loop {
-            println!();
+            eprintln!();
             let password = ask_password_from_console("Password: ")?;
             match check_password(&password) {
                 Ok(()) => {
                     let confirm_password = ask_password_from_console("Confirm Password: ")?;
                     if password != confirm_password {
                         // TODO Error message formatting (e.g. colorization), here and above
-                        println!("Passwords do not match. Please try again.");
+                        eprintln!("Passwords do not match. Please try again.");
                         continue;
                     }
                     return Ok(password);
                 }
                 Err(err) => {
-                    println!("Error: {}", err);
+                    eprintln!("Error: {}", err);
                     continue;
                 }
             }
@@ -49,22 +52,37 @@ impl PasswordProvider for InteractivePasswordProvider {
     }
 }
 
+/// Asks for the password only once and without confirmation. If stdin isn't a terminal,
+/// e.g. because a script pipes the password in, it is read from stdin without a prompt.
 pub struct NoninteractivePasswordProvider;
 
 impl PasswordProvider for NoninteractivePasswordProvider {
     fn password_for_existing_filesystem(&self) -> Result<String> {
-        let password = ask_password_from_console("Password: ")?;
+        let password = ask_password_noninteractive()?;
         check_password(&password)?;
         Ok(password)
     }
 
     fn password_for_new_filesystem(&self) -> Result<String> {
-        let password = ask_password_from_console("Password: ")?;
+        let password = ask_password_noninteractive()?;
         check_password(&password)?;
         Ok(password)
     }
 }
 
+fn ask_password_noninteractive() -> Result<String> {
+    let stdin = std::io::stdin();
+    if stdin.is_terminal() {
+        ask_password_from_console("Password: ")
+    } else {
+        let config = rpassword::ConfigBuilder::new()
+            .input_reader(stdin)
+            .output_discard()
+            .build();
+        Ok(rpassword::read_password_with_config(config)?)
+    }
+}
+
 fn ask_password_from_console(prompt: &str) -> Result<String> {
     let indent = "  ";
     let prompt = format!("{indent}{prompt}");
@@ -0,0 +1,48 @@
+use anyhow::{Result, ensure};
+use clap::ValueEnum;
+use std::path::PathBuf;
+
+use cryfs_cli_utils::BackendConfig;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum BackendOption {
+    /// Store each block in its own file in the vault directory.
+    #[default]
+    Ondisk,
+
+    /// Keep a full copy of all blocks in the vault directory and in each `--replica` directory.
+    /// Reads use the fastest replica and repair replicas that are missing a block.
+    Mirrored,
+}
+
+impl BackendOption {
+    pub fn to_backend_config(self, replicas: &[PathBuf]) -> Result<BackendConfig> {
+        match self {
+            Self::Ondisk => {
+                ensure!(
+                    replicas.is_empty(),
+                    "--replica can only be used with --backend mirrored"
+                );
+                Ok(BackendConfig::OnDisk)
+            }
+            Self::Mirrored => {
+                ensure!(
+                    !replicas.is_empty(),
+                    "--backend mirrored needs at least one --replica directory"
+                );
+                Ok(BackendConfig::Mirrored {
+                    replicas: replicas.to_vec(),
+                })
+            }
+        }
+    }
+
+    /// Returns true if an existing file system with the backend config `config` uses this backend
+    pub fn matches(self, config: &BackendConfig) -> bool {
+        matches!(
+            (self, config),
+            (Self::Ondisk, BackendConfig::OnDisk)
+                | (Self::Mirrored, BackendConfig::Mirrored { .. })
+        )
+    }
+}
@@ -0,0 +1,103 @@
+use clap::{Args, Subcommand};
+use cryfs_cli_utils::parse_path;
+use std::path::PathBuf;
+
+use super::kdf_option::KdfOption;
+
+#[derive(Subcommand, Debug)]
+pub enum CryfsCommand {
+    /// Manage the passwords that can unlock a file system.
+    /// Each key slot allows unlocking the file system with a different password.
+    /// Adding or removing key slots doesn't re-encrypt the file system.
+    #[command(subcommand)]
+    KeySlot(KeySlotCommand),
+
+    /// Change the password of a file system. If the file system has multiple key slots,
+    /// this changes the password of the key slot whose password is entered to unlock it.
+    /// This doesn't re-encrypt the file system. The old config file is kept as a backup until the new one was written successfully.
+    Passwd {
+        #[command(flatten)]
+        vault: VaultArgs,
+
+        /// The key derivation function used to derive a key from the new password.
+        /// Default: the key derivation function the old password used
+        #[arg(long, value_enum)]
+        kdf: Option<KdfOption>,
+    },
+
+    /// Manage the replicas of a file system created with `--backend mirrored`.
+    #[command(subcommand)]
+    Mirror(MirrorCommand),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum KeySlotCommand {
+    /// List the key slots of a file system.
+    List(VaultArgs),
+
+    /// Add a key slot that allows unlocking the file system with a new password.
+    Add {
+        #[command(flatten)]
+        vault: VaultArgs,
+
+        /// The key derivation function used to derive a key from the new password.
+        /// Default: scrypt
+        #[arg(long, value_enum)]
+        kdf: Option<KdfOption>,
+    },
+
+    /// Remove a key slot so that its password can't unlock the file system anymore.
+    /// The key slot whose password is entered to unlock the file system can't be removed.
+    Remove {
+        #[command(flatten)]
+        vault: VaultArgs,
+
+        /// The number of the key slot to remove, as shown by `cryfs key-slot list`.
+        slot: usize,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum MirrorCommand {
+    /// Bring a replica up to date with another one, e.g. after it was offline while the file system was mounted.
+    /// Blocks missing in the target replica get copied and blocks that only exist in the target replica get removed.
+    /// The file system must not be mounted while this runs. This works on the encrypted blocks and doesn't need the password.
+    Resync {
+        /// The directory containing the encrypted vault.
+        #[arg(value_parser=parse_path)]
+        vaultdir: PathBuf,
+
+        /// The replica to bring up to date. This can also be the vault directory itself.
+        #[arg(value_parser=parse_path)]
+        target: PathBuf,
+
+        /// The replica to copy blocks from.
+        /// Default: the vault directory, or the first other replica if the target is the vault directory.
+        #[arg(long, value_parser=parse_path)]
+        source: Option<PathBuf>,
+
+        /// Also compare the blocks that exist in both replicas. This reads all blocks,
+        /// but catches blocks that were changed while the target replica was offline.
+        #[arg(long)]
+        compare_contents: bool,
+    },
+}
+
+#[derive(Args, Debug)]
+pub struct VaultArgs {
+    /// The directory containing the encrypted vault.
+    #[arg(value_parser=parse_path)]
+    pub vaultdir: PathBuf,
+
+    /// Configuration file. By default, this is a `cryfs.config` file in the vault directory but you can use this option to specify a file outside of the vault directory.
+    #[arg(short, long, value_parser=parse_path)]
+    pub config: Option<PathBuf>,
+}
+
+impl VaultArgs {
+    pub fn config_file_location(&self) -> PathBuf {
+        self.config
+            .clone()
+            .unwrap_or_else(|| self.vaultdir.join("cryfs.config"))
+    }
+}
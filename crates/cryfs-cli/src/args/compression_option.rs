@@ -0,0 +1,26 @@
+use clap::ValueEnum;
+
+use cryfs_blockstore::CompressionAlgorithm;
+
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub enum CompressionOption {
+    /// Don't compress blocks. All blocks have the same size on disk.
+    #[default]
+    None,
+
+    /// Very fast, but doesn't compress as well as zstd.
+    Lz4,
+
+    /// Compresses better than lz4, but is slower.
+    Zstd,
+}
+
+impl From<CompressionOption> for Option<CompressionAlgorithm> {
+    fn from(option: CompressionOption) -> Self {
+        match option {
+            CompressionOption::None => None,
+            CompressionOption::Lz4 => Some(CompressionAlgorithm::Lz4),
+            CompressionOption::Zstd => Some(CompressionAlgorithm::Zstd),
+        }
+    }
+}
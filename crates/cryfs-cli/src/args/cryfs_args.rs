@@ -1,7 +1,7 @@
 use clap::Parser;
 use std::fmt::Write;
 
-use super::MountArgs;
+use super::{CryfsCommand, MountArgs};
 use cryfs_cli_utils::{ENV_VARS_DOCUMENTATION, EnvVarDoc};
 
 fn footer() -> String {
@@ -23,11 +23,14 @@ fn footer() -> String {
 }
 
 #[derive(Parser, Debug)]
-#[command(after_help = footer())]
+#[command(after_help = footer(), subcommand_negates_reqs = true)]
 pub struct CryfsArgs {
     #[command(flatten)]
     pub mount: Option<MountArgs>,
 
+    #[command(subcommand)]
+    pub command: Option<CryfsCommand>,
+
     /// Show a list with the supported encryption ciphers.
     #[arg(long, group = "immediate-exit", conflicts_with("mount"))]
     pub show_ciphers: bool,
@@ -0,0 +1,27 @@
+use clap::ValueEnum;
+
+use cryfs_runner::FsyncPolicy;
+
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub enum FsyncOption {
+    /// Never force data to the disk and leave it to the operating system when to write it.
+    /// This is the fastest option, but data written shortly before a power loss can be lost even if an application called fsync.
+    None,
+
+    /// Force data to the disk when an application calls fsync on a file or directory.
+    #[default]
+    OnFsync,
+
+    /// Force every write to the disk before returning. This is the slowest option.
+    Always,
+}
+
+impl From<FsyncOption> for FsyncPolicy {
+    fn from(option: FsyncOption) -> Self {
+        match option {
+            FsyncOption::None => FsyncPolicy::Never,
+            FsyncOption::OnFsync => FsyncPolicy::OnFsync,
+            FsyncOption::Always => FsyncPolicy::Always,
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use anyhow::{Context, Result, anyhow, bail};
+use byte_unit::Byte;
+use clap::builder::PossibleValue;
+use std::time::Duration;
+
+use cryfs_runner::KernelCacheOptions;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KernelCacheOption {
+    EntryTimeout(Duration),
+    AttrTimeout(Duration),
+    WritebackCache(bool),
+    MaxWrite(u32),
+    MaxReadahead(u32),
+    ParallelDirops(bool),
+}
+
+impl KernelCacheOption {
+    pub fn possible_values() -> impl Iterator<Item = PossibleValue> {
+        [
+            PossibleValue::new("entry_timeout=<SECONDS>")
+                .help("How long the kernel caches file names. Default: 1"),
+            PossibleValue::new("attr_timeout=<SECONDS>")
+                .help("How long the kernel caches file attributes like size and timestamps. Default: 1"),
+            PossibleValue::new("writeback_cache").help(
+                "Let the kernel buffer writes and send them to CryFS in larger chunks. This is faster for small writes, but written data stays in memory a bit longer before it is encrypted",
+            ),
+            PossibleValue::new("no_writeback_cache")
+                .help("Send writes to CryFS immediately. This is the default"),
+            PossibleValue::new("max_write=<BYTES>")
+                .help("The largest write request the kernel sends to CryFS, e.g. 128KiB"),
+            PossibleValue::new("max_readahead=<BYTES>")
+                .help("The largest readahead the kernel does, e.g. 128KiB"),
+            PossibleValue::new("parallel_dirops").help(
+                "Allow the kernel to do concurrent lookups and readdirs in the same directory. This is the default",
+            ),
+            PossibleValue::new("no_parallel_dirops")
+                .help("Serialize lookups and readdirs in the same directory"),
+        ]
+        .into_iter()
+    }
+
+    pub fn parse(option: &str) -> Result<Self> {
+        let (key, value) = match option.split_once('=') {
+            Some((key, value)) => (key, Some(value)),
+            None => (option, None),
+        };
+        let required_value = || value.ok_or_else(|| anyhow!("Option {key} needs a value"));
+        let no_value = || {
+            if value.is_some() {
+                bail!("Option {key} doesn't take a value");
+            }
+            Ok(())
+        };
+        match key {
+            "entry_timeout" => Ok(Self::EntryTimeout(parse_seconds(required_value()?)?)),
+            "attr_timeout" => Ok(Self::AttrTimeout(parse_seconds(required_value()?)?)),
+            "writeback_cache" => no_value().map(|()| Self::WritebackCache(true)),
+            "no_writeback_cache" => no_value().map(|()| Self::WritebackCache(false)),
+            "max_write" => Ok(Self::MaxWrite(parse_bytes(required_value()?)?)),
+            "max_readahead" => Ok(Self::MaxReadahead(parse_bytes(required_value()?)?)),
+            "parallel_dirops" => no_value().map(|()| Self::ParallelDirops(true)),
+            "no_parallel_dirops" => no_value().map(|()| Self::ParallelDirops(false)),
+            _ => bail!("Unknown option {key}"),
+        }
+    }
+
+    /// Later options override earlier ones
+    pub fn to_kernel_cache_options(options: &[KernelCacheOption]) -> KernelCacheOptions {
+        let mut result = KernelCacheOptions::default();
+        for option in options {
+            match *option {
+                Self::EntryTimeout(ttl) => result.entry_ttl = ttl,
+                Self::AttrTimeout(ttl) => result.attr_ttl = ttl,
+                Self::WritebackCache(enabled) => result.writeback_cache = enabled,
+                Self::MaxWrite(bytes) => result.max_write = Some(bytes),
+                Self::MaxReadahead(bytes) => result.max_readahead = Some(bytes),
+                Self::ParallelDirops(enabled) => result.parallel_dirops = enabled,
+            }
+        }
+        result
+    }
+}
+
+fn parse_seconds(value: &str) -> Result<Duration> {
+    let seconds: f64 = value
+        .parse()
+        .with_context(|| format!("Invalid number of seconds: {value}"))?;
+    Duration::try_from_secs_f64(seconds)
+        .with_context(|| format!("Invalid number of seconds: {value}"))
+}
+
+fn parse_bytes(value: &str) -> Result<u32> {
+    let bytes = Byte::parse_str(value, true)
+        .with_context(|| format!("Invalid number of bytes: {value}"))?;
+    u32::try_from(bytes.as_u64()).with_context(|| format!("Number of bytes is too large: {value}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(
+        "entry_timeout=5",
+        KernelCacheOption::EntryTimeout(Duration::from_secs(5))
+    )]
+    #[case(
+        "attr_timeout=0.5",
+        KernelCacheOption::AttrTimeout(Duration::from_millis(500))
+    )]
+    #[case("attr_timeout=0", KernelCacheOption::AttrTimeout(Duration::ZERO))]
+    #[case("writeback_cache", KernelCacheOption::WritebackCache(true))]
+    #[case("no_writeback_cache", KernelCacheOption::WritebackCache(false))]
+    #[case("max_write=131072", KernelCacheOption::MaxWrite(131072))]
+    #[case("max_write=128KiB", KernelCacheOption::MaxWrite(131072))]
+    #[case("max_readahead=1MiB", KernelCacheOption::MaxReadahead(1024 * 1024))]
+    #[case("parallel_dirops", KernelCacheOption::ParallelDirops(true))]
+    #[case("no_parallel_dirops", KernelCacheOption::ParallelDirops(false))]
+    fn test_parse(#[case] input: &str, #[case] expected: KernelCacheOption) {
+        assert_eq!(expected, KernelCacheOption::parse(input).unwrap());
+    }
+
+    #[rstest]
+    #[case("unknown", "Unknown option unknown")]
+    #[case("entry_timeout", "Option entry_timeout needs a value")]
+    #[case("entry_timeout=abc", "Invalid number of seconds: abc")]
+    #[case("attr_timeout=-1", "Invalid number of seconds: -1")]
+    #[case("writeback_cache=1", "Option writeback_cache doesn't take a value")]
+    #[case("max_write=8GiB", "Number of bytes is too large: 8GiB")]
+    fn test_parse_error(#[case] input: &str, #[case] expected_error: &str) {
+        assert_eq!(
+            expected_error,
+            KernelCacheOption::parse(input).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_defaults() {
+        let options = KernelCacheOption::to_kernel_cache_options(&[]);
+        let defaults = KernelCacheOptions::default();
+        assert_eq!(defaults.entry_ttl, options.entry_ttl);
+        assert_eq!(defaults.attr_ttl, options.attr_ttl);
+        assert_eq!(defaults.writeback_cache, options.writeback_cache);
+        assert_eq!(defaults.max_write, options.max_write);
+        assert_eq!(defaults.max_readahead, options.max_readahead);
+        assert_eq!(defaults.parallel_dirops, options.parallel_dirops);
+    }
+
+    #[test]
+    fn test_later_options_override_earlier_ones() {
+        let options = KernelCacheOption::to_kernel_cache_options(&[
+            KernelCacheOption::WritebackCache(true),
+            KernelCacheOption::EntryTimeout(Duration::from_secs(10)),
+            KernelCacheOption::WritebackCache(false),
+            KernelCacheOption::MaxWrite(4096),
+        ]);
+        assert!(!options.writeback_cache);
+        assert_eq!(Duration::from_secs(10), options.entry_ttl);
+        assert_eq!(Some(4096), options.max_write);
+    }
+}
@@ -1,49 +1,95 @@
 mod atime_option;
+mod kernel_cache_option;
 mod permission_option;
-use itertools::{Either, Itertools};
 
-use clap::ValueEnum;
+use clap::builder::{PossibleValue, TypedValueParser};
+use clap::error::ErrorKind;
+use clap::{Arg, Command, ValueEnum};
 use permission_option::FusePermissionOption;
+use std::ffi::OsStr;
 
 pub use atime_option::AtimeOption;
+pub use kernel_cache_option::KernelCacheOption;
 
 #[derive(Debug, Clone, Copy)]
+#[allow(clippy::enum_variant_names)]
 pub enum FuseOption {
     AtimeOption(AtimeOption),
     PermissionOption(FusePermissionOption),
+    KernelCacheOption(KernelCacheOption),
 }
 
-impl ValueEnum for FuseOption {
-    fn value_variants<'a>() -> &'a [Self] {
-        let variants = &[
-            Self::AtimeOption(AtimeOption::Atime),
-            Self::AtimeOption(AtimeOption::Strictatime),
-            Self::AtimeOption(AtimeOption::Noatime),
-            Self::AtimeOption(AtimeOption::Relatime),
-            Self::AtimeOption(AtimeOption::Nodiratime),
-            Self::PermissionOption(FusePermissionOption::AllowOther),
-            Self::PermissionOption(FusePermissionOption::AllowRoot),
-        ];
-        assert_eq!(
-            variants.len(),
-            AtimeOption::value_variants().len() + FusePermissionOption::value_variants().len(),
-        );
-        variants
+impl FuseOption {
+    pub fn parse(option: &str) -> Result<Self, String> {
+        if let Ok(atime_option) = AtimeOption::from_str(option, false) {
+            return Ok(Self::AtimeOption(atime_option));
+        }
+        if let Ok(permission_option) = FusePermissionOption::from_str(option, false) {
+            return Ok(Self::PermissionOption(permission_option));
+        }
+        KernelCacheOption::parse(option)
+            .map(Self::KernelCacheOption)
+            .map_err(|err| format!("{err:#}"))
     }
 
-    fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
-        match self {
-            Self::AtimeOption(atime_option) => atime_option.to_possible_value(),
-            Self::PermissionOption(permission_option) => permission_option.to_possible_value(),
+    pub fn partition(
+        this: &[Self],
+    ) -> (
+        Vec<AtimeOption>,
+        Vec<FusePermissionOption>,
+        Vec<KernelCacheOption>,
+    ) {
+        let mut atime_options = Vec::new();
+        let mut permission_options = Vec::new();
+        let mut kernel_cache_options = Vec::new();
+        for option in this {
+            match *option {
+                Self::AtimeOption(option) => atime_options.push(option),
+                Self::PermissionOption(option) => permission_options.push(option),
+                Self::KernelCacheOption(option) => kernel_cache_options.push(option),
+            }
         }
+        (atime_options, permission_options, kernel_cache_options)
     }
 }
 
-impl FuseOption {
-    pub fn partition(this: &[Self]) -> (Vec<AtimeOption>, Vec<FusePermissionOption>) {
-        this.iter().partition_map(|option| match option {
-            Self::AtimeOption(atime_option) => Either::Left(atime_option),
-            Self::PermissionOption(permission_option) => Either::Right(permission_option),
+/// Some fuse options take values (e.g. `entry_timeout=5`), so they can't be a [ValueEnum].
+/// This parser still lists all of them in `--help`.
+#[derive(Clone, Copy)]
+pub struct FuseOptionParser;
+
+impl TypedValueParser for FuseOptionParser {
+    type Value = FuseOption;
+
+    fn parse_ref(
+        &self,
+        cmd: &Command,
+        _arg: Option<&Arg>,
+        value: &OsStr,
+    ) -> Result<FuseOption, clap::Error> {
+        let value = value
+            .to_str()
+            .ok_or_else(|| clap::Error::new(ErrorKind::InvalidUtf8).with_cmd(cmd))?;
+        FuseOption::parse(value).map_err(|err| {
+            clap::Error::raw(
+                ErrorKind::InvalidValue,
+                format!("Invalid fuse option '{value}': {err}\n"),
+            )
+            .with_cmd(cmd)
         })
     }
+
+    fn possible_values(&self) -> Option<Box<dyn Iterator<Item = PossibleValue> + '_>> {
+        Some(Box::new(
+            AtimeOption::value_variants()
+                .iter()
+                .filter_map(ValueEnum::to_possible_value)
+                .chain(
+                    FusePermissionOption::value_variants()
+                        .iter()
+                        .filter_map(ValueEnum::to_possible_value),
+                )
+                .chain(KernelCacheOption::possible_values()),
+        ))
+    }
 }
@@ -0,0 +1,22 @@
+use clap::ValueEnum;
+
+use cryfs_config::config::KdfAlgorithm;
+
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub enum KdfOption {
+    /// Scrypt, readable by all CryFS versions.
+    #[default]
+    Scrypt,
+
+    /// Argon2id, as recommended by RFC 9106. File systems using it can't be opened by older CryFS versions.
+    Argon2id,
+}
+
+impl From<KdfOption> for KdfAlgorithm {
+    fn from(option: KdfOption) -> Self {
+        match option {
+            KdfOption::Scrypt => KdfAlgorithm::Scrypt,
+            KdfOption::Argon2id => KdfAlgorithm::Argon2id,
+        }
+    }
+}
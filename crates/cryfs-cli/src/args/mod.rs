@@ -1,9 +1,16 @@
+mod backend_option;
+mod command;
+mod compression_option;
 mod cryfs_args;
+mod fsync_option;
 mod fuse_option;
+mod kdf_option;
 mod mount_args;
 
+pub use command::{CryfsCommand, KeySlotCommand, MirrorCommand, VaultArgs};
 pub use cryfs_args::CryfsArgs;
-pub use fuse_option::{AtimeOption, FuseOption};
+pub use fuse_option::{AtimeOption, FuseOption, KernelCacheOption};
+pub use kdf_option::KdfOption;
 pub use mount_args::MountArgs;
 
 // TODO Evaluate `clap_mangen` as a potential automatic manpage generator
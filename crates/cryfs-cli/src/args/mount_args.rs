@@ -4,7 +4,11 @@ use clap::Args;
 use cryfs_cli_utils::parse_path;
 use std::path::PathBuf;
 
-use super::fuse_option::FuseOption;
+use super::backend_option::BackendOption;
+use super::compression_option::CompressionOption;
+use super::fsync_option::FsyncOption;
+use super::fuse_option::{FuseOption, FuseOptionParser};
+use super::kdf_option::KdfOption;
 
 #[derive(Args, Debug)]
 #[group(multiple = true, id = "mount")]
@@ -70,13 +74,46 @@ pub struct MountArgs {
     #[arg(long, value_parser(parse_byte_amount))]
     pub blocksize: Option<Byte>,
 
+    /// The key derivation function used to derive the config file encryption key from your password. Default: scrypt
+    /// This is only used when creating a new file system.
+    #[arg(long, value_enum)]
+    pub kdf: Option<KdfOption>,
+
+    /// Compress blocks before encrypting them. Default: none
+    /// This is only used when creating a new file system.
+    /// Compressed blocks have different sizes on disk, which tells an attacker how well each block compresses
+    /// and therefore something about the kind of data stored in it. File systems with compression can't be opened by older CryFS versions.
+    #[arg(long, value_enum)]
+    pub compression: Option<CompressionOption>,
+
+    /// Create the file system in a format that supports extended attributes, hard links, special files (fifos, sockets, devices),
+    /// sparse files and sharing data between files with copy_file_range. File systems created with this can't be opened by CryFS 1.0.
+    /// Without this flag, these features aren't available, but the file system stays compatible with CryFS 1.0.
+    /// This is only used when creating a new file system.
+    #[arg(long)]
+    pub extended_format: bool,
+
+    /// Where to store the blocks of the file system. Default: ondisk
+    /// If creating a new file system, this will determine the backend for the new file system. It is remembered in a `cryfs.backend` file in the vault directory.
+    /// If opening an existing file system, this will verify that the file system actually uses that backend.
+    #[arg(long, value_enum)]
+    pub backend: Option<BackendOption>,
+
+    /// Directory that keeps a full copy of all blocks when using `--backend mirrored`. Can be specified multiple times to keep more copies.
+    #[arg(long, value_parser=parse_path)]
+    pub replica: Vec<PathBuf>,
+
     /// Automatically unmount if the file system hasn't been used for the specified duration.
     /// Values are human readable durations, e.g. 30sec, 5min, 1h30m, etc.
     #[arg(long)]
     pub unmount_idle: Option<humantime::Duration>,
 
-    /// Additional fuse mount options. Can be specified multiple times, e.g. '-o allow_other -o allow_root'.
-    #[arg(short = 'o', long, value_delimiter = ',')]
+    /// When to force written data to the physical disk. Default: on-fsync
+    #[arg(long, value_enum)]
+    pub fsync: Option<FsyncOption>,
+
+    /// Additional fuse mount options. Can be specified multiple times, e.g. '-o allow_other -o allow_root' or '-o entry_timeout=5,writeback_cache'.
+    #[arg(short = 'o', long, value_delimiter = ',', value_parser = FuseOptionParser)]
     pub fuse_option: Vec<FuseOption>,
 }
 
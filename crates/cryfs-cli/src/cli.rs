@@ -1,6 +1,6 @@
 use std::path::PathBuf;
 
-use anyhow::{Context as _, Result};
+use anyhow::{Context as _, Result, anyhow};
 use clap_logflag::{LogDestination, LogDestinationConfig, LoggingConfig};
 use cryfs_config::config::CryConfigFile;
 use cryfs_config::localstate::{CheckFilesystemIdError, VaultdirMetadata};
@@ -8,13 +8,14 @@ use cryfs_runner::{CreateOrLoad, Mounter};
 use log::LevelFilter;
 
 use super::console::InteractiveConsole;
-use crate::args::{AtimeOption, CryfsArgs, FuseOption, MountArgs};
+use crate::args::{AtimeOption, CryfsArgs, CryfsCommand, FuseOption, KernelCacheOption, MountArgs};
 use cryfs_blockstore::AllowIntegrityViolations;
 use cryfs_cli_utils::password_provider::{
     InteractivePasswordProvider, NoninteractivePasswordProvider,
 };
 use cryfs_cli_utils::{
-    Application, CliError, CliErrorKind, CliResultExt, CliResultExtFn, Environment, print_config,
+    Application, BACKEND_CONFIG_FILE_NAME, BackendConfig, CliError, CliErrorKind, CliResultExt,
+    CliResultExtFn, Environment, print_config,
 };
 use cryfs_config::CRYFS_VERSION;
 use cryfs_config::{
@@ -97,6 +98,10 @@ impl Application for Cli {
             return Ok(());
         }
 
+        if let Some(command) = &self.args.command {
+            return self.run_command(command);
+        }
+
         let mounter = if self.mount_args().foreground {
             Mounter::run_in_foreground().map_cli_error(CliErrorKind::UnspecifiedError)?
         } else {
@@ -157,9 +162,16 @@ impl Cli {
     ) -> Result<(), CliError> {
         let mount_args = self.mount_args();
 
+        // Check the backend arguments before creating a new config file so that invalid arguments don't leave a half-created vault behind
+        let backend = self.backend_config(!self.config_file_location().exists())?;
         let config =
             self.load_or_create_config(mount_args.allow_replaced_filesystem, progress_bars)?;
         print_config(&config);
+        if config.first_time_access {
+            backend
+                .save(&mount_args.vaultdir)
+                .map_cli_error(CliErrorKind::InaccessibleVaultDir)?;
+        }
 
         let on_successfully_mounted = || {
             // TODO Output formatting, e.g. colorization
@@ -183,7 +195,7 @@ impl Cli {
             println!("  To see more information, run `cryfs --help`.");
         };
 
-        let (atime_options, fuse_permission_options) =
+        let (atime_options, fuse_permission_options, kernel_cache_options) =
             FuseOption::partition(&mount_args.fuse_option);
 
         let atime_behavior = AtimeOption::to_atime_behavior(&atime_options)
@@ -193,6 +205,7 @@ impl Cli {
             .mount_filesystem(
                 cryfs_runner::MountArgs {
                     vaultdir: mount_args.vaultdir.clone(),
+                    backend,
                     mountdir: mount_args.mountdir.clone(),
                     allow_integrity_violations: if mount_args.allow_integrity_violations {
                         AllowIntegrityViolations::AllowViolations
@@ -210,6 +223,8 @@ impl Cli {
                     unmount_idle: mount_args.unmount_idle.map(Into::into),
                     fuse_options: fuse_permission_options.iter().map(Into::into).collect(),
                     atime_behavior,
+                    fsync_policy: mount_args.fsync.unwrap_or_default().into(),
+                    kernel_cache: KernelCacheOption::to_kernel_cache_options(&kernel_cache_options),
                 },
                 on_successfully_mounted,
             )
@@ -224,6 +239,25 @@ impl Cli {
         Ok(())
     }
 
+    fn run_command(&self, command: &CryfsCommand) -> Result<(), CliError> {
+        match command {
+            CryfsCommand::KeySlot(command) => super::config_commands::run_key_slot_command(
+                command,
+                self.password_provider(),
+                &self.console(),
+                ConsoleProgressBarManager,
+            ),
+            CryfsCommand::Passwd { vault, kdf } => super::config_commands::run_passwd_command(
+                vault,
+                *kdf,
+                self.password_provider(),
+                &self.console(),
+                ConsoleProgressBarManager,
+            ),
+            CryfsCommand::Mirror(command) => super::mirror_commands::run(command),
+        }
+    }
+
     fn show_ciphers(&self) {
         for cipher in cryfs_config::config::ALL_CIPHERS {
             println!("{}", cipher);
@@ -247,6 +281,9 @@ impl Cli {
                     .missing_block_is_integrity_violation,
                 expected_cipher: mount_args.cipher.clone(),
                 blocksize: mount_args.blocksize,
+                kdf: mount_args.kdf.map(Into::into),
+                compression: mount_args.compression.unwrap_or_default().into(),
+                extended_format: mount_args.extended_format,
             },
             &self.local_state_dir,
             mount_args.allow_filesystem_upgrade,
@@ -291,7 +328,8 @@ impl Cli {
             | ConfigLoadError::SaveFileError(SaveConfigFileError::PermissionDenied { .. })
             | ConfigLoadError::SaveFileError(SaveConfigFileError::IoError(_))
             | ConfigLoadError::SaveFileError(SaveConfigFileError::SerializationError(_))
-            | ConfigLoadError::SaveFileError(SaveConfigFileError::ScryptError(_))
+            | ConfigLoadError::SaveFileError(SaveConfigFileError::KdfError(_))
+            | ConfigLoadError::SaveFileError(SaveConfigFileError::VerificationFailed { .. })
             | ConfigLoadError::ConfigCreateError(ConfigCreateError::CipherNotSupported {
                 ..
             })
@@ -306,13 +344,57 @@ impl Cli {
             })
             | ConfigLoadError::CreateFileError(CreateConfigFileError::IoError(_))
             | ConfigLoadError::CreateFileError(CreateConfigFileError::SerializationError(_))
-            | ConfigLoadError::CreateFileError(CreateConfigFileError::ScryptError(_))
+            | ConfigLoadError::CreateFileError(CreateConfigFileError::KdfError(_))
             | ConfigLoadError::InteractionError(_) => CliErrorKind::UnspecifiedError,
         })?;
         self.check_config_integrity(&config.config, allow_replaced_filesystem)?;
         Ok(config)
     }
 
+    /// Returns the backend requested on the command line for a new file system, or loads and checks the backend config of an existing one.
+    /// The backend config of a new file system only gets saved once its config file was created.
+    fn backend_config(&self, is_new_filesystem: bool) -> Result<BackendConfig, CliError> {
+        let mount_args = self.mount_args();
+        if is_new_filesystem {
+            let backend = mount_args
+                .backend
+                .unwrap_or_default()
+                .to_backend_config(&mount_args.replica)
+                .map_cli_error(CliErrorKind::InvalidArguments)?;
+            for replica in backend
+                .mirror_replica_dirs(&mount_args.vaultdir)
+                .unwrap_or_default()
+            {
+                if !replica.is_dir() {
+                    return Err(anyhow!(
+                        "Replica directory {} doesn't exist or isn't a directory",
+                        replica.display()
+                    ))
+                    .map_cli_error(CliErrorKind::InaccessibleVaultDir);
+                }
+            }
+            Ok(backend)
+        } else {
+            let backend = BackendConfig::load(&mount_args.vaultdir)
+                .map_cli_error(CliErrorKind::InvalidFilesystem)?;
+            if !mount_args.replica.is_empty() {
+                return Err(anyhow!(
+                    "--replica can only be used when creating a file system. The replicas of an existing file system are stored in its {BACKEND_CONFIG_FILE_NAME} file."
+                ))
+                .map_cli_error(CliErrorKind::InvalidArguments);
+            }
+            if let Some(requested) = mount_args.backend {
+                if !requested.matches(&backend) {
+                    return Err(anyhow!(
+                        "The file system doesn't use the {requested:?} backend. Its backend is {backend:?}."
+                    ))
+                    .map_cli_error(CliErrorKind::InvalidArguments);
+                }
+            }
+            Ok(backend)
+        }
+    }
+
     fn config_file_location(&self) -> PathBuf {
         let mount_args = self.mount_args();
         mount_args
@@ -0,0 +1,82 @@
+use cryfs_cli_utils::{CliError, CliErrorKind, CliResultExt, CliResultExtFn};
+use cryfs_config::config::{
+    Access, Console, CryConfigFile, KdfAlgorithm, KeySlotError, PasswordProvider,
+};
+use cryfs_utils::progress::ProgressBarManager;
+
+use super::{kdf_settings, load, save};
+use crate::args::KeySlotCommand;
+
+pub fn run(
+    command: &KeySlotCommand,
+    password_provider: &dyn PasswordProvider,
+    console: &impl Console,
+    progress_bars: impl ProgressBarManager,
+) -> Result<(), CliError> {
+    match command {
+        KeySlotCommand::List(vault) => {
+            let config = load(
+                vault.config_file_location(),
+                password_provider,
+                Access::ReadOnly,
+                progress_bars,
+            )?;
+            list(&config);
+        }
+        KeySlotCommand::Add { vault, kdf } => {
+            let mut config = load(
+                vault.config_file_location(),
+                password_provider,
+                Access::ReadWrite,
+                progress_bars,
+            )?;
+            println!("Enter the new password to add.");
+            let new_password = password_provider
+                .password_for_new_filesystem()
+                .map_cli_error(CliErrorKind::EmptyPassword)?;
+            let kdf_settings = kdf_settings(console, *kdf, KdfAlgorithm::default())?;
+            let index = config
+                .add_key_slot(&new_password, &kdf_settings, progress_bars)
+                .map_cli_error(|error| match error {
+                    KeySlotError::TooManyKeySlots => CliErrorKind::InvalidArguments,
+                    KeySlotError::NoSuchKeySlot { .. }
+                    | KeySlotError::KeySlotInUse { .. }
+                    | KeySlotError::KdfError(_) => CliErrorKind::UnspecifiedError,
+                })?;
+            save(&mut config)?;
+            println!("Added key slot {index}.");
+        }
+        KeySlotCommand::Remove { vault, slot } => {
+            let mut config = load(
+                vault.config_file_location(),
+                password_provider,
+                Access::ReadWrite,
+                progress_bars,
+            )?;
+            config
+                .remove_key_slot(*slot)
+                .map_cli_error(|error| match error {
+                    KeySlotError::NoSuchKeySlot { .. } | KeySlotError::KeySlotInUse { .. } => {
+                        CliErrorKind::InvalidArguments
+                    }
+                    KeySlotError::TooManyKeySlots | KeySlotError::KdfError(_) => {
+                        CliErrorKind::UnspecifiedError
+                    }
+                })?;
+            save(&mut config)?;
+            println!("Removed key slot {slot}.");
+        }
+    }
+    Ok(())
+}
+
+fn list(config: &CryConfigFile) {
+    for (index, key_slot) in config.key_slots().iter().enumerate() {
+        let unlocked = if index == config.unlocked_key_slot() {
+            " (unlocked with the entered password)"
+        } else {
+            ""
+        };
+        println!("Key slot {index}: {}{unlocked}", key_slot.kdf_parameters());
+    }
+}
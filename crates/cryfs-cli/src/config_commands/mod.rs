@@ -0,0 +1,73 @@
+//! Commands that modify the config file of an existing file system without mounting it
+
+use std::path::PathBuf;
+
+use cryfs_cli_utils::{CliError, CliErrorKind, CliResultExt, CliResultExtFn};
+use cryfs_config::config::{
+    Access, Console, CryConfigFile, KdfAlgorithm, KdfSettings, LoadConfigFileError,
+    PasswordProvider, SaveConfigFileError,
+};
+use cryfs_utils::progress::ProgressBarManager;
+
+use crate::args::KdfOption;
+
+mod key_slots;
+mod passwd;
+
+pub use key_slots::run as run_key_slot_command;
+pub use passwd::run as run_passwd_command;
+
+pub fn load(
+    config_file_location: PathBuf,
+    password_provider: &dyn PasswordProvider,
+    access: Access,
+    progress_bars: impl ProgressBarManager,
+) -> Result<CryConfigFile, CliError> {
+    println!("Enter the password of an existing key slot.");
+    // TODO Protect password similar to how we protect EncryptionKey
+    let password = password_provider
+        .password_for_existing_filesystem()
+        .map_cli_error(CliErrorKind::EmptyPassword)?;
+    CryConfigFile::load(config_file_location, &password, access, progress_bars).map_cli_error(
+        |error| match error {
+            LoadConfigFileError::ConfigFileNotFound { .. }
+            | LoadConfigFileError::PermissionDenied { .. }
+            | LoadConfigFileError::IoError(_) => CliErrorKind::InvalidFilesystem,
+            LoadConfigFileError::DeserializationError(_) => {
+                CliErrorKind::WrongPasswordOrCorruptedConfigFile
+            }
+        },
+    )
+}
+
+pub fn save(config: &mut CryConfigFile) -> Result<(), CliError> {
+    config.save().map_cli_error(save_error_kind)
+}
+
+pub fn save_error_kind(error: &SaveConfigFileError) -> CliErrorKind {
+    match error {
+        SaveConfigFileError::DirectoryComponentDoesntExist { .. }
+        | SaveConfigFileError::PermissionDenied { .. }
+        | SaveConfigFileError::IoError(_)
+        | SaveConfigFileError::SerializationError(_)
+        | SaveConfigFileError::KdfError(_)
+        | SaveConfigFileError::VerificationFailed { .. } => CliErrorKind::UnspecifiedError,
+    }
+}
+
+/// Ask for the settings of the KDF given on the command line, or of `default_kdf` if none was given
+pub fn kdf_settings(
+    console: &impl Console,
+    kdf: Option<KdfOption>,
+    default_kdf: KdfAlgorithm,
+) -> Result<KdfSettings, CliError> {
+    let kdf_settings = match kdf.map(KdfAlgorithm::from).unwrap_or(default_kdf) {
+        KdfAlgorithm::Scrypt => console
+            .ask_scrypt_settings_for_new_filesystem()
+            .map(KdfSettings::Scrypt),
+        KdfAlgorithm::Argon2id => console
+            .ask_argon2id_settings_for_new_filesystem()
+            .map(KdfSettings::Argon2id),
+    };
+    kdf_settings.map_cli_error(CliErrorKind::UnspecifiedError)
+}
@@ -0,0 +1,38 @@
+use cryfs_cli_utils::{CliError, CliErrorKind, CliResultExt, CliResultExtFn};
+use cryfs_config::config::{Access, Console, KeySlotError, PasswordProvider};
+use cryfs_utils::progress::ProgressBarManager;
+
+use super::{kdf_settings, load, save_error_kind};
+use crate::args::{KdfOption, VaultArgs};
+
+pub fn run(
+    vault: &VaultArgs,
+    kdf: Option<KdfOption>,
+    password_provider: &dyn PasswordProvider,
+    console: &impl Console,
+    progress_bars: impl ProgressBarManager,
+) -> Result<(), CliError> {
+    let mut config = load(
+        vault.config_file_location(),
+        password_provider,
+        Access::ReadWrite,
+        progress_bars,
+    )?;
+    println!("Enter the new password.");
+    // TODO Protect password similar to how we protect EncryptionKey
+    let new_password = password_provider
+        .password_for_new_filesystem()
+        .map_cli_error(CliErrorKind::EmptyPassword)?;
+    let kdf_settings = kdf_settings(console, kdf, config.kdf_algorithm())?;
+    config
+        .change_password(&new_password, &kdf_settings, progress_bars)
+        .map_cli_error(|error| match error {
+            KeySlotError::NoSuchKeySlot { .. }
+            | KeySlotError::KeySlotInUse { .. }
+            | KeySlotError::TooManyKeySlots
+            | KeySlotError::KdfError(_) => CliErrorKind::UnspecifiedError,
+        })?;
+    config.save_with_backup().map_cli_error(save_error_kind)?;
+    println!("Changed the password.");
+    Ok(())
+}
@@ -5,7 +5,7 @@ use once_cell::unsync::OnceCell;
 use std::{fmt::Display, path::Path};
 
 use cryfs_config::config::Console;
-use cryfs_crypto::kdf::scrypt::ScryptSettings;
+use cryfs_crypto::kdf::{argon2id::Argon2idSettings, scrypt::ScryptSettings};
 use cryfs_version::{Version, VersionInfo};
 
 // TODO Put default block size & cipher into a central place so we can share it with the code that creates file systems with "use default settings? yes"
@@ -121,6 +121,47 @@ impl Console for InteractiveConsole {
         )
     }
 
+    /// We're in the process of creating a new file system with argon2id as KDF and need to ask the user for the argon2id settings to use
+    fn ask_argon2id_settings_for_new_filesystem(&self) -> Result<Argon2idSettings> {
+        let options = [
+            option(
+                "1. Low Memory: less secure, but works on devices with less memory",
+                Argon2idSettings::LOW_MEMORY,
+            ),
+            option("2. Default", Argon2idSettings::DEFAULT),
+            option(
+                "3. Paranoid: more secure, but mounting will be slow",
+                Argon2idSettings::PARANOID,
+            ),
+        ];
+        const DEFAULT_INDEX: usize = 1;
+
+        if self._use_default_creation_settings()? {
+            return Ok(options[DEFAULT_INDEX].1);
+        }
+
+        fn option(name: &str, opt: Argon2idSettings) -> (String, Argon2idSettings) {
+            (
+                format!(
+                    "{name} (memory={m_cost}KiB, iterations={t_cost}, parallelism={p_cost})",
+                    m_cost = opt.m_cost,
+                    t_cost = opt.t_cost,
+                    p_cost = opt.p_cost,
+                ),
+                opt,
+            )
+        }
+
+        ask_multiple_choice(
+            Some(
+                "Argon2id is used to derive an encryption key from your password, to protect your file system against brute force attacks",
+            ),
+            "Please select the argon2id settings to use",
+            options.into_iter(),
+            DEFAULT_INDEX,
+        )
+    }
+
     fn ask_cipher_for_new_filesystem(&self) -> Result<String> {
         // TODO Define default cipher somewhere in a constant not by index but by cipher name or enum, and show it correctly in the `--help` as well. Same for blocksize bytes.
         const DEFAULT_CIPHER_INDEX: usize = 0;
@@ -1,4 +1,6 @@
 #![forbid(unsafe_code)]
+// The futures for running the file system are deeply nested and exceed the default limit when computing their layout
+#![recursion_limit = "256"]
 // TODO #![deny(missing_docs)]
 
 mod args;
@@ -6,7 +8,9 @@ mod args;
 mod cli;
 pub use cli::Cli;
 
+mod config_commands;
 mod console;
+mod mirror_commands;
 mod sanity_checks;
 
 cryfs_version::assert_cargo_version_equals_git_version!();
@@ -0,0 +1,120 @@
+//! Commands that work on the replicas of a file system created with `--backend mirrored`
+
+use anyhow::{Context as _, Result, anyhow, bail};
+use std::path::{Path, PathBuf};
+
+use cryfs_blockstore::{FsyncPolicy, LLBlockStore, MirroringBlockStore, ResyncSummary};
+use cryfs_cli_utils::{
+    BackendConfig, CliError, CliErrorKind, CliResultExt, is_mounted, open_mirror,
+};
+use cryfs_utils::async_drop::AsyncDropGuard;
+
+use crate::args::MirrorCommand;
+
+pub fn run(command: &MirrorCommand) -> Result<(), CliError> {
+    match command {
+        MirrorCommand::Resync {
+            vaultdir,
+            target,
+            source,
+            compare_contents,
+        } => cryfs_runner::init_tokio().block_on(resync(
+            vaultdir,
+            target,
+            source.as_deref(),
+            *compare_contents,
+        )),
+    }
+}
+
+async fn resync(
+    vaultdir: &Path,
+    target: &Path,
+    source: Option<&Path>,
+    compare_contents: bool,
+) -> Result<(), CliError> {
+    let backend = BackendConfig::load(vaultdir).map_cli_error(CliErrorKind::InvalidFilesystem)?;
+    let replica_dirs = backend
+        .mirror_replica_dirs(vaultdir)
+        .ok_or_else(|| {
+            anyhow!(
+                "The file system at {} doesn't use the mirrored backend",
+                vaultdir.display()
+            )
+        })
+        .map_cli_error(CliErrorKind::InvalidArguments)?;
+    let target_index =
+        replica_index(&replica_dirs, target).map_cli_error(CliErrorKind::InvalidArguments)?;
+    let source_index = match source {
+        Some(source) => replica_index(&replica_dirs, source),
+        None if target_index == 0 => Ok(1),
+        None => Ok(0),
+    }
+    .map_cli_error(CliErrorKind::InvalidArguments)?;
+    if source_index == target_index {
+        return Err(anyhow!("The source and target replica must be different"))
+            .map_cli_error(CliErrorKind::InvalidArguments);
+    }
+
+    // Resyncing while the file system is mounted would race with the writes of the mounted file system
+    if is_mounted(vaultdir).map_cli_error(CliErrorKind::UnspecifiedError)? {
+        return Err(anyhow!(
+            "The file system at {} is mounted. Please unmount it before resyncing its replicas.",
+            vaultdir.display()
+        ))
+        .map_cli_error(CliErrorKind::FilesystemInUse);
+    }
+
+    println!(
+        "Resyncing {} from {}...",
+        replica_dirs[target_index].display(),
+        replica_dirs[source_index].display()
+    );
+    let mut mirror = open_mirror(&replica_dirs, FsyncPolicy::default())
+        .map_cli_error(CliErrorKind::InaccessibleVaultDir)?;
+    let result = resync_and_sync(&mirror, source_index, target_index, compare_contents).await;
+    let drop_result = mirror.async_drop().await;
+    let summary = result.map_cli_error(CliErrorKind::UnspecifiedError)?;
+    drop_result.map_cli_error(CliErrorKind::UnspecifiedError)?;
+    println!(
+        "Copied {} blocks, removed {} blocks and repaired {} blocks.",
+        summary.num_copied, summary.num_removed, summary.num_repaired
+    );
+    Ok(())
+}
+
+async fn resync_and_sync<B: LLBlockStore + Send + Sync>(
+    mirror: &AsyncDropGuard<MirroringBlockStore<B>>,
+    source_index: usize,
+    target_index: usize,
+    compare_contents: bool,
+) -> Result<ResyncSummary> {
+    let summary = mirror
+        .resync(source_index, target_index, compare_contents)
+        .await?;
+    mirror.sync().await?;
+    Ok(summary)
+}
+
+/// Finds `replica` in `replica_dirs`, comparing canonical paths so that different ways of writing the same path match
+fn replica_index(replica_dirs: &[PathBuf], replica: &Path) -> Result<usize> {
+    let canonical = |path: &Path| {
+        path.canonicalize()
+            .with_context(|| format!("Replica directory {} is inaccessible", path.display()))
+    };
+    let replica_canonical = canonical(replica)?;
+    for (index, dir) in replica_dirs.iter().enumerate() {
+        if canonical(dir)? == replica_canonical {
+            return Ok(index);
+        }
+    }
+    bail!(
+        "{} isn't a replica of this file system. Its replicas are: {}",
+        replica.display(),
+        replica_dirs
+            .iter()
+            .map(|dir| dir.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
@@ -253,6 +253,72 @@ mod foreground {
     // TODO Test -f flag with both vaultdir and mountdir present, i.e. successfully mounts. In different orderings.
 }
 
+mod key_slot {
+    use super::*;
+
+    #[test]
+    fn show_help() {
+        cryfs_cmd()
+            .args(["key-slot", "--help"])
+            .assert()
+            .success()
+            .stdout(
+                predicates::str::contains("list")
+                    .and(predicates::str::contains("add"))
+                    .and(predicates::str::contains("remove")),
+            );
+    }
+
+    #[test]
+    fn list_missing_vaultdir() {
+        cryfs_cmd()
+            .args(["key-slot", "list"])
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains("<VAULTDIR>"));
+    }
+
+    #[test]
+    fn remove_missing_slot() {
+        cryfs_cmd()
+            .args(["key-slot", "remove", "vaultdir"])
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains("<SLOT>"));
+    }
+
+    #[test]
+    fn add_invalid_kdf() {
+        cryfs_cmd()
+            .args(["key-slot", "add", "vaultdir", "--kdf", "bcrypt"])
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains("invalid value 'bcrypt'"));
+    }
+}
+
+mod passwd {
+    use super::*;
+
+    #[test]
+    fn show_help() {
+        cryfs_cmd()
+            .args(["passwd", "--help"])
+            .assert()
+            .success()
+            .stdout(predicates::str::contains("Usage: cryfs passwd"));
+    }
+
+    #[test]
+    fn missing_vaultdir() {
+        cryfs_cmd()
+            .arg("passwd")
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains("<VAULTDIR>"));
+    }
+}
+
 mod debug_build_warning {
     use super::*;
 
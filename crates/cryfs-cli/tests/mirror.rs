@@ -0,0 +1,218 @@
+//! Tests for `cryfs mirror resync`
+
+use assert_cmd::Command;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+use cryfs_blockstore::{
+    BlockId, BlockStoreReader, BlockStoreWriter, FsyncPolicy, OnDiskBlockStore,
+};
+use cryfs_cli_utils::BackendConfig;
+use cryfs_utils::async_drop::AsyncDropGuard;
+
+struct MirroredVault {
+    tempdir: TempDir,
+}
+
+impl MirroredVault {
+    fn create() -> Self {
+        let tempdir = TempDir::new().unwrap();
+        std::fs::create_dir(tempdir.path().join("vault")).unwrap();
+        std::fs::create_dir(tempdir.path().join("replica")).unwrap();
+        let vault = Self { tempdir };
+        BackendConfig::Mirrored {
+            replicas: vec![vault.replica()],
+        }
+        .save(&vault.vaultdir())
+        .unwrap();
+        vault
+    }
+
+    fn vaultdir(&self) -> PathBuf {
+        self.tempdir.path().join("vault")
+    }
+
+    fn replica(&self) -> PathBuf {
+        self.tempdir.path().join("replica")
+    }
+
+    fn resync(&self, args: &[&Path]) -> assert_cmd::assert::Assert {
+        Command::new(assert_cmd::cargo::cargo_bin!("cryfs"))
+            .args(["mirror", "resync"])
+            .args(args)
+            .env("CRYFS_NO_UPDATE_CHECK", "true")
+            .assert()
+    }
+}
+
+fn with_blockstore<R>(dir: &Path, f: impl AsyncFnOnce(&OnDiskBlockStore) -> R) -> R {
+    cryfs_runner::init_tokio().block_on(async {
+        let mut blockstore: AsyncDropGuard<OnDiskBlockStore> =
+            OnDiskBlockStore::new(dir.to_owned(), FsyncPolicy::Never);
+        let result = f(&blockstore).await;
+        blockstore.async_drop().await.unwrap();
+        result
+    })
+}
+
+fn store_blocks(dir: &Path, blocks: &[(BlockId, &[u8])]) {
+    with_blockstore(dir, async |blockstore| {
+        for (id, data) in blocks {
+            blockstore.store(id, data).await.unwrap();
+        }
+    })
+}
+
+/// Loads the given blocks from `dir`, `None` for blocks that don't exist there
+fn load_blocks(dir: &Path, ids: &[BlockId]) -> Vec<Option<Vec<u8>>> {
+    with_blockstore(dir, async |blockstore| {
+        let mut result = Vec::new();
+        for id in ids {
+            let data = blockstore.load(id).await.unwrap();
+            result.push(data.map(|data| data.as_ref().to_vec()));
+        }
+        result
+    })
+}
+
+#[test]
+fn resync_copies_missing_blocks_and_removes_extra_blocks() {
+    let vault = MirroredVault::create();
+    let (block1, block2, extra) = (
+        BlockId::new_random(),
+        BlockId::new_random(),
+        BlockId::new_random(),
+    );
+    store_blocks(
+        &vault.vaultdir(),
+        &[(block1, b"block 1"), (block2, b"block 2")],
+    );
+    store_blocks(
+        &vault.replica(),
+        &[(block1, b"block 1"), (extra, b"removed")],
+    );
+
+    vault
+        .resync(&[&vault.vaultdir(), &vault.replica()])
+        .success()
+        .stdout(predicates::str::contains(
+            "Copied 1 blocks, removed 1 blocks and repaired 0 blocks.",
+        ));
+
+    let expected = vec![Some(b"block 1".to_vec()), Some(b"block 2".to_vec()), None];
+    assert_eq!(
+        expected,
+        load_blocks(&vault.vaultdir(), &[block1, block2, extra])
+    );
+    assert_eq!(
+        expected,
+        load_blocks(&vault.replica(), &[block1, block2, extra])
+    );
+}
+
+#[test]
+fn resync_with_compare_contents_repairs_changed_blocks() {
+    let vault = MirroredVault::create();
+    let block = BlockId::new_random();
+    store_blocks(&vault.vaultdir(), &[(block, b"new content")]);
+    store_blocks(&vault.replica(), &[(block, b"old content")]);
+
+    let compare_contents = Path::new("--compare-contents");
+    vault
+        .resync(&[&vault.vaultdir(), &vault.replica(), compare_contents])
+        .success()
+        .stdout(predicates::str::contains(
+            "Copied 0 blocks, removed 0 blocks and repaired 1 blocks.",
+        ));
+
+    assert_eq!(
+        vec![Some(b"new content".to_vec())],
+        load_blocks(&vault.replica(), &[block])
+    );
+}
+
+#[test]
+fn resync_can_restore_the_vault_directory_from_a_replica() {
+    let vault = MirroredVault::create();
+    let block = BlockId::new_random();
+    store_blocks(&vault.replica(), &[(block, b"block")]);
+
+    // Without --source, the vault directory is restored from the first replica
+    vault
+        .resync(&[&vault.vaultdir(), &vault.vaultdir()])
+        .success();
+
+    assert_eq!(
+        vec![Some(b"block".to_vec())],
+        load_blocks(&vault.vaultdir(), &[block])
+    );
+}
+
+#[test]
+fn resync_fails_for_same_source_and_target() {
+    let vault = MirroredVault::create();
+    vault
+        .resync(&[
+            &vault.vaultdir(),
+            &vault.replica(),
+            Path::new("--source"),
+            &vault.replica(),
+        ])
+        .failure()
+        .stderr(predicates::str::contains("must be different"));
+}
+
+#[test]
+fn resync_fails_for_directory_that_isnt_a_replica() {
+    let vault = MirroredVault::create();
+    let other = vault.tempdir.path().join("other");
+    std::fs::create_dir(&other).unwrap();
+    vault
+        .resync(&[&vault.vaultdir(), &other])
+        .failure()
+        .stderr(predicates::str::contains(
+            "isn't a replica of this file system",
+        ));
+}
+
+#[test]
+fn resync_fails_for_vault_that_isnt_mirrored() {
+    let vaultdir = TempDir::new().unwrap();
+    let other = TempDir::new().unwrap();
+    Command::new(assert_cmd::cargo::cargo_bin!("cryfs"))
+        .args(["mirror", "resync"])
+        .arg(vaultdir.path())
+        .arg(other.path())
+        .env("CRYFS_NO_UPDATE_CHECK", "true")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "doesn't use the mirrored backend",
+        ));
+}
+
+#[test]
+fn creating_mirrored_vault_without_replica_fails_before_creating_the_vault() {
+    let tempdir = TempDir::new().unwrap();
+    let vaultdir = tempdir.path().join("vault");
+    let mountdir = tempdir.path().join("mount");
+    std::fs::create_dir(&vaultdir).unwrap();
+    std::fs::create_dir(&mountdir).unwrap();
+    let local_state_dir = tempdir.path().join("local_state_dir");
+    std::fs::create_dir(&local_state_dir).unwrap();
+    Command::new(assert_cmd::cargo::cargo_bin!("cryfs"))
+        .arg(&vaultdir)
+        .arg(&mountdir)
+        .args(["--foreground", "--backend", "mirrored"])
+        .env("CRYFS_NO_UPDATE_CHECK", "true")
+        .env(
+            "CRYFS_LOCAL_STATE_DIR",
+            tempdir.path().join("local_state_dir"),
+        )
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "--backend mirrored needs at least one --replica directory",
+        ));
+    assert!(!vaultdir.join("cryfs.config").exists());
+}
@@ -1,15 +1,15 @@
-use anyhow::{Context, Result, bail};
+use anyhow::{Context, Result, bail, ensure};
 use std::fs::File;
 use std::fs::OpenOptions;
 use std::io::{BufReader, BufWriter, ErrorKind};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
-use cryfs_crypto::kdf::scrypt::{Scrypt, ScryptParams, ScryptSettings};
 use cryfs_utils::progress::ProgressBarManager;
 
 use super::cryconfig::CryConfig;
-use super::encryption::ConfigEncryptionKey;
+use super::encryption::{ConfigEncryptionKey, DecryptedConfig, KeySlot, MAX_KEY_SLOTS};
+use super::kdf::{KdfAlgorithm, KdfSettings};
 
 #[derive(Error, Debug)]
 pub enum CreateConfigFileError {
@@ -34,8 +34,8 @@ pub enum CreateConfigFileError {
     #[error("Error serializing the config file: {0:?}")]
     SerializationError(anyhow::Error),
 
-    #[error("Error generating scrypt parameters: {0:?}")]
-    ScryptError(anyhow::Error),
+    #[error("Error generating KDF parameters: {0:?}")]
+    KdfError(anyhow::Error),
 }
 
 #[derive(Error, Debug)]
@@ -55,8 +55,16 @@ pub enum SaveConfigFileError {
     #[error("Error serializing the config file: {0}")]
     SerializationError(anyhow::Error),
 
-    #[error("Error generating scrypt parameters: {0}")]
-    ScryptError(anyhow::Error),
+    #[error("Error generating KDF parameters: {0}")]
+    KdfError(anyhow::Error),
+
+    #[error(
+        "The config file couldn't be read back after saving it. The previous config file was kept at {backup_path}.\nError: {error}"
+    )]
+    VerificationFailed {
+        backup_path: PathBuf,
+        error: anyhow::Error,
+    },
 }
 
 #[derive(Error, Debug)]
@@ -77,6 +85,23 @@ pub enum LoadConfigFileError {
     DeserializationError(anyhow::Error),
 }
 
+#[derive(Error, Debug)]
+pub enum KeySlotError {
+    #[error("Key slot {index} doesn't exist. The config file has {num_key_slots} key slots.")]
+    NoSuchKeySlot { index: usize, num_key_slots: usize },
+
+    #[error(
+        "Key slot {index} is the key slot that was used to unlock the config file. To remove it, unlock the config file with the password of a different key slot."
+    )]
+    KeySlotInUse { index: usize },
+
+    #[error("The config file already has the maximum number of {MAX_KEY_SLOTS} key slots")]
+    TooManyKeySlots,
+
+    #[error("Error creating key slot: {0:?}")]
+    KdfError(anyhow::Error),
+}
+
 pub enum Access {
     /// Never write to the config file, just read it.
     /// Note that this is only sound if the file system itself
@@ -95,7 +120,8 @@ pub struct CryConfigFile {
     path: PathBuf,
     config: CryConfig,
     access: Access,
-    kdf_parameters: ScryptParams,
+    key_slots: Vec<KeySlot>,
+    unlocked_key_slot: usize,
     config_encryption_key: ConfigEncryptionKey,
     modified: bool,
 }
@@ -105,7 +131,7 @@ impl CryConfigFile {
         path: PathBuf,
         config: CryConfig,
         password: &str,
-        kdf_settings: &ScryptSettings,
+        kdf_settings: &KdfSettings,
         progress_bars: impl ProgressBarManager,
     ) -> Result<CryConfigFile, CreateConfigFileError> {
         let file = OpenOptions::new()
@@ -126,17 +152,16 @@ impl CryConfigFile {
                 // TODO Other possible errors?
                 _ => CreateConfigFileError::IoError(error),
             })?;
-        let kdf_parameters = ScryptParams::generate(kdf_settings)
-            .context("Trying to generate scrypt parameters")
-            .map_err(CreateConfigFileError::ScryptError)?;
-        let config_encryption_key =
-            ConfigEncryptionKey::derive::<Scrypt>(&kdf_parameters, password, progress_bars);
+        let (key_slot, config_encryption_key) =
+            KeySlot::new_derived(kdf_settings, password, progress_bars)
+                .map_err(CreateConfigFileError::KdfError)?;
 
         let result = Self {
             path,
             config,
             config_encryption_key,
-            kdf_parameters,
+            key_slots: vec![key_slot],
+            unlocked_key_slot: 0,
             access: Access::ReadWrite,
             modified: false,
         };
@@ -168,27 +193,35 @@ impl CryConfigFile {
                     // TODO Other possible errors?
                     _ => LoadConfigFileError::IoError(error),
                 })?;
-        let (config_encryption_key, kdf_parameters, config) = super::encryption::decrypt::<Scrypt>(
-            &mut BufReader::new(file),
-            password,
-            progress_bars,
-        )
-        .map_err(LoadConfigFileError::DeserializationError)?;
+        let DecryptedConfig {
+            config_encryption_key,
+            key_slots,
+            unlocked_key_slot,
+            config,
+        } = super::encryption::decrypt(&mut BufReader::new(file), password, progress_bars)
+            .map_err(LoadConfigFileError::DeserializationError)?;
         Ok(Self {
             path,
             config,
             config_encryption_key,
-            kdf_parameters,
+            key_slots,
+            unlocked_key_slot,
             access,
             modified: false,
         })
     }
 
+    /// Write the config file. This is atomic, i.e. if the process crashes while saving,
+    /// the config file will either be the old or the new version.
     pub fn save(&mut self) -> Result<(), SaveConfigFileError> {
+        // Write to a temporary file first and then rename it over the config file so that a crash
+        // can't leave a partially written config file behind.
+        let tmp_path = Self::_sibling_path(&self.path, ".tmp");
         let file = OpenOptions::new()
             .write(true)
+            .create(true)
             .truncate(true)
-            .open(&self.path)
+            .open(&tmp_path)
             .map_err(|error| match error.kind() {
                 ErrorKind::NotFound => SaveConfigFileError::DirectoryComponentDoesntExist {
                     path: self.path.clone(),
@@ -199,14 +232,74 @@ impl CryConfigFile {
                 // TODO Other possible errors?
                 _ => SaveConfigFileError::IoError(error),
             })?;
-        self._write(file)
-            .context("Trying to write config to file")
-            .map_err(SaveConfigFileError::SerializationError)?;
+        if let Err(error) = self._write(file) {
+            let _ = std::fs::remove_file(&tmp_path);
+            return Err(SaveConfigFileError::SerializationError(
+                error.context("Trying to write config to file"),
+            ));
+        }
+        if let Err(error) = std::fs::rename(&tmp_path, &self.path) {
+            let _ = std::fs::remove_file(&tmp_path);
+            return Err(SaveConfigFileError::IoError(error));
+        }
+        Self::_sync_parent_dir(&self.path).map_err(SaveConfigFileError::IoError)?;
         self.modified = false;
 
         Ok(())
     }
 
+    /// Copy the config file as it currently is on disk to a backup file next to it and then [Self::save] it.
+    /// The backup is deleted once the new config file was read back successfully. If that fails, it is
+    /// kept and the error tells where it is.
+    ///
+    /// The backup can still be unlocked with the passwords that were valid before the change, which is why
+    /// we don't leave it lying around.
+    pub fn save_with_backup(&mut self) -> Result<(), SaveConfigFileError> {
+        let backup_path = Self::_sibling_path(&self.path, ".bak");
+        std::fs::copy(&self.path, &backup_path).map_err(|error| match error.kind() {
+            ErrorKind::PermissionDenied => SaveConfigFileError::PermissionDenied { error },
+            _ => SaveConfigFileError::IoError(error),
+        })?;
+        File::open(&backup_path)
+            .and_then(|file| file.sync_all())
+            .map_err(SaveConfigFileError::IoError)?;
+        self.save()?;
+        if let Err(error) = self._verify_saved_config() {
+            return Err(SaveConfigFileError::VerificationFailed { backup_path, error });
+        }
+        std::fs::remove_file(&backup_path).map_err(SaveConfigFileError::IoError)?;
+        Self::_sync_parent_dir(&self.path).map_err(SaveConfigFileError::IoError)?;
+        Ok(())
+    }
+
+    /// Check that the config file on disk decrypts to the config we have in memory
+    fn _verify_saved_config(&self) -> Result<()> {
+        let file = File::open(&self.path).context("Trying to open config file")?;
+        let saved_config = super::encryption::decrypt_with_key(
+            &mut BufReader::new(file),
+            &self.config_encryption_key,
+        )?;
+        ensure!(
+            saved_config == self.config,
+            "The saved config file has different contents than expected"
+        );
+        Ok(())
+    }
+
+    fn _sibling_path(path: &Path, suffix: &str) -> PathBuf {
+        let mut file_name = path.file_name().unwrap_or_default().to_owned();
+        file_name.push(suffix);
+        path.with_file_name(file_name)
+    }
+
+    fn _sync_parent_dir(path: &Path) -> std::io::Result<()> {
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        File::open(parent)?.sync_all()
+    }
+
     pub fn save_if_modified_and_has_readwrite_access(&mut self) -> Result<(), SaveConfigFileError> {
         if self.modified {
             match self.access {
@@ -225,12 +318,114 @@ impl CryConfigFile {
             }
             Access::ReadWrite => (),
         }
-        super::encryption::encrypt::<Scrypt>(
+        let mut writer = BufWriter::new(file);
+        super::encryption::encrypt(
             self.config.clone(),
-            self.kdf_parameters.clone(),
+            &self.key_slots,
             &self.config_encryption_key,
-            &mut BufWriter::new(file),
+            &mut writer,
         )?;
+        let file = writer.into_inner().context("Trying to flush config file")?;
+        file.sync_all().context("Trying to sync config file")?;
+        Ok(())
+    }
+
+    /// The key derivation function of the key slot that was used to unlock this config file
+    pub fn kdf_algorithm(&self) -> KdfAlgorithm {
+        self.key_slots[self.unlocked_key_slot]
+            .kdf_parameters()
+            .algorithm()
+    }
+
+    /// The key slots of this config file. Each key slot allows unlocking the config file with a different password.
+    pub fn key_slots(&self) -> &[KeySlot] {
+        &self.key_slots
+    }
+
+    /// The index of the key slot in [Self::key_slots] that was used to unlock this config file
+    pub fn unlocked_key_slot(&self) -> usize {
+        self.unlocked_key_slot
+    }
+
+    /// Add a key slot that allows unlocking this config file with `password`
+    /// and return its index. This doesn't re-encrypt the file system.
+    /// Call [Self::save] to write the change to the config file.
+    pub fn add_key_slot(
+        &mut self,
+        password: &str,
+        kdf_settings: &KdfSettings,
+        progress_bars: impl ProgressBarManager,
+    ) -> Result<usize, KeySlotError> {
+        if self.key_slots.len() >= MAX_KEY_SLOTS {
+            return Err(KeySlotError::TooManyKeySlots);
+        }
+        let key_slot = KeySlot::new_wrapped(
+            &self.config_encryption_key,
+            kdf_settings,
+            password,
+            progress_bars,
+        )
+        .map_err(KeySlotError::KdfError)?;
+        self.key_slots.push(key_slot);
+        self.modified = true;
+        Ok(self.key_slots.len() - 1)
+    }
+
+    /// Remove the key slot with the given index so its password can't unlock this config file anymore.
+    /// The key slot that was used to unlock the config file can't be removed, which also makes sure
+    /// that the last key slot can't be removed.
+    /// Call [Self::save] to write the change to the config file.
+    pub fn remove_key_slot(&mut self, index: usize) -> Result<(), KeySlotError> {
+        if index >= self.key_slots.len() {
+            return Err(KeySlotError::NoSuchKeySlot {
+                index,
+                num_key_slots: self.key_slots.len(),
+            });
+        }
+        if index == self.unlocked_key_slot {
+            return Err(KeySlotError::KeySlotInUse { index });
+        }
+        self.key_slots.remove(index);
+        if index < self.unlocked_key_slot {
+            self.unlocked_key_slot -= 1;
+        }
+        self.modified = true;
+        Ok(())
+    }
+
+    /// Change the password of the key slot that was used to unlock this config file,
+    /// using fresh KDF parameters generated from `kdf_settings`.
+    ///
+    /// If that's the only key slot, this derives a new [ConfigEncryptionKey] from the new password
+    /// so that the config file stays readable by CryFS versions without key slot support.
+    /// Otherwise, the other key slots still need the current [ConfigEncryptionKey], so we keep it
+    /// and replace the key slot with one that wraps it with the new password.
+    /// In both cases, the file system itself isn't re-encrypted.
+    /// Call [Self::save] or [Self::save_with_backup] to write the change to the config file.
+    pub fn change_password(
+        &mut self,
+        new_password: &str,
+        kdf_settings: &KdfSettings,
+        progress_bars: impl ProgressBarManager,
+    ) -> Result<(), KeySlotError> {
+        if self.key_slots.len() == 1 {
+            let (key_slot, config_encryption_key) =
+                KeySlot::new_derived(kdf_settings, new_password, progress_bars)
+                    .map_err(KeySlotError::KdfError)?;
+            self.key_slots = vec![key_slot];
+            self.unlocked_key_slot = 0;
+            self.config_encryption_key = config_encryption_key;
+        } else {
+            let key_slot = KeySlot::new_wrapped(
+                &self.config_encryption_key,
+                kdf_settings,
+                new_password,
+                progress_bars,
+            )
+            .map_err(KeySlotError::KdfError)?;
+            self.key_slots[self.unlocked_key_slot] = key_slot;
+        }
+        self.modified = true;
         Ok(())
     }
 
@@ -248,4 +443,517 @@ impl CryConfigFile {
     }
 }
 
-// TODO Tests, including error cases
+// TODO More tests, including error cases
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::FilesystemId;
+    use byte_unit::Byte;
+    use cryfs_blockstore::CompressionAlgorithm;
+    use cryfs_crypto::kdf::{argon2id::Argon2idSettings, scrypt::ScryptSettings};
+    use cryfs_utils::progress::SilentProgressBarManager;
+
+    fn config() -> CryConfig {
+        CryConfig {
+            root_blob: "6A3155A8017B5A8AC2B7847BAA5663DE".to_string(),
+            enc_key: "6B787D71DE64168DFC4C994046FBABB936B2CFE1629F6772F8294D3955FF8CC0".to_string(),
+            cipher: "aes-256-gcm".to_string(),
+            format_version: "0.10".to_string(),
+            created_with_version: "0.10.2".to_string(),
+            last_opened_with_version: "0.11.1".to_string(),
+            blocksize: Byte::from_u64_with_unit(16, byte_unit::Unit::KiB).unwrap(),
+            filesystem_id: FilesystemId::from_hex("B364DB327ED401F22E99EB37E78FABDC").unwrap(),
+            exclusive_client_id: None,
+            compression: None,
+        }
+    }
+
+    fn assert_create_and_load(kdf_settings: KdfSettings, expected_algorithm: KdfAlgorithm) {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("cryfs.config");
+        let created = CryConfigFile::create_new(
+            path.clone(),
+            config(),
+            "mypassword",
+            &kdf_settings,
+            SilentProgressBarManager,
+        )
+        .unwrap();
+        assert_eq!(expected_algorithm, created.kdf_algorithm());
+
+        let loaded = CryConfigFile::load(
+            path,
+            "mypassword",
+            Access::ReadOnly,
+            SilentProgressBarManager,
+        )
+        .unwrap();
+        assert_eq!(expected_algorithm, loaded.kdf_algorithm());
+        assert_eq!(&config(), loaded.config());
+    }
+
+    #[test]
+    fn create_and_load_scrypt() {
+        assert_create_and_load(
+            KdfSettings::Scrypt(ScryptSettings::TEST),
+            KdfAlgorithm::Scrypt,
+        );
+    }
+
+    #[test]
+    fn create_and_load_argon2id() {
+        assert_create_and_load(
+            KdfSettings::Argon2id(Argon2idSettings::TEST),
+            KdfAlgorithm::Argon2id,
+        );
+    }
+
+    #[test]
+    fn create_and_load_with_compression() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("cryfs.config");
+        let config = CryConfig {
+            compression: Some(CompressionAlgorithm::Zstd),
+            ..config()
+        };
+        CryConfigFile::create_new(
+            path.clone(),
+            config.clone(),
+            "mypassword",
+            &KdfSettings::Scrypt(ScryptSettings::TEST),
+            SilentProgressBarManager,
+        )
+        .unwrap();
+
+        let loaded = CryConfigFile::load(
+            path,
+            "mypassword",
+            Access::ReadOnly,
+            SilentProgressBarManager,
+        )
+        .unwrap();
+        assert_eq!(&config, loaded.config());
+    }
+
+    #[test]
+    fn create_and_load_with_extended_format() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("cryfs.config");
+        assert!(!config().has_extended_format());
+        let config = CryConfig {
+            format_version: "1.1".to_string(),
+            ..config()
+        };
+        CryConfigFile::create_new(
+            path.clone(),
+            config.clone(),
+            "mypassword",
+            &KdfSettings::Scrypt(ScryptSettings::TEST),
+            SilentProgressBarManager,
+        )
+        .unwrap();
+
+        let loaded = CryConfigFile::load(
+            path,
+            "mypassword",
+            Access::ReadOnly,
+            SilentProgressBarManager,
+        )
+        .unwrap();
+        assert_eq!(&config, loaded.config());
+        assert!(loaded.config().has_extended_format());
+    }
+
+    #[test]
+    fn compression_is_only_serialized_if_enabled() {
+        // Keeps file systems without compression readable by older CryFS versions
+        let mut serialized = Vec::new();
+        config().serialize(&mut serialized).unwrap();
+        assert!(
+            !String::from_utf8(serialized)
+                .unwrap()
+                .contains("compression")
+        );
+
+        let mut serialized = Vec::new();
+        CryConfig {
+            compression: Some(CompressionAlgorithm::Lz4),
+            ..config()
+        }
+        .serialize(&mut serialized)
+        .unwrap();
+        assert!(
+            String::from_utf8(serialized)
+                .unwrap()
+                .contains(r#""compression":"lz4""#)
+        );
+    }
+
+    #[test]
+    fn save_keeps_kdf() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("cryfs.config");
+        CryConfigFile::create_new(
+            path.clone(),
+            config(),
+            "mypassword",
+            &KdfSettings::Argon2id(Argon2idSettings::TEST),
+            SilentProgressBarManager,
+        )
+        .unwrap();
+
+        let mut loaded = CryConfigFile::load(
+            path.clone(),
+            "mypassword",
+            Access::ReadWrite,
+            SilentProgressBarManager,
+        )
+        .unwrap();
+        loaded.config_mut().last_opened_with_version = "1.0.0".to_string();
+        loaded.save().unwrap();
+
+        let reloaded = CryConfigFile::load(
+            path,
+            "mypassword",
+            Access::ReadOnly,
+            SilentProgressBarManager,
+        )
+        .unwrap();
+        assert_eq!(KdfAlgorithm::Argon2id, reloaded.kdf_algorithm());
+        assert_eq!("1.0.0", reloaded.config().last_opened_with_version);
+    }
+
+    fn create_with_two_key_slots(path: PathBuf) {
+        let mut created = CryConfigFile::create_new(
+            path,
+            config(),
+            "password_1",
+            &KdfSettings::Scrypt(ScryptSettings::TEST),
+            SilentProgressBarManager,
+        )
+        .unwrap();
+        let index = created
+            .add_key_slot(
+                "password_2",
+                &KdfSettings::Argon2id(Argon2idSettings::TEST),
+                SilentProgressBarManager,
+            )
+            .unwrap();
+        assert_eq!(1, index);
+        created.save().unwrap();
+    }
+
+    #[test]
+    fn added_key_slot_unlocks_config() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("cryfs.config");
+        create_with_two_key_slots(path.clone());
+
+        for (password, expected_slot, expected_algorithm) in [
+            ("password_1", 0, KdfAlgorithm::Scrypt),
+            ("password_2", 1, KdfAlgorithm::Argon2id),
+        ] {
+            let loaded = CryConfigFile::load(
+                path.clone(),
+                password,
+                Access::ReadOnly,
+                SilentProgressBarManager,
+            )
+            .unwrap();
+            assert_eq!(2, loaded.key_slots().len());
+            assert_eq!(expected_slot, loaded.unlocked_key_slot());
+            assert_eq!(expected_algorithm, loaded.kdf_algorithm());
+            assert_eq!(&config(), loaded.config());
+        }
+    }
+
+    #[test]
+    fn removed_key_slot_doesnt_unlock_config() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("cryfs.config");
+        create_with_two_key_slots(path.clone());
+
+        let mut loaded = CryConfigFile::load(
+            path.clone(),
+            "password_2",
+            Access::ReadWrite,
+            SilentProgressBarManager,
+        )
+        .unwrap();
+        loaded.remove_key_slot(0).unwrap();
+        assert_eq!(0, loaded.unlocked_key_slot());
+        loaded.save().unwrap();
+
+        assert!(
+            CryConfigFile::load(
+                path.clone(),
+                "password_1",
+                Access::ReadOnly,
+                SilentProgressBarManager,
+            )
+            .is_err()
+        );
+        let loaded = CryConfigFile::load(
+            path,
+            "password_2",
+            Access::ReadOnly,
+            SilentProgressBarManager,
+        )
+        .unwrap();
+        assert_eq!(1, loaded.key_slots().len());
+        assert_eq!(&config(), loaded.config());
+    }
+
+    #[test]
+    fn removing_last_wrapped_key_slot_restores_old_format() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("cryfs.config");
+        create_with_two_key_slots(path.clone());
+        assert!(
+            std::fs::read(&path)
+                .unwrap()
+                .starts_with(b"cryfs.config;2;")
+        );
+
+        let mut loaded = CryConfigFile::load(
+            path.clone(),
+            "password_1",
+            Access::ReadWrite,
+            SilentProgressBarManager,
+        )
+        .unwrap();
+        loaded.remove_key_slot(1).unwrap();
+        loaded.save().unwrap();
+
+        assert!(
+            std::fs::read(&path)
+                .unwrap()
+                .starts_with(b"cryfs.config;1;scrypt\0")
+        );
+    }
+
+    #[test]
+    fn cannot_remove_key_slot_in_use() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("cryfs.config");
+        let mut created = CryConfigFile::create_new(
+            path,
+            config(),
+            "mypassword",
+            &KdfSettings::Scrypt(ScryptSettings::TEST),
+            SilentProgressBarManager,
+        )
+        .unwrap();
+        assert!(matches!(
+            created.remove_key_slot(0),
+            Err(KeySlotError::KeySlotInUse { index: 0 })
+        ));
+        assert!(matches!(
+            created.remove_key_slot(1),
+            Err(KeySlotError::NoSuchKeySlot {
+                index: 1,
+                num_key_slots: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn cannot_add_more_than_max_key_slots() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("cryfs.config");
+        let mut created = CryConfigFile::create_new(
+            path,
+            config(),
+            "mypassword",
+            &KdfSettings::Scrypt(ScryptSettings::TEST),
+            SilentProgressBarManager,
+        )
+        .unwrap();
+        for _ in 1..MAX_KEY_SLOTS {
+            created
+                .add_key_slot(
+                    "otherpassword",
+                    &KdfSettings::Scrypt(ScryptSettings::TEST),
+                    SilentProgressBarManager,
+                )
+                .unwrap();
+        }
+        created.save().unwrap();
+        assert!(matches!(
+            created.add_key_slot(
+                "otherpassword",
+                &KdfSettings::Scrypt(ScryptSettings::TEST),
+                SilentProgressBarManager,
+            ),
+            Err(KeySlotError::TooManyKeySlots)
+        ));
+    }
+
+    #[test]
+    fn change_password_with_single_key_slot() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("cryfs.config");
+        CryConfigFile::create_new(
+            path.clone(),
+            config(),
+            "old_password",
+            &KdfSettings::Scrypt(ScryptSettings::TEST),
+            SilentProgressBarManager,
+        )
+        .unwrap();
+
+        let mut loaded = CryConfigFile::load(
+            path.clone(),
+            "old_password",
+            Access::ReadWrite,
+            SilentProgressBarManager,
+        )
+        .unwrap();
+        loaded
+            .change_password(
+                "new_password",
+                &KdfSettings::Argon2id(Argon2idSettings::TEST),
+                SilentProgressBarManager,
+            )
+            .unwrap();
+        loaded.save().unwrap();
+
+        // Still stored in the format without key slot support
+        assert!(
+            std::fs::read(&path)
+                .unwrap()
+                .starts_with(b"cryfs.config;1;argon2id\0")
+        );
+        assert!(
+            CryConfigFile::load(
+                path.clone(),
+                "old_password",
+                Access::ReadOnly,
+                SilentProgressBarManager,
+            )
+            .is_err()
+        );
+        let reloaded = CryConfigFile::load(
+            path,
+            "new_password",
+            Access::ReadOnly,
+            SilentProgressBarManager,
+        )
+        .unwrap();
+        assert_eq!(KdfAlgorithm::Argon2id, reloaded.kdf_algorithm());
+        assert_eq!(&config(), reloaded.config());
+    }
+
+    #[test]
+    fn change_password_keeps_other_key_slots() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("cryfs.config");
+        create_with_two_key_slots(path.clone());
+
+        let mut loaded = CryConfigFile::load(
+            path.clone(),
+            "password_1",
+            Access::ReadWrite,
+            SilentProgressBarManager,
+        )
+        .unwrap();
+        loaded
+            .change_password(
+                "new_password",
+                &KdfSettings::Scrypt(ScryptSettings::TEST),
+                SilentProgressBarManager,
+            )
+            .unwrap();
+        loaded.save().unwrap();
+
+        assert!(
+            CryConfigFile::load(
+                path.clone(),
+                "password_1",
+                Access::ReadOnly,
+                SilentProgressBarManager,
+            )
+            .is_err()
+        );
+        for (password, expected_slot) in [("new_password", 0), ("password_2", 1)] {
+            let reloaded = CryConfigFile::load(
+                path.clone(),
+                password,
+                Access::ReadOnly,
+                SilentProgressBarManager,
+            )
+            .unwrap();
+            assert_eq!(2, reloaded.key_slots().len());
+            assert_eq!(expected_slot, reloaded.unlocked_key_slot());
+            assert_eq!(&config(), reloaded.config());
+        }
+    }
+
+    #[test]
+    fn save_doesnt_leave_temporary_file() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("cryfs.config");
+        let mut created = CryConfigFile::create_new(
+            path.clone(),
+            config(),
+            "mypassword",
+            &KdfSettings::Scrypt(ScryptSettings::TEST),
+            SilentProgressBarManager,
+        )
+        .unwrap();
+        created.config_mut().last_opened_with_version = "1.0.0".to_string();
+        created.save().unwrap();
+
+        let files: Vec<_> = std::fs::read_dir(tempdir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(vec![std::ffi::OsString::from("cryfs.config")], files);
+    }
+
+    #[test]
+    fn save_with_backup_removes_backup_after_saving() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("cryfs.config");
+        CryConfigFile::create_new(
+            path.clone(),
+            config(),
+            "old_password",
+            &KdfSettings::Scrypt(ScryptSettings::TEST),
+            SilentProgressBarManager,
+        )
+        .unwrap();
+
+        let mut loaded = CryConfigFile::load(
+            path.clone(),
+            "old_password",
+            Access::ReadWrite,
+            SilentProgressBarManager,
+        )
+        .unwrap();
+        loaded
+            .change_password(
+                "new_password",
+                &KdfSettings::Scrypt(ScryptSettings::TEST),
+                SilentProgressBarManager,
+            )
+            .unwrap();
+        loaded.save_with_backup().unwrap();
+
+        // The backup could still be unlocked with the old password, so it must be gone
+        assert!(!tempdir.path().join("cryfs.config.bak").exists());
+        let files: Vec<_> = std::fs::read_dir(tempdir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(vec![std::ffi::OsString::from("cryfs.config")], files);
+        CryConfigFile::load(
+            path,
+            "new_password",
+            Access::ReadOnly,
+            SilentProgressBarManager,
+        )
+        .unwrap();
+    }
+}
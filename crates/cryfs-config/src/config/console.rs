@@ -2,7 +2,7 @@ use anyhow::Result;
 use byte_unit::Byte;
 use std::path::Path;
 
-use cryfs_crypto::kdf::scrypt::ScryptSettings;
+use cryfs_crypto::kdf::{argon2id::Argon2idSettings, scrypt::ScryptSettings};
 use cryfs_version::{Version, VersionInfo};
 
 /// Interface for cryfs to interact with the user, e.g. ask questions and get answers on the terminal
@@ -42,6 +42,9 @@ pub trait Console {
     /// We're in the process of creating a new file system and need to ask the user for the scrypt settings to use
     fn ask_scrypt_settings_for_new_filesystem(&self) -> Result<ScryptSettings>;
 
+    /// We're in the process of creating a new file system with argon2id as KDF and need to ask the user for the argon2id settings to use
+    fn ask_argon2id_settings_for_new_filesystem(&self) -> Result<Argon2idSettings>;
+
     /// We're in the process of creating a new file system and need to ask the user for the cipher to use
     /// TODO Probably it's better to have an enum for all the ciphers we support and return that here instead of returning a string. This would also need us to change `ciphers.rs` and the lookup code probably.
     fn ask_cipher_for_new_filesystem(&self) -> Result<String>;
@@ -7,7 +7,7 @@ use super::CryConfig;
 use super::ciphers::{SyncCipherCallback, lookup_cipher_sync};
 use super::console::Console;
 use super::loader::{CRYFS_VERSION, CommandLineFlags};
-use crate::config::{FILESYSTEM_FORMAT_VERSION, FilesystemId};
+use crate::config::{EXTENDED_FILESYSTEM_FORMAT_VERSION, FILESYSTEM_FORMAT_VERSION, FilesystemId};
 use crate::localstate::{FilesystemMetadata, LocalStateDir};
 use cryfs_blobstore::BlobId;
 use cryfs_blockstore::ClientId;
@@ -68,13 +68,18 @@ pub fn create(
         root_blob: _generate_root_blob_id().to_hex(),
         enc_key: enc_key.to_hex(),
         cipher: cipher_name,
-        format_version: FILESYSTEM_FORMAT_VERSION.to_string(),
+        format_version: if command_line_flags.extended_format {
+            EXTENDED_FILESYSTEM_FORMAT_VERSION.to_string()
+        } else {
+            FILESYSTEM_FORMAT_VERSION.to_string()
+        },
         created_with_version: CRYFS_VERSION.to_string(),
         last_opened_with_version: CRYFS_VERSION.to_string(),
         // TODO Check block size is valid (i.e. large enough)
         blocksize,
         filesystem_id,
         exclusive_client_id,
+        compression: command_line_flags.compression,
     };
 
     Ok(ConfigCreateResult {
@@ -2,13 +2,21 @@ use byte_unit::Byte;
 use serde::{Deserialize, Serialize};
 use std::io::{Read, Write};
 
+use cryfs_blockstore::CompressionAlgorithm;
 use cryfs_version::Version;
 
 use super::filesystem_id::FilesystemId;
 
+/// Format version of file systems that CryFS 1.0 can open
 pub const FILESYSTEM_FORMAT_VERSION: Version<&'static str> =
     konst::result::unwrap!(Version::parse_const("0.10"));
 
+/// Format version of file systems that use features CryFS 1.0 doesn't know about, e.g. extended attributes,
+/// special files (fifos, sockets, devices), sparse files or files sharing data after `copy_file_range`.
+/// CryFS 1.0 refuses to open these file systems because the format version is too new for it.
+pub const EXTENDED_FILESYSTEM_FORMAT_VERSION: Version<&'static str> =
+    konst::result::unwrap!(Version::parse_const("1.1"));
+
 /// Configuration for a CryFS file system. This is stored in the cryfs.config file.
 /// Note that the `Serialize`, `Deserialize` implementations here are **not** used to (de)serialize the config file. That happens in [super::serialization]
 /// // TODO Do we need this to be clone?
@@ -52,6 +60,19 @@ pub struct CryConfig {
     /// Because this only works in a single-client setting, only this one client Id is allowed to access the file system.
     /// TODO Store this as an instance of `ClientId` instead of `u32`
     pub exclusive_client_id: Option<u32>,
+
+    /// Algorithm used to compress blocks before encrypting them, or `None` if blocks aren't compressed.
+    /// This is set when the file system is created. File systems with compression can't be opened
+    /// by CryFS versions that don't know about this field.
+    ///
+    /// Without compression, all blocks of a file except for the last one have the same size on disk
+    /// ([CryConfig::blocksize]), so the only thing an attacker learns about a file is roughly how large it is.
+    /// Compressed blocks aren't padded back to the block size, because that would undo the space savings.
+    /// This means an attacker can see how well each block compresses, which tells them something about
+    /// the kind of data stored in it (e.g. text vs. images) and, if they can influence some of the data
+    /// written to a block, potentially more (see the CRIME and BREACH attacks). Don't enable compression
+    /// if that is a concern.
+    pub compression: Option<CompressionAlgorithm>,
 }
 
 impl CryConfig {
@@ -68,4 +89,11 @@ impl CryConfig {
     pub fn missing_block_is_integrity_violation(&self) -> bool {
         return self.exclusive_client_id.is_some();
     }
+
+    /// Whether the file system uses the [EXTENDED_FILESYSTEM_FORMAT_VERSION] and is allowed to use features
+    /// that CryFS 1.0 can't read.
+    pub fn has_extended_format(&self) -> bool {
+        Version::parse(&self.format_version)
+            .is_ok_and(|format_version| format_version == EXTENDED_FILESYSTEM_FORMAT_VERSION)
+    }
 }
@@ -1,5 +1,5 @@
 mod cryconfig;
-pub use cryconfig::{CryConfig, FILESYSTEM_FORMAT_VERSION};
+pub use cryconfig::{CryConfig, EXTENDED_FILESYSTEM_FORMAT_VERSION, FILESYSTEM_FORMAT_VERSION};
 
 mod serialization;
 
@@ -5,6 +5,7 @@ use std::cmp::Ordering;
 use std::io::{Read, Write};
 use thiserror::Error;
 
+use cryfs_blockstore::CompressionAlgorithm;
 use cryfs_version::Version;
 
 use super::cryconfig::CryConfig;
@@ -49,6 +50,7 @@ pub fn serialize(config: CryConfig, writer: impl Write) -> Result<(), serde_json
                 blocksize_bytes: Some(config.blocksize.as_u64()),
                 filesystem_id: config.filesystem_id.to_hex(),
                 exclusive_client_id: config.exclusive_client_id,
+                compression: config.compression,
 
                 migrations: Some(SerializableCryConfigInnerMigrations {
                     // This is a trigger to recognize old file systems that didn't have version numbers.
@@ -135,6 +137,7 @@ pub fn deserialize(reader: impl Read) -> Result<CryConfig, DeserializationError>
         blocksize,
         filesystem_id,
         exclusive_client_id: config.cryfs.exclusive_client_id,
+        compression: config.cryfs.compression,
     })
 }
 
@@ -153,15 +156,17 @@ fn check_format_version(
         }
     })?;
 
+    if format_version == super::FILESYSTEM_FORMAT_VERSION
+        || format_version == super::EXTENDED_FILESYSTEM_FORMAT_VERSION
+    {
+        // TODO Return version as `Version` object instead of String (but make sure we still only serialize major.minor, no patch version)
+        return Ok(format_version_string.to_owned());
+    }
     match format_version.cmp(&super::FILESYSTEM_FORMAT_VERSION) {
-        Ordering::Equal => {
-            // TODO Return version as `Version` object instead of String (but make sure we still only serialize major.minor, no patch version)
-            Ok(format_version_string.to_owned())
-        }
-        Ordering::Greater => Err(DeserializationError::VersionTooNew {
+        Ordering::Less => Err(DeserializationError::VersionTooOld {
             read_version: format_version_string.to_owned(),
         }),
-        Ordering::Less => Err(DeserializationError::VersionTooOld {
+        Ordering::Equal | Ordering::Greater => Err(DeserializationError::VersionTooNew {
             read_version: format_version_string.to_owned(),
         }),
     }
@@ -206,6 +211,16 @@ struct SerializableCryConfigInner {
     #[serde_as(as = "Option<DisplayFromStr>")]
     exclusive_client_id: Option<u32>,
 
+    /// Older CryFS versions didn't have this field. It is only written if compression is enabled,
+    /// so that file systems without compression stay readable by older versions.
+    #[serde(
+        rename = "compression",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    compression: Option<CompressionAlgorithm>,
+
     migrations: Option<SerializableCryConfigInnerMigrations>,
 }
 
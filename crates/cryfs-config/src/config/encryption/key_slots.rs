@@ -0,0 +1,246 @@
+use anyhow::{Context, Result, ensure};
+use lockable::InfallibleUnwrap;
+
+use cryfs_crypto::symmetric::{Aes256Gcm, Cipher, CipherDef, EncryptionKey};
+use cryfs_utils::{
+    data::Data,
+    progress::{ProgressBarManager, Spinner},
+};
+
+use super::ConfigEncryptionKey;
+use crate::config::kdf::{KdfParams, KdfSettings};
+
+/// Cipher used to encrypt the [ConfigEncryptionKey] in a [KeySlot] with a key derived from that slot's password
+type KeySlotCipher = Aes256Gcm;
+
+/// Maximal number of key slots a config file can have
+pub const MAX_KEY_SLOTS: usize = 8;
+
+/// A key slot allows unlocking a config file with a password, similar to LUKS key slots.
+///
+/// There are two kinds of key slots:
+/// * A derived key slot derives the [ConfigEncryptionKey] directly from the password.
+///   This is how CryFS always encrypted its config files and config files having only
+///   a single derived key slot are stored in a format that older CryFS versions can read.
+/// * A wrapped key slot derives a key from the password and uses it to encrypt
+///   the [ConfigEncryptionKey]. This allows adding more passwords for an existing config file
+///   without having to re-encrypt the config file or the file system.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeySlot {
+    kdf_parameters: KdfParams,
+    kind: KeySlotKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum KeySlotKind {
+    Derived,
+    Wrapped { encrypted_key: Vec<u8> },
+}
+
+impl KeySlot {
+    /// Create a derived key slot for a new config file, together with the [ConfigEncryptionKey] derived from the password.
+    pub fn new_derived(
+        kdf_settings: &KdfSettings,
+        password: &str,
+        progress_bars: impl ProgressBarManager,
+    ) -> Result<(Self, ConfigEncryptionKey)> {
+        let kdf_parameters =
+            KdfParams::generate(kdf_settings).context("Trying to generate KDF parameters")?;
+        let config_encryption_key =
+            ConfigEncryptionKey::derive(&kdf_parameters, password, progress_bars);
+        let key_slot = Self {
+            kdf_parameters,
+            kind: KeySlotKind::Derived,
+        };
+        Ok((key_slot, config_encryption_key))
+    }
+
+    /// Create a wrapped key slot that allows unlocking `config_encryption_key` with `password`
+    pub fn new_wrapped(
+        config_encryption_key: &ConfigEncryptionKey,
+        kdf_settings: &KdfSettings,
+        password: &str,
+        progress_bars: impl ProgressBarManager,
+    ) -> Result<Self> {
+        let kdf_parameters =
+            KdfParams::generate(kdf_settings).context("Trying to generate KDF parameters")?;
+        let cipher = KeySlotCipher::new(Self::_derive_slot_key(
+            &kdf_parameters,
+            password,
+            progress_bars,
+        ))
+        .context("Trying to initialize KeySlotCipher instance")?;
+
+        // TODO The key is unprotected in memory while it is in this `Data` object. Avoid this.
+        let key_bytes = config_encryption_key.combined_key.as_bytes();
+        let mut plaintext = Data::allocate(
+            KeySlotCipher::CIPHERTEXT_OVERHEAD_PREFIX,
+            key_bytes.len(),
+            KeySlotCipher::CIPHERTEXT_OVERHEAD_SUFFIX,
+        );
+        plaintext.as_mut().copy_from_slice(key_bytes);
+        let encrypted_key = cipher
+            .encrypt(plaintext)
+            .context("Trying to encrypt the config encryption key")?;
+
+        Ok(Self {
+            kdf_parameters,
+            kind: KeySlotKind::Wrapped {
+                encrypted_key: encrypted_key.into_vec(),
+            },
+        })
+    }
+
+    /// Get the [ConfigEncryptionKey] for this key slot from the password.
+    ///
+    /// For wrapped key slots, this returns `None` if the password is wrong.
+    /// For derived key slots, we can't know if the password is right before we actually
+    /// try to decrypt the config file with the returned key, so this always returns a key.
+    pub(super) fn unlock(
+        &self,
+        password: &str,
+        progress_bars: impl ProgressBarManager,
+    ) -> Result<Option<ConfigEncryptionKey>> {
+        match &self.kind {
+            KeySlotKind::Derived => Ok(Some(ConfigEncryptionKey::derive(
+                &self.kdf_parameters,
+                password,
+                progress_bars,
+            ))),
+            KeySlotKind::Wrapped { encrypted_key } => {
+                let cipher = KeySlotCipher::new(Self::_derive_slot_key(
+                    &self.kdf_parameters,
+                    password,
+                    progress_bars,
+                ))
+                .context("Trying to initialize KeySlotCipher instance")?;
+                let Ok(plaintext) = cipher.decrypt(encrypted_key.clone().into()) else {
+                    // Wrong password
+                    return Ok(None);
+                };
+                ensure!(
+                    plaintext.len() == ConfigEncryptionKey::COMBINED_KEY_SIZE,
+                    "Key slot contains a key with {} bytes but expected {} bytes",
+                    plaintext.len(),
+                    ConfigEncryptionKey::COMBINED_KEY_SIZE,
+                );
+                let combined_key = EncryptionKey::new(plaintext.len(), |key_data| {
+                    key_data.copy_from_slice(&plaintext);
+                    Ok(())
+                })
+                .infallible_unwrap();
+                Ok(Some(ConfigEncryptionKey { combined_key }))
+            }
+        }
+    }
+
+    fn _derive_slot_key(
+        kdf_parameters: &KdfParams,
+        password: &str,
+        progress_bars: impl ProgressBarManager,
+    ) -> EncryptionKey {
+        eprintln!();
+        let pb = progress_bars.new_spinner_autotick("Deriving key from password");
+        let slot_key = kdf_parameters.derive_key(KeySlotCipher::KEY_SIZE, password);
+        pb.finish();
+        slot_key
+    }
+
+    /// The KDF parameters used to derive a key from this slot's password
+    pub fn kdf_parameters(&self) -> &KdfParams {
+        &self.kdf_parameters
+    }
+
+    /// Whether this is a derived key slot, i.e. the [ConfigEncryptionKey] is derived directly from the password.
+    /// See [KeySlot] for details.
+    pub fn is_derived(&self) -> bool {
+        matches!(self.kind, KeySlotKind::Derived)
+    }
+
+    pub(super) fn encrypted_key(&self) -> Option<&[u8]> {
+        match &self.kind {
+            KeySlotKind::Derived => None,
+            KeySlotKind::Wrapped { encrypted_key } => Some(encrypted_key),
+        }
+    }
+
+    pub(super) fn from_parts(kdf_parameters: KdfParams, encrypted_key: Option<Vec<u8>>) -> Self {
+        let kind = match encrypted_key {
+            None => KeySlotKind::Derived,
+            Some(encrypted_key) => KeySlotKind::Wrapped { encrypted_key },
+        };
+        Self {
+            kdf_parameters,
+            kind,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryfs_crypto::kdf::{argon2id::Argon2idSettings, scrypt::ScryptSettings};
+    use cryfs_utils::progress::SilentProgressBarManager;
+
+    #[test]
+    fn derived_key_slot_unlocks_to_derived_key() {
+        let (key_slot, key) = KeySlot::new_derived(
+            &KdfSettings::Scrypt(ScryptSettings::TEST),
+            "mypassword",
+            SilentProgressBarManager,
+        )
+        .unwrap();
+        assert!(key_slot.is_derived());
+        let unlocked = key_slot
+            .unlock("mypassword", SilentProgressBarManager)
+            .unwrap()
+            .unwrap();
+        assert_eq!(key, unlocked);
+    }
+
+    #[test]
+    fn wrapped_key_slot_unlocks_with_correct_password() {
+        let (_, key) = KeySlot::new_derived(
+            &KdfSettings::Scrypt(ScryptSettings::TEST),
+            "mypassword",
+            SilentProgressBarManager,
+        )
+        .unwrap();
+        let key_slot = KeySlot::new_wrapped(
+            &key,
+            &KdfSettings::Argon2id(Argon2idSettings::TEST),
+            "otherpassword",
+            SilentProgressBarManager,
+        )
+        .unwrap();
+        assert!(!key_slot.is_derived());
+        let unlocked = key_slot
+            .unlock("otherpassword", SilentProgressBarManager)
+            .unwrap()
+            .unwrap();
+        assert_eq!(key, unlocked);
+    }
+
+    #[test]
+    fn wrapped_key_slot_doesnt_unlock_with_wrong_password() {
+        let (_, key) = KeySlot::new_derived(
+            &KdfSettings::Scrypt(ScryptSettings::TEST),
+            "mypassword",
+            SilentProgressBarManager,
+        )
+        .unwrap();
+        let key_slot = KeySlot::new_wrapped(
+            &key,
+            &KdfSettings::Scrypt(ScryptSettings::TEST),
+            "otherpassword",
+            SilentProgressBarManager,
+        )
+        .unwrap();
+        assert!(
+            key_slot
+                .unlock("mypassword", SilentProgressBarManager)
+                .unwrap()
+                .is_none()
+        );
+    }
+}
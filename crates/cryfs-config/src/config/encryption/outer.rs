@@ -1,19 +1,20 @@
-use anyhow::{Context, Result, ensure};
+use anyhow::{Context, Result, bail, ensure};
 use binrw::{BinRead, BinWrite, NullString, binrw, helpers::until_eof};
 use std::io::{Cursor, Read, Seek, Write};
 
-use cryfs_crypto::{
-    kdf::KDFParameters,
-    symmetric::{Cipher, CipherDef, EncryptionKey},
-};
+use cryfs_crypto::symmetric::{Cipher, CipherDef, EncryptionKey};
 use cryfs_utils::data::Data;
 
+use super::key_slots::{KeySlot, MAX_KEY_SLOTS};
 use super::padding::{add_padding, remove_padding};
 use super::{inner::InnerConfig, padding::PADDING_OVERHEAD_PREFIX};
+use crate::config::kdf::{KdfAlgorithm, KdfParams};
 
-const HEADER: &str = "cryfs.config;1;scrypt";
-
-// TODO Add argon as an alternative to scrypt
+// Config files with a single derived key slot are stored in the format older CryFS versions can read.
+// Its header is this prefix followed by the name of the KDF, e.g. "cryfs.config;1;scrypt"
+const HEADER_V1_PREFIX: &str = "cryfs.config;1;";
+// Config files with any other combination of key slots are stored in a format that can store multiple key slots.
+const HEADER_V2: &str = "cryfs.config;2;keyslots";
 
 pub type OuterCipher = cryfs_crypto::symmetric::Aes256Gcm;
 
@@ -22,9 +23,13 @@ const CONFIG_SIZE: usize = 1024;
 
 #[binrw]
 #[brw(little)]
-struct OuterConfigLayout {
+struct OuterConfigHeader {
     header: NullString,
+}
 
+#[binrw]
+#[brw(little)]
+struct OuterConfigLayoutV1 {
     kdf_parameters_num_bytes: u64,
     #[br(count = kdf_parameters_num_bytes)]
     kdf_parameters_serialized: Vec<u8>,
@@ -34,8 +39,75 @@ struct OuterConfigLayout {
     // TODO Actually storing these Vecs in an `OuterConfigLayout` object means we have to allocate them during (de)serialization. This could be avoided. Maybe by using a `Serializer/Deserializer` system as C++ CryFS cpp-utils had it
 }
 
-/// Wraps an [InnerConfig] instance and encrypts it, then prepends the KDF parameters that were used
-/// and a fixed header.
+#[binrw]
+#[brw(little)]
+struct OuterConfigLayoutV2 {
+    num_key_slots: u32,
+    #[br(count = num_key_slots)]
+    key_slots: Vec<KeySlotLayout>,
+
+    #[br(parse_with = until_eof)]
+    encrypted_inner_config: Vec<u8>,
+}
+
+#[binrw]
+#[brw(little)]
+struct KeySlotLayout {
+    kdf_name: NullString,
+
+    kdf_parameters_num_bytes: u64,
+    #[br(count = kdf_parameters_num_bytes)]
+    kdf_parameters_serialized: Vec<u8>,
+
+    // 0 for derived key slots, 1 for wrapped key slots
+    kind: u8,
+
+    encrypted_key_num_bytes: u64,
+    #[br(count = encrypted_key_num_bytes)]
+    encrypted_key: Vec<u8>,
+}
+
+const KEY_SLOT_KIND_DERIVED: u8 = 0;
+const KEY_SLOT_KIND_WRAPPED: u8 = 1;
+
+impl KeySlotLayout {
+    fn from_key_slot(key_slot: &KeySlot) -> Self {
+        let kdf_parameters_serialized = key_slot.kdf_parameters().serialize();
+        let (kind, encrypted_key) = match key_slot.encrypted_key() {
+            None => (KEY_SLOT_KIND_DERIVED, vec![]),
+            Some(encrypted_key) => (KEY_SLOT_KIND_WRAPPED, encrypted_key.to_vec()),
+        };
+        Self {
+            kdf_name: key_slot.kdf_parameters().algorithm().name().into(),
+            kdf_parameters_num_bytes: kdf_parameters_serialized.len() as u64,
+            kdf_parameters_serialized,
+            kind,
+            encrypted_key_num_bytes: encrypted_key.len() as u64,
+            encrypted_key,
+        }
+    }
+
+    fn into_key_slot(self) -> Result<KeySlot> {
+        let kdf_name: String = self
+            .kdf_name
+            .try_into()
+            .context("KDF name is not valid UTF-8")?;
+        let kdf_parameters = KdfParams::deserialize(
+            KdfAlgorithm::from_name(&kdf_name)?,
+            &self.kdf_parameters_serialized,
+        )
+        .context("Trying to deserialize KDF parameters")?;
+        let encrypted_key = match self.kind {
+            KEY_SLOT_KIND_DERIVED => None,
+            KEY_SLOT_KIND_WRAPPED => Some(self.encrypted_key),
+            kind => bail!("Invalid key slot kind {kind}"),
+        };
+        Ok(KeySlot::from_parts(kdf_parameters, encrypted_key))
+    }
+}
+
+/// Wraps an [InnerConfig] instance and encrypts it, then prepends the key slots
+/// that can be used to unlock it and a header describing the format.
 ///
 /// Common usage patterns are:
 /// * When loading a cryfs config file, call first [OuterConfig::deserialize] to get an [OuterConfig] instance,
@@ -43,14 +115,14 @@ struct OuterConfigLayout {
 /// * When storing a cryfs config file, call [OuterConfig::encrypt] to get an [OuterConfig] instance,
 ///   then call [OuterConfig::serialize] to get the serialized representation of the [OuterConfig] instance.
 pub struct OuterConfig {
-    kdf_parameters_serialized: Vec<u8>,
+    key_slots: Vec<KeySlot>,
     encrypted_inner_config: Vec<u8>,
 }
 
 impl OuterConfig {
     pub fn encrypt(
         config: InnerConfig,
-        kdf_parameters: impl KDFParameters,
+        key_slots: Vec<KeySlot>,
         outer_encryption_key: EncryptionKey,
     ) -> Result<OuterConfig> {
         let mut serialized_inner_config = vec![];
@@ -71,17 +143,26 @@ impl OuterConfig {
         let encrypted_inner_config = cipher
             .encrypt(serialized_inner_config)
             .context("Trying to Cipher::encrypt OuterConfig")?;
+        ensure!(
+            !key_slots.is_empty(),
+            "Config file needs at least one key slot"
+        );
+        ensure!(
+            key_slots.len() <= MAX_KEY_SLOTS,
+            "Config file can have at most {MAX_KEY_SLOTS} key slots but has {}",
+            key_slots.len(),
+        );
         Ok(Self {
-            kdf_parameters_serialized: kdf_parameters.serialize(),
+            key_slots,
             encrypted_inner_config: encrypted_inner_config.into_vec(),
         })
     }
 
-    pub fn decrypt(self, outer_encryption_key: EncryptionKey) -> Result<InnerConfig> {
+    pub fn decrypt(&self, outer_encryption_key: EncryptionKey) -> Result<InnerConfig> {
         let cipher = OuterCipher::new(outer_encryption_key)
             .context("Trying to initialize OuterCipher instance")?;
         let plaintext = cipher
-            .decrypt(self.encrypted_inner_config.into())
+            .decrypt(self.encrypted_inner_config.clone().into())
             .context("Trying to Cipher::decrypt OuterConfig")?;
         let plaintext =
             remove_padding(plaintext).context("Trying to remove padding from OuterConfig")?;
@@ -91,44 +172,104 @@ impl OuterConfig {
     }
 
     pub fn deserialize(source: &mut (impl Read + Seek)) -> Result<Self> {
-        let layout =
-            OuterConfigLayout::read(source).context("Trying to read config file content")?;
-        let config_file_size = len(source).context("Trying to get config file size")?;
-        ensure!(
-            config_file_size <= CONFIG_SIZE,
-            "Config file size {config_file_size} is larger than expected {CONFIG_SIZE}",
-        );
-        let read_header: String = layout
+        let header =
+            OuterConfigHeader::read(source).context("Trying to read config file header")?;
+        let read_header: String = header
             .header
             .try_into()
             .context("Header is not valid UTF-8")?;
+        let result = if read_header == HEADER_V2 {
+            let layout =
+                OuterConfigLayoutV2::read(source).context("Trying to read config file content")?;
+            ensure!(
+                !layout.key_slots.is_empty() && layout.key_slots.len() <= MAX_KEY_SLOTS,
+                "Config file has {} key slots but must have between 1 and {MAX_KEY_SLOTS}",
+                layout.key_slots.len(),
+            );
+            let key_slots = layout
+                .key_slots
+                .into_iter()
+                .map(KeySlotLayout::into_key_slot)
+                .collect::<Result<Vec<_>>>()
+                .context("Trying to read key slots")?;
+            Self {
+                key_slots,
+                encrypted_inner_config: layout.encrypted_inner_config,
+            }
+        } else if let Some(kdf_name) = read_header.strip_prefix(HEADER_V1_PREFIX) {
+            let kdf_algorithm = KdfAlgorithm::from_name(kdf_name)
+                .with_context(|| format!("Invalid header in outer config: '{read_header}'"))?;
+            let layout =
+                OuterConfigLayoutV1::read(source).context("Trying to read config file content")?;
+            assert_eq!(
+                layout.kdf_parameters_num_bytes,
+                layout.kdf_parameters_serialized.len() as u64
+            );
+            let kdf_parameters =
+                KdfParams::deserialize(kdf_algorithm, &layout.kdf_parameters_serialized)
+                    .context("Trying to deserialize KDF parameters")?;
+            Self {
+                key_slots: vec![KeySlot::from_parts(kdf_parameters, None)],
+                encrypted_inner_config: layout.encrypted_inner_config,
+            }
+        } else {
+            bail!(
+                "Invalid header in outer config. Expected '{HEADER_V2}' or a header starting with '{HEADER_V1_PREFIX}', got '{read_header}'"
+            );
+        };
+        let config_file_size = len(source).context("Trying to get config file size")?;
         ensure!(
-            read_header == HEADER,
-            "Invalid header in outer config. Expected '{HEADER}', got '{read_header}'",
-        );
-        assert_eq!(
-            layout.kdf_parameters_num_bytes,
-            layout.kdf_parameters_serialized.len() as u64
+            config_file_size <= CONFIG_SIZE,
+            "Config file size {config_file_size} is larger than expected {CONFIG_SIZE}",
         );
-        Ok(Self {
-            kdf_parameters_serialized: layout.kdf_parameters_serialized,
-            encrypted_inner_config: layout.encrypted_inner_config,
-        })
+        Ok(result)
     }
 
     pub fn serialize(self, dest: &mut (impl Write + Seek)) -> Result<()> {
-        let layout = OuterConfigLayout {
-            header: HEADER.into(),
-            kdf_parameters_num_bytes: self.kdf_parameters_serialized.len() as u64,
-            kdf_parameters_serialized: self.kdf_parameters_serialized,
-            encrypted_inner_config: self.encrypted_inner_config,
-        };
-        layout.write(dest)?;
+        match self.key_slots.as_slice() {
+            [key_slot] if key_slot.is_derived() => {
+                let kdf_parameters_serialized = key_slot.kdf_parameters().serialize();
+                OuterConfigHeader {
+                    header: format!(
+                        "{HEADER_V1_PREFIX}{}",
+                        key_slot.kdf_parameters().algorithm().name()
+                    )
+                    .into(),
+                }
+                .write(dest)?;
+                OuterConfigLayoutV1 {
+                    kdf_parameters_num_bytes: kdf_parameters_serialized.len() as u64,
+                    kdf_parameters_serialized,
+                    encrypted_inner_config: self.encrypted_inner_config,
+                }
+                .write(dest)?;
+            }
+            _ => {
+                OuterConfigHeader {
+                    header: HEADER_V2.into(),
+                }
+                .write(dest)?;
+                OuterConfigLayoutV2 {
+                    num_key_slots: u32::try_from(self.key_slots.len())?,
+                    key_slots: self
+                        .key_slots
+                        .iter()
+                        .map(KeySlotLayout::from_key_slot)
+                        .collect(),
+                    encrypted_inner_config: self.encrypted_inner_config,
+                }
+                .write(dest)?;
+            }
+        }
         Ok(())
     }
 
-    pub fn kdf_parameters(&self) -> &[u8] {
-        &self.kdf_parameters_serialized
+    pub fn key_slots(&self) -> &[KeySlot] {
+        &self.key_slots
+    }
+
+    pub fn into_key_slots(self) -> Vec<KeySlot> {
+        self.key_slots
     }
 }
 
@@ -0,0 +1,155 @@
+use anyhow::{Result, bail};
+
+use cryfs_crypto::{
+    kdf::{
+        KDFParameters, PasswordBasedKDF,
+        argon2id::{Argon2id, Argon2idParams, Argon2idSettings},
+        scrypt::{Scrypt, ScryptParams, ScryptSettings},
+    },
+    symmetric::EncryptionKey,
+};
+
+/// The key derivation functions that can be used to derive the config file encryption key from the password.
+/// Which one was used is recorded in the header of the config file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KdfAlgorithm {
+    /// Used by all CryFS versions so far and the only one older CryFS versions can read.
+    #[default]
+    Scrypt,
+    Argon2id,
+}
+
+impl KdfAlgorithm {
+    /// The name used for this KDF in the config file header
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Scrypt => "scrypt",
+            Self::Argon2id => "argon2id",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "scrypt" => Ok(Self::Scrypt),
+            "argon2id" => Ok(Self::Argon2id),
+            _ => bail!("Unknown key derivation function '{name}'"),
+        }
+    }
+}
+
+/// Settings for generating the [KdfParams] of a new config file
+#[derive(Debug, Clone, Copy)]
+pub enum KdfSettings {
+    Scrypt(ScryptSettings),
+    Argon2id(Argon2idSettings),
+}
+
+/// KDF parameters, including the salt, stored in a config file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KdfParams {
+    Scrypt(ScryptParams),
+    Argon2id(Argon2idParams),
+}
+
+impl KdfParams {
+    pub fn generate(settings: &KdfSettings) -> Result<Self> {
+        match settings {
+            KdfSettings::Scrypt(settings) => {
+                Ok(Self::Scrypt(Scrypt::generate_parameters(settings)?))
+            }
+            KdfSettings::Argon2id(settings) => {
+                Ok(Self::Argon2id(Argon2id::generate_parameters(settings)?))
+            }
+        }
+    }
+
+    pub fn algorithm(&self) -> KdfAlgorithm {
+        match self {
+            Self::Scrypt(_) => KdfAlgorithm::Scrypt,
+            Self::Argon2id(_) => KdfAlgorithm::Argon2id,
+        }
+    }
+
+    pub fn derive_key(&self, key_size: usize, password: &str) -> EncryptionKey {
+        match self {
+            Self::Scrypt(params) => Scrypt::derive_key(key_size, password, params),
+            Self::Argon2id(params) => Argon2id::derive_key(key_size, password, params),
+        }
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        match self {
+            Self::Scrypt(params) => params.serialize(),
+            Self::Argon2id(params) => params.serialize(),
+        }
+    }
+
+    pub fn deserialize(algorithm: KdfAlgorithm, serialized: &[u8]) -> Result<Self> {
+        match algorithm {
+            KdfAlgorithm::Scrypt => Ok(Self::Scrypt(ScryptParams::deserialize(serialized)?)),
+            KdfAlgorithm::Argon2id => Ok(Self::Argon2id(Argon2idParams::deserialize(serialized)?)),
+        }
+    }
+}
+
+impl std::fmt::Display for KdfParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Scrypt(params) => write!(
+                f,
+                "scrypt (N=2^{}, r={}, p={})",
+                params.log_n(),
+                params.r(),
+                params.p()
+            ),
+            Self::Argon2id(params) => write!(
+                f,
+                "argon2id (m={} KiB, t={}, p={})",
+                params.m_cost(),
+                params.t_cost(),
+                params.p_cost()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn algorithm_name_roundtrip() {
+        for algorithm in [KdfAlgorithm::Scrypt, KdfAlgorithm::Argon2id] {
+            assert_eq!(
+                algorithm,
+                KdfAlgorithm::from_name(algorithm.name()).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_algorithm_name() {
+        assert!(KdfAlgorithm::from_name("bcrypt").is_err());
+    }
+
+    #[test]
+    fn generated_params_have_correct_algorithm() {
+        let scrypt = KdfParams::generate(&KdfSettings::Scrypt(ScryptSettings::TEST)).unwrap();
+        assert_eq!(KdfAlgorithm::Scrypt, scrypt.algorithm());
+        let argon2id = KdfParams::generate(&KdfSettings::Argon2id(Argon2idSettings::TEST)).unwrap();
+        assert_eq!(KdfAlgorithm::Argon2id, argon2id.algorithm());
+    }
+
+    #[test]
+    fn serialize_deserialize_roundtrip() {
+        for settings in [
+            KdfSettings::Scrypt(ScryptSettings::TEST),
+            KdfSettings::Argon2id(Argon2idSettings::TEST),
+        ] {
+            let params = KdfParams::generate(&settings).unwrap();
+            let deserialized =
+                KdfParams::deserialize(params.algorithm(), &params.serialize()).unwrap();
+            assert_eq!(params, deserialized);
+        }
+    }
+}
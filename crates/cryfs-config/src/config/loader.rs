@@ -10,19 +10,20 @@ use super::configfile::{
 };
 use super::console::Console;
 use super::creator::ConfigCreateError;
+use super::kdf::{KdfAlgorithm, KdfSettings};
 use super::password_provider::PasswordProvider;
 use crate::localstate::{FilesystemMetadata, LocalStateDir};
-use cryfs_blockstore::ClientId;
+use cryfs_blockstore::{ClientId, CompressionAlgorithm};
 use cryfs_crypto::symmetric::EncryptionKey;
 use cryfs_utils::progress::ProgressBarManager;
 use cryfs_version::{Version, VersionInfo};
 
-use crate::config::FILESYSTEM_FORMAT_VERSION;
+use crate::config::{EXTENDED_FILESYSTEM_FORMAT_VERSION, FILESYSTEM_FORMAT_VERSION};
 pub const CRYFS_VERSION: VersionInfo<'static, 'static, &'static str> =
     crate::version::CRYFS_VERSION;
 const MIN_SUPPORTED_FORMAT_VERSION: Version<&'static str> =
     konst::result::unwrap!(Version::parse_const("0.10"));
-const MAX_SUPPORTED_FORMAT_VERSION: Version<&'static str> = FILESYSTEM_FORMAT_VERSION;
+const MAX_SUPPORTED_FORMAT_VERSION: Version<&'static str> = EXTENDED_FILESYSTEM_FORMAT_VERSION;
 
 #[derive(Error, Debug)]
 pub enum ConfigLoadError {
@@ -119,6 +120,16 @@ pub struct CommandLineFlags {
     pub missing_block_is_integrity_violation: Option<bool>,
     pub expected_cipher: Option<String>,
     pub blocksize: Option<Byte>,
+    /// The key derivation function to use when creating a new file system.
+    /// This is ignored when loading an existing file system.
+    pub kdf: Option<KdfAlgorithm>,
+    /// The compression algorithm to use when creating a new file system, `None` means no compression.
+    /// This is ignored when loading an existing file system.
+    pub compression: Option<CompressionAlgorithm>,
+    /// Whether to create the new file system with [EXTENDED_FILESYSTEM_FORMAT_VERSION], which allows features that
+    /// CryFS 1.0 can't read. If this is `false`, the file system stays compatible with CryFS 1.0.
+    /// This is ignored when loading an existing file system.
+    pub extended_format: bool,
 }
 
 pub fn create(
@@ -226,13 +237,23 @@ fn _create(
         local_state_dir,
         allow_replaced_filesystem,
     )?;
+    let kdf_settings = match command_line_flags.kdf.unwrap_or_default() {
+        KdfAlgorithm::Scrypt => KdfSettings::Scrypt(
+            console
+                .ask_scrypt_settings_for_new_filesystem()
+                .map_err(ConfigLoadError::InteractionError)?,
+        ),
+        KdfAlgorithm::Argon2id => KdfSettings::Argon2id(
+            console
+                .ask_argon2id_settings_for_new_filesystem()
+                .map_err(ConfigLoadError::InteractionError)?,
+        ),
+    };
     let file = CryConfigFile::create_new(
         filename,
         config.config.clone(),
         password,
-        &console
-            .ask_scrypt_settings_for_new_filesystem()
-            .map_err(ConfigLoadError::InteractionError)?,
+        &kdf_settings,
         progress_bars,
     )?;
     Ok(ConfigLoadResult {
@@ -323,18 +344,20 @@ fn _check_version<'a>(
             cryfs_version: CRYFS_VERSION,
         });
     }
-    if !allow_filesystem_upgrade && actual_format_version < MAX_SUPPORTED_FORMAT_VERSION {
+    // File systems in the CryFS 1.0 compatible format don't get migrated to the extended format, that only happens
+    // when a file system is created with it. So the compatible format is the newest one we migrate to.
+    if !allow_filesystem_upgrade && actual_format_version < FILESYSTEM_FORMAT_VERSION {
         let allow_migration = console
             .ask_migrate_filesystem(
                 &actual_format_version,
-                &MAX_SUPPORTED_FORMAT_VERSION,
+                &FILESYSTEM_FORMAT_VERSION,
                 &CRYFS_VERSION,
             )
             .map_err(ConfigLoadError::InteractionError)?;
         if !allow_migration {
             return Err(ConfigLoadError::TooOldFilesystemFormatDeclinedMigration {
                 actual_format_version: actual_format_version.to_owned(),
-                max_supported_format_version: MAX_SUPPORTED_FORMAT_VERSION,
+                max_supported_format_version: FILESYSTEM_FORMAT_VERSION,
                 cryfs_version: CRYFS_VERSION,
             });
         }
@@ -344,7 +367,7 @@ fn _check_version<'a>(
 
 fn _update_version_in_config(config: &mut CryConfigFile) {
     // TODO No unwrap
-    if Version::parse(&config.config().format_version).unwrap() != FILESYSTEM_FORMAT_VERSION {
+    if Version::parse(&config.config().format_version).unwrap() < FILESYSTEM_FORMAT_VERSION {
         config.config_mut().format_version = FILESYSTEM_FORMAT_VERSION.to_string();
     }
     if config.config().last_opened_with_version != CRYFS_VERSION.to_string() {
@@ -5,16 +5,22 @@ mod console;
 mod creator;
 mod cryconfig;
 mod encryption;
+mod kdf;
 mod loader;
 mod password_provider;
 
 pub use ciphers::ALL_CIPHERS;
 pub use configfile::{
-    CreateConfigFileError, CryConfigFile, LoadConfigFileError, SaveConfigFileError,
+    Access, CreateConfigFileError, CryConfigFile, KeySlotError, LoadConfigFileError,
+    SaveConfigFileError,
 };
 pub use console::Console;
 pub use creator::ConfigCreateError;
-pub use cryconfig::{CryConfig, FILESYSTEM_FORMAT_VERSION, FilesystemId};
+pub use cryconfig::{
+    CryConfig, EXTENDED_FILESYSTEM_FORMAT_VERSION, FILESYSTEM_FORMAT_VERSION, FilesystemId,
+};
+pub use encryption::{KeySlot, MAX_KEY_SLOTS};
+pub use kdf::{KdfAlgorithm, KdfParams, KdfSettings};
 pub use loader::{
     CRYFS_VERSION, CommandLineFlags, ConfigLoadError, ConfigLoadResult, create, load_or_create,
     load_readonly,
@@ -7,24 +7,30 @@ use cryfs_rustfs::object_based_api::Dir as _;
 use cryfs_utils::with_async_drop_2;
 use futures::join;
 use maybe_owned::MaybeOwned;
+use std::collections::VecDeque;
 use std::sync::Arc;
 use std::sync::atomic::Ordering;
 use std::{fmt::Debug, time::Instant};
 
 use cryfs_blobstore::{BlobId, BlobStore};
-use cryfs_rustfs::{FsError, FsResult, Statfs, object_based_api::Device};
+use cryfs_rustfs::{FsError, FsResult, InodeNumber, RenameMode, Statfs, object_based_api::Device};
 use cryfs_utils::{
     async_drop::{AsyncDrop, AsyncDropArc, AsyncDropGuard},
-    path::{AbsolutePath, PathComponent},
+    path::{AbsolutePath, AbsolutePathBuf, PathComponent},
 };
 
 use super::{
-    dir::CryDir, file::CryFile, node::CryNode, node_info::NodeInfo, open_file::CryOpenFile,
+    dir::CryDir,
+    file::CryFile,
+    journal, link_metadata,
+    node::{CryNode, stable_id},
+    node_info::NodeInfo,
+    open_file::CryOpenFile,
     symlink::CrySymlink,
 };
 use cryfs_fsblobstore::concurrentfsblobstore::{ConcurrentFsBlob, ConcurrentFsBlobStore};
 use cryfs_fsblobstore::fsblobstore::{
-    AddOrOverwriteError, BlobType, EntryType, FsBlob, FsBlobStore, RemoveError, RenameError,
+    BlobType, EntryType, FsBlob, FsBlobStore, FsFormat, RenameError, ReplaceError, journal_blob_id,
 };
 
 pub struct CryDevice<B>
@@ -46,25 +52,36 @@ where
     B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
     <B as BlobStore>::ConcreteBlob: Send + Sync + AsyncDrop<Error = anyhow::Error>,
 {
-    pub fn load_filesystem(
+    pub async fn load_filesystem(
         blobstore: AsyncDropGuard<B>,
         root_blob_id: BlobId,
         atime_update_behavior: AtimeUpdateBehavior,
-    ) -> AsyncDropGuard<Self> {
-        AsyncDropGuard::new(Self {
-            blobstore: AsyncDropArc::new(ConcurrentFsBlobStore::new(FsBlobStore::new(blobstore))),
+        format: FsFormat,
+    ) -> Result<AsyncDropGuard<Self>> {
+        let blobstore = Self::load_blobstore(blobstore, &root_blob_id, format).await?;
+        let mut device = AsyncDropGuard::new(Self {
+            blobstore: AsyncDropArc::new(blobstore),
             root_blob_id,
             atime_update_behavior,
             last_access_time: Arc::new(AtomicInstant::now()),
-        })
+        });
+        // Operations that were interrupted by a crash need to be finished before we allow any other operations
+        if let Err(err) = journal::replay_pending_intents(&device.blobstore).await {
+            device.async_drop().await?;
+            return Err(err);
+        }
+        Ok(device)
     }
 
     pub async fn create_new_filesystem(
         blobstore: AsyncDropGuard<B>,
         root_blob_id: BlobId,
         atime_update_behavior: AtimeUpdateBehavior,
+        format: FsFormat,
     ) -> Result<AsyncDropGuard<Self>, Arc<anyhow::Error>> {
-        let mut fsblobstore = ConcurrentFsBlobStore::new(FsBlobStore::new(blobstore));
+        let mut fsblobstore = Self::load_blobstore(blobstore, &root_blob_id, format)
+            .await
+            .map_err(Arc::new)?;
         match fsblobstore.create_root_dir_blob(&root_blob_id).await {
             Ok(()) => Ok(AsyncDropGuard::new(Self {
                 blobstore: AsyncDropArc::new(fsblobstore),
@@ -82,6 +99,28 @@ where
         }
     }
 
+    /// The intent journal is stored in the same blobstore as the file system, see [journal_blob_id].
+    /// File systems created before we had the journal get an empty journal the first time they're loaded.
+    /// File systems in the [FsFormat::Cryfs1Compatible] format never get a journal blob because CryFS 1.0 doesn't know about it,
+    /// so multi-blob operations like moving an entry between directories aren't crash-safe in them.
+    async fn load_blobstore(
+        blobstore: AsyncDropGuard<B>,
+        root_blob_id: &BlobId,
+        format: FsFormat,
+    ) -> Result<AsyncDropGuard<ConcurrentFsBlobStore<B>>> {
+        let mut fsblobstore = FsBlobStore::new(blobstore, format);
+        match fsblobstore
+            .load_or_create_journal(&journal_blob_id(root_blob_id))
+            .await
+        {
+            Ok(journal) => Ok(ConcurrentFsBlobStore::new(fsblobstore, journal)),
+            Err(err) => {
+                fsblobstore.async_drop().await?;
+                Err(err)
+            }
+        }
+    }
+
     pub async fn sanity_check(&self) -> Result<()> {
         // Make sure we can load the root dir and load its children
         let rootdir = self.rootdir().await.context("Didn't find root blob")?;
@@ -153,12 +192,18 @@ where
         for path_component in relative_path {
             let blob_id = current_blob
                 .with_lock(async |blob| {
-                    let dir_blob = blob.as_dir().map_err(|_err| FsError::NodeIsNotADirectory)?;
-                    dir_blob.entry_by_name(path_component).map_or(
-                        // TODO This error mapping is weird. Probably better to have as_dir return the right error type.
-                        Err(FsError::NodeDoesNotExist),
-                        |entry| Ok(*entry.blob_id()),
-                    )
+                    let dir_blob = blob
+                        .as_dir_mut()
+                        .map_err(|_err| FsError::NodeIsNotADirectory)?;
+                    dir_blob
+                        .entry_by_name(path_component)
+                        .await
+                        .map_err(FsError::internal_error)?
+                        .map_or(
+                            // TODO This error mapping is weird. Probably better to have as_dir return the right error type.
+                            Err(FsError::NodeDoesNotExist),
+                            |entry| Ok(*entry.blob_id()),
+                        )
                 })
                 .await;
 
@@ -328,6 +373,7 @@ where
         &self,
         source_path: &AbsolutePath,
         dest_path: &AbsolutePath,
+        mode: RenameMode,
     ) -> impl Future<Output = FsResult<()>> {
         async move {
             if source_path.is_ancestor_of(dest_path) {
@@ -338,7 +384,15 @@ where
                 );
                 return Err(FsError::CannotMoveDirectoryIntoSubdirectoryOfItself);
             }
-            // We don't need to check dest_path.is_ancestor_of(source_path) because that case would mean
+            if mode == RenameMode::Exchange && dest_path.is_ancestor_of(source_path) {
+                log::error!(
+                    "Tried to exchange {source_path} with its ancestor {dest_path}",
+                    source_path = source_path,
+                    dest_path = dest_path
+                );
+                return Err(FsError::CannotMoveDirectoryIntoSubdirectoryOfItself);
+            }
+            // For other modes, we don't need to check dest_path.is_ancestor_of(source_path) because that case would mean
             // that dest_path is non-empty (it contains source_path), and we check for non-emptiness below.
 
             let Some((source_parent, source_name)) = source_path.split_last() else {
@@ -362,12 +416,11 @@ where
                     )
                     .await?;
 
-                    match self
-                        .blobstore
-                        .remove_by_id(&overwritten_blobid)
-                        .await
-                        .map_err(FsError::internal_error)?
-                    {
+                    if !overwritten_blob_type.has_blob() {
+                        // Fifos, sockets and devices don't have a blob, removing their entry was enough
+                        return Ok(());
+                    }
+                    match remove_link_to_blob(&self.blobstore, overwritten_blobid).await? {
                         RemoveResult::SuccessfullyRemoved => Ok(()),
                         RemoveResult::NotRemovedBecauseItDoesntExist => {
                             log::error!(
@@ -383,10 +436,23 @@ where
                     with_async_drop_2!(
                         blob,
                         {
+                            if mode == RenameMode::Exchange {
+                                return exchange_entries_in_dir(&blob, source_name, dest_name)
+                                    .await;
+                            }
                             blob.with_lock(async |blob| {
                                 let parent = blob
                                     .as_dir_mut()
                                     .map_err(|_| FsError::NodeIsNotADirectory)?;
+                                if mode == RenameMode::NoReplace
+                                    && parent
+                                        .entry_by_name(dest_name)
+                                        .await
+                                        .map_err(FsError::internal_error)?
+                                        .is_some()
+                                {
+                                    return Err(FsError::NodeAlreadyExists);
+                                }
                                 parent
                                     .rename_entry_by_name(
                                         source_name,
@@ -397,6 +463,7 @@ where
                                     .map_err(|err| match err {
                                         RenameError::NodeDoesNotExist => FsError::NodeDoesNotExist,
                                         RenameError::OnOverwriteError(e) => e,
+                                        RenameError::LoadFailed(e) => FsError::internal_error(e),
                                     })?;
                                 Ok::<(), FsError>(())
                             })
@@ -418,95 +485,61 @@ where
                             with_async_drop_2!(
                                 dest_parent_blob,
                                 {
+                                    if mode == RenameMode::Exchange {
+                                        return exchange_entries_between_dirs(
+                                            &self.blobstore,
+                                            &source_parent_blob,
+                                            source_name,
+                                            &dest_parent_blob,
+                                            dest_name,
+                                        )
+                                        .await;
+                                    }
                                     let entry = source_parent_blob
                                         .with_lock(async |source_parent: &mut FsBlob<B>| {
                                             source_parent
                                                 .as_dir_mut()
                                                 .map_err(|_| FsError::NodeIsNotADirectory)?
                                                 .entry_by_name(source_name)
+                                                .await
+                                                .map_err(FsError::internal_error)?
                                                 .ok_or(FsError::NodeDoesNotExist)
                                                 .cloned() // TODO No cloned?
                                         })
                                         .await?;
                                     let self_blob_id = entry.blob_id();
-
-                                    // TODO In theory, we could load self_blob concurrently with dest_parent_blob. No need to only do it after dest_parent_blob loaded.
-                                    //      But it likely has some dependency with source_parent_blob.
-                                    let self_blob = self
-                                        .blobstore
-                                        .load(self_blob_id)
-                                        .await
-                                        .map_err(|err| {
-                                            log::error!("Error loading blob: {:?}", err);
-                                            FsError::UnknownError
-                                        })?
-                                        .ok_or(FsError::NodeDoesNotExist)?;
-
-                                    // TODO Concurrently drop source_parent_blob, dest_parent_blob and self_blob
-                                    with_async_drop_2!(
-                                        self_blob,
-                                        {
-                                            source_parent_blob
-                                                .with_lock(async |source_parent| {
-                                                    source_parent
-                                                        .as_dir_mut()
-                                                        .map_err(|_| FsError::NodeIsNotADirectory)?
-                                                        .remove_entry_by_name(source_name)
-                                                        .map_err(|err| match err {
-                                                            RemoveError::NodeDoesNotExist => {
-                                                                FsError::NodeDoesNotExist
-                                                            }
-                                                        })
-                                                })
-                                                .await?;
-                                            dest_parent_blob
-                                                .with_lock(async |source_parent| {
-                                                    source_parent
-                                                        .as_dir_mut()
-                                                        .map_err(|_| FsError::NodeIsNotADirectory)?
-                                                        .add_or_overwrite_entry(
-                                                            dest_name.to_owned(),
-                                                            *entry.blob_id(),
-                                                            entry.entry_type(),
-                                                            entry.mode(),
-                                                            entry.uid(),
-                                                            entry.gid(),
-                                                            entry.last_access_time(),
-                                                            entry.last_modification_time(),
-                                                            on_overwritten,
-                                                        )
-                                                        .await
-                                                        .map_err(|err| {
-                                                            // TODO Exception safety - we couldn't add the entry to the destination, but we already removed it from the source. We should probably re-add it to the source.
-                                                            match err {
-                                                                AddOrOverwriteError::ValidationFailed(fs_err) => {
-                                                                    log::error!("Error in add_or_overwrite_entry validation: {fs_err:?}");
-                                                                    FsError::internal_error(fs_err.into()) // This shouldn't happen because we are moving an already validated entry
-                                                                }
-                                                                AddOrOverwriteError::OnOverwriteError(err) => {
-                                                                    log::error!("Error in add_or_overwrite_entry on_overwritten: {err:?}");
-                                                                    err
-                                                                }
-                                                            }
-                                                        })
-                                                }).await?;
-
-                                            self_blob
-                                                .with_lock(async |self_blob| {
-                                                    self_blob
-                                                        .set_parent(&dest_parent_blob.blob_id())
-                                                        .await
-                                                })
+                                    let destination_is_same_blob = dest_parent_blob
+                                        .with_lock(async |dest_parent: &mut FsBlob<B>| {
+                                            let dest_entry = dest_parent
+                                                .as_dir_mut()
+                                                .map_err(|_| FsError::NodeIsNotADirectory)?
+                                                .entry_by_name(dest_name)
                                                 .await
-                                                .map_err(|err| {
-                                                    // TODO Exception safety - we already changed parent dir entries but couldn't update the parent pointer. We should probably try to undo the parent dir entry changes.
-                                                    log::error!("Error setting parent: {err:?}");
-                                                    FsError::UnknownError
-                                                })?;
-                                            Ok::<(), FsError>(())
-                                        },
-                                        FsError::internal_error
+                                                .map_err(FsError::internal_error)?;
+                                            if mode == RenameMode::NoReplace && dest_entry.is_some()
+                                            {
+                                                return Err(FsError::NodeAlreadyExists);
+                                            }
+                                            Ok::<_, FsError>(
+                                                dest_entry.is_some_and(|dest| {
+                                                    dest.blob_id() == self_blob_id
+                                                }),
+                                            )
+                                        })
+                                        .await?;
+                                    if destination_is_same_blob {
+                                        // Source and destination are hard links to the same blob, and POSIX says rename should do nothing in this case.
+                                        return Ok(());
+                                    }
+
+                                    journal::move_entry_between_dirs(
+                                        &self.blobstore,
+                                        &source_parent_blob,
+                                        source_name,
+                                        &dest_parent_blob,
+                                        dest_name,
                                     )
+                                    .await
                                 },
                                 FsError::internal_error
                             )
@@ -547,6 +580,67 @@ where
             num_free_inodes: num_free_blocks,
         })
     }
+
+    async fn find_path_by_stable_ino(&self, ino: InodeNumber) -> FsResult<Option<AbsolutePathBuf>> {
+        // We don't keep an index from inode numbers to blobs, so we have to search the directory tree.
+        // That's slow for large file systems, but it's only needed for inodes the kernel doesn't know anymore,
+        // e.g. for NFS file handles created before the file system was remounted.
+        let mut dirs_to_search = VecDeque::from([(self.root_blob_id, AbsolutePathBuf::root())]);
+        while let Some((dir_blob_id, dir_path)) = dirs_to_search.pop_front() {
+            let mut dir_blob = self
+                .blobstore
+                .load(&dir_blob_id)
+                .await
+                .map_err(|err| {
+                    log::error!("Failed to load blob: {err:?}");
+                    FsError::Custom {
+                        error_code: libc::EIO,
+                    }
+                })?
+                .ok_or_else(|| {
+                    log::error!("Blob not found");
+                    FsError::Custom {
+                        error_code: libc::EIO,
+                    }
+                })?;
+            let entries = dir_blob
+                .with_lock(async |blob| {
+                    let dir_blob = blob
+                        .as_dir_mut()
+                        .map_err(|_err| FsError::NodeIsNotADirectory)?;
+                    Ok::<_, FsError>(
+                        dir_blob
+                            .entries()
+                            .await
+                            .map_err(FsError::internal_error)?
+                            .map(|entry| {
+                                (
+                                    entry.name().to_owned(),
+                                    *entry.blob_id(),
+                                    entry.entry_type(),
+                                )
+                            })
+                            .collect::<Vec<_>>(),
+                    )
+                })
+                .await;
+            dir_blob
+                .async_drop()
+                .await
+                .map_err(FsError::internal_error)?;
+
+            for (name, blob_id, entry_type) in entries? {
+                let path = dir_path.clone().push(&name);
+                if InodeNumber::from_stable_id(stable_id(&blob_id)).handle == ino {
+                    return Ok(Some(path));
+                }
+                if entry_type == EntryType::Dir {
+                    dirs_to_search.push_back((blob_id, path));
+                }
+            }
+        }
+        Ok(None)
+    }
 }
 
 pub async fn check_entry_overwrite_allowed<B>(
@@ -565,6 +659,147 @@ where
     Ok(())
 }
 
+/// Remove one link to a blob whose entry was removed from its parent directory. If the blob is hard linked from
+/// other entries, this only decrements its link count, otherwise it removes the blob and its link metadata blob.
+pub async fn remove_link_to_blob<B>(
+    blobstore: &AsyncDropArc<ConcurrentFsBlobStore<B>>,
+    blob_id: &BlobId,
+) -> FsResult<RemoveResult>
+where
+    B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
+    <B as BlobStore>::ConcreteBlob: Send + Sync + AsyncDrop<Error = anyhow::Error>,
+{
+    let Some(blob) = blobstore.load(blob_id).await.map_err(|err| {
+        log::error!("Error loading blob: {:?}", err);
+        FsError::UnknownError
+    })?
+    else {
+        return Ok(RemoveResult::NotRemovedBecauseItDoesntExist);
+    };
+    let (has_other_links, link_metadata_blob_id) = with_async_drop_2!(
+        blob,
+        {
+            blob.with_lock(async |blob| {
+                if blob.num_links() > 1 {
+                    blob.decrement_num_links().await?;
+                    Ok((true, None))
+                } else {
+                    Ok((false, blob.link_metadata_blob_id()))
+                }
+            })
+            .await
+            .map_err(|err: anyhow::Error| {
+                log::error!("Error decrementing link count: {err:?}");
+                FsError::UnknownError
+            })
+        },
+        FsError::internal_error
+    )?;
+    if has_other_links {
+        return Ok(RemoveResult::SuccessfullyRemoved);
+    }
+    if let Some(link_metadata_blob_id) = link_metadata_blob_id {
+        link_metadata::remove_link_metadata_blob(blobstore, &link_metadata_blob_id).await?;
+    }
+    // TODO There's a race condition if the blob gets hard linked again between releasing the lock above and removing it here
+    blobstore
+        .remove_by_id(blob_id)
+        .await
+        .map_err(FsError::internal_error)
+}
+
+/// Swap the entries `first_name` and `second_name` of the directory `parent`, as needed for `RENAME_EXCHANGE` within one directory.
+/// Both entries keep their name but each one now points to what the other one pointed to.
+pub async fn exchange_entries_in_dir<B>(
+    parent: &ConcurrentFsBlob<B>,
+    first_name: &PathComponent,
+    second_name: &PathComponent,
+) -> FsResult<()>
+where
+    B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
+    <B as BlobStore>::ConcreteBlob: Send + Sync + AsyncDrop<Error = anyhow::Error>,
+{
+    parent
+        .with_lock(async |parent| {
+            let parent = parent
+                .as_dir_mut()
+                .map_err(|_| FsError::NodeIsNotADirectory)?;
+            let first = parent
+                .entry_by_name(first_name)
+                .await
+                .map_err(FsError::internal_error)?
+                .ok_or(FsError::NodeDoesNotExist)?
+                .clone();
+            let second = parent
+                .entry_by_name(second_name)
+                .await
+                .map_err(FsError::internal_error)?
+                .ok_or(FsError::NodeDoesNotExist)?
+                .clone();
+            if first.blob_id() == second.blob_id() {
+                // Both names are hard links to the same blob (or it's the same name), there's nothing to swap
+                return Ok(());
+            }
+            parent
+                .replace_entry_by_name(first_name, &second)
+                .await
+                .map_err(|err| match err {
+                    ReplaceError::NodeDoesNotExist => FsError::NodeDoesNotExist,
+                    ReplaceError::LoadFailed(err) => FsError::internal_error(err),
+                })?;
+            parent
+                .replace_entry_by_name(second_name, &first)
+                .await
+                .map_err(|err| match err {
+                    ReplaceError::NodeDoesNotExist => FsError::NodeDoesNotExist,
+                    ReplaceError::LoadFailed(err) => FsError::internal_error(err),
+                })?;
+            Ok(())
+        })
+        .await
+}
+
+/// Swap the entry `first_name` in `first_parent` with the entry `second_name` in `second_parent`, as needed for `RENAME_EXCHANGE`
+/// across directories. This also updates the parent pointers of both entries' blobs, see [journal::exchange_entries].
+pub async fn exchange_entries_between_dirs<B>(
+    blobstore: &AsyncDropArc<ConcurrentFsBlobStore<B>>,
+    first_parent: &ConcurrentFsBlob<B>,
+    first_name: &PathComponent,
+    second_parent: &ConcurrentFsBlob<B>,
+    second_name: &PathComponent,
+) -> FsResult<()>
+where
+    B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
+    <B as BlobStore>::ConcreteBlob: Send + Sync + AsyncDrop<Error = anyhow::Error>,
+{
+    // TODO We're locking and releasing each parent blob multiple times, this has the same race conditions as the cross-directory move in [CryDevice::rename].
+    let get_entry = async |parent: &ConcurrentFsBlob<B>, name: &PathComponent| {
+        parent
+            .with_lock(async |parent| {
+                parent
+                    .as_dir_mut()
+                    .map_err(|_| FsError::NodeIsNotADirectory)?
+                    .entry_by_name(name)
+                    .await
+                    .map_err(FsError::internal_error)?
+                    .ok_or(FsError::NodeDoesNotExist)
+                    .cloned()
+            })
+            .await
+    };
+    let (first, second) = join!(
+        get_entry(first_parent, first_name),
+        get_entry(second_parent, second_name)
+    );
+    let (first, second) = (first?, second?);
+    if first.blob_id() == second.blob_id() {
+        // Both names are hard links to the same blob, there's nothing to swap
+        return Ok(());
+    }
+
+    journal::exchange_entries(blobstore, first_parent, &first, second_parent, &second).await
+}
+
 fn check_blob_type_transition_allowed(
     source_blob_type: EntryType,
     overwritten_blob_type: EntryType,
@@ -610,9 +845,9 @@ where
             overwritten_blob,
             {
                 overwritten_blob.with_lock(async |overwritten_blob| {
-                let overwritten_blob = overwritten_blob.as_dir()
+                let overwritten_blob = overwritten_blob.as_dir_mut()
                     .map_err(|_| FsError::CorruptedFilesystem { message: format!("Blob {overwritten_blobid:?} is not a directory but its entry in its parent directory says it is") })?;
-                if overwritten_blob.entries().len() > 0 {
+                if overwritten_blob.entries().await.map_err(FsError::internal_error)?.len() > 0 {
                     return Err(FsError::CannotOverwriteNonEmptyDirectory);
                 }
                 Ok(())
@@ -651,3 +886,127 @@ where
         AsyncDropGuard<ConcurrentFsBlob<B>>,
     ),
 }
+
+#[cfg(test)]
+mod tests {
+    use byte_unit::Byte;
+    use cryfs_blobstore::BlobStoreOnBlocks;
+    use cryfs_blockstore::{InMemoryBlockStore, LLSharedBlockStore, LockingBlockStore};
+    use cryfs_rustfs::{Gid, Mode, Uid};
+
+    use super::*;
+
+    fn name(name: &str) -> &PathComponent {
+        PathComponent::try_from_str(name).unwrap()
+    }
+
+    fn path(path: &str) -> &AbsolutePath {
+        AbsolutePath::try_from_str(path).unwrap()
+    }
+
+    #[tokio::test]
+    async fn cryfs1_compatible_filesystem_only_writes_blobs_cryfs1_can_read() {
+        let mut blockstore = LLSharedBlockStore::new(InMemoryBlockStore::new());
+        let make_blobstore = || async {
+            BlobStoreOnBlocks::new(
+                LockingBlockStore::new(LLSharedBlockStore::clone(&blockstore)),
+                Byte::from_u64(4096),
+            )
+            .await
+            .unwrap()
+        };
+        let root_blob_id = BlobId::new_random();
+
+        let mut device = CryDevice::create_new_filesystem(
+            make_blobstore().await,
+            root_blob_id,
+            AtimeUpdateBehavior::Noatime,
+            FsFormat::Cryfs1Compatible,
+        )
+        .await
+        .unwrap();
+        let mut rootdir = device.rootdir().await.unwrap();
+        for dir_name in ["dir1", "dir2"] {
+            let (_, mut dir) = rootdir
+                .create_child_dir(
+                    name(dir_name),
+                    Mode::default().add_dir_flag().add_user_exec_flag(),
+                    Uid::from(1000),
+                    Gid::from(1000),
+                )
+                .await
+                .unwrap();
+            dir.async_drop().await.unwrap();
+        }
+        // Enough entries that the directory would be split into multiple buckets in the extended format
+        for index in 0..100 {
+            let (_, mut symlink) = rootdir
+                .create_child_symlink(
+                    name(&format!("symlink_{index}")),
+                    "target",
+                    Uid::from(1000),
+                    Gid::from(1000),
+                )
+                .await
+                .unwrap();
+            symlink.async_drop().await.unwrap();
+        }
+        // Moving entries between directories goes through the intent journal
+        device
+            .rename(
+                path("/symlink_0"),
+                path("/dir1/symlink_0"),
+                RenameMode::Overwrite,
+            )
+            .await
+            .unwrap();
+        device
+            .rename(path("/dir2"), path("/dir1/dir2"), RenameMode::Overwrite)
+            .await
+            .unwrap();
+        device
+            .rename(
+                path("/dir1/symlink_0"),
+                path("/symlink_1"),
+                RenameMode::Exchange,
+            )
+            .await
+            .unwrap();
+        let mut node = device.lookup(path("/symlink_2")).await.unwrap();
+        assert!(matches!(
+            rootdir.create_child_link(name("link"), &node).await,
+            Err(FsError::HardLinksNotSupported),
+        ));
+        node.async_drop().await.unwrap();
+        rootdir.async_drop().await.unwrap();
+        drop(rootdir);
+        device.async_drop().await.unwrap();
+
+        let mut fsblobstore = FsBlobStore::new(make_blobstore().await, FsFormat::Cryfs1Compatible);
+        assert!(
+            fsblobstore
+                .load(&journal_blob_id(&root_blob_id))
+                .await
+                .unwrap()
+                .is_none()
+        );
+        let mut num_blobs = 0;
+        let mut to_visit = vec![root_blob_id];
+        while let Some(blob_id) = to_visit.pop() {
+            let mut blob = fsblobstore.load(&blob_id).await.unwrap().unwrap();
+            assert!(
+                blob.is_readable_by_cryfs1(),
+                "Blob {blob_id:?} isn't readable by CryFS 1.0"
+            );
+            if let Ok(dir) = blob.as_dir_mut() {
+                dir.load_all_entries().await.unwrap();
+                to_visit.extend(dir.loaded_entries().unwrap().map(|entry| *entry.blob_id()));
+            }
+            blob.async_drop().await.unwrap();
+            num_blobs += 1;
+        }
+        assert_eq!(103, num_blobs);
+        fsblobstore.async_drop().await.unwrap();
+        blockstore.async_drop().await.unwrap();
+    }
+}
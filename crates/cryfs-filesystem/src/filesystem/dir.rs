@@ -4,20 +4,28 @@ use futures::join;
 use std::fmt::Debug;
 use std::time::SystemTime;
 
-use crate::filesystem::device::check_entry_overwrite_allowed;
+use crate::filesystem::device::{
+    check_entry_overwrite_allowed, exchange_entries_between_dirs, exchange_entries_in_dir,
+    remove_link_to_blob,
+};
+use crate::filesystem::journal;
 
 use super::{
-    device::CryDevice, node::CryNode, node_info::NodeInfo, open_file::CryOpenFile,
+    device::CryDevice,
+    link_metadata,
+    node::CryNode,
+    node_info::{BlobAttrs, NodeInfo, dir_entry_to_node_attrs, load_blob_attrs, load_metadata},
+    open_file::CryOpenFile,
     symlink::CrySymlink,
 };
 use cryfs_blobstore::{BlobId, BlobStore, RemoveResult};
 use cryfs_fsblobstore::concurrentfsblobstore::{ConcurrentFsBlob, ConcurrentFsBlobStore};
-use cryfs_fsblobstore::fsblobstore::{
-    AddError, AddOrOverwriteError, FlushBehavior, RemoveError, RenameError,
-};
-use cryfs_fsblobstore::fsblobstore::{BlobType, DirBlob, EntryType, FsBlob, MODE_NEW_SYMLINK};
+use cryfs_fsblobstore::fsblobstore::{AddError, FlushBehavior, IntentId, RemoveError, RenameError};
+use cryfs_fsblobstore::fsblobstore::{DirBlob, EntryType, FsBlob, MODE_NEW_SYMLINK};
 use cryfs_fsblobstore::{Gid, Mode, Uid};
-use cryfs_rustfs::{DirEntry, FsError, FsResult, NodeAttrs, NodeKind, object_based_api::Dir};
+use cryfs_rustfs::{
+    DirEntry, FsError, FsResult, NodeAttrs, NodeKind, RenameMode, object_based_api::Dir,
+};
 use cryfs_utils::{
     async_drop::{AsyncDrop, AsyncDropArc, AsyncDropGuard, flatten_async_drop},
     path::PathComponent,
@@ -54,16 +62,6 @@ where
         self.node_info.load_blob(self.blobstore).await
     }
 
-    fn blob_as_dir<'b>(blob: &'b FsBlob<B>) -> Result<&'b DirBlob<B>, FsError> {
-        blob.as_dir().map_err(|err| {
-            let blob_id = blob.blob_id();
-            FsError::CorruptedFilesystem {
-                // TODO Add to message what it actually is
-                message: format!("Blob {:?} is listed as a directory in its parent directory but is actually not a directory: {err:?}", blob_id),
-            }
-        })
-    }
-
     fn blob_as_dir_mut<'b>(blob: &'b mut FsBlob<B>) -> Result<&'b mut DirBlob<B>, FsError> {
         let blob_id = blob.blob_id();
         blob.as_dir_mut().map_err(|err| {
@@ -74,79 +72,101 @@ where
         })
     }
 
-    async fn create_dir_blob(&self, parent: &BlobId) -> Result<BlobId, FsError> {
-        let mut blob = self
-            .blobstore
-            .create_dir_blob(
-                parent,
-                // Make sure we flush this before the call site gets a chance to add this as an entry to its directory entry list.
-                // This way, we make sure the filesystem stays consistent even if it crashes mid way.
-                // Dropping by itself isn't enough to flush because it may go into a cache.
-                FlushBehavior::FlushImmediately,
-            )
-            .await
-            .map_err(|err| {
-                log::error!("Error creating dir blob: {err:?}");
-                FsError::UnknownError
-            })?;
-
-        let blob_id = blob.blob_id();
+    /// Create a new blob with `create_blob` that the call site will add as the entry `name` to this directory.
+    /// This records an [Intent::Create](cryfs_fsblobstore::fsblobstore::Intent::Create) in the journal, which the call site
+    /// needs to finish with [journal::finish_create] after adding the entry, or with [Self::remove_just_created_blob] if that failed.
+    async fn create_blob(
+        &self,
+        name: &PathComponent,
+        create_blob: impl AsyncFnOnce(&BlobId) -> anyhow::Result<AsyncDropGuard<ConcurrentFsBlob<B>>>,
+    ) -> Result<(BlobId, IntentId), FsError> {
+        let blob_id = BlobId::new_random();
+        let intent_id =
+            journal::begin_create(self.blobstore, self.node_info.blob_id(), name, &blob_id).await?;
+        let mut blob = match create_blob(&blob_id).await {
+            Ok(blob) => blob,
+            Err(err) => {
+                log::error!("Error creating blob: {err:?}");
+                self.remove_just_created_blob(blob_id, intent_id).await;
+                return Err(FsError::UnknownError);
+            }
+        };
         blob.async_drop().await.map_err(FsError::internal_error)?;
-        Ok(blob_id)
+        Ok((blob_id, intent_id))
     }
 
-    async fn create_file_blob(&self, parent: &BlobId) -> Result<BlobId, FsError> {
-        let mut blob = self
-            .blobstore
-            .create_file_blob(
-                parent,
-                // Make sure we flush this before the call site gets a chance to add this as an entry to its directory entry list.
-                // This way, we make sure the filesystem stays consistent even if it crashes mid way.
-                // Dropping by itself isn't enough to flush because it may go into a cache.
-                FlushBehavior::FlushImmediately,
-            )
-            .await
-            .map_err(|err| {
-                log::error!("Error creating file blob: {err:?}");
-                FsError::UnknownError
-            })?;
-
-        let blob_id = blob.blob_id();
-        blob.async_drop().await.map_err(FsError::internal_error)?;
-        Ok(blob_id)
+    async fn create_dir_blob(
+        &self,
+        parent: &BlobId,
+        name: &PathComponent,
+    ) -> Result<(BlobId, IntentId), FsError> {
+        self.create_blob(name, async |blob_id| {
+            self.blobstore
+                .create_dir_blob(
+                    blob_id,
+                    parent,
+                    // Make sure we flush this before the call site gets a chance to add this as an entry to its directory entry list.
+                    // This way, we make sure the filesystem stays consistent even if it crashes mid way.
+                    // Dropping by itself isn't enough to flush because it may go into a cache.
+                    FlushBehavior::FlushImmediately,
+                )
+                .await
+        })
+        .await
     }
 
-    async fn create_symlink_blob(&self, target: &str, parent: &BlobId) -> Result<BlobId, FsError> {
-        let mut blob = self
-            .blobstore
-            .create_symlink_blob(
-                parent,
-                target,
-                // Make sure we flush this before the call site gets a chance to add this as an entry to its directory entry list.
-                // This way, we make sure the filesystem stays consistent even if it crashes mid way.
-                // Dropping by itself isn't enough to flush because it may go into a cache.
-                FlushBehavior::FlushImmediately,
-            )
-            .await
-            .map_err(|err| {
-                log::error!("Error creating symlink blob: {err:?}");
-                FsError::UnknownError
-            })?;
+    async fn create_file_blob(
+        &self,
+        parent: &BlobId,
+        name: &PathComponent,
+    ) -> Result<(BlobId, IntentId), FsError> {
+        self.create_blob(name, async |blob_id| {
+            self.blobstore
+                .create_file_blob(
+                    blob_id,
+                    parent,
+                    // Make sure we flush this before the call site gets a chance to add this as an entry to its directory entry list.
+                    // This way, we make sure the filesystem stays consistent even if it crashes mid way.
+                    // Dropping by itself isn't enough to flush because it may go into a cache.
+                    FlushBehavior::FlushImmediately,
+                )
+                .await
+        })
+        .await
+    }
 
-        let blob_id = blob.blob_id();
-        blob.async_drop().await.map_err(FsError::internal_error)?;
-        Ok(blob_id)
+    async fn create_symlink_blob(
+        &self,
+        target: &str,
+        parent: &BlobId,
+        name: &PathComponent,
+    ) -> Result<(BlobId, IntentId), FsError> {
+        self.create_blob(name, async |blob_id| {
+            self.blobstore
+                .create_symlink_blob(
+                    blob_id,
+                    parent,
+                    target,
+                    // Make sure we flush this before the call site gets a chance to add this as an entry to its directory entry list.
+                    // This way, we make sure the filesystem stays consistent even if it crashes mid way.
+                    // Dropping by itself isn't enough to flush because it may go into a cache.
+                    FlushBehavior::FlushImmediately,
+                )
+                .await
+        })
+        .await
     }
 
     async fn on_rename_overwrites_destination(
         &self,
+        old_destination_entry_type: EntryType,
         old_destination_blob_id: BlobId,
     ) -> FsResult<()> {
-        let result = self
-            .blobstore
-            .remove_by_id(&old_destination_blob_id)
-            .await
-            .map_err(FsError::internal_error)?;
+        if !old_destination_entry_type.has_blob() {
+            // Fifos, sockets and devices don't have a blob, removing their entry was enough
+            return Ok(());
+        }
+        let result = remove_link_to_blob(self.blobstore, &old_destination_blob_id).await?;
         match result {
             RemoveResult::SuccessfullyRemoved => Ok(()),
             RemoveResult::NotRemovedBecauseItDoesntExist => {
@@ -156,6 +176,22 @@ where
         }
     }
 
+    /// Update the modification timestamps of both directories involved in a move from `self` to `newparent`
+    async fn update_modification_timestamps_after_move(&self, newparent: &Self) -> FsResult<()> {
+        // TODO This requires loading the grandparent blobs so we can update the parent blob's timestamps.
+        //      Can this cause a deadlock? What if one of the grandparents is already loaded as one of the parents?
+        let (source_update, dest_update) = join!(
+            self.node_info
+                .update_modification_timestamp_in_parent(self.blobstore),
+            newparent
+                .node_info
+                .update_modification_timestamp_in_parent(self.blobstore),
+        );
+        source_update?;
+        dest_update?;
+        Ok(())
+    }
+
     // TODO Add tests for this ancestor check
     #[cfg(feature = "ancestor_checks_on_move")]
     async fn validate_move_doesnt_cause_cycle(
@@ -174,14 +210,99 @@ where
         }
     }
 
-    async fn remove_just_created_blob(&self, blob_id: BlobId) {
+    /// Add a link to the file or symlink blob of `source_entry` and return the attributes of the blob afterwards.
+    /// When the blob gets hard linked for the first time, this moves the metadata of `source_entry` into a new
+    /// link metadata blob so that it is shared by all links, see [link_metadata].
+    async fn add_link_to_blob(
+        &self,
+        blob_id: &BlobId,
+        source_entry: &cryfs_fsblobstore::fsblobstore::DirEntry,
+    ) -> FsResult<BlobAttrs> {
+        let blob = self
+            .blobstore
+            .load(blob_id)
+            .await
+            .map_err(|err| {
+                log::error!("Error loading blob: {:?}", err);
+                FsError::UnknownError
+            })?
+            .ok_or(FsError::NodeDoesNotExist)?;
+        with_async_drop_2!(
+            blob,
+            {
+                blob.with_lock(async |blob| {
+                    if blob.num_links() == u32::MAX {
+                        return Err(FsError::TooManyLinks);
+                    }
+                    let num_links = if blob.is_hard_linked() {
+                        blob.increment_num_links().await.map_err(|err| {
+                            log::error!("Error incrementing link count: {err:?}");
+                            FsError::UnknownError
+                        })?
+                    } else {
+                        // TODO Changes to the metadata of the source entry made after the call site loaded it get lost here
+                        let link_metadata_blob_id = link_metadata::create_link_metadata_blob(
+                            self.blobstore,
+                            blob_id,
+                            source_entry,
+                        )
+                        .await?;
+                        match blob.add_first_hard_link(&link_metadata_blob_id).await {
+                            Ok(num_links) => num_links,
+                            Err(err) => {
+                                log::error!("Error adding first hard link: {err:?}");
+                                link_metadata::remove_link_metadata_blob(
+                                    self.blobstore,
+                                    &link_metadata_blob_id,
+                                )
+                                .await?;
+                                return Err(FsError::UnknownError);
+                            }
+                        }
+                    };
+                    // Flush the new link count before the call site adds the new entry to its directory.
+                    // This way, a crash can't leave us with more entries than links, which would cause the
+                    // blob to be removed while there are still entries referencing it.
+                    blob.flush().await.map_err(|err| {
+                        log::error!("Error flushing blob: {err:?}");
+                        FsError::UnknownError
+                    })?;
+                    let lstat_size = blob.lstat_size().await.map_err(|err| {
+                        log::error!("Error getting lstat size: {err:?}");
+                        FsError::UnknownError
+                    })?;
+                    Ok(BlobAttrs {
+                        lstat_size: NumBytes::from(lstat_size),
+                        num_links,
+                        link_metadata_blob_id: blob.link_metadata_blob_id(),
+                    })
+                })
+                .await
+            },
+            FsError::internal_error
+        )
+    }
+
+    async fn remove_just_created_blob(&self, blob_id: BlobId, intent_id: IntentId) {
         // TODO This is used in functions like create_child_dir, create_child_symlink and create_and_open_file,
         //      to remove a blob that was created but then we failed to add it to its parent directory.
         //      It might be more performant if we don't even create it if we know it already exists.
         //      But then we can't do self.load_blob() and self.create_dir_blob() above concurrently anymore.
         //      Maybe this works in a world where self.load_blob() is already preloaded and stored in the CryDir object?
         if let Err(err) = self.blobstore.remove_by_id(&blob_id).await {
+            // Keep the intent in the journal, the next mount will try to remove the blob again
             log::error!("Error removing just created dir blob: {err:?}");
+            return;
+        }
+        if let Err(err) = journal::complete_intent(self.blobstore, intent_id).await {
+            log::error!("Error completing intent for just created dir blob: {err:?}");
+        }
+    }
+
+    async fn remove_just_added_link(&self, blob_id: &BlobId) {
+        // Used in create_child_link to undo the link count increment if we failed to add the entry to this directory
+        if let Err(err) = remove_link_to_blob(self.blobstore, blob_id).await {
+            log::error!("Error removing just added link: {err:?}");
         }
     }
 
@@ -210,20 +331,18 @@ where
 
         let blob_details = self_blob
             .with_lock(async |self_blob| {
-                let self_dir = self_blob.as_dir().expect("Parent blob is not a directory");
+                let self_dir = self_blob
+                    .as_dir_mut()
+                    .expect("Parent blob is not a directory");
                 let entry = self_dir
                     .entry_by_name(name)
+                    .await
+                    .map_err(FsError::internal_error)?
                     .ok_or_else(|| FsError::NodeDoesNotExist)?;
-                let blob_id = *entry.blob_id();
-                let blob_type = match entry.entry_type() {
-                    EntryType::File => BlobType::File,
-                    EntryType::Dir => BlobType::Dir,
-                    EntryType::Symlink => BlobType::Symlink,
-                };
-                Ok((blob_id, blob_type))
+                Ok((*entry.blob_id(), entry.entry_type()))
             })
             .await;
-        let (blob_id, blob_type) = match blob_details {
+        let (blob_id, entry_type) = match blob_details {
             Ok(blob_details) => blob_details,
             Err(err) => {
                 self_blob
@@ -240,7 +359,7 @@ where
             self.node_info.ancestors_and_self().ancestors_and_self(),
             name.to_owned(),
             blob_id,
-            blob_type,
+            entry_type,
             self.node_info.atime_update_behavior(),
         );
         Ok(CryNode::new(
@@ -249,37 +368,58 @@ where
         ))
     }
 
-    async fn rename_child(&self, oldname: &PathComponent, newname: &PathComponent) -> FsResult<()> {
+    async fn rename_child(
+        &self,
+        oldname: &PathComponent,
+        newname: &PathComponent,
+        mode: RenameMode,
+    ) -> FsResult<()> {
         self.node_info
-            .concurrently_update_modification_timestamp_in_parent(async || {
+            .concurrently_update_modification_timestamp_in_parent(self.blobstore, async || {
                 let blob = self.load_blob().await?;
                 with_async_drop_2!(
                     blob,
                     {
+                        if mode == RenameMode::Exchange {
+                            return exchange_entries_in_dir(&blob, oldname, newname).await;
+                        }
                         blob.with_lock(async |blob| {
-                            Self::blob_as_dir_mut(&mut *blob)?
-                                .rename_entry_by_name(
-                                    oldname,
-                                    newname.to_owned(),
-                                    async |source_blob_type,
-                                           overwritten_blob_type,
-                                           overwritten_blobid| {
-                                        check_entry_overwrite_allowed(
-                                            &self.blobstore,
-                                            source_blob_type,
-                                            overwritten_blob_type,
-                                            overwritten_blobid,
-                                        )
-                                        .await?;
-                                        self.on_rename_overwrites_destination(*overwritten_blobid)
-                                            .await
-                                    },
-                                )
-                                .await
-                                .map_err(|err| match err {
-                                    RenameError::NodeDoesNotExist => FsError::NodeDoesNotExist,
-                                    RenameError::OnOverwriteError(e) => e,
-                                })
+                            let blob = Self::blob_as_dir_mut(&mut *blob)?;
+                            if mode == RenameMode::NoReplace
+                                && blob
+                                    .entry_by_name(newname)
+                                    .await
+                                    .map_err(FsError::internal_error)?
+                                    .is_some()
+                            {
+                                return Err(FsError::NodeAlreadyExists);
+                            }
+                            blob.rename_entry_by_name(
+                                oldname,
+                                newname.to_owned(),
+                                async |source_blob_type,
+                                       overwritten_blob_type,
+                                       overwritten_blobid| {
+                                    check_entry_overwrite_allowed(
+                                        &self.blobstore,
+                                        source_blob_type,
+                                        overwritten_blob_type,
+                                        overwritten_blobid,
+                                    )
+                                    .await?;
+                                    self.on_rename_overwrites_destination(
+                                        overwritten_blob_type,
+                                        *overwritten_blobid,
+                                    )
+                                    .await
+                                },
+                            )
+                            .await
+                            .map_err(|err| match err {
+                                RenameError::NodeDoesNotExist => FsError::NodeDoesNotExist,
+                                RenameError::OnOverwriteError(e) => e,
+                                RenameError::LoadFailed(e) => FsError::internal_error(e),
+                            })
                         })
                         .await
                     },
@@ -294,6 +434,7 @@ where
         oldname: &PathComponent,
         newparent: AsyncDropGuard<Self>,
         newname: &PathComponent,
+        mode: RenameMode,
     ) -> FsResult<()> {
         // TODO We're currently locking, releasing and re-locking blobs multiple times. This introduces race conditions and is not optimal for performance either.
         //      We should just lock each blob once and keep it locked until we're done. But we need to do it in a deadlock-free way, locking multiple
@@ -306,7 +447,7 @@ where
                 flatten_async_drop::<anyhow::Error, _, _, _, _>(source_parent, dest_parent)
                     .await
                     .map_err(FsError::internal_error)?;
-            // TODO Drop source_parent, dest_parent, newparent and self_blob concurrently
+            // TODO Drop source_parent, dest_parent and newparent concurrently
             with_async_drop_2!(
                 source_parent,
                 {
@@ -319,12 +460,69 @@ where
                                         Self::blob_as_dir_mut(&mut *source_parent_dir)?;
                                     let entry = source_parent_dir
                                         .entry_by_name(oldname)
+                                        .await
+                                        .map_err(FsError::internal_error)?
                                         .ok_or(FsError::NodeDoesNotExist)?;
                                     Ok::<_, FsError>(entry.clone()) // TODO No clone
                                 })
                                 .await?;
 
                             let self_blob_id = entry.blob_id();
+                            if mode == RenameMode::Exchange {
+                                #[cfg(feature = "ancestor_checks_on_move")]
+                                {
+                                    let dest_blob_id = dest_parent
+                                        .with_lock(async |dest_parent_dir| {
+                                            Ok::<_, FsError>(
+                                                *Self::blob_as_dir_mut(&mut *dest_parent_dir)?
+                                                    .entry_by_name(newname)
+                                                    .await
+                                                    .map_err(FsError::internal_error)?
+                                                    .ok_or(FsError::NodeDoesNotExist)?
+                                                    .blob_id(),
+                                            )
+                                        })
+                                        .await?;
+                                    // Both entries move into the other's directory, so neither of them may be an ancestor of the other's directory
+                                    self.validate_move_doesnt_cause_cycle(self_blob_id, &newparent)
+                                        .await?;
+                                    newparent
+                                        .validate_move_doesnt_cause_cycle(&dest_blob_id, self)
+                                        .await?;
+                                }
+                                exchange_entries_between_dirs(
+                                    self.blobstore,
+                                    &source_parent,
+                                    oldname,
+                                    &dest_parent,
+                                    newname,
+                                )
+                                .await?;
+                                return self
+                                    .update_modification_timestamps_after_move(&newparent)
+                                    .await;
+                            }
+                            let destination_is_same_blob = dest_parent
+                                .with_lock(async |dest_parent_dir| {
+                                    let dest_parent_dir =
+                                        Self::blob_as_dir_mut(&mut *dest_parent_dir)?;
+                                    let dest_entry = dest_parent_dir
+                                        .entry_by_name(newname)
+                                        .await
+                                        .map_err(FsError::internal_error)?;
+                                    if mode == RenameMode::NoReplace && dest_entry.is_some() {
+                                        return Err(FsError::NodeAlreadyExists);
+                                    }
+                                    Ok::<_, FsError>(
+                                        dest_entry
+                                            .is_some_and(|dest| dest.blob_id() == self_blob_id),
+                                    )
+                                })
+                                .await?;
+                            if destination_is_same_blob {
+                                // Source and destination are hard links to the same blob, and POSIX says rename should do nothing in this case.
+                                return Ok(());
+                            }
                             #[cfg(feature = "ancestor_checks_on_move")]
                             {
                                 // TODO This can happen concurrently with the load_blob above
@@ -332,105 +530,18 @@ where
                                     .await?;
                             }
 
-                            // TODO In theory, we could load self_blob concurrently with dest_parent_blob. No need to only do it after dest_parent_blob loaded.
-                            //      But it likely has some dependency with source_parent_blob.
-                            let self_blob = self
-                                .blobstore
-                                .load(self_blob_id)
-                                .await
-                                .map_err(|err| {
-                                    log::error!("Error loading blob: {:?}", err);
-                                    FsError::UnknownError
-                                })?
-                                .ok_or(
-                                    // TODO This branch means there was an entry in the parent dir but the blob itself doesn't exist. How should we handle this?
-                                    FsError::NodeDoesNotExist,
-                                )?;
-                            with_async_drop_2!(
-                                self_blob,
-                                {
-                                    let entry = source_parent
-                                        .with_lock(async |source_parent_dir| {
-                                            Self::blob_as_dir_mut(source_parent_dir)?
-                                                .remove_entry_by_name(oldname)
-                                                .map_err(|err| match err {
-                                                    RemoveError::NodeDoesNotExist => {
-                                                        FsError::NodeDoesNotExist
-                                                    }
-                                                })
-                                        })
-                                        .await?;
-                                    dest_parent
-                                        .with_lock(async |dest_parent_dir| {
-                                            Self::blob_as_dir_mut(dest_parent_dir)?
-                                                .add_or_overwrite_entry(
-                                                    newname.to_owned(),
-                                                    *entry.blob_id(),
-                                                    entry.entry_type(),
-                                                    entry.mode(),
-                                                    entry.uid(),
-                                                    entry.gid(),
-                                                    entry.last_access_time(),
-                                                    entry.last_modification_time(),
-                                                    async |source_blob_type, overwritten_blob_type, overwritten_blobid| {
-                                                        // Other checks (ensuring we don't overwrite a dir with a non-dir or a non-dir with a dir) is done in [DirEntryList::_check_allowed_overwrite].
-                                                        check_entry_overwrite_allowed(
-                                                            &self.blobstore,
-                                                            source_blob_type,
-                                                            overwritten_blob_type,
-                                                            overwritten_blobid,
-                                                        )
-                                                        .await?;
-                                                        self.on_rename_overwrites_destination(
-                                                            *overwritten_blobid,
-                                                        )
-                                                        .await
-                                                    },
-                                                )
-                                                .await
-                                                .map_err(|err| {
-                                                    // TODO Exception safety - we couldn't add the entry to the destination, but we already removed it from the source. We should probably re-add it to the source.
-                                                    match err {
-                                                        AddOrOverwriteError::ValidationFailed(fs_err) => {
-                                                            log::error!("Error in add_or_overwrite_entry validation: {fs_err:?}");
-                                                            FsError::internal_error(fs_err.into()) // This shouldn't happen because we are moving an already validated entry
-                                                        }
-                                                        AddOrOverwriteError::OnOverwriteError(err) => {
-                                                            log::error!("Error in add_or_overwrite_entry on_overwritten: {err:?}");
-                                                            err
-                                                        }
-                                                    }
-                                                })
-                                        }).await?;
-
-                                    self_blob
-                                        .with_lock(async |self_blob| {
-                                            self_blob.set_parent(&dest_parent.blob_id()).await
-                                        })
-                                        .await
-                                        .map_err(|err| {
-                                            // TODO Exception safety - we already changed parent dir entries but couldn't update the parent pointer. We should probably try to undo the parent dir entry changes.
-                                            log::error!("Error setting parent: {err:?}");
-                                            FsError::UnknownError
-                                        })?;
-
-                                    // TODO We can probably do this concurrently with the other modifications further up
-                                    // TODO This requires loading the grandparent blobs so we can update the parent blob's timestamps.
-                                    //      Can this cause a deadlock? What if one of the grandparents is already loaded as one of the parents?
-                                    let (source_update, dest_update) = join!(
-                                        self.node_info.update_modification_timestamp_in_parent(),
-                                        newparent
-                                            .node_info
-                                            .update_modification_timestamp_in_parent(),
-                                    );
-
-                                    source_update?;
-                                    dest_update?;
-
-                                    Ok::<(), FsError>(())
-                                },
-                                FsError::internal_error
+                            journal::move_entry_between_dirs(
+                                self.blobstore,
+                                &source_parent,
+                                oldname,
+                                &dest_parent,
+                                newname,
                             )
+                            .await?;
+
+                            // TODO We can probably do this concurrently with the other modifications further up
+                            self.update_modification_timestamps_after_move(&newparent)
+                                .await
                         },
                         FsError::internal_error
                     )
@@ -445,23 +556,18 @@ where
     async fn entries(&self) -> FsResult<Vec<DirEntry>> {
         // TODO Can we return an iterator instead of a Vec from here? But it'll need state with an async destructor. Probably needs async generators.
         self.node_info
-            .concurrently_maybe_update_access_timestamp_in_parent(async || {
+            .concurrently_maybe_update_access_timestamp_in_parent(self.blobstore, async || {
                 let blob = self.load_blob().await?;
                 with_async_drop_2!(
                     blob,
                     {
                         blob.with_lock(async |blob| {
-                            let blob = Self::blob_as_dir(blob)?;
+                            let blob = Self::blob_as_dir_mut(blob)?;
                             let result = blob
                                 .entries()
-                                .map(|entry| cryfs_rustfs::DirEntry {
-                                    name: entry.name().to_owned(),
-                                    kind: match entry.entry_type() {
-                                        EntryType::Dir => NodeKind::Dir,
-                                        EntryType::File => NodeKind::File,
-                                        EntryType::Symlink => NodeKind::Symlink,
-                                    },
-                                })
+                                .await
+                                .map_err(FsError::internal_error)?
+                                .map(convert_dir_entry)
                                 .collect();
                             Ok(result)
                         })
@@ -473,6 +579,54 @@ where
             .await
     }
 
+    async fn entries_with_attrs(&self) -> FsResult<Vec<(DirEntry, NodeAttrs)>> {
+        self.node_info
+            .concurrently_maybe_update_access_timestamp_in_parent(self.blobstore, async || {
+                let blob = self.load_blob().await?;
+                let entries = with_async_drop_2!(
+                    blob,
+                    {
+                        blob.with_lock(async |blob| {
+                            let blob = Self::blob_as_dir_mut(blob)?;
+                            let result = blob
+                                .entries()
+                                .await
+                                .map_err(FsError::internal_error)?
+                                .map(|entry| (entry.clone(), convert_dir_entry(entry)))
+                                .collect::<Vec<_>>();
+                            Ok::<_, FsError>(result)
+                        })
+                        .await
+                    },
+                    FsError::internal_error
+                )?;
+
+                // The size and number of links aren't stored in the dir entry but in the blob of each entry.
+                // Hard linked entries also store their metadata in a link metadata blob instead of the dir entry.
+                // TODO Load the blobs concurrently, but with a limit so large directories don't load all blobs at once
+                let mut result = Vec::with_capacity(entries.len());
+                for (entry, converted_entry) in entries {
+                    let blob_attrs =
+                        load_blob_attrs(self.blobstore, entry.blob_id(), entry.entry_type())
+                            .await?;
+                    let entry = load_metadata(
+                        self.blobstore,
+                        entry,
+                        blob_attrs.link_metadata_blob_id.as_ref(),
+                    )
+                    .await?;
+                    let attrs = dir_entry_to_node_attrs(
+                        &entry,
+                        blob_attrs.lstat_size,
+                        blob_attrs.num_links,
+                    );
+                    result.push((converted_entry, attrs));
+                }
+                Ok(result)
+            })
+            .await
+    }
+
     async fn create_child_dir(
         &self,
         name: &PathComponent,
@@ -481,24 +635,25 @@ where
         gid: cryfs_rustfs::Gid,
     ) -> FsResult<(NodeAttrs, AsyncDropGuard<CryDir<'_, B>>)> {
         self.node_info
-            .concurrently_update_modification_timestamp_in_parent(async || {
+            .concurrently_update_modification_timestamp_in_parent(self.blobstore, async || {
                 let self_blob_id = self.node_info.blob_id();
-                let (blob, new_dir_blob_id) =
-                    join!(self.load_blob(), self.create_dir_blob(&self_blob_id));
+                let (blob, new_dir_blob) =
+                    join!(self.load_blob(), self.create_dir_blob(&self_blob_id, name));
                 let mut blob = match blob {
                     Ok(blob) => blob,
                     Err(err) => {
                         log::error!("Error loading blob: {err:?}");
-                        if let Ok(new_dir_blob_id) = new_dir_blob_id {
-                            self.remove_just_created_blob(new_dir_blob_id).await;
+                        if let Ok((new_dir_blob_id, intent_id)) = new_dir_blob {
+                            self.remove_just_created_blob(new_dir_blob_id, intent_id)
+                                .await;
                         }
                         return Err(err);
                     }
                 };
                 // TODO Is this possible without to_owned()?
                 let name = name.to_owned();
-                let new_dir_blob_id = match new_dir_blob_id {
-                    Ok(new_dir_blob_id) => new_dir_blob_id,
+                let (new_dir_blob_id, intent_id) = match new_dir_blob {
+                    Ok(new_dir_blob) => new_dir_blob,
                     Err(err) => {
                         blob.async_drop().await.map_err(FsError::internal_error)?;
                         return Err(err);
@@ -522,11 +677,13 @@ where
                             atime,
                             mtime,
                         )
+                        .await
                         .map_err(|err| {
                             log::error!("Error in add_entry_dir: {err:?}");
                             match err {
                                 AddError::NodeAlreadyExists => FsError::NodeAlreadyExists,
                                 AddError::ValidationFailed(_) => FsError::InvalidOperation,
+                                AddError::LoadFailed(err) => FsError::internal_error(err),
                             }
                         })?;
 
@@ -539,6 +696,7 @@ where
                             // TODO What should NumBytes be?
                             num_bytes: NumBytes::from(0),
                             num_blocks: None,
+                            rdev: 0,
                             atime,
                             mtime,
                             ctime: mtime,
@@ -549,11 +707,13 @@ where
                     Ok(attrs) => attrs,
                     Err(err) => {
                         log::error!("Error adding dir entry: {err:?}");
-                        self.remove_just_created_blob(new_dir_blob_id).await;
+                        self.remove_just_created_blob(new_dir_blob_id, intent_id)
+                            .await;
                         blob.async_drop().await.map_err(FsError::internal_error)?;
                         return Err(FsError::UnknownError);
                     }
                 };
+                journal::finish_create(self.blobstore, &blob, intent_id).await;
                 let node = CryDir::new(
                     &self.blobstore,
                     AsyncDropArc::new(NodeInfo::new_non_root_dir(
@@ -562,7 +722,7 @@ where
                         self.node_info.ancestors_and_self().ancestors_and_self(),
                         name,
                         new_dir_blob_id,
-                        BlobType::Dir,
+                        EntryType::Dir,
                         self.node_info.atime_update_behavior(),
                     )),
                 );
@@ -573,14 +733,16 @@ where
 
     async fn remove_child_dir(&self, name: &PathComponent) -> FsResult<()> {
         self.node_info
-            .concurrently_update_modification_timestamp_in_parent( async || {
+            .concurrently_update_modification_timestamp_in_parent(self.blobstore, async || {
                 let self_blob = self.load_blob().await?;
                 with_async_drop_2!(self_blob, {
                     let child_id = self_blob
                         .with_lock(async |self_blob| {
-                            let self_blob = Self::blob_as_dir(&*self_blob)?;
+                            let self_blob = Self::blob_as_dir_mut(&mut *self_blob)?;
                             let child_entry = self_blob
                                 .entry_by_name(name)
+                                .await
+                                .map_err(FsError::internal_error)?
                                 .ok_or_else(|| FsError::NodeDoesNotExist)?;
                             if child_entry.entry_type() != EntryType::Dir {
                                 Err(FsError::NodeIsNotADirectory)?;
@@ -592,68 +754,23 @@ where
                     let mut child_blob = self.blobstore.load(&child_id).await.map_err(|_| FsError::NodeDoesNotExist)?.ok_or_else(|| FsError::NodeDoesNotExist)?;
 
                     let entries_check = child_blob.with_lock(async |child_blob| {
-                        let child_blob_dir = Self::blob_as_dir(&child_blob).map_err(|err| {
+                        let child_blob_dir = Self::blob_as_dir_mut(child_blob).map_err(|err| {
                             FsError::CorruptedFilesystem {
                                 // TODO Add to message what it actually is
                                 message: format!("Blob {:?} is listed as a directory in its parent directory but is actually not a directory: {err:?}", child_id),
                             }
                         })?;
-                        if child_blob_dir.entries().len() > 0 {
+                        if child_blob_dir.entries().await.map_err(FsError::internal_error)?.len() > 0 {
                             return Err(FsError::CannotRemoveNonEmptyDirectory)
                         }
                         Ok(())
                     }).await;
-                    if let Err(err) = entries_check {
-                        child_blob.async_drop().await.map_err(FsError::internal_error)?;
-                        return Err(err);
-                    }
+                    // We need to release the child blob before removing it
+                    child_blob.async_drop().await.map_err(FsError::internal_error)?;
+                    entries_check?;
 
                     // TODO We released the lock on self_blob above and are now re-locking it. There is a race condition here.
-
-                    // First remove the entry, then flush that change, and only then remove the blob.
-                    // This is to make sure the file system doesn't end up in an invalid state
-                    // where the blob is removed but the entry is still there.
-
-                    let removed_entry = self_blob.with_lock(async |self_blob| {
-                        let self_blob = Self::blob_as_dir_mut(&mut *self_blob)?;
-                        let entry = self_blob.remove_entry_by_name(name)
-                            .map_err(|err| match err {
-                                RemoveError::NodeDoesNotExist => FsError::NodeDoesNotExist
-                            })?;
-                        match self_blob.flush().await {
-                            Err(err) => {
-                                log::error!("Error flushing blob: {err:?}");
-                                Err(FsError::UnknownError)
-                            }
-                            Ok(()) => {
-                                Ok(entry)
-                            }
-                        }
-                    }).await;
-                    let removed_entry = match removed_entry {
-                        Ok(removed_entry) => removed_entry,
-                        Err(err) => {
-                            child_blob.async_drop().await.map_err(FsError::internal_error)?;
-                            return Err(err);
-                        }
-                    };
-                    assert_eq!(*removed_entry.blob_id(), child_blob.blob_id());
-
-                    let remove_result = ConcurrentFsBlob::remove(child_blob).await;
-                    match remove_result {
-                        Ok(RemoveResult::SuccessfullyRemoved) => Ok(()),
-                        Ok(RemoveResult::NotRemovedBecauseItDoesntExist) => {
-                            Err(FsError::CorruptedFilesystem {
-                                message: format!(
-                                    "Removed entry {name} from directory but didn't find its blob {child_id:?} to remove"
-                                ),
-                            })
-                        }
-                        Err(err) => {
-                            log::error!("Error removing blob: {err:?}");
-                            Err(FsError::UnknownError)
-                        }
-                    }
+                    journal::remove_dir(self.blobstore, &self_blob, name, &child_id).await
                 }, FsError::internal_error)
             })
             .await
@@ -667,30 +784,31 @@ where
         gid: cryfs_rustfs::Gid,
     ) -> FsResult<(NodeAttrs, AsyncDropGuard<CrySymlink<B>>)> {
         self.node_info
-            .concurrently_update_modification_timestamp_in_parent(async || {
+            .concurrently_update_modification_timestamp_in_parent(self.blobstore, async || {
                 // TODO What should NumBytes be? Also, no unwrap?
                 let num_bytes = NumBytes::from(u64::try_from(name.len()).unwrap());
 
                 let self_blob_id = self.node_info.blob_id();
 
-                let (blob, new_symlink_blob_id) = join!(
+                let (blob, new_symlink_blob) = join!(
                     self.load_blob(),
-                    self.create_symlink_blob(target, self_blob_id),
+                    self.create_symlink_blob(target, self_blob_id, name),
                 );
                 let mut blob = match blob {
                     Ok(blob) => blob,
                     Err(err) => {
                         log::error!("Error loading blob: {err:?}");
-                        if let Ok(new_symlink_blob_id) = new_symlink_blob_id {
-                            self.remove_just_created_blob(new_symlink_blob_id).await;
+                        if let Ok((new_symlink_blob_id, intent_id)) = new_symlink_blob {
+                            self.remove_just_created_blob(new_symlink_blob_id, intent_id)
+                                .await;
                         }
                         return Err(err);
                     }
                 };
                 // TODO Is this possible without to_owned()?
                 let name = name.to_owned();
-                let new_symlink_blob_id = match new_symlink_blob_id {
-                    Ok(id) => id,
+                let (new_symlink_blob_id, intent_id) = match new_symlink_blob {
+                    Ok(new_symlink_blob) => new_symlink_blob,
                     Err(err) => {
                         log::error!("Error creating symlink blob: {err:?}");
                         blob.async_drop().await.map_err(FsError::internal_error)?;
@@ -714,11 +832,13 @@ where
                             atime,
                             mtime,
                         )
+                        .await
                         .map_err(|err| {
                             log::error!("Error in add_entry_symlink: {err:?}");
                             match err {
                                 AddError::NodeAlreadyExists => FsError::NodeAlreadyExists,
                                 AddError::ValidationFailed(_) => FsError::InvalidOperation,
+                                AddError::LoadFailed(err) => FsError::internal_error(err),
                             }
                         })?;
 
@@ -731,6 +851,7 @@ where
                             gid,
                             num_bytes,
                             num_blocks: None,
+                            rdev: 0,
                             atime,
                             mtime,
                             ctime: mtime,
@@ -741,11 +862,13 @@ where
                     Ok(attrs) => attrs,
                     Err(err) => {
                         log::error!("Error adding dir entry: {err:?}");
-                        self.remove_just_created_blob(new_symlink_blob_id).await;
+                        self.remove_just_created_blob(new_symlink_blob_id, intent_id)
+                            .await;
                         blob.async_drop().await.map_err(FsError::internal_error)?;
                         return Err(FsError::UnknownError);
                     }
                 };
+                journal::finish_create(self.blobstore, &blob, intent_id).await;
                 let node = CrySymlink::new(
                     &self.blobstore,
                     AsyncDropArc::new(NodeInfo::new_non_root_dir(
@@ -754,7 +877,7 @@ where
                         self.node_info.ancestors_and_self().ancestors_and_self(),
                         name,
                         new_symlink_blob_id,
-                        BlobType::Symlink,
+                        EntryType::Symlink,
                         self.node_info.atime_update_behavior(),
                     )),
                 );
@@ -763,8 +886,205 @@ where
             .await
     }
 
+    async fn create_child_link(
+        &self,
+        name: &PathComponent,
+        node: &CryNode<B>,
+    ) -> FsResult<(NodeAttrs, AsyncDropGuard<CryNode<B>>)> {
+        let node_info = node.node_info();
+        let entry_type = node_info.node_type();
+        if entry_type == EntryType::Dir {
+            return Err(FsError::CannotHardLinkDirectory);
+        }
+        if !entry_type.has_blob() {
+            // TODO Support hard links to fifos, sockets and devices. They don't have a blob to store the link count in.
+            return Err(FsError::NotImplemented);
+        }
+        if !self.blobstore.format().is_extended() {
+            // CryFS 1.0 can't read blobs with a link count
+            return Err(FsError::HardLinksNotSupported);
+        }
+        let source_entry = node_info
+            .load_dir_entry()
+            .await?
+            .expect("Only the root dir doesn't have a dir entry but we know this isn't a dir");
+        let blob_id = *source_entry.blob_id();
+
+        self.node_info
+            .concurrently_update_modification_timestamp_in_parent(self.blobstore, async || {
+                let blob_attrs = self.add_link_to_blob(&blob_id, &source_entry).await?;
+                // The metadata in the dir entries of hard linked blobs is outdated, the up to date metadata is in their link metadata blob
+                let metadata = match load_metadata(
+                    self.blobstore,
+                    source_entry.clone(),
+                    blob_attrs.link_metadata_blob_id.as_ref(),
+                )
+                .await
+                {
+                    Ok(metadata) => metadata,
+                    Err(err) => {
+                        self.remove_just_added_link(&blob_id).await;
+                        return Err(err);
+                    }
+                };
+
+                let name = name.to_owned();
+                let mut blob = match self.load_blob().await {
+                    Ok(blob) => blob,
+                    Err(err) => {
+                        log::error!("Error loading blob: {err:?}");
+                        self.remove_just_added_link(&blob_id).await;
+                        return Err(err);
+                    }
+                };
+                let attrs: FsResult<NodeAttrs> = blob
+                    .with_lock(async |blob| {
+                        let blob = Self::blob_as_dir_mut(&mut *blob)?;
+                        blob.add_entry_link(name.clone(), &source_entry)
+                            .await
+                            .map_err(|err| {
+                                log::error!("Error in add_entry_link: {err:?}");
+                                match err {
+                                    AddError::NodeAlreadyExists => FsError::NodeAlreadyExists,
+                                    AddError::ValidationFailed(_) => FsError::InvalidOperation,
+                                    AddError::LoadFailed(err) => FsError::internal_error(err),
+                                }
+                            })?;
+                        Ok(dir_entry_to_node_attrs(
+                            &metadata,
+                            blob_attrs.lstat_size,
+                            blob_attrs.num_links,
+                        ))
+                    })
+                    .await;
+                let attrs = match attrs {
+                    Ok(attrs) => attrs,
+                    Err(err) => {
+                        self.remove_just_added_link(&blob_id).await;
+                        blob.async_drop().await.map_err(FsError::internal_error)?;
+                        return Err(err);
+                    }
+                };
+                let node_info = NodeInfo::new_non_root_dir(
+                    blob,
+                    #[cfg(feature = "ancestor_checks_on_move")]
+                    self.node_info.ancestors_and_self().ancestors_and_self(),
+                    name,
+                    blob_id,
+                    entry_type,
+                    self.node_info.atime_update_behavior(),
+                );
+                Ok((
+                    attrs,
+                    CryNode::new(AsyncDropArc::clone(self.blobstore), node_info),
+                ))
+            })
+            .await
+    }
+
+    async fn create_child_special_file(
+        &self,
+        name: &PathComponent,
+        mode: cryfs_rustfs::Mode,
+        uid: cryfs_rustfs::Uid,
+        gid: cryfs_rustfs::Gid,
+        rdev: u32,
+    ) -> FsResult<(NodeAttrs, AsyncDropGuard<CryNode<B>>)> {
+        let entry_type = match mode.node_kind() {
+            NodeKind::Fifo => EntryType::Fifo,
+            NodeKind::Socket => EntryType::Socket,
+            NodeKind::CharDevice => EntryType::CharDevice,
+            NodeKind::BlockDevice => EntryType::BlockDevice,
+            NodeKind::File | NodeKind::Dir | NodeKind::Symlink => {
+                log::error!(
+                    "create_child_special_file: called with mode {mode} that isn't a special file"
+                );
+                return Err(FsError::InvalidOperation);
+            }
+        };
+        if !self.blobstore.format().is_extended() {
+            // CryFS 1.0 can't read directories with special file entries
+            return Err(FsError::NodeKindNotSupported);
+        }
+        // The kernel should pass rdev=0 for fifos and sockets, but we don't store it for those in any case
+        let rdev = if entry_type.is_device() { rdev } else { 0 };
+        self.node_info
+            .concurrently_update_modification_timestamp_in_parent(self.blobstore, async || {
+                // Special files aren't backed by a blob, but they still need a unique id so that they have an inode number
+                let new_id = BlobId::new_random();
+                let mut blob = self.load_blob().await?;
+                // TODO Is this possible without to_owned()?
+                let name = name.to_owned();
+
+                let atime = SystemTime::now();
+                let mtime = atime;
+
+                let attrs: FsResult<NodeAttrs> = blob
+                    .with_lock(async |blob| {
+                        let blob = Self::blob_as_dir_mut(&mut *blob)?;
+
+                        blob.add_entry_special(
+                            name.clone(),
+                            new_id,
+                            entry_type,
+                            // TODO Don't convert between fs_types::xxx and cryfs_rustfs::xxx but reuse the same types
+                            Mode::from(u32::from(mode)),
+                            Uid::from(u32::from(uid)),
+                            Gid::from(u32::from(gid)),
+                            rdev,
+                            atime,
+                            mtime,
+                        )
+                        .await
+                        .map_err(|err| {
+                            log::error!("Error in add_entry_special: {err:?}");
+                            match err {
+                                AddError::NodeAlreadyExists => FsError::NodeAlreadyExists,
+                                AddError::ValidationFailed(_) => FsError::InvalidOperation,
+                                AddError::LoadFailed(err) => FsError::internal_error(err),
+                            }
+                        })?;
+
+                        Ok(NodeAttrs {
+                            nlink: 1,
+                            mode,
+                            uid,
+                            gid,
+                            num_bytes: NumBytes::from(0),
+                            num_blocks: None,
+                            rdev,
+                            atime,
+                            mtime,
+                            ctime: mtime,
+                        })
+                    })
+                    .await;
+                let attrs = match attrs {
+                    Ok(attrs) => attrs,
+                    Err(err) => {
+                        blob.async_drop().await.map_err(FsError::internal_error)?;
+                        return Err(err);
+                    }
+                };
+                let node_info = NodeInfo::new_non_root_dir(
+                    blob,
+                    #[cfg(feature = "ancestor_checks_on_move")]
+                    self.node_info.ancestors_and_self().ancestors_and_self(),
+                    name,
+                    new_id,
+                    entry_type,
+                    self.node_info.atime_update_behavior(),
+                );
+                Ok((
+                    attrs,
+                    CryNode::new(AsyncDropArc::clone(self.blobstore), node_info),
+                ))
+            })
+            .await
+    }
+
     async fn remove_child_file_or_symlink(&self, name: &PathComponent) -> FsResult<()> {
-        self.node_info.concurrently_update_modification_timestamp_in_parent( async || {
+        self.node_info.concurrently_update_modification_timestamp_in_parent(self.blobstore, async || {
             let blob = self.load_blob().await?;
 
             with_async_drop_2!(blob, {
@@ -774,8 +1094,10 @@ where
                     // This is to make sure the file system doesn't end up in an invalid state
                     // where the blob is removed but the entry is still there.
                     let removed = blob.remove_entry_by_name(name)
+                        .await
                         .map_err(|err| match err {
-                            RemoveError::NodeDoesNotExist => FsError::NodeDoesNotExist
+                            RemoveError::NodeDoesNotExist => FsError::NodeDoesNotExist,
+                            RemoveError::LoadFailed(err) => FsError::internal_error(err),
                         })?;
                     blob.flush().await.map_err(|err| {
                         log::error!("Error flushing blob: {err:?}");
@@ -789,8 +1111,12 @@ where
                     EntryType::Dir => {
                         Err(FsError::NodeIsADirectory)
                     }
+                    EntryType::Fifo | EntryType::Socket | EntryType::CharDevice | EntryType::BlockDevice => {
+                        // These don't have a blob, removing the entry was enough
+                        Ok(())
+                    }
                     EntryType::File | EntryType::Symlink => {
-                        let remove_result = self.blobstore.remove_by_id(blob_id).await.map_err(FsError::internal_error)?;
+                        let remove_result = remove_link_to_blob(self.blobstore, blob_id).await?;
                         match remove_result {
                             RemoveResult::SuccessfullyRemoved => Ok(()),
                             RemoveResult::NotRemovedBecauseItDoesntExist => {
@@ -820,23 +1146,24 @@ where
         AsyncDropGuard<CryOpenFile<B>>,
     )> {
         self.node_info
-            .concurrently_update_modification_timestamp_in_parent(async || {
+            .concurrently_update_modification_timestamp_in_parent(self.blobstore, async || {
                 let self_blob_id = self.node_info.blob_id();
-                let (blob, new_file_blob_id) =
-                    join!(self.load_blob(), self.create_file_blob(&self_blob_id),);
+                let (blob, new_file_blob) =
+                    join!(self.load_blob(), self.create_file_blob(&self_blob_id, name));
                 let mut blob = match blob {
                     Ok(blob) => blob,
                     Err(err) => {
                         log::error!("Error loading blob: {err:?}");
-                        if let Ok(new_file_blob_id) = new_file_blob_id {
-                            self.remove_just_created_blob(new_file_blob_id).await;
+                        if let Ok((new_file_blob_id, intent_id)) = new_file_blob {
+                            self.remove_just_created_blob(new_file_blob_id, intent_id)
+                                .await;
                         }
                         return Err(err);
                     }
                 };
 
-                let new_file_blob_id = match new_file_blob_id {
-                    Ok(id) => id,
+                let (new_file_blob_id, intent_id) = match new_file_blob {
+                    Ok(new_file_blob) => new_file_blob,
                     Err(err) => {
                         blob.async_drop().await.map_err(FsError::internal_error)?;
                         return Err(err);
@@ -860,11 +1187,13 @@ where
                             atime,
                             mtime,
                         )
+                        .await
                         .map_err(|err| {
                             log::error!("Error in add_entry_file: {err:?}");
                             match err {
                                 AddError::NodeAlreadyExists => FsError::NodeAlreadyExists,
                                 AddError::ValidationFailed(_) => FsError::InvalidOperation,
+                                AddError::LoadFailed(err) => FsError::internal_error(err),
                             }
                         })?;
 
@@ -876,6 +1205,7 @@ where
                             gid,
                             num_bytes: NumBytes::from(0),
                             num_blocks: None,
+                            rdev: 0,
                             atime,
                             mtime,
                             ctime: mtime,
@@ -886,18 +1216,20 @@ where
                     Ok(attrs) => attrs,
                     Err(err) => {
                         log::error!("Error adding dir entry: {err:?}");
-                        self.remove_just_created_blob(new_file_blob_id).await;
+                        self.remove_just_created_blob(new_file_blob_id, intent_id)
+                            .await;
                         blob.async_drop().await.map_err(FsError::internal_error)?;
                         return Err(FsError::UnknownError);
                     }
                 };
+                journal::finish_create(self.blobstore, &blob, intent_id).await;
                 let node_info = AsyncDropArc::new(NodeInfo::new_non_root_dir(
                     blob,
                     #[cfg(feature = "ancestor_checks_on_move")]
                     self.node_info.ancestors_and_self().ancestors_and_self(),
                     name.to_owned(),
                     new_file_blob_id,
-                    BlobType::File,
+                    EntryType::File,
                     self.node_info.atime_update_behavior(),
                 ));
 
@@ -921,7 +1253,7 @@ where
             r1?;
             r2?;
         }
-        Ok(())
+        self.node_info.sync(self.blobstore).await
     }
 }
 
@@ -936,3 +1268,18 @@ where
         self.node_info.async_drop().await
     }
 }
+
+fn convert_dir_entry(entry: &cryfs_fsblobstore::fsblobstore::DirEntry) -> DirEntry {
+    DirEntry {
+        name: entry.name().to_owned(),
+        kind: match entry.entry_type() {
+            EntryType::Dir => NodeKind::Dir,
+            EntryType::File => NodeKind::File,
+            EntryType::Symlink => NodeKind::Symlink,
+            EntryType::Fifo => NodeKind::Fifo,
+            EntryType::Socket => NodeKind::Socket,
+            EntryType::CharDevice => NodeKind::CharDevice,
+            EntryType::BlockDevice => NodeKind::BlockDevice,
+        },
+    }
+}
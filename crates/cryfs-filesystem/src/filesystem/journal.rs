@@ -0,0 +1,719 @@
+//! Multi-blob metadata operations that go through the intent journal of the [ConcurrentFsBlobStore],
+//! see [cryfs_fsblobstore::fsblobstore::IntentJournal].
+//!
+//! Each operation first adds an [Intent] to the journal, then modifies and flushes the blobs, and only then removes the intent.
+//! If we crash in between, [replay_pending_intents] finishes the operation (or rolls it back for [Intent::Create]) on the next mount.
+//! This is why the `apply_*` functions must be idempotent, we don't know how far the original operation got.
+
+use anyhow::Result;
+use futures::join;
+use std::fmt::Debug;
+
+use cryfs_blobstore::{BlobId, BlobStore, RemoveResult};
+use cryfs_fsblobstore::concurrentfsblobstore::{ConcurrentFsBlob, ConcurrentFsBlobStore};
+use cryfs_fsblobstore::fsblobstore::{
+    AddOrOverwriteError, DirEntry, Intent, IntentId, OverwrittenEntry, ReplaceError,
+};
+use cryfs_rustfs::{FsError, FsResult};
+use cryfs_utils::{
+    async_drop::{AsyncDrop, AsyncDropArc, AsyncDropGuard, flatten_async_drop},
+    path::PathComponent,
+    with_async_drop_2,
+};
+
+use super::device::check_entry_overwrite_allowed;
+use super::link_metadata;
+
+/// Move the entry `source_name` from `source_parent` into `dest_parent`, where it will be called `dest_name`.
+/// If `dest_parent` already has an entry called `dest_name`, it is overwritten.
+/// The caller is responsible for checking that the move is allowed, except for the checks about the overwritten entry.
+pub async fn move_entry_between_dirs<B>(
+    blobstore: &AsyncDropArc<ConcurrentFsBlobStore<B>>,
+    source_parent: &ConcurrentFsBlob<B>,
+    source_name: &PathComponent,
+    dest_parent: &ConcurrentFsBlob<B>,
+    dest_name: &PathComponent,
+) -> FsResult<()>
+where
+    B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
+    <B as BlobStore>::ConcreteBlob: Send + Sync + AsyncDrop<Error = anyhow::Error>,
+{
+    let get_entry = async |parent: &ConcurrentFsBlob<B>, name: &PathComponent| {
+        parent
+            .with_lock(async |parent| {
+                Ok::<_, FsError>(
+                    parent
+                        .as_dir_mut()
+                        .map_err(|_| FsError::NodeIsNotADirectory)?
+                        .entry_by_name(name)
+                        .await
+                        .map_err(FsError::internal_error)?
+                        .cloned(),
+                )
+            })
+            .await
+    };
+    let (entry, dest_entry) = join!(
+        get_entry(source_parent, source_name),
+        get_entry(dest_parent, dest_name),
+    );
+    let mut entry = entry?.ok_or(FsError::NodeDoesNotExist)?;
+    let overwritten = match dest_entry? {
+        Some(dest_entry) => {
+            check_entry_overwrite_allowed(
+                blobstore,
+                entry.entry_type(),
+                dest_entry.entry_type(),
+                dest_entry.blob_id(),
+            )
+            .await?;
+            let num_links = if dest_entry.entry_type().has_blob() {
+                let blob = load_blob(blobstore, dest_entry.blob_id())
+                    .await?
+                    .ok_or(FsError::NodeDoesNotExist)?;
+                with_async_drop_2!(
+                    blob,
+                    { Ok::<_, FsError>(blob.with_lock(async |blob| blob.num_links()).await) },
+                    FsError::internal_error
+                )?
+            } else {
+                1
+            };
+            Some(OverwrittenEntry {
+                blob_id: *dest_entry.blob_id(),
+                entry_type: dest_entry.entry_type(),
+                num_links,
+            })
+        }
+        None => None,
+    };
+    entry.set_name(dest_name.to_owned());
+
+    let intent_id = add_intent(
+        blobstore,
+        Intent::Move {
+            source_parent: source_parent.blob_id(),
+            source_name: source_name.to_owned(),
+            dest_parent: dest_parent.blob_id(),
+            entry: entry.clone(),
+            overwritten,
+        },
+    )
+    .await?;
+    apply_move(
+        blobstore,
+        source_parent,
+        source_name,
+        dest_parent,
+        &entry,
+        overwritten.as_ref(),
+    )
+    .await?;
+    complete_intent(blobstore, intent_id).await
+}
+
+/// Swap the entry `first` of `first_parent` with the entry `second` of `second_parent`, both given as they are before the exchange.
+/// This also updates the parent pointers of both entries' blobs.
+pub async fn exchange_entries<B>(
+    blobstore: &AsyncDropArc<ConcurrentFsBlobStore<B>>,
+    first_parent: &ConcurrentFsBlob<B>,
+    first: &DirEntry,
+    second_parent: &ConcurrentFsBlob<B>,
+    second: &DirEntry,
+) -> FsResult<()>
+where
+    B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
+    <B as BlobStore>::ConcreteBlob: Send + Sync + AsyncDrop<Error = anyhow::Error>,
+{
+    let intent_id = add_intent(
+        blobstore,
+        Intent::Exchange {
+            first_parent: first_parent.blob_id(),
+            first: first.clone(),
+            second_parent: second_parent.blob_id(),
+            second: second.clone(),
+        },
+    )
+    .await?;
+    apply_exchange(blobstore, first_parent, first, second_parent, second).await?;
+    complete_intent(blobstore, intent_id).await
+}
+
+/// Remove the entry `name` of `parent` and the empty directory blob `blob_id` it references.
+/// The caller is responsible for checking that the directory is empty.
+pub async fn remove_dir<B>(
+    blobstore: &AsyncDropArc<ConcurrentFsBlobStore<B>>,
+    parent: &ConcurrentFsBlob<B>,
+    name: &PathComponent,
+    blob_id: &BlobId,
+) -> FsResult<()>
+where
+    B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
+    <B as BlobStore>::ConcreteBlob: Send + Sync + AsyncDrop<Error = anyhow::Error>,
+{
+    let intent_id = add_intent(
+        blobstore,
+        Intent::RemoveDir {
+            parent: parent.blob_id(),
+            name: name.to_owned(),
+            blob_id: *blob_id,
+        },
+    )
+    .await?;
+    match apply_remove_dir(blobstore, parent, name, blob_id).await? {
+        RemoveResult::SuccessfullyRemoved => (),
+        RemoveResult::NotRemovedBecauseItDoesntExist => {
+            return Err(FsError::CorruptedFilesystem {
+                message: format!(
+                    "Removed entry {name} from directory but didn't find its blob {blob_id:?} to remove"
+                ),
+            });
+        }
+    }
+    complete_intent(blobstore, intent_id).await
+}
+
+/// Add an [Intent::Create] before creating the blob `blob_id` that will be added as the entry `name` to the directory `parent`.
+/// Once the entry was added, call [finish_create]. If adding the entry failed, remove the blob and call [complete_intent].
+pub async fn begin_create<B>(
+    blobstore: &AsyncDropArc<ConcurrentFsBlobStore<B>>,
+    parent: &BlobId,
+    name: &PathComponent,
+    blob_id: &BlobId,
+) -> FsResult<IntentId>
+where
+    B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
+    <B as BlobStore>::ConcreteBlob: Send + Sync + AsyncDrop<Error = anyhow::Error>,
+{
+    add_intent(
+        blobstore,
+        Intent::Create {
+            parent: *parent,
+            name: name.to_owned(),
+            blob_id: *blob_id,
+        },
+    )
+    .await
+}
+
+/// Finish a create operation started with [begin_create] after the new entry was added to `parent`.
+/// The entry was already added, so failing here doesn't fail the operation. If flushing fails, the intent just
+/// stays in the journal and the next mount keeps or rolls back the entry depending on whether it made it to disk.
+pub async fn finish_create<B>(
+    blobstore: &AsyncDropArc<ConcurrentFsBlobStore<B>>,
+    parent: &ConcurrentFsBlob<B>,
+    intent_id: IntentId,
+) where
+    B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
+    <B as BlobStore>::ConcreteBlob: Send + Sync + AsyncDrop<Error = anyhow::Error>,
+{
+    if flush_blob(parent).await.is_ok() {
+        // complete_intent already logs errors
+        let _ = complete_intent(blobstore, intent_id).await;
+    }
+}
+
+pub async fn complete_intent<B>(
+    blobstore: &AsyncDropArc<ConcurrentFsBlobStore<B>>,
+    intent_id: IntentId,
+) -> FsResult<()>
+where
+    B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
+    <B as BlobStore>::ConcreteBlob: Send + Sync + AsyncDrop<Error = anyhow::Error>,
+{
+    blobstore.complete_intent(intent_id).await.map_err(|err| {
+        log::error!("Error removing intent from journal: {err:?}");
+        FsError::UnknownError
+    })
+}
+
+/// Finish or roll back all operations that were still in the journal when the file system was unmounted the last time,
+/// e.g. because the process crashed. This must run before any other operation on the file system.
+pub async fn replay_pending_intents<B>(
+    blobstore: &AsyncDropArc<ConcurrentFsBlobStore<B>>,
+) -> Result<()>
+where
+    B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
+    <B as BlobStore>::ConcreteBlob: Send + Sync + AsyncDrop<Error = anyhow::Error>,
+{
+    for (intent_id, intent) in blobstore.pending_intents().await {
+        log::warn!("Found unfinished operation in the journal, replaying it: {intent:?}");
+        if let Err(err) = replay_intent(blobstore, &intent).await {
+            // Keeping the intent would fail the same way on every mount, so we drop it and leave the rest to cryfs-check.
+            log::error!(
+                "Failed to replay {intent:?}: {err:?}. The file system might be inconsistent, please run cryfs-check."
+            );
+        }
+        blobstore.complete_intent(intent_id).await?;
+    }
+    Ok(())
+}
+
+async fn replay_intent<B>(
+    blobstore: &AsyncDropArc<ConcurrentFsBlobStore<B>>,
+    intent: &Intent,
+) -> FsResult<()>
+where
+    B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
+    <B as BlobStore>::ConcreteBlob: Send + Sync + AsyncDrop<Error = anyhow::Error>,
+{
+    match intent {
+        Intent::Move {
+            source_parent,
+            source_name,
+            dest_parent,
+            entry,
+            overwritten,
+        } => {
+            let (source_parent, dest_parent) =
+                load_two_dirs(blobstore, source_parent, dest_parent).await?;
+            with_async_drop_2!(
+                source_parent,
+                {
+                    with_async_drop_2!(
+                        dest_parent,
+                        {
+                            apply_move(
+                                blobstore,
+                                &source_parent,
+                                source_name,
+                                &dest_parent,
+                                entry,
+                                overwritten.as_ref(),
+                            )
+                            .await
+                        },
+                        FsError::internal_error
+                    )
+                },
+                FsError::internal_error
+            )
+        }
+        Intent::Exchange {
+            first_parent,
+            first,
+            second_parent,
+            second,
+        } => {
+            let (first_parent, second_parent) =
+                load_two_dirs(blobstore, first_parent, second_parent).await?;
+            with_async_drop_2!(
+                first_parent,
+                {
+                    with_async_drop_2!(
+                        second_parent,
+                        {
+                            apply_exchange(blobstore, &first_parent, first, &second_parent, second)
+                                .await
+                        },
+                        FsError::internal_error
+                    )
+                },
+                FsError::internal_error
+            )
+        }
+        Intent::RemoveDir {
+            parent,
+            name,
+            blob_id,
+        } => {
+            let parent = load_blob(blobstore, parent)
+                .await?
+                .ok_or(FsError::NodeDoesNotExist)?;
+            with_async_drop_2!(
+                parent,
+                {
+                    // If the blob doesn't exist anymore, the original operation already removed it
+                    let _: RemoveResult =
+                        apply_remove_dir(blobstore, &parent, name, blob_id).await?;
+                    Ok(())
+                },
+                FsError::internal_error
+            )
+        }
+        Intent::Create {
+            parent, blob_id, ..
+        } => {
+            let parent_blob = load_blob(blobstore, parent)
+                .await?
+                .ok_or(FsError::NodeDoesNotExist)?;
+            let entry_was_added = with_async_drop_2!(
+                parent_blob,
+                {
+                    parent_blob
+                        .with_lock(async |parent_blob| {
+                            Ok::<_, FsError>(
+                                parent_blob
+                                    .as_dir_mut()
+                                    .map_err(|_| FsError::NodeIsNotADirectory)?
+                                    .entry_by_id(blob_id)
+                                    .await
+                                    .map_err(FsError::internal_error)?
+                                    .is_some(),
+                            )
+                        })
+                        .await
+                },
+                FsError::internal_error
+            )?;
+            if entry_was_added {
+                return Ok(());
+            }
+            let Some(mut blob) = load_blob(blobstore, blob_id).await? else {
+                // The blob was never created, there's nothing to roll back
+                return Ok(());
+            };
+            // The create didn't make it, roll it back. But if the blob was linked from somewhere else
+            // since, e.g. because the intent was left over from a failed flush, we need to keep it.
+            let is_referenced_elsewhere = blob
+                .with_lock(async |blob| blob.parent() != *parent || blob.is_hard_linked())
+                .await;
+            if is_referenced_elsewhere {
+                log::warn!(
+                    "Not rolling back creation of blob {blob_id:?} because it is referenced from elsewhere"
+                );
+                return blob.async_drop().await.map_err(FsError::internal_error);
+            }
+            let _: RemoveResult = ConcurrentFsBlob::remove(blob)
+                .await
+                .map_err(|err| FsError::InternalError { error: err })?;
+            Ok(())
+        }
+    }
+}
+
+async fn apply_move<B>(
+    blobstore: &AsyncDropArc<ConcurrentFsBlobStore<B>>,
+    source_parent: &ConcurrentFsBlob<B>,
+    source_name: &PathComponent,
+    dest_parent: &ConcurrentFsBlob<B>,
+    entry: &DirEntry,
+    overwritten: Option<&OverwrittenEntry>,
+) -> FsResult<()>
+where
+    B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
+    <B as BlobStore>::ConcreteBlob: Send + Sync + AsyncDrop<Error = anyhow::Error>,
+{
+    if entry.entry_type().has_blob() {
+        let Some(blob) = load_blob(blobstore, entry.blob_id()).await? else {
+            // This can only happen when replaying a move whose node was removed before the crash. Adding the entry would leave it dangling.
+            log::warn!(
+                "Blob {:?} of moved entry doesn't exist anymore, not moving it",
+                entry.blob_id()
+            );
+            return Ok(());
+        };
+        let dest_parent_id = dest_parent.blob_id();
+        with_async_drop_2!(
+            blob,
+            {
+                blob.with_lock(async |blob| {
+                    blob.set_parent(&dest_parent_id).await?;
+                    blob.flush().await
+                })
+                .await
+                .map_err(|err| {
+                    log::error!("Error setting parent: {err:?}");
+                    FsError::UnknownError
+                })
+            },
+            FsError::internal_error
+        )?;
+    }
+
+    dest_parent
+        .with_lock(async |dest_parent| {
+            let dest_parent = dest_parent
+                .as_dir_mut()
+                .map_err(|_| FsError::NodeIsNotADirectory)?;
+            dest_parent
+                .add_or_overwrite_entry(
+                    entry.name().to_owned(),
+                    *entry.blob_id(),
+                    entry.entry_type(),
+                    entry.mode(),
+                    entry.uid(),
+                    entry.gid(),
+                    entry.last_access_time(),
+                    entry.last_modification_time(),
+                    entry.xattrs().clone(),
+                    entry.rdev(),
+                    // The overwritten entry was already validated and its link is removed below
+                    async |_, _, _| Ok::<(), FsError>(()),
+                )
+                .await
+                .map_err(|err| match err {
+                    AddOrOverwriteError::ValidationFailed(err) => {
+                        log::error!("Error in add_or_overwrite_entry validation: {err:?}");
+                        FsError::internal_error(err.into()) // This shouldn't happen because we are moving an already validated entry
+                    }
+                    AddOrOverwriteError::OnOverwriteError(err) => err,
+                    AddOrOverwriteError::LoadFailed(err) => FsError::internal_error(err),
+                })?;
+            dest_parent.flush().await.map_err(FsError::internal_error)
+        })
+        .await?;
+
+    source_parent
+        .with_lock(async |source_parent| {
+            let source_parent = source_parent
+                .as_dir_mut()
+                .map_err(|_| FsError::NodeIsNotADirectory)?;
+            if source_parent
+                .entry_by_name(source_name)
+                .await
+                .map_err(FsError::internal_error)?
+                .is_some_and(|source| source.blob_id() == entry.blob_id())
+            {
+                source_parent
+                    .remove_entry_by_name(source_name)
+                    .await
+                    .expect("We just checked that the entry exists");
+            }
+            source_parent.flush().await.map_err(FsError::internal_error)
+        })
+        .await?;
+
+    if let Some(overwritten) = overwritten {
+        remove_overwritten_link(blobstore, overwritten).await?;
+    }
+    Ok(())
+}
+
+/// Remove the link to the blob of an entry that was overwritten by an [Intent::Move], unless a previous attempt already did.
+async fn remove_overwritten_link<B>(
+    blobstore: &AsyncDropArc<ConcurrentFsBlobStore<B>>,
+    overwritten: &OverwrittenEntry,
+) -> FsResult<()>
+where
+    B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
+    <B as BlobStore>::ConcreteBlob: Send + Sync + AsyncDrop<Error = anyhow::Error>,
+{
+    if !overwritten.entry_type.has_blob() {
+        // Fifos, sockets and devices don't have a blob, removing their entry was enough
+        return Ok(());
+    }
+    let Some(mut blob) = load_blob(blobstore, &overwritten.blob_id).await? else {
+        // A previous attempt already removed the blob
+        return Ok(());
+    };
+    // Returns `Some` with the link metadata blob of the blob if the blob should be removed
+    let remove_blob = blob
+        .with_lock(async |blob| {
+            if blob.num_links() != overwritten.num_links {
+                // A previous attempt already decremented the link count
+                return Ok::<_, anyhow::Error>(None);
+            }
+            if blob.num_links() > 1 {
+                blob.decrement_num_links().await?;
+                blob.flush().await?;
+                return Ok(None);
+            }
+            Ok(Some(blob.link_metadata_blob_id()))
+        })
+        .await;
+    match remove_blob {
+        Ok(Some(link_metadata_blob_id)) => {
+            if let Some(link_metadata_blob_id) = link_metadata_blob_id
+                && let Err(err) =
+                    link_metadata::remove_link_metadata_blob(blobstore, &link_metadata_blob_id)
+                        .await
+            {
+                blob.async_drop().await.map_err(FsError::internal_error)?;
+                return Err(err);
+            }
+            let _: RemoveResult = ConcurrentFsBlob::remove(blob)
+                .await
+                .map_err(|err| FsError::InternalError { error: err })?;
+            Ok(())
+        }
+        Ok(None) => blob.async_drop().await.map_err(FsError::internal_error),
+        Err(err) => {
+            log::error!("Error decrementing link count: {err:?}");
+            blob.async_drop().await.map_err(FsError::internal_error)?;
+            Err(FsError::UnknownError)
+        }
+    }
+}
+
+async fn apply_exchange<B>(
+    blobstore: &AsyncDropArc<ConcurrentFsBlobStore<B>>,
+    first_parent: &ConcurrentFsBlob<B>,
+    first: &DirEntry,
+    second_parent: &ConcurrentFsBlob<B>,
+    second: &DirEntry,
+) -> FsResult<()>
+where
+    B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
+    <B as BlobStore>::ConcreteBlob: Send + Sync + AsyncDrop<Error = anyhow::Error>,
+{
+    let replace_entry =
+        async |parent: &ConcurrentFsBlob<B>, replacement: &DirEntry, name: &PathComponent| {
+            parent
+                .with_lock(async |parent| {
+                    let parent = parent
+                        .as_dir_mut()
+                        .map_err(|_| FsError::NodeIsNotADirectory)?;
+                    parent
+                        .replace_entry_by_name(name, replacement)
+                        .await
+                        .map_err(|err| match err {
+                            ReplaceError::NodeDoesNotExist => FsError::NodeDoesNotExist,
+                            ReplaceError::LoadFailed(err) => FsError::internal_error(err),
+                        })?;
+                    parent.flush().await.map_err(FsError::internal_error)
+                })
+                .await
+        };
+    let (first_result, second_result) = join!(
+        replace_entry(first_parent, second, first.name()),
+        replace_entry(second_parent, first, second.name()),
+    );
+    first_result?;
+    second_result?;
+
+    let (first_result, second_result) = join!(
+        set_parent_of_entry_blob(blobstore, first, second_parent.blob_id()),
+        set_parent_of_entry_blob(blobstore, second, first_parent.blob_id()),
+    );
+    first_result?;
+    second_result?;
+    Ok(())
+}
+
+async fn set_parent_of_entry_blob<B>(
+    blobstore: &AsyncDropArc<ConcurrentFsBlobStore<B>>,
+    entry: &DirEntry,
+    new_parent: BlobId,
+) -> FsResult<()>
+where
+    B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
+    <B as BlobStore>::ConcreteBlob: Send + Sync + AsyncDrop<Error = anyhow::Error>,
+{
+    if !entry.entry_type().has_blob() {
+        // Fifos, sockets and devices don't have a blob, so there's no parent pointer to update
+        return Ok(());
+    }
+    let blob = load_blob(blobstore, entry.blob_id())
+        .await?
+        .ok_or(FsError::NodeDoesNotExist)?;
+    with_async_drop_2!(
+        blob,
+        {
+            blob.with_lock(async |blob| {
+                blob.set_parent(&new_parent).await?;
+                blob.flush().await
+            })
+            .await
+            .map_err(|err| {
+                log::error!("Error setting parent: {err:?}");
+                FsError::UnknownError
+            })
+        },
+        FsError::internal_error
+    )
+}
+
+async fn apply_remove_dir<B>(
+    blobstore: &AsyncDropArc<ConcurrentFsBlobStore<B>>,
+    parent: &ConcurrentFsBlob<B>,
+    name: &PathComponent,
+    blob_id: &BlobId,
+) -> FsResult<RemoveResult>
+where
+    B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
+    <B as BlobStore>::ConcreteBlob: Send + Sync + AsyncDrop<Error = anyhow::Error>,
+{
+    // First remove the entry and flush that change, and only then remove the blob.
+    // Even without the journal, this makes sure we don't end up with an entry whose blob is already removed.
+    parent
+        .with_lock(async |parent| {
+            let parent = parent
+                .as_dir_mut()
+                .map_err(|_| FsError::NodeIsNotADirectory)?;
+            if parent
+                .entry_by_name(name)
+                .await
+                .map_err(FsError::internal_error)?
+                .is_some_and(|entry| entry.blob_id() == blob_id)
+            {
+                parent
+                    .remove_entry_by_name(name)
+                    .await
+                    .expect("We just checked that the entry exists");
+            }
+            parent.flush().await.map_err(|err| {
+                log::error!("Error flushing blob: {err:?}");
+                FsError::UnknownError
+            })
+        })
+        .await?;
+    blobstore.remove_by_id(blob_id).await.map_err(|err| {
+        log::error!("Error removing blob: {err:?}");
+        FsError::UnknownError
+    })
+}
+
+async fn add_intent<B>(
+    blobstore: &AsyncDropArc<ConcurrentFsBlobStore<B>>,
+    intent: Intent,
+) -> FsResult<IntentId>
+where
+    B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
+    <B as BlobStore>::ConcreteBlob: Send + Sync + AsyncDrop<Error = anyhow::Error>,
+{
+    blobstore.add_intent(intent).await.map_err(|err| {
+        log::error!("Error adding intent to journal: {err:?}");
+        FsError::UnknownError
+    })
+}
+
+async fn flush_blob<B>(blob: &ConcurrentFsBlob<B>) -> FsResult<()>
+where
+    B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
+    <B as BlobStore>::ConcreteBlob: Send + Sync + AsyncDrop<Error = anyhow::Error>,
+{
+    blob.with_lock(async |blob| blob.flush().await)
+        .await
+        .map_err(|err| {
+            log::error!("Error flushing blob: {err:?}");
+            FsError::UnknownError
+        })
+}
+
+async fn load_blob<B>(
+    blobstore: &AsyncDropArc<ConcurrentFsBlobStore<B>>,
+    blob_id: &BlobId,
+) -> FsResult<Option<AsyncDropGuard<ConcurrentFsBlob<B>>>>
+where
+    B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
+    <B as BlobStore>::ConcreteBlob: Send + Sync + AsyncDrop<Error = anyhow::Error>,
+{
+    blobstore.load(blob_id).await.map_err(|err| {
+        log::error!("Error loading blob: {err:?}");
+        FsError::UnknownError
+    })
+}
+
+async fn load_two_dirs<B>(
+    blobstore: &AsyncDropArc<ConcurrentFsBlobStore<B>>,
+    first: &BlobId,
+    second: &BlobId,
+) -> FsResult<(
+    AsyncDropGuard<ConcurrentFsBlob<B>>,
+    AsyncDropGuard<ConcurrentFsBlob<B>>,
+)>
+where
+    B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
+    <B as BlobStore>::ConcreteBlob: Send + Sync + AsyncDrop<Error = anyhow::Error>,
+{
+    let load = async |blob_id| {
+        load_blob(blobstore, blob_id)
+            .await?
+            .ok_or(FsError::NodeDoesNotExist)
+    };
+    let (first, second) = join!(load(first), load(second));
+    flatten_async_drop::<anyhow::Error, _, _, _, _>(first, second)
+        .await
+        .map_err(FsError::internal_error)
+}
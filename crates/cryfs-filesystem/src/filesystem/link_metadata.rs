@@ -0,0 +1,172 @@
+//! Once a file or symlink is hard linked, its metadata (mode, owner, timestamps, extended attributes) is shared by all
+//! its links. It can't stay in the dir entries, because then changes made through one link wouldn't be visible
+//! through the other links. Instead, it's stored once in a separate file blob, the link metadata blob, whose id is
+//! stored in the link count header of the hard linked blob (see [FsBlob::link_metadata_blob_id]).
+//!
+//! The link metadata blob contains a serialized [DirEntry]. Its name and blob id aren't used, only its metadata.
+//! The dir entries of the links still store a copy of the metadata from when the blob was hard linked, but it is
+//! never updated anymore.
+
+use std::fmt::Debug;
+use std::io::Cursor;
+
+use cryfs_blobstore::{BlobId, BlobStore, RemoveResult};
+use cryfs_fsblobstore::concurrentfsblobstore::ConcurrentFsBlobStore;
+use cryfs_fsblobstore::fsblobstore::{DirEntry, FlushBehavior, FsBlob};
+use cryfs_rustfs::{FsError, FsResult};
+use cryfs_utils::{async_drop::AsyncDrop, with_async_drop_2};
+
+/// Create a link metadata blob for the blob `owner` containing the metadata of `entry` and return its id.
+/// The blob is flushed before this returns, so the call site can reference it from `owner` right away.
+pub async fn create_link_metadata_blob<B>(
+    blobstore: &ConcurrentFsBlobStore<B>,
+    owner: &BlobId,
+    entry: &DirEntry,
+) -> FsResult<BlobId>
+where
+    B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
+    <B as BlobStore>::ConcreteBlob: Send + Sync + AsyncDrop<Error = anyhow::Error>,
+{
+    let data = serialize(entry)?;
+    let blob_id = BlobId::new_random();
+    let blob = blobstore
+        .create_file_blob(&blob_id, owner, FlushBehavior::FlushImmediately)
+        .await
+        .map_err(|err| {
+            log::error!("Error creating link metadata blob: {err:?}");
+            FsError::UnknownError
+        })?;
+    with_async_drop_2!(
+        blob,
+        {
+            blob.with_lock(async |blob| {
+                let blob = blob.as_file_mut()?;
+                blob.write(&data, 0).await?;
+                blob.flush().await
+            })
+            .await
+            .map_err(|err| {
+                log::error!("Error writing link metadata blob: {err:?}");
+                FsError::UnknownError
+            })
+        },
+        FsError::internal_error
+    )?;
+    Ok(blob_id)
+}
+
+/// Run `f` on the metadata stored in the link metadata blob `link_metadata_blob_id` and write it back if `f` changed it.
+/// Concurrent calls for the same link metadata blob are serialized by the blob lock.
+pub async fn with_link_metadata<B, R>(
+    blobstore: &ConcurrentFsBlobStore<B>,
+    link_metadata_blob_id: &BlobId,
+    f: impl FnOnce(&mut DirEntry) -> FsResult<R>,
+) -> FsResult<R>
+where
+    B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
+    <B as BlobStore>::ConcreteBlob: Send + Sync + AsyncDrop<Error = anyhow::Error>,
+{
+    let blob = blobstore
+        .load(link_metadata_blob_id)
+        .await
+        .map_err(|err| {
+            log::error!("Error loading link metadata blob {link_metadata_blob_id:?}: {err:?}");
+            FsError::UnknownError
+        })?
+        .ok_or_else(|| FsError::CorruptedFilesystem {
+            message: format!("Didn't find link metadata blob {link_metadata_blob_id:?}"),
+        })?;
+    with_async_drop_2!(
+        blob,
+        {
+            blob.with_lock(async |blob| {
+                let blob = blob_as_link_metadata(blob)?;
+                let num_bytes = blob.num_bytes().await.map_err(corrupted)?;
+                let mut data = vec![0; num_bytes as usize];
+                blob.try_read(&mut data, 0).await.map_err(corrupted)?;
+                let mut entry =
+                    DirEntry::deserialize(&mut Cursor::new(&data)).map_err(corrupted)?;
+                let result = f(&mut entry)?;
+                let new_data = serialize(&entry)?;
+                if new_data != data {
+                    // Shrink after writing so that a crash in between doesn't cut off the old metadata
+                    blob.write(&new_data, 0).await.map_err(internal_error)?;
+                    if new_data.len() < data.len() {
+                        blob.resize(new_data.len() as u64)
+                            .await
+                            .map_err(internal_error)?;
+                    }
+                }
+                Ok(result)
+            })
+            .await
+        },
+        FsError::internal_error
+    )
+}
+
+/// Load the metadata stored in the link metadata blob `link_metadata_blob_id`.
+pub async fn load_link_metadata<B>(
+    blobstore: &ConcurrentFsBlobStore<B>,
+    link_metadata_blob_id: &BlobId,
+) -> FsResult<DirEntry>
+where
+    B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
+    <B as BlobStore>::ConcreteBlob: Send + Sync + AsyncDrop<Error = anyhow::Error>,
+{
+    with_link_metadata(blobstore, link_metadata_blob_id, |entry| Ok(entry.clone())).await
+}
+
+/// Remove the link metadata blob `link_metadata_blob_id` after the last link to its owner was removed.
+pub async fn remove_link_metadata_blob<B>(
+    blobstore: &ConcurrentFsBlobStore<B>,
+    link_metadata_blob_id: &BlobId,
+) -> FsResult<()>
+where
+    B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
+    <B as BlobStore>::ConcreteBlob: Send + Sync + AsyncDrop<Error = anyhow::Error>,
+{
+    match blobstore.remove_by_id(link_metadata_blob_id).await {
+        Ok(RemoveResult::SuccessfullyRemoved) => Ok(()),
+        Ok(RemoveResult::NotRemovedBecauseItDoesntExist) => {
+            // This can happen if we crashed after removing it but before removing its owner
+            log::warn!("Link metadata blob {link_metadata_blob_id:?} was already removed");
+            Ok(())
+        }
+        Err(err) => {
+            log::error!("Error removing link metadata blob: {err:?}");
+            Err(FsError::UnknownError)
+        }
+    }
+}
+
+fn blob_as_link_metadata<B>(
+    blob: &mut FsBlob<B>,
+) -> FsResult<&mut cryfs_fsblobstore::fsblobstore::FileBlob<B>>
+where
+    B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
+    <B as BlobStore>::ConcreteBlob: Send + Sync + AsyncDrop<Error = anyhow::Error>,
+{
+    let blob_id = blob.blob_id();
+    blob.as_file_mut()
+        .map_err(|err| FsError::CorruptedFilesystem {
+            message: format!("Link metadata blob {blob_id:?} isn't a file blob: {err:?}"),
+        })
+}
+
+fn serialize(entry: &DirEntry) -> FsResult<Vec<u8>> {
+    let mut data = Cursor::new(Vec::new());
+    entry.serialize(&mut data).map_err(internal_error)?;
+    Ok(data.into_inner())
+}
+
+fn corrupted(err: anyhow::Error) -> FsError {
+    FsError::CorruptedFilesystem {
+        message: format!("Link metadata blob is unreadable: {err:?}"),
+    }
+}
+
+fn internal_error(err: anyhow::Error) -> FsError {
+    log::error!("Error writing link metadata blob: {err:?}");
+    FsError::UnknownError
+}
@@ -1,9 +1,13 @@
 mod device;
 mod dir;
 mod file;
+mod journal;
+mod link_metadata;
 mod node;
 mod node_info;
 mod open_file;
 mod symlink;
 
+pub use cryfs_fsblobstore::fsblobstore::FsFormat;
 pub use device::CryDevice;
+pub use node::stable_id;
@@ -9,12 +9,18 @@ use std::time::SystemTime;
 use super::CryDevice;
 use super::node_info::NodeInfo;
 use super::{dir::CryDir, file::CryFile, symlink::CrySymlink};
-use cryfs_blobstore::BlobStore;
+use cryfs_blobstore::{BlobId, BlobStore};
 use cryfs_fsblobstore::concurrentfsblobstore::{ConcurrentFsBlob, ConcurrentFsBlobStore};
-use cryfs_fsblobstore::fsblobstore::BlobType;
-use cryfs_rustfs::{FsError, FsResult, NodeAttrs, NumBytes, object_based_api::Node};
+use cryfs_fsblobstore::fsblobstore::EntryType;
+use cryfs_rustfs::{FsError, FsResult, NodeAttrs, NumBytes, SetXattrMode, object_based_api::Node};
 use cryfs_utils::async_drop::{AsyncDrop, AsyncDropArc, AsyncDropGuard};
 
+/// Blob ids are random and never reused, so they identify a node across remounts.
+/// For fifos, sockets and devices, this is the unique id stored in their dir entry.
+pub fn stable_id(blob_id: &BlobId) -> u128 {
+    u128::from_be_bytes(*blob_id.data())
+}
+
 pub struct CryNode<B>
 where
     B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
@@ -51,6 +57,10 @@ where
         Self::new_internal(blobstore, AsyncDropArc::new(node_info))
     }
 
+    pub(super) fn node_info(&self) -> &NodeInfo<B> {
+        &self.node_info
+    }
+
     pub(super) fn new_internal(
         blobstore: AsyncDropGuard<AsyncDropArc<ConcurrentFsBlobStore<B>>>,
         node_info: AsyncDropGuard<AsyncDropArc<NodeInfo<B>>>,
@@ -63,6 +73,10 @@ where
 
     #[cfg(feature = "testutils")]
     async fn flush_blob(&self) -> FsResult<()> {
+        if !self.node_info.node_type().has_blob() {
+            // Fifos, sockets and devices only live in their parent's dir entry, so there's nothing to flush here
+            return Ok(());
+        }
         // TODO We'd only have to flush it if it's actually in some cache, but it might be far down the stack in some blockstore cache.
         let blob = self.node_info.load_blob(&self.blobstore).await?;
         with_async_drop_2!(
@@ -91,8 +105,12 @@ where
 {
     type Device = CryDevice<B>;
 
+    fn stable_id(&self) -> Option<u128> {
+        Some(stable_id(self.node_info.blob_id()))
+    }
+
     async fn as_dir<'a>(&'a self) -> FsResult<AsyncDropGuard<CryDir<'a, B>>> {
-        if self.node_info.node_type() == BlobType::Dir {
+        if self.node_info.node_type() == EntryType::Dir {
             Ok(CryDir::new(
                 &self.blobstore,
                 AsyncDropArc::clone(&self.node_info),
@@ -103,7 +121,7 @@ where
     }
 
     async fn as_symlink<'a>(&'a self) -> FsResult<AsyncDropGuard<CrySymlink<'a, B>>> {
-        if self.node_info.node_type() == BlobType::Symlink {
+        if self.node_info.node_type() == EntryType::Symlink {
             Ok(CrySymlink::new(
                 &self.blobstore,
                 AsyncDropArc::clone(&self.node_info),
@@ -115,15 +133,19 @@ where
 
     async fn as_file<'a>(&'a self) -> FsResult<AsyncDropGuard<CryFile<'a, B>>> {
         match self.node_info.node_type() {
-            BlobType::File => Ok(CryFile::new(
+            EntryType::File => Ok(CryFile::new(
                 &self.blobstore,
                 AsyncDropArc::clone(&self.node_info),
             )),
-            BlobType::Symlink => {
+            EntryType::Symlink
+            | EntryType::Fifo
+            | EntryType::Socket
+            | EntryType::CharDevice
+            | EntryType::BlockDevice => {
                 // TODO What's the right error here?
                 Err(FsError::UnknownError)
             }
-            BlobType::Dir => Err(FsError::NodeIsADirectory),
+            EntryType::Dir => Err(FsError::NodeIsADirectory),
         }
     }
 
@@ -146,6 +168,28 @@ where
             .await
     }
 
+    async fn getxattr(&self, name: &str) -> FsResult<Vec<u8>> {
+        self.node_info.getxattr(&self.blobstore, name).await
+    }
+
+    async fn listxattr(&self) -> FsResult<Vec<String>> {
+        self.node_info.listxattr(&self.blobstore).await
+    }
+
+    async fn setxattr(&self, name: &str, value: &[u8], mode: SetXattrMode) -> FsResult<()> {
+        if !self.blobstore.format().is_extended() {
+            // CryFS 1.0 can't read directories with entries that have extended attributes
+            return Err(FsError::XattrNotSupported);
+        }
+        self.node_info
+            .setxattr(&self.blobstore, name, value, mode)
+            .await
+    }
+
+    async fn removexattr(&self, name: &str) -> FsResult<()> {
+        self.node_info.removexattr(&self.blobstore, name).await
+    }
+
     #[cfg(feature = "testutils")]
     async fn fsync(&self, datasync: bool) -> FsResult<()> {
         // TODO Can we unify the fsync implementation betweeh CryNode, CryDir and CryOpenFile?
@@ -157,7 +201,7 @@ where
             r1?;
             r2?;
         }
-        Ok(())
+        self.node_info.sync(&self.blobstore).await
     }
 }
 
@@ -1,5 +1,6 @@
 use async_trait::async_trait;
 use cryfs_utils::with_async_drop_2;
+use std::borrow::Cow;
 use std::fmt::Debug;
 use std::time::SystemTime;
 use tokio::join;
@@ -7,16 +8,18 @@ use tokio::join;
 use cryfs_blobstore::{BlobId, BlobStore};
 use cryfs_fsblobstore::concurrentfsblobstore::{ConcurrentFsBlob, ConcurrentFsBlobStore};
 use cryfs_fsblobstore::fsblobstore::{
-    BlobType, DIR_LSTAT_SIZE, DirBlob, DirEntry, FileBlob, FsBlob, SetAttrError,
+    DIR_LSTAT_SIZE, DirBlob, DirEntry, EntryType, FileBlob, FsBlob, SetAttrError, SetXattrError,
     UpdateTimestampError,
 };
 use cryfs_fsblobstore::{Gid, Mode, Uid};
-use cryfs_rustfs::{AtimeUpdateBehavior, FsError, FsResult, NodeAttrs, NumBytes};
+use cryfs_rustfs::{AtimeUpdateBehavior, FsError, FsResult, NodeAttrs, NumBytes, SetXattrMode};
 use cryfs_utils::{
     async_drop::{AsyncDrop, AsyncDropGuard},
-    path::PathComponentBuf,
+    path::{PathComponent, PathComponentBuf},
 };
 
+use super::link_metadata;
+
 // TODO The ancestor_checks_on_move feature implements checks that when moving a node to a different directory,
 //      it doesn't get moved into an ancestor or child of itself. But it requires that `NodeInfo` remembers the blob ids
 //      of all ancestors, from the root blob to the blob itself. This could be expensive? Or maybe not?
@@ -47,9 +50,10 @@ where
 
         name: PathComponentBuf,
 
+        /// For fifos, sockets and devices, this is a unique id that isn't backed by a blob.
         blob_id: BlobId,
 
-        blob_type: BlobType,
+        entry_type: EntryType,
 
         atime_update_behavior: AtimeUpdateBehavior,
     },
@@ -86,7 +90,7 @@ where
         #[cfg(feature = "ancestor_checks_on_move")] ancestors: Box<[BlobId]>,
         name: PathComponentBuf,
         blob_id: BlobId,
-        blob_type: BlobType,
+        entry_type: EntryType,
         atime_update_behavior: AtimeUpdateBehavior,
     ) -> AsyncDropGuard<Self> {
         AsyncDropGuard::new(Self {
@@ -96,7 +100,7 @@ where
                 ancestors,
                 name,
                 blob_id,
-                blob_type,
+                entry_type,
                 atime_update_behavior,
             },
         })
@@ -143,10 +147,10 @@ where
         AncestorChain::new(ancestors_and_self)
     }
 
-    pub fn node_type(&self) -> BlobType {
+    pub fn node_type(&self) -> EntryType {
         match &self.inner {
-            NodeInfoImpl::IsRootDir { .. } => BlobType::Dir,
-            NodeInfoImpl::IsNotRootDir { blob_type, .. } => *blob_type,
+            NodeInfoImpl::IsRootDir { .. } => EntryType::Dir,
+            NodeInfoImpl::IsNotRootDir { entry_type, .. } => *entry_type,
         }
     }
 
@@ -154,20 +158,7 @@ where
         &self,
         blobstore: &ConcurrentFsBlobStore<B>,
     ) -> FsResult<AsyncDropGuard<ConcurrentFsBlob<B>>> {
-        let blob_id = self.blob_id();
-        blobstore
-            .load(&blob_id)
-            .await
-            .map_err(|err| {
-                log::error!("Error loading blob {:?}: {:?}", blob_id, err);
-                FsError::UnknownError
-            })?
-            .ok_or_else(|| {
-                log::error!("Blob {:?} not found", blob_id);
-                FsError::CorruptedFilesystem {
-                    message: format!("Didn't find blob {:?}", blob_id),
-                }
-            })
+        load_blob(blobstore, self.blob_id()).await
     }
 
     pub async fn flush_if_cached(&self, blobstore: &ConcurrentFsBlobStore<B>) -> FsResult<()> {
@@ -178,6 +169,15 @@ where
         })
     }
 
+    /// Write barrier making sure that everything that was flushed from the caches so far, including changes
+    /// to this node, survives a crash. Flush the node first, e.g. with [Self::flush_if_cached].
+    pub async fn sync(&self, blobstore: &ConcurrentFsBlobStore<B>) -> FsResult<()> {
+        blobstore.sync().await.map_err(|err| {
+            log::error!("Error syncing blobstore: {:?}", err);
+            FsError::UnknownError
+        })
+    }
+
     pub fn as_file_mut<'s>(blob: &'s mut FsBlob<B>) -> FsResult<&'s mut FileBlob<B>> {
         let blob_id = blob.blob_id();
         blob.as_file_mut().map_err(|err| {
@@ -188,19 +188,31 @@ where
         })
     }
 
-    async fn load_lstat_size(&self, blobstore: &ConcurrentFsBlobStore<B>) -> FsResult<NumBytes> {
-        let mut blob = self.load_blob(blobstore).await?;
-        let lstat_size = blob.with_lock(async |blob| blob.lstat_size().await).await;
-        let result = match lstat_size {
-            // TODO Return NumBytes from blob.lstat_size() instead of converting it here
-            Ok(size) => Ok(NumBytes::from(size)),
-            Err(err) => {
-                log::error!("Error getting lstat size: {:?}", err);
-                Err(FsError::UnknownError)
-            }
-        };
-        blob.async_drop().await.map_err(FsError::internal_error)?;
-        result
+    async fn load_blob_attrs(&self, blobstore: &ConcurrentFsBlobStore<B>) -> FsResult<BlobAttrs> {
+        load_blob_attrs(blobstore, self.blob_id(), self.node_type()).await
+    }
+
+    /// Hard linked nodes store their metadata in a link metadata blob instead of their dir entry, see [super::link_metadata].
+    async fn load_link_metadata_blob_id(
+        &self,
+        blobstore: &ConcurrentFsBlobStore<B>,
+    ) -> FsResult<Option<BlobId>> {
+        if !blobstore.format().is_extended()
+            || !matches!(self.node_type(), EntryType::File | EntryType::Symlink)
+        {
+            // Only file and symlink blobs in the extended format can be hard linked, no need to load the blob
+            return Ok(None);
+        }
+        let blob = self.load_blob(blobstore).await?;
+        with_async_drop_2!(
+            blob,
+            {
+                Ok(blob
+                    .with_lock(async |blob| blob.link_metadata_blob_id())
+                    .await)
+            },
+            FsError::internal_error
+        )
     }
 
     pub async fn getattr(&self, blobstore: &ConcurrentFsBlobStore<B>) -> FsResult<NodeAttrs> {
@@ -227,25 +239,54 @@ where
                     num_bytes: cryfs_rustfs::NumBytes::from(DIR_LSTAT_SIZE),
                     // Setting num_blocks to none means it'll be automatically calculated for us
                     num_blocks: None,
+                    rdev: 0,
                     atime: now,
                     mtime: now,
                     ctime: now,
                 })
             }
+            NodeInfoImpl::IsNotRootDir { .. } => {
+                let blob_attrs = self.load_blob_attrs(blobstore).await?;
+                let entry = self
+                    .load_dir_entry()
+                    .await?
+                    .expect("Only the root dir doesn't have a dir entry");
+                let entry =
+                    load_metadata(blobstore, entry, blob_attrs.link_metadata_blob_id.as_ref())
+                        .await?;
+                Ok(dir_entry_to_node_attrs(
+                    &entry,
+                    blob_attrs.lstat_size,
+                    blob_attrs.num_links,
+                ))
+            }
+        }
+    }
+
+    /// Load the entry for this node from its parent directory. The root dir doesn't have a parent directory and returns `None`.
+    /// For hard linked nodes, the metadata in this entry is outdated, see [super::link_metadata].
+    pub async fn load_dir_entry(&self) -> FsResult<Option<DirEntry>> {
+        match &self.inner {
+            NodeInfoImpl::IsRootDir { .. } => Ok(None),
             NodeInfoImpl::IsNotRootDir {
-                parent_blob, name, ..
+                parent_blob,
+                name,
+                blob_id,
+                ..
             } => {
-                let lstat_size = self.load_lstat_size(blobstore).await?;
-                let entry: DirEntry = parent_blob
+                parent_blob
                     .with_lock(async |blob| {
-                        blob.as_dir()
-                            .expect("Parent dir is not a directory")
-                            .entry_by_name(name)
+                        let parent_dir = blob.as_dir_mut().expect("Parent dir is not a directory");
+                        let name = resolve_entry_name(parent_dir, name, blob_id).await?;
+                        parent_dir
+                            .entry_by_name(&name)
+                            .await
+                            .map_err(FsError::internal_error)?
                             .cloned()
+                            .map(Some)
                             .ok_or_else(|| FsError::NodeDoesNotExist)
                     })
-                    .await?;
-                Ok(dir_entry_to_node_attrs(&entry, lstat_size))
+                    .await
             }
         }
     }
@@ -259,10 +300,10 @@ where
         size: Option<NumBytes>,
         atime: Option<SystemTime>,
         mtime: Option<SystemTime>,
-        ctime: Option<SystemTime>,
+        // With the writeback cache, the kernel maintains ctime itself and sends it along with the other attributes.
+        // Changing any attribute below already sets ctime to the current time, so we don't need to store it separately.
+        _ctime: Option<SystemTime>,
     ) -> FsResult<NodeAttrs> {
-        // TODO Or is setting ctime allowed? What would it mean?
-        assert!(ctime.is_none(), "Cannot set ctime via setattr");
         // TODO Improve concurrency
         if let Some(size) = size {
             self.truncate_file(blobstore, size).await?;
@@ -275,37 +316,68 @@ where
                 Err(FsError::InvalidOperation)
             }
             NodeInfoImpl::IsNotRootDir {
-                parent_blob, name, ..
+                parent_blob,
+                name,
+                blob_id,
+                ..
             } => {
                 // TODO No Mode/Uid/Gid conversion
                 let mode = mode.map(|mode| Mode::from(u32::from(mode)));
                 let uid = uid.map(|uid| Uid::from(u32::from(uid)));
                 let gid = gid.map(|gid| Gid::from(u32::from(gid)));
-                let lstat_size = self.load_lstat_size(blobstore).await?;
+                let blob_attrs = self.load_blob_attrs(blobstore).await?;
+                let (lstat_size, num_links) = (blob_attrs.lstat_size, blob_attrs.num_links);
+                if let Some(link_metadata_blob_id) = &blob_attrs.link_metadata_blob_id {
+                    return link_metadata::with_link_metadata(
+                        blobstore,
+                        link_metadata_blob_id,
+                        |entry| {
+                            entry
+                                .set_attr(mode, uid, gid, atime, mtime)
+                                .map_err(|err| {
+                                    log::error!("Error setting attributes of entry: {:?}", err);
+                                    FsError::InvalidOperation
+                                })?;
+                            // Even if other fields are `None` (i.e. we don't run chmod, chown, utime), we still need to update the mtime in a truncate operation
+                            if size.is_some() {
+                                entry.update_modification_time();
+                            }
+                            Ok(dir_entry_to_node_attrs(entry, lstat_size, num_links))
+                        },
+                    )
+                    .await;
+                }
                 parent_blob
                     .with_lock(async |parent_blob| {
                         let parent_dir = parent_blob
                             .as_dir_mut()
                             .expect("Parent dir is not a directory");
+                        let name = resolve_entry_name(parent_dir, name, blob_id).await?;
                         let result = parent_dir
-                            .set_attr_of_entry_by_name(name, mode, uid, gid, atime, mtime)
-                            .map(|result| dir_entry_to_node_attrs(result, lstat_size))
+                            .set_attr_of_entry_by_name(&name, mode, uid, gid, atime, mtime)
+                            .await
+                            .map(|result| dir_entry_to_node_attrs(result, lstat_size, num_links))
                             .map_err(|err| {
                                 log::error!("Error setting attributes of entry: {:?}", err);
                                 match err {
                                     SetAttrError::NodeDoesNotExist => FsError::NodeDoesNotExist,
                                     SetAttrError::ValidationFailed(_) => FsError::InvalidOperation,
+                                    SetAttrError::LoadFailed(err) => FsError::internal_error(err),
                                 }
                             });
                         // Even if other fields are `None` (i.e. we don't run chmod, chown, utime), we still need to update the mtime in a truncate operation
                         if size.is_some() {
                             // TODO Don't look up the entry by name twice when we have attrs and size.is_some(). Looking it up once should be enough.
                             parent_dir
-                                .update_modification_timestamp_by_name(name)
+                                .update_modification_timestamp_by_name(&name)
+                                .await
                                 .map_err(|err| match err {
                                     UpdateTimestampError::NodeDoesNotExist => {
                                         FsError::NodeDoesNotExist
                                     }
+                                    UpdateTimestampError::LoadFailed(err) => {
+                                        FsError::internal_error(err)
+                                    }
                                 })?;
                         }
                         result
@@ -315,11 +387,148 @@ where
         }
     }
 
+    pub async fn getxattr(
+        &self,
+        blobstore: &ConcurrentFsBlobStore<B>,
+        name: &str,
+    ) -> FsResult<Vec<u8>> {
+        self._with_metadata(blobstore, |entry| {
+            entry
+                .xattrs()
+                .get(name)
+                .map(<[u8]>::to_vec)
+                .ok_or(FsError::XattrDoesNotExist)
+        })
+        .await
+    }
+
+    pub async fn listxattr(&self, blobstore: &ConcurrentFsBlobStore<B>) -> FsResult<Vec<String>> {
+        self._with_metadata(blobstore, |entry| {
+            Ok(entry.xattrs().names().map(str::to_owned).collect())
+        })
+        .await
+    }
+
+    pub async fn setxattr(
+        &self,
+        blobstore: &ConcurrentFsBlobStore<B>,
+        name: &str,
+        value: &[u8],
+        mode: SetXattrMode,
+    ) -> FsResult<()> {
+        self._with_metadata_mut(blobstore, |entry| {
+            let exists = entry.xattrs().contains(name);
+            match (mode, exists) {
+                (SetXattrMode::Create, true) => return Err(FsError::XattrAlreadyExists),
+                (SetXattrMode::Replace, false) => return Err(FsError::XattrDoesNotExist),
+                _ => {}
+            }
+            entry.set_xattr(name, value).map_err(|err| match err {
+                SetXattrError::InvalidName => FsError::InvalidOperation,
+                SetXattrError::NameTooLong => FsError::XattrNameTooLong,
+                SetXattrError::ValueTooLarge => FsError::XattrValueTooLarge,
+                SetXattrError::NotEnoughSpace => FsError::XattrNoSpace,
+            })
+        })
+        .await
+    }
+
+    pub async fn removexattr(
+        &self,
+        blobstore: &ConcurrentFsBlobStore<B>,
+        name: &str,
+    ) -> FsResult<()> {
+        self._with_metadata_mut(blobstore, |entry| {
+            entry
+                .remove_xattr(name)
+                .map(|_| ())
+                .ok_or(FsError::XattrDoesNotExist)
+        })
+        .await
+    }
+
+    /// Run `f` on the dir entry storing the metadata of this node. That's usually the entry in the parent directory,
+    /// but for hard linked nodes, it's the entry in their link metadata blob.
+    async fn _with_metadata<R>(
+        &self,
+        blobstore: &ConcurrentFsBlobStore<B>,
+        f: impl FnOnce(&DirEntry) -> FsResult<R>,
+    ) -> FsResult<R> {
+        if let Some(link_metadata_blob_id) = self.load_link_metadata_blob_id(blobstore).await? {
+            return link_metadata::with_link_metadata(blobstore, &link_metadata_blob_id, |entry| {
+                f(entry)
+            })
+            .await;
+        }
+        self._with_entry_in_parent(async |parent_dir, entry_name| {
+            let entry = parent_dir
+                .entry_by_name(entry_name)
+                .await
+                .map_err(FsError::internal_error)?
+                .ok_or(FsError::NodeDoesNotExist)?;
+            f(entry)
+        })
+        .await
+    }
+
+    /// Like [Self::_with_metadata], but allows `f` to modify the metadata.
+    async fn _with_metadata_mut<R>(
+        &self,
+        blobstore: &ConcurrentFsBlobStore<B>,
+        f: impl FnOnce(&mut DirEntry) -> FsResult<R>,
+    ) -> FsResult<R> {
+        if let Some(link_metadata_blob_id) = self.load_link_metadata_blob_id(blobstore).await? {
+            return link_metadata::with_link_metadata(blobstore, &link_metadata_blob_id, f).await;
+        }
+        self._with_entry_in_parent(async |parent_dir, entry_name| {
+            let entry = parent_dir
+                .entry_by_name_mut(entry_name)
+                .await
+                .map_err(FsError::internal_error)?
+                .ok_or(FsError::NodeDoesNotExist)?;
+            f(entry)
+        })
+        .await
+    }
+
+    /// Extended attributes are stored in the dir entry in the parent directory.
+    async fn _with_entry_in_parent<R>(
+        &self,
+        f: impl AsyncFnOnce(&mut DirBlob<B>, &PathComponent) -> FsResult<R>,
+    ) -> FsResult<R> {
+        match &self.inner {
+            NodeInfoImpl::IsRootDir { .. } => {
+                // We're the root dir
+                // TODO The root dir doesn't have a dir entry, so there's no place to store its extended attributes.
+                Err(FsError::XattrNotSupported)
+            }
+            NodeInfoImpl::IsNotRootDir {
+                parent_blob,
+                name,
+                blob_id,
+                ..
+            } => {
+                parent_blob
+                    .with_lock(async |parent_blob| {
+                        let parent_dir = parent_blob
+                            .as_dir_mut()
+                            .expect("Parent blob is not a directory");
+                        let name = resolve_entry_name(parent_dir, name, blob_id).await?;
+                        f(parent_dir, &name).await
+                    })
+                    .await
+            }
+        }
+    }
+
     pub async fn truncate_file(
         &self,
         blobstore: &ConcurrentFsBlobStore<B>,
         new_size: NumBytes,
     ) -> FsResult<()> {
+        if !self.node_type().has_blob() {
+            return Err(FsError::InvalidOperation);
+        }
         let blob = self.load_blob(blobstore).await?;
         with_async_drop_2!(
             blob,
@@ -339,10 +548,11 @@ where
 
     pub async fn concurrently_maybe_update_access_timestamp_in_parent<F>(
         &self,
+        blobstore: &ConcurrentFsBlobStore<B>,
         concurrent_fn: impl AsyncFnOnce() -> FsResult<F>,
     ) -> FsResult<F> {
         let (update_result, fn_result) = join!(
-            self.maybe_update_access_timestamp_in_parent(self.atime_update_behavior()),
+            self.maybe_update_access_timestamp_in_parent(blobstore, self.atime_update_behavior()),
             concurrent_fn(),
         );
         update_result?;
@@ -351,39 +561,70 @@ where
 
     pub async fn concurrently_update_modification_timestamp_in_parent<F>(
         &self,
+        blobstore: &ConcurrentFsBlobStore<B>,
         concurrent_fn: impl AsyncFnOnce() -> FsResult<F>,
     ) -> FsResult<F> {
         let (update_result, fn_result) = join!(
-            self.update_modification_timestamp_in_parent(),
+            self.update_modification_timestamp_in_parent(blobstore),
             concurrent_fn(),
         );
         update_result?;
         fn_result
     }
 
+    /// Hard linked nodes store their timestamps in their link metadata blob instead of the parent directory.
     pub async fn maybe_update_access_timestamp_in_parent(
         &self,
+        blobstore: &ConcurrentFsBlobStore<B>,
         atime_update_behavior: AtimeUpdateBehavior,
     ) -> FsResult<()> {
-        self._update_in_parent(|parent, blob_id| {
+        if let Some(link_metadata_blob_id) = self.load_link_metadata_blob_id(blobstore).await? {
+            return link_metadata::with_link_metadata(blobstore, &link_metadata_blob_id, |entry| {
+                let now = SystemTime::now();
+                if entry.should_update_access_time(
+                    AtimeUpdateBehaviorAdapter(atime_update_behavior),
+                    now,
+                ) {
+                    entry.set_last_access_time(now);
+                }
+                Ok(())
+            })
+            .await;
+        }
+        self._update_in_parent(async |parent, name| {
             parent
-                .maybe_update_access_timestamp_of_entry(
-                    blob_id,
+                .maybe_update_access_timestamp_by_name(
+                    name,
                     AtimeUpdateBehaviorAdapter(atime_update_behavior),
                 )
+                .await
                 .map_err(|err| match err {
                     UpdateTimestampError::NodeDoesNotExist => FsError::NodeDoesNotExist,
+                    UpdateTimestampError::LoadFailed(err) => FsError::internal_error(err),
                 })
         })
         .await
     }
 
-    pub async fn update_modification_timestamp_in_parent(&self) -> FsResult<()> {
-        self._update_in_parent(|parent, blob_id| {
+    /// Hard linked nodes store their timestamps in their link metadata blob instead of the parent directory.
+    pub async fn update_modification_timestamp_in_parent(
+        &self,
+        blobstore: &ConcurrentFsBlobStore<B>,
+    ) -> FsResult<()> {
+        if let Some(link_metadata_blob_id) = self.load_link_metadata_blob_id(blobstore).await? {
+            return link_metadata::with_link_metadata(blobstore, &link_metadata_blob_id, |entry| {
+                entry.update_modification_time();
+                Ok(())
+            })
+            .await;
+        }
+        self._update_in_parent(async |parent, name| {
             parent
-                .update_modification_timestamp_of_entry(blob_id)
+                .update_modification_timestamp_by_name(name)
+                .await
                 .map_err(|err| match err {
                     UpdateTimestampError::NodeDoesNotExist => FsError::NodeDoesNotExist,
+                    UpdateTimestampError::LoadFailed(err) => FsError::internal_error(err),
                 })
         })
         .await
@@ -391,7 +632,8 @@ where
 
     async fn _update_in_parent(
         &self,
-        update_fn: impl FnOnce(&mut DirBlob<B>, &BlobId) -> FsResult<()>,
+        // Entries are looked up by name because with hard links, there can be multiple entries with the same blob id
+        update_fn: impl AsyncFnOnce(&mut DirBlob<B>, &PathComponent) -> FsResult<()>,
     ) -> FsResult<()> {
         match &self.inner {
             NodeInfoImpl::IsRootDir { .. } => {
@@ -401,6 +643,7 @@ where
             }
             NodeInfoImpl::IsNotRootDir {
                 parent_blob,
+                name,
                 blob_id,
                 ..
             } => {
@@ -409,7 +652,8 @@ where
                         let parent_dir = parent_blob
                             .as_dir_mut()
                             .expect("Parent blob is not a directory");
-                        update_fn(parent_dir, blob_id)
+                        let name = resolve_entry_name(parent_dir, name, blob_id).await?;
+                        update_fn(parent_dir, &name).await
                     })
                     .await
             }
@@ -473,10 +717,128 @@ where
     }
 }
 
-fn dir_entry_to_node_attrs(entry: &DirEntry, num_bytes: NumBytes) -> NodeAttrs {
+/// `name` is the name the node had when it was looked up. It goes stale if the entry gets renamed while the node is loaded,
+/// so if it doesn't point to `blob_id` anymore, we look the entry up by its blob id instead.
+/// We can't only look up by blob id because with hard links, there can be multiple entries with the same blob id.
+async fn resolve_entry_name<'n, B>(
+    parent_dir: &mut DirBlob<B>,
+    name: &'n PathComponent,
+    blob_id: &BlobId,
+) -> FsResult<Cow<'n, PathComponent>>
+where
+    B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
+    <B as BlobStore>::ConcreteBlob: Send + Sync + AsyncDrop<Error = anyhow::Error>,
+{
+    let entry = parent_dir
+        .entry_by_name(name)
+        .await
+        .map_err(FsError::internal_error)?;
+    if entry.is_some_and(|entry| entry.blob_id() == blob_id) {
+        return Ok(Cow::Borrowed(name));
+    }
+    parent_dir
+        .entry_by_id(blob_id)
+        .await
+        .map_err(FsError::internal_error)?
+        .map(|entry| Cow::Owned(entry.name().to_owned()))
+        .ok_or(FsError::NodeDoesNotExist)
+}
+
+async fn load_blob<B>(
+    blobstore: &ConcurrentFsBlobStore<B>,
+    blob_id: &BlobId,
+) -> FsResult<AsyncDropGuard<ConcurrentFsBlob<B>>>
+where
+    B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
+    <B as BlobStore>::ConcreteBlob: Send + Sync + AsyncDrop<Error = anyhow::Error>,
+{
+    blobstore
+        .load(blob_id)
+        .await
+        .map_err(|err| {
+            log::error!("Error loading blob {:?}: {:?}", blob_id, err);
+            FsError::UnknownError
+        })?
+        .ok_or_else(|| {
+            log::error!("Blob {:?} not found", blob_id);
+            FsError::CorruptedFilesystem {
+                message: format!("Didn't find blob {:?}", blob_id),
+            }
+        })
+}
+
+/// The attributes of a node that are stored in its blob and not in its dir entry
+pub(super) struct BlobAttrs {
+    pub lstat_size: NumBytes,
+    pub num_links: u32,
+    /// Only set for hard linked nodes, see [super::link_metadata]
+    pub link_metadata_blob_id: Option<BlobId>,
+}
+
+/// Load the size reported by `lstat`, the number of hard links and the link metadata blob of a node with the given blob and type
+pub(super) async fn load_blob_attrs<B>(
+    blobstore: &ConcurrentFsBlobStore<B>,
+    blob_id: &BlobId,
+    node_type: EntryType,
+) -> FsResult<BlobAttrs>
+where
+    B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
+    <B as BlobStore>::ConcreteBlob: Send + Sync + AsyncDrop<Error = anyhow::Error>,
+{
+    if !node_type.has_blob() {
+        // Fifos, sockets and devices don't have a blob and can't be hard linked
+        return Ok(BlobAttrs {
+            lstat_size: NumBytes::from(0),
+            num_links: 1,
+            link_metadata_blob_id: None,
+        });
+    }
+    let mut blob = load_blob(blobstore, blob_id).await?;
+    let blob_attrs = blob
+        .with_lock(async |blob| {
+            Ok::<_, anyhow::Error>(BlobAttrs {
+                // TODO Return NumBytes from blob.lstat_size() instead of converting it here
+                lstat_size: NumBytes::from(blob.lstat_size().await?),
+                num_links: blob.num_links(),
+                link_metadata_blob_id: blob.link_metadata_blob_id(),
+            })
+        })
+        .await;
+    let result = blob_attrs.map_err(|err| {
+        log::error!("Error getting lstat size: {:?}", err);
+        FsError::UnknownError
+    });
+    blob.async_drop().await.map_err(FsError::internal_error)?;
+    result
+}
+
+/// Hard linked nodes store their metadata in their link metadata blob and the metadata in their dir entries is outdated.
+/// This returns the up to date metadata for a node with the dir entry `entry`.
+pub(super) async fn load_metadata<B>(
+    blobstore: &ConcurrentFsBlobStore<B>,
+    entry: DirEntry,
+    link_metadata_blob_id: Option<&BlobId>,
+) -> FsResult<DirEntry>
+where
+    B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
+    <B as BlobStore>::ConcreteBlob: Send + Sync + AsyncDrop<Error = anyhow::Error>,
+{
+    match link_metadata_blob_id {
+        Some(link_metadata_blob_id) => {
+            link_metadata::load_link_metadata(blobstore, link_metadata_blob_id).await
+        }
+        None => Ok(entry),
+    }
+}
+
+pub(super) fn dir_entry_to_node_attrs(
+    entry: &DirEntry,
+    num_bytes: NumBytes,
+    num_links: u32,
+) -> NodeAttrs {
     NodeAttrs {
         //TODO If possible without performance loss, then for a directory, nlink should return number of dir entries (including "." and "..")
-        nlink: 1,
+        nlink: num_links,
         // TODO Remove those conversions
         mode: cryfs_rustfs::Mode::from(u32::from(entry.mode())),
         uid: cryfs_rustfs::Uid::from(u32::from(entry.uid())),
@@ -484,6 +846,7 @@ fn dir_entry_to_node_attrs(entry: &DirEntry, num_bytes: NumBytes) -> NodeAttrs {
         num_bytes,
         // Setting num_blocks to none means it'll be automatically calculated for us
         num_blocks: None,
+        rdev: entry.rdev(),
         atime: entry.last_access_time(),
         mtime: entry.last_modification_time(),
         ctime: entry.last_metadata_change_time(),
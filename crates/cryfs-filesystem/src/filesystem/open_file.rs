@@ -5,7 +5,8 @@ use std::time::SystemTime;
 
 use cryfs_blobstore::BlobStore;
 use cryfs_rustfs::{
-    FsError, FsResult, Gid, Mode, NodeAttrs, NumBytes, Uid, object_based_api::OpenFile,
+    FallocateMode, FsError, FsResult, Gid, Mode, NodeAttrs, NumBytes, SeekWhence, Uid,
+    object_based_api::OpenFile,
 };
 use cryfs_utils::{
     async_drop::{AsyncDrop, AsyncDropArc, AsyncDropGuard},
@@ -19,6 +20,9 @@ use cryfs_fsblobstore::{
     fsblobstore::{FileBlob, FsBlob},
 };
 
+/// Maximum number of bytes we hold in memory while copying data between two ranges of the same file
+const MAX_BYTES_PER_COPY_CHUNK: u64 = 1024 * 1024;
+
 // TODO Make sure we don't keep a lock on the file blob, or keep the lock in an Arc that is shared between all File, Node and OpenFile instances of the same file
 
 pub struct CryOpenFile<B>
@@ -87,6 +91,18 @@ where
         )
     }
 
+    async fn flush_caches(&self, datasync: bool) -> FsResult<()> {
+        if datasync {
+            self.flush_file_contents().await?;
+        } else {
+            let (r1, r2) = join!(self.flush_file_contents(), self.node_info.flush_metadata());
+            // TODO Report both errors if both happen
+            r1?;
+            r2?;
+        }
+        Ok(())
+    }
+
     async fn _read(&self, offset: NumBytes, size: NumBytes) -> FsResult<Data> {
         let blob = self.load_blob().await?;
         with_async_drop_2!(
@@ -138,6 +154,209 @@ where
             FsError::internal_error
         )
     }
+
+    async fn _fallocate(
+        &self,
+        offset: NumBytes,
+        length: NumBytes,
+        mode: FallocateMode,
+    ) -> FsResult<()> {
+        let blob = self.load_blob().await?;
+        with_async_drop_2!(
+            blob,
+            {
+                blob.with_lock(async |mut blob| {
+                    let file = Self::as_file_mut(&mut blob).map_err(|err| {
+                        log::error!("Failed to cast blob to FileBlob: {err:?}");
+                        FsError::UnknownError
+                    })?;
+                    let keep_size = match mode {
+                        FallocateMode::Allocate { keep_size } => keep_size,
+                        FallocateMode::PunchHole => true,
+                        FallocateMode::ZeroRange { keep_size } => keep_size,
+                    };
+                    let end = u64::from(offset) + u64::from(length);
+                    if !keep_size {
+                        let num_bytes = file.num_bytes().await.map_err(|err| {
+                            log::error!("Failed to get size of blob: {err:?}");
+                            FsError::UnknownError
+                        })?;
+                        if end > num_bytes {
+                            // With the extended format, blobs grow sparsely, so this doesn't actually allocate the new range. There's no
+                            // point in preallocating anyways since we can't guarantee that there's space for it in the underlying storage.
+                            file.resize(end).await.map_err(|err| {
+                                log::error!("Failed to resize blob: {err:?}");
+                                FsError::UnknownError
+                            })?;
+                        }
+                    }
+                    match mode {
+                        FallocateMode::Allocate { .. } => {}
+                        FallocateMode::PunchHole | FallocateMode::ZeroRange { .. } => {
+                            // Holes read as zeroes, so zeroing a range is the same as punching a hole into it.
+                            // File systems without holes write the zeroes instead.
+                            file.punch_hole(offset.into(), length.into())
+                                .await
+                                .map_err(|err| {
+                                    log::error!("Failed to punch hole into blob: {err:?}");
+                                    FsError::UnknownError
+                                })?;
+                        }
+                    }
+                    Ok(())
+                })
+                .await
+            },
+            FsError::internal_error
+        )
+    }
+
+    async fn _lseek(&self, offset: NumBytes, whence: SeekWhence) -> FsResult<NumBytes> {
+        let blob = self.load_blob().await?;
+        with_async_drop_2!(
+            blob,
+            {
+                blob.with_lock(async |mut blob| {
+                    let file = Self::as_file_mut(&mut blob).map_err(|err| {
+                        log::error!("Failed to cast blob to FileBlob: {err:?}");
+                        FsError::UnknownError
+                    })?;
+                    let result = match whence {
+                        SeekWhence::Data => file.seek_data(offset.into()).await,
+                        SeekWhence::Hole => file.seek_hole(offset.into()).await,
+                    };
+                    let result = result.map_err(|err| {
+                        log::error!("Failed to seek in blob: {err:?}");
+                        FsError::UnknownError
+                    })?;
+                    result
+                        .map(NumBytes::from)
+                        .ok_or(FsError::OffsetBeyondEndOfFile)
+                })
+                .await
+            },
+            FsError::internal_error
+        )
+    }
+
+    async fn _copy_file_range(
+        &self,
+        offset_in: NumBytes,
+        dest: &Self,
+        offset_out: NumBytes,
+        len: NumBytes,
+    ) -> FsResult<NumBytes> {
+        let source_blob = self.load_blob().await?;
+        with_async_drop_2!(
+            source_blob,
+            {
+                let dest_blob = dest.load_blob().await?;
+                with_async_drop_2!(
+                    dest_blob,
+                    {
+                        let source_id = source_blob.blob_id();
+                        let dest_id = dest_blob.blob_id();
+                        if source_id == dest_id {
+                            source_blob
+                                .with_lock(async |blob| {
+                                    let file = Self::as_file_mut(blob)?;
+                                    Self::_copy_range_within(file, offset_in, offset_out, len).await
+                                })
+                                .await
+                        } else if source_id < dest_id {
+                            // Always lock the blob with the smaller id first so that concurrent copies between the same two files can't deadlock
+                            source_blob
+                                .with_lock(async |source| {
+                                    dest_blob
+                                        .with_lock(async |dest| {
+                                            Self::_clone_range(
+                                                source, offset_in, dest, offset_out, len,
+                                            )
+                                            .await
+                                        })
+                                        .await
+                                })
+                                .await
+                        } else {
+                            dest_blob
+                                .with_lock(async |dest| {
+                                    source_blob
+                                        .with_lock(async |source| {
+                                            Self::_clone_range(
+                                                source, offset_in, dest, offset_out, len,
+                                            )
+                                            .await
+                                        })
+                                        .await
+                                })
+                                .await
+                        }
+                    },
+                    FsError::internal_error
+                )
+            },
+            FsError::internal_error
+        )
+    }
+
+    async fn _clone_range(
+        source: &mut FsBlob<B>,
+        offset_in: NumBytes,
+        dest: &mut FsBlob<B>,
+        offset_out: NumBytes,
+        len: NumBytes,
+    ) -> FsResult<NumBytes> {
+        let source = Self::as_file_mut(source)?;
+        let dest = Self::as_file_mut(dest)?;
+        let num_bytes = dest
+            .clone_range_from(source, offset_in.into(), offset_out.into(), len.into())
+            .await
+            .map_err(|err| {
+                log::error!("Failed to clone range between blobs: {err:?}");
+                FsError::UnknownError
+            })?;
+        Ok(NumBytes::from(num_bytes))
+    }
+
+    async fn _copy_range_within(
+        file: &mut FileBlob<B>,
+        offset_in: NumBytes,
+        offset_out: NumBytes,
+        len: NumBytes,
+    ) -> FsResult<NumBytes> {
+        let offset_in = u64::from(offset_in);
+        let offset_out = u64::from(offset_out);
+        let len = u64::from(len);
+        // Like Linux, we don't allow overlapping copies within the same file
+        if offset_in < offset_out.saturating_add(len) && offset_out < offset_in.saturating_add(len)
+        {
+            return Err(FsError::InvalidOperation);
+        }
+        // Data can't be shared within the same blob, so we fall back to copying it
+        let mut buffer: Data = vec![0; len.min(MAX_BYTES_PER_COPY_CHUNK) as usize].into();
+        let mut num_copied = 0;
+        while num_copied < len {
+            let chunk_size = (len - num_copied).min(MAX_BYTES_PER_COPY_CHUNK) as usize;
+            let num_read = file
+                .try_read(&mut buffer[..chunk_size], offset_in + num_copied)
+                .await
+                .map_err(|err| {
+                    log::error!("Failed to read from blob: {err:?}");
+                    FsError::UnknownError
+                })?;
+            if num_read == 0 {
+                break;
+            }
+            file.write(&buffer[..num_read], offset_out + num_copied)
+                .await
+                .map_err(|err| {
+                    log::error!("Failed to write to blob: {err:?}");
+                    FsError::UnknownError
+                })?;
+            num_copied += num_read as u64;
+        }
+        Ok(NumBytes::from(num_copied))
+    }
 }
 
 impl<B> Debug for CryOpenFile<B>
@@ -182,7 +401,7 @@ where
         let should_update_atime = size > NumBytes::from(0);
         if should_update_atime {
             self.node_info
-                .concurrently_maybe_update_access_timestamp_in_parent(async || {
+                .concurrently_maybe_update_access_timestamp_in_parent(&self.blobstore, async || {
                     self._read(offset, size).await
                 })
                 .await
@@ -196,7 +415,7 @@ where
         let should_update_mtime = data.len() > 0;
         if should_update_mtime {
             self.node_info
-                .concurrently_update_modification_timestamp_in_parent(async || {
+                .concurrently_update_modification_timestamp_in_parent(&self.blobstore, async || {
                     self._write(offset, data).await
                 })
                 .await
@@ -205,6 +424,52 @@ where
         }
     }
 
+    async fn fallocate(
+        &self,
+        offset: NumBytes,
+        length: NumBytes,
+        mode: FallocateMode,
+    ) -> FsResult<()> {
+        // Preallocating without changing the size doesn't change the file contents
+        let should_update_mtime = length > NumBytes::from(0)
+            && !matches!(mode, FallocateMode::Allocate { keep_size: true });
+        if should_update_mtime {
+            self.node_info
+                .concurrently_update_modification_timestamp_in_parent(&self.blobstore, async || {
+                    self._fallocate(offset, length, mode).await
+                })
+                .await
+        } else {
+            self._fallocate(offset, length, mode).await
+        }
+    }
+
+    async fn lseek(&self, offset: NumBytes, whence: SeekWhence) -> FsResult<NumBytes> {
+        self._lseek(offset, whence).await
+    }
+
+    async fn copy_file_range(
+        &self,
+        offset_in: NumBytes,
+        dest: &Self,
+        offset_out: NumBytes,
+        len: NumBytes,
+    ) -> FsResult<NumBytes> {
+        // Like write, this should update mtime if (and only if) len > 0
+        let should_update_mtime = len > NumBytes::from(0);
+        if should_update_mtime {
+            dest.node_info
+                .concurrently_update_modification_timestamp_in_parent(&self.blobstore, async || {
+                    self._copy_file_range(offset_in, dest, offset_out, len)
+                        .await
+                })
+                .await
+        } else {
+            self._copy_file_range(offset_in, dest, offset_out, len)
+                .await
+        }
+    }
+
     async fn flush(&self) -> FsResult<()> {
         // Flush is different from fsync, it's not meant to flush contents or metadata to disk,
         // but it's meant to give the file system a chance to return an error when a descriptor
@@ -212,21 +477,14 @@ where
         // We're just ignoring the call to flush() here.
         // TODO Is there actually something we should do?
 
-        // TODO For now we're calling fsync here because C++ was doing that, so we have a fairer performance comparison. But we should remove this
-        self.fsync(false).await?;
-        Ok(())
+        // TODO For now we're flushing caches here because C++ was doing that, so we have a fairer performance comparison. But we should remove this
+        // Note that we don't issue a write barrier to the block store here, that only happens on an actual fsync.
+        self.flush_caches(false).await
     }
 
     async fn fsync(&self, datasync: bool) -> FsResult<()> {
-        if datasync {
-            self.flush_file_contents().await?;
-        } else {
-            let (r1, r2) = join!(self.flush_file_contents(), self.node_info.flush_metadata());
-            // TODO Report both errors if both happen
-            r1?;
-            r2?;
-        }
-        Ok(())
+        self.flush_caches(datasync).await?;
+        self.node_info.sync(&self.blobstore).await
     }
 }
 
@@ -7,7 +7,8 @@ mod mounter;
 mod runner;
 mod unmount_trigger;
 
-pub use cryfs_rustfs::AtimeUpdateBehavior;
+pub use cryfs_blockstore::FsyncPolicy;
+pub use cryfs_rustfs::{AtimeUpdateBehavior, KernelCacheOptions};
 pub use mounter::Mounter;
 pub use runner::{CreateOrLoad, FuseOption, MountArgs, make_device};
 
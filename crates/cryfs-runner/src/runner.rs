@@ -1,17 +1,17 @@
 use anyhow::{Context, Result};
 use cryfs_blobstore::{BlobId, BlobStore, BlobStoreOnBlocks};
 use cryfs_blockstore::{
-    AllowIntegrityViolations, ClientId, IntegrityConfig, InvalidBlockSizeError, LLBlockStore,
-    LockingBlockStore, MissingBlockIsIntegrityViolation, OnDiskBlockStore,
+    AllowIntegrityViolations, ClientId, FsyncPolicy, IntegrityConfig, InvalidBlockSizeError,
+    LLBlockStore, LockingBlockStore, MissingBlockIsIntegrityViolation,
 };
 use cryfs_cli_utils::{
-    BlockstoreCallback, CliError, CliErrorKind, CliResultExt, CliResultExtFn,
-    setup_blockstore_stack,
+    BackendConfig, BlockstoreCallback, CliError, CliErrorKind, CliResultExt, CliResultExtFn,
+    FilesystemLock, setup_blockstore_stack_for_backend,
 };
 use cryfs_config::{config::CryConfig, localstate::LocalStateDir};
-use cryfs_filesystem::filesystem::CryDevice;
-use cryfs_rustfs::AtimeUpdateBehavior;
+use cryfs_filesystem::filesystem::{CryDevice, FsFormat, stable_id};
 use cryfs_rustfs::object_based_api::{MountOption, RustfsBackend};
+use cryfs_rustfs::{AtimeUpdateBehavior, KernelCacheOptions};
 use cryfs_utils::async_drop::{AsyncDrop, AsyncDropGuard};
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
@@ -33,6 +33,7 @@ pub enum CreateOrLoad {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MountArgs {
     pub vaultdir: PathBuf,
+    pub backend: BackendConfig,
     pub mountdir: PathBuf,
     pub config: CryConfig,
     pub allow_integrity_violations: AllowIntegrityViolations,
@@ -42,6 +43,8 @@ pub struct MountArgs {
     pub unmount_idle: Option<Duration>,
     pub fuse_options: Box<[FuseOption]>,
     pub atime_behavior: AtimeUpdateBehavior,
+    pub fsync_policy: FsyncPolicy,
+    pub kernel_cache: KernelCacheOptions,
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
@@ -56,6 +59,11 @@ pub async fn mount_filesystem(
     mount_args: MountArgs,
     on_successfully_mounted: impl FnOnce() + Send + Sync,
 ) -> Result<(), CliError> {
+    // Held until the file system is unmounted so that cryfs-check can't repair it while it's mounted
+    let _lock = FilesystemLock::try_acquire(
+        &mount_args.local_state_dir,
+        &mount_args.config.filesystem_id,
+    )?;
     let missing_block_is_integrity_violation =
         if mount_args.config.missing_block_is_integrity_violation() {
             MissingBlockIsIntegrityViolation::IsAViolation
@@ -65,8 +73,14 @@ pub async fn mount_filesystem(
     let unmount_trigger = UnmountTrigger::new();
     let unmount_trigger_clone = unmount_trigger.clone();
     let trigger_reason = Arc::clone(unmount_trigger.trigger_reason());
-    setup_blockstore_stack(
-        OnDiskBlockStore::new(mount_args.vaultdir.to_owned()),
+    let invalidator = mount_args.kernel_cache.invalidator.clone();
+    let blockstore = mount_args
+        .backend
+        .open(&mount_args.vaultdir, mount_args.fsync_policy)
+        .await
+        .map_cli_error(CliErrorKind::InaccessibleVaultDir)?;
+    setup_blockstore_stack_for_backend(
+        blockstore,
         &mount_args.config,
         mount_args.my_client_id,
         &mount_args.local_state_dir,
@@ -76,6 +90,14 @@ pub async fn mount_filesystem(
             on_integrity_violation: Box::new(move |err| {
                 unmount_trigger_clone.trigger_now(TriggerReason::IntegrityViolation(err.clone()));
             }),
+            on_block_changed_by_other_client: Box::new(move |block_id| {
+                // The root block of a blob has the same id as the blob. If another client changed a blob's root block,
+                // e.g. a small file or directory, the kernel may have cached stale data or attributes for it.
+                // Other blocks don't map to a node, invalidating them is a no-op. Changes to those are picked up once the kernel's cache expires.
+                invalidator.invalidate_inode_with_stable_id(stable_id(
+                    &BlobId::from_root_block_id(*block_id),
+                ));
+            }),
         },
         FilesystemRunner {
             vaultdir: &mount_args.vaultdir,
@@ -87,6 +109,7 @@ pub async fn mount_filesystem(
             unmount_idle: mount_args.unmount_idle,
             fuse_options: mount_args.fuse_options,
             atime_behavior: mount_args.atime_behavior,
+            kernel_cache: mount_args.kernel_cache,
         },
     )
     .await??;
@@ -115,6 +138,7 @@ struct FilesystemRunner<'v, 'm, 'c, OnSuccessfullyMounted: FnOnce()> {
     pub unmount_idle: Option<Duration>,
     pub fuse_options: Box<[FuseOption]>,
     pub atime_behavior: AtimeUpdateBehavior,
+    pub kernel_cache: KernelCacheOptions,
 }
 
 impl<'v, 'm, 'c, OnSuccessfullyMounted: FnOnce()> BlockstoreCallback
@@ -158,7 +182,6 @@ impl<'v, 'm, 'c, OnSuccessfullyMounted: FnOnce()> BlockstoreCallback
             // let the kernel handle permission checking based on permission flags instead of calling the `access()` function of the fuse filesystem
             MountOption::DefaultPermissions,
             fuse_atime_option,
-            // TODO What other MountOptions should we set (or let the user set on the command line)?
         ]
         .into_iter()
         .chain(self.fuse_options.iter().map(|o| match o {
@@ -172,6 +195,7 @@ impl<'v, 'm, 'c, OnSuccessfullyMounted: FnOnce()> BlockstoreCallback
             tokio::runtime::Handle::current(),
             Some(self.unmount_trigger.waiter()),
             &mount_options,
+            self.kernel_cache,
             self.on_successfully_mounted,
         )
         .await
@@ -203,14 +227,21 @@ where
         }
     };
 
+    let format = if config.has_extended_format() {
+        FsFormat::Extended
+    } else {
+        FsFormat::Cryfs1Compatible
+    };
     let mut device = match create_or_load {
         CreateOrLoad::CreateNewFilesystem => {
-            CryDevice::create_new_filesystem(blobstore, root_blob_id, atime_behavior)
+            CryDevice::create_new_filesystem(blobstore, root_blob_id, atime_behavior, format)
                 .await
                 .map_cli_error(CliErrorKind::UnspecifiedError)?
         }
         CreateOrLoad::LoadExistingFilesystem => {
-            CryDevice::load_filesystem(blobstore, root_blob_id, atime_behavior)
+            CryDevice::load_filesystem(blobstore, root_blob_id, atime_behavior, format)
+                .await
+                .map_cli_error(CliErrorKind::InvalidFilesystem)?
         }
     };
     match device
@@ -0,0 +1,207 @@
+//! Helpers to create a vault and mount it in-process through [cryfs_runner::Mounter], like `cryfs --foreground` does.
+
+use anyhow::Result;
+use byte_unit::Byte;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+use tempfile::TempDir;
+
+use cryfs_cli_utils::BackendConfig;
+use cryfs_config::{
+    config::{CommandLineFlags, Console, CryConfig, FixedPasswordProvider},
+    localstate::LocalStateDir,
+};
+use cryfs_crypto::kdf::{argon2id::Argon2idSettings, scrypt::ScryptSettings};
+use cryfs_runner::{
+    AtimeUpdateBehavior, CreateOrLoad, FsyncPolicy, KernelCacheOptions, MountArgs, Mounter,
+};
+use cryfs_utils::progress::SilentProgressBarManager;
+use cryfs_version::{Version, VersionInfo};
+
+/// The file system unmounts itself after being idle for this long, which is how the tests unmount it
+const UNMOUNT_IDLE: Duration = Duration::from_secs(2);
+
+pub struct TestVault {
+    tempdir: TempDir,
+    config: CryConfig,
+    my_client_id: cryfs_blockstore::ClientId,
+}
+
+impl TestVault {
+    pub fn create() -> Self {
+        let tempdir = tempfile::Builder::new()
+            .prefix("cryfs-runner-mount")
+            .tempdir()
+            .unwrap();
+        for dir in ["vault", "mount", "local_state_dir"] {
+            std::fs::create_dir(tempdir.path().join(dir)).unwrap();
+        }
+        let config = cryfs_config::config::create(
+            tempdir.path().join("vault").join("cryfs.config"),
+            &FixedPasswordProvider::new("password".to_owned()),
+            &TestConsole,
+            &CommandLineFlags {
+                missing_block_is_integrity_violation: Some(false),
+                expected_cipher: Some("xchacha20-poly1305".to_owned()),
+                blocksize: Some(Byte::from_u64(4096)),
+                kdf: None,
+                compression: None,
+                extended_format: true,
+            },
+            &LocalStateDir::new(tempdir.path().join("local_state_dir")),
+            true,
+            SilentProgressBarManager,
+        )
+        .unwrap();
+        Self {
+            config: config.config.config().clone(),
+            my_client_id: config.my_client_id,
+            tempdir,
+        }
+    }
+
+    pub fn vaultdir(&self) -> PathBuf {
+        self.tempdir.path().join("vault")
+    }
+
+    pub fn mountdir(&self) -> PathBuf {
+        self.tempdir.path().join("mount")
+    }
+
+    /// Creates another directory next to the vault directory, e.g. for a replica
+    pub fn create_dir(&self, name: &str) -> PathBuf {
+        let dir = self.tempdir.path().join(name);
+        std::fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    /// Mounts the file system, runs `f` on the mount directory and waits until the file system is unmounted again
+    pub fn mount(
+        &self,
+        backend: &BackendConfig,
+        create_or_load: CreateOrLoad,
+        f: impl FnOnce(&Path),
+    ) -> Result<()> {
+        let mount_args = MountArgs {
+            vaultdir: self.vaultdir(),
+            backend: backend.clone(),
+            mountdir: self.mountdir(),
+            config: self.config.clone(),
+            allow_integrity_violations:
+                cryfs_blockstore::AllowIntegrityViolations::DontAllowViolations,
+            create_or_load,
+            my_client_id: self.my_client_id,
+            local_state_dir: LocalStateDir::new(self.tempdir.path().join("local_state_dir")),
+            unmount_idle: Some(UNMOUNT_IDLE),
+            fuse_options: Box::new([]),
+            atime_behavior: AtimeUpdateBehavior::Noatime,
+            fsync_policy: FsyncPolicy::Never,
+            kernel_cache: KernelCacheOptions::default(),
+        };
+        let (mounted_sender, mounted_receiver) = mpsc::channel();
+        let filesystem = std::thread::spawn(move || {
+            cryfs_runner::init_tokio().block_on(async move {
+                Mounter::run_in_foreground()
+                    .unwrap()
+                    .mount_filesystem(mount_args, move || {
+                        mounted_sender.send(()).unwrap();
+                    })
+                    .await
+            })
+        });
+        if mounted_receiver
+            .recv_timeout(Duration::from_secs(30))
+            .is_ok()
+        {
+            f(&self.mountdir());
+        }
+        filesystem
+            .join()
+            .unwrap()
+            .map_err(|err| anyhow::anyhow!("{:?}", err.error))
+    }
+}
+
+/// Returns the contents of all files in the subdirectories of `dir`, keyed by their relative path.
+/// [cryfs_blockstore::OnDiskBlockStore] stores blocks in subdirectories, so this skips the config files at the top level.
+pub fn block_files(dir: &Path) -> BTreeMap<PathBuf, Vec<u8>> {
+    let mut files = BTreeMap::new();
+    for subdir in std::fs::read_dir(dir).unwrap() {
+        let subdir = subdir.unwrap().path();
+        if !subdir.is_dir() {
+            continue;
+        }
+        for file in std::fs::read_dir(&subdir).unwrap() {
+            let file = file.unwrap().path();
+            files.insert(
+                file.strip_prefix(dir).unwrap().to_owned(),
+                std::fs::read(&file).unwrap(),
+            );
+        }
+    }
+    files
+}
+
+/// Mounting needs FUSE. Tests skip themselves where it isn't available, e.g. in containers without /dev/fuse.
+pub fn fuse_available() -> bool {
+    let available = Path::new("/dev/fuse").exists();
+    if !available {
+        eprintln!("Skipping test because /dev/fuse doesn't exist");
+    }
+    available
+}
+
+struct TestConsole;
+
+impl Console for TestConsole {
+    fn ask_migrate_filesystem(
+        &self,
+        _current_filesystem_format_version: &Version<&str>,
+        _new_filesystem_format_version: &Version<&str>,
+        _cryfs_version: &VersionInfo<&str>,
+    ) -> Result<bool> {
+        panic!("unused")
+    }
+
+    fn ask_allow_replaced_filesystem(&self) -> Result<bool> {
+        panic!("unused")
+    }
+
+    fn ask_allow_changed_encryption_key(&self) -> Result<bool> {
+        panic!("unused")
+    }
+
+    fn ask_disable_single_client_mode(&self) -> Result<bool> {
+        panic!("unused")
+    }
+
+    fn ask_single_client_mode_for_new_filesystem(&self) -> Result<bool> {
+        panic!("unused")
+    }
+
+    fn ask_scrypt_settings_for_new_filesystem(&self) -> Result<ScryptSettings> {
+        Ok(ScryptSettings::TEST)
+    }
+
+    fn ask_argon2id_settings_for_new_filesystem(&self) -> Result<Argon2idSettings> {
+        Ok(Argon2idSettings::TEST)
+    }
+
+    fn ask_cipher_for_new_filesystem(&self) -> Result<String> {
+        panic!("unused")
+    }
+
+    fn ask_blocksize_bytes_for_new_filesystem(&self) -> Result<Byte> {
+        panic!("unused")
+    }
+
+    fn ask_create_vaultdir(&self, _path: &Path) -> Result<bool> {
+        panic!("unused")
+    }
+
+    fn ask_create_mountdir(&self, _path: &Path) -> Result<bool> {
+        panic!("unused")
+    }
+}
@@ -0,0 +1,106 @@
+//! Mounts file systems with each storage backend and checks that files survive a remount
+//! and that the blocks end up where the backend says.
+
+use cryfs_cli_utils::BackendConfig;
+use cryfs_runner::CreateOrLoad;
+
+mod common;
+use common::{TestVault, block_files, fuse_available};
+
+const FILE_CONTENT: &[u8] = b"some file content that is stored in the vault";
+
+fn write_file(mountdir: &std::path::Path) {
+    std::fs::create_dir(mountdir.join("dir")).unwrap();
+    std::fs::write(mountdir.join("dir").join("file"), FILE_CONTENT).unwrap();
+}
+
+fn assert_file_exists(mountdir: &std::path::Path) {
+    assert_eq!(
+        FILE_CONTENT,
+        std::fs::read(mountdir.join("dir").join("file")).unwrap()
+    );
+}
+
+#[test]
+fn ondisk() {
+    if !fuse_available() {
+        return;
+    }
+    let vault = TestVault::create();
+    let backend = BackendConfig::OnDisk;
+
+    vault
+        .mount(&backend, CreateOrLoad::CreateNewFilesystem, write_file)
+        .unwrap();
+
+    assert!(!block_files(&vault.vaultdir()).is_empty());
+
+    vault
+        .mount(
+            &backend,
+            CreateOrLoad::LoadExistingFilesystem,
+            assert_file_exists,
+        )
+        .unwrap();
+}
+
+#[test]
+fn mirrored() {
+    if !fuse_available() {
+        return;
+    }
+    let vault = TestVault::create();
+    let replica = vault.create_dir("replica");
+    let backend = BackendConfig::Mirrored {
+        replicas: vec![replica.clone()],
+    };
+
+    vault
+        .mount(&backend, CreateOrLoad::CreateNewFilesystem, write_file)
+        .unwrap();
+
+    let blocks = block_files(&vault.vaultdir());
+    assert!(!blocks.is_empty());
+    assert_eq!(blocks, block_files(&replica));
+
+    vault
+        .mount(
+            &backend,
+            CreateOrLoad::LoadExistingFilesystem,
+            assert_file_exists,
+        )
+        .unwrap();
+}
+
+#[test]
+fn mirrored_reads_from_replica_if_vaultdir_lost_its_blocks() {
+    if !fuse_available() {
+        return;
+    }
+    let vault = TestVault::create();
+    let replica = vault.create_dir("replica");
+    let backend = BackendConfig::Mirrored {
+        replicas: vec![replica.clone()],
+    };
+    vault
+        .mount(&backend, CreateOrLoad::CreateNewFilesystem, write_file)
+        .unwrap();
+
+    for block in block_files(&vault.vaultdir()).keys() {
+        std::fs::remove_file(vault.vaultdir().join(block)).unwrap();
+    }
+
+    vault
+        .mount(
+            &backend,
+            CreateOrLoad::LoadExistingFilesystem,
+            assert_file_exists,
+        )
+        .unwrap();
+    // Reading the blocks from the replica copied them back to the vault directory
+    let repaired = block_files(&vault.vaultdir());
+    assert!(!repaired.is_empty());
+    for block in repaired.keys() {
+        assert!(replica.join(block).exists());
+    }
+}
@@ -3,6 +3,7 @@ use std::hint::black_box;
 
 use cryfs_crypto::kdf::{
     PasswordBasedKDF,
+    argon2id::{Argon2id, Argon2idSettings},
     scrypt::{
         ScryptSettings,
         backends::{openssl::ScryptOpenssl, scrypt::ScryptScrypt},
@@ -46,5 +47,30 @@ fn bench_scrypt(c: &mut Criterion) {
     }
 }
 
-criterion_group!(benches, bench_scrypt);
+fn bench_argon2id(c: &mut Criterion) {
+    let mut group = c.benchmark_group("argon2id");
+
+    for m_cost in [1024, 32 * 1024, 256 * 1024] {
+        for t_cost in [1, 2, 4] {
+            for p_cost in [1, 2, 4, 8] {
+                let settings = Argon2idSettings {
+                    m_cost,
+                    t_cost,
+                    p_cost,
+                    salt_len: 32,
+                };
+                group.bench_with_input(
+                    BenchmarkId::new("argon2id", format!("{settings:?}")),
+                    &settings,
+                    |b, settings| {
+                        let params = Argon2id::generate_parameters(settings).unwrap();
+                        b.iter(|| black_box(Argon2id::derive_key(64, "password", &params)));
+                    },
+                );
+            }
+        }
+    }
+}
+
+criterion_group!(benches, bench_scrypt, bench_argon2id);
 criterion_main!(benches);
@@ -0,0 +1,88 @@
+//! Argon2id key derivation function.
+//!
+//! Argon2id is the winner of the Password Hashing Competition and the variant recommended by
+//! RFC 9106. Like scrypt, it is memory-hard, which makes brute-force attacks with specialized
+//! hardware (ASICs and GPUs) expensive. It combines the side-channel resistance of Argon2i
+//! with the GPU resistance of Argon2d.
+//!
+//! # Parameters
+//!
+//! Argon2id has three main parameters that control its cost:
+//!
+//! - **m_cost**: Memory cost in KiB.
+//! - **t_cost**: Number of passes over the memory.
+//! - **p_cost**: Degree of parallelism (number of lanes).
+//!
+//! # Preset Settings
+//!
+//! The module provides several preset configurations via [`Argon2idSettings`]:
+//!
+//! - [`Argon2idSettings::PARANOID`]: Maximum security (4GB memory, very slow)
+//! - [`Argon2idSettings::DEFAULT`]: Good balance of security and usability (1GB memory)
+//! - [`Argon2idSettings::LOW_MEMORY`]: For memory-constrained environments (256MB memory)
+//! - [`Argon2idSettings::TEST`]: Fast settings for testing only (64KB memory)
+//!
+//! # Example
+//!
+//! ```
+//! use cryfs_crypto::kdf::argon2id::{Argon2id, Argon2idSettings};
+//! use cryfs_crypto::kdf::PasswordBasedKDF;
+//!
+//! // Generate parameters with test settings (fast, for examples only)
+//! let params = Argon2id::generate_parameters(&Argon2idSettings::TEST).unwrap();
+//!
+//! // Derive a 32-byte encryption key
+//! let key = Argon2id::derive_key(32, "my_secure_password", &params);
+//! assert_eq!(key.num_bytes(), 32);
+//! ```
+
+use anyhow::Result;
+// TODO Separate out InfallibleUnwrap from lockable and don't depend on lockable from this crate
+use lockable::InfallibleUnwrap;
+
+use crate::kdf::PasswordBasedKDF;
+use crate::symmetric::EncryptionKey;
+
+mod params;
+pub use params::Argon2idParams;
+
+mod settings;
+pub use settings::Argon2idSettings;
+
+/// Argon2id implementation using the pure Rust `argon2` crate.
+pub struct Argon2id;
+
+impl PasswordBasedKDF for Argon2id {
+    type Settings = Argon2idSettings;
+    type Parameters = Argon2idParams;
+
+    fn derive_key(
+        key_size: usize,
+        password: &str,
+        kdf_parameters: &Argon2idParams,
+    ) -> EncryptionKey {
+        let params = match kdf_parameters.to_argon2_params(key_size) {
+            Ok(params) => params,
+            Err(_) => panic!("Invalid argon2id parameters: {kdf_parameters:?}"),
+        };
+        let argon2 = ::argon2::Argon2::new(
+            ::argon2::Algorithm::Argon2id,
+            ::argon2::Version::V0x13,
+            params,
+        );
+        EncryptionKey::new(key_size, |key_data| {
+            argon2
+                .hash_password_into(password.as_bytes(), kdf_parameters.salt(), key_data)
+                .expect("Error in argon2id");
+            Ok(())
+        })
+        .infallible_unwrap()
+    }
+
+    fn generate_parameters(settings: &Argon2idSettings) -> Result<Argon2idParams> {
+        Argon2idParams::generate(settings)
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,213 @@
+//! Argon2id parameter storage and serialization.
+
+use anyhow::{Result, anyhow, ensure};
+use binrw::{BinRead, BinWrite, binrw, helpers::until_eof};
+use rand::{Rng, rng};
+use std::fmt::Debug;
+use std::io::Cursor;
+
+use super::super::KDFParameters;
+use super::Argon2idSettings;
+
+/// Parameters for the argon2id key derivation function.
+///
+/// `Argon2idParams` contains all the information needed to derive the same key
+/// from a password: the cost parameters (m_cost, t_cost, p_cost) and the random salt.
+/// These parameters must be stored alongside encrypted data to enable decryption.
+/// The argon2 version is always 0x13.
+///
+/// # Serialization Format
+///
+/// The parameters are serialized in little-endian binary format:
+/// - `m_cost` (4 bytes): Memory cost in KiB
+/// - `t_cost` (4 bytes): Number of iterations
+/// - `p_cost` (4 bytes): Degree of parallelism
+/// - `salt` (remaining bytes): Random salt
+///
+/// # Example
+///
+/// ```
+/// use cryfs_crypto::kdf::argon2id::{Argon2idParams, Argon2idSettings};
+/// use cryfs_crypto::kdf::KDFParameters;
+///
+/// // Generate new parameters
+/// let params = Argon2idParams::generate(&Argon2idSettings::TEST).unwrap();
+///
+/// // Serialize for storage
+/// let serialized = params.serialize();
+///
+/// // Later, deserialize to derive the same key
+/// let restored = Argon2idParams::deserialize(&serialized).unwrap();
+/// assert_eq!(params.m_cost(), restored.m_cost());
+/// ```
+#[derive(Clone, PartialEq, Eq)]
+#[binrw]
+#[brw(little)]
+pub struct Argon2idParams {
+    m_cost: u32,
+
+    t_cost: u32,
+
+    p_cost: u32,
+
+    #[br(parse_with = until_eof)]
+    salt: Vec<u8>,
+}
+
+impl Argon2idParams {
+    /// Generates new argon2id parameters with a random salt.
+    ///
+    /// # Arguments
+    ///
+    /// * `settings` - The cost settings (m_cost, t_cost, p_cost, salt_len)
+    ///
+    /// # Returns
+    ///
+    /// New parameters with a randomly generated salt, or an error if
+    /// the settings are invalid (e.g., m_cost < 8 * p_cost).
+    pub fn generate(settings: &Argon2idSettings) -> Result<Self> {
+        let mut salt = vec![0; settings.salt_len];
+        rng().fill_bytes(&mut salt);
+        let result = Self {
+            m_cost: settings.m_cost,
+            t_cost: settings.t_cost,
+            p_cost: settings.p_cost,
+            salt,
+        };
+        result.validate()?;
+        Ok(result)
+    }
+
+    /// Returns the memory cost in KiB.
+    pub fn m_cost(&self) -> u32 {
+        self.m_cost
+    }
+
+    /// Returns the number of iterations.
+    pub fn t_cost(&self) -> u32 {
+        self.t_cost
+    }
+
+    /// Returns the degree of parallelism.
+    pub fn p_cost(&self) -> u32 {
+        self.p_cost
+    }
+
+    /// Returns the random salt used for key derivation.
+    pub fn salt(&self) -> &[u8] {
+        &self.salt
+    }
+
+    pub(super) fn to_argon2_params(&self, output_len: usize) -> Result<::argon2::Params> {
+        ::argon2::Params::new(self.m_cost, self.t_cost, self.p_cost, Some(output_len))
+            .map_err(|err| anyhow!("Invalid argon2id parameters: {err}"))
+    }
+
+    fn validate(&self) -> Result<()> {
+        ensure!(
+            self.salt.len() >= ::argon2::MIN_SALT_LEN && self.salt.len() <= ::argon2::MAX_SALT_LEN,
+            "Argon2id salt length is {} but must be between {} and {}",
+            self.salt.len(),
+            ::argon2::MIN_SALT_LEN,
+            ::argon2::MAX_SALT_LEN,
+        );
+        self.to_argon2_params(::argon2::Params::DEFAULT_OUTPUT_LEN)?;
+        Ok(())
+    }
+}
+
+impl KDFParameters for Argon2idParams {
+    fn serialize(&self) -> Vec<u8> {
+        let mut result = Cursor::new(vec![]);
+        self.write(&mut result)
+            .expect("Writing can't fail because our serializer shouldn't throw anywhere");
+        result.into_inner()
+    }
+
+    fn deserialize(serialized: &[u8]) -> Result<Self> {
+        let mut cursor = Cursor::new(serialized);
+        let result = Self::read(&mut cursor)?;
+        result.validate()?;
+        Ok(result)
+    }
+}
+
+impl Debug for Argon2idParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Argon2idParams")
+            .field("m_cost", &self.m_cost)
+            .field("t_cost", &self.t_cost)
+            .field("p_cost", &self.p_cost)
+            .field("salt", &hex::encode(&self.salt))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate() {
+        let params = Argon2idParams::generate(&Argon2idSettings {
+            m_cost: 1024,
+            t_cost: 3,
+            p_cost: 2,
+            salt_len: 53,
+        })
+        .unwrap();
+        assert_eq!(params.m_cost, 1024);
+        assert_eq!(params.t_cost, 3);
+        assert_eq!(params.p_cost, 2);
+        assert_eq!(params.salt.len(), 53);
+    }
+
+    #[test]
+    fn generate_with_too_little_memory_fails() {
+        let result = Argon2idParams::generate(&Argon2idSettings {
+            m_cost: 15,
+            t_cost: 3,
+            p_cost: 2,
+            salt_len: 32,
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn generate_with_too_short_salt_fails() {
+        let result = Argon2idParams::generate(&Argon2idSettings {
+            m_cost: 1024,
+            t_cost: 3,
+            p_cost: 2,
+            salt_len: 7,
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn serialize() {
+        let params = Argon2idParams::generate(&Argon2idSettings {
+            m_cost: 1024,
+            t_cost: 3,
+            p_cost: 2,
+            salt_len: 53,
+        })
+        .unwrap();
+        let serialized = params.serialize();
+        let deserialized = Argon2idParams::deserialize(&serialized).unwrap();
+        assert_eq!(deserialized.m_cost, 1024);
+        assert_eq!(deserialized.t_cost, 3);
+        assert_eq!(deserialized.p_cost, 2);
+        assert_eq!(deserialized.salt, params.salt);
+    }
+
+    #[test]
+    fn deserialize_invalid_params_fails() {
+        // t_cost = 0 is invalid
+        let serialized = hex::decode(
+            "000400000000000002000000E429AFB0500BD5D172089598B76E6B9ED6D0DDAF3B08F99AA05357F96F4F7823",
+        )
+        .unwrap();
+        assert!(Argon2idParams::deserialize(&serialized).is_err());
+    }
+}
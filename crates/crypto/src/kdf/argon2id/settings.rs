@@ -0,0 +1,93 @@
+//! Argon2id cost settings and presets.
+
+/// Configuration settings for argon2id key derivation.
+///
+/// These settings control the computational cost of the argon2id algorithm.
+/// Higher values provide more security against brute-force attacks but
+/// require more memory and CPU time.
+///
+/// # Presets
+///
+/// Several preset configurations are provided:
+/// - [`Argon2idSettings::PARANOID`]: Maximum security, high resource usage
+/// - [`Argon2idSettings::DEFAULT`]: Recommended for most use cases
+/// - [`Argon2idSettings::LOW_MEMORY`]: For memory-constrained environments
+/// - [`Argon2idSettings::TEST`]: Fast settings for testing only
+///
+/// # Fields
+///
+/// - `m_cost`: Memory usage in KiB. Must be at least `8 * p_cost`.
+/// - `t_cost`: Number of passes over the memory.
+/// - `p_cost`: Degree of parallelism.
+/// - `salt_len`: Length of the random salt in bytes.
+#[derive(Debug, Clone, Copy)]
+pub struct Argon2idSettings {
+    /// Memory cost in KiB.
+    pub m_cost: u32,
+    /// Number of iterations.
+    pub t_cost: u32,
+    /// Degree of parallelism.
+    pub p_cost: u32,
+    /// Length of the random salt in bytes. Must be at least 8.
+    pub salt_len: usize,
+}
+
+impl Argon2idSettings {
+    /// Memory usage: 4GB
+    pub const PARANOID: Self = Self {
+        m_cost: 4 * 1024 * 1024,
+        t_cost: 4,
+        p_cost: 4,
+        salt_len: 32,
+    };
+
+    /// Memory usage: 1GB
+    pub const DEFAULT: Self = Self {
+        m_cost: 1024 * 1024,
+        t_cost: 3,
+        p_cost: 4,
+        salt_len: 32,
+    };
+
+    /// Memory usage: 256MB. Uses more passes to make up for the lower memory usage.
+    pub const LOW_MEMORY: Self = Self {
+        m_cost: 256 * 1024,
+        t_cost: 6,
+        p_cost: 4,
+        salt_len: 32,
+    };
+
+    /// Memory usage: 64kB
+    // TODO#[cfg(test)]
+    pub const TEST: Self = Self {
+        m_cost: 64,
+        t_cost: 1,
+        // Use p_cost != t_cost so we find serialization errors
+        p_cost: 2,
+        salt_len: 32,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::Argon2idSettings;
+    use crate::kdf::argon2id::Argon2idParams;
+
+    #[rstest]
+    fn params_are_valid(
+        #[values(
+            Argon2idSettings::PARANOID,
+            Argon2idSettings::DEFAULT,
+            Argon2idSettings::LOW_MEMORY,
+            Argon2idSettings::TEST
+        )]
+        settings: Argon2idSettings,
+    ) {
+        let params = Argon2idParams::generate(&settings).unwrap();
+        params
+            .to_argon2_params(32)
+            .expect("Invalid argon2id parameters");
+    }
+}
@@ -0,0 +1,128 @@
+use crate::kdf::{
+    KDFParameters, PasswordBasedKDF,
+    argon2id::{Argon2id, Argon2idParams, Argon2idSettings},
+};
+
+#[test]
+fn generated_key_is_reproductible_448() {
+    let params = Argon2id::generate_parameters(&Argon2idSettings::TEST).unwrap();
+    let derived_key = Argon2id::derive_key(56, "mypassword", &params);
+
+    let params = Argon2idParams::deserialize(&params.serialize()).unwrap();
+    let rederived_key = Argon2id::derive_key(56, "mypassword", &params);
+
+    assert_eq!(derived_key.to_hex(), rederived_key.to_hex());
+}
+
+#[test]
+fn backwards_compatibility_448() {
+    let kdf_parameters = Argon2idParams::deserialize(&hex::decode("400000000100000002000000E429AFB0500BD5D172089598B76E6B9ED6D0DDAF3B08F99AA05357F96F4F7823").unwrap()).unwrap();
+    let rederived_key = Argon2id::derive_key(56, "mypassword", &kdf_parameters);
+    assert_eq!(
+        "C8697AF784074F4E7BD5138FE6DE9268E81C00500E8F2157D4B8E253A720722208F1282FE7389B62EBDD937F4ADE6CE8B63D32961936902C",
+        rederived_key.to_hex()
+    );
+}
+
+#[test]
+fn generated_key_is_reproductible_256() {
+    let params = Argon2id::generate_parameters(&Argon2idSettings::TEST).unwrap();
+    let derived_key = Argon2id::derive_key(32, "mypassword", &params);
+
+    let params = Argon2idParams::deserialize(&params.serialize()).unwrap();
+    let rederived_key = Argon2id::derive_key(32, "mypassword", &params);
+
+    assert_eq!(derived_key.to_hex(), rederived_key.to_hex());
+}
+
+#[test]
+fn backwards_compatibility_256() {
+    let kdf_parameters = Argon2idParams::deserialize(&hex::decode("4000000001000000020000007D65C035E0C4250003A24ED11ABD41F6101DEEC104F6875EE1B808A6683535BD").unwrap()).unwrap();
+    let rederived_key = Argon2id::derive_key(32, "mypassword", &kdf_parameters);
+    assert_eq!(
+        "16234A8FEACDA5EB224F7694BFC1F3D76350BE792BD94C70B63F4A2C5E4C0AFD",
+        rederived_key.to_hex()
+    );
+}
+
+#[test]
+fn generated_key_is_reproductible_128() {
+    let params = Argon2id::generate_parameters(&Argon2idSettings::TEST).unwrap();
+    let derived_key = Argon2id::derive_key(16, "mypassword", &params);
+
+    let params = Argon2idParams::deserialize(&params.serialize()).unwrap();
+    let rederived_key = Argon2id::derive_key(16, "mypassword", &params);
+
+    assert_eq!(derived_key.to_hex(), rederived_key.to_hex());
+}
+
+#[test]
+fn backwards_compatibility_128() {
+    let kdf_parameters = Argon2idParams::deserialize(&hex::decode("4000000001000000020000008514339A7F583D80C9865C9EA01B698EE8AEAF99AE5F7AE79C8817D2E73D553D").unwrap()).unwrap();
+    let rederived_key = Argon2id::derive_key(16, "mypassword", &kdf_parameters);
+    assert_eq!("547D96AFC3203B76C1079D5858CB7EAE", rederived_key.to_hex());
+}
+
+#[test]
+fn different_passwords_result_in_different_keys() {
+    let params = Argon2id::generate_parameters(&Argon2idSettings::TEST).unwrap();
+    let derived_key_1 = Argon2id::derive_key(16, "mypassword", &params);
+    let derived_key_2 = Argon2id::derive_key(16, "mypassword2", &params);
+
+    assert_ne!(derived_key_1.to_hex(), derived_key_2.to_hex());
+}
+
+#[test]
+fn different_salts_result_in_different_keys() {
+    let params_1 = Argon2id::generate_parameters(&Argon2idSettings::TEST).unwrap();
+    let params_2 = Argon2id::generate_parameters(&Argon2idSettings::TEST).unwrap();
+    let derived_key_1 = Argon2id::derive_key(16, "mypassword", &params_1);
+    let derived_key_2 = Argon2id::derive_key(16, "mypassword", &params_2);
+
+    assert_ne!(derived_key_1.to_hex(), derived_key_2.to_hex());
+}
+
+#[test]
+fn empty_password_produces_valid_key() {
+    let params = Argon2id::generate_parameters(&Argon2idSettings::TEST).unwrap();
+    let derived_key = Argon2id::derive_key(32, "", &params);
+
+    // Empty password should still produce a valid key
+    assert_eq!(derived_key.num_bytes(), 32);
+
+    // Key should be reproducible
+    let rederived_key = Argon2id::derive_key(32, "", &params);
+    assert_eq!(derived_key.to_hex(), rederived_key.to_hex());
+}
+
+#[test]
+fn unicode_password_produces_valid_key() {
+    let params = Argon2id::generate_parameters(&Argon2idSettings::TEST).unwrap();
+
+    // Test with emoji and multi-byte characters
+    let unicode_password = "пароль🔐密码";
+    let derived_key = Argon2id::derive_key(32, unicode_password, &params);
+
+    // Unicode password should produce a valid key
+    assert_eq!(derived_key.num_bytes(), 32);
+
+    // Key should be reproducible
+    let rederived_key = Argon2id::derive_key(32, unicode_password, &params);
+    assert_eq!(derived_key.to_hex(), rederived_key.to_hex());
+}
+
+#[test]
+fn long_password_produces_valid_key() {
+    let params = Argon2id::generate_parameters(&Argon2idSettings::TEST).unwrap();
+
+    // Test with a password longer than 1KB
+    let long_password = "a".repeat(2048);
+    let derived_key = Argon2id::derive_key(32, &long_password, &params);
+
+    // Long password should produce a valid key
+    assert_eq!(derived_key.num_bytes(), 32);
+
+    // Key should be reproducible
+    let rederived_key = Argon2id::derive_key(32, &long_password, &params);
+    assert_eq!(derived_key.to_hex(), rederived_key.to_hex());
+}
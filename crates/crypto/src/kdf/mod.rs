@@ -10,6 +10,7 @@
 //! # Available KDFs
 //!
 //! - [`scrypt`]: Memory-hard KDF recommended for password hashing
+//! - [`argon2id`]: Memory-hard KDF recommended by RFC 9106, an alternative to scrypt
 //!
 //! # Example
 //!
@@ -60,7 +61,8 @@ pub trait KDFParameters: Sized + Debug {
 ///
 /// # Security
 ///
-/// - Use appropriate settings for your security requirements (see [`scrypt::ScryptSettings`])
+/// - Use appropriate settings for your security requirements (see [`scrypt::ScryptSettings`]
+///   and [`argon2id::Argon2idSettings`])
 /// - Store parameters alongside encrypted data for decryption
 /// - Never reuse parameters across different passwords
 pub trait PasswordBasedKDF {
@@ -103,4 +105,5 @@ pub trait PasswordBasedKDF {
     fn generate_parameters(settings: &Self::Settings) -> Result<Self::Parameters>;
 }
 
+pub mod argon2id;
 pub mod scrypt;
@@ -12,8 +12,9 @@ use cryfs_blockstore::{
 };
 use cryfs_filesystem::filesystem::CryDevice;
 use cryfs_rustfs::{
-    Callback, FileHandle, FsResult, Gid, Mode, NodeAttrs, NodeKind, NumBytes, OpenInFlags, Statfs,
-    Uid, high_level_api::AsyncFilesystem as _, object_based_api::ObjectBasedFsAdapter,
+    Callback, FileHandle, FsResult, Gid, KernelCacheOptions, Mode, NodeAttrs, NodeKind, NumBytes,
+    OpenInFlags, Statfs, Uid, high_level_api::AsyncFilesystem as _,
+    object_based_api::ObjectBasedFsAdapter,
 };
 use cryfs_utils::{
     async_drop::{AsyncDrop, AsyncDropArc, AsyncDropGuard},
@@ -49,7 +50,7 @@ impl FilesystemDriver for FusemtFilesystemDriver {
 
     async fn new(device: AsyncDropGuard<Device>) -> AsyncDropGuard<Self> {
         AsyncDropGuard::new(Self {
-            fs: ObjectBasedFsAdapter::new(|_uid, _gid| device),
+            fs: ObjectBasedFsAdapter::new(|_uid, _gid| device, &KernelCacheOptions::default()),
         })
     }
 
@@ -365,7 +366,14 @@ impl FilesystemDriver for FusemtFilesystemDriver {
         let new_path = new_parent
             .unwrap_or_else(AbsolutePathBuf::root)
             .join(new_name);
-        self.fs.rename(request_info(), &old_path, &new_path).await
+        self.fs
+            .rename(
+                request_info(),
+                &old_path,
+                &new_path,
+                0, // flags - using 0 for default behavior
+            )
+            .await
     }
 
     async fn fsync(
@@ -17,8 +17,8 @@ use cryfs_blockstore::{
 };
 use cryfs_filesystem::filesystem::CryDevice;
 use cryfs_rustfs::{
-    Callback, FileHandle, FsError, FsResult, Gid, InodeNumber, Mode, NodeAttrs, NodeKind, NumBytes,
-    OpenInFlags, Statfs, Uid,
+    Callback, FileHandle, FsError, FsResult, Gid, InodeNumber, KernelCacheOptions, Mode, NodeAttrs,
+    NodeKind, NumBytes, OpenInFlags, Statfs, Uid,
     low_level_api::{AsyncFilesystemLL, ReplyDirectory, ReplyDirectoryAddResult},
     object_based_api::{FUSE_ROOT_ID, ObjectBasedFsAdapterLL},
 };
@@ -275,7 +275,10 @@ impl<C: FuserCacheBehavior> FilesystemDriver for FuserFilesystemDriver<C> {
 
     async fn new(device: AsyncDropGuard<Device>) -> AsyncDropGuard<Self> {
         AsyncDropGuard::new(FuserFilesystemDriver {
-            fs: AsyncDropArc::new(ObjectBasedFsAdapterLL::new(|_uid, _gid| device)),
+            fs: AsyncDropArc::new(ObjectBasedFsAdapterLL::new(
+                |_uid, _gid| device,
+                KernelCacheOptions::default(),
+            )),
             _c: PhantomData,
         })
     }
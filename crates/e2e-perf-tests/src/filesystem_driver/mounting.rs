@@ -6,7 +6,7 @@ use nix::{
 };
 use std::{
     io::SeekFrom,
-    os::unix::fs::{MetadataExt, PermissionsExt},
+    os::unix::fs::{FileTypeExt as _, MetadataExt, PermissionsExt},
     path::{Path, PathBuf},
     sync::{Arc, Mutex},
     time::{Duration, SystemTime},
@@ -25,7 +25,7 @@ use cryfs_blockstore::{
 use cryfs_filesystem::filesystem::CryDevice;
 use cryfs_rustfs::object_based_api::RustfsBackend as _;
 use cryfs_rustfs::{
-    FsError, FsResult, Gid, Mode, NodeAttrs, NodeKind, NumBytes, Statfs, Uid,
+    FsError, FsResult, Gid, KernelCacheOptions, Mode, NodeAttrs, NodeKind, NumBytes, Statfs, Uid,
     backend::{BackgroundSession, RunningFilesystem},
 };
 use cryfs_utils::{
@@ -77,6 +77,7 @@ impl MountingBackend for FuserBackend {
             mountdir,
             runtime,
             &[],
+            KernelCacheOptions::default(),
         )
         .await
         .map_err(|error| FsError::InternalError {
@@ -109,6 +110,7 @@ impl MountingBackend for FusemtBackend {
             mountdir,
             runtime,
             &[],
+            KernelCacheOptions::default(),
         )
         .await
         .map_err(|error| FsError::InternalError {
@@ -681,6 +683,14 @@ where
                 NodeKind::File
             } else if kind.is_symlink() {
                 NodeKind::Symlink
+            } else if kind.is_fifo() {
+                NodeKind::Fifo
+            } else if kind.is_socket() {
+                NodeKind::Socket
+            } else if kind.is_char_device() {
+                NodeKind::CharDevice
+            } else if kind.is_block_device() {
+                NodeKind::BlockDevice
             } else {
                 panic!("Unknown file type");
             };
@@ -809,6 +819,7 @@ fn metadata_to_node_attrs(metadata: std::fs::Metadata) -> NodeAttrs {
         gid: Gid::from(metadata.gid()),
         num_bytes: NumBytes::from(metadata.len()),
         num_blocks: None,
+        rdev: metadata.rdev() as u32,
         atime: SystemTime::UNIX_EPOCH + Duration::from_secs(metadata.atime() as u64),
         mtime: SystemTime::UNIX_EPOCH + Duration::from_secs(metadata.mtime() as u64),
         ctime: SystemTime::UNIX_EPOCH + Duration::from_secs(metadata.ctime() as u64),
@@ -147,6 +147,7 @@ where
                 on_integrity_violation: Box::new(move |err| {
                     panic!("Didn't expect integrity violations in test but got {err:?}");
                 }),
+                on_block_changed_by_other_client: Box::new(|_| {}),
             },
         )
         .await
@@ -275,5 +276,6 @@ fn config(blocksize: Byte) -> CryConfig {
         blocksize,
         filesystem_id: FilesystemId::from_hex("8de43828c75c9bb10cac251eaf4ad9bd").unwrap(),
         exclusive_client_id: Some(MY_CLIENT_ID.get()),
+        compression: None,
     }
 }
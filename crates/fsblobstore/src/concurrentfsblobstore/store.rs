@@ -1,6 +1,7 @@
 use anyhow::Result;
 use async_trait::async_trait;
 use byte_unit::Byte;
+use futures::lock::Mutex;
 use std::{fmt::Debug, sync::Arc};
 
 use cryfs_blobstore::{BlobId, BlobStore, RemoveResult};
@@ -14,7 +15,7 @@ use crate::{
         ConcurrentFsBlob,
         loaded_blobs::{LoadedBlobs, RequestRemovalResult},
     },
-    fsblobstore::{FlushBehavior, FsBlobStore},
+    fsblobstore::{FlushBehavior, FsBlobStore, FsFormat, Intent, IntentId, IntentJournal},
 };
 
 #[derive(Debug)]
@@ -26,6 +27,7 @@ where
 {
     blobstore: AsyncDropGuard<AsyncDropArc<FsBlobStore<B>>>,
     loaded_blobs: AsyncDropGuard<AsyncDropArc<LoadedBlobs<B>>>,
+    journal: Mutex<IntentJournal>,
 }
 
 impl<B> ConcurrentFsBlobStore<B>
@@ -33,10 +35,14 @@ where
     B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + Sync + 'static,
     <B as BlobStore>::ConcreteBlob: Send + AsyncDrop<Error = anyhow::Error>,
 {
-    pub fn new(blobstore: AsyncDropGuard<FsBlobStore<B>>) -> AsyncDropGuard<Self> {
+    pub fn new(
+        blobstore: AsyncDropGuard<FsBlobStore<B>>,
+        journal: IntentJournal,
+    ) -> AsyncDropGuard<Self> {
         AsyncDropGuard::new(Self {
             blobstore: AsyncDropArc::new(blobstore),
             loaded_blobs: AsyncDropArc::new(LoadedBlobs::new()),
+            journal: Mutex::new(journal),
         })
     }
 
@@ -57,12 +63,13 @@ where
 
     pub async fn create_file_blob(
         &self,
+        blob_id: &BlobId,
         parent: &BlobId,
         flush_behavior: FlushBehavior,
     ) -> Result<AsyncDropGuard<ConcurrentFsBlob<B>>> {
         let blob = self
             .blobstore
-            .create_file_blob(parent, flush_behavior)
+            .create_file_blob(blob_id, parent, flush_behavior)
             .await?;
         let inserted = LoadedBlobs::try_insert_loaded(&self.loaded_blobs, blob)
             .await
@@ -72,12 +79,13 @@ where
 
     pub async fn create_dir_blob(
         &self,
+        blob_id: &BlobId,
         parent: &BlobId,
         flush_behavior: FlushBehavior,
     ) -> Result<AsyncDropGuard<ConcurrentFsBlob<B>>> {
         let blob = self
             .blobstore
-            .create_dir_blob(parent, flush_behavior)
+            .create_dir_blob(blob_id, parent, flush_behavior)
             .await?;
         let inserted = LoadedBlobs::try_insert_loaded(&self.loaded_blobs, blob)
             .await
@@ -87,13 +95,14 @@ where
 
     pub async fn create_symlink_blob(
         &self,
+        blob_id: &BlobId,
         parent: &BlobId,
         target: &str,
         flush_behavior: FlushBehavior,
     ) -> Result<AsyncDropGuard<ConcurrentFsBlob<B>>> {
         let blob = self
             .blobstore
-            .create_symlink_blob(parent, target, flush_behavior)
+            .create_symlink_blob(blob_id, parent, target, flush_behavior)
             .await?;
         let inserted = LoadedBlobs::try_insert_loaded(&self.loaded_blobs, blob)
             .await
@@ -118,6 +127,10 @@ where
         Ok(loaded_blob.map(ConcurrentFsBlob::new))
     }
 
+    pub fn format(&self) -> FsFormat {
+        self.blobstore.format()
+    }
+
     pub async fn num_blocks(&self) -> Result<u64> {
         self.blobstore.num_blocks().await
     }
@@ -163,6 +176,33 @@ where
         }
         Ok(())
     }
+
+    /// Record `intent` in the journal before starting a multi-blob operation, see [IntentJournal].
+    pub async fn add_intent(&self, intent: Intent) -> Result<IntentId> {
+        let mut journal = self.journal.lock().await;
+        self.blobstore.add_intent(&mut journal, intent).await
+    }
+
+    /// Remove an intent from the journal once its operation finished and all blobs it modified are flushed.
+    pub async fn complete_intent(&self, id: IntentId) -> Result<()> {
+        let mut journal = self.journal.lock().await;
+        self.blobstore.complete_intent(&mut journal, id).await
+    }
+
+    /// Intents of operations that didn't finish before the file system was unmounted the last time.
+    pub async fn pending_intents(&self) -> Vec<(IntentId, Intent)> {
+        self.journal
+            .lock()
+            .await
+            .pending_intents()
+            .map(|(id, intent)| (id, intent.clone()))
+            .collect()
+    }
+
+    /// Write barrier, see [BlobStore::sync]. This doesn't flush any loaded blobs.
+    pub async fn sync(&self) -> anyhow::Result<()> {
+        self.blobstore.sync().await
+    }
 }
 
 #[async_trait]
@@ -0,0 +1,24 @@
+/// Decides which on-disk data structures an [FsBlobStore](super::FsBlobStore) is allowed to write.
+///
+/// File systems stay readable by CryFS 1.0 unless they were explicitly created with the extended format.
+/// Features that need data structures CryFS 1.0 doesn't know about are either emulated in a compatible way
+/// (e.g. gaps in sparse files are filled with zeroes) or not available in the compatible format.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FsFormat {
+    /// Only write data structures that CryFS 1.0 can read.
+    Cryfs1Compatible,
+
+    /// Allow extended attributes, link counts for hard links, special files (fifos, sockets, devices), holes in
+    /// sparse files, subtrees shared between files, the bucket layout for directories and the intent journal blob.
+    /// CryFS 1.0 can't read file systems using any of these.
+    Extended,
+}
+
+impl FsFormat {
+    pub fn is_extended(self) -> bool {
+        match self {
+            Self::Cryfs1Compatible => false,
+            Self::Extended => true,
+        }
+    }
+}
@@ -1,11 +1,12 @@
-use anyhow::{Result, ensure};
+use anyhow::{Result, bail, ensure};
 use async_trait::async_trait;
 use binary_layout::Field;
 use futures::stream::BoxStream;
 use std::fmt::Debug;
 
-use super::layout::{self, FORMAT_VERSION_HEADER};
-use cryfs_blobstore::{Blob, BlobId, BlobStore};
+use super::layout::{self, FORMAT_VERSION_HEADER, FORMAT_VERSION_HEADER_WITH_LINK_COUNT};
+use crate::fsblobstore::FsFormat;
+use cryfs_blobstore::{BLOBID_LEN, Blob, BlobId, BlobStore};
 use cryfs_blockstore::BlockId;
 use cryfs_utils::{
     async_drop::{AsyncDrop, AsyncDropGuard},
@@ -19,8 +20,16 @@ where
 {
     blob: AsyncDropGuard<B::ConcreteBlob>,
     header_cache: layout::fsblob_header::View<Data>,
+    // Only blobs with [FORMAT_VERSION_HEADER_WITH_LINK_COUNT] store a link count, all other blobs have exactly one link.
+    num_links: Option<u32>,
+    link_metadata_blob_id: Option<BlobId>,
+    format: FsFormat,
 }
 
+/// File systems in the [FsFormat::Cryfs1Compatible] format can't have holes or shared subtrees, so we write zeroes
+/// or copy the data instead. This is the number of bytes we write at once.
+const WRITE_DATA_CHUNK_SIZE: usize = 1024 * 1024;
+
 impl<B> BaseBlob<B>
 where
     B: BlobStore + Debug,
@@ -28,6 +37,7 @@ where
 {
     pub async fn parse(
         mut blob: AsyncDropGuard<B::ConcreteBlob>,
+        format: FsFormat,
     ) -> Result<AsyncDropGuard<BaseBlob<B>>> {
         // TODO No need to zero-initialize
         let mut header = vec![0; layout::fsblob_header::SIZE.unwrap()];
@@ -39,15 +49,32 @@ where
             }
         }
         let header_cache = layout::fsblob_header::View::new(header.into());
-        if header_cache.format_version_header().read() != FORMAT_VERSION_HEADER {
-            blob.async_drop().await.unwrap(); //TODO no unwrap
-            anyhow::bail!(
-                "Loaded FsBlob with format version {} but current version is {}",
-                header_cache.format_version_header().read(),
-                FORMAT_VERSION_HEADER
-            );
-        }
-        Ok(AsyncDropGuard::new(Self { blob, header_cache }))
+        let (num_links, link_metadata_blob_id) = match header_cache.format_version_header().read() {
+            FORMAT_VERSION_HEADER => (None, None),
+            FORMAT_VERSION_HEADER_WITH_LINK_COUNT => match read_link_count_header(&mut *blob).await
+            {
+                Ok((num_links, link_metadata_blob_id)) => (Some(num_links), link_metadata_blob_id),
+                Err(e) => {
+                    blob.async_drop().await.unwrap(); //TODO no unwrap
+                    return Err(e);
+                }
+            },
+            format_version => {
+                blob.async_drop().await.unwrap(); //TODO no unwrap
+                anyhow::bail!(
+                    "Loaded FsBlob with format version {} but current version is {}",
+                    format_version,
+                    FORMAT_VERSION_HEADER
+                );
+            }
+        };
+        Ok(AsyncDropGuard::new(Self {
+            blob,
+            header_cache,
+            num_links,
+            link_metadata_blob_id,
+            format,
+        }))
     }
 
     pub async fn try_create_with_id(
@@ -56,8 +83,11 @@ where
         blob_type: layout::BlobType,
         parent: &BlobId,
         data: &[u8],
+        format: FsFormat,
     ) -> Result<Option<AsyncDropGuard<BaseBlob<B>>>> {
-        let blob_data = create_data_for_new_blob(blob_type, parent, data);
+        // Directories can't be hard linked, so they don't need a link count
+        let with_link_count = format.is_extended() && blob_type != layout::BlobType::Dir;
+        let blob_data = create_data_for_new_blob(blob_type, parent, data, with_link_count);
 
         // TODO Directly creating the blob with the data would probably be faster
         // than first creating it empty and then writing to it
@@ -74,35 +104,36 @@ where
         Ok(Some(AsyncDropGuard::new(Self {
             blob,
             header_cache: layout::fsblob_header::View::new(blob_data),
+            num_links: with_link_count.then_some(1),
+            link_metadata_blob_id: None,
+            format,
         })))
     }
 
     pub async fn create(
+        blob_id: &BlobId,
         blobstore: &B,
         blob_type: layout::BlobType,
         parent: &BlobId,
         data: &[u8],
+        format: FsFormat,
     ) -> Result<AsyncDropGuard<BaseBlob<B>>> {
-        let blob_data = create_data_for_new_blob(blob_type, parent, data);
-
-        // TODO Directly creating the blob with the data would probably be faster
-        // than first creating it empty and then writing to it
-        let mut blob = blobstore.create().await?;
-        blob.write(&blob_data, 0).await?;
-
-        let mut header_cache = blob_data;
-        header_cache.shrink_to_subregion(..layout::fsblob_header::SIZE.unwrap());
-
-        Ok(AsyncDropGuard::new(Self {
-            blob,
-            header_cache: layout::fsblob_header::View::new(header_cache),
-        }))
+        let Some(blob) =
+            Self::try_create_with_id(blob_id, blobstore, blob_type, parent, data, format).await?
+        else {
+            bail!("Blob {blob_id:?} already exists");
+        };
+        Ok(blob)
     }
 
     pub fn blob_id(&self) -> BlobId {
         self.blob.id()
     }
 
+    pub fn format(&self) -> FsFormat {
+        self.format
+    }
+
     pub fn blob_type(&self) -> Result<layout::BlobType> {
         Ok(self.header_cache.blob_type().try_read()?)
     }
@@ -124,42 +155,255 @@ where
         Ok(())
     }
 
+    /// Number of directory entries referencing this blob.
+    pub fn num_links(&self) -> u32 {
+        self.num_links.unwrap_or(1)
+    }
+
+    /// Only blobs with a link count can be hard linked. In the extended format, file and symlink blobs
+    /// get a link count when they're created.
+    pub fn has_link_count(&self) -> bool {
+        self.num_links.is_some()
+    }
+
+    /// Blobs that were hard linked at some point store their metadata in the blob returned by [Self::link_metadata_blob_id].
+    /// Such blobs can be referenced from multiple directories and their parent pointer isn't maintained anymore.
+    pub fn is_hard_linked(&self) -> bool {
+        self.link_metadata_blob_id.is_some()
+    }
+
+    /// The blob storing the metadata shared by all links to this blob, or `None` if the blob was never hard linked
+    /// and its metadata is stored in its dir entry.
+    pub fn link_metadata_blob_id(&self) -> Option<BlobId> {
+        self.link_metadata_blob_id
+    }
+
+    /// Add the first hard link to a blob that was never hard linked before and return the new link count.
+    /// `link_metadata_blob_id` must already contain the metadata from the dir entry of the existing link.
+    /// This only writes the link count header, so a crash either leaves the blob as it was or hard linked.
+    pub async fn add_first_hard_link(&mut self, link_metadata_blob_id: &BlobId) -> Result<u32> {
+        let Some(num_links) = self.num_links else {
+            bail!("Blob doesn't have a link count and can't be hard linked");
+        };
+        ensure!(
+            self.link_metadata_blob_id.is_none(),
+            "Blob is already hard linked"
+        );
+        ensure!(
+            num_links == 1,
+            "Blob that was never hard linked has {num_links} links"
+        );
+        self.write_link_count_header(2, Some(*link_metadata_blob_id))
+            .await?;
+        Ok(2)
+    }
+
+    /// Increment the link count of a blob that is already hard linked and return the new link count.
+    /// Blobs that were never hard linked need to go through [Self::add_first_hard_link] instead.
+    pub async fn increment_num_links(&mut self) -> Result<u32> {
+        let (Some(num_links), Some(link_metadata_blob_id)) =
+            (self.num_links, self.link_metadata_blob_id)
+        else {
+            bail!("Blob isn't hard linked yet");
+        };
+        let Some(new_num_links) = num_links.checked_add(1) else {
+            bail!("Blob already has the maximal number of links");
+        };
+        self.write_link_count_header(new_num_links, Some(link_metadata_blob_id))
+            .await?;
+        Ok(new_num_links)
+    }
+
+    /// Decrement the link count and return the new link count. This fails if the blob only has one link left,
+    /// the last link has to be removed by removing the blob.
+    pub async fn decrement_num_links(&mut self) -> Result<u32> {
+        let num_links = self.num_links();
+        ensure!(
+            num_links > 1,
+            "Tried to decrement the link count of a blob that only has {num_links} links"
+        );
+        // The metadata stays in the link metadata blob even if only one link is left
+        self.write_link_count_header(num_links - 1, self.link_metadata_blob_id)
+            .await?;
+        Ok(num_links - 1)
+    }
+
+    async fn write_link_count_header(
+        &mut self,
+        num_links: u32,
+        link_metadata_blob_id: Option<BlobId>,
+    ) -> Result<()> {
+        assert!(self.has_link_count(), "Blob doesn't have a link count");
+        let mut header = layout::fsblob_link_count_header::View::new(
+            [0; layout::fsblob_link_count_header::SIZE.unwrap()],
+        );
+        header.num_links_mut().write(num_links);
+        if let Some(link_metadata_blob_id) = &link_metadata_blob_id {
+            header
+                .link_metadata_blob_id_mut()
+                .copy_from_slice(link_metadata_blob_id.data());
+        }
+        // The link count header is written in one write so it can't get torn
+        self.blob
+            .write(
+                &header.into_storage(),
+                layout::fsblob_header::SIZE.unwrap() as u64,
+            )
+            .await?;
+        self.num_links = Some(num_links);
+        self.link_metadata_blob_id = link_metadata_blob_id;
+        Ok(())
+    }
+
+    fn data_offset(&self) -> u64 {
+        let header_size = layout::fsblob_header::SIZE.unwrap() as u64;
+        if self.has_link_count() {
+            header_size + layout::fsblob_link_count_header::SIZE.unwrap() as u64
+        } else {
+            header_size
+        }
+    }
+
     pub async fn num_data_bytes(&mut self) -> Result<u64> {
         // TODO Make self parameter non-mut?
-        Ok(self.blob.num_bytes().await? - layout::fsblob_header::SIZE.unwrap() as u64)
+        Ok(self.blob.num_bytes().await? - self.data_offset())
     }
 
     pub async fn resize_data(&mut self, new_num_bytes: u64) -> Result<()> {
-        self.blob
-            .resize(new_num_bytes + layout::fsblob_header::SIZE.unwrap() as u64)
-            .await
+        if !self.format.is_extended() {
+            let old_num_bytes = self.num_data_bytes().await?;
+            if new_num_bytes > old_num_bytes {
+                // Growing the blob would leave a hole, so write the zeroes instead
+                return self
+                    .write_zeroes(old_num_bytes, new_num_bytes - old_num_bytes)
+                    .await;
+            }
+        }
+        let data_offset = self.data_offset();
+        self.blob.resize(new_num_bytes + data_offset).await
     }
 
     pub async fn try_read_data(&mut self, target: &mut [u8], offset: u64) -> Result<usize> {
         // TODO Make self parameter non-mut?
-        self.blob
-            .try_read(target, offset + layout::fsblob_header::SIZE.unwrap() as u64)
-            .await
+        let data_offset = self.data_offset();
+        self.blob.try_read(target, offset + data_offset).await
     }
 
     pub async fn read_all_data(&mut self) -> Result<Data> {
         // TODO We should probably enforce a max size for the read so we don't block when a file system is bad
         //      This is only used for symlink blobs right now and those aren't supposed to be that large.
+        let data_offset = self.data_offset() as usize;
         let mut data = self.blob.read_all().await?;
         ensure!(
-            data.len() >= layout::fsblob_header::SIZE.unwrap(),
+            data.len() >= data_offset,
             "Blob is too small to contain a header"
         );
-        data.shrink_to_subregion(layout::fsblob_header::SIZE.unwrap()..);
+        data.shrink_to_subregion(data_offset..);
         Ok(data)
     }
 
     pub async fn write_data(&mut self, source: &[u8], offset: u64) -> Result<()> {
+        if !self.format.is_extended() {
+            let num_bytes = self.num_data_bytes().await?;
+            if offset > num_bytes {
+                // Writing after the end of the blob would leave a hole, so fill the gap with zeroes first
+                self.write_zeroes(num_bytes, offset - num_bytes).await?;
+            }
+        }
+        let data_offset = self.data_offset();
+        self.blob.write(source, offset + data_offset).await
+    }
+
+    pub async fn punch_hole_data(&mut self, offset: u64, len: u64) -> Result<()> {
+        if !self.format.is_extended() {
+            // Punching a hole doesn't change the size of the blob, it only zeroes the range
+            let num_bytes = self.num_data_bytes().await?;
+            let len = len.min(num_bytes.saturating_sub(offset));
+            return self.write_zeroes(offset, len).await;
+        }
+        let data_offset = self.data_offset();
+        self.blob.punch_hole(offset + data_offset, len).await
+    }
+
+    async fn write_zeroes(&mut self, offset: u64, len: u64) -> Result<()> {
+        let data_offset = self.data_offset();
+        let zeroes = vec![0; WRITE_DATA_CHUNK_SIZE];
+        let mut written = 0;
+        while written < len {
+            let chunk_len = (len - written).min(WRITE_DATA_CHUNK_SIZE as u64);
+            self.blob
+                .write(
+                    &zeroes[..chunk_len as usize],
+                    offset + written + data_offset,
+                )
+                .await?;
+            written += chunk_len;
+        }
+        Ok(())
+    }
+
+    pub async fn seek_data(&mut self, offset: u64) -> Result<Option<u64>> {
+        let data_offset = self.data_offset();
+        let result = self.blob.seek_data(offset + data_offset).await?;
+        Ok(result.map(|result| result - data_offset))
+    }
+
+    pub async fn seek_hole(&mut self, offset: u64) -> Result<Option<u64>> {
+        let data_offset = self.data_offset();
+        let result = self.blob.seek_hole(offset + data_offset).await?;
+        Ok(result.map(|result| result - data_offset))
+    }
+
+    pub async fn clone_data_range_from(
+        &mut self,
+        source: &mut BaseBlob<B>,
+        source_offset: u64,
+        offset: u64,
+        len: u64,
+    ) -> Result<u64> {
+        if !self.format.is_extended() {
+            // Shared subtrees can't be read by CryFS 1.0, so copy the data instead
+            return self
+                .copy_data_range_from(source, source_offset, offset, len)
+                .await;
+        }
+        // Both blobs can have different header sizes. If the data offsets don't line up, the blob store falls back to copying.
+        let source_data_offset = source.data_offset();
+        let data_offset = self.data_offset();
         self.blob
-            .write(source, offset + layout::fsblob_header::SIZE.unwrap() as u64)
+            .clone_range_from(
+                &mut source.blob,
+                source_offset + source_data_offset,
+                offset + data_offset,
+                len,
+            )
             .await
     }
 
+    async fn copy_data_range_from(
+        &mut self,
+        source: &mut BaseBlob<B>,
+        source_offset: u64,
+        offset: u64,
+        len: u64,
+    ) -> Result<u64> {
+        let mut buffer = vec![0; WRITE_DATA_CHUNK_SIZE];
+        let mut num_copied = 0;
+        while num_copied < len {
+            let chunk_len = (len - num_copied).min(WRITE_DATA_CHUNK_SIZE as u64) as usize;
+            let num_read = source
+                .try_read_data(&mut buffer[..chunk_len], source_offset + num_copied)
+                .await?;
+            if num_read == 0 {
+                break;
+            }
+            self.write_data(&buffer[..num_read], offset + num_copied)
+                .await?;
+            num_copied += num_read as u64;
+        }
+        Ok(num_copied)
+    }
+
     pub async fn flush(&mut self) -> Result<()> {
         self.blob.flush().await
     }
@@ -193,6 +437,8 @@ where
             .field("blob_id", &self.blob_id())
             .field("blob_type", &self.blob_type())
             .field("parent", &self.parent())
+            .field("num_links", &self.num_links)
+            .field("link_metadata_blob_id", &self.link_metadata_blob_id)
             .finish()
     }
 }
@@ -210,17 +456,55 @@ where
     }
 }
 
-fn create_data_for_new_blob(blob_type: layout::BlobType, parent: &BlobId, data: &[u8]) -> Data {
+async fn read_link_count_header<B>(blob: &mut B) -> Result<(u32, Option<BlobId>)>
+where
+    B: Blob,
+{
+    let mut header = [0; layout::fsblob_link_count_header::SIZE.unwrap()];
+    blob.read(&mut header, layout::fsblob_header::SIZE.unwrap() as u64)
+        .await?;
+    let header = layout::fsblob_link_count_header::View::new(header);
+    let num_links = header.num_links().read();
+    ensure!(num_links >= 1, "Loaded FsBlob with a link count of 0");
+    let link_metadata_blob_id = *header.link_metadata_blob_id();
+    let link_metadata_blob_id = (link_metadata_blob_id != [0; BLOBID_LEN])
+        .then(|| BlobId::from_array(&link_metadata_blob_id));
+    Ok((num_links, link_metadata_blob_id))
+}
+
+fn create_data_for_new_blob(
+    blob_type: layout::BlobType,
+    parent: &BlobId,
+    data: &[u8],
+    with_link_count: bool,
+) -> Data {
+    let header_size = layout::fsblob_header::SIZE.unwrap();
+    let link_count_header_size = if with_link_count {
+        layout::fsblob_link_count_header::SIZE.unwrap()
+    } else {
+        0
+    };
     // TODO No need to zero-fill header
-    let blob_data: Data = vec![0; layout::fsblob_header::SIZE.unwrap() + data.len()].into();
+    let blob_data: Data = vec![0; header_size + link_count_header_size + data.len()].into();
     let mut view = layout::fsblob::View::new(blob_data);
     view.header_mut()
         .format_version_header_mut()
-        .write(layout::FORMAT_VERSION_HEADER);
+        .write(if with_link_count {
+            FORMAT_VERSION_HEADER_WITH_LINK_COUNT
+        } else {
+            FORMAT_VERSION_HEADER
+        });
     view.header_mut().blob_type_mut().write(blob_type);
     view.header_mut()
         .parent_mut()
         .copy_from_slice(parent.data());
-    view.data_mut().copy_from_slice(data);
+    if with_link_count {
+        let mut link_count_header = layout::fsblob_link_count_header::View::new(
+            &mut view.data_mut()[..link_count_header_size],
+        );
+        // A new blob has exactly one link and was never hard linked
+        link_count_header.num_links_mut().write(1);
+    }
+    view.data_mut()[link_count_header_size..].copy_from_slice(data);
     view.into_storage()
 }
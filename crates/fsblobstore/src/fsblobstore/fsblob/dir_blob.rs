@@ -8,7 +8,7 @@ use super::base_blob::BaseBlob;
 use super::layout::BlobType;
 use crate::{
     fsblobstore::{
-        RemoveError, RenameError,
+        FsFormat, RemoveError, RenameError, ReplaceError,
         fsblob::{
             AddError, UpdateTimestampError,
             dir_entries::{
@@ -25,7 +25,7 @@ use cryfs_utils::{
     path::{PathComponent, PathComponentBuf},
 };
 
-use super::dir_entries::{DirEntry, DirEntryList, EntryType};
+use super::dir_entries::{DirEntry, DirEntryList, EntryType, Xattrs};
 
 pub const DIR_LSTAT_SIZE: u64 = 4096;
 pub const MODE_NEW_SYMLINK: Mode = Mode::zero()
@@ -59,7 +59,8 @@ where
     pub(super) async fn new(
         mut blob: AsyncDropGuard<BaseBlob<B>>,
     ) -> Result<AsyncDropGuard<DirBlob<B>>> {
-        let entries = DirEntryList::deserialize(&mut blob).await;
+        let format = blob.format();
+        let entries = DirEntryList::deserialize(&mut *blob, format).await;
         let entries = match entries {
             Ok(entries) => entries,
             Err(e) => {
@@ -70,16 +71,22 @@ where
         Ok(AsyncDropGuard::new(Self { blob, entries }))
     }
 
-    pub async fn create_blob(blobstore: &B, parent: &BlobId) -> Result<AsyncDropGuard<DirBlob<B>>> {
+    pub async fn create_blob(
+        blobstore: &B,
+        blob_id: &BlobId,
+        parent: &BlobId,
+        format: FsFormat,
+    ) -> Result<AsyncDropGuard<DirBlob<B>>> {
         Ok(AsyncDropGuard::new(Self {
-            blob: BaseBlob::create(blobstore, BlobType::Dir, parent, &[]).await?,
-            entries: DirEntryList::empty(),
+            blob: BaseBlob::create(blob_id, blobstore, BlobType::Dir, parent, &[], format).await?,
+            entries: DirEntryList::empty(format),
         }))
     }
 
     pub async fn create_root_dir_blob(
         blobstore: &B,
         root_blob_id: &BlobId,
+        format: FsFormat,
     ) -> Result<AsyncDropGuard<DirBlob<B>>> {
         let mut blob = BaseBlob::try_create_with_id(
             root_blob_id,
@@ -87,23 +94,45 @@ where
             BlobType::Dir,
             &BlobId::zero(),
             &[],
+            format,
         )
         .await?
         .ok_or_else(|| anyhow!("Root blob {:?} already exists", root_blob_id))?;
         blob.flush().await?; // Don't cache, but directly write the root blob (this    causes it to fail early if the vault directory is not accessible)
         Ok(AsyncDropGuard::new(Self {
             blob,
-            entries: DirEntryList::empty(),
+            entries: DirEntryList::empty(format),
         }))
     }
 
+    /// Iterate over all entries. This has to load the whole directory, so prefer looking up entries by name.
     // TODO DoubleEndedIterator + FusedIterator
-    pub fn entries(&self) -> impl Iterator<Item = &DirEntry> + ExactSizeIterator + use<'_, B> {
-        self.entries.iter()
+    pub async fn entries(
+        &mut self,
+    ) -> Result<impl Iterator<Item = &DirEntry> + ExactSizeIterator + use<'_, B>> {
+        self.entries.iter(&mut *self.blob).await
+    }
+
+    /// Whether the blob is stored in the bucket layout for directories, which CryFS 1.0 can't read.
+    pub fn is_stored_in_bucket_layout(&self) -> bool {
+        self.entries.is_stored_in_bucket_layout()
+    }
+
+    /// Load all entries so that they can be accessed through [Self::loaded_entries]
+    pub async fn load_all_entries(&mut self) -> Result<()> {
+        self.entries.load_all(&mut *self.blob).await
+    }
+
+    /// Iterate over the entries without loading anything, or return `None` if [Self::load_all_entries] wasn't called
+    /// and some entries aren't loaded yet.
+    pub fn loaded_entries(
+        &self,
+    ) -> Option<impl Iterator<Item = &DirEntry> + ExactSizeIterator + use<'_, B>> {
+        self.entries.loaded_iter()
     }
 
     pub async fn writeback(&mut self) -> Result<SerializeIfDirtyResult> {
-        self.entries.serialize_if_dirty(&mut self.blob).await
+        self.entries.serialize_if_dirty(&mut *self.blob).await
     }
 
     pub async fn flush(&mut self) -> Result<()> {
@@ -128,20 +157,23 @@ where
         self.blob.set_parent(new_parent).await
     }
 
-    pub fn num_entries(&self) -> usize {
-        self.entries.num_entries()
+    pub async fn num_entries(&mut self) -> Result<usize> {
+        self.entries.num_entries(&mut *self.blob).await
     }
 
-    pub fn entry_by_id(&self, id: &BlobId) -> Option<&DirEntry> {
-        self.entries.get_by_id(id)
+    pub async fn entry_by_id(&mut self, id: &BlobId) -> Result<Option<&DirEntry>> {
+        self.entries.get_by_id(&mut *self.blob, id).await
     }
 
-    pub fn entry_by_name(&self, name: &PathComponent) -> Option<&DirEntry> {
-        self.entries.get_by_name(name)
+    pub async fn entry_by_name(&mut self, name: &PathComponent) -> Result<Option<&DirEntry>> {
+        self.entries.get_by_name(&mut *self.blob, name).await
     }
 
-    pub fn entry_by_name_mut(&mut self, name: &PathComponent) -> Option<&mut DirEntry> {
-        self.entries.get_by_name_mut(name)
+    pub async fn entry_by_name_mut(
+        &mut self,
+        name: &PathComponent,
+    ) -> Result<Option<&mut DirEntry>> {
+        self.entries.get_by_name_mut(&mut *self.blob, name).await
     }
 
     pub async fn rename_entry_by_name<E>(
@@ -152,18 +184,21 @@ where
         on_overwritten: impl AsyncFnOnce(EntryType, EntryType, &BlobId) -> Result<(), E>,
     ) -> Result<(), RenameError<E>> {
         self.entries
-            .rename_by_name(old_name, new_name, on_overwritten)
+            .rename_by_name(&mut *self.blob, old_name, new_name, on_overwritten)
             .await
     }
 
-    pub fn update_modification_timestamp_of_entry(
+    pub async fn replace_entry_by_name(
         &mut self,
-        blob_id: &BlobId,
-    ) -> Result<(), UpdateTimestampError> {
-        self.entries.update_modification_timestamp(blob_id)
+        name: &PathComponent,
+        replacement: &DirEntry,
+    ) -> Result<DirEntry, ReplaceError> {
+        self.entries
+            .replace_by_name(&mut *self.blob, name, replacement)
+            .await
     }
 
-    pub fn set_attr_of_entry_by_name<'s>(
+    pub async fn set_attr_of_entry_by_name<'s>(
         &'s mut self,
         name: &PathComponent,
         mode: Option<Mode>,
@@ -173,34 +208,43 @@ where
         mtime: Option<SystemTime>,
     ) -> Result<&'s DirEntry, SetAttrError> {
         self.entries
-            .set_attr_by_name(name, mode, uid, gid, atime, mtime)
+            .set_attr_by_name(&mut *self.blob, name, mode, uid, gid, atime, mtime)
+            .await
     }
 
-    pub fn update_modification_timestamp_by_name(
+    pub async fn update_modification_timestamp_by_name(
         &mut self,
         name: &PathComponent,
     ) -> Result<(), UpdateTimestampError> {
-        self.entries.update_modification_timestamp_by_name(name)
+        self.entries
+            .update_modification_timestamp_by_name(&mut *self.blob, name)
+            .await
     }
 
-    pub fn maybe_update_access_timestamp_of_entry(
+    pub async fn maybe_update_access_timestamp_by_name(
         &mut self,
-        blob_id: &BlobId,
+        name: &PathComponent,
         atime_update_behavior: impl AtimeUpdateBehavior,
     ) -> Result<(), UpdateTimestampError> {
         self.entries
-            .maybe_update_access_timestamp(blob_id, atime_update_behavior)
+            .maybe_update_access_timestamp_by_name(&mut *self.blob, name, atime_update_behavior)
+            .await
     }
 
-    pub fn remove_entry_by_name(&mut self, name: &PathComponent) -> Result<DirEntry, RemoveError> {
-        self.entries.remove_by_name(name)
+    pub async fn remove_entry_by_name(
+        &mut self,
+        name: &PathComponent,
+    ) -> Result<DirEntry, RemoveError> {
+        self.entries.remove_by_name(&mut *self.blob, name).await
     }
 
-    pub fn remove_entry_by_id_if_exists(&mut self, blob_id: &BlobId) {
-        self.entries.remove_by_id_if_exists(blob_id);
+    pub async fn remove_entry_by_id_if_exists(&mut self, blob_id: &BlobId) -> Result<()> {
+        self.entries
+            .remove_by_id_if_exists(&mut *self.blob, blob_id)
+            .await
     }
 
-    pub fn add_entry_dir(
+    pub async fn add_entry_dir(
         &mut self,
         name: PathComponentBuf,
         id: BlobId,
@@ -210,19 +254,22 @@ where
         last_access_time: SystemTime,
         last_modification_time: SystemTime,
     ) -> Result<(), AddError> {
-        self.entries.add(
-            name,
-            id,
-            EntryType::Dir,
-            mode,
-            uid,
-            gid,
-            last_access_time,
-            last_modification_time,
-        )
+        self.entries
+            .add(
+                &mut *self.blob,
+                name,
+                id,
+                EntryType::Dir,
+                mode,
+                uid,
+                gid,
+                last_access_time,
+                last_modification_time,
+            )
+            .await
     }
 
-    pub fn add_entry_file(
+    pub async fn add_entry_file(
         &mut self,
         name: PathComponentBuf,
         id: BlobId,
@@ -232,19 +279,22 @@ where
         last_access_time: SystemTime,
         last_modification_time: SystemTime,
     ) -> Result<(), AddError> {
-        self.entries.add(
-            name,
-            id,
-            EntryType::File,
-            mode,
-            uid,
-            gid,
-            last_access_time,
-            last_modification_time,
-        )
+        self.entries
+            .add(
+                &mut *self.blob,
+                name,
+                id,
+                EntryType::File,
+                mode,
+                uid,
+                gid,
+                last_access_time,
+                last_modification_time,
+            )
+            .await
     }
 
-    pub fn add_entry_symlink(
+    pub async fn add_entry_symlink(
         &mut self,
         name: PathComponentBuf,
         id: BlobId,
@@ -253,16 +303,61 @@ where
         last_access_time: SystemTime,
         last_modification_time: SystemTime,
     ) -> Result<(), AddError> {
-        self.entries.add(
-            name,
-            id,
-            EntryType::Symlink,
-            MODE_NEW_SYMLINK,
-            uid,
-            gid,
-            last_access_time,
-            last_modification_time,
-        )
+        self.entries
+            .add(
+                &mut *self.blob,
+                name,
+                id,
+                EntryType::Symlink,
+                MODE_NEW_SYMLINK,
+                uid,
+                gid,
+                last_access_time,
+                last_modification_time,
+            )
+            .await
+    }
+
+    /// Add a fifo, socket or device node. Those aren't backed by a blob, `id` only identifies the entry.
+    /// `rdev` is the device number for device nodes and must be zero for other entry types.
+    pub async fn add_entry_special(
+        &mut self,
+        name: PathComponentBuf,
+        id: BlobId,
+        entry_type: EntryType,
+        mode: Mode,
+        uid: Uid,
+        gid: Gid,
+        rdev: u32,
+        last_access_time: SystemTime,
+        last_modification_time: SystemTime,
+    ) -> Result<(), AddError> {
+        self.entries
+            .add_special(
+                &mut *self.blob,
+                name,
+                id,
+                entry_type,
+                mode,
+                uid,
+                gid,
+                rdev,
+                last_access_time,
+                last_modification_time,
+            )
+            .await
+    }
+
+    /// Add a hard link named `name` to the blob referenced by `source`, which can be an entry of this or of another directory.
+    /// The new entry starts out with the same metadata as `source`.
+    // TODO Metadata is stored in the dir entries, so changing the metadata through one link doesn't change it for the other links.
+    //      We should probably store metadata for hard linked blobs in the blob itself.
+    pub async fn add_entry_link(
+        &mut self,
+        name: PathComponentBuf,
+        source: &DirEntry,
+    ) -> Result<(), AddError> {
+        self.entries.add_link(&mut *self.blob, name, source).await
     }
 
     pub async fn add_or_overwrite_entry<E>(
@@ -275,10 +370,13 @@ where
         gid: Gid,
         last_access_time: SystemTime,
         last_modification_time: SystemTime,
+        xattrs: Xattrs,
+        rdev: u32,
         on_overwritten: impl AsyncFnOnce(EntryType, EntryType, &BlobId) -> Result<(), E>,
     ) -> Result<(), AddOrOverwriteError<E>> {
         self.entries
             .add_or_overwrite(
+                &mut *self.blob,
                 name,
                 id,
                 entry_type,
@@ -287,6 +385,8 @@ where
                 gid,
                 last_access_time,
                 last_modification_time,
+                xattrs,
+                rdev,
                 on_overwritten,
             )
             .await
@@ -356,3 +456,83 @@ where
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use byte_unit::Byte;
+    use cryfs_blobstore::BlobStoreOnBlocks;
+    use cryfs_blockstore::{
+        InMemoryBlockStore, LLSharedBlockStore, LLTrackingBlockStore, LockingBlockStore,
+    };
+    use std::time::UNIX_EPOCH;
+
+    use super::*;
+    use crate::fsblobstore::{FlushBehavior, FsBlobStore};
+
+    const NUM_ENTRIES: usize = 10_000;
+
+    fn name(index: usize) -> PathComponentBuf {
+        PathComponentBuf::try_from_string(format!("entry_{index}")).unwrap()
+    }
+
+    fn blob_id(index: usize) -> BlobId {
+        BlobId::from_hex(&format!("{index:032x}")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn looking_up_an_entry_only_loads_a_few_blocks() {
+        let mut blockstore =
+            LLSharedBlockStore::new(LLTrackingBlockStore::new(InMemoryBlockStore::new()));
+        let mut fsblobstore = FsBlobStore::new(
+            BlobStoreOnBlocks::new(
+                LockingBlockStore::new(LLSharedBlockStore::clone(&blockstore)),
+                Byte::from_u64(4096),
+            )
+            .await
+            .unwrap(),
+            FsFormat::Extended,
+        );
+
+        let mut blob = fsblobstore
+            .create_dir_blob(&blob_id(0), &BlobId::zero(), FlushBehavior::DontFlush)
+            .await
+            .unwrap();
+        let dir_blob_id = blob.blob_id();
+        let dir = blob.as_dir_mut().unwrap();
+        for index in 0..NUM_ENTRIES {
+            dir.add_entry_file(
+                name(index),
+                blob_id(index + 1),
+                Mode::from(0o644),
+                Uid::from(1000),
+                Gid::from(1000),
+                UNIX_EPOCH,
+                UNIX_EPOCH,
+            )
+            .await
+            .unwrap();
+        }
+        let num_blocks = dir.num_nodes().await.unwrap();
+        blob.async_drop().await.unwrap();
+        fsblobstore.clear_cache_slow().await.unwrap();
+        blockstore.get_and_reset_counts();
+
+        let mut blob = fsblobstore.load(&dir_blob_id).await.unwrap().unwrap();
+        let dir = blob.as_dir_mut().unwrap();
+        let entry = dir.entry_by_name(&name(5000)).await.unwrap().unwrap();
+        assert_eq!(&blob_id(5001), entry.blob_id());
+        let counts = blockstore.get_and_reset_counts();
+        // Only the root node, the inner nodes on the paths to the header, the bucket table and the bucket,
+        // and the leaves storing those need to be loaded, not the whole directory.
+        assert!(num_blocks > 100);
+        assert!(
+            counts.load <= 10,
+            "Loaded {} of the {num_blocks} blocks of the directory",
+            counts.load,
+        );
+
+        blob.async_drop().await.unwrap();
+        fsblobstore.async_drop().await.unwrap();
+        blockstore.async_drop().await.unwrap();
+    }
+}
@@ -0,0 +1,312 @@
+//! On-disk layout of directory blobs.
+//!
+//! Entries are grouped into buckets by a hash of their name (extendible hashing), and each bucket occupies
+//! a fixed-size slot in the blob. Adding, removing or modifying an entry only rewrites the slot of the bucket
+//! that entry lives in, so changes to huge directories don't have to rewrite the whole directory blob.
+//! When a bucket outgrows its slot, only that bucket is split into two and the new bucket is appended to the blob.
+//!
+//! A bucket with local depth `d` holds the entries whose name hash has `prefix` as its lowest `d` bits.
+//! The depth and prefix of all buckets are stored in a small bucket table at the start of the blob, so loading a
+//! directory only needs to read that table. Bucket slots are only read once an entry in them is needed.
+//! The mapping from hashes to buckets isn't stored, it is rebuilt from the bucket table when loading.
+//!
+//! Layout:
+//! - [BucketLayoutHeader]
+//! - Bucket table with `table_capacity` [BucketDescriptor]s, of which the first `num_buckets` are used.
+//!   The unused ones leave room for bucket splits without having to move the slots.
+//! - `num_buckets` slots of `bucket_size` bytes each. A slot starts with a [BucketHeader],
+//!   followed by the serialized entries, followed by padding.
+//!
+//! Directory blobs written before this layout existed store a plain list of serialized entries, ordered by blob id.
+//! Those are still readable and get migrated to the bucket layout the next time the directory is modified.
+//! CryFS 1.0 only understands that legacy layout, so file systems in the
+//! [FsFormat::Cryfs1Compatible](crate::fsblobstore::FsFormat::Cryfs1Compatible) format keep using it and never get migrated.
+
+use anyhow::{Result, ensure};
+use binrw::{BinRead, BinWrite, binrw};
+use std::io::Cursor;
+
+use super::entry::DirEntry;
+use cryfs_utils::path::PathComponent;
+
+/// First byte of a directory blob in the bucket layout. Directory blobs in the legacy layout start
+/// with the entry type byte of their first entry, which can never be 0xFF.
+const BUCKET_LAYOUT_MARKER: u8 = 0xFF;
+const BUCKET_LAYOUT_VERSION: u8 = 1;
+
+/// Slot size used for new directories. Slots only get larger if a bucket can't be split any further,
+/// e.g. because a single entry has large extended attributes.
+pub const DEFAULT_BUCKET_SIZE: u32 = 1024;
+
+/// Buckets are identified by the lowest `local_depth` bits of the name hash. The in-memory mapping from hashes to buckets
+/// has `2^local_depth` elements for the deepest bucket, so we limit the depth and grow the bucket size instead if a bucket
+/// at this depth overflows.
+pub const MAX_LOCAL_DEPTH: u8 = 20;
+
+/// Smallest bucket table written for new directories
+const MIN_TABLE_CAPACITY: u32 = 16;
+
+#[binrw]
+// The magic is BUCKET_LAYOUT_MARKER
+#[brw(little, magic = 0xFFu8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BucketLayoutHeader {
+    #[br(assert(version == BUCKET_LAYOUT_VERSION, "Unsupported directory layout version {version}"))]
+    #[bw(calc = BUCKET_LAYOUT_VERSION)]
+    version: u8,
+    pub bucket_size: u32,
+    pub num_buckets: u32,
+    pub table_capacity: u32,
+}
+
+impl BucketLayoutHeader {
+    pub const LEN: u64 = 1 + 1 + 4 + 4 + 4;
+
+    /// Header for a blob that is written from scratch. The bucket table gets some room to grow.
+    pub fn new(bucket_size: u32, num_buckets: u32) -> Self {
+        Self {
+            bucket_size,
+            num_buckets,
+            table_capacity: num_buckets.next_power_of_two().max(MIN_TABLE_CAPACITY),
+        }
+    }
+
+    /// Header for a blob whose bucket table can be updated in place
+    pub fn with_num_buckets(&self, num_buckets: u32) -> Self {
+        assert!(num_buckets <= self.table_capacity);
+        Self {
+            num_buckets,
+            ..*self
+        }
+    }
+
+    pub fn deserialize(data: &[u8]) -> Result<Self> {
+        let header = Self::read(&mut Cursor::new(data))?;
+        ensure!(
+            u64::from(header.bucket_size) >= BucketHeader::LEN,
+            "Bucket size {} is too small",
+            header.bucket_size,
+        );
+        ensure!(header.num_buckets >= 1, "Directory blob has no buckets");
+        ensure!(
+            header.num_buckets <= header.table_capacity,
+            "Directory blob has {} buckets but its bucket table only has room for {}",
+            header.num_buckets,
+            header.table_capacity,
+        );
+        Ok(header)
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut writer = Cursor::new(Vec::new());
+        self.write(&mut writer)
+            .expect("Writing to a Vec<u8> can't fail");
+        writer.into_inner()
+    }
+
+    pub fn blob_size(&self) -> u64 {
+        self.slots_offset() + u64::from(self.num_buckets) * u64::from(self.bucket_size)
+    }
+
+    pub fn table_len(&self) -> u64 {
+        u64::from(self.num_buckets) * BucketDescriptor::LEN
+    }
+
+    pub fn descriptor_offset(&self, bucket_index: usize) -> u64 {
+        Self::LEN + bucket_index as u64 * BucketDescriptor::LEN
+    }
+
+    fn slots_offset(&self) -> u64 {
+        Self::LEN + u64::from(self.table_capacity) * BucketDescriptor::LEN
+    }
+
+    pub fn bucket_offset(&self, bucket_index: usize) -> u64 {
+        self.slots_offset() + bucket_index as u64 * u64::from(self.bucket_size)
+    }
+}
+
+/// Entry of the bucket table
+#[binrw]
+#[brw(little)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BucketDescriptor {
+    pub local_depth: u8,
+    pub prefix: u32,
+}
+
+impl BucketDescriptor {
+    pub const LEN: u64 = 1 + 4;
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut writer = Cursor::new(Vec::new());
+        self.write(&mut writer)
+            .expect("Writing to a Vec<u8> can't fail");
+        writer.into_inner()
+    }
+
+    /// Deserialize the used part of a bucket table
+    pub fn deserialize_table(data: &[u8]) -> Result<Vec<Self>> {
+        ensure!(
+            data.len() as u64 % Self::LEN == 0,
+            "Bucket table has an invalid size of {} bytes",
+            data.len(),
+        );
+        let mut reader = Cursor::new(data);
+        (0..data.len() as u64 / Self::LEN)
+            .map(|_| {
+                let descriptor = Self::read(&mut reader)?;
+                ensure!(
+                    descriptor.local_depth <= MAX_LOCAL_DEPTH,
+                    "Bucket has local depth {} but the maximum is {MAX_LOCAL_DEPTH}",
+                    descriptor.local_depth,
+                );
+                ensure!(
+                    descriptor.prefix & !depth_mask(descriptor.local_depth) == 0,
+                    "Bucket prefix {:#x} has more bits than its local depth {}",
+                    descriptor.prefix,
+                    descriptor.local_depth,
+                );
+                Ok(descriptor)
+            })
+            .collect()
+    }
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct BucketHeader {
+    // Number of bytes used by the serialized entries following this header
+    entries_len: u32,
+}
+
+impl BucketHeader {
+    const LEN: u64 = 4;
+}
+
+pub fn is_bucket_layout(data: &[u8]) -> bool {
+    data.first() == Some(&BUCKET_LAYOUT_MARKER)
+}
+
+/// Hash of an entry name, used to assign entries to buckets. This is part of the on-disk format and must never change.
+pub fn name_hash(name: &PathComponent) -> u32 {
+    // FNV-1a, followed by the murmur3 finalizer because we only use the lowest bits and those are weak in FNV.
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+    let mut hash = name.as_str().bytes().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+    });
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^= hash >> 33;
+    hash as u32
+}
+
+/// Mask selecting the lowest `depth` bits of a hash
+pub fn depth_mask(depth: u8) -> u32 {
+    assert!(depth <= MAX_LOCAL_DEPTH);
+    (1 << depth) - 1
+}
+
+#[derive(Debug)]
+pub struct Bucket {
+    pub local_depth: u8,
+    pub prefix: u32,
+    pub entries: Vec<DirEntry>,
+}
+
+impl Bucket {
+    pub fn empty(descriptor: BucketDescriptor) -> Self {
+        Self {
+            local_depth: descriptor.local_depth,
+            prefix: descriptor.prefix,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn descriptor(&self) -> BucketDescriptor {
+        BucketDescriptor {
+            local_depth: self.local_depth,
+            prefix: self.prefix,
+        }
+    }
+
+    pub fn contains_hash(&self, hash: u32) -> bool {
+        hash & depth_mask(self.local_depth) == self.prefix
+    }
+
+    /// Serialize the bucket, including its header but without padding. Depth and prefix are stored in the bucket table.
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        let mut writer = Cursor::new(Vec::new());
+        writer.set_position(BucketHeader::LEN);
+        for entry in &self.entries {
+            entry.serialize(&mut writer)?;
+        }
+        let entries_len = u32::try_from(writer.position() - BucketHeader::LEN)?;
+        writer.set_position(0);
+        BucketHeader { entries_len }.write(&mut writer)?;
+        Ok(writer.into_inner())
+    }
+
+    /// Deserialize a bucket from its slot, ignoring any padding after its entries.
+    pub fn deserialize(descriptor: BucketDescriptor, slot: &[u8]) -> Result<Self> {
+        let header = BucketHeader::read(&mut Cursor::new(slot))?;
+        let entries_begin = BucketHeader::LEN as usize;
+        let entries_end = entries_begin + header.entries_len as usize;
+        ensure!(
+            entries_end <= slot.len(),
+            "Bucket claims to have {} bytes of entries but the slot only has {} bytes",
+            header.entries_len,
+            slot.len() - entries_begin,
+        );
+        let bucket = Self {
+            local_depth: descriptor.local_depth,
+            prefix: descriptor.prefix,
+            entries: deserialize_entry_list(&slot[entries_begin..entries_end])?,
+        };
+        for entry in &bucket.entries {
+            ensure!(
+                bucket.contains_hash(name_hash(entry.name())),
+                "Entry {} is stored in the wrong bucket",
+                entry.name(),
+            );
+        }
+        Ok(bucket)
+    }
+
+    /// Number of bytes this bucket needs in its slot
+    pub fn serialized_len(&self) -> Result<u64> {
+        let mut len = BucketHeader::LEN;
+        for entry in &self.entries {
+            len += entry.serialized_len()? as u64;
+        }
+        Ok(len)
+    }
+}
+
+/// Serialize a plain list of entries. This is the whole blob content in the legacy layout.
+pub fn serialize_entry_list<'a>(
+    entries: impl IntoIterator<Item = &'a DirEntry>,
+) -> Result<Vec<u8>> {
+    let mut writer = Cursor::new(Vec::new());
+    for entry in entries {
+        entry.serialize(&mut writer)?;
+    }
+    Ok(writer.into_inner())
+}
+
+/// Deserialize a plain list of entries. This is the whole blob content in the legacy layout.
+pub fn deserialize_entry_list(data: &[u8]) -> Result<Vec<DirEntry>> {
+    let len = data.len() as u64;
+    let mut entries = Vec::new();
+    let mut reader = Cursor::new(data);
+    // TODO Use reader.is_empty() once that is stabilized
+    while reader.position() < len {
+        let entry = DirEntry::deserialize(&mut reader)?;
+        entries.push(entry);
+    }
+    assert_eq!(reader.position(), len, "Did not read all data.");
+    Ok(entries)
+}
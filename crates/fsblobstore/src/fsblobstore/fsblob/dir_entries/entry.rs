@@ -1,11 +1,13 @@
 use anyhow::Result;
-use binrw::{BinRead, BinResult, BinWrite, Endian};
+use binrw::{BinRead, BinResult, BinWrite, Endian, binrw};
 use derive_more::{Display, Error};
 use std::fmt::Debug;
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
 use std::num::NonZeroU8;
 use std::time::SystemTime;
 
+use super::AtimeUpdateBehavior;
+use super::xattrs::{SetXattrError, Xattrs};
 use crate::utils::fs_types::{Gid, Mode, Uid};
 use cryfs_blobstore::BlobId;
 use cryfs_utils::{
@@ -21,11 +23,94 @@ pub enum EntryType {
     Dir = 0x00,
     File = 0x01,
     Symlink = 0x02,
+    Fifo = 0x03,
+    Socket = 0x04,
+    CharDevice = 0x05,
+    BlockDevice = 0x06,
 }
 
-#[derive(Clone, BinRead, BinWrite, Debug)]
+impl EntryType {
+    /// Fifos, sockets and device nodes don't have any content, so they aren't backed by a blob.
+    /// Their entries still get a unique blob id so they can be addressed like other entries,
+    /// but no blob with that id exists.
+    pub const fn has_blob(self) -> bool {
+        match self {
+            Self::Dir | Self::File | Self::Symlink => true,
+            Self::Fifo | Self::Socket | Self::CharDevice | Self::BlockDevice => false,
+        }
+    }
+
+    /// Device nodes store their device number (rdev) in the entry.
+    pub const fn is_device(self) -> bool {
+        matches!(self, Self::CharDevice | Self::BlockDevice)
+    }
+}
+
+/// Set in the entry type byte if the entry has extended attributes. Those are then stored after the blob id.
+/// Entries without extended attributes don't set this bit and are serialized exactly like before
+/// extended attributes were supported.
+const ENTRY_TYPE_FLAG_HAS_XATTRS: u8 = 0x80;
+
+#[derive(Clone, Copy, Debug)]
+struct EntryTypeAndFlags {
+    entry_type: EntryType,
+    has_xattrs: bool,
+}
+
+impl BinRead for EntryTypeAndFlags {
+    type Args<'a> = ();
+
+    fn read_options<R: Read + Seek>(reader: &mut R, endian: Endian, _: ()) -> BinResult<Self> {
+        let pos = reader.stream_position()?;
+        let byte = u8::read_options(reader, endian, ())?;
+        let entry_type = match byte & !ENTRY_TYPE_FLAG_HAS_XATTRS {
+            0x00 => EntryType::Dir,
+            0x01 => EntryType::File,
+            0x02 => EntryType::Symlink,
+            0x03 => EntryType::Fifo,
+            0x04 => EntryType::Socket,
+            0x05 => EntryType::CharDevice,
+            0x06 => EntryType::BlockDevice,
+            entry_type => {
+                return Err(binrw::Error::AssertFail {
+                    pos,
+                    message: format!("Invalid entry type {entry_type}"),
+                });
+            }
+        };
+        Ok(Self {
+            entry_type,
+            has_xattrs: byte & ENTRY_TYPE_FLAG_HAS_XATTRS != 0,
+        })
+    }
+}
+
+impl BinWrite for EntryTypeAndFlags {
+    type Args<'a> = ();
+
+    fn write_options<W: Write + Seek>(
+        &self,
+        writer: &mut W,
+        endian: Endian,
+        _: (),
+    ) -> BinResult<()> {
+        let mut byte = self.entry_type as u8;
+        if self.has_xattrs {
+            byte |= ENTRY_TYPE_FLAG_HAS_XATTRS;
+        }
+        byte.write_options(writer, endian, ())
+    }
+}
+
+#[binrw]
+#[derive(Clone, Debug)]
 #[brw(little)]
 pub struct DirEntryImpl {
+    #[br(temp)]
+    #[bw(calc = EntryTypeAndFlags { entry_type: *entry_type, has_xattrs: !xattrs.is_empty() })]
+    entry_type_and_flags: EntryTypeAndFlags,
+    #[br(calc = entry_type_and_flags.entry_type)]
+    #[bw(ignore)]
     entry_type: EntryType,
     mode: Mode,
     uid: Uid,
@@ -43,6 +128,12 @@ pub struct DirEntryImpl {
     #[bw(write_with = write_path_component)]
     name: PathComponentBuf,
     blob_id: BlobId,
+    #[br(if(entry_type_and_flags.entry_type.is_device()))]
+    #[bw(if(entry_type.is_device()))]
+    rdev: u32,
+    #[br(if(entry_type_and_flags.has_xattrs))]
+    #[bw(if(!xattrs.is_empty()))]
+    xattrs: Xattrs,
 }
 
 #[derive(Clone, Debug)]
@@ -68,6 +159,10 @@ impl DirEntry {
             EntryType::File => mode.with_file_flag(),
             EntryType::Dir => mode.with_dir_flag(),
             EntryType::Symlink => mode.with_symlink_flag(),
+            EntryType::Fifo => mode.with_fifo_flag(),
+            EntryType::Socket => mode.with_socket_flag(),
+            EntryType::CharDevice => mode.with_char_device_flag(),
+            EntryType::BlockDevice => mode.with_block_device_flag(),
         };
         let result = Self {
             inner: DirEntryImpl {
@@ -80,6 +175,8 @@ impl DirEntry {
                 last_metadata_change_time,
                 name,
                 blob_id,
+                rdev: 0,
+                xattrs: Xattrs::default(),
             },
         };
         result.validate()?;
@@ -101,25 +198,33 @@ impl DirEntry {
         Ok(())
     }
 
+    pub fn serialized_len(&self) -> Result<usize> {
+        let mut writer = Cursor::new(Vec::new());
+        self.serialize(&mut writer)?;
+        Ok(writer.into_inner().len())
+    }
+
     fn validate(&self) -> Result<(), ValidationFailed> {
-        let is_valid = ((self.inner.entry_type == EntryType::File)
-            && self.inner.mode.has_file_flag()
-            && !self.inner.mode.has_dir_flag()
-            && !self.inner.mode.has_symlink_flag())
-            || ((self.inner.entry_type == EntryType::Dir)
-                && !self.inner.mode.has_file_flag()
-                && self.inner.mode.has_dir_flag()
-                && !self.inner.mode.has_symlink_flag())
-            || ((self.inner.entry_type == EntryType::Symlink)
-                && !self.inner.mode.has_file_flag()
-                && !self.inner.mode.has_dir_flag()
-                && self.inner.mode.has_symlink_flag());
+        // The file type bits of a mode are an enum, not a bit set, so each of these checks also ensures that no other file type is set.
+        let mode = self.inner.mode;
+        let is_valid = match self.inner.entry_type {
+            EntryType::File => mode.has_file_flag(),
+            EntryType::Dir => mode.has_dir_flag(),
+            EntryType::Symlink => mode.has_symlink_flag(),
+            EntryType::Fifo => mode.has_fifo_flag(),
+            EntryType::Socket => mode.has_socket_flag(),
+            EntryType::CharDevice => mode.has_char_device_flag(),
+            EntryType::BlockDevice => mode.has_block_device_flag(),
+        };
         if !is_valid {
             return Err(ValidationFailed::WrongModeBits {
                 entry_type: self.inner.entry_type,
-                is_file: self.inner.mode.has_file_flag(),
-                is_dir: self.inner.mode.has_dir_flag(),
-                is_symlink: self.inner.mode.has_symlink_flag(),
+                mode,
+            });
+        }
+        if !self.inner.entry_type.is_device() && self.inner.rdev != 0 {
+            return Err(ValidationFailed::RdevOnNonDevice {
+                entry_type: self.inner.entry_type,
             });
         }
         Ok(())
@@ -212,6 +317,34 @@ impl DirEntry {
         self.set_last_access_time(SystemTime::now());
     }
 
+    /// Whether reading the node at time `now` should update its access time, given the `atime_update_behavior` of the mount.
+    pub fn should_update_access_time(
+        &self,
+        atime_update_behavior: impl AtimeUpdateBehavior,
+        now: SystemTime,
+    ) -> bool {
+        let last_access_time = self.last_access_time();
+        let last_modification_time = self.last_modification_time();
+        match self.entry_type() {
+            EntryType::File
+            | EntryType::Symlink
+            | EntryType::Fifo
+            | EntryType::Socket
+            | EntryType::CharDevice
+            | EntryType::BlockDevice => atime_update_behavior
+                .should_update_atime_on_file_or_symlink_read(
+                    last_access_time,
+                    last_modification_time,
+                    now,
+                ),
+            EntryType::Dir => atime_update_behavior.should_update_atime_on_directory_read(
+                last_access_time,
+                last_modification_time,
+                now,
+            ),
+        }
+    }
+
     pub fn last_modification_time(&self) -> SystemTime {
         self.inner.last_modification_time
     }
@@ -250,10 +383,46 @@ impl DirEntry {
         self.inner.blob_id = blob_id;
         self._update_metadata_change_time();
     }
+
+    /// Device number of a char or block device. Always zero for other entry types.
+    pub fn rdev(&self) -> u32 {
+        self.inner.rdev
+    }
+
+    pub fn set_rdev(&mut self, rdev: u32) -> Result<(), ValidationFailed> {
+        let old_rdev = self.inner.rdev;
+        self.inner.rdev = rdev;
+        self.validate().inspect_err(|_| {
+            self.inner.rdev = old_rdev;
+        })
+    }
+
+    pub fn xattrs(&self) -> &Xattrs {
+        &self.inner.xattrs
+    }
+
+    pub fn set_xattrs(&mut self, xattrs: Xattrs) {
+        self.inner.xattrs = xattrs;
+        self._update_metadata_change_time();
+    }
+
+    pub fn set_xattr(&mut self, name: &str, value: &[u8]) -> Result<(), SetXattrError> {
+        self.inner.xattrs.set(name, value)?;
+        self._update_metadata_change_time();
+        Ok(())
+    }
+
+    pub fn remove_xattr(&mut self, name: &str) -> Option<Vec<u8>> {
+        let removed = self.inner.xattrs.remove(name);
+        if removed.is_some() {
+            self._update_metadata_change_time();
+        }
+        removed
+    }
 }
 
 // TODO Tests
-fn read_path_component<R: Read + Seek>(
+pub(crate) fn read_path_component<R: Read + Seek>(
     reader: &mut R,
     endian: Endian,
     _: (),
@@ -275,7 +444,7 @@ fn read_path_component<R: Read + Seek>(
 }
 
 // TODO Tests
-fn write_path_component(
+pub(crate) fn write_path_component(
     v: &PathComponentBuf,
     writer: &mut (impl Write + Seek),
     endian: Endian,
@@ -295,13 +464,144 @@ fn write_path_component(
 
 #[derive(Error, Display, Debug)]
 pub enum ValidationFailed {
-    #[display(
-        "Wrong mode bit set. Entry type is {entry_type:?} and mode bits say is_file={is_file}, is_dir={is_dir}, is_symlink={is_symlink}"
-    )]
-    WrongModeBits {
-        entry_type: EntryType,
-        is_file: bool,
-        is_dir: bool,
-        is_symlink: bool,
-    },
+    #[display("Wrong mode bit set. Entry type is {entry_type:?} but mode is {mode:?}")]
+    WrongModeBits { entry_type: EntryType, mode: Mode },
+
+    #[display("Entry type {entry_type:?} is not a device but has a device number")]
+    RdevOnNonDevice { entry_type: EntryType },
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+
+    fn make_entry() -> DirEntry {
+        let time = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        DirEntry::new(
+            EntryType::File,
+            PathComponentBuf::try_from_string("filename".to_string()).unwrap(),
+            BlobId::from_hex("918ca6ac525c700c275615c3de0cea1b").unwrap(),
+            Mode::from(0o644),
+            Uid::from(1000),
+            Gid::from(1000),
+            time,
+            time,
+            time,
+        )
+        .unwrap()
+    }
+
+    fn serialize(entry: &DirEntry) -> Vec<u8> {
+        let mut data = Cursor::new(Vec::new());
+        entry.serialize(&mut data).unwrap();
+        data.into_inner()
+    }
+
+    fn deserialize(data: &[u8]) -> DirEntry {
+        let mut cursor = Cursor::new(data);
+        let entry = DirEntry::deserialize(&mut cursor).unwrap();
+        assert_eq!(data.len() as u64, cursor.position());
+        entry
+    }
+
+    #[test]
+    fn without_xattrs_uses_original_format() {
+        let entry = make_entry();
+        let data = serialize(&entry);
+        assert_eq!(EntryType::File as u8, data[0]);
+        assert_eq!(1 + 4 + 4 + 4 + 3 * 12 + "filename\0".len() + 16, data.len());
+        let deserialized = deserialize(&data);
+        assert_eq!(EntryType::File, deserialized.entry_type());
+        assert_eq!(entry.name(), deserialized.name());
+        assert_eq!(entry.blob_id(), deserialized.blob_id());
+        assert!(deserialized.xattrs().is_empty());
+    }
+
+    #[test]
+    fn with_xattrs_roundtrip() {
+        let mut entry = make_entry();
+        entry.set_xattr("user.a", b"value").unwrap();
+        entry.set_xattr("user.b", &[0, 1, 2]).unwrap();
+        let data = serialize(&entry);
+        assert_eq!(EntryType::File as u8 | ENTRY_TYPE_FLAG_HAS_XATTRS, data[0]);
+        let deserialized = deserialize(&data);
+        assert_eq!(EntryType::File, deserialized.entry_type());
+        assert_eq!(entry.name(), deserialized.name());
+        assert_eq!(entry.blob_id(), deserialized.blob_id());
+        assert_eq!(entry.xattrs(), deserialized.xattrs());
+    }
+
+    #[test]
+    fn removing_last_xattr_goes_back_to_original_format() {
+        let mut entry = make_entry();
+        let data_without_xattrs = serialize(&entry);
+        entry.set_xattr("user.a", b"value").unwrap();
+        assert_eq!(Some(b"value".to_vec()), entry.remove_xattr("user.a"));
+        let data = serialize(&entry);
+        assert_eq!(data_without_xattrs.len(), data.len());
+        assert_eq!(data_without_xattrs[0], data[0]);
+    }
+
+    fn make_special_entry(entry_type: EntryType) -> DirEntry {
+        let time = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        DirEntry::new(
+            entry_type,
+            PathComponentBuf::try_from_string("special".to_string()).unwrap(),
+            BlobId::from_hex("918ca6ac525c700c275615c3de0cea1b").unwrap(),
+            Mode::from(0o600),
+            Uid::from(1000),
+            Gid::from(1000),
+            time,
+            time,
+            time,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn fifo_and_socket_dont_store_rdev() {
+        for entry_type in [EntryType::Fifo, EntryType::Socket] {
+            let entry = make_special_entry(entry_type);
+            let data = serialize(&entry);
+            assert_eq!(entry_type as u8, data[0]);
+            assert_eq!(1 + 4 + 4 + 4 + 3 * 12 + "special\0".len() + 16, data.len());
+            let deserialized = deserialize(&data);
+            assert_eq!(entry_type, deserialized.entry_type());
+            assert_eq!(entry.mode(), deserialized.mode());
+            assert_eq!(0, deserialized.rdev());
+        }
+    }
+
+    #[test]
+    fn device_rdev_roundtrip() {
+        for entry_type in [EntryType::CharDevice, EntryType::BlockDevice] {
+            let mut entry = make_special_entry(entry_type);
+            entry.set_rdev(0x0801).unwrap();
+            entry.set_xattr("user.a", b"value").unwrap();
+            let data = serialize(&entry);
+            assert_eq!(entry_type as u8 | ENTRY_TYPE_FLAG_HAS_XATTRS, data[0]);
+            let deserialized = deserialize(&data);
+            assert_eq!(entry_type, deserialized.entry_type());
+            assert_eq!(entry.mode(), deserialized.mode());
+            assert_eq!(0x0801, deserialized.rdev());
+            assert_eq!(entry.xattrs(), deserialized.xattrs());
+        }
+    }
+
+    #[test]
+    fn rdev_on_non_device_is_rejected() {
+        let mut entry = make_special_entry(EntryType::Fifo);
+        assert!(entry.set_rdev(0x0801).is_err());
+        assert_eq!(0, entry.rdev());
+    }
+
+    #[test]
+    fn invalid_entry_type() {
+        let mut data = serialize(&make_entry());
+        data[0] = 0x07;
+        assert!(DirEntry::deserialize(&mut Cursor::new(data)).is_err());
+    }
 }
@@ -1,180 +1,552 @@
-use anyhow::Result;
+use anyhow::{Result, ensure};
 use cryfs_utils::async_drop::AsyncDrop;
 use derive_more::{Display, Error};
+use std::collections::BTreeSet;
 use std::fmt::Debug;
-use std::io::Cursor;
+use std::future::Future;
+use std::iter::FlatMap;
 use std::time::SystemTime;
 
 use super::super::base_blob::BaseBlob;
+use super::bucket_layout::{
+    self, Bucket, BucketDescriptor, BucketLayoutHeader, DEFAULT_BUCKET_SIZE, MAX_LOCAL_DEPTH,
+    name_hash,
+};
 use super::entry::{DirEntry, EntryType};
+use super::xattrs::Xattrs;
+use crate::fsblobstore::FsFormat;
 use crate::fsblobstore::fsblob::dir_entries::AtimeUpdateBehavior;
 use crate::fsblobstore::fsblob::dir_entries::entry::ValidationFailed;
 use crate::utils::fs_types::{Gid, Mode, Uid};
 use cryfs_blobstore::{BlobId, BlobStore};
 use cryfs_utils::path::{PathComponent, PathComponentBuf};
 
+/// Data of the blob a [DirEntryList] is stored in. [DirEntryList] only reads the parts of it that it needs.
+pub trait DirBlobData {
+    fn num_bytes(&mut self) -> impl Future<Output = Result<u64>> + Send;
+    fn read(&mut self, offset: u64, len: usize) -> impl Future<Output = Result<Vec<u8>>> + Send;
+    fn write(&mut self, offset: u64, data: &[u8]) -> impl Future<Output = Result<()>> + Send;
+    fn resize(&mut self, new_num_bytes: u64) -> impl Future<Output = Result<()>> + Send;
+}
+
+impl<B> DirBlobData for BaseBlob<B>
+where
+    B: BlobStore + Debug,
+    B::ConcreteBlob: Send + AsyncDrop<Error = anyhow::Error>,
+{
+    async fn num_bytes(&mut self) -> Result<u64> {
+        self.num_data_bytes().await
+    }
+
+    async fn read(&mut self, offset: u64, len: usize) -> Result<Vec<u8>> {
+        let mut data = vec![0; len];
+        let num_read = self.try_read_data(&mut data, offset).await?;
+        ensure!(
+            num_read == len,
+            "Tried to read {len} bytes at offset {offset} from the directory blob but only got {num_read} bytes",
+        );
+        Ok(data)
+    }
+
+    async fn write(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        self.write_data(data, offset).await
+    }
+
+    async fn resize(&mut self, new_num_bytes: u64) -> Result<()> {
+        self.resize_data(new_num_bytes).await
+    }
+}
+
+#[derive(Debug)]
+enum LazyBucket {
+    // The slot of this bucket wasn't read from the blob yet
+    NotLoaded(BucketDescriptor),
+    Loaded(Bucket),
+}
+
+impl LazyBucket {
+    fn descriptor(&self) -> BucketDescriptor {
+        match self {
+            Self::NotLoaded(descriptor) => *descriptor,
+            Self::Loaded(bucket) => bucket.descriptor(),
+        }
+    }
+
+    fn is_loaded(&self) -> bool {
+        matches!(self, Self::Loaded(_))
+    }
+}
+
 #[derive(Debug)]
 pub struct DirEntryList {
-    // TODO The implementation currently assumes that there is at most one
-    // entry with any given block id. If we add hard links, we have to change this.
-    // TODO While we don't have hardlinks, add invariant assertions that each
-    // id only exists once and that entries are ordered.
-    entries: Vec<DirEntry>,
-
-    // At least one entry was modified since last serialization
-    dirty: bool,
+    // Directories in the [FsFormat::Cryfs1Compatible] format are written in the legacy layout that CryFS 1.0 can read.
+    // Only [FsFormat::Extended] file systems use the bucket layout.
+    format: FsFormat,
+
+    // Entries grouped into buckets by the hash of their name, see [bucket_layout]. Buckets are stored in the blob in this order.
+    // A bucket is only read from the blob once an entry in it is needed.
+    // Because of hard links, there can be multiple entries with the same blob id,
+    // so lookups that need to find one specific entry need to look it up by name.
+    buckets: Vec<LazyBucket>,
+
+    // Maps the lowest `global_depth` bits of a name hash to the index of the bucket responsible for that name.
+    // Buckets with a local depth smaller than `global_depth` appear multiple times.
+    directory: Vec<usize>,
+    global_depth: u8,
+
+    // Size of each bucket slot in the blob
+    bucket_size: u32,
+
+    // Buckets with entries that were modified since last serialization
+    dirty_buckets: BTreeSet<usize>,
+
+    // Buckets whose entry in the bucket table changed since last serialization, i.e. buckets that were split or created by a split
+    dirty_descriptors: BTreeSet<usize>,
+
+    // The bucket layout the blob is currently stored in. This is `None` if the blob is empty or in the legacy layout,
+    // and in that case all buckets are loaded.
+    blob_layout: Option<BucketLayoutHeader>,
+
+    // The blob doesn't store the bucket layout yet, either because it is still in the legacy layout
+    // or because the bucket size or the bucket table had to grow. The next serialization needs to rewrite the whole blob.
+    needs_full_rewrite: bool,
 }
 
 impl DirEntryList {
-    pub fn empty() -> Self {
+    pub fn empty(format: FsFormat) -> Self {
         Self {
-            entries: Vec::new(),
-            dirty: false,
+            format,
+            buckets: vec![LazyBucket::Loaded(Bucket::empty(BucketDescriptor {
+                local_depth: 0,
+                prefix: 0,
+            }))],
+            directory: vec![0],
+            global_depth: 0,
+            bucket_size: DEFAULT_BUCKET_SIZE,
+            dirty_buckets: BTreeSet::new(),
+            dirty_descriptors: BTreeSet::new(),
+            blob_layout: None,
+            needs_full_rewrite: true,
         }
     }
 
-    pub fn get_by_name(&self, name: &PathComponent) -> Option<&DirEntry> {
-        // TODO Instead of linear search, we could use a HashMap
-        for entry in &self.entries {
-            if entry.name() == name {
-                return Some(entry);
-            }
+    /// Whether the blob is currently stored in the bucket layout, as opposed to the legacy layout that CryFS 1.0 can read.
+    /// Changes that aren't serialized yet aren't taken into account.
+    pub fn is_stored_in_bucket_layout(&self) -> bool {
+        self.blob_layout.is_some()
+    }
+
+    fn _bucket_index(&self, name: &PathComponent) -> usize {
+        self.directory[(name_hash(name) & bucket_layout::depth_mask(self.global_depth)) as usize]
+    }
+
+    fn _loaded_bucket(&self, bucket_index: usize) -> &Bucket {
+        match &self.buckets[bucket_index] {
+            LazyBucket::Loaded(bucket) => bucket,
+            LazyBucket::NotLoaded(_) => panic!("Bucket {bucket_index} isn't loaded"),
         }
-        None
     }
 
-    pub fn get_by_name_mut(&mut self, name: &PathComponent) -> Option<&mut DirEntry> {
-        for entry in &mut self.entries {
-            if entry.name() == name {
-                self.dirty = true;
-                return Some(entry);
-            }
+    fn _loaded_bucket_mut(&mut self, bucket_index: usize) -> &mut Bucket {
+        match &mut self.buckets[bucket_index] {
+            LazyBucket::Loaded(bucket) => bucket,
+            LazyBucket::NotLoaded(_) => panic!("Bucket {bucket_index} isn't loaded"),
         }
-        None
     }
 
-    fn _get_by_name_with_index(&self, name: &PathComponent) -> Option<(usize, &DirEntry)> {
-        // TODO Instead of linear search, we could use a HashMap
-        for (index, entry) in self.entries.iter().enumerate() {
-            if entry.name() == name {
-                return Some((index, entry));
+    fn _blob_layout_of_unloaded_buckets(&self) -> BucketLayoutHeader {
+        self.blob_layout
+            .expect("Buckets that aren't loaded yet must be stored in the blob")
+    }
+
+    async fn _load_bucket(
+        &mut self,
+        blob: &mut impl DirBlobData,
+        bucket_index: usize,
+    ) -> Result<()> {
+        if let LazyBucket::NotLoaded(descriptor) = self.buckets[bucket_index] {
+            let layout = self._blob_layout_of_unloaded_buckets();
+            let slot = blob
+                .read(
+                    layout.bucket_offset(bucket_index),
+                    layout.bucket_size as usize,
+                )
+                .await?;
+            self.buckets[bucket_index] =
+                LazyBucket::Loaded(Bucket::deserialize(descriptor, &slot)?);
+        }
+        Ok(())
+    }
+
+    async fn _load_all_buckets(&mut self, blob: &mut impl DirBlobData) -> Result<()> {
+        if self.buckets.iter().all(LazyBucket::is_loaded) {
+            return Ok(());
+        }
+        // Reading all slots with one read is faster than reading them one by one
+        let layout = self._blob_layout_of_unloaded_buckets();
+        let slots_offset = layout.bucket_offset(0);
+        let slots = blob
+            .read(
+                slots_offset,
+                usize::try_from(layout.blob_size() - slots_offset)?,
+            )
+            .await?;
+        let bucket_size = layout.bucket_size as usize;
+        for (bucket_index, bucket) in self.buckets.iter_mut().enumerate() {
+            if let LazyBucket::NotLoaded(descriptor) = *bucket {
+                let offset = bucket_index * bucket_size;
+                *bucket = LazyBucket::Loaded(Bucket::deserialize(
+                    descriptor,
+                    &slots[offset..offset + bucket_size],
+                )?);
             }
         }
-        None
+        Ok(())
+    }
+
+    /// Load the bucket responsible for `name` and look for `name` in it.
+    /// Returns the bucket index and, if `name` exists, the index within the bucket.
+    async fn _find(
+        &mut self,
+        blob: &mut impl DirBlobData,
+        name: &PathComponent,
+    ) -> Result<(usize, Option<usize>)> {
+        let bucket_index = self._bucket_index(name);
+        self._load_bucket(blob, bucket_index).await?;
+        Ok((bucket_index, self._position_in_bucket(bucket_index, name)))
     }
 
-    fn _get_index_by_name(&self, name: &PathComponent) -> Option<usize> {
-        self._get_by_name_with_index(name).map(|(index, _)| index)
+    fn _position_in_bucket(&self, bucket_index: usize, name: &PathComponent) -> Option<usize> {
+        self._loaded_bucket(bucket_index)
+            .entries
+            .iter()
+            .position(|entry| entry.name() == name)
     }
 
-    fn _get_index_by_id(&self, id: &BlobId) -> Option<usize> {
-        let found = self._find_lower_bound(id);
-        if found == self.entries.len() || self.entries[found].blob_id() != id {
-            return None;
-        }
-        Some(found)
+    pub async fn get_by_name(
+        &mut self,
+        blob: &mut impl DirBlobData,
+        name: &PathComponent,
+    ) -> Result<Option<&DirEntry>> {
+        let (bucket_index, index) = self._find(blob, name).await?;
+        Ok(index.map(|index| &self._loaded_bucket(bucket_index).entries[index]))
     }
 
-    fn _find_lower_bound(&self, id: &BlobId) -> usize {
-        self._find_first_index(id, |entry| entry.blob_id() >= id)
+    pub async fn get_by_name_mut(
+        &mut self,
+        blob: &mut impl DirBlobData,
+        name: &PathComponent,
+    ) -> Result<Option<&mut DirEntry>> {
+        let (bucket_index, Some(index)) = self._find(blob, name).await? else {
+            return Ok(None);
+        };
+        self.dirty_buckets.insert(bucket_index);
+        Ok(Some(
+            &mut self._loaded_bucket_mut(bucket_index).entries[index],
+        ))
     }
 
-    fn _find_upper_bound(&self, id: &BlobId) -> usize {
-        self._find_first_index(id, |entry| entry.blob_id() > id)
+    pub async fn get_by_id(
+        &mut self,
+        blob: &mut impl DirBlobData,
+        id: &BlobId,
+    ) -> Result<Option<&DirEntry>> {
+        // Buckets are keyed by name, so lookups by id need to go through all entries. They're rare enough that this is fine.
+        Ok(self.iter(blob).await?.find(|entry| entry.blob_id() == id))
     }
 
-    fn _find_first_index(&self, hint: &BlobId, mut pred: impl FnMut(&DirEntry) -> bool) -> usize {
-        // TODO Factor out a datastructure that keeps a sorted std::vector and allows these _findLowerBound()/_findUpperBound operations using this hinted linear search
-        if self.entries.is_empty() {
-            return self.entries.len();
+    /// Load a directory from its blob. This only reads the header and the bucket table, buckets are loaded when they're needed.
+    pub async fn deserialize(blob: &mut impl DirBlobData, format: FsFormat) -> Result<Self> {
+        let num_bytes = blob.num_bytes().await?;
+        let header_data = blob
+            .read(0, num_bytes.min(BucketLayoutHeader::LEN) as usize)
+            .await?;
+        if !bucket_layout::is_bucket_layout(&header_data) {
+            // Legacy layout, or an empty directory that was never written to. The legacy layout has to be read completely.
+            // Keep it in memory in the bucket layout. In the extended format, it gets migrated on the next serialization.
+            let data = blob.read(0, usize::try_from(num_bytes)?).await?;
+            let mut result = Self::empty(format);
+            for entry in bucket_layout::deserialize_entry_list(&data)? {
+                result._add_without_marking_dirty(entry);
+            }
+            return Ok(result);
         }
-        let startpos_percent =
-            u32::from_le_bytes(hint.data()[..4].try_into().unwrap()) as f64 / u32::MAX as f64;
-        let mut index = (startpos_percent * (self.entries.len() - 1) as f64) as usize;
-        assert!(index < self.entries.len(), "Startpos out of range");
 
-        while index != 0 && pred(&self.entries[index]) {
-            index -= 1;
-        }
-        while index != self.entries.len() && !pred(&self.entries[index]) {
-            index += 1;
+        let header = BucketLayoutHeader::deserialize(&header_data)?;
+        ensure!(
+            num_bytes == header.blob_size(),
+            "Directory blob has {} bytes but its layout needs {} bytes",
+            num_bytes,
+            header.blob_size(),
+        );
+        let table = blob
+            .read(
+                BucketLayoutHeader::LEN,
+                usize::try_from(header.table_len())?,
+            )
+            .await?;
+        let descriptors = BucketDescriptor::deserialize_table(&table)?;
+
+        let global_depth = descriptors
+            .iter()
+            .map(|descriptor| descriptor.local_depth)
+            .max()
+            .expect("There is at least one bucket");
+        let mut directory = vec![None; 1 << global_depth];
+        for (bucket_index, descriptor) in descriptors.iter().enumerate() {
+            for slot in directory
+                .iter_mut()
+                .skip(descriptor.prefix as usize)
+                .step_by(1 << descriptor.local_depth)
+            {
+                ensure!(
+                    slot.is_none(),
+                    "Bucket {bucket_index} overlaps with another bucket"
+                );
+                *slot = Some(bucket_index);
+            }
         }
-        index
+        let directory = directory
+            .into_iter()
+            .map(|bucket_index| {
+                bucket_index.ok_or_else(|| anyhow::anyhow!("Some names don't belong to any bucket"))
+            })
+            .collect::<Result<Vec<usize>>>()?;
+
+        Ok(Self {
+            format,
+            buckets: descriptors.into_iter().map(LazyBucket::NotLoaded).collect(),
+            directory,
+            global_depth,
+            bucket_size: header.bucket_size,
+            dirty_buckets: BTreeSet::new(),
+            dirty_descriptors: BTreeSet::new(),
+            blob_layout: Some(header),
+            needs_full_rewrite: false,
+        })
     }
 
-    pub fn get_by_id(&self, id: &BlobId) -> Option<&DirEntry> {
-        self._get_index_by_id(id).map(|index| &self.entries[index])
+    pub async fn serialize_if_dirty(
+        &mut self,
+        blob: &mut impl DirBlobData,
+    ) -> Result<SerializeIfDirtyResult> {
+        if self.dirty_buckets.is_empty() {
+            return Ok(SerializeIfDirtyResult::NotSerialized);
+        }
+        self._prepare_writeback(blob).await?;
+        let pending_writes = self._pending_writes()?;
+        if let Some(new_size) = pending_writes.new_size {
+            blob.resize(new_size).await?;
+        }
+        // TODO Would it have a better performance to concurrently write the buckets?
+        for (offset, data) in &pending_writes.writes {
+            blob.write(*offset, data).await?;
+        }
+        self._mark_clean(pending_writes.new_layout);
+        Ok(SerializeIfDirtyResult::Serialized)
     }
 
-    pub fn get_by_id_mut(&mut self, id: &BlobId) -> Option<&mut DirEntry> {
-        match self._get_index_by_id(id) {
-            None => None,
-            Some(entry) => {
-                self.dirty = true;
-                Some(&mut self.entries[entry])
+    /// Split buckets that outgrew their slot and load all buckets if the whole blob needs to be rewritten.
+    async fn _prepare_writeback(&mut self, blob: &mut impl DirBlobData) -> Result<()> {
+        if self.format.is_extended() {
+            self._split_overflowing_buckets()?;
+            if let Some(blob_layout) = &self.blob_layout
+                && self.buckets.len() > blob_layout.table_capacity as usize
+            {
+                // The bucket table is full. Growing it moves all slots.
+                self.needs_full_rewrite = true;
+            }
+            if !self.needs_full_rewrite {
+                return Ok(());
             }
         }
+        self._load_all_buckets(blob).await
     }
 
-    pub async fn deserialize<B>(blob: &mut BaseBlob<B>) -> Result<Self>
-    where
-        B: BlobStore + Debug,
-        B::ConcreteBlob: Send + AsyncDrop<Error = anyhow::Error>,
-    {
-        // TODO Stream this into a BufReader instead of reading everything up front? But is that actually better?
-        let data = blob.read_all_data().await?;
-        let len = data.len() as u64;
+    /// Compute the writes needed to bring the blob up to date. Usually, only the slots of modified buckets are written.
+    /// Buckets that outgrew their slot are split and the new buckets are appended to the blob.
+    /// The whole blob is only rewritten when migrating from the legacy layout or when the bucket size or bucket table had to grow.
+    /// In the [FsFormat::Cryfs1Compatible] format, the whole blob is always rewritten in the legacy layout.
+    fn _pending_writes(&self) -> Result<PendingWrites> {
+        if !self.format.is_extended() {
+            // CryFS 1.0 expects the entries to be ordered by blob id. The sort is stable, so hard links
+            // to the same blob keep their relative order like they would when inserted by CryFS 1.0.
+            let mut entries: Vec<&DirEntry> = self._iter_loaded().collect();
+            entries.sort_by_key(|entry| *entry.blob_id());
+            let data = bucket_layout::serialize_entry_list(entries)?;
+            return Ok(PendingWrites {
+                new_size: Some(data.len() as u64),
+                writes: vec![(0, data)],
+                new_layout: None,
+            });
+        }
 
-        let mut entries = Vec::new();
-        let mut reader = Cursor::new(data);
-        // TODO Use reader.is_empty() once that is stabilized
-        while reader.position() < len {
-            let entry = DirEntry::deserialize(&mut reader)?;
-            entries.push(entry);
+        let num_buckets = u32::try_from(self.buckets.len()).expect("Too many buckets");
+        if self.needs_full_rewrite {
+            let header = BucketLayoutHeader::new(self.bucket_size, num_buckets);
+            let mut data = header.serialize();
+            data.reserve(header.blob_size() as usize - data.len());
+            for bucket in &self.buckets {
+                data.extend_from_slice(&bucket.descriptor().serialize());
+            }
+            data.resize(header.bucket_offset(0) as usize, 0);
+            for bucket_index in 0..self.buckets.len() {
+                let slot_end = data.len() + self.bucket_size as usize;
+                data.extend_from_slice(&self._loaded_bucket(bucket_index).serialize()?);
+                assert!(data.len() <= slot_end, "Bucket doesn't fit into its slot");
+                data.resize(slot_end, 0);
+            }
+            return Ok(PendingWrites {
+                new_size: Some(data.len() as u64),
+                writes: vec![(0, data)],
+                new_layout: Some(header),
+            });
         }
-        assert_eq!(reader.position(), len, "Did not read all data.");
-        Ok(Self {
-            entries,
-            dirty: false,
+
+        let blob_layout = self
+            .blob_layout
+            .expect("The blob is already in the bucket layout, otherwise we'd do a full rewrite");
+        let header = blob_layout.with_num_buckets(num_buckets);
+        let mut writes =
+            Vec::with_capacity(1 + self.dirty_descriptors.len() + self.dirty_buckets.len());
+        let new_size = if header != blob_layout {
+            writes.push((0, header.serialize()));
+            Some(header.blob_size())
+        } else {
+            None
+        };
+        for &bucket_index in &self.dirty_descriptors {
+            writes.push((
+                header.descriptor_offset(bucket_index),
+                self.buckets[bucket_index].descriptor().serialize(),
+            ));
+        }
+        for &bucket_index in &self.dirty_buckets {
+            // Padding doesn't need to be written, the bucket header knows where its entries end
+            writes.push((
+                header.bucket_offset(bucket_index),
+                self._loaded_bucket(bucket_index).serialize()?,
+            ));
+        }
+        Ok(PendingWrites {
+            new_size,
+            writes,
+            new_layout: Some(header),
         })
     }
 
-    pub async fn serialize_if_dirty<B>(
-        &mut self,
-        blob: &mut BaseBlob<B>,
-    ) -> Result<SerializeIfDirtyResult>
-    where
-        B: BlobStore + Debug,
-        B::ConcreteBlob: Send + AsyncDrop<Error = anyhow::Error>,
-    {
-        if self.dirty {
-            // TODO Stream this into a BufWriter instead of writing everything at the end? But is that actually better?
-            let mut writer = Cursor::new(Vec::new());
-            for entry in &self.entries {
-                entry.serialize(&mut writer)?;
-            }
-            let data = writer.into_inner();
-            // TODO Would it have a better performance to concurrently resize and write?
-            blob.resize_data(data.len() as u64).await?;
-            blob.write_data(&data, 0).await?;
-            self.dirty = false;
-            Ok(SerializeIfDirtyResult::Serialized)
+    fn _split_overflowing_buckets(&mut self) -> Result<()> {
+        let mut to_check: Vec<usize> = if self.needs_full_rewrite {
+            // Buckets that aren't loaded are unchanged and still fit into their slot
+            (0..self.buckets.len())
+                .filter(|&bucket_index| self.buckets[bucket_index].is_loaded())
+                .collect()
         } else {
-            Ok(SerializeIfDirtyResult::NotSerialized)
+            self.dirty_buckets.iter().copied().collect()
+        };
+        while let Some(bucket_index) = to_check.pop() {
+            let bucket = self._loaded_bucket(bucket_index);
+            let serialized_len = bucket.serialized_len()?;
+            if serialized_len <= u64::from(self.bucket_size) {
+                continue;
+            }
+            if bucket.local_depth < MAX_LOCAL_DEPTH && bucket.entries.len() > 1 {
+                let new_bucket_index = self._split_bucket(bucket_index);
+                to_check.push(bucket_index);
+                to_check.push(new_bucket_index);
+            } else {
+                // The bucket can't be split any further, so the slots need to get larger
+                self.bucket_size = u32::try_from(serialized_len.next_power_of_two())?;
+                self.needs_full_rewrite = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Split a bucket into two by looking at one more bit of the name hash. Entries with that bit set
+    /// move to a new bucket, which is appended to the list of buckets. Returns the index of the new bucket.
+    fn _split_bucket(&mut self, bucket_index: usize) -> usize {
+        let bucket = self._loaded_bucket_mut(bucket_index);
+        let split_bit = 1 << bucket.local_depth;
+        bucket.local_depth += 1;
+        let (moved, kept) = std::mem::take(&mut bucket.entries)
+            .into_iter()
+            .partition(|entry| name_hash(entry.name()) & split_bit != 0);
+        bucket.entries = kept;
+        let new_bucket = Bucket {
+            local_depth: bucket.local_depth,
+            prefix: bucket.prefix | split_bit,
+            entries: moved,
+        };
+
+        if new_bucket.local_depth > self.global_depth {
+            self.directory.extend_from_within(..);
+            self.global_depth += 1;
         }
+        let new_bucket_index = self.buckets.len();
+        for slot in self
+            .directory
+            .iter_mut()
+            .skip(new_bucket.prefix as usize)
+            .step_by(1 << new_bucket.local_depth)
+        {
+            *slot = new_bucket_index;
+        }
+        self.buckets.push(LazyBucket::Loaded(new_bucket));
+        self.dirty_buckets.insert(bucket_index);
+        self.dirty_buckets.insert(new_bucket_index);
+        self.dirty_descriptors.insert(bucket_index);
+        self.dirty_descriptors.insert(new_bucket_index);
+        new_bucket_index
+    }
+
+    fn _mark_clean(&mut self, new_layout: Option<BucketLayoutHeader>) {
+        self.dirty_buckets.clear();
+        self.dirty_descriptors.clear();
+        self.blob_layout = new_layout;
+        self.needs_full_rewrite = false;
     }
 
+    /// Iterate over all entries. This loads all buckets.
     // TODO FusedIterator, DoubleEndedIterator
-    pub fn iter(&self) -> impl Iterator<Item = &DirEntry> + ExactSizeIterator {
-        self.entries.iter()
+    pub async fn iter(&mut self, blob: &mut impl DirBlobData) -> Result<Iter<'_>> {
+        self._load_all_buckets(blob).await?;
+        Ok(self._iter_loaded())
+    }
+
+    /// Load all buckets so that [Self::loaded_iter] can iterate over the entries without access to the blob
+    pub async fn load_all(&mut self, blob: &mut impl DirBlobData) -> Result<()> {
+        self._load_all_buckets(blob).await
     }
 
-    pub fn num_entries(&self) -> usize {
-        self.entries.len()
+    /// Iterate over all entries, or return `None` if some buckets aren't loaded yet
+    pub fn loaded_iter(&self) -> Option<Iter<'_>> {
+        self.buckets
+            .iter()
+            .all(LazyBucket::is_loaded)
+            .then(|| self._iter_loaded())
     }
 
-    pub fn add(
+    fn _iter_loaded(&self) -> Iter<'_> {
+        Iter {
+            entries: self
+                .buckets
+                .iter()
+                .flat_map(bucket_entries as fn(&LazyBucket) -> BucketEntries<'_>),
+            remaining: (0..self.buckets.len())
+                .map(|bucket_index| self._loaded_bucket(bucket_index).entries.len())
+                .sum(),
+        }
+    }
+
+    /// Number of entries in the directory. This loads all buckets.
+    pub async fn num_entries(&mut self, blob: &mut impl DirBlobData) -> Result<usize> {
+        Ok(self.iter(blob).await?.len())
+    }
+
+    pub async fn add(
         &mut self,
+        blob: &mut impl DirBlobData,
         name: PathComponentBuf,
         id: BlobId,
         entry_type: EntryType,
@@ -184,7 +556,11 @@ impl DirEntryList {
         last_access_time: SystemTime,
         last_modification_time: SystemTime,
     ) -> Result<(), AddError> {
-        if self.get_by_name(&name).is_some() {
+        let (_, existing) = self
+            ._find(blob, &name)
+            .await
+            .map_err(AddError::LoadFailed)?;
+        if existing.is_some() {
             return Err(AddError::NodeAlreadyExists);
         }
         self._add(
@@ -204,14 +580,100 @@ impl DirEntryList {
         Ok(())
     }
 
+    pub async fn add_link(
+        &mut self,
+        blob: &mut impl DirBlobData,
+        name: PathComponentBuf,
+        source: &DirEntry,
+    ) -> Result<(), AddError> {
+        let (_, existing) = self
+            ._find(blob, &name)
+            .await
+            .map_err(AddError::LoadFailed)?;
+        if existing.is_some() {
+            return Err(AddError::NodeAlreadyExists);
+        }
+        let mut entry = DirEntry::new(
+            source.entry_type(),
+            name,
+            *source.blob_id(),
+            source.mode(),
+            source.uid(),
+            source.gid(),
+            source.last_access_time(),
+            source.last_modification_time(),
+            SystemTime::now(),
+        )
+        .map_err(AddError::ValidationFailed)?;
+        entry
+            .set_rdev(source.rdev())
+            .map_err(AddError::ValidationFailed)?;
+        entry.set_xattrs(source.xattrs().clone());
+        self._add(entry);
+        Ok(())
+    }
+
+    pub async fn add_special(
+        &mut self,
+        blob: &mut impl DirBlobData,
+        name: PathComponentBuf,
+        id: BlobId,
+        entry_type: EntryType,
+        mode: Mode,
+        uid: Uid,
+        gid: Gid,
+        rdev: u32,
+        last_access_time: SystemTime,
+        last_modification_time: SystemTime,
+    ) -> Result<(), AddError> {
+        assert!(
+            !entry_type.has_blob(),
+            "add_special is only for entry types without a blob, but got {entry_type:?}"
+        );
+        let (_, existing) = self
+            ._find(blob, &name)
+            .await
+            .map_err(AddError::LoadFailed)?;
+        if existing.is_some() {
+            return Err(AddError::NodeAlreadyExists);
+        }
+        let mut entry = DirEntry::new(
+            entry_type,
+            name,
+            id,
+            mode,
+            uid,
+            gid,
+            last_access_time,
+            last_modification_time,
+            SystemTime::now(),
+        )
+        .map_err(AddError::ValidationFailed)?;
+        entry.set_rdev(rdev).map_err(AddError::ValidationFailed)?;
+        self._add(entry);
+        Ok(())
+    }
+
+    /// Add an entry to its bucket. The caller must have loaded that bucket.
     fn _add(&mut self, entry: DirEntry) {
-        let upper_bound = self._find_upper_bound(entry.blob_id());
-        self.entries.insert(upper_bound, entry);
-        self.dirty = true;
+        let bucket_index = self._add_without_marking_dirty(entry);
+        self.dirty_buckets.insert(bucket_index);
+    }
+
+    fn _add_without_marking_dirty(&mut self, entry: DirEntry) -> usize {
+        let bucket_index = self._bucket_index(entry.name());
+        self._loaded_bucket_mut(bucket_index).entries.push(entry);
+        bucket_index
+    }
+
+    fn _remove_at(&mut self, bucket_index: usize, index: usize) -> DirEntry {
+        self.dirty_buckets.insert(bucket_index);
+        self._loaded_bucket_mut(bucket_index).entries.remove(index)
     }
 
     pub async fn add_or_overwrite<E>(
         &mut self,
+        blob: &mut impl DirBlobData,
         name: PathComponentBuf,
         id: BlobId,
         entry_type: EntryType,
@@ -220,11 +682,16 @@ impl DirEntryList {
         gid: Gid,
         last_access_time: SystemTime,
         last_modification_time: SystemTime,
+        xattrs: Xattrs,
+        rdev: u32,
         // TODO Return overwritten entry instead of taking an on_overwritten callback
         on_overwritten: impl AsyncFnOnce(EntryType, EntryType, &BlobId) -> Result<(), E>,
     ) -> Result<(), AddOrOverwriteError<E>> {
-        let already_exists = self._get_by_name_with_index(&name);
-        let entry = DirEntry::new(
+        let (bucket_index, already_exists) = self
+            ._find(blob, &name)
+            .await
+            .map_err(AddOrOverwriteError::LoadFailed)?;
+        let mut entry = DirEntry::new(
             entry_type,
             name,
             id,
@@ -236,7 +703,12 @@ impl DirEntryList {
             SystemTime::now(),
         )
         .map_err(AddOrOverwriteError::ValidationFailed)?;
-        if let Some((index, old_entry)) = already_exists {
+        entry
+            .set_rdev(rdev)
+            .map_err(AddOrOverwriteError::ValidationFailed)?;
+        entry.set_xattrs(xattrs);
+        if let Some(index) = already_exists {
+            let old_entry = &self._loaded_bucket(bucket_index).entries[index];
             on_overwritten(
                 entry.entry_type(),
                 old_entry.entry_type(),
@@ -244,73 +716,104 @@ impl DirEntryList {
             )
             .await
             .map_err(AddOrOverwriteError::OnOverwriteError)?;
-            self._overwrite(index, entry);
+            self._overwrite(bucket_index, index, entry);
         } else {
             self._add(entry);
         }
         Ok(())
     }
 
-    fn _overwrite(&mut self, index: usize, entry: DirEntry) {
-        assert_eq!(self.entries[index].name(), entry.name());
-
-        // The new entry has possibly a different blockId, so it has to be in a different list position (list is ordered by blockIds).
-        // That's why we remove-and-add instead of just modifying the existing entry.
-        // TODO Removing moves the whole tail of elements, while it would be more performant to move the elements between the old and the new position.
-        self.entries.remove(index);
-        self._add(entry);
+    fn _overwrite(&mut self, bucket_index: usize, index: usize, entry: DirEntry) {
+        let bucket = self._loaded_bucket_mut(bucket_index);
+        // The name didn't change, so the entry stays in the same bucket
+        assert_eq!(bucket.entries[index].name(), entry.name());
+        bucket.entries[index] = entry;
+        self.dirty_buckets.insert(bucket_index);
     }
 
     pub async fn rename_by_name<E>(
         &mut self,
+        blob: &mut impl DirBlobData,
         old_name: &PathComponent,
         new_name: PathComponentBuf,
         on_overwritten: impl AsyncFnOnce(EntryType, EntryType, &BlobId) -> Result<(), E>,
     ) -> Result<(), RenameError<E>> {
-        let Some((mut source_index, source_entry)) = self._get_by_name_with_index(old_name) else {
+        let (source_bucket_index, Some(source_index)) = self
+            ._find(blob, old_name)
+            .await
+            .map_err(RenameError::LoadFailed)?
+        else {
             return Err(RenameError::NodeDoesNotExist);
         };
-        let source_blob_id = *source_entry.blob_id();
+        let source = &self._loaded_bucket(source_bucket_index).entries[source_index];
+        let source_blob_id = *source.blob_id();
+        let source_entry_type = source.entry_type();
 
-        if let Some((found_same_name_index, found_same_name)) =
-            self._get_by_name_with_index(&new_name)
-        {
+        let (found_same_name_bucket_index, found_same_name_index) = self
+            ._find(blob, &new_name)
+            .await
+            .map_err(RenameError::LoadFailed)?;
+        if let Some(found_same_name_index) = found_same_name_index {
+            let found_same_name =
+                &self._loaded_bucket(found_same_name_bucket_index).entries[found_same_name_index];
             if *found_same_name.blob_id() == source_blob_id {
-                // If the current name holder is already our source blob, we don't need to rename it
-                assert_eq!(source_index, found_same_name_index);
-                assert_eq!(old_name, &*new_name);
-                assert_eq!(self.entries[source_index].name(), &*new_name);
+                // If the current name holder is already our source blob, we don't need to rename it.
+                // This is either a rename to the same name or both names are hard links to the same blob,
+                // and POSIX says rename should do nothing in both cases.
                 return Ok(());
             }
 
-            let source = &self.entries[source_index];
             on_overwritten(
-                source.entry_type(),
+                source_entry_type,
                 found_same_name.entry_type(),
                 found_same_name.blob_id(),
             )
             .await
             .map_err(RenameError::OnOverwriteError)?;
 
-            self.dirty = true;
-            self.entries.remove(found_same_name_index);
-
-            // Since we removed an entry, we need to update the index
-            // we're keeping into the vector
-            assert_ne!(source_index, found_same_name_index);
-            if source_index >= found_same_name_index {
-                source_index -= 1;
-            }
-            assert_eq!(*self.entries[source_index].blob_id(), source_blob_id);
+            self._remove_at(found_same_name_bucket_index, found_same_name_index);
         }
 
-        self.dirty = true;
-        self.entries[source_index].set_name(new_name);
+        // Removing the overwritten entry may have shifted the source entry within its bucket, so look it up again
+        let source_index = self
+            ._position_in_bucket(source_bucket_index, old_name)
+            .expect("Source entry vanished");
+        let mut entry = self._remove_at(source_bucket_index, source_index);
+        assert_eq!(*entry.blob_id(), source_blob_id);
+        entry.set_name(new_name);
+        self._add(entry);
         Ok(())
     }
 
-    pub fn set_attr_by_name<'s>(
+    /// Replace the entry `name` with a copy of `replacement` that is renamed to `name`, and return the previous entry.
+    /// This is used by `RENAME_EXCHANGE`, where both directory entries keep their name but swap what they point to.
+    pub async fn replace_by_name(
+        &mut self,
+        blob: &mut impl DirBlobData,
+        name: &PathComponent,
+        replacement: &DirEntry,
+    ) -> Result<DirEntry, ReplaceError> {
+        let (bucket_index, Some(index)) = self
+            ._find(blob, name)
+            .await
+            .map_err(ReplaceError::LoadFailed)?
+        else {
+            return Err(ReplaceError::NodeDoesNotExist);
+        };
+        let mut entry = replacement.clone();
+        entry.set_name(name.to_owned());
+        // The name didn't change, so the entry stays in the same bucket
+        let previous = std::mem::replace(
+            &mut self._loaded_bucket_mut(bucket_index).entries[index],
+            entry,
+        );
+        self.dirty_buckets.insert(bucket_index);
+        Ok(previous)
+    }
+
+    pub async fn set_attr_by_name<'s>(
         &'s mut self,
+        blob: &mut impl DirBlobData,
         name: &PathComponent,
         mode: Option<Mode>,
         uid: Option<Uid>,
@@ -318,7 +821,11 @@ impl DirEntryList {
         atime: Option<SystemTime>,
         mtime: Option<SystemTime>,
     ) -> Result<&'s DirEntry, SetAttrError> {
-        let Some(entry) = self.get_by_name_mut(name) else {
+        let Some(entry) = self
+            .get_by_name_mut(blob, name)
+            .await
+            .map_err(SetAttrError::LoadFailed)?
+        else {
             return Err(SetAttrError::NodeDoesNotExist);
         };
         entry
@@ -327,86 +834,121 @@ impl DirEntryList {
         Ok(entry)
     }
 
-    pub fn maybe_update_access_timestamp(
+    pub async fn maybe_update_access_timestamp_by_name(
         &mut self,
-        blob_id: &BlobId,
+        blob: &mut impl DirBlobData,
+        name: &PathComponent,
         atime_update_behavior: impl AtimeUpdateBehavior,
     ) -> Result<(), UpdateTimestampError> {
-        let Some(index) = self._get_index_by_id(blob_id) else {
+        let (bucket_index, Some(index)) = self
+            ._find(blob, name)
+            .await
+            .map_err(UpdateTimestampError::LoadFailed)?
+        else {
             return Err(UpdateTimestampError::NodeDoesNotExist);
         };
-        let entry = &self.entries[index];
-        let last_access_time = entry.last_access_time();
-        let last_modification_time = entry.last_modification_time();
         let now = SystemTime::now();
-
-        let should_update_atime = match entry.entry_type() {
-            EntryType::File | EntryType::Symlink => atime_update_behavior
-                .should_update_atime_on_file_or_symlink_read(
-                    last_access_time,
-                    last_modification_time,
-                    now,
-                ),
-            EntryType::Dir => atime_update_behavior.should_update_atime_on_directory_read(
-                last_access_time,
-                last_modification_time,
-                now,
-            ),
-        };
-
-        if should_update_atime {
-            self.entries[index].set_last_access_time(now);
-            self.dirty = true;
+        let entry = &mut self._loaded_bucket_mut(bucket_index).entries[index];
+        if entry.should_update_access_time(atime_update_behavior, now) {
+            entry.set_last_access_time(now);
+            self.dirty_buckets.insert(bucket_index);
         }
         Ok(())
     }
 
-    pub fn update_modification_timestamp(
+    pub async fn update_modification_timestamp_by_name(
         &mut self,
-        blob_id: &BlobId,
+        blob: &mut impl DirBlobData,
+        name: &PathComponent,
     ) -> Result<(), UpdateTimestampError> {
-        let Some(entry) = self.get_by_id_mut(blob_id) else {
+        let Some(entry) = self
+            .get_by_name_mut(blob, name)
+            .await
+            .map_err(UpdateTimestampError::LoadFailed)?
+        else {
             return Err(UpdateTimestampError::NodeDoesNotExist);
         };
         entry.update_modification_time();
         Ok(())
     }
 
-    pub fn update_modification_timestamp_by_name(
+    pub async fn remove_by_name(
         &mut self,
+        blob: &mut impl DirBlobData,
         name: &PathComponent,
-    ) -> Result<(), UpdateTimestampError> {
-        let Some(entry) = self.get_by_name_mut(name) else {
-            return Err(UpdateTimestampError::NodeDoesNotExist);
+    ) -> Result<DirEntry, RemoveError> {
+        let (bucket_index, Some(index)) = self
+            ._find(blob, name)
+            .await
+            .map_err(RemoveError::LoadFailed)?
+        else {
+            return Err(RemoveError::NodeDoesNotExist);
         };
-        entry.update_modification_time();
-        Ok(())
+        Ok(self._remove_at(bucket_index, index))
     }
 
-    pub fn remove_by_name(&mut self, name: &PathComponent) -> Result<DirEntry, RemoveError> {
-        let Some((index, _entry)) = self._get_by_name_with_index(name) else {
-            return Err(RemoveError::NodeDoesNotExist);
-        };
-        self.dirty = true;
-        let removed = self.entries.remove(index);
-        Ok(removed)
-    }
-
-    pub fn remove_by_id_if_exists(&mut self, blob_id: &BlobId) {
-        if let Some(index) = self._get_index_by_id(blob_id) {
-            self.dirty = true;
-            self.entries.remove(index);
-            if index < self.entries.len() {
-                // TODO Remove if this never fires. For some reason our C++ implementation had
-                //      a loop here that deletes all entries with the same blob id, even though
-                //      we don't support hardlinks. Just wanna make sure there isn't some corner
-                //      case we missed so adding an assertion.
-                assert_ne!(self.entries[index].blob_id(), blob_id);
+    /// Remove all entries for the given blob id. There can be multiple if the blob is hard linked multiple times into this directory.
+    pub async fn remove_by_id_if_exists(
+        &mut self,
+        blob: &mut impl DirBlobData,
+        blob_id: &BlobId,
+    ) -> Result<()> {
+        self._load_all_buckets(blob).await?;
+        for bucket_index in 0..self.buckets.len() {
+            let bucket = self._loaded_bucket_mut(bucket_index);
+            let len_before = bucket.entries.len();
+            bucket.entries.retain(|entry| entry.blob_id() != blob_id);
+            if bucket.entries.len() != len_before {
+                self.dirty_buckets.insert(bucket_index);
             }
-        };
+        }
+        Ok(())
+    }
+}
+
+struct PendingWrites {
+    /// If set, the blob needs to be resized to this size before writing
+    new_size: Option<u64>,
+    /// (offset, data) pairs to write to the blob
+    writes: Vec<(u64, Vec<u8>)>,
+    /// The bucket layout of the blob after the writes, or `None` if it is written in the legacy layout
+    new_layout: Option<BucketLayoutHeader>,
+}
+
+type BucketEntries<'a> = std::slice::Iter<'a, DirEntry>;
+
+fn bucket_entries(bucket: &LazyBucket) -> BucketEntries<'_> {
+    match bucket {
+        LazyBucket::Loaded(bucket) => bucket.entries.iter(),
+        LazyBucket::NotLoaded(_) => panic!("Bucket isn't loaded"),
     }
 }
 
+pub struct Iter<'a> {
+    entries: FlatMap<
+        std::slice::Iter<'a, LazyBucket>,
+        BucketEntries<'a>,
+        fn(&'a LazyBucket) -> BucketEntries<'a>,
+    >,
+    remaining: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a DirEntry;
+
+    fn next(&mut self) -> Option<&'a DirEntry> {
+        let entry = self.entries.next()?;
+        self.remaining -= 1;
+        Some(entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
 pub enum SerializeIfDirtyResult {
     Serialized,
     NotSerialized,
@@ -416,32 +958,500 @@ pub enum SerializeIfDirtyResult {
 pub enum AddOrOverwriteError<E> {
     ValidationFailed(ValidationFailed),
     OnOverwriteError(E),
+    #[error(ignore)]
+    LoadFailed(anyhow::Error),
 }
 
 #[derive(Debug, Display, Error)]
 pub enum AddError {
     NodeAlreadyExists,
     ValidationFailed(ValidationFailed),
+    #[error(ignore)]
+    LoadFailed(anyhow::Error),
 }
 
 #[derive(Debug, Display, Error)]
 pub enum SetAttrError {
     NodeDoesNotExist,
     ValidationFailed(ValidationFailed),
+    #[error(ignore)]
+    LoadFailed(anyhow::Error),
 }
 
 #[derive(Debug, Display, Error)]
 pub enum UpdateTimestampError {
     NodeDoesNotExist,
+    #[error(ignore)]
+    LoadFailed(anyhow::Error),
 }
 
 #[derive(Debug, Display, Error)]
 pub enum RemoveError {
     NodeDoesNotExist,
+    #[error(ignore)]
+    LoadFailed(anyhow::Error),
+}
+
+#[derive(Debug, Display, Error)]
+pub enum ReplaceError {
+    NodeDoesNotExist,
+    #[error(ignore)]
+    LoadFailed(anyhow::Error),
 }
 
 #[derive(Debug, Display, Error)]
 pub enum RenameError<E> {
     NodeDoesNotExist,
     OnOverwriteError(E),
+    #[error(ignore)]
+    LoadFailed(anyhow::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use std::io::Cursor;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+
+    impl DirBlobData for Vec<u8> {
+        async fn num_bytes(&mut self) -> Result<u64> {
+            Ok(self.len() as u64)
+        }
+
+        async fn read(&mut self, offset: u64, len: usize) -> Result<Vec<u8>> {
+            let offset = offset as usize;
+            Ok(self[offset..offset + len].to_vec())
+        }
+
+        async fn write(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+            let offset = offset as usize;
+            self[offset..offset + data.len()].copy_from_slice(data);
+            Ok(())
+        }
+
+        async fn resize(&mut self, new_num_bytes: u64) -> Result<()> {
+            Vec::resize(self, new_num_bytes as usize, 0);
+            Ok(())
+        }
+    }
+
+    fn name(index: usize) -> PathComponentBuf {
+        PathComponentBuf::try_from_string(format!("entry_{index}")).unwrap()
+    }
+
+    fn blob_id(index: usize) -> BlobId {
+        BlobId::from_hex(&format!("{index:032x}")).unwrap()
+    }
+
+    fn make_entry(index: usize) -> DirEntry {
+        let time = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        DirEntry::new(
+            EntryType::File,
+            name(index),
+            blob_id(index),
+            Mode::from(0o644),
+            Uid::from(1000),
+            Gid::from(1000),
+            time,
+            time,
+            time,
+        )
+        .unwrap()
+    }
+
+    fn add_entry(list: &mut DirEntryList, data: &mut Vec<u8>, index: usize) {
+        let entry = make_entry(index);
+        block_on(list.add(
+            data,
+            entry.name().to_owned(),
+            *entry.blob_id(),
+            entry.entry_type(),
+            entry.mode(),
+            entry.uid(),
+            entry.gid(),
+            entry.last_access_time(),
+            entry.last_modification_time(),
+        ))
+        .unwrap();
+    }
+
+    fn get<'a>(list: &'a mut DirEntryList, data: &mut Vec<u8>, index: usize) -> &'a DirEntry {
+        block_on(list.get_by_name(data, &name(index)))
+            .unwrap()
+            .unwrap()
+    }
+
+    fn load(data: &mut Vec<u8>, format: FsFormat) -> DirEntryList {
+        block_on(DirEntryList::deserialize(data, format)).unwrap()
+    }
+
+    fn legacy_layout(entries: impl IntoIterator<Item = DirEntry>) -> Vec<u8> {
+        let mut writer = Cursor::new(Vec::new());
+        for entry in entries {
+            entry.serialize(&mut writer).unwrap();
+        }
+        writer.into_inner()
+    }
+
+    /// Apply the pending writes of `list` to `data` like [DirEntryList::serialize_if_dirty] would apply them to a blob
+    fn writeback(list: &mut DirEntryList, data: &mut Vec<u8>) -> Option<PendingWrites> {
+        if list.dirty_buckets.is_empty() {
+            return None;
+        }
+        block_on(list._prepare_writeback(data)).unwrap();
+        let pending_writes = list._pending_writes().unwrap();
+        if let Some(new_size) = pending_writes.new_size {
+            data.resize(new_size as usize, 0);
+        }
+        for (offset, write) in &pending_writes.writes {
+            let offset = *offset as usize;
+            data[offset..offset + write.len()].copy_from_slice(write);
+        }
+        list._mark_clean(pending_writes.new_layout);
+        Some(pending_writes)
+    }
+
+    fn is_full_rewrite(pending_writes: &PendingWrites, data: &[u8]) -> bool {
+        pending_writes.writes.len() == 1
+            && pending_writes.writes[0].0 == 0
+            && pending_writes.writes[0].1.len() == data.len()
+    }
+
+    fn num_loaded_buckets(list: &DirEntryList) -> usize {
+        list.buckets
+            .iter()
+            .filter(|bucket| bucket.is_loaded())
+            .count()
+    }
+
+    fn assert_entries(
+        expected: impl IntoIterator<Item = usize>,
+        list: &mut DirEntryList,
+        data: &mut Vec<u8>,
+    ) {
+        let mut expected: Vec<String> = expected
+            .into_iter()
+            .map(|index| name(index).as_str().to_owned())
+            .collect();
+        let entries: Vec<DirEntry> = block_on(list.iter(data)).unwrap().cloned().collect();
+        let mut actual: Vec<String> = entries
+            .iter()
+            .map(|entry| entry.name().as_str().to_owned())
+            .collect();
+        expected.sort();
+        actual.sort();
+        assert_eq!(expected, actual);
+        assert_eq!(entries.len(), block_on(list.num_entries(data)).unwrap());
+        for entry in &entries {
+            assert_eq!(
+                entry.name(),
+                block_on(list.get_by_name(data, entry.name()))
+                    .unwrap()
+                    .unwrap()
+                    .name()
+            );
+        }
+    }
+
+    #[test]
+    fn empty_blob_is_empty_dir() {
+        let mut data = Vec::new();
+        let mut list = load(&mut data, FsFormat::Extended);
+        assert_entries([], &mut list, &mut data);
+        assert!(writeback(&mut list, &mut data).is_none());
+    }
+
+    #[test]
+    fn legacy_layout_is_read_and_migrated_on_write() {
+        let mut data = legacy_layout((0..10).map(make_entry));
+        let mut list = load(&mut data, FsFormat::Extended);
+        assert_entries(0..10, &mut list, &mut data);
+
+        // Reading doesn't migrate
+        assert!(writeback(&mut list, &mut data).is_none());
+        assert_eq!(legacy_layout((0..10).map(make_entry)), data);
+
+        add_entry(&mut list, &mut data, 10);
+        let pending_writes = writeback(&mut list, &mut data).unwrap();
+        assert!(is_full_rewrite(&pending_writes, &data));
+        assert!(bucket_layout::is_bucket_layout(&data));
+        assert_entries(0..11, &mut load(&mut data, FsFormat::Extended), &mut data);
+    }
+
+    #[test]
+    fn lookups_only_load_one_bucket() {
+        let mut list = DirEntryList::empty(FsFormat::Extended);
+        let mut data = Vec::new();
+        for index in 0..1000 {
+            add_entry(&mut list, &mut data, index);
+        }
+        writeback(&mut list, &mut data).unwrap();
+
+        let mut list = load(&mut data, FsFormat::Extended);
+        assert!(list.buckets.len() > 1);
+        assert_eq!(0, num_loaded_buckets(&list));
+        assert_eq!(&blob_id(500), get(&mut list, &mut data, 500).blob_id());
+        assert_eq!(1, num_loaded_buckets(&list));
+        add_entry(&mut list, &mut data, 1000);
+        assert!(num_loaded_buckets(&list) <= 2);
+    }
+
+    #[test]
+    fn modifying_an_entry_only_writes_its_bucket() {
+        let mut list = DirEntryList::empty(FsFormat::Extended);
+        let mut data = Vec::new();
+        for index in 0..1000 {
+            add_entry(&mut list, &mut data, index);
+        }
+        writeback(&mut list, &mut data).unwrap();
+        let mut list = load(&mut data, FsFormat::Extended);
+        assert!(list.buckets.len() > 1);
+        let data_len = data.len();
+
+        block_on(list.update_modification_timestamp_by_name(&mut data, &name(500))).unwrap();
+        let pending_writes = writeback(&mut list, &mut data).unwrap();
+        assert_eq!(None, pending_writes.new_size);
+        assert_eq!(1, pending_writes.writes.len());
+        assert!(pending_writes.writes[0].1.len() <= DEFAULT_BUCKET_SIZE as usize);
+        assert_eq!(data_len, data.len());
+        assert_eq!(1, num_loaded_buckets(&list));
+
+        let mut list = load(&mut data, FsFormat::Extended);
+        assert_entries(0..1000, &mut list, &mut data);
+        assert_ne!(
+            make_entry(500).last_modification_time(),
+            get(&mut list, &mut data, 500).last_modification_time(),
+        );
+    }
+
+    #[test]
+    fn growing_and_shrinking_one_entry_at_a_time() {
+        let mut list = DirEntryList::empty(FsFormat::Extended);
+        let mut data = Vec::new();
+        let mut num_full_rewrites = 0;
+        for index in 0..500 {
+            add_entry(&mut list, &mut data, index);
+            let pending_writes = writeback(&mut list, &mut data).unwrap();
+            if is_full_rewrite(&pending_writes, &data) {
+                num_full_rewrites += 1;
+            } else {
+                // Adding an entry writes at most the header, the table entries of the bucket it was added to
+                // and of the bucket split off from it, and the slots of those two buckets.
+                // This can happen recursively, but that's rare enough to not happen for these names.
+                assert!(pending_writes.writes.len() <= 5);
+            }
+            list = load(&mut data, FsFormat::Extended);
+        }
+        // The first write creates the bucket layout, and the bucket table doubles its capacity whenever it is full
+        assert!(list.buckets.len() <= 64);
+        assert!(num_full_rewrites <= 3);
+        assert_entries(0..500, &mut list, &mut data);
+        assert!(list.buckets.len() > 1);
+        assert_eq!(DEFAULT_BUCKET_SIZE, list.bucket_size);
+        for index in 0..250 {
+            block_on(list.remove_by_name(&mut data, &name(index))).unwrap();
+            writeback(&mut list, &mut data).unwrap();
+            list = load(&mut data, FsFormat::Extended);
+        }
+        assert_entries(250..500, &mut list, &mut data);
+    }
+
+    #[test]
+    fn rename_moves_entry_to_its_new_bucket() {
+        let mut list = DirEntryList::empty(FsFormat::Extended);
+        let mut data = Vec::new();
+        for index in 0..200 {
+            add_entry(&mut list, &mut data, index);
+        }
+        writeback(&mut list, &mut data).unwrap();
+
+        block_on(list.rename_by_name(
+            &mut data,
+            &name(0),
+            name(1000),
+            async |_, _, _| -> Result<(), ()> { panic!("Nothing should be overwritten") },
+        ))
+        .unwrap();
+        block_on(
+            list.rename_by_name(&mut data, &name(1), name(2), async |_, _, _| {
+                Ok::<(), ()>(())
+            }),
+        )
+        .unwrap();
+        writeback(&mut list, &mut data).unwrap();
+
+        let mut list = load(&mut data, FsFormat::Extended);
+        assert_entries((2..200).chain([1000]), &mut list, &mut data);
+        assert_eq!(&blob_id(0), get(&mut list, &mut data, 1000).blob_id());
+        assert_eq!(&blob_id(1), get(&mut list, &mut data, 2).blob_id());
+    }
+
+    #[test]
+    fn entries_larger_than_bucket_size() {
+        let mut list = DirEntryList::empty(FsFormat::Extended);
+        let mut data = Vec::new();
+        for index in 0..3 {
+            add_entry(&mut list, &mut data, index);
+            block_on(list.get_by_name_mut(&mut data, &name(index)))
+                .unwrap()
+                .unwrap()
+                .set_xattr("user.large", &vec![index as u8; 10_000])
+                .unwrap();
+        }
+        writeback(&mut list, &mut data).unwrap();
+        assert!(list.bucket_size > DEFAULT_BUCKET_SIZE);
+
+        let mut list = load(&mut data, FsFormat::Extended);
+        assert_entries(0..3, &mut list, &mut data);
+        assert_eq!(
+            Some(&vec![2; 10_000][..]),
+            get(&mut list, &mut data, 2).xattrs().get("user.large"),
+        );
+    }
+
+    #[test]
+    fn bucket_size_grows_with_unloaded_buckets() {
+        let mut list = DirEntryList::empty(FsFormat::Extended);
+        let mut data = Vec::new();
+        for index in 0..1000 {
+            add_entry(&mut list, &mut data, index);
+        }
+        writeback(&mut list, &mut data).unwrap();
+
+        let mut list = load(&mut data, FsFormat::Extended);
+        block_on(list.get_by_name_mut(&mut data, &name(500)))
+            .unwrap()
+            .unwrap()
+            .set_xattr("user.large", &[1; 10_000])
+            .unwrap();
+        let pending_writes = writeback(&mut list, &mut data).unwrap();
+        assert!(is_full_rewrite(&pending_writes, &data));
+        assert!(list.bucket_size > DEFAULT_BUCKET_SIZE);
+
+        let mut list = load(&mut data, FsFormat::Extended);
+        assert_entries(0..1000, &mut list, &mut data);
+        assert_eq!(
+            Some(&[1; 10_000][..]),
+            get(&mut list, &mut data, 500).xattrs().get("user.large"),
+        );
+    }
+
+    #[test]
+    fn remove_by_id_removes_all_hard_links() {
+        let mut list = DirEntryList::empty(FsFormat::Extended);
+        let mut data = Vec::new();
+        for index in 0..100 {
+            add_entry(&mut list, &mut data, index);
+        }
+        let source = make_entry(50);
+        block_on(list.add_link(&mut data, name(1000), &source)).unwrap();
+        block_on(list.add_link(&mut data, name(1001), &source)).unwrap();
+        assert_eq!(102, block_on(list.num_entries(&mut data)).unwrap());
+
+        block_on(list.remove_by_id_if_exists(&mut data, &blob_id(50))).unwrap();
+        assert_entries((0..100).filter(|&index| index != 50), &mut list, &mut data);
+        assert!(
+            block_on(list.get_by_id(&mut data, &blob_id(50)))
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            block_on(list.get_by_id(&mut data, &blob_id(51)))
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn replace_swaps_entries_and_keeps_names() {
+        let mut list = DirEntryList::empty(FsFormat::Extended);
+        let mut data = Vec::new();
+        for index in 0..200 {
+            add_entry(&mut list, &mut data, index);
+        }
+        writeback(&mut list, &mut data).unwrap();
+
+        let first = get(&mut list, &mut data, 3).clone();
+        let second = get(&mut list, &mut data, 150).clone();
+        let previous = block_on(list.replace_by_name(&mut data, &name(3), &second)).unwrap();
+        assert_eq!(first.blob_id(), previous.blob_id());
+        block_on(list.replace_by_name(&mut data, &name(150), &previous)).unwrap();
+        assert!(matches!(
+            block_on(list.replace_by_name(&mut data, &name(1000), &first)),
+            Err(ReplaceError::NodeDoesNotExist)
+        ));
+        writeback(&mut list, &mut data).unwrap();
+
+        let mut list = load(&mut data, FsFormat::Extended);
+        assert_entries(0..200, &mut list, &mut data);
+        assert_eq!(&blob_id(150), get(&mut list, &mut data, 3).blob_id());
+        assert_eq!(&blob_id(3), get(&mut list, &mut data, 150).blob_id());
+    }
+
+    #[test]
+    fn compat_format_rewrites_legacy_layout_byte_identically() {
+        let mut data = legacy_layout((0..100).map(make_entry));
+        let mut list = load(&mut data, FsFormat::Cryfs1Compatible);
+        assert_entries(0..100, &mut list, &mut data);
+
+        // Mark the directory as modified without actually changing an entry
+        block_on(list.get_by_name_mut(&mut data, &name(50)))
+            .unwrap()
+            .unwrap();
+        let pending_writes = writeback(&mut list, &mut data).unwrap();
+        assert!(is_full_rewrite(&pending_writes, &data));
+        assert_eq!(legacy_layout((0..100).map(make_entry)), data);
+    }
+
+    #[test]
+    fn compat_format_writes_legacy_layout_ordered_by_blob_id() {
+        let mut list = DirEntryList::empty(FsFormat::Cryfs1Compatible);
+        let mut data = Vec::new();
+        // Add entries in a different order than their blob ids
+        for index in (0..500).rev() {
+            add_entry(&mut list, &mut data, index);
+        }
+        writeback(&mut list, &mut data).unwrap();
+        assert!(!bucket_layout::is_bucket_layout(&data));
+        assert_eq!(1, list.buckets.len());
+
+        let expected_entries: Vec<DirEntry> = (0..500)
+            .map(|index| get(&mut list, &mut data, index).clone())
+            .collect();
+        assert_eq!(legacy_layout(expected_entries), data);
+        assert_entries(
+            0..500,
+            &mut load(&mut data, FsFormat::Cryfs1Compatible),
+            &mut data,
+        );
+    }
+
+    #[test]
+    fn compat_format_never_migrates_legacy_layout() {
+        let mut data = legacy_layout((0..10).map(make_entry));
+        let mut list = load(&mut data, FsFormat::Cryfs1Compatible);
+
+        add_entry(&mut list, &mut data, 10);
+        block_on(list.remove_by_name(&mut data, &name(3))).unwrap();
+        block_on(
+            list.rename_by_name(&mut data, &name(4), name(1000), async |_, _, _| {
+                Ok::<(), ()>(())
+            }),
+        )
+        .unwrap();
+        writeback(&mut list, &mut data).unwrap();
+
+        assert!(!bucket_layout::is_bucket_layout(&data));
+        let entries = bucket_layout::deserialize_entry_list(&data).unwrap();
+        assert!(entries.is_sorted_by_key(|entry| *entry.blob_id()));
+        assert_entries(
+            (0..11)
+                .filter(|&index| index != 3 && index != 4)
+                .chain([1000]),
+            &mut load(&mut data, FsFormat::Cryfs1Compatible),
+            &mut data,
+        );
+    }
 }
@@ -1,10 +1,16 @@
 mod atime_update_behavior;
+mod bucket_layout;
 mod entry;
 mod entry_list;
+mod xattrs;
 
 pub use atime_update_behavior::AtimeUpdateBehavior;
 pub use entry::{DirEntry, EntryType};
+pub(crate) use entry::{read_path_component, write_path_component};
 pub use entry_list::{
-    AddError, AddOrOverwriteError, DirEntryList, RemoveError, RenameError, SerializeIfDirtyResult,
-    SetAttrError, UpdateTimestampError,
+    AddError, AddOrOverwriteError, DirEntryList, RemoveError, RenameError, ReplaceError,
+    SerializeIfDirtyResult, SetAttrError, UpdateTimestampError,
+};
+pub use xattrs::{
+    MAX_XATTR_NAME_LEN, MAX_XATTR_VALUE_LEN, MAX_XATTRS_TOTAL_LEN, SetXattrError, Xattrs,
 };
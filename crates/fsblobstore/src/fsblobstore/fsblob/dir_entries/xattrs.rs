@@ -0,0 +1,250 @@
+use binrw::{BinRead, BinResult, BinWrite, Endian};
+use derive_more::{Display, Error};
+use std::collections::BTreeMap;
+use std::io::{Read, Seek, Write};
+use std::num::NonZeroU8;
+
+use cryfs_utils::binary::{read_null_string, write_null_string};
+
+/// Maximum length of an extended attribute name in bytes. This is the same as `XATTR_NAME_MAX` on Linux.
+pub const MAX_XATTR_NAME_LEN: usize = 255;
+
+/// Maximum length of an extended attribute value in bytes. This is the same as `XATTR_SIZE_MAX` on Linux.
+pub const MAX_XATTR_VALUE_LEN: usize = 64 * 1024;
+
+/// Maximum number of bytes all extended attribute names and values of one node can take together.
+/// Extended attributes are stored in the dir entry in the parent directory, and this limit makes sure
+/// that they can't grow the directory blob indefinitely.
+pub const MAX_XATTRS_TOTAL_LEN: usize = 64 * 1024;
+
+/// The extended attributes of a file system node, stored in its [DirEntry](super::DirEntry).
+///
+/// Serialized as a `u32` number of attributes, followed by each attribute as a null-terminated name,
+/// a `u32` value length and the value bytes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Xattrs {
+    // Ordered so that the serialization is deterministic
+    entries: BTreeMap<String, Vec<u8>>,
+}
+
+impl Xattrs {
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.entries.get(name).map(Vec::as_slice)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.contains_key(name)
+    }
+
+    pub fn names(&self) -> impl ExactSizeIterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
+    }
+
+    /// Add an extended attribute or replace its value if it already exists.
+    pub fn set(&mut self, name: &str, value: &[u8]) -> Result<(), SetXattrError> {
+        if name.is_empty() || name.as_bytes().contains(&0) {
+            return Err(SetXattrError::InvalidName);
+        }
+        if name.len() > MAX_XATTR_NAME_LEN {
+            return Err(SetXattrError::NameTooLong);
+        }
+        if value.len() > MAX_XATTR_VALUE_LEN {
+            return Err(SetXattrError::ValueTooLarge);
+        }
+        let total_len_without_this_attr = self.total_len()
+            - self
+                .entries
+                .get(name)
+                .map(|old_value| name.len() + old_value.len())
+                .unwrap_or(0);
+        if total_len_without_this_attr + name.len() + value.len() > MAX_XATTRS_TOTAL_LEN {
+            return Err(SetXattrError::NotEnoughSpace);
+        }
+        self.entries.insert(name.to_owned(), value.to_vec());
+        Ok(())
+    }
+
+    /// Remove an extended attribute and return its value, or `None` if it didn't exist.
+    pub fn remove(&mut self, name: &str) -> Option<Vec<u8>> {
+        self.entries.remove(name)
+    }
+
+    fn total_len(&self) -> usize {
+        self.entries
+            .iter()
+            .map(|(name, value)| name.len() + value.len())
+            .sum()
+    }
+}
+
+impl BinRead for Xattrs {
+    type Args<'a> = ();
+
+    fn read_options<R: Read + Seek>(reader: &mut R, endian: Endian, _args: ()) -> BinResult<Self> {
+        let num_entries = u32::read_options(reader, endian, ())?;
+        let mut entries = BTreeMap::new();
+        for _ in 0..num_entries {
+            let pos = reader.stream_position()?;
+            let name = read_null_string(reader, endian, ())?;
+            let name = String::from_utf8(name.into_iter().map(NonZeroU8::get).collect()).map_err(
+                |err| binrw::Error::AssertFail {
+                    pos,
+                    message: format!("{err:?}"),
+                },
+            )?;
+            let value_len = u32::read_options(reader, endian, ())?;
+            let mut value = vec![0; value_len as usize];
+            reader.read_exact(&mut value)?;
+            if entries.insert(name, value).is_some() {
+                return Err(binrw::Error::AssertFail {
+                    pos,
+                    message: "Duplicate extended attribute name".to_string(),
+                });
+            }
+        }
+        Ok(Self { entries })
+    }
+}
+
+impl BinWrite for Xattrs {
+    type Args<'a> = ();
+
+    fn write_options<W: Write + Seek>(
+        &self,
+        writer: &mut W,
+        endian: Endian,
+        _args: (),
+    ) -> BinResult<()> {
+        u32::try_from(self.entries.len())
+            .expect("Number of extended attributes doesn't fit into u32")
+            .write_options(writer, endian, ())?;
+        for (name, value) in &self.entries {
+            let name: Vec<NonZeroU8> = name
+                .as_bytes()
+                .iter()
+                .map(|c| {
+                    NonZeroU8::try_from(*c)
+                        .expect("Xattrs::set ensures that there aren't any null bytes")
+                })
+                .collect();
+            write_null_string(&name, writer, endian, ())?;
+            u32::try_from(value.len())
+                .expect("Xattrs::set ensures that values are small enough")
+                .write_options(writer, endian, ())?;
+            writer.write_all(value)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Error, Display, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetXattrError {
+    #[display("Extended attribute names must not be empty and must not contain null bytes")]
+    InvalidName,
+    #[display("Extended attribute name is longer than {MAX_XATTR_NAME_LEN} bytes")]
+    NameTooLong,
+    #[display("Extended attribute value is larger than {MAX_XATTR_VALUE_LEN} bytes")]
+    ValueTooLarge,
+    #[display("Extended attributes of a node can't take more than {MAX_XATTRS_TOTAL_LEN} bytes")]
+    NotEnoughSpace,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn serialize(xattrs: &Xattrs) -> Vec<u8> {
+        let mut data = Cursor::new(Vec::new());
+        xattrs.write_le(&mut data).unwrap();
+        data.into_inner()
+    }
+
+    fn deserialize(data: &[u8]) -> Xattrs {
+        Xattrs::read_le(&mut Cursor::new(data)).unwrap()
+    }
+
+    #[test]
+    fn empty_roundtrip() {
+        let xattrs = Xattrs::default();
+        let data = serialize(&xattrs);
+        assert_eq!(vec![0, 0, 0, 0], data);
+        assert_eq!(xattrs, deserialize(&data));
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut xattrs = Xattrs::default();
+        xattrs.set("user.b", b"value").unwrap();
+        xattrs.set("user.a", b"").unwrap();
+        xattrs.set("security.selinux", &[0, 1, 2, 0]).unwrap();
+        assert_eq!(xattrs, deserialize(&serialize(&xattrs)));
+        assert_eq!(
+            vec!["security.selinux", "user.a", "user.b"],
+            xattrs.names().collect::<Vec<_>>(),
+        );
+    }
+
+    #[test]
+    fn set_replaces_existing_value() {
+        let mut xattrs = Xattrs::default();
+        xattrs.set("user.a", b"old value").unwrap();
+        xattrs.set("user.a", b"new").unwrap();
+        assert_eq!(Some(&b"new"[..]), xattrs.get("user.a"));
+        assert_eq!(1, xattrs.len());
+    }
+
+    #[test]
+    fn remove() {
+        let mut xattrs = Xattrs::default();
+        xattrs.set("user.a", b"value").unwrap();
+        assert_eq!(None, xattrs.remove("user.b"));
+        assert_eq!(Some(b"value".to_vec()), xattrs.remove("user.a"));
+        assert!(xattrs.is_empty());
+    }
+
+    #[test]
+    fn invalid_names() {
+        let mut xattrs = Xattrs::default();
+        assert_eq!(Err(SetXattrError::InvalidName), xattrs.set("", b"value"));
+        assert_eq!(
+            Err(SetXattrError::InvalidName),
+            xattrs.set("user.\0a", b"value")
+        );
+        let name = "a".repeat(MAX_XATTR_NAME_LEN);
+        xattrs.set(&name, b"value").unwrap();
+        let name = "a".repeat(MAX_XATTR_NAME_LEN + 1);
+        assert_eq!(Err(SetXattrError::NameTooLong), xattrs.set(&name, b"value"));
+    }
+
+    #[test]
+    fn size_limits() {
+        let mut xattrs = Xattrs::default();
+        assert_eq!(
+            Err(SetXattrError::ValueTooLarge),
+            xattrs.set("user.a", &vec![0; MAX_XATTR_VALUE_LEN + 1]),
+        );
+        xattrs
+            .set("user.a", &vec![0; MAX_XATTR_VALUE_LEN - 6])
+            .unwrap();
+        assert_eq!(
+            Err(SetXattrError::NotEnoughSpace),
+            xattrs.set("user.b", b"x"),
+        );
+        // Replacing an existing value only counts the new value
+        xattrs
+            .set("user.a", &vec![1; MAX_XATTR_VALUE_LEN - 6])
+            .unwrap();
+        xattrs.set("user.a", b"small").unwrap();
+        xattrs.set("user.b", b"x").unwrap();
+    }
+}
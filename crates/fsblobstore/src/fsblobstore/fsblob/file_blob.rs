@@ -5,6 +5,7 @@ use futures::stream::BoxStream;
 use std::fmt::Debug;
 
 use super::{base_blob::BaseBlob, layout::BlobType};
+use crate::fsblobstore::FsFormat;
 use cryfs_blobstore::{BlobId, BlobStore};
 use cryfs_blockstore::BlockId;
 
@@ -27,10 +28,12 @@ where
 
     pub async fn create_blob(
         blobstore: &B,
+        blob_id: &BlobId,
         parent: &BlobId,
+        format: FsFormat,
     ) -> Result<AsyncDropGuard<FileBlob<B>>> {
         Ok(AsyncDropGuard::new(Self {
-            blob: BaseBlob::create(blobstore, BlobType::File, parent, &[]).await?,
+            blob: BaseBlob::create(blob_id, blobstore, BlobType::File, parent, &[], format).await?,
         }))
     }
 
@@ -54,6 +57,30 @@ where
         self.blob.write_data(source, offset).await
     }
 
+    pub async fn punch_hole(&mut self, offset: u64, len: u64) -> Result<()> {
+        self.blob.punch_hole_data(offset, len).await
+    }
+
+    pub async fn seek_data(&mut self, offset: u64) -> Result<Option<u64>> {
+        self.blob.seek_data(offset).await
+    }
+
+    pub async fn seek_hole(&mut self, offset: u64) -> Result<Option<u64>> {
+        self.blob.seek_hole(offset).await
+    }
+
+    pub async fn clone_range_from(
+        &mut self,
+        source: &mut FileBlob<B>,
+        source_offset: u64,
+        offset: u64,
+        len: u64,
+    ) -> Result<u64> {
+        self.blob
+            .clone_data_range_from(&mut source.blob, source_offset, offset, len)
+            .await
+    }
+
     pub async fn flush(&mut self) -> Result<()> {
         self.blob.flush().await
     }
@@ -66,6 +93,34 @@ where
         self.blob.set_parent(new_parent).await
     }
 
+    pub fn num_links(&self) -> u32 {
+        self.blob.num_links()
+    }
+
+    pub fn has_link_count(&self) -> bool {
+        self.blob.has_link_count()
+    }
+
+    pub fn is_hard_linked(&self) -> bool {
+        self.blob.is_hard_linked()
+    }
+
+    pub fn link_metadata_blob_id(&self) -> Option<BlobId> {
+        self.blob.link_metadata_blob_id()
+    }
+
+    pub async fn add_first_hard_link(&mut self, link_metadata_blob_id: &BlobId) -> Result<u32> {
+        self.blob.add_first_hard_link(link_metadata_blob_id).await
+    }
+
+    pub async fn increment_num_links(&mut self) -> Result<u32> {
+        self.blob.increment_num_links().await
+    }
+
+    pub async fn decrement_num_links(&mut self) -> Result<u32> {
+        self.blob.decrement_num_links().await
+    }
+
     pub async fn remove(this: AsyncDropGuard<Self>) -> Result<()> {
         BaseBlob::remove(this.unsafe_into_inner_dont_drop().blob).await
     }
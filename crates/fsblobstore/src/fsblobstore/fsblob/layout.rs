@@ -10,6 +10,11 @@ const MAGIC_NUMBER_SYMLINK: u8 = 0x02;
 
 pub const FORMAT_VERSION_HEADER: u16 = 1;
 
+/// File and symlink blobs in the extended format use this format version. Their header is extended by
+/// [fsblob_link_count_header], which is reserved when the blob is created so that hard linking the blob
+/// later only has to update the header and never has to move its data.
+pub const FORMAT_VERSION_HEADER_WITH_LINK_COUNT: u16 = 2;
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Ord, PartialOrd, Hash)]
 pub enum BlobType {
     Dir,
@@ -46,6 +51,15 @@ binary_layout!(fsblob_header, LittleEndian, {
     parent: [u8; BLOBID_LEN], // TODO `BlobId as [u8; BLOBID_LEN]` with binary_layout::LayoutAs
 });
 
+// Follows directly after [fsblob_header] for blobs with [FORMAT_VERSION_HEADER_WITH_LINK_COUNT]
+binary_layout!(fsblob_link_count_header, LittleEndian, {
+    num_links: u32,
+    // Once a blob was hard linked, its metadata (mode, owner, timestamps, extended attributes) is shared by all its links
+    // and lives in a separate file blob. This is the id of that blob, or all zeroes if the blob was never hard linked.
+    // Hard linked blobs can be referenced from multiple directories and their parent pointer isn't maintained anymore.
+    link_metadata_blob_id: [u8; BLOBID_LEN],
+});
+
 binary_layout!(fsblob, LittleEndian, {
         header: fsblob_header::NestedView,
         data: [u8],
@@ -7,6 +7,8 @@ use cryfs_blobstore::{BlobId, BlobStore};
 use cryfs_blockstore::BlockId;
 use cryfs_utils::async_drop::{AsyncDrop, AsyncDropGuard};
 
+use crate::fsblobstore::FsFormat;
+
 mod layout;
 pub use layout::BlobType;
 
@@ -24,9 +26,11 @@ pub use symlink_blob::SymlinkBlob;
 
 mod dir_entries;
 pub use dir_entries::{
-    AddError, AddOrOverwriteError, AtimeUpdateBehavior, DirEntry, EntryType, RemoveError,
-    RenameError, SetAttrError, UpdateTimestampError,
+    AddError, AddOrOverwriteError, AtimeUpdateBehavior, DirEntry, EntryType, MAX_XATTR_NAME_LEN,
+    MAX_XATTR_VALUE_LEN, MAX_XATTRS_TOTAL_LEN, RemoveError, RenameError, ReplaceError,
+    SetAttrError, SetXattrError, UpdateTimestampError, Xattrs,
 };
+pub(crate) use dir_entries::{read_path_component, write_path_component};
 
 // TODO Now that FileBlob, DirBlob and SymlinkBlob are only ever returned as references,
 //      we can probably store BaseBlob directly in here and just have FileBlob, DirBlob and SymlinkBlob
@@ -49,8 +53,11 @@ where
     B: BlobStore + Debug + 'static,
     <B as BlobStore>::ConcreteBlob: Send + AsyncDrop<Error = anyhow::Error>,
 {
-    pub async fn parse(blob: AsyncDropGuard<B::ConcreteBlob>) -> Result<AsyncDropGuard<FsBlob<B>>> {
-        let mut blob = BaseBlob::parse(blob).await?;
+    pub async fn parse(
+        blob: AsyncDropGuard<B::ConcreteBlob>,
+        format: FsFormat,
+    ) -> Result<AsyncDropGuard<FsBlob<B>>> {
+        let mut blob = BaseBlob::parse(blob, format).await?;
         match blob.blob_type() {
             Ok(BlobType::Dir) => Ok(AsyncDropGuard::new(Self::Directory(
                 DirBlob::new(blob).await?,
@@ -88,6 +95,83 @@ where
         }
     }
 
+    /// Number of directory entries referencing this blob. Directories can't be hard linked and always have one link.
+    pub fn num_links(&self) -> u32 {
+        match &self {
+            Self::File(blob) => blob.num_links(),
+            Self::Directory(_) => 1,
+            Self::Symlink(blob) => blob.num_links(),
+        }
+    }
+
+    /// In the extended format, file and symlink blobs store a link count so they can be hard linked.
+    pub fn has_link_count(&self) -> bool {
+        match &self {
+            Self::File(blob) => blob.has_link_count(),
+            Self::Directory(_) => false,
+            Self::Symlink(blob) => blob.has_link_count(),
+        }
+    }
+
+    /// Whether CryFS 1.0 can read this blob as it is currently stored, i.e. it doesn't have a link count
+    /// and isn't a directory in the bucket layout. This doesn't look at the individual entries of a directory.
+    pub fn is_readable_by_cryfs1(&self) -> bool {
+        match &self {
+            Self::File(blob) => !blob.has_link_count(),
+            Self::Directory(blob) => !blob.is_stored_in_bucket_layout(),
+            Self::Symlink(blob) => !blob.has_link_count(),
+        }
+    }
+
+    /// Blobs that were hard linked at some point can be referenced from multiple directories
+    /// and their parent pointer isn't maintained anymore.
+    pub fn is_hard_linked(&self) -> bool {
+        match &self {
+            Self::File(blob) => blob.is_hard_linked(),
+            Self::Directory(_) => false,
+            Self::Symlink(blob) => blob.is_hard_linked(),
+        }
+    }
+
+    /// Hard linked blobs store the metadata shared by all their links in a separate file blob with this id.
+    /// Blobs that were never hard linked store their metadata in their dir entry and return `None`.
+    pub fn link_metadata_blob_id(&self) -> Option<BlobId> {
+        match &self {
+            Self::File(blob) => blob.link_metadata_blob_id(),
+            Self::Directory(_) => None,
+            Self::Symlink(blob) => blob.link_metadata_blob_id(),
+        }
+    }
+
+    /// Add the first hard link to a blob that was never hard linked before and return the new link count.
+    /// The link metadata blob must already contain the metadata from the dir entry of the existing link.
+    pub async fn add_first_hard_link(&mut self, link_metadata_blob_id: &BlobId) -> Result<u32> {
+        match self {
+            Self::File(blob) => blob.add_first_hard_link(link_metadata_blob_id).await,
+            Self::Directory(_) => bail!("Directories can't be hard linked"),
+            Self::Symlink(blob) => blob.add_first_hard_link(link_metadata_blob_id).await,
+        }
+    }
+
+    /// Increment the link count of an already hard linked blob and return the new link count.
+    pub async fn increment_num_links(&mut self) -> Result<u32> {
+        match self {
+            Self::File(blob) => blob.increment_num_links().await,
+            Self::Directory(_) => bail!("Directories can't be hard linked"),
+            Self::Symlink(blob) => blob.increment_num_links().await,
+        }
+    }
+
+    /// Decrement the link count and return the new link count. The last link can't be removed this way,
+    /// the blob needs to be removed instead.
+    pub async fn decrement_num_links(&mut self) -> Result<u32> {
+        match self {
+            Self::File(blob) => blob.decrement_num_links().await,
+            Self::Directory(_) => bail!("Directories can't be hard linked"),
+            Self::Symlink(blob) => blob.decrement_num_links().await,
+        }
+    }
+
     pub async fn remove(this: AsyncDropGuard<Self>) -> Result<()> {
         match this.unsafe_into_inner_dont_drop() {
             Self::File(blob) => FileBlob::remove(blob).await,
@@ -6,6 +6,7 @@ use std::fmt::Debug;
 
 use super::base_blob::BaseBlob;
 use super::layout::BlobType;
+use crate::fsblobstore::FsFormat;
 use cryfs_blobstore::{BlobId, BlobStore};
 use cryfs_blockstore::BlockId;
 
@@ -28,11 +29,21 @@ where
 
     pub async fn create_blob(
         blobstore: &B,
+        blob_id: &BlobId,
         parent: &BlobId,
         target: &str,
+        format: FsFormat,
     ) -> Result<AsyncDropGuard<SymlinkBlob<B>>> {
         Ok(AsyncDropGuard::new(Self {
-            blob: BaseBlob::create(blobstore, BlobType::Symlink, parent, target.as_bytes()).await?,
+            blob: BaseBlob::create(
+                blob_id,
+                blobstore,
+                BlobType::Symlink,
+                parent,
+                target.as_bytes(),
+                format,
+            )
+            .await?,
         }))
     }
 
@@ -56,6 +67,34 @@ where
         self.blob.set_parent(new_parent).await
     }
 
+    pub fn num_links(&self) -> u32 {
+        self.blob.num_links()
+    }
+
+    pub fn has_link_count(&self) -> bool {
+        self.blob.has_link_count()
+    }
+
+    pub fn is_hard_linked(&self) -> bool {
+        self.blob.is_hard_linked()
+    }
+
+    pub fn link_metadata_blob_id(&self) -> Option<BlobId> {
+        self.blob.link_metadata_blob_id()
+    }
+
+    pub async fn add_first_hard_link(&mut self, link_metadata_blob_id: &BlobId) -> Result<u32> {
+        self.blob.add_first_hard_link(link_metadata_blob_id).await
+    }
+
+    pub async fn increment_num_links(&mut self) -> Result<u32> {
+        self.blob.increment_num_links().await
+    }
+
+    pub async fn decrement_num_links(&mut self) -> Result<u32> {
+        self.blob.decrement_num_links().await
+    }
+
     pub async fn remove(this: AsyncDropGuard<Self>) -> Result<()> {
         BaseBlob::remove(this.unsafe_into_inner_dont_drop().blob).await
     }
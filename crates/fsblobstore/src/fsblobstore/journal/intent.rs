@@ -0,0 +1,287 @@
+use binrw::{BinResult, Endian, binrw};
+use std::io::{Read, Seek, Write};
+
+use super::super::fsblob::{DirEntry, EntryType, read_path_component, write_path_component};
+use cryfs_blobstore::BlobId;
+use cryfs_utils::path::PathComponentBuf;
+
+/// A multi-blob metadata operation that is recorded in the [super::IntentJournal] before it is executed.
+/// If the process dies while executing it, the intent is still in the journal on the next mount and
+/// gets replayed (or rolled back for [Intent::Create]). Replaying an intent must be idempotent because
+/// we don't know how far the original operation got.
+#[binrw]
+#[brw(little)]
+#[derive(Clone, Debug)]
+pub enum Intent {
+    /// Move the entry `source_name` in the directory `source_parent` into the directory `dest_parent`.
+    /// `entry` is the moved entry, already renamed to its name in the destination directory.
+    #[brw(magic = 0u8)]
+    Move {
+        source_parent: BlobId,
+        #[br(parse_with = read_path_component)]
+        #[bw(write_with = write_path_component)]
+        source_name: PathComponentBuf,
+        dest_parent: BlobId,
+        #[br(parse_with = read_dir_entry)]
+        #[bw(write_with = write_dir_entry)]
+        entry: DirEntry,
+        #[br(temp)]
+        #[bw(calc = u8::from(overwritten.is_some()))]
+        has_overwritten: u8,
+        #[br(if(has_overwritten != 0))]
+        overwritten: Option<OverwrittenEntry>,
+    },
+
+    /// Swap the entry `first` in the directory `first_parent` with the entry `second` in the directory `second_parent`.
+    /// Both entries are stored as they were before the exchange, i.e. each one with its own name.
+    #[brw(magic = 1u8)]
+    Exchange {
+        first_parent: BlobId,
+        #[br(parse_with = read_dir_entry)]
+        #[bw(write_with = write_dir_entry)]
+        first: DirEntry,
+        second_parent: BlobId,
+        #[br(parse_with = read_dir_entry)]
+        #[bw(write_with = write_dir_entry)]
+        second: DirEntry,
+    },
+
+    /// Remove the empty directory `blob_id` that is the entry `name` in the directory `parent`.
+    #[brw(magic = 2u8)]
+    RemoveDir {
+        parent: BlobId,
+        #[br(parse_with = read_path_component)]
+        #[bw(write_with = write_path_component)]
+        name: PathComponentBuf,
+        blob_id: BlobId,
+    },
+
+    /// Create the blob `blob_id` and add it as the entry `name` to the directory `parent`.
+    /// Adding the entry is what commits this operation. Replaying it removes the blob if the entry wasn't added yet.
+    #[brw(magic = 3u8)]
+    Create {
+        parent: BlobId,
+        #[br(parse_with = read_path_component)]
+        #[bw(write_with = write_path_component)]
+        name: PathComponentBuf,
+        blob_id: BlobId,
+    },
+}
+
+/// The entry that was replaced by an [Intent::Move].
+#[binrw]
+#[brw(little)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OverwrittenEntry {
+    pub blob_id: BlobId,
+    pub entry_type: EntryType,
+    /// Link count of the overwritten blob before the move. This lets a replay find out whether
+    /// the original operation already removed the link to it.
+    pub num_links: u32,
+}
+
+fn read_dir_entry<R: Read + Seek>(reader: &mut R, _endian: Endian, _: ()) -> BinResult<DirEntry> {
+    let pos = reader.stream_position()?;
+    DirEntry::deserialize(reader).map_err(|err| binrw::Error::AssertFail {
+        pos,
+        message: format!("{err:?}"),
+    })
+}
+
+fn write_dir_entry(
+    entry: &DirEntry,
+    writer: &mut (impl Write + Seek),
+    _endian: Endian,
+    _: (),
+) -> BinResult<()> {
+    let pos = writer.stream_position()?;
+    entry
+        .serialize(writer)
+        .map_err(|err| binrw::Error::AssertFail {
+            pos,
+            message: format!("{err:?}"),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use binrw::{BinRead, BinWrite};
+    use std::io::Cursor;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+    use crate::utils::fs_types::{Gid, Mode, Uid};
+
+    fn name(name: &str) -> PathComponentBuf {
+        PathComponentBuf::try_from_string(name.to_string()).unwrap()
+    }
+
+    fn entry(entry_type: EntryType, entry_name: &str, blob_id: &str) -> DirEntry {
+        let time = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        DirEntry::new(
+            entry_type,
+            name(entry_name),
+            BlobId::from_hex(blob_id).unwrap(),
+            Mode::from(0o644),
+            Uid::from(1000),
+            Gid::from(1000),
+            time,
+            time,
+            time,
+        )
+        .unwrap()
+    }
+
+    fn roundtrip(intent: &Intent) -> Intent {
+        let mut data = Cursor::new(Vec::new());
+        intent.write(&mut data).unwrap();
+        data.set_position(0);
+        let result = Intent::read(&mut data).unwrap();
+        assert_eq!(data.get_ref().len() as u64, data.position());
+        result
+    }
+
+    #[test]
+    fn move_without_overwritten() {
+        let intent = Intent::Move {
+            source_parent: BlobId::from_hex("1491ba7ac5aa7aa02f24ff9ee2fb3ee3").unwrap(),
+            source_name: name("old"),
+            dest_parent: BlobId::from_hex("bcba5a5d2b6e7b3e1c7d2f9b4dd7bfc4").unwrap(),
+            entry: entry(EntryType::File, "new", "918ca6ac525c700c275615c3de0cea1b"),
+            overwritten: None,
+        };
+        let Intent::Move {
+            source_parent,
+            source_name,
+            dest_parent,
+            entry,
+            overwritten,
+        } = roundtrip(&intent)
+        else {
+            panic!("Wrong intent type");
+        };
+        assert_eq!(
+            BlobId::from_hex("1491ba7ac5aa7aa02f24ff9ee2fb3ee3").unwrap(),
+            source_parent
+        );
+        assert_eq!(name("old"), source_name);
+        assert_eq!(
+            BlobId::from_hex("bcba5a5d2b6e7b3e1c7d2f9b4dd7bfc4").unwrap(),
+            dest_parent
+        );
+        assert_eq!("new", entry.name().as_str());
+        assert_eq!(EntryType::File, entry.entry_type());
+        assert_eq!(None, overwritten);
+    }
+
+    #[test]
+    fn move_with_overwritten() {
+        let overwritten = OverwrittenEntry {
+            blob_id: BlobId::from_hex("f9b4dd7bfc4bcba5a5d2b6e7b3e1c7d2").unwrap(),
+            entry_type: EntryType::File,
+            num_links: 3,
+        };
+        let intent = Intent::Move {
+            source_parent: BlobId::from_hex("1491ba7ac5aa7aa02f24ff9ee2fb3ee3").unwrap(),
+            source_name: name("old"),
+            dest_parent: BlobId::from_hex("bcba5a5d2b6e7b3e1c7d2f9b4dd7bfc4").unwrap(),
+            entry: entry(EntryType::File, "new", "918ca6ac525c700c275615c3de0cea1b"),
+            overwritten: Some(overwritten),
+        };
+        let Intent::Move {
+            overwritten: read_overwritten,
+            ..
+        } = roundtrip(&intent)
+        else {
+            panic!("Wrong intent type");
+        };
+        assert_eq!(Some(overwritten), read_overwritten);
+    }
+
+    #[test]
+    fn exchange() {
+        let intent = Intent::Exchange {
+            first_parent: BlobId::from_hex("1491ba7ac5aa7aa02f24ff9ee2fb3ee3").unwrap(),
+            first: entry(EntryType::Dir, "first", "918ca6ac525c700c275615c3de0cea1b"),
+            second_parent: BlobId::from_hex("bcba5a5d2b6e7b3e1c7d2f9b4dd7bfc4").unwrap(),
+            second: entry(
+                EntryType::Symlink,
+                "second",
+                "f9b4dd7bfc4bcba5a5d2b6e7b3e1c7d2",
+            ),
+        };
+        let Intent::Exchange {
+            first_parent,
+            first,
+            second_parent,
+            second,
+        } = roundtrip(&intent)
+        else {
+            panic!("Wrong intent type");
+        };
+        assert_eq!(
+            BlobId::from_hex("1491ba7ac5aa7aa02f24ff9ee2fb3ee3").unwrap(),
+            first_parent
+        );
+        assert_eq!("first", first.name().as_str());
+        assert_eq!(EntryType::Dir, first.entry_type());
+        assert_eq!(
+            BlobId::from_hex("bcba5a5d2b6e7b3e1c7d2f9b4dd7bfc4").unwrap(),
+            second_parent
+        );
+        assert_eq!("second", second.name().as_str());
+        assert_eq!(EntryType::Symlink, second.entry_type());
+    }
+
+    #[test]
+    fn remove_dir() {
+        let intent = Intent::RemoveDir {
+            parent: BlobId::from_hex("1491ba7ac5aa7aa02f24ff9ee2fb3ee3").unwrap(),
+            name: name("dir"),
+            blob_id: BlobId::from_hex("918ca6ac525c700c275615c3de0cea1b").unwrap(),
+        };
+        let Intent::RemoveDir {
+            parent,
+            name: read_name,
+            blob_id,
+        } = roundtrip(&intent)
+        else {
+            panic!("Wrong intent type");
+        };
+        assert_eq!(
+            BlobId::from_hex("1491ba7ac5aa7aa02f24ff9ee2fb3ee3").unwrap(),
+            parent
+        );
+        assert_eq!(name("dir"), read_name);
+        assert_eq!(
+            BlobId::from_hex("918ca6ac525c700c275615c3de0cea1b").unwrap(),
+            blob_id
+        );
+    }
+
+    #[test]
+    fn create() {
+        let intent = Intent::Create {
+            parent: BlobId::from_hex("1491ba7ac5aa7aa02f24ff9ee2fb3ee3").unwrap(),
+            name: name("file"),
+            blob_id: BlobId::from_hex("918ca6ac525c700c275615c3de0cea1b").unwrap(),
+        };
+        let Intent::Create {
+            parent,
+            name: read_name,
+            blob_id,
+        } = roundtrip(&intent)
+        else {
+            panic!("Wrong intent type");
+        };
+        assert_eq!(
+            BlobId::from_hex("1491ba7ac5aa7aa02f24ff9ee2fb3ee3").unwrap(),
+            parent
+        );
+        assert_eq!(name("file"), read_name);
+        assert_eq!(
+            BlobId::from_hex("918ca6ac525c700c275615c3de0cea1b").unwrap(),
+            blob_id
+        );
+    }
+}
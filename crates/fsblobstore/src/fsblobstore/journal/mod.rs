@@ -0,0 +1,201 @@
+use anyhow::{Context as _, Result};
+use binrw::{BinRead, BinWrite, binrw};
+use std::io::Cursor;
+
+use cryfs_blobstore::{BLOBID_LEN, Blob, BlobId, BlobStore};
+use cryfs_utils::async_drop::{AsyncDrop, AsyncDropGuard};
+
+mod intent;
+pub use intent::{Intent, OverwrittenEntry};
+
+/// The journal is stored in a blob whose id is derived from the root blob id,
+/// so we can find it on mount without storing its id anywhere.
+pub fn journal_blob_id(root_blob_id: &BlobId) -> BlobId {
+    let mut id: [u8; BLOBID_LEN] = *root_blob_id.data();
+    for byte in &mut id {
+        *byte = !*byte;
+    }
+    BlobId::from_array(&id)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct IntentId(u64);
+
+#[binrw]
+#[brw(little, magic = b"cryfs.journal;0\0")]
+#[derive(Debug, Default)]
+struct JournalContent {
+    #[br(temp)]
+    #[bw(calc = u32::try_from(intents.len()).expect("Too many intents"))]
+    num_intents: u32,
+    #[br(count = num_intents)]
+    intents: Vec<Intent>,
+}
+
+/// Write-ahead journal for metadata operations that modify more than one blob, e.g. moving an entry between directories.
+/// An operation adds its [Intent] to the journal before it modifies any blobs and removes it once all modified blobs are flushed.
+/// If the process dies in between, the intent is still in the journal when the file system is mounted the next time.
+///
+/// Adding an intent flushes it from our caches but doesn't [BlobStore::sync] the underlying storage,
+/// i.e. whether it survives a power loss depends on the fsync policy of the block store.
+///
+/// The journal blob is only loaded while we write it. Keeping it loaded would keep its blocks locked,
+/// which blocks anyone waiting for all blocks to be released, e.g. when clearing the cache.
+///
+/// CryFS 1.0 doesn't know about the journal blob, so file systems in the
+/// [FsFormat::Cryfs1Compatible](crate::fsblobstore::FsFormat::Cryfs1Compatible) format don't get one,
+/// see [Self::in_memory].
+#[derive(Debug)]
+pub struct IntentJournal {
+    // `None` if the journal is only kept in memory
+    blob_id: Option<BlobId>,
+    next_id: u64,
+    pending: Vec<(IntentId, Intent)>,
+}
+
+impl IntentJournal {
+    /// Load the journal from the blob `blob_id`, or create an empty journal there if it doesn't exist yet.
+    /// File systems created before the journal was introduced don't have a journal blob yet.
+    pub async fn load_or_create<B>(blobstore: &B, blob_id: &BlobId) -> Result<Self>
+    where
+        B: BlobStore,
+        B::ConcreteBlob: AsyncDrop<Error = anyhow::Error>,
+    {
+        if let Some(mut blob) = blobstore.load(blob_id).await? {
+            let content = Self::_read_content(&mut *blob).await;
+            blob.async_drop().await?;
+            let pending: Vec<(IntentId, Intent)> = content?
+                .intents
+                .into_iter()
+                .enumerate()
+                .map(|(index, intent)| (IntentId(index as u64), intent))
+                .collect();
+            Ok(Self {
+                blob_id: Some(*blob_id),
+                next_id: pending.len() as u64,
+                pending,
+            })
+        } else {
+            let blob = blobstore
+                .try_create(blob_id)
+                .await?
+                .with_context(|| format!("Journal blob {blob_id:?} was created concurrently"))?;
+            let journal = Self {
+                blob_id: Some(*blob_id),
+                next_id: 0,
+                pending: vec![],
+            };
+            journal._write_to(blob).await?;
+            Ok(journal)
+        }
+    }
+
+    /// Create a journal that isn't stored anywhere. Operations still go through it, but if we crash in the middle of one,
+    /// it won't be finished on the next mount, i.e. multi-blob operations aren't crash-safe with this journal.
+    pub fn in_memory() -> Self {
+        Self {
+            blob_id: None,
+            next_id: 0,
+            pending: vec![],
+        }
+    }
+
+    async fn _read_content<Blb: Blob>(blob: &mut Blb) -> Result<JournalContent> {
+        let data = blob.read_all().await?;
+        let content = JournalContent::read(&mut Cursor::new(data.as_ref()))
+            .with_context(|| format!("Failed to parse journal blob {:?}", blob.id()))?;
+        Ok(content)
+    }
+
+    /// Intents of operations that didn't finish, in the order they were added.
+    pub fn pending_intents(&self) -> impl Iterator<Item = (IntentId, &Intent)> {
+        self.pending.iter().map(|(id, intent)| (*id, intent))
+    }
+
+    /// Add an intent and flush it before returning, so the caller can start modifying blobs.
+    pub async fn add<B>(&mut self, blobstore: &B, intent: Intent) -> Result<IntentId>
+    where
+        B: BlobStore,
+        B::ConcreteBlob: AsyncDrop<Error = anyhow::Error>,
+    {
+        let id = IntentId(self.next_id);
+        self.next_id += 1;
+        self.pending.push((id, intent));
+        if let Err(err) = self._write(blobstore).await {
+            self.pending.pop();
+            return Err(err);
+        }
+        Ok(id)
+    }
+
+    /// Remove an intent after its operation finished and all blobs it modified are flushed.
+    /// This is flushed as well, because replaying an intent after later operations modified the same entries could undo those operations.
+    pub async fn remove<B>(&mut self, blobstore: &B, id: IntentId) -> Result<()>
+    where
+        B: BlobStore,
+        B::ConcreteBlob: AsyncDrop<Error = anyhow::Error>,
+    {
+        let Some(index) = self
+            .pending
+            .iter()
+            .position(|(pending_id, _)| *pending_id == id)
+        else {
+            panic!("Tried to remove intent {id:?} that isn't in the journal");
+        };
+        self.pending.remove(index);
+        self._write(blobstore).await
+    }
+
+    async fn _write<B>(&self, blobstore: &B) -> Result<()>
+    where
+        B: BlobStore,
+        B::ConcreteBlob: AsyncDrop<Error = anyhow::Error>,
+    {
+        let Some(blob_id) = &self.blob_id else {
+            return Ok(());
+        };
+        let blob = blobstore
+            .load(blob_id)
+            .await?
+            .with_context(|| format!("Journal blob {blob_id:?} not found"))?;
+        self._write_to(blob).await
+    }
+
+    async fn _write_to<Blb>(&self, mut blob: AsyncDropGuard<Blb>) -> Result<()>
+    where
+        Blb: Blob + AsyncDrop<Error = anyhow::Error>,
+    {
+        let content = JournalContent {
+            intents: self
+                .pending
+                .iter()
+                .map(|(_, intent)| intent.clone())
+                .collect(),
+        };
+        let mut data = Cursor::new(Vec::new());
+        let write_result = async {
+            content.write(&mut data)?;
+            let data = data.into_inner();
+            blob.resize(data.len() as u64).await?;
+            blob.write(&data, 0).await?;
+            blob.flush().await
+        }
+        .await;
+        blob.async_drop().await?;
+        write_result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn journal_blob_id_differs_from_root_blob_id() {
+        let root_blob_id = BlobId::from_hex("918ca6ac525c700c275615c3de0cea1b").unwrap();
+        assert_eq!(
+            BlobId::from_hex("6e735953ada38ff3d8a9ea3c21f315e4").unwrap(),
+            journal_blob_id(&root_blob_id)
+        );
+    }
+}
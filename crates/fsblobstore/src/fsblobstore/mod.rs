@@ -6,13 +6,19 @@ use std::fmt::Debug;
 use cryfs_blobstore::{BlobId, BlobStore, RemoveResult};
 use cryfs_utils::async_drop::{AsyncDrop, AsyncDropGuard};
 
+mod format;
 mod fsblob;
+mod journal;
+
+pub use format::FsFormat;
 
 pub use fsblob::{
     AddError, AddOrOverwriteError, AtimeUpdateBehavior, BlobType, DIR_LSTAT_SIZE, DirBlob,
-    DirEntry, EntryType, FileBlob, FsBlob, MODE_NEW_SYMLINK, RemoveError, RenameError,
-    SetAttrError, SymlinkBlob, UpdateTimestampError,
+    DirEntry, EntryType, FileBlob, FsBlob, MAX_XATTR_NAME_LEN, MAX_XATTR_VALUE_LEN,
+    MAX_XATTRS_TOTAL_LEN, MODE_NEW_SYMLINK, RemoveError, RenameError, ReplaceError, SetAttrError,
+    SetXattrError, SymlinkBlob, UpdateTimestampError, Xattrs,
 };
+pub use journal::{Intent, IntentId, IntentJournal, OverwrittenEntry, journal_blob_id};
 
 // TODO With an adapter we can run block store tests on this, similar to how we do it for BlobStore
 // TODO Add a CachingFsBlobStore, that currently only exists in C++.
@@ -25,6 +31,7 @@ where
     <B as BlobStore>::ConcreteBlob: Send + AsyncDrop<Error = anyhow::Error>,
 {
     blobstore: AsyncDropGuard<B>,
+    format: FsFormat,
 }
 
 impl<B> FsBlobStore<B>
@@ -32,8 +39,12 @@ where
     B: BlobStore + AsyncDrop<Error = anyhow::Error> + Debug + Send + 'static,
     <B as BlobStore>::ConcreteBlob: Send + AsyncDrop<Error = anyhow::Error>,
 {
-    pub fn new(blobstore: AsyncDropGuard<B>) -> AsyncDropGuard<Self> {
-        AsyncDropGuard::new(Self { blobstore })
+    pub fn new(blobstore: AsyncDropGuard<B>, format: FsFormat) -> AsyncDropGuard<Self> {
+        AsyncDropGuard::new(Self { blobstore, format })
+    }
+
+    pub fn format(&self) -> FsFormat {
+        self.format
     }
 
     pub async fn create_root_dir_blob(
@@ -41,16 +52,18 @@ where
         root_blob_id: &BlobId,
     ) -> Result<AsyncDropGuard<fsblob::FsBlob<B>>> {
         Ok(AsyncDropGuard::new(FsBlob::Directory(
-            DirBlob::create_root_dir_blob(&*self.blobstore, root_blob_id).await?,
+            DirBlob::create_root_dir_blob(&*self.blobstore, root_blob_id, self.format).await?,
         )))
     }
 
     pub async fn create_file_blob<'a>(
         &'a self,
+        blob_id: &BlobId,
         parent: &BlobId,
         flush_behavior: FlushBehavior,
     ) -> Result<AsyncDropGuard<fsblob::FsBlob<B>>> {
-        let mut file_blob = FileBlob::create_blob(&*self.blobstore, parent).await?;
+        let mut file_blob =
+            FileBlob::create_blob(&*self.blobstore, blob_id, parent, self.format).await?;
         match flush_behavior {
             FlushBehavior::FlushImmediately => {
                 file_blob.flush().await?;
@@ -62,10 +75,12 @@ where
 
     pub async fn create_dir_blob<'a>(
         &'a self,
+        blob_id: &BlobId,
         parent: &BlobId,
         flush_behavior: FlushBehavior,
     ) -> Result<AsyncDropGuard<fsblob::FsBlob<B>>> {
-        let mut dir_blob = DirBlob::create_blob(&*self.blobstore, parent).await?;
+        let mut dir_blob =
+            DirBlob::create_blob(&*self.blobstore, blob_id, parent, self.format).await?;
         match flush_behavior {
             FlushBehavior::FlushImmediately => {
                 dir_blob.flush().await?;
@@ -77,11 +92,14 @@ where
 
     pub async fn create_symlink_blob<'a>(
         &'a self,
+        blob_id: &BlobId,
         parent: &BlobId,
         target: &str,
         flush_behavior: FlushBehavior,
     ) -> Result<AsyncDropGuard<fsblob::FsBlob<B>>> {
-        let mut symlink_blob = SymlinkBlob::create_blob(&*self.blobstore, parent, target).await?;
+        let mut symlink_blob =
+            SymlinkBlob::create_blob(&*self.blobstore, blob_id, parent, target, self.format)
+                .await?;
         match flush_behavior {
             FlushBehavior::FlushImmediately => {
                 symlink_blob.flush().await?;
@@ -93,12 +111,34 @@ where
 
     pub async fn load<'a>(&'a self, blob_id: &BlobId) -> Result<Option<AsyncDropGuard<FsBlob<B>>>> {
         if let Some(blob) = self.blobstore.load(blob_id).await? {
-            Ok(Some(FsBlob::parse(blob).await?))
+            Ok(Some(FsBlob::parse(blob, self.format).await?))
         } else {
             Ok(None)
         }
     }
 
+    /// Load the [IntentJournal] stored in the blob `journal_blob_id`, or create an empty one if it doesn't exist yet.
+    /// File systems in the [FsFormat::Cryfs1Compatible] format don't get a journal blob, they only get an in-memory journal.
+    pub async fn load_or_create_journal(&self, journal_blob_id: &BlobId) -> Result<IntentJournal> {
+        if self.format.is_extended() {
+            IntentJournal::load_or_create(&*self.blobstore, journal_blob_id).await
+        } else {
+            Ok(IntentJournal::in_memory())
+        }
+    }
+
+    pub async fn add_intent(
+        &self,
+        journal: &mut IntentJournal,
+        intent: Intent,
+    ) -> Result<IntentId> {
+        journal.add(&*self.blobstore, intent).await
+    }
+
+    pub async fn complete_intent(&self, journal: &mut IntentJournal, id: IntentId) -> Result<()> {
+        journal.remove(&*self.blobstore, id).await
+    }
+
     pub async fn num_blocks(&self) -> Result<u64> {
         self.blobstore.num_nodes().await
     }
@@ -120,7 +160,7 @@ where
         this.unsafe_into_inner_dont_drop().blobstore
     }
 
-    #[cfg(feature = "testutils")]
+    #[cfg(any(test, feature = "testutils"))]
     pub async fn clear_cache_slow(&self) -> Result<()> {
         self.blobstore.clear_cache_slow().await
     }
@@ -128,6 +168,10 @@ where
     pub async fn flush_if_cached(&self, blob_id: BlobId) -> Result<()> {
         self.blobstore.flush_if_cached(blob_id).await
     }
+
+    pub async fn sync(&self) -> Result<()> {
+        self.blobstore.sync().await
+    }
 }
 
 pub enum FlushBehavior {
@@ -31,6 +31,10 @@ const S_IFMT: Mode = Mode(0o170000);
 const S_IFDIR: Mode = Mode(0o040000);
 const S_IFREG: Mode = Mode(0o100000);
 const S_IFLNK: Mode = Mode(0o120000);
+const S_IFIFO: Mode = Mode(0o010000);
+const S_IFSOCK: Mode = Mode(0o140000);
+const S_IFCHR: Mode = Mode(0o020000);
+const S_IFBLK: Mode = Mode(0o060000);
 
 const S_IRUSR: Mode = Mode(0o000400);
 const S_IWUSR: Mode = Mode(0o000200);
@@ -63,6 +67,26 @@ impl Mode {
         (self.0 & S_IFMT.0) == S_IFLNK.0
     }
 
+    #[allow(non_snake_case)]
+    const fn S_ISFIFO(self) -> bool {
+        (self.0 & S_IFMT.0) == S_IFIFO.0
+    }
+
+    #[allow(non_snake_case)]
+    const fn S_ISSOCK(self) -> bool {
+        (self.0 & S_IFMT.0) == S_IFSOCK.0
+    }
+
+    #[allow(non_snake_case)]
+    const fn S_ISCHR(self) -> bool {
+        (self.0 & S_IFMT.0) == S_IFCHR.0
+    }
+
+    #[allow(non_snake_case)]
+    const fn S_ISBLK(self) -> bool {
+        (self.0 & S_IFMT.0) == S_IFBLK.0
+    }
+
     pub const fn with_file_flag(mut self) -> Self {
         self.0 |= S_IFREG.0;
         self
@@ -78,6 +102,26 @@ impl Mode {
         self
     }
 
+    pub const fn with_fifo_flag(mut self) -> Self {
+        self.0 |= S_IFIFO.0;
+        self
+    }
+
+    pub const fn with_socket_flag(mut self) -> Self {
+        self.0 |= S_IFSOCK.0;
+        self
+    }
+
+    pub const fn with_char_device_flag(mut self) -> Self {
+        self.0 |= S_IFCHR.0;
+        self
+    }
+
+    pub const fn with_block_device_flag(mut self) -> Self {
+        self.0 |= S_IFBLK.0;
+        self
+    }
+
     pub const fn with_user_read_flag(mut self) -> Self {
         self.0 |= S_IRUSR.0;
         self
@@ -134,4 +178,20 @@ impl Mode {
     pub const fn has_symlink_flag(self) -> bool {
         self.S_ISLNK()
     }
+
+    pub const fn has_fifo_flag(self) -> bool {
+        self.S_ISFIFO()
+    }
+
+    pub const fn has_socket_flag(self) -> bool {
+        self.S_ISSOCK()
+    }
+
+    pub const fn has_char_device_flag(self) -> bool {
+        self.S_ISCHR()
+    }
+
+    pub const fn has_block_device_flag(self) -> bool {
+        self.S_ISBLK()
+    }
 }
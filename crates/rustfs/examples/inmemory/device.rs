@@ -3,7 +3,7 @@ use std::fmt::Debug;
 use std::sync::{Arc, Mutex};
 
 use cryfs_rustfs::{
-    FsError, FsResult, Gid, Mode, Statfs, Uid,
+    FsError, FsResult, Gid, Mode, RenameMode, Statfs, Uid,
     object_based_api::{Device, Node},
 };
 use cryfs_utils::{
@@ -13,7 +13,7 @@ use cryfs_utils::{
     with_async_drop_2,
 };
 
-use super::dir::{DirInode, InMemoryDirRef};
+use super::dir::{DirInode, InMemoryDirRef, move_entry};
 use super::file::{InMemoryFileRef, InMemoryOpenFileRef};
 use super::node::InMemoryNodeRef;
 use super::symlink::InMemorySymlinkRef;
@@ -103,6 +103,7 @@ impl Device for InMemoryDevice {
         &self,
         from_path: &AbsolutePath,
         to_path: &AbsolutePath,
+        mode: RenameMode,
     ) -> impl Future<Output = FsResult<()>> {
         async move {
             // TODO Go through CryNode assertions (C++) and check if we should do them here too,
@@ -124,7 +125,7 @@ impl Device for InMemoryDevice {
                 with_async_drop_2!(new_parent, {
                     if old_parent_path == new_parent_path {
                         // We're just renaming it within one directory
-                        new_parent.rename(old_name, new_name)
+                        new_parent.rename(old_name, new_name, mode)
                     } else {
                         let source_parent = self.rootdir.load_node(old_parent_path)?;
                         with_async_drop_2!(source_parent, {
@@ -133,27 +134,13 @@ impl Device for InMemoryDevice {
                                 // We're moving it to another directory
                                 let (mut source_inode, mut target_inode) =
                                     lock_in_ptr_order(&source_parent.inode(), &new_parent.inode());
-                                let source_entries = source_inode.entries_mut();
-                                let target_entries = target_inode.entries_mut();
-                                if target_entries.contains_key(new_name) {
-                                    // TODO Some forms of overwriting are actually ok, we don't need to block them all
-                                    Err(FsError::NodeAlreadyExists)
-                                } else {
-                                    let old_entry = match source_entries.remove(old_name) {
-                                        Some(node) => node,
-                                        None => {
-                                            return Err(FsError::NodeDoesNotExist);
-                                        }
-                                    };
-                                    // TODO Use try_insert once stable
-                                    let insert_result =
-                                        target_entries.insert(new_name.to_owned(), old_entry);
-                                    assert!(
-                                        insert_result.is_none(),
-                                        "We checked above that `new_name` doesn't exist in the map. Inserting it shouldn't fail."
-                                    );
-                                    Ok(())
-                                }
+                                move_entry(
+                                    source_inode.entries_mut(),
+                                    old_name,
+                                    target_inode.entries_mut(),
+                                    new_name,
+                                    mode,
+                                )
                             })
                         })
                     }
@@ -1,7 +1,7 @@
 use async_trait::async_trait;
 use cryfs_rustfs::{
-    DirEntry, FsError, FsResult, Gid, Mode, NodeAttrs, NodeKind, NumBytes, OpenInFlags, Uid,
-    object_based_api::Dir,
+    DirEntry, FsError, FsResult, Gid, Mode, NodeAttrs, NodeKind, NumBytes, OpenInFlags, RenameMode,
+    Uid, object_based_api::Dir,
 };
 use cryfs_utils::{
     async_drop::{AsyncDrop, AsyncDropGuard},
@@ -41,6 +41,7 @@ mod inode {
                     gid,
                     num_bytes: NumBytes::from(0),
                     num_blocks: None,
+                    rdev: 0,
                     atime: SystemTime::now(),
                     mtime: SystemTime::now(),
                     ctime: SystemTime::now(),
@@ -77,8 +78,29 @@ mod inode {
             &mut self.entries
         }
 
-        pub fn rename(&mut self, from: &PathComponent, to: &PathComponent) -> FsResult<()> {
-            if self.entries.contains_key(to) {
+        pub fn rename(
+            &mut self,
+            from: &PathComponent,
+            to: &PathComponent,
+            mode: RenameMode,
+        ) -> FsResult<()> {
+            if mode == RenameMode::Exchange {
+                if !self.entries.contains_key(from) || !self.entries.contains_key(to) {
+                    return Err(FsError::NodeDoesNotExist);
+                }
+                if from != to {
+                    let from_entry = self
+                        .entries
+                        .remove(from)
+                        .expect("We checked above that it exists");
+                    let to_entry = self
+                        .entries
+                        .insert(to.to_owned(), from_entry)
+                        .expect("We checked above that it exists");
+                    self.entries.insert(from.to_owned(), to_entry);
+                }
+                Ok(())
+            } else if self.entries.contains_key(to) {
                 // TODO Some forms of overwriting are actually ok, we don't need to block them all
                 Err(FsError::NodeAlreadyExists)
             } else {
@@ -156,8 +178,13 @@ impl InMemoryDirRef {
             .setattr(mode, uid, gid, atime, mtime, ctime)
     }
 
-    pub fn rename(&self, from: &PathComponent, to: &PathComponent) -> FsResult<()> {
-        self.inode.lock().unwrap().rename(from, to)
+    pub fn rename(
+        &self,
+        from: &PathComponent,
+        to: &PathComponent,
+        mode: RenameMode,
+    ) -> FsResult<()> {
+        self.inode.lock().unwrap().rename(from, to, mode)
     }
 
     // TODO Don't let `Arc` escape here, rather return `&Mutex`
@@ -183,9 +210,14 @@ impl Dir for InMemoryDirRef {
         Ok(AsyncDropGuard::new(self.get_child(name)?))
     }
 
-    async fn rename_child(&self, oldname: &PathComponent, newname: &PathComponent) -> FsResult<()> {
+    async fn rename_child(
+        &self,
+        oldname: &PathComponent,
+        newname: &PathComponent,
+        mode: RenameMode,
+    ) -> FsResult<()> {
         let mut inode = self.inode.lock().unwrap();
-        inode.rename(oldname, newname)
+        inode.rename(oldname, newname, mode)
     }
 
     async fn move_child_to(
@@ -193,31 +225,19 @@ impl Dir for InMemoryDirRef {
         oldname: &PathComponent,
         newparent: AsyncDropGuard<Self>,
         newname: &PathComponent,
+        mode: RenameMode,
     ) -> FsResult<()> {
         with_async_drop_2!(newparent, {
             // We're moving it to another directory
             let (mut source_inode, mut target_inode) =
                 lock_in_ptr_order(&self.inode(), &newparent.inode());
-            let source_entries = source_inode.entries_mut();
-            let target_entries = target_inode.entries_mut();
-            if target_entries.contains_key(newname) {
-                // TODO Some forms of overwriting are actually ok, we don't need to block them all
-                Err(FsError::NodeAlreadyExists)
-            } else {
-                let old_entry = match source_entries.remove(oldname) {
-                    Some(node) => node,
-                    None => {
-                        return Err(FsError::NodeDoesNotExist);
-                    }
-                };
-                // TODO Use try_insert once stable
-                let insert_result = target_entries.insert(newname.to_owned(), old_entry);
-                assert!(
-                    insert_result.is_none(),
-                    "We checked above that `new_name` doesn't exist in the map. Inserting it shouldn't fail."
-                );
-                Ok(())
-            }
+            move_entry(
+                source_inode.entries_mut(),
+                oldname,
+                target_inode.entries_mut(),
+                newname,
+                mode,
+            )
         })
     }
 
@@ -298,6 +318,27 @@ impl Dir for InMemoryDirRef {
         Ok((metadata, AsyncDropGuard::new(symlink)))
     }
 
+    async fn create_child_link(
+        &self,
+        _name: &PathComponent,
+        _node: &InMemoryNodeRef,
+    ) -> FsResult<(NodeAttrs, AsyncDropGuard<InMemoryNodeRef>)> {
+        // TODO Implement hard links
+        Err(FsError::NotImplemented)
+    }
+
+    async fn create_child_special_file(
+        &self,
+        _name: &PathComponent,
+        _mode: Mode,
+        _uid: Uid,
+        _gid: Gid,
+        _rdev: u32,
+    ) -> FsResult<(NodeAttrs, AsyncDropGuard<InMemoryNodeRef>)> {
+        // TODO Implement fifos, sockets and device nodes
+        Err(FsError::NotImplemented)
+    }
+
     async fn remove_child_file_or_symlink(&self, name: &PathComponent) -> FsResult<()> {
         let mut inode = self.inode.lock().unwrap();
         // TODO Use try_insert once that is stable
@@ -362,3 +403,41 @@ impl AsyncDrop for InMemoryDirRef {
         Ok(())
     }
 }
+
+/// Move the entry `oldname` from `source_entries` to `newname` in `target_entries`,
+/// or swap the two entries if `mode` is [RenameMode::Exchange].
+pub(super) fn move_entry(
+    source_entries: &mut HashMap<PathComponentBuf, InMemoryNodeRef>,
+    oldname: &PathComponent,
+    target_entries: &mut HashMap<PathComponentBuf, InMemoryNodeRef>,
+    newname: &PathComponent,
+    mode: RenameMode,
+) -> FsResult<()> {
+    if mode == RenameMode::Exchange {
+        let (Some(source_entry), Some(target_entry)) = (
+            source_entries.get_mut(oldname),
+            target_entries.get_mut(newname),
+        ) else {
+            return Err(FsError::NodeDoesNotExist);
+        };
+        std::mem::swap(source_entry, target_entry);
+        Ok(())
+    } else if target_entries.contains_key(newname) {
+        // TODO Some forms of overwriting are actually ok, we don't need to block them all
+        Err(FsError::NodeAlreadyExists)
+    } else {
+        let old_entry = match source_entries.remove(oldname) {
+            Some(node) => node,
+            None => {
+                return Err(FsError::NodeDoesNotExist);
+            }
+        };
+        // TODO Use try_insert once stable
+        let insert_result = target_entries.insert(newname.to_owned(), old_entry);
+        assert!(
+            insert_result.is_none(),
+            "We checked above that `new_name` doesn't exist in the map. Inserting it shouldn't fail."
+        );
+        Ok(())
+    }
+}
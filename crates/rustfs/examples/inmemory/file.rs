@@ -1,6 +1,7 @@
 use async_trait::async_trait;
 use cryfs_rustfs::{
-    Data, FsError, FsResult, Gid, Mode, NodeAttrs, NumBytes, OpenInFlags, Uid,
+    Data, FallocateMode, FsError, FsResult, Gid, Mode, NodeAttrs, NumBytes, OpenInFlags,
+    SeekWhence, Uid,
     object_based_api::{File, OpenFile},
 };
 use cryfs_utils::async_drop::{AsyncDrop, AsyncDropGuard};
@@ -33,6 +34,7 @@ mod inode {
                     gid,
                     num_bytes: NumBytes::from(0),
                     num_blocks: None,
+                    rdev: 0,
                     atime: SystemTime::now(),
                     mtime: SystemTime::now(),
                     ctime: SystemTime::now(),
@@ -228,6 +230,86 @@ impl OpenFile for InMemoryOpenFileRef {
         }
     }
 
+    async fn fallocate(
+        &self,
+        offset: NumBytes,
+        length: NumBytes,
+        mode: FallocateMode,
+    ) -> FsResult<()> {
+        match self.openflags {
+            OpenInFlags::Read => Err(FsError::WriteOnReadOnlyFileDescriptor),
+            OpenInFlags::Write | OpenInFlags::ReadWrite => {
+                let inode = &mut self.inode.lock().unwrap();
+                let end = offset + length;
+                let keep_size = match mode {
+                    FallocateMode::Allocate { keep_size } => keep_size,
+                    FallocateMode::PunchHole => true,
+                    FallocateMode::ZeroRange { keep_size } => keep_size,
+                };
+                if !keep_size && end > inode.len() {
+                    inode.resize(end);
+                }
+                if matches!(
+                    mode,
+                    FallocateMode::PunchHole | FallocateMode::ZeroRange { .. }
+                ) {
+                    // We don't store holes, but the range needs to read as zeroes
+                    let len = usize::try_from(u64::from(inode.len())).unwrap();
+                    let begin = usize::try_from(u64::from(offset)).unwrap().min(len);
+                    let end = usize::try_from(u64::from(end)).unwrap().min(len);
+                    inode.data_mut()[begin..end].fill(0);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    async fn lseek(&self, offset: NumBytes, whence: SeekWhence) -> FsResult<NumBytes> {
+        let len = self.inode.lock().unwrap().len();
+        if offset >= len {
+            return Err(FsError::OffsetBeyondEndOfFile);
+        }
+        // We don't store holes, the whole file is data and the only hole is at its end
+        match whence {
+            SeekWhence::Data => Ok(offset),
+            SeekWhence::Hole => Ok(len),
+        }
+    }
+
+    async fn copy_file_range(
+        &self,
+        offset_in: NumBytes,
+        dest: &Self,
+        offset_out: NumBytes,
+        len: NumBytes,
+    ) -> FsResult<NumBytes> {
+        if self.openflags == OpenInFlags::Write {
+            return Err(FsError::ReadOnWriteOnlyFileDescriptor);
+        }
+        if dest.openflags == OpenInFlags::Read {
+            return Err(FsError::WriteOnReadOnlyFileDescriptor);
+        }
+        // Copy the data out first so we don't have to lock both inodes at once, they could be the same inode
+        let data = {
+            let inode = self.inode.lock().unwrap();
+            let data = inode.data();
+            let offset = usize::try_from(u64::from(offset_in))
+                .unwrap()
+                .min(data.len());
+            let len = usize::try_from(u64::from(len)).unwrap();
+            let actually_read = std::cmp::min(len, data.len() - offset);
+            data[offset..offset + actually_read].to_vec()
+        };
+        let data_len = NumBytes::from(u64::try_from(data.len()).unwrap());
+        let inode = &mut dest.inode.lock().unwrap();
+        if offset_out + data_len > inode.len() {
+            inode.resize(offset_out + data_len);
+        }
+        let offset_out = usize::try_from(u64::from(offset_out)).unwrap();
+        inode.data_mut()[offset_out..offset_out + data.len()].copy_from_slice(&data);
+        Ok(data_len)
+    }
+
     async fn flush(&self) -> FsResult<()> {
         // TODO Is flush allowed when openflags are readonly?
         // No need to flush because we're in-memory
@@ -5,7 +5,7 @@ mod inode_metadata;
 mod node;
 mod symlink;
 
-use cryfs_rustfs::object_based_api::ObjectBasedFsAdapterLL;
+use cryfs_rustfs::{KernelCacheOptions, object_based_api::ObjectBasedFsAdapterLL};
 use device::InMemoryDevice;
 
 const USAGE: &str = "Usage: inmemoryfs [mountdir]";
@@ -28,11 +28,12 @@ fn main() {
         .unwrap();
     runtime
         .block_on(cryfs_rustfs::backend::fuser::mount(
-            ObjectBasedFsAdapterLL::new(fs),
+            ObjectBasedFsAdapterLL::new(fs, KernelCacheOptions::default()),
             mountdir,
             runtime.handle().clone(),
             None,
             &[],
+            KernelCacheOptions::default(),
             || {},
         ))
         .unwrap();
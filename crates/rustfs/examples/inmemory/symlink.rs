@@ -38,6 +38,7 @@ mod inode {
                     gid,
                     num_bytes: NumBytes::from(0),
                     num_blocks: None,
+                    rdev: 0,
                     atime: SystemTime::now(),
                     mtime: SystemTime::now(),
                     ctime: SystemTime::now(),
@@ -1,7 +1,7 @@
 use async_trait::async_trait;
 
-use super::errors::{IoResultExt, NixResultExt};
-use cryfs_rustfs::{FsError, FsResult, Statfs, object_based_api::Device};
+use super::errors::NixResultExt;
+use cryfs_rustfs::{FsError, FsResult, RenameMode, Statfs, object_based_api::Device};
 use cryfs_utils::{
     async_drop::{AsyncDrop, AsyncDropGuard},
     path::{AbsolutePath, AbsolutePathBuf},
@@ -12,6 +12,7 @@ use super::file::PassthroughFile;
 use super::node::PassthroughNode;
 use super::openfile::PassthroughOpenFile;
 use super::symlink::PassthroughSymlink;
+use super::utils::rename;
 
 #[derive(Debug)]
 pub struct PassthroughDevice {
@@ -62,14 +63,14 @@ impl Device for PassthroughDevice {
         &self,
         from_path: &AbsolutePath,
         to_path: &AbsolutePath,
+        mode: RenameMode,
     ) -> impl Future<Output = FsResult<()>> {
         async move {
             // TODO Build AbsolutePathBuf::join(&self, &AbsolutePath) and join_all, which can be more efficient because clone+push likely causes two reallocations.
             //      Then grep the codebase for the clone().push{_all} pattern and replate it
             let old_path = self.basedir.clone().push_all(from_path);
             let new_path = self.basedir.clone().push_all(to_path);
-            tokio::fs::rename(old_path, new_path).await.map_error()?;
-            Ok(())
+            rename(old_path, new_path, mode).await
         }
     }
 
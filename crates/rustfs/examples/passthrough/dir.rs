@@ -1,6 +1,6 @@
 use async_trait::async_trait;
 use cryfs_rustfs::{
-    DirEntry, FsError, FsResult, Gid, Mode, NodeAttrs, NodeKind, OpenInFlags, Uid,
+    DirEntry, FsError, FsResult, Gid, Mode, NodeAttrs, NodeKind, OpenInFlags, RenameMode, Uid,
     object_based_api::{Dir, Node},
 };
 use cryfs_utils::{
@@ -9,7 +9,7 @@ use cryfs_utils::{
     with_async_drop_2,
 };
 use nix::fcntl::{AT_FDCWD, AtFlags};
-use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::fs::{FileTypeExt as _, OpenOptionsExt};
 use tokio::fs::OpenOptions;
 
 use super::device::PassthroughDevice;
@@ -17,7 +17,7 @@ use super::errors::{IoResultExt, NixResultExt};
 use super::node::PassthroughNode;
 use super::openfile::PassthroughOpenFile;
 use super::symlink::PassthroughSymlink;
-use super::utils::convert_metadata;
+use super::utils::{convert_metadata, rename};
 
 #[derive(Debug)]
 pub struct PassthroughDir {
@@ -51,10 +51,15 @@ impl Dir for PassthroughDir {
         Ok(PassthroughNode::new(child_path))
     }
 
-    async fn rename_child(&self, oldname: &PathComponent, newname: &PathComponent) -> FsResult<()> {
+    async fn rename_child(
+        &self,
+        oldname: &PathComponent,
+        newname: &PathComponent,
+        mode: RenameMode,
+    ) -> FsResult<()> {
         let old_path = self.path.clone().push(oldname);
         let new_path = self.path.clone().push(newname);
-        tokio::fs::rename(old_path, new_path).await.map_error()
+        rename(old_path, new_path, mode).await
     }
 
     async fn move_child_to(
@@ -62,11 +67,12 @@ impl Dir for PassthroughDir {
         oldname: &PathComponent,
         newparent: AsyncDropGuard<Self>,
         newname: &PathComponent,
+        mode: RenameMode,
     ) -> FsResult<()> {
         with_async_drop_2!(newparent, {
             let old_path = self.path.clone().push(oldname);
             let new_path = newparent.path.clone().push(newname);
-            tokio::fs::rename(old_path, new_path).await.map_error()
+            rename(old_path, new_path, mode).await
         })
     }
 
@@ -92,6 +98,14 @@ impl Dir for PassthroughDir {
                 NodeKind::Dir
             } else if node_type.is_symlink() {
                 NodeKind::Symlink
+            } else if node_type.is_fifo() {
+                NodeKind::Fifo
+            } else if node_type.is_socket() {
+                NodeKind::Socket
+            } else if node_type.is_char_device() {
+                NodeKind::CharDevice
+            } else if node_type.is_block_device() {
+                NodeKind::BlockDevice
             } else {
                 panic!(
                     "Unknown node type in {path:?} : {entry:?}",
@@ -179,6 +193,27 @@ impl Dir for PassthroughDir {
         })
     }
 
+    async fn create_child_link(
+        &self,
+        _name: &PathComponent,
+        _node: &PassthroughNode,
+    ) -> FsResult<(NodeAttrs, AsyncDropGuard<PassthroughNode>)> {
+        // TODO Implement hard links
+        Err(FsError::NotImplemented)
+    }
+
+    async fn create_child_special_file(
+        &self,
+        _name: &PathComponent,
+        _mode: Mode,
+        _uid: Uid,
+        _gid: Gid,
+        _rdev: u32,
+    ) -> FsResult<(NodeAttrs, AsyncDropGuard<PassthroughNode>)> {
+        // TODO Implement fifos, sockets and device nodes
+        Err(FsError::NotImplemented)
+    }
+
     async fn remove_child_file_or_symlink(&self, name: &PathComponent) -> FsResult<()> {
         let path = self.path.clone().push(name);
         tokio::fs::remove_file(path).await.map_error()?;
@@ -10,7 +10,7 @@ mod openfile;
 mod symlink;
 mod utils;
 
-use cryfs_rustfs::{Gid, Uid, object_based_api::ObjectBasedFsAdapterLL};
+use cryfs_rustfs::{Gid, KernelCacheOptions, Uid, object_based_api::ObjectBasedFsAdapterLL};
 use device::PassthroughDevice;
 
 const USAGE: &str = "Usage: passthroughfs [basedir] [mountdir]";
@@ -34,11 +34,12 @@ fn main() {
         .unwrap();
     runtime
         .block_on(cryfs_rustfs::backend::fuser::mount(
-            ObjectBasedFsAdapterLL::new(fs),
+            ObjectBasedFsAdapterLL::new(fs, KernelCacheOptions::default()),
             mountdir,
             runtime.handle().clone(),
             None,
             &[],
+            KernelCacheOptions::default(),
             || {},
         ))
         .unwrap();
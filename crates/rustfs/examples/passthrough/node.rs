@@ -4,7 +4,7 @@ use std::os::unix::fs::PermissionsExt;
 use std::time::SystemTime;
 
 use cryfs_rustfs::{
-    FsError, FsResult, Gid, Mode, NodeAttrs, NumBytes, Uid, object_based_api::Node,
+    FsError, FsResult, Gid, Mode, NodeAttrs, NumBytes, SetXattrMode, Uid, object_based_api::Node,
 };
 use cryfs_utils::{
     async_drop::{AsyncDrop, AsyncDropGuard},
@@ -169,6 +169,26 @@ impl Node for PassthroughNode {
         self.getattr().await
     }
 
+    async fn getxattr(&self, _name: &str) -> FsResult<Vec<u8>> {
+        // TODO Implement extended attributes
+        Err(FsError::NotImplemented)
+    }
+
+    async fn listxattr(&self) -> FsResult<Vec<String>> {
+        // TODO Implement extended attributes
+        Err(FsError::NotImplemented)
+    }
+
+    async fn setxattr(&self, _name: &str, _value: &[u8], _mode: SetXattrMode) -> FsResult<()> {
+        // TODO Implement extended attributes
+        Err(FsError::NotImplemented)
+    }
+
+    async fn removexattr(&self, _name: &str) -> FsResult<()> {
+        // TODO Implement extended attributes
+        Err(FsError::NotImplemented)
+    }
+
     #[cfg(feature = "testutils")]
     async fn fsync(&self, _datasync: bool) -> FsResult<()> {
         // No-op for passthrough filesystem
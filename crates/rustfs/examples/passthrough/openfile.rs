@@ -1,7 +1,8 @@
 use anyhow::Result;
 use async_trait::async_trait;
 use cryfs_rustfs::{
-    Data, FsError, FsResult, Gid, Mode, NodeAttrs, NumBytes, Uid, object_based_api::OpenFile,
+    Data, FallocateMode, FsError, FsResult, Gid, Mode, NodeAttrs, NumBytes, SeekWhence, Uid,
+    object_based_api::OpenFile,
 };
 use cryfs_utils::async_drop::{AsyncDrop, AsyncDropGuard};
 use std::os::fd::AsFd;
@@ -185,6 +186,113 @@ impl OpenFile for PassthroughOpenFile {
         Ok(())
     }
 
+    #[cfg(target_os = "linux")]
+    async fn fallocate(
+        &self,
+        offset: NumBytes,
+        length: NumBytes,
+        mode: FallocateMode,
+    ) -> FsResult<()> {
+        use nix::fcntl::FallocateFlags;
+        let flags = match mode {
+            FallocateMode::Allocate { keep_size: false } => FallocateFlags::empty(),
+            FallocateMode::Allocate { keep_size: true } => FallocateFlags::FALLOC_FL_KEEP_SIZE,
+            FallocateMode::PunchHole => {
+                FallocateFlags::FALLOC_FL_PUNCH_HOLE | FallocateFlags::FALLOC_FL_KEEP_SIZE
+            }
+            FallocateMode::ZeroRange { keep_size: false } => FallocateFlags::FALLOC_FL_ZERO_RANGE,
+            FallocateMode::ZeroRange { keep_size: true } => {
+                FallocateFlags::FALLOC_FL_ZERO_RANGE | FallocateFlags::FALLOC_FL_KEEP_SIZE
+            }
+        };
+        // TODO Is this possible without duplicating the file descriptor?
+        let open_file = self.open_file.try_clone().await.map_error()?;
+        tokio::runtime::Handle::current()
+            .spawn_blocking(move || {
+                nix::fcntl::fallocate(
+                    open_file.as_fd(),
+                    flags,
+                    i64::try_from(u64::from(offset)).unwrap(),
+                    i64::try_from(u64::from(length)).unwrap(),
+                )
+                .map_error()
+            })
+            .await
+            .map_err(|_: tokio::task::JoinError| FsError::UnknownError)?
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn fallocate(
+        &self,
+        _offset: NumBytes,
+        _length: NumBytes,
+        _mode: FallocateMode,
+    ) -> FsResult<()> {
+        Err(FsError::OperationNotSupported)
+    }
+
+    async fn lseek(&self, offset: NumBytes, whence: SeekWhence) -> FsResult<NumBytes> {
+        let whence = match whence {
+            SeekWhence::Data => nix::unistd::Whence::SeekData,
+            SeekWhence::Hole => nix::unistd::Whence::SeekHole,
+        };
+        // TODO Is this possible without duplicating the file descriptor?
+        let open_file = self.open_file.try_clone().await.map_error()?;
+        tokio::runtime::Handle::current()
+            .spawn_blocking(move || {
+                // This changes the file position, but that's fine because we only use `pread` and `pwrite`.
+                let new_offset = nix::unistd::lseek(
+                    open_file.as_fd(),
+                    i64::try_from(u64::from(offset)).unwrap(),
+                    whence,
+                )
+                .map_error()?;
+                Ok(NumBytes::from(u64::try_from(new_offset).unwrap()))
+            })
+            .await
+            .map_err(|_: tokio::task::JoinError| FsError::UnknownError)?
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn copy_file_range(
+        &self,
+        offset_in: NumBytes,
+        dest: &Self,
+        offset_out: NumBytes,
+        len: NumBytes,
+    ) -> FsResult<NumBytes> {
+        // TODO Is this possible without duplicating the file descriptors?
+        let open_file_in = self.open_file.try_clone().await.map_error()?;
+        let open_file_out = dest.open_file.try_clone().await.map_error()?;
+        tokio::runtime::Handle::current()
+            .spawn_blocking(move || {
+                let mut offset_in = i64::try_from(u64::from(offset_in)).unwrap();
+                let mut offset_out = i64::try_from(u64::from(offset_out)).unwrap();
+                let copied = nix::fcntl::copy_file_range(
+                    open_file_in.as_fd(),
+                    Some(&mut offset_in),
+                    open_file_out.as_fd(),
+                    Some(&mut offset_out),
+                    usize::try_from(u64::from(len)).unwrap(),
+                )
+                .map_error()?;
+                Ok(NumBytes::from(u64::try_from(copied).unwrap()))
+            })
+            .await
+            .map_err(|_: tokio::task::JoinError| FsError::UnknownError)?
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn copy_file_range(
+        &self,
+        _offset_in: NumBytes,
+        _dest: &Self,
+        _offset_out: NumBytes,
+        _len: NumBytes,
+    ) -> FsResult<NumBytes> {
+        Err(FsError::OperationNotSupported)
+    }
+
     async fn flush(&self) -> FsResult<()> {
         // flush strictly speaking isn't a request to sync dirty data,
         // but it's a good place to do it because it's usually triggered
@@ -1,9 +1,12 @@
-use cryfs_rustfs::{FsResult, NodeAttrs, NumBytes};
+use cryfs_rustfs::{FsError, FsResult, NodeAttrs, NumBytes, RenameMode};
+use cryfs_utils::path::AbsolutePathBuf;
 use std::fs::Metadata;
 use std::os::unix::fs::MetadataExt;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use super::errors::IoResultExt;
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+use super::errors::NixResultExt;
 
 pub fn convert_metadata(metadata: Metadata) -> FsResult<NodeAttrs> {
     Ok(NodeAttrs {
@@ -16,6 +19,8 @@ pub fn convert_metadata(metadata: Metadata) -> FsResult<NodeAttrs> {
         gid: metadata.gid().into(),
         num_bytes: NumBytes::from(metadata.len()),
         num_blocks: Some(metadata.blocks()),
+        // TODO Make rdev platform independent
+        rdev: u32::try_from(metadata.rdev()).unwrap_or(0),
         atime: metadata.accessed().map_error()?,
         mtime: metadata.modified().map_error()?,
         // TODO No unwrap in ctime
@@ -31,3 +36,50 @@ pub fn convert_timespec(time: SystemTime) -> nix::sys::time::TimeSpec {
         .expect("Time is before unix epoch")
         .into()
 }
+
+pub async fn rename(
+    old_path: AbsolutePathBuf,
+    new_path: AbsolutePathBuf,
+    mode: RenameMode,
+) -> FsResult<()> {
+    if mode == RenameMode::Overwrite {
+        return tokio::fs::rename(old_path, new_path).await.map_error();
+    }
+    renameat2(old_path, new_path, mode).await
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+async fn renameat2(
+    old_path: AbsolutePathBuf,
+    new_path: AbsolutePathBuf,
+    mode: RenameMode,
+) -> FsResult<()> {
+    let flags = match mode {
+        RenameMode::Overwrite => nix::fcntl::RenameFlags::empty(),
+        RenameMode::NoReplace => nix::fcntl::RenameFlags::RENAME_NOREPLACE,
+        RenameMode::Exchange => nix::fcntl::RenameFlags::RENAME_EXCHANGE,
+    };
+    tokio::runtime::Handle::current()
+        .spawn_blocking(move || {
+            nix::fcntl::renameat2(
+                nix::fcntl::AT_FDCWD,
+                old_path.as_str(),
+                nix::fcntl::AT_FDCWD,
+                new_path.as_str(),
+                flags,
+            )
+            .map_error()
+        })
+        .await
+        .map_err(|_: tokio::task::JoinError| FsError::UnknownError)?
+}
+
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+async fn renameat2(
+    _old_path: AbsolutePathBuf,
+    _new_path: AbsolutePathBuf,
+    _mode: RenameMode,
+) -> FsResult<()> {
+    // TODO Make this platform independent
+    Err(FsError::NotImplemented)
+}
@@ -344,10 +344,12 @@ where
             async move || {
                 let oldpath = parse_absolute_path_with_last_component(oldparent, oldname)?;
                 let newpath = parse_absolute_path_with_last_component(newparent, newname)?;
+                // fuse_mt doesn't forward the `renameat2` flags, so we can only do plain renames here.
                 self.fs().await?.rename(
                     req.into(),
                     &oldpath,
                     &newpath,
+                    0,
                 ).await
             },
         )
@@ -698,6 +700,9 @@ where
             },
         )
     }
+
+    // TODO fuse_mt doesn't forward getlk/setlk and doesn't request FUSE_POSIX_LOCKS, so the kernel only enforces
+    //      fcntl and flock locks locally on this backend. The fuser backend tracks locks in `LockManager`.
 }
 
 fn convert_node_attrs(attrs: NodeAttrs) -> FileAttr {
@@ -715,7 +720,7 @@ fn convert_node_attrs(attrs: NodeAttrs) -> FileAttr {
         uid: attrs.uid.into(),
         gid: attrs.gid.into(),
         // Device ID (if special file)
-        rdev: 0, // TODO What to do about this?
+        rdev: attrs.rdev,
         // Flags (macOS only; see chflags(2))
         flags: 0, // TODO What to do about this?
     }
@@ -737,6 +742,10 @@ fn convert_node_kind(kind: NodeKind) -> fuse_mt::FileType {
         NodeKind::File => fuse_mt::FileType::RegularFile,
         NodeKind::Dir => fuse_mt::FileType::Directory,
         NodeKind::Symlink => fuse_mt::FileType::Symlink,
+        NodeKind::Fifo => fuse_mt::FileType::NamedPipe,
+        NodeKind::Socket => fuse_mt::FileType::Socket,
+        NodeKind::CharDevice => fuse_mt::FileType::CharDevice,
+        NodeKind::BlockDevice => fuse_mt::FileType::BlockDevice,
     }
 }
 
@@ -16,8 +16,8 @@ use std::time::{Duration, SystemTime};
 use tokio::sync::{OwnedRwLockReadGuard, RwLock, RwLockWriteGuard};
 
 use crate::common::{
-    Callback, FileHandle, FsError, FsResult, Gid, InodeNumber, Mode, NodeAttrs, NodeKind, NumBytes,
-    OpenInFlags, OpenOutFlags, RequestInfo, Statfs, Uid,
+    Callback, FileHandle, FsError, FsResult, Gid, InodeNumber, KernelCacheOptions, Mode, NodeAttrs,
+    NodeKind, NumBytes, OpenInFlags, OpenOutFlags, RequestInfo, Statfs, Uid,
 };
 use crate::low_level_api::{
     self, AsyncFilesystemLL, ReplyDirectory, ReplyDirectoryAddResult, ReplyDirectoryPlus,
@@ -27,10 +27,6 @@ use cryfs_utils::path::{PathComponent, PathComponentBuf};
 
 // TODO Check if any of the APIs in the high or low level interface would benefit from replacing Vec<> with impl Iterator
 
-// TODO Fuse has a requirement that (inode, generation) tuples are unique throughout the lifetime of the filesystem, not just the lifetime of the mount.
-//      See https://github.com/libfuse/libfuse/blob/d92bf83c152ff88c2d92bd852752d4c326004400/include/fuse_lowlevel.h#L69-L81 and https://github.com/wfraser/fuse-mt/issues/19
-//      This means currently, CryFS can't be used over NFS. We should fix this.
-
 // TODO Implement async file io. And actually, would this allow CryFS to benefit from being used with io-uring or not?
 
 // TODO Use tracing crate for logging of file system operations
@@ -48,6 +44,8 @@ where
     fs: Arc<tokio::sync::RwLock<AsyncDropGuard<Fs>>>,
 
     runtime: tokio::runtime::Handle,
+
+    kernel_cache: KernelCacheOptions,
 }
 
 impl<Fs> Debug for BackendAdapter<Fs>
@@ -63,10 +61,15 @@ impl<Fs> BackendAdapter<Fs>
 where
     Fs: AsyncFilesystemLL + AsyncDrop<Error = FsError> + Debug + Send + Sync + 'static,
 {
-    pub fn new(fs: AsyncDropGuard<Fs>, runtime: tokio::runtime::Handle) -> Self {
+    pub fn new(
+        fs: AsyncDropGuard<Fs>,
+        runtime: tokio::runtime::Handle,
+        kernel_cache: KernelCacheOptions,
+    ) -> Self {
         Self {
             fs: Arc::new(RwLock::new(fs)),
             runtime,
+            kernel_cache,
         }
     }
 
@@ -117,6 +120,43 @@ where
         Ok(fs)
     }
 
+    fn apply_kernel_cache_options(&self, config: &mut KernelConfig) {
+        if self.kernel_cache.writeback_cache
+            && let Err(unsupported) = config.add_capabilities(fuser::consts::FUSE_WRITEBACK_CACHE)
+        {
+            log::warn!(
+                "Kernel doesn't support writeback cache capabilities {unsupported:#x}, writes will be sent to the file system immediately"
+            );
+        }
+        if self.kernel_cache.parallel_dirops
+            && let Err(unsupported) = config.add_capabilities(fuser::consts::FUSE_PARALLEL_DIROPS)
+        {
+            log::warn!(
+                "Kernel doesn't support parallel dirops capabilities {unsupported:#x}, lookups and readdirs in the same directory will be serialized"
+            );
+        }
+        if let Some(max_write) = self.kernel_cache.max_write
+            && let Err(nearest_supported) = config.set_max_write(max_write)
+        {
+            log::warn!(
+                "max_write of {max_write} isn't supported, using {nearest_supported} instead"
+            );
+            config
+                .set_max_write(nearest_supported)
+                .expect("fuser told us this value is supported");
+        }
+        if let Some(max_readahead) = self.kernel_cache.max_readahead
+            && let Err(nearest_supported) = config.set_max_readahead(max_readahead)
+        {
+            log::warn!(
+                "max_readahead of {max_readahead} isn't supported, using {nearest_supported} instead"
+            );
+            config
+                .set_max_readahead(nearest_supported)
+                .expect("fuser told us this value is supported");
+        }
+    }
+
     fn run_blocking<R: Debug>(
         runtime: &tokio::runtime::Handle,
         log_msg: &str,
@@ -504,7 +544,35 @@ where
     Fs: AsyncFilesystemLL + AsyncDrop<Error = FsError> + Debug + Send + Sync + 'static,
 {
     fn init(&mut self, req: &Request<'_>, config: &mut KernelConfig) -> Result<(), c_int> {
-        // TODO Allow a way to set KernelConfig? Or should we make choices in rustfs? The fuse-mt crate chose to hide it.
+        // Without these, the kernel only enforces fcntl and flock locks locally and doesn't send us getlk/setlk
+        if let Err(unsupported) = config
+            .add_capabilities(fuser::consts::FUSE_POSIX_LOCKS | fuser::consts::FUSE_FLOCK_LOCKS)
+        {
+            log::warn!(
+                "Kernel doesn't support file locking capabilities {unsupported:#x}, locks will only be enforced locally"
+            );
+        }
+
+        // Tells the kernel that we handle lookups of "." and "..", which it needs to export the file system over NFS.
+        // Fuse requires (inode, generation) tuples to be unique throughout the lifetime of the file system, not just the lifetime of the mount,
+        // see https://github.com/libfuse/libfuse/blob/d92bf83c152ff88c2d92bd852752d4c326004400/include/fuse_lowlevel.h#L69-L81.
+        // This holds for file systems whose nodes have a stable id, see [crate::object_based_api::Node::stable_id].
+        if let Err(unsupported) = config.add_capabilities(fuser::consts::FUSE_EXPORT_SUPPORT) {
+            log::warn!(
+                "Kernel doesn't support export capabilities {unsupported:#x}, the file system can't be exported over NFS"
+            );
+        }
+
+        // With READDIRPLUS_AUTO, the kernel only uses readdirplus when it expects the entries to be looked up afterwards, e.g. for `ls -l`
+        if let Err(unsupported) = config.add_capabilities(
+            fuser::consts::FUSE_DO_READDIRPLUS | fuser::consts::FUSE_READDIRPLUS_AUTO,
+        ) {
+            log::warn!(
+                "Kernel doesn't support readdirplus capabilities {unsupported:#x}, falling back to readdir"
+            );
+        }
+
+        self.apply_kernel_cache_options(config);
 
         Self::run_blocking(&self.runtime, &format!("init({config:?})"), async || {
             self.fs_write().await?.init(&RequestInfo::from(req)).await
@@ -538,6 +606,13 @@ where
             reply,
             async move |fs| {
                 let parent_ino = parse_inode(parent_ino)?;
+                // The kernel only looks up "." and ".." because we negotiated FUSE_EXPORT_SUPPORT in [Self::init]
+                if name == "." {
+                    return fs.lookup_self(&req, parent_ino).await;
+                }
+                if name == ".." {
+                    return fs.lookup_parent(&req, parent_ino).await;
+                }
                 let name: PathComponentBuf = name.try_into().map_err(|err| {
                     log::warn!("Failed to parse path component in lookup: {err:?}");
                     FsError::InvalidPath
@@ -1529,6 +1604,24 @@ fn _reply_directory_add(
 }
 
 impl ReplyDirectoryPlus for fuser::ReplyDirectoryPlus {
+    fn add_self_reference(
+        &mut self,
+        ino: InodeNumber,
+        offset: i64,
+        attr: &NodeAttrs,
+    ) -> ReplyDirectoryAddResult {
+        _reply_directory_plus_add(self, ino, offset, ".", &Duration::ZERO, attr, 0)
+    }
+
+    fn add_parent_reference(
+        &mut self,
+        ino: InodeNumber,
+        offset: i64,
+        attr: &NodeAttrs,
+    ) -> ReplyDirectoryAddResult {
+        _reply_directory_plus_add(self, ino, offset, "..", &Duration::ZERO, attr, 0)
+    }
+
     fn add(
         &mut self,
         ino: InodeNumber,
@@ -1538,20 +1631,32 @@ impl ReplyDirectoryPlus for fuser::ReplyDirectoryPlus {
         attr: &NodeAttrs,
         generation: u64,
     ) -> ReplyDirectoryAddResult {
-        let attr = convert_node_attrs(*attr, ino);
-        let result = fuser::ReplyDirectoryPlus::add(
-            self,
-            NonZeroU64::from(ino).get(),
-            offset,
-            name.as_str(),
-            ttl,
-            &attr,
-            generation,
-        );
-        match result {
-            true => ReplyDirectoryAddResult::Full,
-            false => ReplyDirectoryAddResult::NotFull,
-        }
+        _reply_directory_plus_add(self, ino, offset, name.as_str(), ttl, attr, generation)
+    }
+}
+
+fn _reply_directory_plus_add(
+    reply: &mut fuser::ReplyDirectoryPlus,
+    ino: InodeNumber,
+    offset: i64,
+    name: impl AsRef<OsStr>,
+    ttl: &Duration,
+    attr: &NodeAttrs,
+    generation: u64,
+) -> ReplyDirectoryAddResult {
+    let attr = convert_node_attrs(*attr, ino);
+    let result = fuser::ReplyDirectoryPlus::add(
+        reply,
+        NonZeroU64::from(ino).get(),
+        offset,
+        name,
+        ttl,
+        &attr,
+        generation,
+    );
+    match result {
+        true => ReplyDirectoryAddResult::Full,
+        false => ReplyDirectoryAddResult::NotFull,
     }
 }
 
@@ -1626,7 +1731,7 @@ fn convert_node_attrs(attrs: NodeAttrs, ino: InodeNumber) -> fuser::FileAttr {
         uid: attrs.uid.into(),
         gid: attrs.gid.into(),
         // Device ID (if special file)
-        rdev: 0, // TODO What to do about this?
+        rdev: attrs.rdev,
         // Flags (macOS only; see chflags(2))
         flags: 0,      // TODO What to do about this?
         blksize: 4096, // TODO What to do about this?
@@ -1638,6 +1743,10 @@ fn convert_node_kind(kind: NodeKind) -> fuser::FileType {
         NodeKind::File => fuser::FileType::RegularFile,
         NodeKind::Dir => fuser::FileType::Directory,
         NodeKind::Symlink => fuser::FileType::Symlink,
+        NodeKind::Fifo => fuser::FileType::NamedPipe,
+        NodeKind::Socket => fuser::FileType::Socket,
+        NodeKind::CharDevice => fuser::FileType::CharDevice,
+        NodeKind::BlockDevice => fuser::FileType::BlockDevice,
     }
 }
 
@@ -1,10 +1,12 @@
 use fuser::MountOption;
+use std::ffi::OsStr;
 use std::fmt::Debug;
+use std::num::NonZeroU64;
 use std::path::Path;
 use tokio_util::sync::CancellationToken;
 
 use super::{RunningFilesystem, backend_adapter::BackendAdapter};
-use crate::common::FsError;
+use crate::common::{FsError, Invalidation, KernelCacheOptions};
 use crate::low_level_api::AsyncFilesystemLL;
 use cryfs_utils::async_drop::{AsyncDrop, AsyncDropGuard};
 
@@ -14,12 +16,13 @@ pub async fn mount<Fs>(
     runtime: tokio::runtime::Handle,
     unmount_trigger: Option<CancellationToken>,
     mount_options: &[MountOption],
+    kernel_cache: KernelCacheOptions,
     on_successfully_mounted: impl FnOnce(),
 ) -> std::io::Result<()>
 where
     Fs: AsyncFilesystemLL + AsyncDrop<Error = FsError> + Debug + Send + Sync + 'static,
 {
-    let fs = spawn_mount(fs, mountpoint, runtime, mount_options).await?;
+    let fs = spawn_mount(fs, mountpoint, runtime, mount_options, kernel_cache).await?;
     on_successfully_mounted();
 
     if let Some(unmount_trigger) = unmount_trigger {
@@ -35,11 +38,13 @@ pub async fn spawn_mount<Fs>(
     mountpoint: impl AsRef<Path>,
     runtime: tokio::runtime::Handle,
     mount_options: &[MountOption],
+    kernel_cache: KernelCacheOptions,
 ) -> std::io::Result<RunningFilesystem>
 where
     Fs: AsyncFilesystemLL + AsyncDrop<Error = FsError> + Debug + Send + Sync + 'static,
 {
-    let backend = BackendAdapter::new(fs, runtime);
+    let invalidator = kernel_cache.invalidator.clone();
+    let backend = BackendAdapter::new(fs, runtime.clone(), kernel_cache);
 
     // We need to keep a handle to the internal arc because we need to manually async drop it if fuser::spawn_mount2 fails.
     // This is because usually, the internal Arc is dropped in BackendAdapter::destroy() but if fuser::spawn_mount2 fails,
@@ -60,5 +65,26 @@ where
         }
     };
 
+    let notifier = session.notifier();
+    invalidator.connect(move |invalidation| {
+        let notifier = notifier.clone();
+        // The kernel may hold locks while waiting for one of our file system operations to finish, and it needs some of those locks
+        // to process an invalidation. Sending it from a file system operation would then deadlock, so we send it from a separate thread.
+        runtime.spawn_blocking(move || {
+            let result = match &invalidation {
+                Invalidation::Inode(ino) => {
+                    // Offset 0 and length 0 invalidate the attributes and all cached data of the inode
+                    notifier.inval_inode(NonZeroU64::from(*ino).get(), 0, 0)
+                }
+                Invalidation::Entry { parent, name } => {
+                    notifier.inval_entry(NonZeroU64::from(*parent).get(), OsStr::new(name.as_str()))
+                }
+            };
+            if let Err(err) = result {
+                log::warn!("Failed to send {invalidation:?} to the kernel: {err:?}");
+            }
+        });
+    });
+
     Ok(RunningFilesystem::new(session))
 }
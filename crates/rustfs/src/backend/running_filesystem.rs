@@ -75,8 +75,16 @@ where
         let session_clone = self.session.clone();
         tokio::task::spawn(async move {
             unmount_trigger.cancelled().await;
-            if let Some(session) = session_clone.lock().unwrap().take() {
-                session.join();
+            // Joining blocks until the file system is destroyed, which runs async code on this runtime.
+            // Blocking a runtime worker here can keep that code from making progress, so join from a blocking thread.
+            let join_result = tokio::task::spawn_blocking(move || {
+                if let Some(session) = session_clone.lock().unwrap().take() {
+                    session.join();
+                }
+            })
+            .await;
+            if let Err(err) = join_result {
+                log::error!("Failed to unmount file system: {err:?}");
             }
         });
     }
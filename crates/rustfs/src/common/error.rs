@@ -76,11 +76,56 @@ pub enum FsError {
     #[error("Cannot move a directory into itself or a subdirectory of itself")]
     CannotMoveDirectoryIntoSubdirectoryOfItself,
 
+    #[error("Cannot create a hard link to a directory")]
+    CannotHardLinkDirectory,
+
+    #[error("The file system node already has the maximal number of hard links")]
+    TooManyLinks,
+
+    #[error("The file system doesn't support hard links")]
+    HardLinksNotSupported,
+
     #[error("The extended attributes don't fit into the buffer provided")]
     XattrBufferTooSmall,
 
+    #[error("The extended attribute does not exist")]
+    XattrDoesNotExist,
+
+    #[error("The extended attribute already exists")]
+    XattrAlreadyExists,
+
+    #[error("The extended attribute name is too long")]
+    XattrNameTooLong,
+
+    #[error("The extended attribute value is too large")]
+    XattrValueTooLarge,
+
+    #[error("Not enough space left to store the extended attribute")]
+    XattrNoSpace,
+
+    #[error("The file system node doesn't support extended attributes")]
+    XattrNotSupported,
+
+    #[error("The operation is not supported")]
+    OperationNotSupported,
+
+    #[error("The file system doesn't support creating this kind of file system node")]
+    NodeKindNotSupported,
+
+    #[error("The offset is at or beyond the end of the file")]
+    OffsetBeyondEndOfFile,
+
+    #[error("The range is locked by another lock owner")]
+    LockConflict,
+
+    #[error("Waiting for the lock would deadlock")]
+    Deadlock,
+
     #[error("File system is already terminated, cannot execute operation")]
     FilesystemDestroyed,
+
+    #[error("The inode number doesn't refer to a file system node anymore")]
+    StaleInode,
 }
 
 impl FsError {
@@ -112,8 +157,26 @@ impl FsError {
             FsError::CorruptedFilesystem { .. } => libc::EIO,
             FsError::InternalError { .. } => libc::EIO,
             FsError::CannotMoveDirectoryIntoSubdirectoryOfItself => libc::EINVAL,
+            FsError::CannotHardLinkDirectory => libc::EPERM,
+            FsError::TooManyLinks => libc::EMLINK,
+            FsError::HardLinksNotSupported => libc::EPERM,
             FsError::XattrBufferTooSmall => libc::ERANGE,
+            #[cfg(target_os = "macos")]
+            FsError::XattrDoesNotExist => libc::ENOATTR,
+            #[cfg(not(target_os = "macos"))]
+            FsError::XattrDoesNotExist => libc::ENODATA,
+            FsError::XattrAlreadyExists => libc::EEXIST,
+            FsError::XattrNameTooLong => libc::ERANGE,
+            FsError::XattrValueTooLarge => libc::E2BIG,
+            FsError::XattrNoSpace => libc::ENOSPC,
+            FsError::XattrNotSupported => libc::ENOTSUP,
+            FsError::OperationNotSupported => libc::EOPNOTSUPP,
+            FsError::NodeKindNotSupported => libc::EPERM,
+            FsError::OffsetBeyondEndOfFile => libc::ENXIO,
+            FsError::LockConflict => libc::EAGAIN,
+            FsError::Deadlock => libc::EDEADLK,
             FsError::FilesystemDestroyed => libc::EIO,
+            FsError::StaleInode => libc::ESTALE,
         }
     }
 }
@@ -0,0 +1,47 @@
+use super::{FsError, FsResult};
+
+/// What a `fallocate` call should do with the given range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FallocateMode {
+    /// Make sure the range is allocated. Grows the file if the range reaches beyond its end, unless `keep_size` is set.
+    Allocate { keep_size: bool },
+    /// Deallocate the range so that it reads as zeroes (`FALLOC_FL_PUNCH_HOLE`). Never changes the file size.
+    PunchHole,
+    /// Set the range to zeroes (`FALLOC_FL_ZERO_RANGE`). Grows the file if the range reaches beyond its end, unless `keep_size` is set.
+    ZeroRange { keep_size: bool },
+}
+
+impl FallocateMode {
+    /// Parse the `mode` argument of the `fallocate` syscall
+    #[cfg(target_os = "linux")]
+    pub fn from_flags(flags: libc::c_int) -> FsResult<Self> {
+        const SUPPORTED_FLAGS: libc::c_int =
+            libc::FALLOC_FL_KEEP_SIZE | libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_ZERO_RANGE;
+        if flags & !SUPPORTED_FLAGS != 0 {
+            // e.g. FALLOC_FL_COLLAPSE_RANGE or FALLOC_FL_INSERT_RANGE
+            return Err(FsError::OperationNotSupported);
+        }
+        let keep_size = flags & libc::FALLOC_FL_KEEP_SIZE != 0;
+        match (
+            flags & libc::FALLOC_FL_PUNCH_HOLE != 0,
+            flags & libc::FALLOC_FL_ZERO_RANGE != 0,
+        ) {
+            (false, false) => Ok(Self::Allocate { keep_size }),
+            // Punching holes is only allowed together with FALLOC_FL_KEEP_SIZE, see `man 2 fallocate`
+            (true, false) if keep_size => Ok(Self::PunchHole),
+            (true, false) => Err(FsError::InvalidOperation),
+            (false, true) => Ok(Self::ZeroRange { keep_size }),
+            (true, true) => Err(FsError::InvalidOperation),
+        }
+    }
+
+    /// Parse the `mode` argument of the `fallocate` syscall. The mode flags are linux specific, other systems can only do plain allocations.
+    #[cfg(not(target_os = "linux"))]
+    pub fn from_flags(flags: libc::c_int) -> FsResult<Self> {
+        if flags == 0 {
+            Ok(Self::Allocate { keep_size: false })
+        } else {
+            Err(FsError::OperationNotSupported)
+        }
+    }
+}
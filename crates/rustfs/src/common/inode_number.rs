@@ -4,6 +4,7 @@ use derive_more::{Display, From, Into};
 
 #[cfg(any(feature = "fuser", feature = "fuse_mt"))]
 use crate::common::HandleTrait;
+use crate::common::HandleWithGeneration;
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, From, Into, Display)]
 pub struct InodeNumber {
@@ -22,8 +23,29 @@ impl InodeNumber {
             Some(nz) => Some(Self::from_const(nz)),
         }
     }
+
+    /// Derive the inode number and generation of a node from an id that identifies it across remounts,
+    /// e.g. its blob id. The upper half of the id becomes the inode number (with [STABLE_INODE_BIT] set)
+    /// and the lower half the generation, so different ids give different (inode, generation) tuples
+    /// even if their inode numbers collide.
+    pub const fn from_stable_id(stable_id: u128) -> HandleWithGeneration<Self> {
+        let ino = ((stable_id >> 64) as u64) | STABLE_INODE_BIT;
+        HandleWithGeneration {
+            handle: Self::from_const(NonZeroU64::new(ino).unwrap()),
+            generation: stable_id as u64,
+        }
+    }
+
+    /// Whether this inode number was derived from a stable id with [Self::from_stable_id].
+    pub const fn is_stable(&self) -> bool {
+        self.v.get() & STABLE_INODE_BIT != 0
+    }
 }
 
+/// Inode numbers with this bit set are derived from a stable id, see [InodeNumber::from_stable_id].
+/// Inode numbers that are assigned dynamically never have it set.
+const STABLE_INODE_BIT: u64 = 1 << 63;
+
 #[cfg(any(feature = "fuser", feature = "fuse_mt"))]
 impl HandleTrait for InodeNumber {
     const MIN: Self = Self { v: NonZeroU64::MIN };
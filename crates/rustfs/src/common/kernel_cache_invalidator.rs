@@ -0,0 +1,161 @@
+use std::fmt::{self, Debug};
+use std::num::NonZeroU64;
+use std::sync::{Arc, OnceLock};
+
+use cryfs_utils::path::{PathComponent, PathComponentBuf};
+
+use crate::common::InodeNumber;
+
+/// The inode number the kernel uses for the root directory
+const ROOT_INO: InodeNumber = InodeNumber::from_const(NonZeroU64::new(1).unwrap());
+
+/// An entry the kernel should drop from its caches
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Invalidation {
+    /// Drop the cached attributes and data of an inode
+    Inode(InodeNumber),
+
+    /// Drop the cached lookup result for `name` in the directory `parent`
+    Entry {
+        parent: InodeNumber,
+        name: PathComponentBuf,
+    },
+}
+
+/// [KernelCacheInvalidator] lets the file system tell the kernel to drop cached entries that changed
+/// without the kernel knowing, e.g. because the file system changed them internally or because they
+/// were changed by a different client sharing the same storage.
+///
+/// It can be created before the file system is mounted and gets connected to the kernel when mounting.
+/// Invalidations sent before that, or with a backend that doesn't support them, are dropped.
+/// Clones share the same connection.
+#[derive(Clone, Default)]
+pub struct KernelCacheInvalidator {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    send: OnceLock<Box<dyn Fn(Invalidation) + Send + Sync>>,
+    root_stable_id: OnceLock<u128>,
+}
+
+impl KernelCacheInvalidator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn invalidate_inode(&self, ino: InodeNumber) {
+        self._send(Invalidation::Inode(ino));
+    }
+
+    pub fn invalidate_entry(&self, parent: InodeNumber, name: &PathComponent) {
+        self._send(Invalidation::Entry {
+            parent,
+            name: name.to_owned(),
+        });
+    }
+
+    /// Like [Self::invalidate_inode], but for a node with the given [stable id](crate::object_based_api::Node::stable_id).
+    pub fn invalidate_inode_with_stable_id(&self, stable_id: u128) {
+        self.invalidate_inode(self._ino_for_stable_id(stable_id));
+    }
+
+    /// Like [Self::invalidate_entry], but for a parent directory with the given [stable id](crate::object_based_api::Node::stable_id).
+    pub fn invalidate_entry_with_stable_id(&self, parent_stable_id: u128, name: &PathComponent) {
+        self.invalidate_entry(self._ino_for_stable_id(parent_stable_id), name);
+    }
+
+    /// Called by the backend when mounting. `send` must not block on the kernel, because the kernel
+    /// may wait for a file system operation to finish before it processes an invalidation.
+    pub(crate) fn connect(&self, send: impl Fn(Invalidation) + Send + Sync + 'static) {
+        if self.inner.send.set(Box::new(send)).is_err() {
+            panic!("KernelCacheInvalidator is already connected to a mounted file system");
+        }
+    }
+
+    /// The root directory is exposed under [ROOT_INO] instead of the inode number derived from its stable id.
+    pub(crate) fn set_root_stable_id(&self, stable_id: u128) {
+        let root_stable_id = *self.inner.root_stable_id.get_or_init(|| stable_id);
+        assert_eq!(
+            stable_id, root_stable_id,
+            "Tried to change the stable id of the root directory"
+        );
+    }
+
+    fn _ino_for_stable_id(&self, stable_id: u128) -> InodeNumber {
+        if self.inner.root_stable_id.get() == Some(&stable_id) {
+            ROOT_INO
+        } else {
+            // If the stable inode number collides with a different node, the kernel may know the node under a different inode number.
+            // We then invalidate the other node instead, which only costs us a reload.
+            InodeNumber::from_stable_id(stable_id).handle
+        }
+    }
+
+    fn _send(&self, invalidation: Invalidation) {
+        match self.inner.send.get() {
+            Some(send) => send(invalidation),
+            None => log::debug!("Not mounted, dropping {invalidation:?}"),
+        }
+    }
+}
+
+impl Debug for KernelCacheInvalidator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KernelCacheInvalidator")
+            .field("connected", &self.inner.send.get().is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn connected() -> (KernelCacheInvalidator, Arc<Mutex<Vec<Invalidation>>>) {
+        let invalidator = KernelCacheInvalidator::new();
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let sent_clone = Arc::clone(&sent);
+        invalidator.connect(move |invalidation| sent_clone.lock().unwrap().push(invalidation));
+        (invalidator, sent)
+    }
+
+    #[test]
+    fn drops_invalidations_before_connecting() {
+        let invalidator = KernelCacheInvalidator::new();
+        invalidator.invalidate_inode(ROOT_INO);
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let sent_clone = Arc::clone(&sent);
+        invalidator
+            .clone()
+            .connect(move |invalidation| sent_clone.lock().unwrap().push(invalidation));
+        assert_eq!(Vec::<Invalidation>::new(), *sent.lock().unwrap());
+
+        // Clones share the connection
+        invalidator.invalidate_inode(ROOT_INO);
+        assert_eq!(vec![Invalidation::Inode(ROOT_INO)], *sent.lock().unwrap());
+    }
+
+    #[test]
+    fn stable_ids_map_to_stable_inode_numbers() {
+        let (invalidator, sent) = connected();
+        invalidator.set_root_stable_id(0x1111);
+        invalidator.invalidate_inode_with_stable_id(0x2222);
+        invalidator.invalidate_inode_with_stable_id(0x1111);
+        invalidator
+            .invalidate_entry_with_stable_id(0x1111, PathComponent::try_from_str("name").unwrap());
+        assert_eq!(
+            vec![
+                Invalidation::Inode(InodeNumber::from_stable_id(0x2222).handle),
+                Invalidation::Inode(ROOT_INO),
+                Invalidation::Entry {
+                    parent: ROOT_INO,
+                    name: PathComponent::try_from_str("name").unwrap().to_owned(),
+                },
+            ],
+            *sent.lock().unwrap()
+        );
+    }
+}
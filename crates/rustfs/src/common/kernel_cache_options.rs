@@ -0,0 +1,56 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use super::KernelCacheInvalidator;
+
+/// Defines how much the kernel caches and how large the requests are that it sends to the file system.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KernelCacheOptions {
+    /// How long the kernel caches the result of a lookup, i.e. that a name in a directory refers to a certain inode.
+    /// The kernel gets the attributes of a node together with each lookup and caches them for the same time, so lookups
+    /// use the smaller of this and [Self::attr_ttl].
+    pub entry_ttl: Duration,
+
+    /// How long the kernel caches the attributes of a node, e.g. its size, mode and timestamps.
+    pub attr_ttl: Duration,
+
+    /// Let the kernel buffer writes in its page cache and send them to the file system in larger chunks.
+    /// The kernel then also maintains mtime/ctime and the file size itself and only tells the file system
+    /// about them when it flushes the writes.
+    pub writeback_cache: bool,
+
+    /// The largest write request the kernel sends. `None` means the backend default.
+    pub max_write: Option<u32>,
+
+    /// The largest readahead the kernel does. `None` means the kernel default.
+    pub max_readahead: Option<u32>,
+
+    /// Allow the kernel to send concurrent lookups and readdirs for the same directory.
+    /// Without this, the kernel serializes them.
+    pub parallel_dirops: bool,
+
+    /// Gets connected to the kernel when mounting, so the file system can invalidate cache entries that changed without the kernel knowing.
+    #[serde(skip)]
+    pub invalidator: KernelCacheInvalidator,
+}
+
+impl KernelCacheOptions {
+    /// The TTL to use for replies that contain both an entry and its attributes
+    pub fn entry_and_attr_ttl(&self) -> Duration {
+        self.entry_ttl.min(self.attr_ttl)
+    }
+}
+
+impl Default for KernelCacheOptions {
+    fn default() -> Self {
+        Self {
+            entry_ttl: Duration::from_secs(1),
+            attr_ttl: Duration::from_secs(1),
+            writeback_cache: false,
+            max_write: None,
+            max_readahead: None,
+            parallel_dirops: true,
+            invalidator: KernelCacheInvalidator::new(),
+        }
+    }
+}
@@ -4,6 +4,9 @@ pub use dir_entry::{DirEntry, DirEntryOrReference};
 mod error;
 pub use error::{FsError, FsResult};
 
+mod fallocate_mode;
+pub use fallocate_mode::FallocateMode;
+
 mod gid;
 pub use gid::Gid;
 
@@ -25,6 +28,15 @@ pub use open_in_flags::OpenInFlags;
 mod open_out_flags;
 pub use open_out_flags::OpenOutFlags;
 
+mod rename_mode;
+pub use rename_mode::RenameMode;
+
+mod seek_whence;
+pub use seek_whence::SeekWhence;
+
+mod set_xattr_mode;
+pub use set_xattr_mode::SetXattrMode;
+
 mod statfs;
 pub use statfs::Statfs;
 
@@ -52,3 +64,9 @@ pub use callback::{Callback, CallbackImpl};
 
 mod atime_update_behavior;
 pub use atime_update_behavior::AtimeUpdateBehavior;
+
+mod kernel_cache_options;
+pub use kernel_cache_options::KernelCacheOptions;
+
+mod kernel_cache_invalidator;
+pub use kernel_cache_invalidator::{Invalidation, KernelCacheInvalidator};
@@ -8,6 +8,10 @@ const S_IFMT: Mode = Mode(0o170000);
 const S_IFDIR: Mode = Mode(0o040000);
 const S_IFREG: Mode = Mode(0o100000);
 const S_IFLNK: Mode = Mode(0o120000);
+const S_IFIFO: Mode = Mode(0o010000);
+const S_IFSOCK: Mode = Mode(0o140000);
+const S_IFCHR: Mode = Mode(0o020000);
+const S_IFBLK: Mode = Mode(0o060000);
 
 const S_IRUSR: Mode = Mode(0o000400);
 const S_IWUSR: Mode = Mode(0o000200);
@@ -34,6 +38,26 @@ const fn S_ISLNK(mode: Mode) -> bool {
     (mode.0 & S_IFMT.0) == S_IFLNK.0
 }
 
+#[allow(non_snake_case)]
+const fn S_ISFIFO(mode: Mode) -> bool {
+    (mode.0 & S_IFMT.0) == S_IFIFO.0
+}
+
+#[allow(non_snake_case)]
+const fn S_ISSOCK(mode: Mode) -> bool {
+    (mode.0 & S_IFMT.0) == S_IFSOCK.0
+}
+
+#[allow(non_snake_case)]
+const fn S_ISCHR(mode: Mode) -> bool {
+    (mode.0 & S_IFMT.0) == S_IFCHR.0
+}
+
+#[allow(non_snake_case)]
+const fn S_ISBLK(mode: Mode) -> bool {
+    (mode.0 & S_IFMT.0) == S_IFBLK.0
+}
+
 #[derive(
     Default,
     Clone,
@@ -89,6 +113,30 @@ impl Mode {
         self
     }
 
+    #[inline]
+    pub const fn add_fifo_flag(mut self) -> Self {
+        self.0 |= S_IFIFO.0;
+        self
+    }
+
+    #[inline]
+    pub const fn add_socket_flag(mut self) -> Self {
+        self.0 |= S_IFSOCK.0;
+        self
+    }
+
+    #[inline]
+    pub const fn add_char_device_flag(mut self) -> Self {
+        self.0 |= S_IFCHR.0;
+        self
+    }
+
+    #[inline]
+    pub const fn add_block_device_flag(mut self) -> Self {
+        self.0 |= S_IFBLK.0;
+        self
+    }
+
     #[inline]
     pub const fn add_user_read_flag(mut self) -> Self {
         self.0 |= S_IRUSR.0;
@@ -151,6 +199,14 @@ impl Mode {
             NodeKind::Dir
         } else if S_ISLNK(self) {
             NodeKind::Symlink
+        } else if S_ISFIFO(self) {
+            NodeKind::Fifo
+        } else if S_ISSOCK(self) {
+            NodeKind::Socket
+        } else if S_ISCHR(self) {
+            NodeKind::CharDevice
+        } else if S_ISBLK(self) {
+            NodeKind::BlockDevice
         } else {
             // TODO What to do here? Maybe we should instead check this invariant when Mode objects get created or modified.
             panic!("invalid mode")
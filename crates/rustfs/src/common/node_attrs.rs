@@ -22,6 +22,9 @@ pub struct NodeAttrs {
     /// with that default, you can leave this field as `None`.
     pub num_blocks: Option<u64>,
 
+    /// Device number for char and block devices. Zero for all other node kinds.
+    pub rdev: u32,
+
     #[debug("{}", format_datetime(*atime))]
     pub atime: SystemTime,
     #[debug("{}", format_datetime(*mtime))]
@@ -0,0 +1,36 @@
+use super::{FsError, FsResult};
+
+/// How a `rename` call should treat an already existing destination entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RenameMode {
+    /// Replace the destination if it exists.
+    Overwrite,
+    /// Fail with [FsError::NodeAlreadyExists] if the destination exists (`RENAME_NOREPLACE`).
+    NoReplace,
+    /// Atomically swap source and destination, both have to exist (`RENAME_EXCHANGE`).
+    Exchange,
+}
+
+impl RenameMode {
+    /// Parse the `flags` argument of the `renameat2` syscall
+    #[cfg(target_os = "linux")]
+    pub fn from_flags(flags: u32) -> FsResult<Self> {
+        match flags {
+            0 => Ok(Self::Overwrite),
+            libc::RENAME_NOREPLACE => Ok(Self::NoReplace),
+            libc::RENAME_EXCHANGE => Ok(Self::Exchange),
+            // RENAME_NOREPLACE and RENAME_EXCHANGE together, or RENAME_WHITEOUT which is only needed by overlayfs
+            _ => Err(FsError::InvalidOperation),
+        }
+    }
+
+    /// Parse the `flags` argument of the `rename` call. The flags are linux specific, other systems can only do plain renames.
+    #[cfg(not(target_os = "linux"))]
+    pub fn from_flags(flags: u32) -> FsResult<Self> {
+        if flags == 0 {
+            Ok(Self::Overwrite)
+        } else {
+            Err(FsError::InvalidOperation)
+        }
+    }
+}
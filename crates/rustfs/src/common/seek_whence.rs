@@ -0,0 +1,22 @@
+use super::{FsError, FsResult};
+
+/// Which `lseek` operation the kernel forwards to the file system. The other `whence` values
+/// (`SEEK_SET`, `SEEK_CUR`, `SEEK_END`) are handled by the kernel itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SeekWhence {
+    /// Seek to the next offset that contains data (`SEEK_DATA`)
+    Data,
+    /// Seek to the next offset that is in a hole (`SEEK_HOLE`). The end of the file counts as a hole.
+    Hole,
+}
+
+impl SeekWhence {
+    /// Parse the `whence` argument of the `lseek` syscall
+    pub fn from_whence(whence: libc::c_int) -> FsResult<Self> {
+        match whence {
+            libc::SEEK_DATA => Ok(Self::Data),
+            libc::SEEK_HOLE => Ok(Self::Hole),
+            _ => Err(FsError::InvalidOperation),
+        }
+    }
+}
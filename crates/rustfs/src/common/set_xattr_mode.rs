@@ -0,0 +1,27 @@
+use super::FsError;
+
+/// Whether a `setxattr` call may create a new extended attribute, replace an existing one, or both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SetXattrMode {
+    /// Create the attribute if it doesn't exist, or replace its value if it does.
+    CreateOrReplace,
+    /// Fail with [FsError::XattrAlreadyExists] if the attribute already exists (`XATTR_CREATE`).
+    Create,
+    /// Fail with [FsError::XattrDoesNotExist] if the attribute doesn't exist yet (`XATTR_REPLACE`).
+    Replace,
+}
+
+impl SetXattrMode {
+    /// Parse the `flags` argument of the `setxattr` syscall
+    pub fn from_flags(flags: libc::c_int) -> Result<Self, FsError> {
+        match (
+            flags & libc::XATTR_CREATE != 0,
+            flags & libc::XATTR_REPLACE != 0,
+        ) {
+            (false, false) => Ok(Self::CreateOrReplace),
+            (true, false) => Ok(Self::Create),
+            (false, true) => Ok(Self::Replace),
+            (true, true) => Err(FsError::InvalidOperation),
+        }
+    }
+}
@@ -177,11 +177,13 @@ pub trait AsyncFilesystem {
     ///
     /// * `oldpath`: path to the existing entry
     /// * `newpath`: path the entry should be reachable at after the rename/move operation
+    /// * `flags`: `0`, `RENAME_NOREPLACE` or `RENAME_EXCHANGE`, see [crate::RenameMode::from_flags].
     async fn rename(
         &self,
         req: RequestInfo,
         oldpath: &AbsolutePath,
         newpath: &AbsolutePath,
+        flags: u32,
     ) -> FsResult<()>;
 
     /// Create a hard link.
@@ -8,9 +8,10 @@ pub mod low_level_api;
 
 mod common;
 pub use common::{
-    AtimeUpdateBehavior, DirEntry, DirEntryOrReference, FileHandle, FsError, FsResult, Gid,
-    InodeNumber, Mode, NodeAttrs, NodeKind, NumBytes, OpenInFlags, OpenOutFlags, RequestInfo,
-    Statfs, Uid,
+    AtimeUpdateBehavior, DirEntry, DirEntryOrReference, FallocateMode, FileHandle, FsError,
+    FsResult, Gid, InodeNumber, Invalidation, KernelCacheInvalidator, KernelCacheOptions, Mode,
+    NodeAttrs, NodeKind, NumBytes, OpenInFlags, OpenOutFlags, RenameMode, RequestInfo, SeekWhence,
+    SetXattrMode, Statfs, Uid,
 };
 
 pub mod backend;
@@ -125,6 +125,25 @@ pub trait ReplyDirectory {
 }
 
 pub trait ReplyDirectoryPlus {
+    /// Add the '.' entry to the directory reply buffer. Returns whether the buffer is full.
+    /// The kernel doesn't look up '.' and '..' from readdirplus, so their attributes are ignored and they don't need a `forget` call.
+    #[must_use]
+    fn add_self_reference(
+        &mut self,
+        ino: InodeNumber,
+        offset: i64,
+        attr: &NodeAttrs,
+    ) -> ReplyDirectoryAddResult;
+
+    /// Add the '..' entry to the directory reply buffer. Returns whether the buffer is full.
+    #[must_use]
+    fn add_parent_reference(
+        &mut self,
+        ino: InodeNumber,
+        offset: i64,
+        attr: &NodeAttrs,
+    ) -> ReplyDirectoryAddResult;
+
     /// Add an entry to the directory reply buffer. Returns whether the buffer is full.
     /// A transparent offset value can be provided for each entry. The kernel uses these
     /// value to request the next entries in further readdir calls
@@ -159,6 +178,17 @@ pub trait AsyncFilesystemLL {
         name: &PathComponent,
     ) -> FsResult<ReplyEntry>;
 
+    /// Look up the "." entry of an inode, i.e. the inode itself.
+    /// The kernel only sends this if the file system supports being exported over NFS, to resolve an NFS file handle into an inode.
+    /// The kernel may not hold a reference to `ino` anymore, e.g. because the file system was remounted since the file handle was created.
+    /// Counts as a lookup, i.e. the kernel will call `forget` for it.
+    async fn lookup_self(&self, req: &RequestInfo, ino: InodeNumber) -> FsResult<ReplyEntry>;
+
+    /// Look up the ".." entry of a directory inode, i.e. its parent directory.
+    /// The kernel only sends this if the file system supports being exported over NFS.
+    /// Counts as a lookup, i.e. the kernel will call `forget` for it.
+    async fn lookup_parent(&self, req: &RequestInfo, ino: InodeNumber) -> FsResult<ReplyEntry>;
+
     /// Forget about an inode.
     /// The nlookup parameter indicates the number of lookups previously performed on
     /// this inode. If the filesystem implements inode lifetimes, it is recommended that
@@ -4,7 +4,7 @@ use std::path::Path;
 use cryfs_utils::async_drop::{AsyncDrop, AsyncDropGuard};
 use tokio_util::sync::CancellationToken;
 
-use crate::FsError;
+use crate::{FsError, KernelCacheOptions};
 
 pub use fuser::MountOption;
 
@@ -25,6 +25,7 @@ pub trait RustfsBackend: Send + Sync {
         runtime: tokio::runtime::Handle,
         unmount_trigger: Option<CancellationToken>,
         mount_options: &[MountOption],
+        kernel_cache: KernelCacheOptions,
         on_successfully_mounted: impl FnOnce(),
     ) -> std::io::Result<()>
     where
@@ -37,6 +38,7 @@ pub trait RustfsBackend: Send + Sync {
         mountpoint: impl AsRef<Path>,
         runtime: tokio::runtime::Handle,
         mount_options: &[MountOption],
+        kernel_cache: KernelCacheOptions,
     ) -> std::io::Result<RunningFilesystem<Self::BackgroundSession>>
     where
         Fs: Device + AsyncDrop<Error = FsError> + Send + Sync + Debug + 'static,
@@ -56,6 +58,7 @@ impl RustfsBackend for RustfsFuserBackend {
         runtime: tokio::runtime::Handle,
         unmount_trigger: Option<CancellationToken>,
         mount_options: &[MountOption],
+        kernel_cache: KernelCacheOptions,
         on_successfully_mounted: impl FnOnce(),
     ) -> std::io::Result<()>
     where
@@ -66,11 +69,12 @@ impl RustfsBackend for RustfsFuserBackend {
         use crate::object_based_api::ObjectBasedFsAdapterLL;
 
         crate::backend::fuser::mount(
-            ObjectBasedFsAdapterLL::new(fs),
+            ObjectBasedFsAdapterLL::new(fs, kernel_cache.clone()),
             mountpoint,
             runtime,
             unmount_trigger,
             mount_options,
+            kernel_cache,
             on_successfully_mounted,
         )
         .await
@@ -81,6 +85,7 @@ impl RustfsBackend for RustfsFuserBackend {
         mountpoint: impl AsRef<Path>,
         runtime: tokio::runtime::Handle,
         mount_options: &[MountOption],
+        kernel_cache: KernelCacheOptions,
     ) -> std::io::Result<RunningFilesystem<Self::BackgroundSession>>
     where
         Fs: Device + AsyncDrop<Error = FsError> + Send + Sync + Debug + 'static,
@@ -90,15 +95,18 @@ impl RustfsBackend for RustfsFuserBackend {
         use crate::object_based_api::ObjectBasedFsAdapterLL;
 
         crate::backend::fuser::spawn_mount(
-            ObjectBasedFsAdapterLL::new(fs),
+            ObjectBasedFsAdapterLL::new(fs, kernel_cache.clone()),
             mountpoint,
             runtime,
             mount_options,
+            kernel_cache,
         )
         .await
     }
 }
 
+/// fuse-mt doesn't let us configure the kernel, so this backend only applies the TTLs from [KernelCacheOptions]
+/// and drops invalidations.
 #[cfg(feature = "fuse_mt")]
 pub struct RustfsFusemtBackend;
 #[cfg(feature = "fuse_mt")]
@@ -111,6 +119,7 @@ impl RustfsBackend for RustfsFusemtBackend {
         runtime: tokio::runtime::Handle,
         unmount_trigger: Option<CancellationToken>,
         mount_options: &[MountOption],
+        kernel_cache: KernelCacheOptions,
         on_successfully_mounted: impl FnOnce(),
     ) -> std::io::Result<()>
     where
@@ -121,7 +130,7 @@ impl RustfsBackend for RustfsFusemtBackend {
         use crate::object_based_api::ObjectBasedFsAdapter;
 
         crate::backend::fuse_mt::mount(
-            ObjectBasedFsAdapter::new(fs),
+            ObjectBasedFsAdapter::new(fs, &kernel_cache),
             mountpoint,
             runtime,
             unmount_trigger,
@@ -136,6 +145,7 @@ impl RustfsBackend for RustfsFusemtBackend {
         mountpoint: impl AsRef<Path>,
         runtime: tokio::runtime::Handle,
         mount_options: &[MountOption],
+        kernel_cache: KernelCacheOptions,
     ) -> std::io::Result<RunningFilesystem<Self::BackgroundSession>>
     where
         Fs: Device + AsyncDrop<Error = FsError> + Send + Sync + Debug + 'static,
@@ -145,7 +155,7 @@ impl RustfsBackend for RustfsFusemtBackend {
         use crate::object_based_api::ObjectBasedFsAdapter;
 
         crate::backend::fuse_mt::spawn_mount(
-            ObjectBasedFsAdapter::new(fs),
+            ObjectBasedFsAdapter::new(fs, &kernel_cache),
             mountpoint,
             runtime,
             mount_options,
@@ -1,31 +1,27 @@
 use async_trait::async_trait;
+use futures::join;
 use std::fmt::Debug;
 use std::sync::{Arc, RwLock};
 use std::time::{Duration, SystemTime};
 
-use super::utils::{MaybeInitializedFs, OpenFileList};
+use super::utils::{MaybeInitializedFs, OpenFileList, xattr_name_list, xattr_value_reply};
 use super::{Device, Dir, File, Node, OpenFile, Symlink};
 use crate::common::{
-    Callback, DirEntryOrReference, FileHandle, FsError, FsResult, Gid, HandleTrait as _, Mode,
-    NumBytes, OpenInFlags, OpenOutFlags, RequestInfo, Statfs, Uid,
+    Callback, DirEntryOrReference, FileHandle, FsError, FsResult, Gid, HandleTrait as _,
+    KernelCacheOptions, Mode, NodeKind, NumBytes, OpenInFlags, OpenOutFlags, RenameMode,
+    RequestInfo, SetXattrMode, Statfs, Uid,
 };
 use crate::high_level_api::{
     AsyncFilesystem, AttrResponse, CreateResponse, OpenResponse, OpendirResponse,
 };
 use cryfs_utils::{
-    async_drop::{AsyncDrop, AsyncDropGuard},
+    async_drop::{AsyncDrop, AsyncDropGuard, flatten_async_drop},
     path::AbsolutePath,
     with_async_drop_2,
 };
 
 // TODO Make sure each function checks the preconditions on its parameters, e.g. paths must be absolute, here and elsewhere.
 
-// TODO Set these TTLs to the fuse defaults
-const TTL_GETATTR: Duration = Duration::from_secs(1);
-const TTL_MKDIR: Duration = Duration::from_secs(1);
-const TTL_SYMLINK: Duration = Duration::from_secs(1);
-const TTL_CREATE: Duration = Duration::from_secs(1);
-
 pub struct ObjectBasedFsAdapter<Fs>
 where
     // TODO Is this send+sync bound only needed because fuse_mt goes multi threaded or would it also be required for fuser?
@@ -36,6 +32,11 @@ where
     fs: Arc<RwLock<AsyncDropGuard<MaybeInitializedFs<Fs>>>>,
 
     open_files: AsyncDropGuard<OpenFileList<Fs::OpenFile>>,
+
+    /// TTL for replies with the attributes of a node
+    attr_ttl: Duration,
+    /// TTL for replies that create an entry, see [KernelCacheOptions::entry_and_attr_ttl]
+    entry_ttl: Duration,
 }
 
 impl<Fs> ObjectBasedFsAdapter<Fs>
@@ -46,12 +47,15 @@ where
 {
     pub fn new(
         fs: impl FnOnce(Uid, Gid) -> AsyncDropGuard<Fs> + Send + Sync + 'static,
+        kernel_cache: &KernelCacheOptions,
     ) -> AsyncDropGuard<Self> {
         AsyncDropGuard::new(Self {
             fs: Arc::new(RwLock::new(MaybeInitializedFs::new_uninitialized(
                 Box::new(fs),
             ))),
             open_files: OpenFileList::new(),
+            attr_ttl: kernel_cache.attr_ttl,
+            entry_ttl: kernel_cache.entry_and_attr_ttl(),
         })
     }
 
@@ -138,7 +142,7 @@ where
             with_async_drop_2!(node, { node.getattr().await })?
         };
         Ok(AttrResponse {
-            ttl: TTL_GETATTR,
+            ttl: self.attr_ttl,
             attrs: attrs,
         })
     }
@@ -287,15 +291,54 @@ where
 
     async fn mknod(
         &self,
-        _req: RequestInfo,
-        _path: &AbsolutePath,
-        _mode: Mode,
-        _rdev: u32,
+        req: RequestInfo,
+        path: &AbsolutePath,
+        mode: Mode,
+        rdev: u32,
     ) -> FsResult<AttrResponse> {
         self.trigger_on_operation().await?;
 
-        // TODO Do we want to implement this?
-        Err(FsError::NotImplemented)
+        let (parent, name) = path.split_last().ok_or_else(|| {
+            assert!(path.is_root());
+            log::error!("mknod: called with root path");
+            FsError::InvalidOperation
+        })?;
+        let fs = self.fs.read().unwrap();
+        let parent_dir = fs.get().lookup(parent).await?;
+        with_async_drop_2!(parent_dir, {
+            let parent_dir = parent_dir.as_dir().await?;
+            with_async_drop_2!(parent_dir, {
+                // TODO No need to return the node object to just immediately async_drop it
+                let (attrs, mut node) = match mode.node_kind() {
+                    NodeKind::File => {
+                        let (attrs, node, mut open_file) = parent_dir
+                            .create_and_open_file(&name, mode, req.uid, req.gid, OpenInFlags::Read)
+                            .await?;
+                        open_file.async_drop().await?;
+                        (attrs, node)
+                    }
+                    NodeKind::Fifo
+                    | NodeKind::Socket
+                    | NodeKind::CharDevice
+                    | NodeKind::BlockDevice => {
+                        parent_dir
+                            .create_child_special_file(&name, mode, req.uid, req.gid, rdev)
+                            .await?
+                    }
+                    NodeKind::Dir | NodeKind::Symlink => {
+                        log::warn!(
+                            "mknod: called with mode {mode} that isn't a file or special file"
+                        );
+                        return Err(FsError::InvalidOperation);
+                    }
+                };
+                node.async_drop().await?;
+                Ok(AttrResponse {
+                    ttl: self.entry_ttl,
+                    attrs,
+                })
+            })
+        })
     }
 
     async fn mkdir(
@@ -324,7 +367,7 @@ where
                     .await?;
                 new_dir.async_drop().await?;
                 Ok(AttrResponse {
-                    ttl: TTL_MKDIR,
+                    ttl: self.entry_ttl,
                     attrs: new_dir_attrs,
                 })
             })
@@ -393,7 +436,7 @@ where
                     .await?;
                 symlink.async_drop().await?;
                 Ok(AttrResponse {
-                    ttl: TTL_SYMLINK,
+                    ttl: self.entry_ttl,
                     attrs: new_symlink_attrs,
                 })
             })
@@ -405,9 +448,11 @@ where
         _req: RequestInfo,
         oldpath: &AbsolutePath,
         newpath: &AbsolutePath,
+        flags: u32,
     ) -> FsResult<()> {
         self.trigger_on_operation().await?;
 
+        let mode = RenameMode::from_flags(flags)?;
         if oldpath.is_root() {
             log::error!("rename: tried to rename the root directory into '{newpath}'");
             return Err(FsError::InvalidOperation);
@@ -417,21 +462,44 @@ where
             return Err(FsError::InvalidOperation);
         };
         let fs = self.fs.read().unwrap();
-        // TODO Should rename overwrite a potentially already existing target or not? Make sure we handle that the right way.
-        fs.get().rename(oldpath, newpath).await?;
+        fs.get().rename(oldpath, newpath, mode).await?;
         Ok(())
     }
 
     async fn link(
         &self,
         _req: RequestInfo,
-        _oldpath: &AbsolutePath,
-        _newpath: &AbsolutePath,
+        oldpath: &AbsolutePath,
+        newpath: &AbsolutePath,
     ) -> FsResult<AttrResponse> {
         self.trigger_on_operation().await?;
 
-        // TODO Should we implement this?
-        Err(FsError::NotImplemented)
+        let (newparent, newname) = newpath.split_last().ok_or_else(|| {
+            assert!(newpath.is_root());
+            log::error!("link: called with root path as target");
+            FsError::InvalidOperation
+        })?;
+        let fs = self.fs.read().unwrap();
+        let (node, newparent_dir) = join!(fs.get().lookup(oldpath), fs.get().lookup(newparent));
+        let (mut node, mut newparent_dir) =
+            flatten_async_drop::<FsError, _, _, _, _>(node, newparent_dir).await?;
+        let result = async {
+            let newparent_dir = newparent_dir.as_dir().await?;
+            with_async_drop_2!(newparent_dir, {
+                // TODO No need to return the node object to just immediately async_drop it
+                let (attrs, mut new_node) = newparent_dir.create_child_link(newname, &node).await?;
+                new_node.async_drop().await?;
+                Ok(AttrResponse {
+                    ttl: self.entry_ttl,
+                    attrs,
+                })
+            })
+        }
+        .await;
+        // TODO Drop concurrently and drop latter even if first one fails
+        node.async_drop().await?;
+        newparent_dir.async_drop().await?;
+        result
     }
 
     async fn open(
@@ -638,75 +706,92 @@ where
     async fn setxattr(
         &self,
         _req: RequestInfo,
-        _path: &AbsolutePath,
-        _name: &str,
-        _value: &[u8],
-        _flags: u32,
-        _position: NumBytes,
+        path: &AbsolutePath,
+        name: &str,
+        value: &[u8],
+        flags: u32,
+        position: NumBytes,
     ) -> FsResult<()> {
         self.trigger_on_operation().await?;
 
-        // TODO Should we implement this?
-        Err(FsError::NotImplemented)
+        let mode = SetXattrMode::from_flags(flags as libc::c_int)?;
+        if position != NumBytes::from(0) {
+            // A nonzero position is only used by macOS for resource forks
+            return Err(FsError::XattrNotSupported);
+        }
+        let fs = self.fs.read().unwrap();
+        let node = fs.get().lookup(path).await?;
+        with_async_drop_2!(node, { node.setxattr(name, value, mode).await })
     }
 
     async fn getxattr_numbytes(
         &self,
         _req: RequestInfo,
-        _path: &AbsolutePath,
-        _name: &str,
+        path: &AbsolutePath,
+        name: &str,
     ) -> FsResult<NumBytes> {
         self.trigger_on_operation().await?;
 
-        // TODO Should we implement this?
-        Err(FsError::NotImplemented)
+        // TODO Add a Node::getxattr_numbytes so we don't have to load the value just to get its size
+        let fs = self.fs.read().unwrap();
+        let node = fs.get().lookup(path).await?;
+        let value = with_async_drop_2!(node, { node.getxattr(name).await })?;
+        Ok(NumBytes::from(value.len() as u64))
     }
 
     async fn getxattr_data(
         &self,
         _req: RequestInfo,
-        _path: &AbsolutePath,
-        _name: &str,
-        _size: NumBytes,
+        path: &AbsolutePath,
+        name: &str,
+        size: NumBytes,
     ) -> FsResult<Vec<u8>> {
         self.trigger_on_operation().await?;
 
-        // TODO Should we implement this?
-        Err(FsError::NotImplemented)
+        let fs = self.fs.read().unwrap();
+        let node = fs.get().lookup(path).await?;
+        let value = with_async_drop_2!(node, { node.getxattr(name).await })?;
+        xattr_value_reply(value, size)
     }
 
     async fn listxattr_numbytes(
         &self,
         _req: RequestInfo,
-        _path: &AbsolutePath,
+        path: &AbsolutePath,
     ) -> FsResult<NumBytes> {
         self.trigger_on_operation().await?;
 
-        // TODO Should we implement this?
-        Err(FsError::NotImplemented)
+        let fs = self.fs.read().unwrap();
+        let node = fs.get().lookup(path).await?;
+        let names = with_async_drop_2!(node, { node.listxattr().await })?;
+        Ok(NumBytes::from(xattr_name_list(names).len() as u64))
     }
 
     async fn listxattr_data(
         &self,
         _req: RequestInfo,
-        _path: &AbsolutePath,
-        _size: NumBytes,
+        path: &AbsolutePath,
+        size: NumBytes,
     ) -> FsResult<Vec<u8>> {
         self.trigger_on_operation().await?;
 
-        // TODO Should we implement this?
-        Err(FsError::NotImplemented)
+        let fs = self.fs.read().unwrap();
+        let node = fs.get().lookup(path).await?;
+        let names = with_async_drop_2!(node, { node.listxattr().await })?;
+        xattr_value_reply(xattr_name_list(names), size)
     }
 
     async fn removexattr(
         &self,
         _req: RequestInfo,
-        _path: &AbsolutePath,
-        _name: &str,
+        path: &AbsolutePath,
+        name: &str,
     ) -> FsResult<()> {
         self.trigger_on_operation().await?;
 
-        Err(FsError::NotImplemented)
+        let fs = self.fs.read().unwrap();
+        let node = fs.get().lookup(path).await?;
+        with_async_drop_2!(node, { node.removexattr(name).await })
     }
 
     async fn access(&self, _req: RequestInfo, _path: &AbsolutePath, _mask: u32) -> FsResult<()> {
@@ -745,7 +830,7 @@ where
             node.async_drop().await?;
             let fh = self.open_files.add(open_file);
             Ok(CreateResponse {
-                ttl: TTL_CREATE,
+                ttl: self.entry_ttl,
                 attrs: file_attrs,
                 fh: fh.handle,
                 flags: OpenOutFlags {},
@@ -3,10 +3,10 @@ use std::fmt::Debug;
 
 use super::dir::Dir;
 use super::node::Node;
-use crate::common::{FsError, FsResult, Statfs};
+use crate::common::{FsError, FsResult, InodeNumber, RenameMode, Statfs};
 use cryfs_utils::{
     async_drop::{AsyncDrop, AsyncDropGuard},
-    path::AbsolutePath,
+    path::{AbsolutePath, AbsolutePathBuf},
     with_async_drop_2,
 };
 
@@ -45,9 +45,26 @@ pub trait Device {
     async fn rootdir(&self) -> FsResult<AsyncDropGuard<Self::Dir<'_>>>;
 
     // TODO We can probably remove `rename`. It's only called from the fuse-mt backend and fuser uses CryDir::{rename_child,move_child_to} instead. We can probably make fuse-mt use those too.
-    fn rename(&self, from: &AbsolutePath, to: &AbsolutePath) -> impl Future<Output = FsResult<()>>;
+    fn rename(
+        &self,
+        from: &AbsolutePath,
+        to: &AbsolutePath,
+        mode: RenameMode,
+    ) -> impl Future<Output = FsResult<()>>;
     async fn statfs(&self) -> FsResult<Statfs>;
 
+    /// Find the path of the node whose [Node::stable_id](super::Node::stable_id) gives the inode number `ino`.
+    /// This is called when the kernel asks for an inode that isn't loaded, e.g. when an NFS client uses a file handle
+    /// it got before the file system was remounted. It's only called for inode numbers with [InodeNumber::is_stable] set.
+    /// Returns `None` if there is no such node, in which case the kernel gets an `ESTALE` error.
+    /// The default implementation doesn't support this and always returns `None`.
+    async fn find_path_by_stable_ino(&self, _ino: InodeNumber) -> FsResult<Option<AbsolutePathBuf>>
+    where
+        Self: 'static,
+    {
+        Ok(None)
+    }
+
     // If the node at `path` doesn't exist, it's ok to either immediately fail with [FsError::NodeDoesNotExist]
     // or to return a [Node] object that throws [FsError::NodeDoesNotExist] when any of its members that
     // require existence are called.
@@ -1,13 +1,15 @@
 use async_trait::async_trait;
 use std::fmt::Debug;
 
+use super::Node as _;
 use crate::{
     OpenInFlags,
-    common::{DirEntry, FsResult, Gid, Mode, NodeAttrs, Uid},
+    common::{DirEntry, FsResult, Gid, Mode, NodeAttrs, RenameMode, Uid},
 };
 use cryfs_utils::{
     async_drop::{AsyncDrop, AsyncDropGuard},
     path::PathComponent,
+    with_async_drop_2,
 };
 
 #[async_trait]
@@ -20,6 +22,20 @@ pub trait Dir: AsyncDrop + Debug + Sized {
 
     async fn entries(&self) -> FsResult<Vec<DirEntry>>;
 
+    /// Like [Dir::entries], but also returns the attributes of each entry. This is used for `readdirplus`.
+    /// The default implementation looks up each child. Implementations that already have the attributes
+    /// when listing the entries should override it.
+    async fn entries_with_attrs(&self) -> FsResult<Vec<(DirEntry, NodeAttrs)>> {
+        let entries = self.entries().await?;
+        let mut result = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let child = self.lookup_child(&entry.name).await?;
+            let attrs = with_async_drop_2!(child, { child.getattr().await })?;
+            result.push((entry, attrs));
+        }
+        Ok(result)
+    }
+
     /// If the child doesn't exist, this must fail with [crate::FsError::NodeDoesNotExist] rather than returning a [super::Node]
     /// object that throws [crate::FsError::NodeDoesNotExist] when any of its members that require existence are called.
     async fn lookup_child(
@@ -27,13 +43,23 @@ pub trait Dir: AsyncDrop + Debug + Sized {
         name: &PathComponent,
     ) -> FsResult<AsyncDropGuard<<Self::Device as super::Device>::Node>>;
 
-    async fn rename_child(&self, oldname: &PathComponent, newname: &PathComponent) -> FsResult<()>;
+    /// Rename the child `oldname` to `newname` within this directory.
+    /// With [RenameMode::NoReplace], this must fail with [crate::FsError::NodeAlreadyExists] if `newname` already exists.
+    /// With [RenameMode::Exchange], both entries must exist and are swapped atomically.
+    async fn rename_child(
+        &self,
+        oldname: &PathComponent,
+        newname: &PathComponent,
+        mode: RenameMode,
+    ) -> FsResult<()>;
 
+    /// Like [Dir::rename_child], but the destination `newname` is in the directory `newparent`.
     async fn move_child_to(
         &self,
         oldname: &PathComponent,
         newparent: AsyncDropGuard<Self>,
         newname: &PathComponent,
+        mode: RenameMode,
     ) -> FsResult<()>;
 
     async fn create_child_dir(
@@ -61,6 +87,31 @@ pub trait Dir: AsyncDrop + Debug + Sized {
         AsyncDropGuard<<Self::Device as super::Device>::Symlink<'_>>,
     )>;
 
+    /// Create a hard link `name` in this directory that points to the existing `node`.
+    /// Directories can't be hard linked.
+    async fn create_child_link(
+        &self,
+        name: &PathComponent,
+        node: &<Self::Device as super::Device>::Node,
+    ) -> FsResult<(
+        NodeAttrs,
+        AsyncDropGuard<<Self::Device as super::Device>::Node>,
+    )>;
+
+    /// Create a fifo, socket, char device or block device `name` in this directory.
+    /// The node kind is taken from `mode`. `rdev` is the device number and only used for devices.
+    async fn create_child_special_file(
+        &self,
+        name: &PathComponent,
+        mode: Mode,
+        uid: Uid,
+        gid: Gid,
+        rdev: u32,
+    ) -> FsResult<(
+        NodeAttrs,
+        AsyncDropGuard<<Self::Device as super::Device>::Node>,
+    )>;
+
     async fn remove_child_file_or_symlink(&self, name: &PathComponent) -> FsResult<()>;
 
     async fn create_and_open_file(
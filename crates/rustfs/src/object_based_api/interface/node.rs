@@ -2,7 +2,7 @@ use async_trait::async_trait;
 use cryfs_utils::async_drop::AsyncDropGuard;
 use std::time::SystemTime;
 
-use crate::common::{FsResult, Gid, Mode, NodeAttrs, NumBytes, Uid};
+use crate::common::{FsResult, Gid, Mode, NodeAttrs, NumBytes, SetXattrMode, Uid};
 
 #[async_trait]
 pub trait Node {
@@ -14,6 +14,14 @@ pub trait Node {
         &self,
     ) -> FsResult<AsyncDropGuard<<Self::Device as super::Device>::Symlink<'_>>>;
 
+    /// An id that identifies this node across remounts, e.g. the id of the blob storing it.
+    /// Inode numbers and generations given to the kernel are derived from it with [InodeNumber::from_stable_id](crate::InodeNumber::from_stable_id),
+    /// which keeps them stable across remounts and lets hard links share one inode number.
+    /// Returning `None` (the default) gives the node an inode number that is only valid while it is loaded.
+    fn stable_id(&self) -> Option<u128> {
+        None
+    }
+
     async fn getattr(&self) -> FsResult<NodeAttrs>;
     async fn setattr(
         &self,
@@ -26,6 +34,20 @@ pub trait Node {
         ctime: Option<SystemTime>,
     ) -> FsResult<NodeAttrs>;
 
+    /// Get the value of the extended attribute `name`.
+    /// Return [FsError::XattrDoesNotExist](crate::FsError::XattrDoesNotExist) if it doesn't exist.
+    async fn getxattr(&self, name: &str) -> FsResult<Vec<u8>>;
+
+    /// List the names of all extended attributes of this node.
+    async fn listxattr(&self) -> FsResult<Vec<String>>;
+
+    /// Set the extended attribute `name` to `value`.
+    async fn setxattr(&self, name: &str, value: &[u8], mode: SetXattrMode) -> FsResult<()>;
+
+    /// Remove the extended attribute `name`.
+    /// Return [FsError::XattrDoesNotExist](crate::FsError::XattrDoesNotExist) if it doesn't exist.
+    async fn removexattr(&self, name: &str) -> FsResult<()>;
+
     #[cfg(feature = "testutils")]
     async fn fsync(&self, datasync: bool) -> FsResult<()>;
 }
@@ -2,7 +2,7 @@ use async_trait::async_trait;
 use std::fmt::Debug;
 use std::time::SystemTime;
 
-use crate::common::{FsResult, Gid, Mode, NodeAttrs, NumBytes, Uid};
+use crate::common::{FallocateMode, FsResult, Gid, Mode, NodeAttrs, NumBytes, SeekWhence, Uid};
 use cryfs_utils::data::Data;
 
 #[async_trait]
@@ -22,6 +22,29 @@ pub trait OpenFile: Debug {
     // TODO Is it a better API to return a &[u8] from `read` by having the implementation pass &[u8] to a callback instead of returning a Data object? Might reduce copies. fuse-mt does this.
     async fn read(&self, offset: NumBytes, size: NumBytes) -> FsResult<Data>;
     async fn write(&self, offset: NumBytes, data: Data) -> FsResult<()>;
+
+    /// Allocate, deallocate or zero the range of `length` bytes starting at `offset`, see [FallocateMode].
+    async fn fallocate(
+        &self,
+        offset: NumBytes,
+        length: NumBytes,
+        mode: FallocateMode,
+    ) -> FsResult<()>;
+
+    /// Find the next offset at or after `offset` that contains data or is in a hole, depending on `whence`.
+    /// Return [FsError::OffsetBeyondEndOfFile](crate::FsError::OffsetBeyondEndOfFile) if `offset` is at or beyond the end of the file.
+    async fn lseek(&self, offset: NumBytes, whence: SeekWhence) -> FsResult<NumBytes>;
+
+    /// Copy up to `len` bytes starting at `offset_in` in this file to `offset_out` in `dest`, which can be the same file.
+    /// Return the number of bytes copied, which is less than `len` if this file ends before `offset_in + len`.
+    async fn copy_file_range(
+        &self,
+        offset_in: NumBytes,
+        dest: &Self,
+        offset_out: NumBytes,
+        len: NumBytes,
+    ) -> FsResult<NumBytes>;
+
     async fn flush(&self) -> FsResult<()>;
     async fn fsync(&self, datasync: bool) -> FsResult<()>;
 }
@@ -1,40 +1,39 @@
 use async_trait::async_trait;
 use futures::join;
+use std::collections::HashMap;
 use std::fmt::Debug;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 use tokio::sync::RwLock;
 
-use super::utils::{MaybeInitializedFs, OpenFileList};
+use super::utils::{MaybeInitializedFs, OpenFileList, xattr_name_list, xattr_value_reply};
 use super::{Device, Dir, File, Node, OpenFile, Symlink};
 #[cfg(target_os = "macos")]
 use crate::low_level_api::ReplyXTimes;
-use crate::object_based_api::utils::{DUMMY_INO, MakeOrphanError, MoveInodeError};
+use crate::object_based_api::utils::{
+    DUMMY_INO, ExchangeInodesError, FUSE_ROOT_ID, MakeOrphanError, MoveInodeError,
+};
 use crate::{
     DirEntry,
     common::{
-        Callback, FileHandle, FsError, FsResult, Gid, InodeNumber, Mode, NumBytes, OpenInFlags,
-        OpenOutFlags, RequestInfo, Statfs, Uid,
+        Callback, FallocateMode, FileHandle, FsError, FsResult, Gid, HandleWithGeneration,
+        InodeNumber, KernelCacheInvalidator, KernelCacheOptions, Mode, NodeAttrs, NodeKind,
+        NumBytes, OpenInFlags, OpenOutFlags, RenameMode, RequestInfo, SeekWhence, SetXattrMode,
+        Statfs, Uid,
     },
     low_level_api::{
         AsyncFilesystemLL, ReplyAttr, ReplyBmap, ReplyCreate, ReplyDirectory,
         ReplyDirectoryAddResult, ReplyDirectoryPlus, ReplyEntry, ReplyIoctl, ReplyLock, ReplyLseek,
         ReplyOpen, ReplyWrite,
     },
-    object_based_api::utils::{DirCache, InodeList, OpenDirHandle},
+    object_based_api::utils::{DirCache, InodeList, Lock, LockKind, LockManager, OpenDirHandle},
 };
 use cryfs_utils::{
     async_drop::{AsyncDrop, AsyncDropArc, AsyncDropGuard, flatten_async_drop},
-    path::PathComponent,
+    path::{PathComponent, PathComponentBuf},
     with_async_drop_2,
 };
 
-// TODO What are good TTLs here?
-const TTL_LOOKUP: Duration = Duration::from_secs(1);
-const TTL_GETATTR: Duration = Duration::from_secs(1);
-const TTL_CREATE: Duration = Duration::from_secs(1);
-const TTL_SYMLINK: Duration = Duration::from_secs(1);
-
 // TODO Can we share more code with [super::high_level_adapter::ObjectBasedFsAdapter]?
 pub struct ObjectBasedFsAdapterLL<Fs>
 where
@@ -50,6 +49,14 @@ where
 
     open_files: AsyncDropGuard<OpenFileList<Fs::OpenFile>>,
     open_dirs: AsyncDropGuard<DirCache>,
+
+    locks: LockManager,
+
+    /// TTL for replies with the attributes of a node
+    attr_ttl: Duration,
+    /// TTL for replies with an entry, see [KernelCacheOptions::entry_and_attr_ttl]
+    entry_ttl: Duration,
+    invalidator: KernelCacheInvalidator,
 }
 
 impl<Fs> ObjectBasedFsAdapterLL<Fs>
@@ -61,6 +68,7 @@ where
 {
     pub fn new(
         fs: impl FnOnce(Uid, Gid) -> AsyncDropGuard<Fs> + Send + Sync + 'static,
+        kernel_cache: KernelCacheOptions,
     ) -> AsyncDropGuard<Self> {
         AsyncDropGuard::new(Self {
             fs: Arc::new(RwLock::new(MaybeInitializedFs::new_uninitialized(
@@ -69,6 +77,10 @@ where
             inodes: InodeList::new(),
             open_files: OpenFileList::new(),
             open_dirs: DirCache::new(),
+            locks: LockManager::new(),
+            attr_ttl: kernel_cache.attr_ttl,
+            entry_ttl: kernel_cache.entry_and_attr_ttl(),
+            invalidator: kernel_cache.invalidator,
         })
     }
 
@@ -133,6 +145,88 @@ where
         self.inodes.get_node(ino).await
     }
 
+    /// Look up the child `name` of `parent_ino` and register it in the inode list, or increment its refcount if it is already registered.
+    /// Each call must be balanced by a `forget` call from the kernel.
+    async fn _lookup_and_register_child(
+        &self,
+        parent_ino: InodeNumber,
+        name: &PathComponent,
+    ) -> FsResult<(
+        HandleWithGeneration<InodeNumber>,
+        AsyncDropGuard<AsyncDropArc<Fs::Node>>,
+    )> {
+        let name_clone = name.to_owned();
+        let load_child = move |parent_node: &AsyncDropGuard<AsyncDropArc<Fs::Node>>| {
+            let parent_node = AsyncDropArc::clone(parent_node); // TODO Why is this necessary?
+            async move {
+                with_async_drop_2!(parent_node, {
+                    let parent_node_dir = parent_node
+                        .as_dir()
+                        .await
+                        .expect("Error: Inode number is not a directory");
+                    with_async_drop_2!(parent_node_dir, {
+                        // TODO Can we avoid the async_drop here by using something like parent_node_dir.into_lookup_child() ?
+                        parent_node_dir.lookup_child(&name_clone).await
+                    })
+                })
+            }
+        };
+
+        self.inodes
+            .add_or_increment_refcount(parent_ino, name.to_owned(), load_child)
+            .await
+    }
+
+    /// Register a node the kernel doesn't hold a reference to anymore, given only its stable inode number.
+    /// We find its path and look up each path component, so that the node and its ancestors are in the inode list
+    /// like after a normal path lookup. The kernel only holds a reference to the node itself, not to its ancestors.
+    async fn _register_by_stable_ino(
+        &self,
+        ino: InodeNumber,
+    ) -> FsResult<(
+        HandleWithGeneration<InodeNumber>,
+        AsyncDropGuard<AsyncDropArc<Fs::Node>>,
+    )> {
+        if !ino.is_stable() {
+            // Dynamically assigned inode numbers aren't valid after the kernel forgot them
+            return Err(FsError::StaleInode);
+        }
+        let path = {
+            let fs = self.fs.read().await;
+            fs.get().find_path_by_stable_ino(ino).await?
+        }
+        .ok_or(FsError::StaleInode)?;
+        let Some((parent_path, name)) = path.split_last() else {
+            // The root dir is always exposed as FUSE_ROOT_ID, never under a stable inode number
+            return Err(FsError::StaleInode);
+        };
+
+        let mut parent_ino = FUSE_ROOT_ID;
+        for component in parent_path {
+            let lookup_result = self._lookup_and_register_child(parent_ino, component).await;
+            if parent_ino != FUSE_ROOT_ID {
+                // The child we just registered keeps its parent alive, so we can give up our own reference
+                self.inodes.forget(parent_ino, 1).await?;
+            }
+            let (child_ino, mut child) = lookup_result?;
+            child.async_drop().await?;
+            parent_ino = child_ino.handle;
+        }
+        let lookup_result = self._lookup_and_register_child(parent_ino, name).await;
+        if parent_ino != FUSE_ROOT_ID {
+            self.inodes.forget(parent_ino, 1).await?;
+        }
+        let (child_ino, mut child) = lookup_result?;
+
+        if child_ino.handle != ino {
+            // The node was moved or removed since we found its path, or its stable inode number collides with another node
+            child.async_drop().await?;
+            self.inodes.forget(child_ino.handle, 1).await?;
+            return Err(FsError::StaleInode);
+        }
+        Ok((child_ino, child))
+    }
+
     async fn _orphan_inode(&self, parent_ino: InodeNumber, name: &PathComponent) {
         match self.inodes.make_into_orphan(parent_ino, name).await {
             Ok(()) => {
@@ -190,6 +284,118 @@ where
             Err(MoveInodeError::ErrorWhileDroppingNode(err)) => Err(err),
         }
     }
+
+    async fn _exchange_inodes(
+        &self,
+        first_parent_ino: InodeNumber,
+        first_name: &PathComponent,
+        second_parent_ino: InodeNumber,
+        second_name: &PathComponent,
+    ) -> FsResult<()> {
+        match self
+            .inodes
+            .exchange_inodes(
+                first_parent_ino,
+                first_name.to_owned(),
+                second_parent_ino,
+                second_name.to_owned(),
+            )
+            .await
+        {
+            Ok(()) => Ok(()),
+            Err(ExchangeInodesError::FirstParentNotFound) => {
+                panic!(
+                    "Tried to exchange inode with name {:?} under parent inode {:?} with name {:?} under parent inode {:?}, but the operation (rename) seems to have been successful, but when trying to update the inode list, the first parent inode was not found",
+                    first_name, first_parent_ino, second_name, second_parent_ino
+                );
+            }
+            Err(ExchangeInodesError::SecondParentNotFound) => {
+                panic!(
+                    "Tried to exchange inode with name {:?} under parent inode {:?} with name {:?} under parent inode {:?}, but the operation (rename) seems to have been successful, but when trying to update the inode list, the second parent inode was not found",
+                    first_name, first_parent_ino, second_name, second_parent_ino
+                );
+            }
+            Err(ExchangeInodesError::ErrorWhileDroppingNode(err)) => Err(err),
+        }
+    }
+
+    async fn _update_inodes_after_rename(
+        &self,
+        oldparent_ino: InodeNumber,
+        oldname: &PathComponent,
+        newparent_ino: InodeNumber,
+        newname: &PathComponent,
+        mode: RenameMode,
+    ) -> FsResult<()> {
+        match mode {
+            RenameMode::Overwrite | RenameMode::NoReplace => {
+                self._move_inode(oldparent_ino, oldname, newparent_ino, newname)
+                    .await
+            }
+            RenameMode::Exchange => {
+                self._exchange_inodes(oldparent_ino, oldname, newparent_ino, newname)
+                    .await
+            }
+        }
+    }
+
+    async fn _rename(
+        &self,
+        oldparent_ino: InodeNumber,
+        oldname: &PathComponent,
+        newparent_ino: InodeNumber,
+        newname: &PathComponent,
+        mode: RenameMode,
+    ) -> FsResult<()> {
+        // TODO Check that oldparent+oldname/newparent+newname aren't ancestors of each other, or at least write a test that fuse already blocks that
+        if oldparent_ino == newparent_ino {
+            let shared_parent = self.get_inode(oldparent_ino).await?;
+            with_async_drop_2!(shared_parent, {
+                let parent_dir = shared_parent.as_dir().await?;
+                with_async_drop_2!(parent_dir, {
+                    parent_dir.rename_child(oldname, newname, mode).await?;
+                    self._update_inodes_after_rename(
+                        oldparent_ino,
+                        oldname,
+                        newparent_ino,
+                        newname,
+                        mode,
+                    )
+                    .await?;
+                    Ok::<_, FsError>(())
+                })?;
+                Ok(())
+            })
+        } else {
+            let (oldparent, newparent) =
+                join!(self.get_inode(oldparent_ino), self.get_inode(newparent_ino));
+            let (mut oldparent, mut newparent) =
+                flatten_async_drop::<FsError, _, _, _, _>(oldparent, newparent).await?;
+            let result = async {
+                let oldparent_dir = oldparent.as_dir().await?;
+                with_async_drop_2!(oldparent_dir, {
+                    let newparent_dir = newparent.as_dir().await?;
+                    oldparent_dir
+                        .move_child_to(oldname, newparent_dir, newname, mode)
+                        .await?;
+                    self._update_inodes_after_rename(
+                        oldparent_ino,
+                        oldname,
+                        newparent_ino,
+                        newname,
+                        mode,
+                    )
+                    .await?;
+                    Ok::<_, FsError>(())
+                })
+            }
+            .await;
+            // TODO Drop concurrently and drop latter even if first one fails
+            oldparent.async_drop().await?;
+            newparent.async_drop().await?;
+            result
+        }
+    }
 }
 
 #[async_trait]
@@ -205,6 +411,9 @@ where
         let mut fs = self.fs.write().await;
         fs.initialize(req.uid, req.gid);
         let rootdir = Dir::into_node(fs.get().rootdir().await?);
+        if let Some(stable_id) = rootdir.stable_id() {
+            self.invalidator.set_root_stable_id(stable_id);
+        }
         self.inodes.insert_rootdir(rootdir).await;
         Ok(())
     }
@@ -226,37 +435,48 @@ where
         //      child.getattr looks it up again, to get the node attrs. Both need to first lock the parent dir
         //      and then look up the same entry. Can we optimize that?
 
-        let name_clone = name.to_owned();
-        let load_child = move |parent_node: &AsyncDropGuard<AsyncDropArc<Fs::Node>>| {
-            let parent_node = AsyncDropArc::clone(parent_node); // TODO Why is this necessary?
-            async move {
-                with_async_drop_2!(parent_node, {
-                    let parent_node_dir = parent_node
-                        .as_dir()
-                        .await
-                        .expect("Error: Inode number is not a directory");
-                    with_async_drop_2!(parent_node_dir, {
-                        // TODO Can we avoid the async_drop here by using something like parent_node_dir.into_lookup_child() ?
-                        parent_node_dir.lookup_child(&name_clone).await
-                    })
-                })
-            }
-        };
-
-        let (ino, child) = self
-            .inodes
-            .add_or_increment_refcount(parent_ino, name.to_owned(), load_child)
-            .await?;
+        let (ino, child) = self._lookup_and_register_child(parent_ino, name).await?;
 
         with_async_drop_2!(child, {
             child.getattr().await.map(|attr| ReplyEntry {
-                ttl: TTL_LOOKUP,
+                ttl: self.entry_ttl,
+                ino,
+                attr,
+            })
+        })
+    }
+
+    async fn lookup_self(&self, _req: &RequestInfo, ino: InodeNumber) -> FsResult<ReplyEntry> {
+        self.trigger_on_operation().await?;
+
+        let (ino, node) = match self.inodes.add_lookup(ino).await? {
+            Some(registered) => registered,
+            None => self._register_by_stable_ino(ino).await?,
+        };
+
+        with_async_drop_2!(node, {
+            node.getattr().await.map(|attr| ReplyEntry {
+                ttl: self.entry_ttl,
                 ino,
                 attr,
             })
         })
     }
 
+    async fn lookup_parent(&self, _req: &RequestInfo, ino: InodeNumber) -> FsResult<ReplyEntry> {
+        self.trigger_on_operation().await?;
+
+        let (parent_ino, parent) = self.inodes.add_lookup_to_parent(ino).await?;
+
+        with_async_drop_2!(parent, {
+            parent.getattr().await.map(|attr| ReplyEntry {
+                ttl: self.entry_ttl,
+                ino: parent_ino,
+                attr,
+            })
+        })
+    }
+
     async fn forget(&self, _req: &RequestInfo, ino: InodeNumber, nlookup: u64) -> FsResult<()> {
         self.trigger_on_operation().await?;
 
@@ -292,7 +512,7 @@ where
         };
 
         Ok(ReplyAttr {
-            ttl: TTL_GETATTR,
+            ttl: self.attr_ttl,
             attr,
             ino,
         })
@@ -335,7 +555,7 @@ where
         };
 
         Ok(ReplyAttr {
-            ttl: TTL_GETATTR,
+            ttl: self.attr_ttl,
             attr,
             ino,
         })
@@ -377,17 +597,58 @@ where
 
     async fn mknod(
         &self,
-        _req: &RequestInfo,
-        _parent_ino: InodeNumber,
-        _name: &PathComponent,
-        _mode: Mode,
+        req: &RequestInfo,
+        parent_ino: InodeNumber,
+        name: &PathComponent,
+        mode: Mode,
         _umask: u32,
-        _rdev: u32,
+        rdev: u32,
     ) -> FsResult<ReplyEntry> {
         self.trigger_on_operation().await?;
 
-        // TODO
-        Err(FsError::NotImplemented)
+        // In my tests with fuser 0.12.0, umask is already auto-applied to mode and the `umask` argument is always `0`.
+        // TODO see https://github.com/cberner/fuser/issues/256
+        let parent = self.get_inode(parent_ino).await?;
+        let (attr, child) = with_async_drop_2!(parent, {
+            let parent_dir = parent.as_dir().await?;
+            with_async_drop_2!(parent_dir, {
+                match mode.node_kind() {
+                    NodeKind::File => {
+                        // The kernel usually calls `create` for regular files, but it falls back to `mknod` in some cases.
+                        let (attrs, child, mut open_file) = parent_dir
+                            .create_and_open_file(name, mode, req.uid, req.gid, OpenInFlags::Read)
+                            .await?;
+                        open_file.async_drop().await?;
+                        Ok((attrs, child))
+                    }
+                    NodeKind::Fifo
+                    | NodeKind::Socket
+                    | NodeKind::CharDevice
+                    | NodeKind::BlockDevice => {
+                        parent_dir
+                            .create_child_special_file(name, mode, req.uid, req.gid, rdev)
+                            .await
+                    }
+                    NodeKind::Dir | NodeKind::Symlink => {
+                        log::warn!(
+                            "mknod: called with mode {mode} that isn't a file or special file"
+                        );
+                        Err(FsError::InvalidOperation)
+                    }
+                }
+            })
+        })?;
+        // Fuser counts mknod as a lookup and will call forget on the inode we allocate here.
+        let ino = self
+            .inodes
+            .add(parent_ino, child, name.to_owned())
+            .await
+            .expect("Parent inode vanished while executing");
+        Ok(ReplyEntry {
+            ttl: self.entry_ttl,
+            attr,
+            ino,
+        })
     }
 
     async fn mkdir(
@@ -421,7 +682,7 @@ where
             .await
             .expect("Parent inode vanished while executing");
         Ok(ReplyEntry {
-            ttl: TTL_GETATTR,
+            ttl: self.attr_ttl,
             attr,
             ino,
         })
@@ -496,7 +757,7 @@ where
             .await
             .expect("Parent inode vanished while executing");
         Ok(ReplyEntry {
-            ttl: TTL_SYMLINK,
+            ttl: self.entry_ttl,
             attr: attrs,
             ino,
         })
@@ -509,60 +770,51 @@ where
         oldname: &PathComponent,
         newparent_ino: InodeNumber,
         newname: &PathComponent,
-        _flags: u32,
+        flags: u32,
     ) -> FsResult<()> {
         self.trigger_on_operation().await?;
 
-        // TODO Honor flags
-        // TODO Check that oldparent+oldname/newparent+newname aren't ancestors of each other, or at least write a test that fuse already blocks that
-        if oldparent_ino == newparent_ino {
-            let shared_parent = self.get_inode(oldparent_ino).await?;
-            with_async_drop_2!(shared_parent, {
-                let parent_dir = shared_parent.as_dir().await?;
-                with_async_drop_2!(parent_dir, {
-                    parent_dir.rename_child(oldname, newname).await?;
-                    self._move_inode(oldparent_ino, oldname, newparent_ino, newname)
-                        .await?;
-                    Ok::<_, FsError>(())
-                })?;
-                Ok(())
-            })
-        } else {
-            let (oldparent, newparent) =
-                join!(self.get_inode(oldparent_ino), self.get_inode(newparent_ino));
-            let (mut oldparent, mut newparent) =
-                flatten_async_drop::<FsError, _, _, _, _>(oldparent, newparent).await?;
-            let result = async {
-                let oldparent_dir = oldparent.as_dir().await?;
-                with_async_drop_2!(oldparent_dir, {
-                    let newparent_dir = newparent.as_dir().await?;
-                    oldparent_dir
-                        .move_child_to(oldname, newparent_dir, newname)
-                        .await?;
-                    self._move_inode(oldparent_ino, oldname, newparent_ino, newname)
-                        .await?;
-                    Ok::<_, FsError>(())
-                })
-            }
-            .await;
-            // TODO Drop concurrently and drop latter even if first one fails
-            oldparent.async_drop().await?;
-            newparent.async_drop().await?;
-            result
-        }
+        let mode = RenameMode::from_flags(flags)?;
+        self._rename(oldparent_ino, oldname, newparent_ino, newname, mode)
+            .await
     }
 
     async fn link(
         &self,
         _req: &RequestInfo,
-        _ino: InodeNumber,
-        _newparent_ino: InodeNumber,
-        _newname: &PathComponent,
+        ino: InodeNumber,
+        newparent_ino: InodeNumber,
+        newname: &PathComponent,
     ) -> FsResult<ReplyEntry> {
         self.trigger_on_operation().await?;
 
-        // TODO
-        Err(FsError::NotImplemented)
+        let (node, newparent) = join!(self.get_inode(ino), self.get_inode(newparent_ino));
+        let (mut node, mut newparent) =
+            flatten_async_drop::<FsError, _, _, _, _>(node, newparent).await?;
+        let result = async {
+            let newparent_dir = newparent.as_dir().await?;
+            with_async_drop_2!(newparent_dir, {
+                newparent_dir.create_child_link(newname, &node).await
+            })
+        }
+        .await;
+        // TODO Drop concurrently and drop latter even if first one fails
+        node.async_drop().await?;
+        newparent.async_drop().await?;
+        let (attrs, child) = result?;
+
+        // Fuser counts link as a lookup and will call forget on the inode we allocate here.
+        // If the node has a stable id, the new link is exposed under the same inode number as the other links to it.
+        let ino = self
+            .inodes
+            .add(newparent_ino, child, newname.to_owned())
+            .await
+            .expect("Parent inode vanished while executing");
+        Ok(ReplyEntry {
+            ttl: self.entry_ttl,
+            attr: attrs,
+            ino,
+        })
     }
 
     async fn open(
@@ -649,13 +901,15 @@ where
     async fn flush(
         &self,
         _req: &RequestInfo,
-        _ino: InodeNumber,
+        ino: InodeNumber,
         fh: FileHandle,
-        _lock_owner: u64,
+        lock_owner: u64,
     ) -> FsResult<()> {
         self.trigger_on_operation().await?;
 
-        // TODO What to do about lock_owner?
+        // Closing any file descriptor releases all POSIX locks the process holds on the file
+        self.locks.unlock_all(ino, lock_owner);
+
         self.open_files
             .get(fh, async |open_file| open_file.flush().await)
             .await
@@ -664,17 +918,21 @@ where
     async fn release(
         &self,
         _req: &RequestInfo,
-        _ino: InodeNumber,
+        ino: InodeNumber,
         fh: FileHandle,
         _flags: OpenInFlags,
-        _lock_owner: Option<u64>,
+        lock_owner: Option<u64>,
         flush: bool,
     ) -> FsResult<()> {
         self.trigger_on_operation().await?;
 
         // TODO Would it make sense to have `fh` always be equal to `ino`? Might simplify some things. Also, we could add an `assert_eq!(ino, fh)` here.
 
-        // TODO What to do with flags, lock_owner?
+        if let Some(lock_owner) = lock_owner {
+            self.locks.unlock_all(ino, lock_owner);
+        }
+
+        // TODO What to do with flags?
         let open_file = self.open_files.remove(fh);
         with_async_drop_2!(open_file, {
             if flush {
@@ -811,15 +1069,102 @@ where
     async fn readdirplus<R: ReplyDirectoryPlus + Send + 'static>(
         &self,
         _req: &RequestInfo,
-        _ino: InodeNumber,
-        _fh: FileHandle,
-        _offset: u64,
-        _reply: &mut R,
+        ino: InodeNumber,
+        fh: FileHandle,
+        offset: u64,
+        reply: &mut R,
     ) -> FsResult<()> {
         self.trigger_on_operation().await?;
 
-        // TODO
-        Err(FsError::NotImplemented)
+        let dir_cache_entry = self.open_dirs.get(OpenDirHandle::from(fh)).ok_or_else(|| {
+            log::error!("Tried to access a file descriptor for a directory that isn't opened");
+            FsError::InvalidFileDescriptor { fh }
+        })?;
+        if dir_cache_entry.dir_ino() != ino {
+            log::error!(
+                "Tried to access a directory with inode {ino:?} using a file descriptor for inode {:?}",
+                dir_cache_entry.dir_ino()
+            );
+            return Err(FsError::InvalidFileDescriptor { fh });
+        }
+
+        let (node, parent_ino) = self.get_inode_and_parent_ino(ino).await?;
+
+        with_async_drop_2!(node, {
+            let offset = usize::try_from(offset).unwrap(); // TODO No unwrap
+
+            if offset <= 1 {
+                // The kernel ignores the attributes of '.' and '..', so it's fine to send the attributes of this dir for both
+                let attrs = node.getattr().await?;
+                if offset == 0 {
+                    match reply.add_self_reference(ino, 1, &attrs) {
+                        ReplyDirectoryAddResult::Full => {
+                            return Ok(());
+                        }
+                        ReplyDirectoryAddResult::NotFull => {
+                            // continue
+                        }
+                    }
+                }
+                match reply.add_parent_reference(parent_ino, 2, &attrs) {
+                    ReplyDirectoryAddResult::Full => {
+                        return Ok(());
+                    }
+                    ReplyDirectoryAddResult::NotFull => {
+                        // continue
+                    }
+                }
+            }
+
+            let load_dir_entries = async || {
+                let dir = node.as_dir().await?;
+                with_async_drop_2!(dir, { dir.entries_with_attrs().await })
+            };
+
+            let handle_dir_entries =
+                async |entries: &[DirEntry], attrs: &HashMap<PathComponentBuf, NodeAttrs>| {
+                    let entries = entries.iter().enumerate().skip(offset.saturating_sub(2)); // skip 2 less because those offset indices are for '.' and '..'
+                    for (offset, entry) in entries {
+                        let Some(attrs) = attrs.get(&entry.name) else {
+                            // The entry was removed after readdir cached the entry list
+                            continue;
+                        };
+
+                        // Unlike readdir, readdirplus registers the inodes. The kernel will call forget() for each entry we return.
+                        let (child_ino, mut child) =
+                            self._lookup_and_register_child(ino, &entry.name).await?;
+                        child.async_drop().await?;
+
+                        // offset +2 because of '.' and '..' and +1 because fuser actually expects us to return the offset of the **next** entry
+                        let offset = offset + 3;
+
+                        let offset = i64::try_from(offset).unwrap(); // TODO No unwrap
+                        let buffer_is_full = reply.add(
+                            child_ino.handle,
+                            offset,
+                            &entry.name,
+                            &self.entry_ttl,
+                            attrs,
+                            child_ino.generation,
+                        );
+                        match buffer_is_full {
+                            ReplyDirectoryAddResult::Full => {
+                                // The entry wasn't added, so the kernel won't forget it
+                                self.inodes.forget(child_ino.handle, 1).await?;
+                                break;
+                            }
+                            ReplyDirectoryAddResult::NotFull => {
+                                // continue
+                            }
+                        }
+                    }
+                    Ok(())
+                };
+
+            dir_cache_entry
+                .get_or_query_entries_with_attrs(load_dir_entries, handle_dir_entries)
+                .await
+        })
     }
 
     async fn releasedir(
@@ -863,76 +1208,82 @@ where
     async fn setxattr(
         &self,
         _req: &RequestInfo,
-        _ino: InodeNumber,
-        _name: &PathComponent,
-        _value: &[u8],
-        _flags: i32,
-        _position: NumBytes,
+        ino: InodeNumber,
+        name: &PathComponent,
+        value: &[u8],
+        flags: i32,
+        position: NumBytes,
     ) -> FsResult<()> {
         self.trigger_on_operation().await?;
 
-        // TODO
-        Err(FsError::NotImplemented)
+        let mode = SetXattrMode::from_flags(flags)?;
+        if position != NumBytes::from(0) {
+            // A nonzero position is only used by macOS for resource forks
+            return Err(FsError::XattrNotSupported);
+        }
+        let node = self.get_inode(ino).await?;
+        with_async_drop_2!(node, { node.setxattr(name.as_str(), value, mode).await })
     }
 
     async fn getxattr_numbytes(
         &self,
         _req: &RequestInfo,
-        _ino: InodeNumber,
-        _name: &PathComponent,
+        ino: InodeNumber,
+        name: &PathComponent,
     ) -> FsResult<NumBytes> {
         self.trigger_on_operation().await?;
 
-        // TODO
-        return Err(FsError::NotImplemented);
+        // TODO Add a Node::getxattr_numbytes so we don't have to load the value just to get its size
+        let node = self.get_inode(ino).await?;
+        let value = with_async_drop_2!(node, { node.getxattr(name.as_str()).await })?;
+        Ok(NumBytes::from(value.len() as u64))
     }
 
     async fn getxattr_data(
         &self,
         _req: &RequestInfo,
-        _ino: InodeNumber,
-        _name: &PathComponent,
-        _max_bytes_to_read: NumBytes,
+        ino: InodeNumber,
+        name: &PathComponent,
+        max_bytes_to_read: NumBytes,
     ) -> FsResult<Vec<u8>> {
         self.trigger_on_operation().await?;
 
-        // TODO
-        return Err(FsError::NotImplemented);
+        let node = self.get_inode(ino).await?;
+        let value = with_async_drop_2!(node, { node.getxattr(name.as_str()).await })?;
+        xattr_value_reply(value, max_bytes_to_read)
     }
 
-    async fn listxattr_numbytes(
-        &self,
-        _req: &RequestInfo,
-        _ino: InodeNumber,
-    ) -> FsResult<NumBytes> {
+    async fn listxattr_numbytes(&self, _req: &RequestInfo, ino: InodeNumber) -> FsResult<NumBytes> {
         self.trigger_on_operation().await?;
 
-        // TODO
-        return Err(FsError::NotImplemented);
+        let node = self.get_inode(ino).await?;
+        let names = with_async_drop_2!(node, { node.listxattr().await })?;
+        Ok(NumBytes::from(xattr_name_list(names).len() as u64))
     }
 
     async fn listxattr_data(
         &self,
         _req: &RequestInfo,
-        _ino: InodeNumber,
-        _max_bytes_to_read: NumBytes,
+        ino: InodeNumber,
+        max_bytes_to_read: NumBytes,
     ) -> FsResult<Vec<u8>> {
         self.trigger_on_operation().await?;
 
-        // TODO
-        return Err(FsError::NotImplemented);
+        let node = self.get_inode(ino).await?;
+        let names = with_async_drop_2!(node, { node.listxattr().await })?;
+        xattr_value_reply(xattr_name_list(names), max_bytes_to_read)
     }
 
     async fn removexattr(
         &self,
         _req: &RequestInfo,
-        _ino: InodeNumber,
-        _name: &PathComponent,
+        ino: InodeNumber,
+        name: &PathComponent,
     ) -> FsResult<()> {
         self.trigger_on_operation().await?;
 
-        // TODO
-        Err(FsError::NotImplemented)
+        let node = self.get_inode(ino).await?;
+        with_async_drop_2!(node, { node.removexattr(name.as_str()).await })
     }
 
     async fn access(&self, _req: &RequestInfo, _ino: InodeNumber, _mask: i32) -> FsResult<()> {
@@ -974,7 +1325,7 @@ where
 
             let fh = self.open_files.add(open_file);
             Ok(ReplyCreate {
-                ttl: TTL_CREATE,
+                ttl: self.entry_ttl,
                 attr,
                 ino: child_ino,
                 fh: fh.handle,
@@ -986,36 +1337,71 @@ where
     async fn getlk(
         &self,
         _req: &RequestInfo,
-        _ino: InodeNumber,
+        ino: InodeNumber,
         _fh: FileHandle,
-        _lock_owner: u64,
-        _start: u64,
-        _end: u64,
-        _typ: i32,
-        _pid: u32,
+        lock_owner: u64,
+        start: u64,
+        end: u64,
+        typ: i32,
+        pid: u32,
     ) -> FsResult<ReplyLock> {
         self.trigger_on_operation().await?;
 
-        // TODO
-        Err(FsError::NotImplemented)
+        let kind = LockKind::from_typ(typ)?.ok_or(FsError::InvalidOperation)?;
+        let lock = Lock {
+            owner: lock_owner,
+            pid,
+            kind,
+            start,
+            end,
+        };
+        match self.locks.test(ino, &lock) {
+            Some(conflict) => Ok(ReplyLock {
+                start: NumBytes::from(conflict.start),
+                end: NumBytes::from(conflict.end),
+                typ: conflict.kind.to_typ(),
+                pid: conflict.pid,
+            }),
+            None => Ok(ReplyLock {
+                start: NumBytes::from(start),
+                end: NumBytes::from(end),
+                typ: libc::F_UNLCK,
+                pid,
+            }),
+        }
     }
 
     async fn setlk(
         &self,
         _req: &RequestInfo,
-        _ino: InodeNumber,
+        ino: InodeNumber,
         _fh: FileHandle,
-        _lock_owner: u64,
-        _start: u64,
-        _end: u64,
-        _typ: i32,
-        _pid: u32,
-        _sleep: bool,
+        lock_owner: u64,
+        start: u64,
+        end: u64,
+        typ: i32,
+        pid: u32,
+        sleep: bool,
     ) -> FsResult<()> {
         self.trigger_on_operation().await?;
 
-        // TODO
-        Err(FsError::NotImplemented)
+        let Some(kind) = LockKind::from_typ(typ)? else {
+            self.locks.unlock(ino, lock_owner, start, end);
+            return Ok(());
+        };
+        let lock = Lock {
+            owner: lock_owner,
+            pid,
+            kind,
+            start,
+            end,
+        };
+        if sleep {
+            // TODO fuser doesn't forward FUSE_INTERRUPT yet, so a process blocked here can only continue once the lock is released
+            self.locks.lock(ino, lock).await
+        } else {
+            self.locks.try_lock(ino, lock)
+        }
     }
 
     async fn bmap(
@@ -1038,12 +1424,22 @@ where
         _ino: InodeNumber,
         _fh: FileHandle,
         _flags: u32,
-        _cmd: u32,
+        cmd: u32,
         _in_data: &[u8],
         _out_size: u32,
     ) -> FsResult<ReplyIoctl> {
         self.trigger_on_operation().await?;
 
+        // Linux handles FICLONE and FICLONERANGE in the VFS via remap_file_range, which FUSE doesn't support,
+        // so they shouldn't ever reach us. Report them as unsupported so that tools like `cp --reflink=auto`
+        // fall back to `copy_file_range`, which shares data between the files where possible.
+        #[cfg(target_os = "linux")]
+        if cmd == libc::FICLONE as u32 || cmd == libc::FICLONERANGE as u32 {
+            return Err(FsError::OperationNotSupported);
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = cmd;
+
         // TODO
         Err(FsError::NotImplemented)
     }
@@ -1052,47 +1448,71 @@ where
         &self,
         _req: &RequestInfo,
         _ino: InodeNumber,
-        _fh: FileHandle,
-        _offset: NumBytes,
-        _length: NumBytes,
-        _mode: Mode,
+        fh: FileHandle,
+        offset: NumBytes,
+        length: NumBytes,
+        mode: Mode,
     ) -> FsResult<()> {
         self.trigger_on_operation().await?;
 
-        // TODO
-        Err(FsError::NotImplemented)
+        // TODO The backend passes the fallocate flags as a [Mode] but they aren't a file mode
+        let mode = FallocateMode::from_flags(u32::from(mode) as libc::c_int)?;
+        self.open_files
+            .get(fh, async |open_file| {
+                open_file.fallocate(offset, length, mode).await
+            })
+            .await
     }
 
     async fn lseek(
         &self,
         _req: &RequestInfo,
         _ino: InodeNumber,
-        _fh: FileHandle,
-        _offset: NumBytes,
-        _whence: i32,
+        fh: FileHandle,
+        offset: NumBytes,
+        whence: i32,
     ) -> FsResult<ReplyLseek> {
         self.trigger_on_operation().await?;
 
-        // TODO
-        Err(FsError::NotImplemented)
+        let whence = SeekWhence::from_whence(whence)?;
+        let offset = self
+            .open_files
+            .get(fh, async |open_file| open_file.lseek(offset, whence).await)
+            .await?;
+        Ok(ReplyLseek { offset })
     }
 
     async fn copy_file_range(
         &self,
         _req: &RequestInfo,
         _ino_in: InodeNumber,
-        _fh_in: FileHandle,
-        _offset_in: NumBytes,
+        fh_in: FileHandle,
+        offset_in: NumBytes,
         _ino_out: InodeNumber,
-        _fh_out: FileHandle,
-        _offset_out: NumBytes,
-        _len: NumBytes,
-        _flags: u32,
+        fh_out: FileHandle,
+        offset_out: NumBytes,
+        len: NumBytes,
+        flags: u32,
     ) -> FsResult<ReplyWrite> {
         self.trigger_on_operation().await?;
 
-        // TODO
-        Err(FsError::NotImplemented)
+        // copy_file_range doesn't define any flags yet
+        if flags != 0 {
+            return Err(FsError::InvalidOperation);
+        }
+        let written = self
+            .open_files
+            .get(fh_in, async |source| {
+                self.open_files
+                    .get(fh_out, async |dest| {
+                        source
+                            .copy_file_range(offset_in, dest, offset_out, len)
+                            .await
+                    })
+                    .await
+            })
+            .await?;
+        Ok(ReplyWrite { written })
     }
 
     #[cfg(target_os = "macos")]
@@ -1107,16 +1527,23 @@ where
     async fn exchange(
         &self,
         _req: &RequestInfo,
-        _parent_ino: InodeNumber,
-        _name: &PathComponent,
-        _newparent_ino: InodeNumber,
-        _newname: &PathComponent,
+        parent_ino: InodeNumber,
+        name: &PathComponent,
+        newparent_ino: InodeNumber,
+        newname: &PathComponent,
+        // TODO Honor options
         _options: u64,
     ) -> FsResult<()> {
         self.trigger_on_operation().await?;
 
-        // TODO
-        Err(FsError::NotImplemented)
+        self._rename(
+            parent_ino,
+            name,
+            newparent_ino,
+            newname,
+            RenameMode::Exchange,
+        )
+        .await
     }
 
     #[cfg(target_os = "macos")]
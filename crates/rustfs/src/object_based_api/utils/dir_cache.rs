@@ -1,10 +1,12 @@
 use async_trait::async_trait;
 use cryfs_utils::async_drop::{AsyncDrop, AsyncDropArc, AsyncDropGuard, SyncDrop};
+use cryfs_utils::path::PathComponentBuf;
 use derive_more::{From, Into};
+use std::collections::HashMap;
 use std::fmt::Debug;
 
 use crate::{
-    DirEntry, FileHandle, FsError, FsResult, InodeNumber,
+    DirEntry, FileHandle, FsError, FsResult, InodeNumber, NodeAttrs,
     common::{HandleMap, HandleTrait, HandleWithGeneration},
 };
 
@@ -82,16 +84,24 @@ impl AsyncDrop for DirCache {
 #[derive(Debug)]
 pub struct DirCacheEntry {
     dir_ino: InodeNumber,
+    cached: tokio::sync::Mutex<CachedEntries>,
+}
+
+#[derive(Debug, Default)]
+struct CachedEntries {
     // None = not yet queried
     // Some(vec) = cached entries
-    entries: tokio::sync::Mutex<Option<Vec<DirEntry>>>,
+    entries: Option<Vec<DirEntry>>,
+    // Attributes of the entries, only queried once readdirplus is called.
+    // Stored by name because a previous readdir call could have cached `entries` before the directory was modified.
+    attrs: Option<HashMap<PathComponentBuf, NodeAttrs>>,
 }
 
 impl DirCacheEntry {
     pub fn new(dir_ino: InodeNumber) -> AsyncDropGuard<Self> {
         AsyncDropGuard::new(Self {
             dir_ino,
-            entries: tokio::sync::Mutex::new(None),
+            cached: tokio::sync::Mutex::new(CachedEntries::default()),
         })
     }
 
@@ -106,16 +116,48 @@ impl DirCacheEntry {
         query_fn: impl AsyncFnOnce() -> FsResult<Vec<DirEntry>>,
         result_callback: impl FnOnce(&[DirEntry]) -> FsResult<()>,
     ) -> FsResult<()> {
-        let mut entries = self.entries.lock().await;
-        if let Some(entries) = &*entries {
+        let mut cached = self.cached.lock().await;
+        if let Some(entries) = &cached.entries {
             result_callback(entries)
         } else {
             let queried_entries = query_fn().await?;
             let result = result_callback(&queried_entries);
-            *entries = Some(queried_entries);
+            cached.entries = Some(queried_entries);
             result
         }
     }
+
+    /// Like [DirCacheEntry::get_or_query_entries], but also returns the attributes of the entries.
+    /// Entries that were removed since the entry list was cached don't have attributes.
+    pub async fn get_or_query_entries_with_attrs(
+        &self,
+        query_fn: impl AsyncFnOnce() -> FsResult<Vec<(DirEntry, NodeAttrs)>>,
+        result_callback: impl AsyncFnOnce(
+            &[DirEntry],
+            &HashMap<PathComponentBuf, NodeAttrs>,
+        ) -> FsResult<()>,
+    ) -> FsResult<()> {
+        let mut cached = self.cached.lock().await;
+        let cached = &mut *cached;
+        if cached.attrs.is_none() {
+            let (queried_entries, queried_attrs): (Vec<DirEntry>, Vec<NodeAttrs>) =
+                query_fn().await?.into_iter().unzip();
+            cached.attrs = Some(
+                queried_entries
+                    .iter()
+                    .map(|entry| entry.name.clone())
+                    .zip(queried_attrs)
+                    .collect(),
+            );
+            // If readdir already cached the entries, keep them so the offsets it returned stay valid
+            if cached.entries.is_none() {
+                cached.entries = Some(queried_entries);
+            }
+        }
+        let entries = cached.entries.as_ref().expect("Set above");
+        let attrs = cached.attrs.as_ref().expect("Set above");
+        result_callback(entries, attrs).await
+    }
 }
 
 #[async_trait]
@@ -127,3 +169,119 @@ impl AsyncDrop for DirCacheEntry {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Gid, Mode, NodeKind, NumBytes, Uid};
+    use cryfs_utils::path::PathComponent;
+    use std::num::NonZeroU64;
+    use std::time::SystemTime;
+
+    const INO: InodeNumber = InodeNumber::from_const(NonZeroU64::new(10).unwrap());
+
+    fn entry(name: &str) -> DirEntry {
+        DirEntry {
+            name: PathComponent::try_from_str(name).unwrap().to_owned(),
+            kind: NodeKind::File,
+        }
+    }
+
+    fn attrs(num_bytes: u64) -> NodeAttrs {
+        NodeAttrs {
+            nlink: 1,
+            mode: Mode::default().add_file_flag(),
+            uid: Uid::from(1000),
+            gid: Gid::from(1000),
+            num_bytes: NumBytes::from(num_bytes),
+            num_blocks: None,
+            rdev: 0,
+            atime: SystemTime::UNIX_EPOCH,
+            mtime: SystemTime::UNIX_EPOCH,
+            ctime: SystemTime::UNIX_EPOCH,
+        }
+    }
+
+    async fn get_with_attrs(
+        cache_entry: &DirCacheEntry,
+        current_entries: Vec<(DirEntry, NodeAttrs)>,
+    ) -> Vec<(DirEntry, Option<NodeAttrs>)> {
+        let mut result = vec![];
+        cache_entry
+            .get_or_query_entries_with_attrs(
+                async || Ok(current_entries),
+                async |entries, attrs| {
+                    result = entries
+                        .iter()
+                        .map(|entry| (entry.clone(), attrs.get(&entry.name).copied()))
+                        .collect();
+                    Ok(())
+                },
+            )
+            .await
+            .unwrap();
+        result
+    }
+
+    #[tokio::test]
+    async fn readdir_and_readdirplus_share_the_entry_list() {
+        let mut cache_entry = DirCacheEntry::new(INO);
+        let with_attrs = get_with_attrs(
+            &cache_entry,
+            vec![(entry("a"), attrs(1)), (entry("b"), attrs(2))],
+        )
+        .await;
+        assert_eq!(
+            vec![(entry("a"), Some(attrs(1))), (entry("b"), Some(attrs(2)))],
+            with_attrs,
+        );
+
+        let mut without_attrs = vec![];
+        cache_entry
+            .get_or_query_entries(
+                async || panic!("Entries should be cached"),
+                |entries| {
+                    without_attrs = entries.to_vec();
+                    Ok(())
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(vec![entry("a"), entry("b")], without_attrs);
+
+        cache_entry.async_drop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn readdirplus_after_readdir_keeps_the_order_from_readdir() {
+        let mut cache_entry = DirCacheEntry::new(INO);
+        cache_entry
+            .get_or_query_entries(
+                async || Ok(vec![entry("a"), entry("b"), entry("c")]),
+                |_| Ok(()),
+            )
+            .await
+            .unwrap();
+
+        // In the meantime, "b" was removed and "d" was added
+        let with_attrs = get_with_attrs(
+            &cache_entry,
+            vec![
+                (entry("d"), attrs(4)),
+                (entry("c"), attrs(3)),
+                (entry("a"), attrs(1)),
+            ],
+        )
+        .await;
+        assert_eq!(
+            vec![
+                (entry("a"), Some(attrs(1))),
+                (entry("b"), None),
+                (entry("c"), Some(attrs(3))),
+            ],
+            with_attrs,
+        );
+
+        cache_entry.async_drop().await.unwrap();
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+
+use crate::InodeNumber;
+use crate::common::HandleWithGeneration;
+
+/// [ExposedInodes] maps the inode numbers we give to the kernel to the handles of the nodes in the inode forest.
+///
+/// Nodes with a [stable id](crate::object_based_api::Node::stable_id) are exposed under the inode number derived from it,
+/// so the kernel sees the same inode number for them across remounts. A hard linked node gets loaded once for each path
+/// it was looked up under, each with its own handle. Since they have the same stable id, they're all exposed under the same
+/// inode number, and the kernel treats them as one inode, the same way it does on other file systems.
+///
+/// Nodes without a stable id are exposed under their handle. So are nodes whose stable inode number is already taken by a node
+/// with a different stable id. Handles are assigned dynamically and never have the [stable bit](InodeNumber::is_stable) set,
+/// so they can't collide with stable inode numbers.
+#[derive(Debug, Default)]
+pub struct ExposedInodes {
+    // Invariants:
+    // * A: Each entry in `stable_inodes` has at least one handle, and each of its handles has a lookup count > 0.
+    // * B: Each handle listed in `stable_inodes` has an entry in `stable_ino_by_handle` pointing back to it.
+    //   * Note: The reverse isn't necessarily true. After the kernel forgot a stable inode number, its handles
+    //     can stay in the inode forest, e.g. because they have children. We remember which inode number they're
+    //     exposed under until they're removed from the inode forest, see [Self::remove_handle].
+    stable_inodes: HashMap<InodeNumber, StableInode>,
+    stable_ino_by_handle: HashMap<InodeNumber, InodeNumber>,
+}
+
+#[derive(Debug)]
+struct StableInode {
+    stable_id: u128,
+
+    /// The handles exposed under this inode number, together with the number of lookups the kernel did through each of them.
+    handles: Vec<(InodeNumber, u64)>,
+}
+
+impl ExposedInodes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Called whenever the kernel gets a new reference to the node with the handle `handle`, e.g. because it looked it up.
+    /// Returns the inode number and generation the kernel should see for it.
+    pub fn expose(
+        &mut self,
+        handle: HandleWithGeneration<InodeNumber>,
+        stable_id: Option<u128>,
+    ) -> HandleWithGeneration<InodeNumber> {
+        assert!(
+            !handle.handle.is_stable(),
+            "Handles must not collide with stable inode numbers"
+        );
+        let Some(stable_id) = stable_id else {
+            return handle;
+        };
+        let stable = InodeNumber::from_stable_id(stable_id);
+        let entry = self
+            .stable_inodes
+            .entry(stable.handle)
+            .or_insert_with(|| StableInode {
+                stable_id,
+                handles: Vec::new(),
+            });
+        if entry.stable_id != stable_id {
+            log::warn!(
+                "Inode {}: Stable inode number is already taken by a different node. Exposing inode {} instead.",
+                stable.handle,
+                handle.handle,
+            );
+            return handle;
+        }
+        match entry.handles.iter_mut().find(|(h, _)| *h == handle.handle) {
+            Some((_, num_lookups)) => *num_lookups += 1,
+            None => entry.handles.push((handle.handle, 1)),
+        }
+        self.stable_ino_by_handle
+            .insert(handle.handle, stable.handle);
+        stable
+    }
+
+    /// Returns the handle of a node exposed under the inode number `ino`, or `None` if the kernel doesn't hold a reference to `ino`.
+    /// If there are several handles (i.e. hard links), any of them is returned.
+    pub fn resolve(&self, ino: InodeNumber) -> Option<InodeNumber> {
+        if ino.is_stable() {
+            let entry = self.stable_inodes.get(&ino)?;
+            let (handle, _) = entry
+                .handles
+                .first()
+                .expect("Invariant A violated: entry without handles");
+            Some(*handle)
+        } else {
+            Some(ino)
+        }
+    }
+
+    /// Returns the inode number the node with the handle `handle` is exposed under.
+    pub fn exposed_ino(&self, handle: InodeNumber) -> InodeNumber {
+        self.stable_ino_by_handle
+            .get(&handle)
+            .copied()
+            .unwrap_or(handle)
+    }
+
+    /// Called when the kernel forgets `nlookup` references to `ino`. Returns the handles whose refcount
+    /// has to be decreased, together with the number to decrease them by.
+    /// Returns `None` if the kernel forgets more references than it holds.
+    pub fn forget(&mut self, ino: InodeNumber, nlookup: u64) -> Option<Vec<(InodeNumber, u64)>> {
+        if !ino.is_stable() {
+            return Some(vec![(ino, nlookup)]);
+        }
+        let entry = self.stable_inodes.get_mut(&ino)?;
+        let total_lookups: u64 = entry.handles.iter().map(|(_, n)| n).sum();
+        if total_lookups < nlookup {
+            return None;
+        }
+
+        // The kernel doesn't tell us which path a forget is for, but any distribution works
+        // as long as each handle's refcount goes to zero once the kernel forgot all of its lookups.
+        let mut remaining = nlookup;
+        let mut to_decrease = Vec::new();
+        entry.handles.retain_mut(|(handle, num_lookups)| {
+            let decrease = remaining.min(*num_lookups);
+            if decrease > 0 {
+                remaining -= decrease;
+                *num_lookups -= decrease;
+                to_decrease.push((*handle, decrease));
+            }
+            *num_lookups > 0
+        });
+        if entry.handles.is_empty() {
+            self.stable_inodes.remove(&ino);
+        }
+        Some(to_decrease)
+    }
+
+    /// Called when `handle` is removed from the inode forest, after the kernel forgot all of its lookups.
+    pub fn remove_handle(&mut self, handle: InodeNumber) {
+        if let Some(ino) = self.stable_ino_by_handle.remove(&handle)
+            && let Some(entry) = self.stable_inodes.get(&ino)
+        {
+            assert!(
+                entry.handles.iter().all(|(h, _)| *h != handle),
+                "Tried to remove handle {handle} but the kernel still holds a reference to it"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dynamic(handle: u64) -> HandleWithGeneration<InodeNumber> {
+        HandleWithGeneration {
+            handle: InodeNumber::try_from(handle).unwrap(),
+            generation: 3,
+        }
+    }
+
+    #[test]
+    fn without_stable_id_exposes_handle() {
+        let mut exposed = ExposedInodes::new();
+        assert_eq!(dynamic(5), exposed.expose(dynamic(5), None));
+        assert_eq!(Some(dynamic(5).handle), exposed.resolve(dynamic(5).handle));
+        assert_eq!(dynamic(5).handle, exposed.exposed_ino(dynamic(5).handle));
+        assert_eq!(
+            Some(vec![(dynamic(5).handle, 2)]),
+            exposed.forget(dynamic(5).handle, 2)
+        );
+    }
+
+    #[test]
+    fn with_stable_id_exposes_stable_ino() {
+        let mut exposed = ExposedInodes::new();
+        let stable = InodeNumber::from_stable_id(0x1234);
+        assert!(stable.handle.is_stable());
+        assert_eq!(0x1234, stable.generation);
+        assert_eq!(stable, exposed.expose(dynamic(5), Some(0x1234)));
+        assert_eq!(Some(dynamic(5).handle), exposed.resolve(stable.handle));
+        assert_eq!(stable.handle, exposed.exposed_ino(dynamic(5).handle));
+
+        assert_eq!(
+            Some(vec![(dynamic(5).handle, 1)]),
+            exposed.forget(stable.handle, 1)
+        );
+        assert_eq!(None, exposed.resolve(stable.handle));
+        // The handle is still in the inode forest and keeps its inode number until it is removed
+        assert_eq!(stable.handle, exposed.exposed_ino(dynamic(5).handle));
+        exposed.remove_handle(dynamic(5).handle);
+        assert_eq!(dynamic(5).handle, exposed.exposed_ino(dynamic(5).handle));
+    }
+
+    #[test]
+    fn hard_links_share_stable_ino() {
+        let mut exposed = ExposedInodes::new();
+        let stable = InodeNumber::from_stable_id(0x1234);
+        assert_eq!(stable, exposed.expose(dynamic(5), Some(0x1234)));
+        assert_eq!(stable, exposed.expose(dynamic(6), Some(0x1234)));
+        assert_eq!(stable, exposed.expose(dynamic(6), Some(0x1234)));
+
+        assert_eq!(None, exposed.forget(stable.handle, 4));
+        assert_eq!(
+            Some(vec![(dynamic(5).handle, 1), (dynamic(6).handle, 1)]),
+            exposed.forget(stable.handle, 2)
+        );
+        assert_eq!(Some(dynamic(6).handle), exposed.resolve(stable.handle));
+        assert_eq!(
+            Some(vec![(dynamic(6).handle, 1)]),
+            exposed.forget(stable.handle, 1)
+        );
+        assert_eq!(None, exposed.resolve(stable.handle));
+    }
+
+    #[test]
+    fn colliding_stable_ino_exposes_handle() {
+        let mut exposed = ExposedInodes::new();
+        let stable = InodeNumber::from_stable_id(0x1234);
+        // Same upper half, so same inode number, but a different generation
+        let colliding_id = 0x5678;
+        assert_eq!(
+            stable.handle,
+            InodeNumber::from_stable_id(colliding_id).handle
+        );
+
+        assert_eq!(stable, exposed.expose(dynamic(5), Some(0x1234)));
+        assert_eq!(dynamic(6), exposed.expose(dynamic(6), Some(colliding_id)));
+        assert_eq!(Some(dynamic(5).handle), exposed.resolve(stable.handle));
+        assert_eq!(Some(dynamic(6).handle), exposed.resolve(dynamic(6).handle));
+    }
+}
@@ -58,6 +58,11 @@ where
         self.handles.acquire_specific(handle);
     }
 
+    /// Returns `handle` together with its current generation, if it is in use.
+    pub fn handle_with_generation(&self, handle: Handle) -> Option<HandleWithGeneration<Handle>> {
+        self.handles.lookup(handle)
+    }
+
     pub fn get(&self, index: &Handle) -> Option<&Node<Handle, EdgeKey, NodeValue>> {
         let node = self.nodes.get(index)?;
         Some(&**node)
@@ -306,6 +311,70 @@ where
         }
     }
 
+    /// Swap the children at `first_parent_handle/first_edge` and `second_parent_handle/second_edge`.
+    /// Each of the two children may or may not be loaded. If only one of them is loaded, it is just moved to the other position.
+    pub fn exchange_nodes(
+        &mut self,
+        first_parent_handle: Handle,
+        first_edge: EdgeKey,
+        second_parent_handle: Handle,
+        second_edge: EdgeKey,
+    ) -> Result<ExchangeNodesSuccess, ExchangeNodesError> {
+        if self.nodes.get(&first_parent_handle).is_none() {
+            return Err(ExchangeNodesError::FirstParentNotFound);
+        }
+        if self.nodes.get(&second_parent_handle).is_none() {
+            return Err(ExchangeNodesError::SecondParentNotFound);
+        }
+
+        // Remove both children from their parents
+        let first_child = self
+            .nodes
+            .get_mut(&first_parent_handle)
+            .expect("We just checked that it exists")
+            .try_remove_child(&first_edge)
+            .map(|(handle, _remove_result)| handle);
+        let second_child = self
+            .nodes
+            .get_mut(&second_parent_handle)
+            .expect("We just checked that it exists")
+            .try_remove_child(&second_edge)
+            .map(|(handle, _remove_result)| handle);
+
+        // Re-insert them at each other's position and update their parent pointers
+        if let Some(first_child) = &first_child {
+            self._insert_child_at(
+                first_child,
+                second_parent_handle.clone(),
+                second_edge.clone(),
+            );
+        }
+        if let Some(second_child) = &second_child {
+            self._insert_child_at(second_child, first_parent_handle, first_edge);
+        }
+
+        Ok(ExchangeNodesSuccess {
+            first_was_loaded: first_child.is_some(),
+            second_was_loaded: second_child.is_some(),
+        })
+    }
+
+    fn _insert_child_at(&mut self, child_handle: &Handle, parent_handle: Handle, edge: EdgeKey) {
+        let overwritten_child = self
+            .nodes
+            .get_mut(&parent_handle)
+            .expect("Caller checked that the parent exists")
+            .insert_child(edge.clone(), child_handle.clone());
+        assert!(
+            overwritten_child.is_none(),
+            "We removed the child at this position before"
+        );
+        self.nodes
+            .get_mut(child_handle)
+            .expect("Invariant B2 violated")
+            .set_parent(Some((parent_handle, edge)));
+    }
+
     #[cfg(feature = "testutils")]
     pub fn drain(
         &mut self,
@@ -346,6 +415,18 @@ pub enum MoveInodeError {
     ChildNotFound,
 }
 
+#[must_use]
+pub struct ExchangeNodesSuccess {
+    pub first_was_loaded: bool,
+    pub second_was_loaded: bool,
+}
+
+#[derive(Error, Debug, Display)]
+pub enum ExchangeNodesError {
+    FirstParentNotFound,
+    SecondParentNotFound,
+}
+
 #[derive(Error, Debug, Display)]
 pub enum TryRemoveResult<Handle>
 where
@@ -4,6 +4,6 @@ mod node;
 
 pub use delayed_handle_release::DelayedHandleRelease;
 pub use handle_forest::{
-    GetChildOfError, HandleForest, MakeOrphanError, MoveInodeError, MoveInodeSuccess,
-    TryInsertError, TryRemoveResult,
+    ExchangeNodesError, ExchangeNodesSuccess, GetChildOfError, HandleForest, MakeOrphanError,
+    MoveInodeError, MoveInodeSuccess, TryInsertError, TryRemoveResult,
 };
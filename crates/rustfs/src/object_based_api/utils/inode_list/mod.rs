@@ -21,11 +21,14 @@ use crate::FsResult;
 use crate::InodeNumber;
 use crate::common::HandleWithGeneration;
 use crate::object_based_api::utils::inode_list::handle_forest::{
-    DelayedHandleRelease, GetChildOfError, HandleForest, MoveInodeSuccess, TryInsertError,
-    TryRemoveResult,
+    DelayedHandleRelease, ExchangeNodesError, ExchangeNodesSuccess, GetChildOfError, HandleForest,
+    MoveInodeSuccess, TryInsertError, TryRemoveResult,
 };
 use crate::object_based_api::utils::inode_list::inode_tree_node::RefcountInfo;
-use crate::{FsError, object_based_api::Device};
+use crate::{
+    FsError,
+    object_based_api::{Device, Node as _},
+};
 use cryfs_utils::path::{PathComponent, PathComponentBuf};
 
 pub const FUSE_ROOT_ID: InodeNumber =
@@ -39,6 +42,9 @@ pub use handle_forest::MakeOrphanError;
 mod inode_tree_node;
 use inode_tree_node::InodeTreeNode;
 
+mod exposed_inodes;
+use exposed_inodes::ExposedInodes;
+
 const PARENT_OF_ROOT_INO: InodeNumber = FUSE_ROOT_ID; // Root inode's parent is itself
 
 /// [InodeList] keeps track of all inodes that have been registered with FUSE
@@ -95,6 +101,13 @@ where
     // released it and all its children.
     // TODO Use Vec or slab instead of HashMap since InodeNumber is mostly contiguous?
     inode_forest: AsyncDropGuard<HandleForest<InodeNumber, PathComponentBuf, InodeTreeNode<Fs>>>,
+
+    // The inode numbers in `inodes` and `inode_forest` are only used internally. The kernel sees the inode numbers from `exposed`,
+    // which are derived from the nodes' stable ids if they have one. All public functions take and return exposed inode numbers.
+    // Invariants:
+    // * G: Each InodeNumber that `exposed` resolves to is in `inode_forest`, and `exposed` keeps a lookup count for it
+    //      that counts towards its refcount (see E1).
+    exposed: ExposedInodes,
 }
 
 impl<Fs> InodeList<Fs>
@@ -112,6 +125,7 @@ where
             inner: Mutex::new(InodeListInner {
                 inodes,
                 inode_forest,
+                exposed: ExposedInodes::new(),
             }),
         })
         // Fulfilling invariants:
@@ -182,20 +196,35 @@ where
             })
     }
 
+    /// Translate an inode number given to us by the kernel into the InodeNumber used in `inodes` and `inode_forest`.
+    fn _resolve(
+        inner: &MutexGuard<'_, InodeListInner<Fs>>,
+        ino: InodeNumber,
+    ) -> FsResult<InodeNumber> {
+        inner.exposed.resolve(ino).ok_or_else(|| {
+            // Invariant G violated, but this can happen if the kernel gives us wrong inode numbers, so treating it as InvalidOperation
+            log::error!(
+                "inode {ino}: Tried to use an inode number the kernel doesn't hold a reference to"
+            );
+            FsError::InvalidOperation
+        })
+    }
+
     pub async fn get_node_and_parent_ino(
         &self,
         ino: InodeNumber,
     ) -> FsResult<(AsyncDropGuard<AsyncDropArc<Fs::Node>>, InodeNumber)> {
         let inner = self.inner.lock().await;
-        let inode_tree_node = inner.inode_forest.get(&ino).ok_or_else(|| {
+        let handle = Self::_resolve(&inner, ino)?;
+        let inode_tree_node = inner.inode_forest.get(&handle).ok_or_else(|| {
             log::error!("inode {ino}: Tried to get inode info for unknown inode");
             FsError::InvalidOperation
         })?;
         let parent_ino = inode_tree_node
             .parent_handle()
-            .copied()
+            .map(|parent_handle| inner.exposed.exposed_ino(*parent_handle))
             .unwrap_or(PARENT_OF_ROOT_INO);
-        let node = Self::_get_node(&inner, ino).await?;
+        let node = Self::_get_node(&inner, handle).await?;
         // Fulfilling Invariant B: The user gave us `ino`, i.e. we handed it out before and know with B2 that its ancestors are also loaded.
         Ok((node, parent_ino))
     }
@@ -205,7 +234,8 @@ where
         ino: InodeNumber,
     ) -> FsResult<AsyncDropGuard<AsyncDropArc<Fs::Node>>> {
         let inner = self.inner.lock().await;
-        Self::_get_node(&inner, ino).await
+        let handle = Self::_resolve(&inner, ino)?;
+        Self::_get_node(&inner, handle).await
     }
 
     async fn _get_node(
@@ -226,7 +256,9 @@ where
         name: PathComponentBuf,
     ) -> FsResult<HandleWithGeneration<InodeNumber>> {
         let mut inner = self.inner.lock().await;
+        let parent_ino = Self::_resolve(&inner, parent_ino)?;
         let name_clone = name.clone();
+        let stable_id = node.stable_id();
 
         // TODO This Arc::clone is only necessary because MutexGuard can't project and get &mut on both inner.inodes and inner.inode_forest at the same time. Once Rust supports that, we can avoid this clone.
         let inodes = inner.inodes.clone_ref();
@@ -271,7 +303,7 @@ where
                     log::debug!(
                         "Inode {new_child_ino}: Added with parent={parent_ino}, name={name_clone}"
                     );
-                    Ok(new_child_ino)
+                    Ok(inner.exposed.expose(new_child_ino, stable_id))
                 }
             }
         })
@@ -286,6 +318,7 @@ where
         //      we also didn't add it to self.inodes (see invariant C in previous point), so no need to block it.
         // * E: The new entry got a refcount of 1 because we are returning it now, and its parent got its refcount incremented by 1, if adding was successful.
         // * F: The new entry has exactly one guard active in inode_forest.
+        // * G: The new entry is exposed with a lookup count of 1.
     }
 
     pub async fn add_or_increment_refcount<F>(
@@ -301,6 +334,7 @@ where
         F: Future<Output = FsResult<AsyncDropGuard<Fs::Node>>> + Send,
     {
         let mut inner = self.inner.lock().await;
+        let parent_ino = Self::_resolve(&inner, parent_ino)?;
 
         match inner.inode_forest.get_child_of_mut(&parent_ino, &name) {
             Err(GetChildOfError::ParentNotFound) => {
@@ -316,9 +350,16 @@ where
                 let name_clone = name.clone();
                 let (child_ino, node) = self._add_new(inner, parent_ino, name, loading_fn).await?;
                 log::debug!("Inode {child_ino}: Added with parent={parent_ino}, name={name_clone}");
+                let child_ino = self
+                    .inner
+                    .lock()
+                    .await
+                    .exposed
+                    .expose(child_ino, node.stable_id());
                 Ok((child_ino, node))
 
                 // Fulfilling invariants: See comments in [Self::_add_new]
+                // * G: The new entry is exposed with a lookup count of 1.
             }
             Ok((child_ino, child_inode)) => {
                 // Child already exists, increment its refcount and return it
@@ -335,16 +376,102 @@ where
                 log::debug!(
                     "Inode {child_ino}: Found existing with parent={parent_ino}, name={name}"
                 );
+                let child_ino = self
+                    .inner
+                    .lock()
+                    .await
+                    .exposed
+                    .expose(child_ino, inode.stable_id());
                 Ok((child_ino, inode))
 
                 // Fulfilling invariants:
                 // * A, C, D, E, F: No change here
+                // * G: The lookup count in `exposed` was incremented together with the refcount
                 // * B1: We've just waited for loading to be successful and complete before returning the inode number
                 // * B2: The user gave us parent_ino, so by invariant B1+B2, all its ancestors are also fully loaded.
             }
         }
     }
 
+    /// Add a reference to the inode `ino`, e.g. because the kernel looked up "." in it.
+    /// Returns `None` if the kernel doesn't hold a reference to `ino` already, e.g. because it got `ino` from an earlier mount.
+    pub async fn add_lookup(
+        &self,
+        ino: InodeNumber,
+    ) -> FsResult<
+        Option<(
+            HandleWithGeneration<InodeNumber>,
+            AsyncDropGuard<AsyncDropArc<Fs::Node>>,
+        )>,
+    > {
+        let mut inner = self.inner.lock().await;
+        let Some(handle) = inner.exposed.resolve(ino) else {
+            return Ok(None);
+        };
+        let result = Self::_add_lookup(&mut inner, handle).await?;
+        Ok(Some(result))
+    }
+
+    /// Add a reference to the parent of the inode `ino`, e.g. because the kernel looked up ".." in it.
+    /// The parent of the root inode is the root inode itself.
+    pub async fn add_lookup_to_parent(
+        &self,
+        ino: InodeNumber,
+    ) -> FsResult<(
+        HandleWithGeneration<InodeNumber>,
+        AsyncDropGuard<AsyncDropArc<Fs::Node>>,
+    )> {
+        let mut inner = self.inner.lock().await;
+        let handle = Self::_resolve(&inner, ino)?;
+        let parent_handle = inner
+            .inode_forest
+            .get(&handle)
+            .ok_or_else(|| {
+                log::error!("inode {ino}: Tried to get parent of unknown inode");
+                FsError::InvalidOperation
+            })?
+            .parent_handle()
+            .copied()
+            .unwrap_or(PARENT_OF_ROOT_INO);
+        Self::_add_lookup(&mut inner, parent_handle).await
+
+        // Fulfilling invariants: See comments in [Self::_add_lookup]
+        // * B2: By invariant B1+B2 for `ino`, its parent is fully loaded as well
+    }
+
+    async fn _add_lookup(
+        inner: &mut MutexGuard<'_, InodeListInner<Fs>>,
+        handle: InodeNumber,
+    ) -> FsResult<(
+        HandleWithGeneration<InodeNumber>,
+        AsyncDropGuard<AsyncDropArc<Fs::Node>>,
+    )> {
+        let node = Self::_get_node(inner, handle).await?;
+        let handle_with_generation = inner
+            .inode_forest
+            .handle_with_generation(handle)
+            .expect("Invariant A violated");
+        if handle == FUSE_ROOT_ID {
+            // The root inode is never removed, so we don't need to count references to it. It is always exposed as FUSE_ROOT_ID.
+            return Ok((handle_with_generation, node));
+        }
+        inner
+            .inode_forest
+            .get_mut(&handle)
+            .expect("We just loaded the node above")
+            .value_mut()
+            .increment_refcount();
+        let exposed_ino = inner
+            .exposed
+            .expose(handle_with_generation, node.stable_id());
+        Ok((exposed_ino, node))
+
+        // Fulfilling invariants:
+        // * A, B, C, D, F: No change here
+        // * E: We incremented the refcount because we're returning the InodeNumber
+        // * G: The lookup count in `exposed` was incremented together with the refcount
+    }
+
     async fn _wait_for_node_loaded(
         mut node_future: AsyncDropGuard<
             AsyncDropShared<
@@ -535,17 +662,31 @@ where
             return Err(FsError::InvalidOperation);
         }
 
-        let inner = self.inner.lock().await;
         log::debug!("Inode {ino}: Forgetting nlookup={nlookup}");
-        self._decrease_refcount(inner, ino, nlookup)
+        let handles = self
+            .inner
+            .lock()
             .await
-            .map_err(|err| match err {
-                DecrementRefcountError::NodeNotFound => {
-                    // Kernel gave us a wrong inode number
-                    FsError::InvalidOperation
-                }
-                DecrementRefcountError::ErrorWhileDroppingNode(err) => err,
-            })
+            .exposed
+            .forget(ino, nlookup)
+            .ok_or_else(|| {
+                // Kernel gave us a wrong inode number or forgot more lookups than it did
+                log::error!("Inode {ino}: Tried to forget more lookups than the kernel did");
+                FsError::InvalidOperation
+            })?;
+        for (handle, nlookup) in handles {
+            let inner = self.inner.lock().await;
+            self._decrease_refcount(inner, handle, nlookup)
+                .await
+                .map_err(|err| match err {
+                    DecrementRefcountError::NodeNotFound => {
+                        // Kernel gave us a wrong inode number
+                        FsError::InvalidOperation
+                    }
+                    DecrementRefcountError::ErrorWhileDroppingNode(err) => err,
+                })?;
+        }
+        Ok(())
     }
 
     async fn _decrease_refcount(
@@ -646,6 +787,7 @@ where
                 .inode_forest
                 .try_remove(child_ino)
                 .expect("Inode reference disappeared or still has children");
+            inner.exposed.remove_handle(child_ino);
             to_async_drop.push((child_ino, removed_entry, delayed_handle_release));
 
             let parent_inode = inner.inode_forest.get_mut(&parent_ino).expect(
@@ -731,6 +873,10 @@ where
         //      We support that here, but does CryNode support that correctly? The previous child node might still exist and stored in the orphaned inode.
         let mut inner = self.inner.lock().await;
         log::debug!("Inode {parent_ino} / {name}: Making into orphan");
+        let parent_ino = inner
+            .exposed
+            .resolve(parent_ino)
+            .ok_or(MakeOrphanError::ParentNotFound)?;
         inner.inode_forest.make_node_into_orphan(&parent_ino, name)
 
         // Fulfilling invariants:
@@ -755,6 +901,14 @@ where
         log::debug!(
             "Inode {old_parent_ino} / {old_name} -> {new_parent_ino} / {new_name}: Moving inode"
         );
+        let old_parent_ino = inner
+            .exposed
+            .resolve(old_parent_ino)
+            .ok_or(MoveInodeError::OldParentNotFound)?;
+        let new_parent_ino = inner
+            .exposed
+            .resolve(new_parent_ino)
+            .ok_or(MoveInodeError::NewParentNotFound)?;
         let move_result = inner
             .inode_forest
             .move_node(old_parent_ino, old_name, new_parent_ino, new_name)
@@ -799,9 +953,8 @@ where
         } else {
             match move_result {
                 MoveInodeSuccess::OrphanedExistingChildInNewParent => {
-                    panic!(
-                        "If parents are the same, we should never hit the case of orphaning an existing child."
-                    )
+                    // This can happen when renaming between two hard links to the same file, which leaves both directory entries in place.
+                    // The orphaned child still points to the parent, so per invariant E1, the parent's refcount doesn't change.
                 }
                 MoveInodeSuccess::AddedAsNewChildToNewParent => {
                     // everything ok
@@ -819,6 +972,78 @@ where
         Ok(())
     }
 
+    /// Swap the inodes at `first_parent_ino/first_name` and `second_parent_ino/second_name`, as needed for `RENAME_EXCHANGE`.
+    /// Either of them may not be loaded, in which case the other one (if loaded) is just moved.
+    pub async fn exchange_inodes(
+        &self,
+        first_parent_ino: InodeNumber,
+        first_name: PathComponentBuf,
+        second_parent_ino: InodeNumber,
+        second_name: PathComponentBuf,
+    ) -> Result<(), ExchangeInodesError> {
+        let mut inner = self.inner.lock().await;
+        log::debug!(
+            "Inode {first_parent_ino} / {first_name} <-> {second_parent_ino} / {second_name}: Exchanging inodes"
+        );
+        let first_parent_ino = inner
+            .exposed
+            .resolve(first_parent_ino)
+            .ok_or(ExchangeInodesError::FirstParentNotFound)?;
+        let second_parent_ino = inner
+            .exposed
+            .resolve(second_parent_ino)
+            .ok_or(ExchangeInodesError::SecondParentNotFound)?;
+        let ExchangeNodesSuccess {
+            first_was_loaded,
+            second_was_loaded,
+        } = inner
+            .inode_forest
+            .exchange_nodes(first_parent_ino, first_name, second_parent_ino, second_name)
+            .map_err(|err| match err {
+                ExchangeNodesError::FirstParentNotFound => ExchangeInodesError::FirstParentNotFound,
+                ExchangeNodesError::SecondParentNotFound => {
+                    ExchangeInodesError::SecondParentNotFound
+                }
+            })?;
+
+        // According to invariant E1, each child's parent pointer counts towards the refcount of the parent.
+        // If only one of the children was loaded, one parent gained a child pointer and the other one lost one.
+        // If both or neither were loaded, the number of pointers to each parent didn't change.
+        if first_parent_ino != second_parent_ino && first_was_loaded != second_was_loaded {
+            let (gaining_parent, losing_parent) = if first_was_loaded {
+                (second_parent_ino, first_parent_ino)
+            } else {
+                (first_parent_ino, second_parent_ino)
+            };
+            inner
+                .inode_forest
+                .get_mut(&gaining_parent)
+                .expect("We already checked that it exists when we exchanged the nodes above")
+                .value_mut()
+                .increment_refcount();
+            self._decrease_refcount(inner, losing_parent, 1)
+                .await
+                .map_err(|err| match err {
+                    DecrementRefcountError::NodeNotFound => {
+                        panic!(
+                            "We already checked that it exists when we exchanged the nodes above"
+                        )
+                    }
+                    DecrementRefcountError::ErrorWhileDroppingNode(err) => {
+                        ExchangeInodesError::ErrorWhileDroppingNode(err)
+                    }
+                })?;
+        }
+
+        // Fulfilling invariants:
+        // * A, C, D, F: No change here
+        // * B1: No change here
+        // * B2: We assigned new parent pointers to the nodes, but with B1+B2, we know that those parents are fully loaded.
+        // * E1: The parent that gained a child pointer got its refcount incremented, the one that lost a child pointer got it decremented.
+        // * E2: We used [Self::_decrease_refcount] to decrement the refcount, which would drop the parent if its refcount went to zero.
+        Ok(())
+    }
+
     #[cfg(feature = "testutils")]
     pub async fn clear_all_slow(&self) -> FsResult<()> {
         let mut inner = self.inner.lock().await;
@@ -838,6 +1063,7 @@ where
         .await?;
         // Dropping references also drops the corresponding entries in self.inodes
         assert!(inner.inodes.is_empty());
+        inner.exposed = ExposedInodes::new();
 
         let root_node = root_node_drop_result
             .await
@@ -915,3 +1141,10 @@ pub enum MoveInodeError {
     ChildNotFound,
     ErrorWhileDroppingNode(FsError),
 }
+
+#[derive(Error, Debug, Display)]
+pub enum ExchangeInodesError {
+    FirstParentNotFound,
+    SecondParentNotFound,
+    ErrorWhileDroppingNode(FsError),
+}
@@ -0,0 +1,429 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::Notify;
+
+use crate::{FsError, FsResult, InodeNumber};
+
+/// Lock owners as sent by the kernel. All locks held by the same owner (e.g. all fcntl locks of a process) never conflict with each other.
+pub type LockOwner = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LockKind {
+    /// `F_RDLCK`, can be held by multiple owners at the same time
+    Shared,
+    /// `F_WRLCK`, excludes all other locks on the range
+    Exclusive,
+}
+
+impl LockKind {
+    /// Parse the `typ` argument of `getlk`/`setlk`. Returns `None` for `F_UNLCK`.
+    pub fn from_typ(typ: libc::c_int) -> FsResult<Option<Self>> {
+        match typ {
+            libc::F_RDLCK => Ok(Some(Self::Shared)),
+            libc::F_WRLCK => Ok(Some(Self::Exclusive)),
+            libc::F_UNLCK => Ok(None),
+            _ => Err(FsError::InvalidOperation),
+        }
+    }
+
+    pub fn to_typ(self) -> libc::c_int {
+        match self {
+            Self::Shared => libc::F_RDLCK,
+            Self::Exclusive => libc::F_WRLCK,
+        }
+    }
+}
+
+/// A byte-range lock. `end` is inclusive, the kernel uses `OFFSET_MAX` for locks that extend to the end of the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lock {
+    pub owner: LockOwner,
+    pub pid: u32,
+    pub kind: LockKind,
+    pub start: u64,
+    pub end: u64,
+}
+
+impl Lock {
+    fn overlaps(&self, start: u64, end: u64) -> bool {
+        self.start <= end && start <= self.end
+    }
+
+    fn conflicts_with(&self, other: &Lock) -> bool {
+        self.owner != other.owner
+            && self.overlaps(other.start, other.end)
+            && (self.kind == LockKind::Exclusive || other.kind == LockKind::Exclusive)
+    }
+}
+
+/// Keeps track of POSIX byte-range locks (`fcntl(F_SETLK)`) for all inodes of a mounted file system.
+/// The kernel sends `flock` locks as whole-file locks owned by the open file, so they're handled here as well
+/// and, like on NFS, conflict with `fcntl` locks of other owners.
+///
+/// Locks are only known to this process, so they only protect against other processes accessing the same mount.
+pub struct LockManager {
+    state: Mutex<LockManagerState>,
+    // Notified whenever locks are released or downgraded so that blocked [LockManager::lock] calls can retry
+    released: Notify,
+}
+
+#[derive(Default)]
+struct LockManagerState {
+    locks: HashMap<InodeNumber, Vec<Lock>>,
+    // For each owner blocked in [LockManager::lock], the owner of a lock it is waiting for. Used to detect deadlocks.
+    waiting_for: HashMap<LockOwner, LockOwner>,
+}
+
+impl LockManager {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(LockManagerState::default()),
+            released: Notify::new(),
+        }
+    }
+
+    /// Return a lock that conflicts with `lock`, or `None` if `lock` could be taken right now.
+    pub fn test(&self, ino: InodeNumber, lock: &Lock) -> Option<Lock> {
+        self.state.lock().unwrap().conflicting_lock(ino, lock)
+    }
+
+    /// Take `lock`, replacing any locks the same owner already holds on that range.
+    /// Fails with [FsError::LockConflict] if another owner holds a conflicting lock.
+    pub fn try_lock(&self, ino: InodeNumber, lock: Lock) -> FsResult<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.conflicting_lock(ino, &lock).is_some() {
+            return Err(FsError::LockConflict);
+        }
+        state.set(ino, lock);
+        drop(state);
+        // Replacing an exclusive lock with a shared one can unblock waiters
+        self.released.notify_waiters();
+        Ok(())
+    }
+
+    /// Like [LockManager::try_lock], but waits until conflicting locks are released.
+    /// Fails with [FsError::Deadlock] if waiting would deadlock because the owner of a conflicting lock is (directly or
+    /// indirectly) waiting for a lock held by `lock.owner`.
+    /// Dropping the returned future cancels the wait.
+    pub async fn lock(&self, ino: InodeNumber, lock: Lock) -> FsResult<()> {
+        let _waiting = WaitingGuard {
+            manager: self,
+            owner: lock.owner,
+        };
+        loop {
+            let released = self.released.notified();
+            tokio::pin!(released);
+            // Register for notifications before checking so we can't miss a release that happens in between
+            released.as_mut().enable();
+            {
+                let mut state = self.state.lock().unwrap();
+                match state.conflicting_lock(ino, &lock) {
+                    None => {
+                        state.set(ino, lock);
+                        drop(state);
+                        self.released.notify_waiters();
+                        return Ok(());
+                    }
+                    Some(conflict) => {
+                        if state.is_waiting_for(conflict.owner, lock.owner) {
+                            return Err(FsError::Deadlock);
+                        }
+                        state.waiting_for.insert(lock.owner, conflict.owner);
+                    }
+                }
+            }
+            released.await;
+        }
+    }
+
+    /// Release all locks `owner` holds on the range `[start, end]`
+    pub fn unlock(&self, ino: InodeNumber, owner: LockOwner, start: u64, end: u64) {
+        self.state.lock().unwrap().remove(ino, owner, start, end);
+        self.released.notify_waiters();
+    }
+
+    /// Release all locks `owner` holds on the inode, e.g. because it closed the file
+    pub fn unlock_all(&self, ino: InodeNumber, owner: LockOwner) {
+        self.unlock(ino, owner, 0, u64::MAX);
+    }
+}
+
+impl LockManagerState {
+    fn conflicting_lock(&self, ino: InodeNumber, lock: &Lock) -> Option<Lock> {
+        self.locks
+            .get(&ino)?
+            .iter()
+            .find(|existing| existing.conflicts_with(lock))
+            .copied()
+    }
+
+    /// Whether `owner` is (directly or indirectly) waiting for a lock held by `target`
+    fn is_waiting_for(&self, mut owner: LockOwner, target: LockOwner) -> bool {
+        // Each owner waits for at most one other owner, so following the chain can't take more steps than there are waiters
+        for _ in 0..=self.waiting_for.len() {
+            if owner == target {
+                return true;
+            }
+            match self.waiting_for.get(&owner) {
+                Some(next) => owner = *next,
+                None => return false,
+            }
+        }
+        false
+    }
+
+    fn set(&mut self, ino: InodeNumber, lock: Lock) {
+        self.remove(ino, lock.owner, lock.start, lock.end);
+        let locks = self.locks.entry(ino).or_default();
+        let mut lock = lock;
+        // POSIX merges adjacent and overlapping locks of the same owner and kind
+        locks.retain(|existing| {
+            let adjacent_or_overlapping = existing.start <= lock.end.saturating_add(1)
+                && lock.start <= existing.end.saturating_add(1);
+            if existing.owner == lock.owner && existing.kind == lock.kind && adjacent_or_overlapping
+            {
+                lock.start = lock.start.min(existing.start);
+                lock.end = lock.end.max(existing.end);
+                false
+            } else {
+                true
+            }
+        });
+        locks.push(lock);
+    }
+
+    fn remove(&mut self, ino: InodeNumber, owner: LockOwner, start: u64, end: u64) {
+        let Some(locks) = self.locks.get_mut(&ino) else {
+            return;
+        };
+        let mut remaining = Vec::with_capacity(locks.len());
+        for lock in locks.drain(..) {
+            if lock.owner != owner || !lock.overlaps(start, end) {
+                remaining.push(lock);
+                continue;
+            }
+            // Keep the parts of the lock outside of the removed range
+            if lock.start < start {
+                remaining.push(Lock {
+                    end: start - 1,
+                    ..lock
+                });
+            }
+            if lock.end > end {
+                remaining.push(Lock {
+                    start: end + 1,
+                    ..lock
+                });
+            }
+        }
+        if remaining.is_empty() {
+            self.locks.remove(&ino);
+        } else {
+            *locks = remaining;
+        }
+    }
+}
+
+struct WaitingGuard<'a> {
+    manager: &'a LockManager,
+    owner: LockOwner,
+}
+
+impl Drop for WaitingGuard<'_> {
+    fn drop(&mut self) {
+        self.manager
+            .state
+            .lock()
+            .unwrap()
+            .waiting_for
+            .remove(&self.owner);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::num::NonZeroU64;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    const INO: InodeNumber = InodeNumber::from_const(NonZeroU64::new(10).unwrap());
+    const OTHER_INO: InodeNumber = InodeNumber::from_const(NonZeroU64::new(11).unwrap());
+
+    fn lock(owner: LockOwner, kind: LockKind, start: u64, end: u64) -> Lock {
+        Lock {
+            owner,
+            pid: owner as u32,
+            kind,
+            start,
+            end,
+        }
+    }
+
+    #[test]
+    fn shared_locks_dont_conflict() {
+        let manager = LockManager::new();
+        manager
+            .try_lock(INO, lock(1, LockKind::Shared, 0, 100))
+            .unwrap();
+        manager
+            .try_lock(INO, lock(2, LockKind::Shared, 50, 150))
+            .unwrap();
+        assert_eq!(None, manager.test(INO, &lock(3, LockKind::Shared, 0, 200)));
+    }
+
+    #[test]
+    fn exclusive_lock_conflicts_with_overlapping_locks_of_other_owners() {
+        let manager = LockManager::new();
+        manager
+            .try_lock(INO, lock(1, LockKind::Shared, 0, 100))
+            .unwrap();
+        assert!(matches!(
+            manager.try_lock(INO, lock(2, LockKind::Exclusive, 100, 200)),
+            Err(FsError::LockConflict),
+        ));
+        assert_eq!(
+            Some(lock(1, LockKind::Shared, 0, 100)),
+            manager.test(INO, &lock(2, LockKind::Exclusive, 100, 200)),
+        );
+        manager
+            .try_lock(INO, lock(2, LockKind::Exclusive, 101, 200))
+            .unwrap();
+        manager
+            .try_lock(OTHER_INO, lock(2, LockKind::Exclusive, 0, 200))
+            .unwrap();
+        // The same owner can upgrade its own lock
+        manager
+            .try_lock(INO, lock(1, LockKind::Exclusive, 0, 100))
+            .unwrap();
+    }
+
+    #[test]
+    fn unlocking_part_of_a_lock_keeps_the_rest() {
+        let manager = LockManager::new();
+        manager
+            .try_lock(INO, lock(1, LockKind::Exclusive, 0, 100))
+            .unwrap();
+        manager.unlock(INO, 1, 40, 59);
+        assert_eq!(
+            None,
+            manager.test(INO, &lock(2, LockKind::Exclusive, 40, 59))
+        );
+        assert_eq!(
+            Some(lock(1, LockKind::Exclusive, 0, 39)),
+            manager.test(INO, &lock(2, LockKind::Shared, 39, 40)),
+        );
+        assert_eq!(
+            Some(lock(1, LockKind::Exclusive, 60, 100)),
+            manager.test(INO, &lock(2, LockKind::Shared, 59, 60)),
+        );
+    }
+
+    #[test]
+    fn adjacent_locks_of_the_same_owner_are_merged() {
+        let manager = LockManager::new();
+        manager
+            .try_lock(INO, lock(1, LockKind::Shared, 0, 9))
+            .unwrap();
+        manager
+            .try_lock(INO, lock(1, LockKind::Shared, 10, 19))
+            .unwrap();
+        assert_eq!(
+            Some(lock(1, LockKind::Shared, 0, 19)),
+            manager.test(INO, &lock(2, LockKind::Exclusive, 5, 5)),
+        );
+    }
+
+    #[test]
+    fn unlock_all_releases_locks_of_owner() {
+        let manager = LockManager::new();
+        manager
+            .try_lock(INO, lock(1, LockKind::Exclusive, 0, 9))
+            .unwrap();
+        manager
+            .try_lock(INO, lock(1, LockKind::Exclusive, 20, u64::MAX))
+            .unwrap();
+        manager
+            .try_lock(INO, lock(2, LockKind::Exclusive, 10, 19))
+            .unwrap();
+        manager.unlock_all(INO, 1);
+        assert_eq!(None, manager.test(INO, &lock(3, LockKind::Exclusive, 0, 9)));
+        assert_eq!(
+            Some(lock(2, LockKind::Exclusive, 10, 19)),
+            manager.test(INO, &lock(3, LockKind::Exclusive, 0, u64::MAX)),
+        );
+    }
+
+    #[tokio::test]
+    async fn blocking_lock_waits_until_conflicting_lock_is_released() {
+        let manager = Arc::new(LockManager::new());
+        manager
+            .try_lock(INO, lock(1, LockKind::Exclusive, 0, 100))
+            .unwrap();
+        let waiter = tokio::spawn({
+            let manager = Arc::clone(&manager);
+            async move { manager.lock(INO, lock(2, LockKind::Shared, 50, 60)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiter.is_finished());
+
+        manager.unlock(INO, 1, 0, 100);
+        waiter.await.unwrap().unwrap();
+        assert_eq!(
+            Some(lock(2, LockKind::Shared, 50, 60)),
+            manager.test(INO, &lock(1, LockKind::Exclusive, 0, 100)),
+        );
+    }
+
+    #[tokio::test]
+    async fn blocking_lock_detects_deadlock() {
+        let manager = Arc::new(LockManager::new());
+        manager
+            .try_lock(INO, lock(1, LockKind::Exclusive, 0, 9))
+            .unwrap();
+        manager
+            .try_lock(INO, lock(2, LockKind::Exclusive, 10, 19))
+            .unwrap();
+        let waiter = tokio::spawn({
+            let manager = Arc::clone(&manager);
+            async move {
+                manager
+                    .lock(INO, lock(1, LockKind::Exclusive, 10, 19))
+                    .await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        assert!(matches!(
+            manager.lock(INO, lock(2, LockKind::Exclusive, 0, 9)).await,
+            Err(FsError::Deadlock),
+        ));
+        manager.unlock_all(INO, 2);
+        waiter.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn cancelled_wait_doesnt_cause_false_deadlock() {
+        let manager = LockManager::new();
+        manager
+            .try_lock(INO, lock(1, LockKind::Exclusive, 0, 9))
+            .unwrap();
+        manager
+            .try_lock(INO, lock(2, LockKind::Exclusive, 10, 19))
+            .unwrap();
+        let result = tokio::time::timeout(
+            Duration::from_millis(10),
+            manager.lock(INO, lock(1, LockKind::Exclusive, 10, 19)),
+        )
+        .await;
+        assert!(result.is_err());
+
+        // Owner 1 isn't waiting anymore, so owner 2 can wait for it
+        let result = tokio::time::timeout(
+            Duration::from_millis(10),
+            manager.lock(INO, lock(2, LockKind::Exclusive, 0, 9)),
+        )
+        .await;
+        assert!(result.is_err());
+    }
+}
@@ -1,6 +1,9 @@
 mod maybe_initialized_fs;
 pub use maybe_initialized_fs::MaybeInitializedFs;
 
+mod xattr;
+pub use xattr::{xattr_name_list, xattr_value_reply};
+
 #[cfg(any(feature = "fuser", feature = "fuse_mt"))]
 mod open_file_list;
 #[cfg(all(any(feature = "fuser", feature = "fuse_mt"), feature = "testutils"))]
@@ -13,7 +16,14 @@ mod dir_cache;
 #[cfg(feature = "fuser")]
 pub use dir_cache::{DirCache, OpenDirHandle};
 
+#[cfg(feature = "fuser")]
+mod lock_manager;
+#[cfg(feature = "fuser")]
+pub use lock_manager::{Lock, LockKind, LockManager};
+
 #[cfg(feature = "fuser")]
 mod inode_list;
 #[cfg(feature = "fuser")]
-pub use inode_list::{DUMMY_INO, FUSE_ROOT_ID, InodeList, MakeOrphanError, MoveInodeError};
+pub use inode_list::{
+    DUMMY_INO, ExchangeInodesError, FUSE_ROOT_ID, InodeList, MakeOrphanError, MoveInodeError,
+};
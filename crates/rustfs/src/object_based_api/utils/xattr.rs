@@ -0,0 +1,19 @@
+use crate::common::{FsError, FsResult, NumBytes};
+
+/// Check that an extended attribute value fits into a buffer of `max_bytes` bytes.
+pub fn xattr_value_reply(value: Vec<u8>, max_bytes: NumBytes) -> FsResult<Vec<u8>> {
+    if NumBytes::from(value.len() as u64) > max_bytes {
+        return Err(FsError::XattrBufferTooSmall);
+    }
+    Ok(value)
+}
+
+/// Serialize extended attribute names the way `listxattr` returns them, i.e. each name followed by a null byte.
+pub fn xattr_name_list(names: Vec<String>) -> Vec<u8> {
+    let mut result = Vec::with_capacity(names.iter().map(|name| name.len() + 1).sum());
+    for name in names {
+        result.extend_from_slice(name.as_bytes());
+        result.push(0);
+    }
+    result
+}
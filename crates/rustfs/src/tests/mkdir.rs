@@ -76,6 +76,7 @@ fn mkdir_return_ok(mode: Mode) -> FsResult<ReplyEntry> {
             gid: Gid::from(1000),
             num_bytes: NumBytes::from(532),
             num_blocks: None,
+            rdev: 0,
             atime: now,
             mtime: now,
             ctime: now,
@@ -6,6 +6,7 @@ use cryfs_utils::async_drop::{AsyncDropArc, AsyncDropGuard, SyncDrop};
 use super::filesystem_driver::FilesystemDriver;
 use super::mock_low_level_api::MockAsyncFilesystemLL;
 use crate::{
+    KernelCacheOptions,
     backend::fuser::{RunningFilesystem, spawn_mount},
     tests::utils::mock_low_level_api::MockFilesystem,
 };
@@ -43,6 +44,7 @@ impl Runner {
             mountpoint.path(),
             runtime,
             &[],
+            KernelCacheOptions::default(),
         )
         .await
         .expect("Failed to spawn filesystem");
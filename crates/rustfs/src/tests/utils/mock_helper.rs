@@ -68,6 +68,12 @@ impl<'a> MockHelper<'a> {
             NodeKind::Dir => some_dir_attrs(),
             NodeKind::File => some_file_attrs(),
             NodeKind::Symlink => some_symlink_attrs(),
+            NodeKind::Fifo => some_special_file_attrs(Mode::default().add_fifo_flag()),
+            NodeKind::Socket => some_special_file_attrs(Mode::default().add_socket_flag()),
+            NodeKind::CharDevice => some_special_file_attrs(Mode::default().add_char_device_flag()),
+            NodeKind::BlockDevice => {
+                some_special_file_attrs(Mode::default().add_block_device_flag())
+            }
         };
         self.expect_lookup_has_attrs(parent_ino, name, attr)
     }
@@ -177,6 +183,7 @@ fn some_dir_attrs() -> NodeAttrs {
         gid: Gid::from(1000),
         num_bytes: NumBytes::from(532),
         num_blocks: None,
+        rdev: 0,
         atime: now,
         mtime: now,
         ctime: now,
@@ -192,6 +199,7 @@ fn some_file_attrs() -> NodeAttrs {
         gid: Gid::from(1000),
         num_bytes: NumBytes::from(532),
         num_blocks: None,
+        rdev: 0,
         atime: now,
         mtime: now,
         ctime: now,
@@ -207,6 +215,23 @@ fn some_symlink_attrs() -> NodeAttrs {
         gid: Gid::from(1000),
         num_bytes: NumBytes::from(532),
         num_blocks: None,
+        rdev: 0,
+        atime: now,
+        mtime: now,
+        ctime: now,
+    }
+}
+
+fn some_special_file_attrs(mode: Mode) -> NodeAttrs {
+    let now = SystemTime::now();
+    NodeAttrs {
+        nlink: 1,
+        mode,
+        uid: Uid::from(1000),
+        gid: Gid::from(1000),
+        num_bytes: NumBytes::from(0),
+        num_blocks: None,
+        rdev: 0,
         atime: now,
         mtime: now,
         ctime: now,
@@ -61,6 +61,10 @@ mock! {
             name: &PathComponent,
         ) -> FsResult<ReplyEntry>;
 
+        async fn lookup_self(&self, req: &RequestInfo, ino: InodeNumber) -> FsResult<ReplyEntry>;
+
+        async fn lookup_parent(&self, req: &RequestInfo, ino: InodeNumber) -> FsResult<ReplyEntry>;
+
         async fn forget(&self, req: &RequestInfo, ino: InodeNumber, nlookup: u64) -> FsResult<()>;
 
         async fn getattr(&self, req: &RequestInfo, ino: InodeNumber, fh: Option<FileHandle>) -> FsResult<ReplyAttr>;
@@ -442,6 +446,14 @@ impl AsyncFilesystemLL for AsyncDropArc<MockAsyncFilesystemLL> {
         self.deref().lookup(req, parent, name).await
     }
 
+    async fn lookup_self(&self, req: &RequestInfo, ino: InodeNumber) -> FsResult<ReplyEntry> {
+        self.deref().lookup_self(req, ino).await
+    }
+
+    async fn lookup_parent(&self, req: &RequestInfo, ino: InodeNumber) -> FsResult<ReplyEntry> {
+        self.deref().lookup_parent(req, ino).await
+    }
+
     async fn forget(&self, req: &RequestInfo, ino: InodeNumber, nlookup: u64) -> FsResult<()> {
         self.deref().forget(req, ino, nlookup).await
     }